@@ -0,0 +1,5 @@
+-- Row versions for optimistic concurrency: every update increments the
+-- version, which the admin API exposes as the ETag and checks against If-Match
+ALTER TABLE users ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE roles ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE permissions ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,5 @@
+-- Deleting a user only stamps deleted_at; the row (and its username, email
+-- and role assignments) is kept until purged after the retention period
+ALTER TABLE users ADD COLUMN deleted_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX idx_users_deleted_at ON users(deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,52 @@
+-- Usernames and emails become case-insensitive. Usernames keep the case they
+-- were registered with but are unique by username_folded (NFKC, lowercased);
+-- emails are stored trimmed, NFKC-normalized and lowercased. The application
+-- computes both forms on write; existing rows are converted here.
+
+-- Refuse to upgrade while existing accounts would collide, listing them so
+-- they can be renamed or merged first
+DO $$
+DECLARE
+    collisions TEXT;
+BEGIN
+    WITH normalized AS (
+        SELECT id, username, email,
+               normalize(lower(normalize(username, NFKC)), NFKC) AS username_folded,
+               lower(normalize(btrim(email), NFKC)) AS email_normalized
+        FROM users
+    )
+    SELECT string_agg(collision, E'\n')
+    INTO collisions
+    FROM (
+        SELECT format('username %L: %s', username_folded,
+                      string_agg(format('%s (%s)', username, id), ', ' ORDER BY username)) AS collision
+        FROM normalized
+        GROUP BY username_folded
+        HAVING COUNT(*) > 1
+        UNION ALL
+        SELECT format('email %L: %s', email_normalized,
+                      string_agg(format('%s (%s)', email, id), ', ' ORDER BY email))
+        FROM normalized
+        GROUP BY email_normalized
+        HAVING COUNT(*) > 1
+    ) c;
+
+    IF collisions IS NOT NULL THEN
+        RAISE EXCEPTION E'Users differ only in case or Unicode form; rename them before upgrading:\n%', collisions;
+    END IF;
+END
+$$;
+
+ALTER TABLE users ADD COLUMN username_folded VARCHAR(255);
+
+UPDATE users
+SET username = normalize(username, NFKC),
+    username_folded = normalize(lower(normalize(username, NFKC)), NFKC),
+    email = lower(normalize(btrim(email), NFKC));
+
+ALTER TABLE users ALTER COLUMN username_folded SET NOT NULL;
+
+-- The constraint keeps its name, so conflicts are still reported on users.username
+ALTER TABLE users DROP CONSTRAINT users_username_key;
+ALTER TABLE users ADD CONSTRAINT users_username_key UNIQUE (username_folded);
+DROP INDEX idx_users_username;
@@ -0,0 +1,28 @@
+-- A single counter bumped whenever what a role name grants may have changed.
+-- Compact tokens carry the value they were issued at, so services caching
+-- the role to permission mapping know when theirs is too old.
+CREATE TABLE permissions_version (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    version BIGINT NOT NULL DEFAULT 1
+);
+
+INSERT INTO permissions_version DEFAULT VALUES;
+
+CREATE FUNCTION bump_permissions_version() RETURNS TRIGGER AS $$
+BEGIN
+    UPDATE permissions_version SET version = version + 1;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER role_permissions_changed
+    AFTER INSERT OR UPDATE OR DELETE ON role_permissions
+    FOR EACH STATEMENT EXECUTE FUNCTION bump_permissions_version();
+
+CREATE TRIGGER roles_renamed_or_deleted
+    AFTER UPDATE OF name OR DELETE ON roles
+    FOR EACH STATEMENT EXECUTE FUNCTION bump_permissions_version();
+
+CREATE TRIGGER permissions_renamed_or_deleted
+    AFTER UPDATE OF name OR DELETE ON permissions
+    FOR EACH STATEMENT EXECUTE FUNCTION bump_permissions_version();
@@ -0,0 +1,21 @@
+-- Pending OAuth 2.0 device authorization requests (RFC 8628). The device
+-- polls with its device code, stored hashed, until a signed-in user enters
+-- the user code and approves or denies the request. Rows are deleted when
+-- the device collects the outcome, or purged once expired.
+CREATE TABLE device_authorizations (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    device_code_hash VARCHAR(64) NOT NULL UNIQUE,
+    user_code VARCHAR(16) NOT NULL UNIQUE,
+    client_id VARCHAR(255) NOT NULL,
+    -- Minimum seconds between polls; raised each time the device polls too fast
+    interval_seconds INTEGER NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    last_polled_at TIMESTAMP WITH TIME ZONE,
+    -- The user who approved or denied the request
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+    approved_at TIMESTAMP WITH TIME ZONE,
+    denied_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX idx_device_authorizations_expires_at ON device_authorizations(expires_at);
@@ -0,0 +1,17 @@
+-- Record of users erased on request. The erased user's id is not kept: rows
+-- that must outlive them, such as audit records, refer to them by
+-- `pseudonym_id` instead, which no longer leads back to the person.
+CREATE TABLE erasures (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    pseudonym_id UUID NOT NULL UNIQUE,
+    -- The admin who carried out the request; not a foreign key so the record
+    -- outlives them (if they are erased in turn, this becomes their pseudonym)
+    requested_by UUID NOT NULL,
+    reason TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_erasures_created_at ON erasures(created_at DESC);
+
+-- Finds the outbox events about a user for exports and erasures
+CREATE INDEX idx_webhook_events_user_id ON webhook_events ((payload -> 'user' ->> 'id'));
@@ -0,0 +1,11 @@
+-- The ids (`jti`) of signed JWT client assertions already exchanged for a
+-- token, kept until the assertion expires so it cannot be replayed
+-- (RFC 7523 section 3). Rows are purged once expired.
+CREATE TABLE used_client_assertions (
+    service_account_id UUID NOT NULL REFERENCES service_accounts(id) ON DELETE CASCADE,
+    jti TEXT NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    PRIMARY KEY (service_account_id, jti)
+);
+
+CREATE INDEX idx_used_client_assertions_expires_at ON used_client_assertions(expires_at);
@@ -0,0 +1,15 @@
+-- Create users table
+-- SQLite has no UUID or TIMESTAMPTZ types: ids are stored as 16-byte blobs and
+-- timestamps as RFC 3339 UTC text, which is how SQLx encodes Uuid and DateTime<Utc>
+CREATE TABLE users (
+    id BLOB PRIMARY KEY NOT NULL DEFAULT (randomblob(16)),
+    username VARCHAR(255) UNIQUE NOT NULL,
+    email VARCHAR(255) UNIQUE NOT NULL,
+    password_hash VARCHAR(255) NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    updated_at DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    is_active BOOLEAN NOT NULL DEFAULT TRUE
+);
+
+CREATE INDEX idx_users_username ON users(username);
+CREATE INDEX idx_users_email ON users(email);
@@ -0,0 +1,9 @@
+-- Create roles table
+CREATE TABLE roles (
+    id BLOB PRIMARY KEY NOT NULL DEFAULT (randomblob(16)),
+    name VARCHAR(100) UNIQUE NOT NULL,
+    description TEXT,
+    created_at DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'))
+);
+
+CREATE INDEX idx_roles_name ON roles(name);
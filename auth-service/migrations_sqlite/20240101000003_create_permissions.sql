@@ -0,0 +1,11 @@
+-- Create permissions table
+CREATE TABLE permissions (
+    id BLOB PRIMARY KEY NOT NULL DEFAULT (randomblob(16)),
+    name VARCHAR(100) UNIQUE NOT NULL,
+    resource VARCHAR(100) NOT NULL,
+    action VARCHAR(50) NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'))
+);
+
+CREATE INDEX idx_permissions_name ON permissions(name);
+CREATE INDEX idx_permissions_resource_action ON permissions(resource, action);
@@ -0,0 +1,9 @@
+-- Create user_roles junction table
+CREATE TABLE user_roles (
+    user_id BLOB REFERENCES users(id) ON DELETE CASCADE,
+    role_id BLOB REFERENCES roles(id) ON DELETE CASCADE,
+    PRIMARY KEY (user_id, role_id)
+);
+
+CREATE INDEX idx_user_roles_user_id ON user_roles(user_id);
+CREATE INDEX idx_user_roles_role_id ON user_roles(role_id);
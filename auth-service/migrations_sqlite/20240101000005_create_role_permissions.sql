@@ -0,0 +1,9 @@
+-- Create role_permissions junction table
+CREATE TABLE role_permissions (
+    role_id BLOB REFERENCES roles(id) ON DELETE CASCADE,
+    permission_id BLOB REFERENCES permissions(id) ON DELETE CASCADE,
+    PRIMARY KEY (role_id, permission_id)
+);
+
+CREATE INDEX idx_role_permissions_role_id ON role_permissions(role_id);
+CREATE INDEX idx_role_permissions_permission_id ON role_permissions(permission_id);
@@ -0,0 +1,37 @@
+-- Create groups table
+CREATE TABLE groups (
+    id BLOB PRIMARY KEY NOT NULL DEFAULT (randomblob(16)),
+    name VARCHAR(100) UNIQUE NOT NULL,
+    description TEXT,
+    created_at DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'))
+);
+
+CREATE INDEX idx_groups_name ON groups(name);
+
+-- Direct user membership of a group
+CREATE TABLE group_members (
+    group_id BLOB NOT NULL REFERENCES groups(id) ON DELETE CASCADE,
+    user_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    PRIMARY KEY (group_id, user_id)
+);
+
+CREATE INDEX idx_group_members_user_id ON group_members(user_id);
+
+-- Nested groups: members of the child group are also members of the parent group
+CREATE TABLE group_subgroups (
+    parent_group_id BLOB NOT NULL REFERENCES groups(id) ON DELETE CASCADE,
+    child_group_id BLOB NOT NULL REFERENCES groups(id) ON DELETE CASCADE,
+    PRIMARY KEY (parent_group_id, child_group_id),
+    CHECK (parent_group_id <> child_group_id)
+);
+
+CREATE INDEX idx_group_subgroups_child_group_id ON group_subgroups(child_group_id);
+
+-- Roles granted to every (direct or nested) member of a group
+CREATE TABLE group_roles (
+    group_id BLOB NOT NULL REFERENCES groups(id) ON DELETE CASCADE,
+    role_id BLOB NOT NULL REFERENCES roles(id) ON DELETE CASCADE,
+    PRIMARY KEY (group_id, role_id)
+);
+
+CREATE INDEX idx_group_roles_role_id ON group_roles(role_id);
@@ -0,0 +1,23 @@
+-- Create invitations table
+CREATE TABLE invitations (
+    id BLOB PRIMARY KEY NOT NULL DEFAULT (randomblob(16)),
+    email VARCHAR(255) NOT NULL,
+    -- SHA-256 of the invitation token; the token itself is only shown once
+    token_hash VARCHAR(64) UNIQUE NOT NULL,
+    invited_by BLOB,
+    expires_at DATETIME NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    accepted_at DATETIME,
+    accepted_user_id BLOB REFERENCES users(id) ON DELETE SET NULL,
+    revoked_at DATETIME
+);
+
+CREATE INDEX idx_invitations_email ON invitations(email);
+CREATE INDEX idx_invitations_created_at ON invitations(created_at DESC);
+
+-- Roles granted to the user created by accepting an invitation
+CREATE TABLE invitation_roles (
+    invitation_id BLOB NOT NULL REFERENCES invitations(id) ON DELETE CASCADE,
+    role_id BLOB NOT NULL REFERENCES roles(id) ON DELETE CASCADE,
+    PRIMARY KEY (invitation_id, role_id)
+);
@@ -0,0 +1,21 @@
+-- Permission required to mint impersonation tokens; not granted to any role by default
+INSERT INTO permissions (name, resource, action) VALUES
+    ('user:impersonate', 'user', 'impersonate')
+ON CONFLICT (name) DO NOTHING;
+
+-- Audit trail of impersonation tokens. Ids are not foreign keys and the
+-- usernames are copied so that records outlive the accounts involved.
+CREATE TABLE impersonation_events (
+    id BLOB PRIMARY KEY NOT NULL DEFAULT (randomblob(16)),
+    actor_id BLOB NOT NULL,
+    actor_username VARCHAR(255) NOT NULL,
+    target_user_id BLOB NOT NULL,
+    target_username VARCHAR(255) NOT NULL,
+    reason TEXT NOT NULL,
+    expires_at DATETIME NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'))
+);
+
+CREATE INDEX idx_impersonation_events_actor_id ON impersonation_events(actor_id);
+CREATE INDEX idx_impersonation_events_target_user_id ON impersonation_events(target_user_id);
+CREATE INDEX idx_impersonation_events_created_at ON impersonation_events(created_at DESC);
@@ -0,0 +1,7 @@
+-- One-time token printed on startup while no admin exists; exchanging it at
+-- POST /auth/setup creates the first admin. Only the SHA-256 is stored.
+CREATE TABLE setup_tokens (
+    token_hash VARCHAR(64) PRIMARY KEY,
+    created_at DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    expires_at DATETIME NOT NULL
+);
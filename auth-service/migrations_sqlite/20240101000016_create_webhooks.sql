@@ -0,0 +1,44 @@
+-- Endpoints notified of user lifecycle events
+CREATE TABLE webhooks (
+    id BLOB PRIMARY KEY NOT NULL DEFAULT (randomblob(16)),
+    url TEXT NOT NULL,
+    -- Key for the HMAC signature on each delivery; kept in plaintext because
+    -- signing needs it, and only shown to the admin when the webhook is created
+    secret TEXT NOT NULL,
+    -- JSON array of the event types to deliver; empty means every event
+    event_types TEXT NOT NULL DEFAULT '[]',
+    created_at DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'))
+);
+
+-- Transactional outbox: events are written in the same transaction as the
+-- change they describe, then fanned out into deliveries by the dispatcher
+CREATE TABLE webhook_events (
+    id BLOB PRIMARY KEY NOT NULL DEFAULT (randomblob(16)),
+    event_type VARCHAR(64) NOT NULL,
+    payload TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    dispatched_at DATETIME
+);
+
+CREATE INDEX idx_webhook_events_undispatched ON webhook_events(created_at)
+    WHERE dispatched_at IS NULL;
+
+-- One row per attempt to get an event to a webhook, retried until it succeeds
+-- or runs out of attempts
+CREATE TABLE webhook_deliveries (
+    id BLOB PRIMARY KEY NOT NULL DEFAULT (randomblob(16)),
+    webhook_id BLOB NOT NULL REFERENCES webhooks(id) ON DELETE CASCADE,
+    event_id BLOB NOT NULL REFERENCES webhook_events(id) ON DELETE CASCADE,
+    status VARCHAR(16) NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at DATETIME,
+    response_status INTEGER,
+    error TEXT,
+    created_at DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    updated_at DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'))
+);
+
+CREATE INDEX idx_webhook_deliveries_webhook_id ON webhook_deliveries(webhook_id, created_at DESC);
+CREATE INDEX idx_webhook_deliveries_event_id ON webhook_deliveries(event_id);
+CREATE INDEX idx_webhook_deliveries_due ON webhook_deliveries(next_attempt_at)
+    WHERE status = 'pending';
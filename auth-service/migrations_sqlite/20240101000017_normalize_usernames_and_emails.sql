@@ -0,0 +1,28 @@
+-- Usernames and emails become case-insensitive. Usernames keep the case they
+-- were registered with but are unique by username_folded (NFKC, lowercased);
+-- emails are stored trimmed, NFKC-normalized and lowercased. The application
+-- computes both forms on write. SQLite has no Unicode normalization and its
+-- lower() only folds ASCII, so this converts ASCII only; run_sqlite_migrations
+-- then normalizes the rows outside ASCII in Rust, failing on collisions there.
+
+-- Refuse to upgrade while existing accounts would collide. SQLite cannot put
+-- them in the message; list them with
+--   SELECT lower(username) FROM users GROUP BY 1 HAVING COUNT(*) > 1;
+--   SELECT lower(trim(email)) FROM users GROUP BY 1 HAVING COUNT(*) > 1;
+CREATE TEMP TABLE case_collisions (value TEXT);
+CREATE TEMP TRIGGER report_case_collisions BEFORE INSERT ON case_collisions
+BEGIN
+    SELECT RAISE(ABORT, 'Users differ only in case; rename them before upgrading (see migration 20240101000017)');
+END;
+INSERT INTO case_collisions
+SELECT lower(username) FROM users GROUP BY lower(username) HAVING COUNT(*) > 1
+UNION ALL
+SELECT lower(trim(email)) FROM users GROUP BY lower(trim(email)) HAVING COUNT(*) > 1;
+DROP TABLE case_collisions;
+
+ALTER TABLE users ADD COLUMN username_folded VARCHAR(255) NOT NULL DEFAULT '';
+
+UPDATE users SET username_folded = lower(username), email = lower(trim(email));
+
+CREATE UNIQUE INDEX users_username_folded ON users(username_folded);
+DROP INDEX idx_users_username;
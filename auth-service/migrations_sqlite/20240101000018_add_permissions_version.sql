@@ -0,0 +1,39 @@
+-- A single counter bumped whenever what a role name grants may have changed.
+-- Compact tokens carry the value they were issued at, so services caching
+-- the role to permission mapping know when theirs is too old.
+CREATE TABLE permissions_version (
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+    version INTEGER NOT NULL
+);
+
+INSERT INTO permissions_version (id, version) VALUES (1, 1);
+
+CREATE TRIGGER role_permissions_inserted AFTER INSERT ON role_permissions
+BEGIN
+    UPDATE permissions_version SET version = version + 1;
+END;
+
+CREATE TRIGGER role_permissions_deleted AFTER DELETE ON role_permissions
+BEGIN
+    UPDATE permissions_version SET version = version + 1;
+END;
+
+CREATE TRIGGER roles_renamed AFTER UPDATE OF name ON roles
+BEGIN
+    UPDATE permissions_version SET version = version + 1;
+END;
+
+CREATE TRIGGER roles_deleted AFTER DELETE ON roles
+BEGIN
+    UPDATE permissions_version SET version = version + 1;
+END;
+
+CREATE TRIGGER permissions_renamed AFTER UPDATE OF name ON permissions
+BEGIN
+    UPDATE permissions_version SET version = version + 1;
+END;
+
+CREATE TRIGGER permissions_deleted AFTER DELETE ON permissions
+BEGIN
+    UPDATE permissions_version SET version = version + 1;
+END;
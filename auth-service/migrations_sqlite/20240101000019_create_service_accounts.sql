@@ -0,0 +1,46 @@
+-- Non-human principals. They hold roles like users but never log in with a
+-- password: they obtain tokens from /auth/token with one of their credentials
+CREATE TABLE service_accounts (
+    id BLOB PRIMARY KEY NOT NULL DEFAULT (randomblob(16)),
+    name VARCHAR(255) NOT NULL UNIQUE,
+    description TEXT,
+    -- The user answerable for the account; cleared if that user is purged
+    owner_id BLOB REFERENCES users(id) ON DELETE SET NULL,
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    updated_at DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'))
+);
+
+CREATE INDEX idx_service_accounts_owner_id ON service_accounts(owner_id);
+
+CREATE TABLE service_account_roles (
+    service_account_id BLOB NOT NULL REFERENCES service_accounts(id) ON DELETE CASCADE,
+    role_id BLOB NOT NULL REFERENCES roles(id) ON DELETE CASCADE,
+    assigned_at DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    PRIMARY KEY (service_account_id, role_id)
+);
+
+CREATE INDEX idx_service_account_roles_role_id ON service_account_roles(role_id);
+
+-- Several credentials may be live at once so they can be rotated without
+-- downtime. A secret is stored hashed; a public key verifies the signed JWT
+-- assertions of RFC 7523.
+CREATE TABLE service_account_credentials (
+    id BLOB PRIMARY KEY NOT NULL DEFAULT (randomblob(16)),
+    service_account_id BLOB NOT NULL REFERENCES service_accounts(id) ON DELETE CASCADE,
+    kind VARCHAR(16) NOT NULL CHECK (kind IN ('secret', 'public_key')),
+    secret_hash VARCHAR(64),
+    public_key TEXT,
+    -- JWS algorithm the key signs with, such as RS256 or ES256
+    algorithm VARCHAR(16),
+    created_at DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    expires_at DATETIME,
+    last_used_at DATETIME,
+    CHECK (
+        (kind = 'secret' AND secret_hash IS NOT NULL AND public_key IS NULL)
+        OR (kind = 'public_key' AND public_key IS NOT NULL AND algorithm IS NOT NULL
+            AND secret_hash IS NULL)
+    )
+);
+
+CREATE INDEX idx_service_account_credentials_account ON service_account_credentials(service_account_id);
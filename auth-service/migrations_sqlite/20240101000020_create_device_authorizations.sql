@@ -0,0 +1,21 @@
+-- Pending OAuth 2.0 device authorization requests (RFC 8628). The device
+-- polls with its device code, stored hashed, until a signed-in user enters
+-- the user code and approves or denies the request. Rows are deleted when
+-- the device collects the outcome, or purged once expired.
+CREATE TABLE device_authorizations (
+    id BLOB PRIMARY KEY NOT NULL DEFAULT (randomblob(16)),
+    device_code_hash VARCHAR(64) NOT NULL UNIQUE,
+    user_code VARCHAR(16) NOT NULL UNIQUE,
+    client_id VARCHAR(255) NOT NULL,
+    -- Minimum seconds between polls; raised each time the device polls too fast
+    interval_seconds INTEGER NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    expires_at DATETIME NOT NULL,
+    last_polled_at DATETIME,
+    -- The user who approved or denied the request
+    user_id BLOB REFERENCES users(id) ON DELETE CASCADE,
+    approved_at DATETIME,
+    denied_at DATETIME
+);
+
+CREATE INDEX idx_device_authorizations_expires_at ON device_authorizations(expires_at);
@@ -0,0 +1,17 @@
+-- Accounts at upstream OpenID Connect providers linked to local users. A
+-- user signing in through a provider is found by the provider's stable
+-- subject identifier, never by a claim the user could change.
+CREATE TABLE user_identities (
+    id BLOB PRIMARY KEY NOT NULL DEFAULT (randomblob(16)),
+    user_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- The provider's configured name
+    provider VARCHAR(100) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    -- The email address the provider reported when the identity was linked
+    email VARCHAR(255),
+    created_at DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    last_login_at DATETIME,
+    UNIQUE (provider, subject)
+);
+
+CREATE INDEX idx_user_identities_user_id ON user_identities(user_id);
@@ -0,0 +1,17 @@
+-- Record of users erased on request. The erased user's id is not kept: rows
+-- that must outlive them, such as audit records, refer to them by
+-- `pseudonym_id` instead, which no longer leads back to the person.
+CREATE TABLE erasures (
+    id BLOB PRIMARY KEY NOT NULL DEFAULT (randomblob(16)),
+    pseudonym_id BLOB NOT NULL UNIQUE,
+    -- The admin who carried out the request; not a foreign key so the record
+    -- outlives them (if they are erased in turn, this becomes their pseudonym)
+    requested_by BLOB NOT NULL,
+    reason TEXT,
+    created_at DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'))
+);
+
+CREATE INDEX idx_erasures_created_at ON erasures(created_at DESC);
+
+-- Finds the outbox events about a user for exports and erasures
+CREATE INDEX idx_webhook_events_user_id ON webhook_events (json_extract(payload, '$.user.id'));
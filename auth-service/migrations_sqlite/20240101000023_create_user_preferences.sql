@@ -0,0 +1,16 @@
+-- Each user's defaults for the weather and time services. A missing row, or
+-- a NULL column, means the user has not chosen and the service default applies.
+CREATE TABLE user_preferences (
+    user_id BLOB PRIMARY KEY NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- 'metric' or 'imperial'
+    units VARCHAR(16),
+    -- BCP 47 language tag
+    locale VARCHAR(35),
+    -- '12h' or '24h'
+    time_format VARCHAR(8),
+    -- IANA time zone name
+    home_timezone VARCHAR(64),
+    -- JSON array, most important first
+    favourite_cities TEXT NOT NULL DEFAULT '[]',
+    updated_at DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'))
+);
@@ -0,0 +1,63 @@
+use auth_service::cli::{parse_args, rotate_key, Admin, Command, USAGE};
+use std::process::ExitCode;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    dotenv::dotenv().ok();
+    env_logger::init_from_env(env_logger::Env::new().default_filter_or("warn"));
+
+    let cli = match parse_args(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("error: {e}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    match cli.command {
+        Command::Help => {
+            print!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        // Needs no database
+        Command::KeyRotate => {
+            return match rotate_key(&mut std::io::stdout()) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("error: {e:#}");
+                    ExitCode::FAILURE
+                }
+            };
+        }
+        _ => {}
+    }
+
+    let Some(database_url) = cli
+        .database_url
+        .or_else(|| std::env::var("DATABASE_URL").ok())
+    else {
+        eprintln!("error: pass --database-url or set DATABASE_URL");
+        return ExitCode::from(2);
+    };
+
+    let result = match Admin::connect(&database_url).await {
+        Ok(admin) => {
+            admin
+                .run(
+                    cli.command,
+                    &mut std::io::stdin().lock(),
+                    &mut std::io::stdout(),
+                )
+                .await
+        }
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,830 @@
+//! `karl-auth-admin`: operator commands run directly against the database,
+//! for when going through the `/admin` API is not possible or convenient.
+
+use crate::db::{
+    create_pool, create_sqlite_pool, run_migrations, run_sqlite_migrations, DatabaseBackend,
+    StartupLock, MIGRATOR, SQLITE_MIGRATOR,
+};
+use crate::handlers::admin::BUILTIN_ROLES;
+use crate::handlers::auth::load_roles_and_permissions;
+use crate::handlers::privacy::ExportedEvent;
+use crate::models::user::UpdateUser;
+use crate::models::{
+    fold_username, Erasure, ImpersonationEvent, Permission, Role, User, WebhookEvent,
+};
+use crate::repositories::Repositories;
+use crate::services::{generate_opaque_token, hash_password, USER_TOKEN_TTL_SECONDS};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use serde::Serialize;
+use sqlx::migrate::{Migrate, Migrator};
+use sqlx::{PgPool, SqlitePool};
+use std::collections::HashSet;
+use std::io::{BufRead, Write};
+use uuid::Uuid;
+
+pub const USAGE: &str = "\
+Usage: karl-auth-admin [--database-url <url>] <command>
+
+The database defaults to DATABASE_URL. <user>, <role> and <permission> accept
+a name or an id. Passwords not given with --password are read from stdin.
+
+Users:
+  user list
+  user show <user>
+  user create <username> <email> [--password <password>] [--admin]
+  user edit <user> [--username <username>] [--email <email>]
+  user set-password <user> [--password <password>]
+  user activate <user>
+  user deactivate <user>
+  user delete <user>
+  user restore <user>
+
+Roles and permissions:
+  role list
+  role create <name> [--description <text>]
+  role update <role> [--name <name>] [--description <text>]
+  role delete <role>
+  role assign <user> <role>
+  role unassign <user> <role>
+  permission list
+  permission create <name> <resource> <action>
+  permission update <permission> [--name <name>] [--resource <resource>]
+                    [--action <action>]
+  permission delete <permission>
+  permission grant <role> <permission>
+  permission revoke <role> <permission>
+
+Operations:
+  migrate status
+  migrate apply
+  audit export [--user <user>]
+  key rotate
+";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Help,
+    UserList,
+    UserShow {
+        user: String,
+    },
+    UserCreate {
+        username: String,
+        email: String,
+        password: Option<String>,
+        admin: bool,
+    },
+    UserEdit {
+        user: String,
+        username: Option<String>,
+        email: Option<String>,
+    },
+    UserSetPassword {
+        user: String,
+        password: Option<String>,
+    },
+    UserActivate {
+        user: String,
+    },
+    UserDeactivate {
+        user: String,
+    },
+    UserDelete {
+        user: String,
+    },
+    UserRestore {
+        user: String,
+    },
+    RoleList,
+    RoleCreate {
+        name: String,
+        description: Option<String>,
+    },
+    RoleUpdate {
+        role: String,
+        name: Option<String>,
+        description: Option<String>,
+    },
+    RoleDelete {
+        role: String,
+    },
+    RoleAssign {
+        user: String,
+        role: String,
+    },
+    RoleUnassign {
+        user: String,
+        role: String,
+    },
+    PermissionList,
+    PermissionCreate {
+        name: String,
+        resource: String,
+        action: String,
+    },
+    PermissionUpdate {
+        permission: String,
+        name: Option<String>,
+        resource: Option<String>,
+        action: Option<String>,
+    },
+    PermissionDelete {
+        permission: String,
+    },
+    PermissionGrant {
+        role: String,
+        permission: String,
+    },
+    PermissionRevoke {
+        role: String,
+        permission: String,
+    },
+    MigrateStatus,
+    MigrateApply,
+    AuditExport {
+        user: Option<String>,
+    },
+    KeyRotate,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cli {
+    pub database_url: Option<String>,
+    pub command: Command,
+}
+
+const VALUE_FLAGS: &[&str] = &[
+    "database-url",
+    "password",
+    "description",
+    "user",
+    "username",
+    "email",
+    "name",
+    "resource",
+    "action",
+];
+const SWITCH_FLAGS: &[&str] = &["admin", "help"];
+
+/// Parses the arguments after the program name
+pub fn parse_args<I>(args: I) -> Result<Cli, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut positional = Vec::new();
+    let mut values = std::collections::HashMap::new();
+    let mut switches = HashSet::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "-h" {
+            switches.insert("help");
+            continue;
+        }
+        let Some(flag) = arg.strip_prefix("--") else {
+            positional.push(arg);
+            continue;
+        };
+        if let Some(&name) = SWITCH_FLAGS.iter().find(|&&f| f == flag) {
+            switches.insert(name);
+        } else if let Some(&name) = VALUE_FLAGS.iter().find(|&&f| f == flag) {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("--{name} requires a value"))?;
+            values.insert(name, value);
+        } else {
+            return Err(format!("unknown option '{arg}'"));
+        }
+    }
+
+    let database_url = values.remove("database-url");
+    if switches.contains("help") || positional.is_empty() {
+        return Ok(Cli {
+            database_url,
+            command: Command::Help,
+        });
+    }
+
+    let args: Vec<&str> = positional.iter().map(String::as_str).collect();
+    let arg = |s: &str| s.to_string();
+    let command = match args.as_slice() {
+        ["help"] => Command::Help,
+        ["user", "list"] => Command::UserList,
+        ["user", "show", user] => Command::UserShow { user: arg(user) },
+        ["user", "create", username, email] => Command::UserCreate {
+            username: arg(username),
+            email: arg(email),
+            password: values.remove("password"),
+            admin: switches.contains("admin"),
+        },
+        ["user", "edit", user] => Command::UserEdit {
+            user: arg(user),
+            username: values.remove("username"),
+            email: values.remove("email"),
+        },
+        ["user", "set-password", user] => Command::UserSetPassword {
+            user: arg(user),
+            password: values.remove("password"),
+        },
+        ["user", "activate", user] => Command::UserActivate { user: arg(user) },
+        ["user", "deactivate", user] => Command::UserDeactivate { user: arg(user) },
+        ["user", "delete", user] => Command::UserDelete { user: arg(user) },
+        ["user", "restore", user] => Command::UserRestore { user: arg(user) },
+        ["role", "list"] => Command::RoleList,
+        ["role", "create", name] => Command::RoleCreate {
+            name: arg(name),
+            description: values.remove("description"),
+        },
+        ["role", "update", role] => Command::RoleUpdate {
+            role: arg(role),
+            name: values.remove("name"),
+            description: values.remove("description"),
+        },
+        ["role", "delete", role] => Command::RoleDelete { role: arg(role) },
+        ["role", "assign", user, role] => Command::RoleAssign {
+            user: arg(user),
+            role: arg(role),
+        },
+        ["role", "unassign", user, role] => Command::RoleUnassign {
+            user: arg(user),
+            role: arg(role),
+        },
+        ["permission", "list"] => Command::PermissionList,
+        ["permission", "create", name, resource, action] => Command::PermissionCreate {
+            name: arg(name),
+            resource: arg(resource),
+            action: arg(action),
+        },
+        ["permission", "update", permission] => Command::PermissionUpdate {
+            permission: arg(permission),
+            name: values.remove("name"),
+            resource: values.remove("resource"),
+            action: values.remove("action"),
+        },
+        ["permission", "delete", permission] => Command::PermissionDelete {
+            permission: arg(permission),
+        },
+        ["permission", "grant", role, permission] => Command::PermissionGrant {
+            role: arg(role),
+            permission: arg(permission),
+        },
+        ["permission", "revoke", role, permission] => Command::PermissionRevoke {
+            role: arg(role),
+            permission: arg(permission),
+        },
+        ["migrate", "status"] => Command::MigrateStatus,
+        ["migrate", "apply"] => Command::MigrateApply,
+        ["audit", "export"] => Command::AuditExport {
+            user: values.remove("user"),
+        },
+        ["key", "rotate"] => Command::KeyRotate,
+        _ => return Err(format!("unknown command '{}'", positional.join(" "))),
+    };
+
+    if let Some(name) = values.keys().next() {
+        return Err(format!("--{name} does not apply to this command"));
+    }
+
+    Ok(Cli {
+        database_url,
+        command,
+    })
+}
+
+/// Prints a new `JWT_SECRET` and how to roll it out. The secret it replaces
+/// stays accepted for as long as the tokens it signed can last, so nobody is
+/// signed out by the rotation.
+pub fn rotate_key(out: &mut dyn Write) -> Result<()> {
+    let secret = generate_opaque_token();
+    let until = (Utc::now() + Duration::seconds(USER_TOKEN_TTL_SECONDS))
+        .to_rfc3339_opts(SecondsFormat::Secs, true);
+
+    writeln!(out, "{secret}")?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "Set these on the auth, weather and time services and restart them:"
+    )?;
+    writeln!(out, "  JWT_PREVIOUS_SECRET=<their current JWT_SECRET>")?;
+    writeln!(out, "  JWT_PREVIOUS_SECRET_UNTIL={until}")?;
+    writeln!(out, "  JWT_SECRET={secret}")?;
+    writeln!(
+        out,
+        "Tokens signed with the previous secret are accepted until {until}; unset \
+         JWT_PREVIOUS_SECRET and JWT_PREVIOUS_SECRET_UNTIL after that."
+    )?;
+    Ok(())
+}
+
+enum Pool {
+    Postgres(PgPool),
+    Sqlite(SqlitePool),
+}
+
+/// A connection to the auth database that commands run against
+pub struct Admin {
+    database_url: String,
+    pool: Pool,
+    repos: Repositories,
+}
+
+impl Admin {
+    pub async fn connect(database_url: &str) -> Result<Self> {
+        let (pool, repos) = match DatabaseBackend::from_url(database_url) {
+            Some(DatabaseBackend::Postgres) => {
+                let pool = create_pool(database_url).await?;
+                (Pool::Postgres(pool.clone()), Repositories::postgres(pool))
+            }
+            Some(DatabaseBackend::Sqlite) => {
+                let pool = create_sqlite_pool(database_url).await?;
+                (Pool::Sqlite(pool.clone()), Repositories::sqlite(pool))
+            }
+            None => bail!("DATABASE_URL must start with postgres://, postgresql:// or sqlite:"),
+        };
+
+        Ok(Self {
+            database_url: database_url.to_string(),
+            pool,
+            repos,
+        })
+    }
+
+    /// Runs a command, reading passwords from `input` and reporting to `out`
+    pub async fn run(
+        &self,
+        command: Command,
+        input: &mut dyn BufRead,
+        out: &mut dyn Write,
+    ) -> Result<()> {
+        let repos = &self.repos;
+
+        match command {
+            Command::Help => write!(out, "{USAGE}")?,
+            Command::UserList => {
+                for user in repos.users.list().await? {
+                    print_user_row(out, &user)?;
+                }
+            }
+            Command::UserShow { user } => {
+                let user = self.find_user(&user).await?;
+                let (roles, permissions) = load_roles_and_permissions(repos, user.id)
+                    .await
+                    .map_err(|e| anyhow!("{e}"))?;
+                writeln!(out, "id:          {}", user.id)?;
+                writeln!(out, "username:    {}", user.username)?;
+                writeln!(out, "email:       {}", user.email)?;
+                writeln!(out, "active:      {}", user.is_active)?;
+                writeln!(out, "created:     {}", user.created_at.to_rfc3339())?;
+                writeln!(out, "roles:       {}", roles.join(", "))?;
+                writeln!(out, "permissions: {}", permissions.join(", "))?;
+            }
+            Command::UserCreate {
+                username,
+                email,
+                password,
+                admin,
+            } => {
+                let password = read_password(password, input)?;
+                let role_name = if admin { "admin" } else { "user" };
+                let role = self.find_role(role_name).await?;
+                let user = repos
+                    .users
+                    .create_with_roles(&username, &email, &hash_password(&password)?, &[role.id])
+                    .await?;
+                writeln!(
+                    out,
+                    "Created user '{}' ({}) with role '{role_name}'",
+                    user.username, user.id
+                )?;
+            }
+            Command::UserEdit {
+                user,
+                username,
+                email,
+            } => {
+                if username.is_none() && email.is_none() {
+                    bail!("nothing to change; give --username and/or --email");
+                }
+                if username.as_deref() == Some("") || email.as_deref() == Some("") {
+                    bail!("username and email cannot be empty");
+                }
+                let user = self.find_user(&user).await?;
+                let update = UpdateUser {
+                    username,
+                    email,
+                    password: None,
+                    is_active: None,
+                };
+                let updated = repos
+                    .users
+                    .update(user.id, &update, Some(user.version))
+                    .await?
+                    .ok_or_else(|| anyhow!("user '{}' not found", user.username))?;
+                writeln!(
+                    out,
+                    "Updated '{}' ({}): {} <{}>",
+                    user.username, updated.id, updated.username, updated.email
+                )?;
+            }
+            Command::UserSetPassword { user, password } => {
+                let user = self.find_user(&user).await?;
+                let password = read_password(password, input)?;
+                let update = UpdateUser {
+                    username: None,
+                    email: None,
+                    password: Some(hash_password(&password)?),
+                    is_active: None,
+                };
+                repos.users.update(user.id, &update, None).await?;
+                let revoked = repos.sessions.revoke_all_for_user(user.id, None).await?;
+                writeln!(
+                    out,
+                    "Password updated for '{}'; revoked {revoked} session(s)",
+                    user.username
+                )?;
+            }
+            Command::UserActivate { user } => {
+                let user = self.set_active(&user, true).await?;
+                writeln!(out, "Activated '{}'", user.username)?;
+            }
+            Command::UserDeactivate { user } => {
+                let user = self.set_active(&user, false).await?;
+                let revoked = repos.sessions.revoke_all_for_user(user.id, None).await?;
+                writeln!(
+                    out,
+                    "Deactivated '{}'; revoked {revoked} session(s)",
+                    user.username
+                )?;
+            }
+            Command::UserDelete { user } => {
+                let user = self.find_user(&user).await?;
+                repos.users.delete(user.id, None).await?;
+                let revoked = repos.sessions.revoke_all_for_user(user.id, None).await?;
+                writeln!(
+                    out,
+                    "Deleted '{}' ({}); revoked {revoked} session(s). It can be restored until purged",
+                    user.username, user.id
+                )?;
+            }
+            Command::UserRestore { user } => {
+                // Deleted users are invisible to find_user
+                let deleted = repos
+                    .users
+                    .list_deleted()
+                    .await?
+                    .into_iter()
+                    .find(|u| {
+                        u.id.to_string() == user
+                            || fold_username(&u.username) == fold_username(&user)
+                    })
+                    .ok_or_else(|| anyhow!("no deleted user '{user}'"))?;
+                let restored = repos
+                    .users
+                    .restore(deleted.id)
+                    .await?
+                    .ok_or_else(|| anyhow!("no deleted user '{user}'"))?;
+                writeln!(out, "Restored '{}' ({})", restored.username, restored.id)?;
+            }
+            Command::RoleList => {
+                for role in repos.roles.list().await? {
+                    writeln!(
+                        out,
+                        "{}  {:<24}  {}",
+                        role.id,
+                        role.name,
+                        role.description.as_deref().unwrap_or("")
+                    )?;
+                }
+            }
+            Command::RoleCreate { name, description } => {
+                let role = repos.roles.create(&name, description.as_deref()).await?;
+                writeln!(out, "Created role '{}' ({})", role.name, role.id)?;
+            }
+            Command::RoleUpdate {
+                role,
+                name,
+                description,
+            } => {
+                let role = self.find_role(&role).await?;
+                let name = name.unwrap_or_else(|| role.name.clone());
+                if name.is_empty() {
+                    bail!("role name cannot be empty");
+                }
+                if name != role.name && BUILTIN_ROLES.contains(&role.name.as_str()) {
+                    bail!("built-in role '{}' cannot be renamed", role.name);
+                }
+                let description = description.or_else(|| role.description.clone());
+                let updated = repos
+                    .roles
+                    .update(role.id, &name, description.as_deref(), Some(role.version))
+                    .await?
+                    .ok_or_else(|| anyhow!("role '{}' not found", role.name))?;
+                writeln!(out, "Updated role '{}' ({})", updated.name, updated.id)?;
+            }
+            Command::RoleDelete { role } => {
+                let role = self.find_role(&role).await?;
+                if BUILTIN_ROLES.contains(&role.name.as_str()) {
+                    bail!("built-in role '{}' cannot be deleted", role.name);
+                }
+                if !repos.roles.delete(role.id, Some(role.version)).await? {
+                    bail!("role '{}' not found", role.name);
+                }
+                writeln!(out, "Deleted role '{}' ({})", role.name, role.id)?;
+            }
+            Command::RoleAssign { user, role } => {
+                let user = self.find_user(&user).await?;
+                let role = self.find_role(&role).await?;
+                repos
+                    .assignments
+                    .assign_role_to_user(user.id, role.id)
+                    .await?;
+                writeln!(out, "Assigned role '{}' to '{}'", role.name, user.username)?;
+            }
+            Command::RoleUnassign { user, role } => {
+                let user = self.find_user(&user).await?;
+                let role = self.find_role(&role).await?;
+                if !repos
+                    .assignments
+                    .remove_role_from_user(user.id, role.id)
+                    .await?
+                {
+                    bail!("'{}' does not have role '{}'", user.username, role.name);
+                }
+                writeln!(out, "Removed role '{}' from '{}'", role.name, user.username)?;
+            }
+            Command::PermissionList => {
+                for permission in repos.permissions.list().await? {
+                    print_permission_row(out, &permission)?;
+                }
+            }
+            Command::PermissionCreate {
+                name,
+                resource,
+                action,
+            } => {
+                let permission = repos.permissions.create(&name, &resource, &action).await?;
+                writeln!(
+                    out,
+                    "Created permission '{}' ({})",
+                    permission.name, permission.id
+                )?;
+            }
+            Command::PermissionUpdate {
+                permission,
+                name,
+                resource,
+                action,
+            } => {
+                let permission = self.find_permission(&permission).await?;
+                let name = name.unwrap_or_else(|| permission.name.clone());
+                let resource = resource.unwrap_or_else(|| permission.resource.clone());
+                let action = action.unwrap_or_else(|| permission.action.clone());
+                if name.is_empty() || resource.is_empty() || action.is_empty() {
+                    bail!("permission name, resource and action cannot be empty");
+                }
+                let updated = repos
+                    .permissions
+                    .update(
+                        permission.id,
+                        &name,
+                        &resource,
+                        &action,
+                        Some(permission.version),
+                    )
+                    .await?
+                    .ok_or_else(|| anyhow!("permission '{}' not found", permission.name))?;
+                writeln!(
+                    out,
+                    "Updated permission '{}' ({})",
+                    updated.name, updated.id
+                )?;
+            }
+            Command::PermissionDelete { permission } => {
+                let permission = self.find_permission(&permission).await?;
+                if !repos
+                    .permissions
+                    .delete(permission.id, Some(permission.version))
+                    .await?
+                {
+                    bail!("permission '{}' not found", permission.name);
+                }
+                writeln!(
+                    out,
+                    "Deleted permission '{}' ({})",
+                    permission.name, permission.id
+                )?;
+            }
+            Command::PermissionGrant { role, permission } => {
+                let role = self.find_role(&role).await?;
+                let permission = self.find_permission(&permission).await?;
+                repos
+                    .assignments
+                    .assign_permission_to_role(role.id, permission.id)
+                    .await?;
+                writeln!(out, "Granted '{}' to role '{}'", permission.name, role.name)?;
+            }
+            Command::PermissionRevoke { role, permission } => {
+                let role = self.find_role(&role).await?;
+                let permission = self.find_permission(&permission).await?;
+                if !repos
+                    .assignments
+                    .remove_permission_from_role(role.id, permission.id)
+                    .await?
+                {
+                    bail!("Role '{}' does not have '{}'", role.name, permission.name);
+                }
+                writeln!(
+                    out,
+                    "Revoked '{}' from role '{}'",
+                    permission.name, role.name
+                )?;
+            }
+            Command::MigrateStatus => match &self.pool {
+                Pool::Postgres(pool) => {
+                    let mut conn = pool.acquire().await?;
+                    print_migration_status(out, &MIGRATOR, &mut *conn).await?;
+                }
+                Pool::Sqlite(pool) => {
+                    let mut conn = pool.acquire().await?;
+                    print_migration_status(out, &SQLITE_MIGRATOR, &mut *conn).await?;
+                }
+            },
+            Command::MigrateApply => {
+                match &self.pool {
+                    Pool::Postgres(pool) => {
+                        let lock = StartupLock::acquire(&self.database_url).await?;
+                        run_migrations(pool).await?;
+                        lock.release().await?;
+                    }
+                    Pool::Sqlite(pool) => run_sqlite_migrations(pool).await?,
+                }
+                writeln!(out, "Migrations are up to date")?;
+            }
+            Command::AuditExport { user } => {
+                let mut records = Vec::new();
+                match user {
+                    Some(user) => {
+                        // An id is taken as is: deleted and erased users
+                        // still have an audit trail
+                        let user_id = match Uuid::parse_str(&user) {
+                            Ok(id) => id,
+                            Err(_) => self.find_user(&user).await?.id,
+                        };
+                        let events = repos.privacy.user_events(user_id).await?;
+                        let impersonations = repos.privacy.user_impersonations(user_id).await?;
+                        records.extend(events.into_iter().map(AuditRecord::event));
+                        records.extend(impersonations.into_iter().map(AuditRecord::Impersonation));
+                    }
+                    None => {
+                        let events = repos.webhooks.list_events().await?;
+                        let impersonations = repos.impersonations.list(None).await?;
+                        let erasures = repos.privacy.list_erasures().await?;
+                        records.extend(events.into_iter().map(AuditRecord::event));
+                        records.extend(impersonations.into_iter().map(AuditRecord::Impersonation));
+                        records.extend(erasures.into_iter().map(AuditRecord::Erasure));
+                    }
+                }
+                records.sort_by_key(AuditRecord::created_at);
+                for record in records {
+                    writeln!(out, "{}", serde_json::to_string(&record)?)?;
+                }
+            }
+            Command::KeyRotate => rotate_key(out)?,
+        }
+
+        Ok(())
+    }
+
+    async fn find_user(&self, user: &str) -> Result<User> {
+        let found = match Uuid::parse_str(user) {
+            Ok(id) => self.repos.users.find_by_id(id).await?,
+            Err(_) => self.repos.users.find_by_username(user).await?,
+        };
+        found.ok_or_else(|| anyhow!("user '{user}' not found"))
+    }
+
+    async fn find_role(&self, role: &str) -> Result<Role> {
+        let found = match Uuid::parse_str(role) {
+            Ok(id) => self.repos.roles.find_by_id(id).await?,
+            Err(_) => self.repos.roles.find_by_name(role).await?,
+        };
+        found.ok_or_else(|| anyhow!("role '{role}' not found"))
+    }
+
+    async fn find_permission(&self, permission: &str) -> Result<Permission> {
+        let found = match Uuid::parse_str(permission) {
+            Ok(id) => self.repos.permissions.find_by_id(id).await?,
+            Err(_) => self.repos.permissions.find_by_name(permission).await?,
+        };
+        found.ok_or_else(|| anyhow!("permission '{permission}' not found"))
+    }
+
+    async fn set_active(&self, user: &str, is_active: bool) -> Result<User> {
+        let user = self.find_user(user).await?;
+        let update = UpdateUser {
+            username: None,
+            email: None,
+            password: None,
+            is_active: Some(is_active),
+        };
+        self.repos
+            .users
+            .update(user.id, &update, None)
+            .await?
+            .ok_or_else(|| anyhow!("user '{}' not found", user.username))
+    }
+}
+
+/// A line of `audit export`: an outbox event, an impersonation or an erasure
+#[derive(Debug, Serialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+enum AuditRecord {
+    Event(ExportedEvent),
+    Impersonation(ImpersonationEvent),
+    Erasure(Erasure),
+}
+
+impl AuditRecord {
+    fn event(event: WebhookEvent) -> Self {
+        Self::Event(event.into())
+    }
+
+    fn created_at(&self) -> DateTime<Utc> {
+        match self {
+            Self::Event(event) => event.created_at,
+            Self::Impersonation(impersonation) => impersonation.created_at,
+            Self::Erasure(erasure) => erasure.created_at,
+        }
+    }
+}
+
+fn read_password(password: Option<String>, input: &mut dyn BufRead) -> Result<String> {
+    let password = match password {
+        Some(password) => password,
+        None => {
+            let mut line = String::new();
+            input
+                .read_line(&mut line)
+                .context("Failed to read password from stdin")?;
+            line.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+
+    if password.len() < 8 {
+        bail!("Password must be at least 8 characters long");
+    }
+
+    Ok(password)
+}
+
+fn print_user_row(out: &mut dyn Write, user: &User) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "{}  {:<24}  {:<32}  {}",
+        user.id,
+        user.username,
+        user.email,
+        if user.is_active { "active" } else { "inactive" }
+    )
+}
+
+fn print_permission_row(out: &mut dyn Write, permission: &Permission) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "{}  {:<24}  {}:{}",
+        permission.id, permission.name, permission.resource, permission.action
+    )
+}
+
+async fn print_migration_status<C>(
+    out: &mut dyn Write,
+    migrator: &Migrator,
+    conn: &mut C,
+) -> Result<()>
+where
+    C: Migrate + ?Sized,
+{
+    conn.ensure_migrations_table().await?;
+    let applied: HashSet<i64> = conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|m| m.version)
+        .collect();
+
+    for migration in migrator.iter() {
+        let state = if applied.contains(&migration.version) {
+            "applied"
+        } else {
+            "pending"
+        };
+        writeln!(
+            out,
+            "{}  {:<8}  {}",
+            migration.version, state, migration.description
+        )?;
+    }
+
+    Ok(())
+}
@@ -1,10 +1,248 @@
+use shared::PreviousSecret;
 use std::env;
+use std::str::FromStr;
+
+/// Controls who may create an account without an admin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistrationMode {
+    /// Anyone may self-register via `/auth/register`
+    Open,
+    /// Accounts can only be created by accepting an admin-issued invitation
+    InviteOnly,
+    /// Only admins can create accounts
+    Closed,
+}
+
+impl FromStr for RegistrationMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "open" => Ok(Self::Open),
+            "invite-only" | "invite_only" => Ok(Self::InviteOnly),
+            "closed" => Ok(Self::Closed),
+            other => Err(format!("unknown registration mode '{other}'")),
+        }
+    }
+}
+
+/// What access tokens carry about the holder's permissions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenFormat {
+    /// The full list of permissions granted by the user's roles
+    Full,
+    /// Only the roles and the permissions version they were issued under;
+    /// services resolve permissions from `/auth/role-permissions`
+    Compact,
+}
+
+impl FromStr for TokenFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "full" => Ok(Self::Full),
+            "compact" => Ok(Self::Compact),
+            other => Err(format!("unknown token format '{other}'")),
+        }
+    }
+}
+
+/// A way `POST /auth/login` can check a password
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthBackendKind {
+    /// The password hash stored with the local user
+    Local,
+    /// A bind to the LDAP directory as the user
+    Ldap,
+}
+
+impl FromStr for AuthBackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "local" => Ok(Self::Local),
+            "ldap" => Ok(Self::Ldap),
+            other => Err(format!("unknown auth backend '{other}'")),
+        }
+    }
+}
+
+/// The LDAP directory users can sign in against, configured by `LDAP_*`
+/// variables
+#[derive(Debug, Clone)]
+pub struct LdapConfig {
+    /// `ldap://` or `ldaps://` URL of the directory
+    pub url: String,
+    /// Upgrade `ldap://` connections with StartTLS
+    pub starttls: bool,
+    /// DN to bind as, with `{username}` standing for the name signed in with
+    pub user_dn_template: String,
+    pub email_attribute: String,
+    /// Attribute of the user's entry listing the groups they belong to
+    pub groups_attribute: String,
+    /// Roles granted for each group DN, as (lowercased group DN, role name)
+    /// pairs
+    pub group_roles: Vec<(String, String)>,
+    /// Create users signing in for the first time; otherwise only users
+    /// already linked, or found by email address, can sign in
+    pub auto_provision: bool,
+    /// Whether the directory's email addresses are verified, so a first
+    /// login links the local user with the same address. Off unless the
+    /// directory keeps users from setting their own.
+    pub trust_email: bool,
+}
+
+impl LdapConfig {
+    fn from_env() -> Option<Self> {
+        let var = |key: &str| env::var(key).ok().filter(|v| !v.is_empty());
+        let flag = |key: &str, default: bool| {
+            var(key)
+                .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+                .unwrap_or(default)
+        };
+
+        let url = var("LDAP_URL")?;
+        let user_dn_template = var("LDAP_USER_DN_TEMPLATE")
+            .filter(|template| template.contains("{username}"))
+            .expect("LDAP_USER_DN_TEMPLATE must be set and contain {username}");
+        let group_roles = var("LDAP_GROUP_ROLES")
+            .map(|mapping| parse_group_roles("LDAP_GROUP_ROLES", &mapping, ';'))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(group, role)| (group.to_lowercase(), role))
+            .collect();
+
+        Some(Self {
+            url,
+            starttls: flag("LDAP_STARTTLS", false),
+            user_dn_template,
+            email_attribute: var("LDAP_EMAIL_ATTRIBUTE").unwrap_or_else(|| "mail".to_string()),
+            groups_attribute: var("LDAP_GROUPS_ATTRIBUTE")
+                .unwrap_or_else(|| "memberOf".to_string()),
+            group_roles,
+            auto_provision: flag("LDAP_AUTO_PROVISION", true),
+            trust_email: flag("LDAP_TRUST_EMAIL", false),
+        })
+    }
+}
+
+/// Credentials for the admin account created on first startup
+#[derive(Debug, Clone)]
+pub struct BootstrapAdmin {
+    pub username: String,
+    pub email: String,
+    pub password: String,
+}
+
+/// Parses `group=role` pairs separated by `separator`. Splits at the last
+/// `=`, so groups can be LDAP distinguished names.
+fn parse_group_roles(var: &str, mapping: &str, separator: char) -> Vec<(String, String)> {
+    mapping
+        .split(separator)
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| {
+            let (group, role) = pair
+                .rsplit_once('=')
+                .unwrap_or_else(|| panic!("{var} must be a list of group=role pairs"));
+            (group.trim().to_string(), role.trim().to_string())
+        })
+        .collect()
+}
+
+/// An upstream OpenID Connect provider users can sign in with, configured
+/// by `OIDC_<NAME>_*` variables for each name listed in `OIDC_PROVIDERS`
+#[derive(Debug, Clone)]
+pub struct OidcProvider {
+    /// Names the provider in `/auth/oidc/{name}/...`
+    pub name: String,
+    /// Issuer URL; the provider's metadata is discovered below it
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Space-separated scopes to request
+    pub scopes: String,
+    /// Callback registered at the provider; defaults to
+    /// `/auth/oidc/{name}/callback` on the host the login started on
+    pub redirect_uri: Option<String>,
+    /// ID token claim naming the local user to create
+    pub username_claim: String,
+    pub email_claim: String,
+    /// ID token claim listing the user's groups at the provider
+    pub groups_claim: String,
+    /// Roles granted for each group, as (group, role name) pairs
+    pub group_roles: Vec<(String, String)>,
+    /// Create users signing in for the first time; otherwise only users
+    /// already linked, or found by a verified email address, can sign in
+    pub auto_provision: bool,
+}
+
+impl OidcProvider {
+    fn from_env(name: &str) -> Self {
+        let prefix = format!("OIDC_{}", name.to_ascii_uppercase().replace('-', "_"));
+        let var = |key: &str| {
+            env::var(format!("{prefix}_{key}"))
+                .ok()
+                .filter(|v| !v.is_empty())
+        };
+        let required = |key: &str| var(key).unwrap_or_else(|| panic!("{prefix}_{key} must be set"));
+
+        let group_roles = var("GROUP_ROLES")
+            .map(|mapping| parse_group_roles(&format!("{prefix}_GROUP_ROLES"), &mapping, ','))
+            .unwrap_or_default();
+
+        Self {
+            name: name.to_string(),
+            issuer: required("ISSUER").trim_end_matches('/').to_string(),
+            client_id: required("CLIENT_ID"),
+            client_secret: required("CLIENT_SECRET"),
+            scopes: var("SCOPES").unwrap_or_else(|| "openid email profile".to_string()),
+            redirect_uri: var("REDIRECT_URI"),
+            username_claim: var("USERNAME_CLAIM")
+                .unwrap_or_else(|| "preferred_username".to_string()),
+            email_claim: var("EMAIL_CLAIM").unwrap_or_else(|| "email".to_string()),
+            groups_claim: var("GROUPS_CLAIM").unwrap_or_else(|| "groups".to_string()),
+            group_roles,
+            auto_provision: var("AUTO_PROVISION")
+                .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+                .unwrap_or(true),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub database_url: String,
     pub jwt_secret: String,
+    /// The secret `jwt_secret` replaced, accepted until its grace window ends
+    pub jwt_previous_secret: Option<PreviousSecret>,
     pub port: u16,
+    pub registration_mode: RegistrationMode,
+    /// Bearer token for the SCIM provisioning client; SCIM is disabled when unset
+    pub scim_bearer_token: Option<String>,
+    /// Apply the embedded migrations before serving requests
+    pub run_migrations: bool,
+    /// Initial admin to create if no user holds the admin role; when unset a
+    /// one-time setup token is printed instead
+    pub bootstrap_admin: Option<BootstrapAdmin>,
+    /// Days a soft-deleted user can still be restored before being purged
+    pub user_retention_days: i64,
+    /// Attempts at a webhook delivery before it is marked failed
+    pub webhook_max_attempts: i32,
+    pub token_format: TokenFormat,
+    /// The page where users enter device user codes, which calls
+    /// `/oauth/device` for them; the device flow is refused when unset
+    pub device_verification_uri: Option<String>,
+    /// The public URL of `POST /auth/token`, which signed client assertions
+    /// must name as their audience; they are refused when unset
+    pub token_endpoint_url: Option<String>,
+    /// Upstream identity providers users can sign in with
+    pub oidc_providers: Vec<OidcProvider>,
+    /// How `POST /auth/login` checks passwords, tried in order until one
+    /// accepts
+    pub auth_backends: Vec<AuthBackendKind>,
+    pub ldap: Option<LdapConfig>,
 }
 
 impl Config {
@@ -12,16 +250,114 @@ impl Config {
         let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
 
         let jwt_secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+        let jwt_previous_secret = PreviousSecret::from_env();
 
         let port = env::var("PORT")
             .unwrap_or_else(|_| "8000".to_string())
             .parse::<u16>()
             .expect("PORT must be a valid number");
 
+        let registration_mode = env::var("REGISTRATION_MODE")
+            .unwrap_or_else(|_| "open".to_string())
+            .parse::<RegistrationMode>()
+            .expect("REGISTRATION_MODE must be one of: open, invite-only, closed");
+
+        let scim_bearer_token = env::var("SCIM_BEARER_TOKEN").ok().filter(|t| !t.is_empty());
+
+        let run_migrations = env::var("RUN_MIGRATIONS")
+            .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+
+        let bootstrap_admin = match (
+            env::var("BOOTSTRAP_ADMIN_USERNAME").ok().filter(|v| !v.is_empty()),
+            env::var("BOOTSTRAP_ADMIN_EMAIL").ok().filter(|v| !v.is_empty()),
+            env::var("BOOTSTRAP_ADMIN_PASSWORD").ok().filter(|v| !v.is_empty()),
+        ) {
+            (Some(username), Some(email), Some(password)) => Some(BootstrapAdmin {
+                username,
+                email,
+                password,
+            }),
+            (None, None, None) => None,
+            _ => panic!(
+                "BOOTSTRAP_ADMIN_USERNAME, BOOTSTRAP_ADMIN_EMAIL and BOOTSTRAP_ADMIN_PASSWORD must be set together"
+            ),
+        };
+
+        let user_retention_days = env::var("USER_RETENTION_DAYS")
+            .unwrap_or_else(|_| "30".to_string())
+            .parse::<i64>()
+            .ok()
+            .filter(|days| *days >= 0)
+            .expect("USER_RETENTION_DAYS must be a non-negative number of days");
+
+        let webhook_max_attempts = env::var("WEBHOOK_MAX_ATTEMPTS")
+            .unwrap_or_else(|_| "8".to_string())
+            .parse::<i32>()
+            .ok()
+            .filter(|attempts| *attempts >= 1)
+            .expect("WEBHOOK_MAX_ATTEMPTS must be a positive number");
+
+        let token_format = env::var("TOKEN_FORMAT")
+            .unwrap_or_else(|_| "full".to_string())
+            .parse::<TokenFormat>()
+            .expect("TOKEN_FORMAT must be full or compact");
+
+        let device_verification_uri = env::var("DEVICE_VERIFICATION_URI")
+            .ok()
+            .filter(|v| !v.is_empty());
+
+        let token_endpoint_url = env::var("TOKEN_ENDPOINT_URL")
+            .ok()
+            .filter(|v| !v.is_empty());
+
+        let oidc_providers = env::var("OIDC_PROVIDERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(OidcProvider::from_env)
+            .collect();
+
+        let ldap = LdapConfig::from_env();
+        // With a directory, it decides for the users it knows
+        let default_backends = if ldap.is_some() {
+            "ldap,local"
+        } else {
+            "local"
+        };
+        let auth_backends: Vec<AuthBackendKind> = env::var("AUTH_BACKENDS")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| default_backends.to_string())
+            .split(',')
+            .map(|name| name.trim().parse())
+            .collect::<Result<_, _>>()
+            .expect("AUTH_BACKENDS must be a list of: local, ldap");
+        if auth_backends.is_empty() {
+            panic!("AUTH_BACKENDS must name at least one backend");
+        }
+        if auth_backends.contains(&AuthBackendKind::Ldap) && ldap.is_none() {
+            panic!("AUTH_BACKENDS includes ldap, but LDAP_URL is not set");
+        }
+
         Self {
             database_url,
             jwt_secret,
+            jwt_previous_secret,
             port,
+            registration_mode,
+            scim_bearer_token,
+            run_migrations,
+            bootstrap_admin,
+            user_retention_days,
+            webhook_max_attempts,
+            token_format,
+            device_verification_uri,
+            token_endpoint_url,
+            oidc_providers,
+            auth_backends,
+            ldap,
         }
     }
 }
@@ -0,0 +1,36 @@
+use sqlx::{Connection, PgConnection};
+
+/// Advisory lock key for startup work; the ASCII bytes of "karlauth"
+const STARTUP_LOCK_KEY: i64 = 0x6b61_726c_6175_7468;
+
+/// A Postgres advisory lock serializing startup work (migrations and the
+/// admin bootstrap) across replicas sharing a database.
+///
+/// The lock lives on a dedicated connection rather than a pooled one, so it is
+/// released when the connection closes even if `release` is never reached.
+pub struct StartupLock {
+    conn: PgConnection,
+}
+
+impl StartupLock {
+    /// Waits until no other replica holds the lock
+    pub async fn acquire(database_url: &str) -> Result<Self, sqlx::Error> {
+        let mut conn = PgConnection::connect(database_url).await?;
+
+        sqlx::query("SELECT pg_advisory_lock($1)")
+            .bind(STARTUP_LOCK_KEY)
+            .execute(&mut conn)
+            .await?;
+
+        Ok(Self { conn })
+    }
+
+    pub async fn release(mut self) -> Result<(), sqlx::Error> {
+        sqlx::query("SELECT pg_advisory_unlock($1)")
+            .bind(STARTUP_LOCK_KEY)
+            .execute(&mut self.conn)
+            .await?;
+
+        self.conn.close().await
+    }
+}
@@ -0,0 +1,74 @@
+use crate::models::{fold_username, normalize_email, normalize_username};
+use log::{error, info};
+use sqlx::migrate::{MigrateError, Migrator};
+use sqlx::{PgPool, SqlitePool};
+use uuid::Uuid;
+
+/// The Postgres schema, embedded at compile time from `migrations`
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// The SQLite schema, embedded at compile time from `migrations_sqlite`
+pub static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("./migrations_sqlite");
+
+/// Applies any pending migrations. The migrator holds its own advisory lock
+/// while it runs, so replicas starting together apply each migration once.
+pub async fn run_migrations(pool: &PgPool) -> Result<(), MigrateError> {
+    info!("Applying database migrations...");
+    MIGRATOR.run(pool).await?;
+    info!("Database migrations are up to date");
+
+    Ok(())
+}
+
+pub async fn run_sqlite_migrations(pool: &SqlitePool) -> Result<(), MigrateError> {
+    info!("Applying SQLite migrations...");
+    SQLITE_MIGRATOR.run(pool).await?;
+    normalize_non_ascii_users(pool).await?;
+    info!("SQLite migrations are up to date");
+
+    Ok(())
+}
+
+/// Finishes migration 20240101000017 for usernames and emails outside ASCII:
+/// SQLite has no Unicode normalization and its `lower()` only folds ASCII,
+/// so such rows are normalized here the way lookups fold them. Rows already
+/// normalized are left alone, so this is cheap to repeat. A row that would
+/// collide with another user stops the upgrade, and the log names the user.
+async fn normalize_non_ascii_users(pool: &SqlitePool) -> Result<(), MigrateError> {
+    let users: Vec<(Uuid, String, String, String)> = sqlx::query_as(
+        "SELECT id, username, username_folded, email FROM users
+         WHERE username GLOB '*[^ -~]*' OR email GLOB '*[^ -~]*'",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut tx = pool.begin().await?;
+    for (id, username, username_folded, email) in users {
+        let normalized = (
+            normalize_username(&username),
+            fold_username(&username),
+            normalize_email(&email),
+        );
+        if normalized == (username.clone(), username_folded, email.clone()) {
+            continue;
+        }
+
+        sqlx::query("UPDATE users SET username = ?, username_folded = ?, email = ? WHERE id = ?")
+            .bind(&normalized.0)
+            .bind(&normalized.1)
+            .bind(&normalized.2)
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                error!(
+                    "User {username} <{email}> ({id}) differs from another user only in case \
+                     or Unicode form; rename one of them before upgrading"
+                );
+                MigrateError::Execute(e)
+            })?;
+    }
+    tx.commit().await?;
+
+    Ok(())
+}
@@ -1,3 +1,7 @@
+pub mod lock;
+pub mod migrate;
 pub mod pool;
 
-pub use pool::create_pool;
+pub use lock::StartupLock;
+pub use migrate::{run_migrations, run_sqlite_migrations, MIGRATOR, SQLITE_MIGRATOR};
+pub use pool::{create_pool, create_sqlite_pool, DatabaseBackend};
@@ -1,7 +1,31 @@
 use log::info;
-use sqlx::{postgres::PgPoolOptions, PgPool};
+use sqlx::{
+    postgres::PgPoolOptions,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions},
+    PgPool, SqlitePool,
+};
+use std::str::FromStr;
 use std::time::Duration;
 
+/// Database engines auth-service can run on, picked by the `DATABASE_URL` scheme
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatabaseBackend {
+    Postgres,
+    Sqlite,
+}
+
+impl DatabaseBackend {
+    pub fn from_url(database_url: &str) -> Option<Self> {
+        if database_url.starts_with("postgres://") || database_url.starts_with("postgresql://") {
+            Some(Self::Postgres)
+        } else if database_url.starts_with("sqlite:") {
+            Some(Self::Sqlite)
+        } else {
+            None
+        }
+    }
+}
+
 pub async fn create_pool(database_url: &str) -> Result<PgPool, sqlx::Error> {
     info!("Connecting to database...");
 
@@ -15,3 +39,32 @@ pub async fn create_pool(database_url: &str) -> Result<PgPool, sqlx::Error> {
 
     Ok(pool)
 }
+
+/// Opens a SQLite database, creating the file if it does not exist yet
+pub async fn create_sqlite_pool(database_url: &str) -> Result<SqlitePool, sqlx::Error> {
+    info!("Opening SQLite database...");
+
+    let options = SqliteConnectOptions::from_str(database_url)?
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal)
+        .foreign_keys(true);
+
+    let mut pool_options = SqlitePoolOptions::new()
+        .max_connections(5)
+        .acquire_timeout(Duration::from_secs(30));
+
+    // Every connection to an in-memory database sees its own empty database,
+    // and the data is gone once that connection closes
+    if database_url.contains(":memory:") {
+        pool_options = pool_options
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None);
+    }
+
+    let pool = pool_options.connect_with(options).await?;
+
+    info!("SQLite connection pool created successfully");
+
+    Ok(pool)
+}
@@ -1,12 +1,26 @@
-use crate::handlers::auth::RegisterRequest;
-use crate::models::User;
-use crate::services::hash_password;
-use actix_web::{web, HttpResponse, Responder};
+use crate::config::Config;
+use crate::handlers::auth::{
+    apply_token_format, load_roles_and_permissions, user_write_error, RegisterRequest,
+    RevokedSessionsResponse, SessionResponse,
+};
+use crate::handlers::conditional::{etag, optional_version, required_version, stale};
+use crate::models::user::UpdateUser;
+use crate::models::{Group, Invitation, InvitationStatus, User};
+use crate::repositories::{Repositories, RepositoryError};
+use crate::services::{
+    create_impersonation_claims, generate_opaque_token, generate_token, hash_opaque_token,
+    hash_password,
+};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
-use shared::{ApiResponse, AppError, AppResult};
-use sqlx::PgPool;
+use serde_json::Value;
+use shared::{Actor, ApiResponse, AppError, AppResult, Claims, PrincipalType};
 use uuid::Uuid;
 
+/// Roles the migrations create and the service relies on; they cannot be
+/// renamed or deleted
+pub const BUILTIN_ROLES: [&str; 2] = ["admin", "user"];
+
 #[derive(Debug, Serialize)]
 pub struct UserResponse {
     pub id: Uuid,
@@ -28,8 +42,10 @@ impl From<User> for UserResponse {
     }
 }
 
-pub async fn list_users(pool: web::Data<PgPool>) -> AppResult<impl Responder> {
-    let users = User::list(&pool)
+pub async fn list_users(repos: web::Data<Repositories>) -> AppResult<impl Responder> {
+    let users = repos
+        .users
+        .list()
         .await
         .map_err(|e| AppError::Internal(format!("Failed to list users: {e}")))?;
 
@@ -37,20 +53,26 @@ pub async fn list_users(pool: web::Data<PgPool>) -> AppResult<impl Responder> {
     Ok(HttpResponse::Ok().json(ApiResponse::new(response)))
 }
 
-pub async fn get_user(pool: web::Data<PgPool>, path: web::Path<Uuid>) -> AppResult<impl Responder> {
+pub async fn get_user(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+) -> AppResult<impl Responder> {
     let user_id = path.into_inner();
 
-    let user = User::find_by_id(&pool, user_id)
+    let user = repos
+        .users
+        .find_by_id(user_id)
         .await
         .map_err(|e| AppError::Internal(format!("Failed to get user: {e}")))?
         .ok_or_else(|| AppError::NotFound(format!("User with id {user_id} not found")))?;
 
-    let response: UserResponse = user.into();
-    Ok(HttpResponse::Ok().json(ApiResponse::new(response)))
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(user.version))
+        .json(ApiResponse::new(UserResponse::from(user))))
 }
 
 pub async fn create_user(
-    pool: web::Data<PgPool>,
+    repos: web::Data<Repositories>,
     req: web::Json<RegisterRequest>,
 ) -> AppResult<impl Responder> {
     // Validate input
@@ -60,85 +82,153 @@ pub async fn create_user(
         ));
     }
 
-    // Check if username or email already exists
-    if User::find_by_username(&pool, &req.username)
-        .await
-        .map_err(|e| AppError::Internal(format!("Database error: {e}")))?
-        .is_some()
-    {
-        return Err(AppError::Conflict("Username already exists".to_string()));
-    }
-
-    if User::find_by_email(&pool, &req.email)
-        .await
-        .map_err(|e| AppError::Internal(format!("Database error: {e}")))?
-        .is_some()
-    {
-        return Err(AppError::Conflict("Email already exists".to_string()));
-    }
-
-    // Hash password
-    let password_hash = hash_password(&req.password)
-        .map_err(|e| AppError::Internal(format!("Failed to hash password: {e}")))?;
+    let password_hash = hash_new_password(&req.password)?;
 
     // Create user
-    let user = User::create(&pool, &req.username, &req.email, &password_hash)
+    let user = repos
+        .users
+        .create(&req.username, &req.email, &password_hash)
         .await
-        .map_err(|e| AppError::Internal(format!("Failed to create user: {e}")))?;
+        .map_err(|e| user_write_error(e, "create"))?;
 
-    let response: UserResponse = user.into();
-    Ok(HttpResponse::Created().json(ApiResponse::new(response)))
+    Ok(HttpResponse::Created()
+        .insert_header(etag(user.version))
+        .json(ApiResponse::new(UserResponse::from(user))))
 }
 
+/// Full replacement for `PUT /admin/users/{id}`; `password` is write-only
+/// and left unchanged when omitted
 #[derive(Debug, Deserialize)]
-pub struct UpdateUserRequest {
-    pub username: Option<String>,
-    pub email: Option<String>,
+pub struct ReplaceUserRequest {
+    pub username: String,
+    pub email: String,
+    pub is_active: bool,
     pub password: Option<String>,
-    pub is_active: Option<bool>,
 }
 
 pub async fn update_user(
-    pool: web::Data<PgPool>,
+    repos: web::Data<Repositories>,
+    http_req: HttpRequest,
     path: web::Path<Uuid>,
-    req: web::Json<UpdateUserRequest>,
+    req: web::Json<ReplaceUserRequest>,
 ) -> AppResult<impl Responder> {
     let user_id = path.into_inner();
+    let expected_version = required_version(&http_req, "User")?;
+    let req = req.into_inner();
 
-    let password_hash = if let Some(ref password) = req.password {
-        Some(
-            hash_password(password)
-                .map_err(|e| AppError::Internal(format!("Failed to hash password: {e}")))?,
-        )
-    } else {
-        None
+    if req.username.is_empty() || req.email.is_empty() {
+        return Err(AppError::BadRequest(
+            "Username and email are required".to_string(),
+        ));
+    }
+
+    let update = UpdateUser {
+        username: Some(req.username),
+        email: Some(req.email),
+        password: req.password.as_deref().map(hash_new_password).transpose()?,
+        is_active: Some(req.is_active),
+    };
+
+    apply_user_update(&repos, user_id, update, expected_version).await
+}
+
+/// `PATCH /admin/users/{id}` with a JSON Merge Patch (RFC 7396) document;
+/// members that are absent stay unchanged. `If-Match` is honoured but not
+/// required since a patch only touches the members it names.
+pub async fn patch_user(
+    repos: web::Data<Repositories>,
+    http_req: HttpRequest,
+    path: web::Path<Uuid>,
+    patch: web::Json<Value>,
+) -> AppResult<impl Responder> {
+    let user_id = path.into_inner();
+    let expected_version = optional_version(&http_req, "User")?;
+
+    let Value::Object(members) = patch.into_inner() else {
+        return Err(AppError::BadRequest(
+            "A merge patch must be a JSON object".to_string(),
+        ));
     };
 
-    let update = crate::models::user::UpdateUser {
-        username: req.username.clone(),
-        email: req.email.clone(),
-        password: password_hash,
-        is_active: req.is_active,
+    let mut update = UpdateUser {
+        username: None,
+        email: None,
+        password: None,
+        is_active: None,
     };
+    for (name, value) in members {
+        match (name.as_str(), value) {
+            ("username" | "email" | "password" | "is_active", Value::Null) => {
+                return Err(AppError::BadRequest(format!("'{name}' cannot be removed")))
+            }
+            ("username", Value::String(v)) if !v.is_empty() => update.username = Some(v),
+            ("email", Value::String(v)) if !v.is_empty() => update.email = Some(v),
+            ("password", Value::String(v)) => update.password = Some(hash_new_password(&v)?),
+            ("is_active", Value::Bool(v)) => update.is_active = Some(v),
+            ("username" | "email" | "password" | "is_active", _) => {
+                return Err(AppError::BadRequest(format!("Invalid value for '{name}'")))
+            }
+            _ => return Err(AppError::BadRequest(format!("'{name}' cannot be changed"))),
+        }
+    }
+
+    apply_user_update(&repos, user_id, update, expected_version).await
+}
+
+/// Hashes a password an admin sets, held to the same minimum as `register`
+fn hash_new_password(password: &str) -> AppResult<String> {
+    if password.len() < 8 {
+        return Err(AppError::BadRequest(
+            "Password must be at least 8 characters long".to_string(),
+        ));
+    }
+
+    hash_password(password).map_err(|e| AppError::Internal(format!("Failed to hash password: {e}")))
+}
 
-    let user = User::update(&pool, user_id, &update)
+async fn apply_user_update(
+    repos: &Repositories,
+    user_id: Uuid,
+    update: UpdateUser,
+    expected_version: Option<i32>,
+) -> AppResult<HttpResponse> {
+    let user = repos
+        .users
+        .update(user_id, &update, expected_version)
         .await
-        .map_err(|e| AppError::Internal(format!("Failed to update user: {e}")))?
+        .map_err(|e| user_write_error(e, "update"))?
         .ok_or_else(|| AppError::NotFound(format!("User with id {user_id} not found")))?;
 
-    let response: UserResponse = user.into();
-    Ok(HttpResponse::Ok().json(ApiResponse::new(response)))
+    // A password change or deactivation signs the user out everywhere
+    if update.password.is_some() || update.is_active == Some(false) {
+        repos
+            .sessions
+            .revoke_all_for_user(user_id, None)
+            .await
+            .map_err(|e| AppError::Internal(format!("Failed to revoke sessions: {e}")))?;
+    }
+
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(user.version))
+        .json(ApiResponse::new(UserResponse::from(user))))
 }
 
 pub async fn delete_user(
-    pool: web::Data<PgPool>,
+    repos: web::Data<Repositories>,
+    http_req: HttpRequest,
     path: web::Path<Uuid>,
 ) -> AppResult<impl Responder> {
     let user_id = path.into_inner();
+    let expected_version = required_version(&http_req, "User")?;
 
-    let deleted = User::delete(&pool, user_id)
+    let deleted = repos
+        .users
+        .delete(user_id, expected_version)
         .await
-        .map_err(|e| AppError::Internal(format!("Failed to delete user: {e}")))?;
+        .map_err(|e| match e {
+            RepositoryError::VersionMismatch => stale("User"),
+            e => AppError::Internal(format!("Failed to delete user: {e}")),
+        })?;
 
     if !deleted {
         return Err(AppError::NotFound(format!(
@@ -146,9 +236,187 @@ pub async fn delete_user(
         )));
     }
 
+    repos
+        .sessions
+        .revoke_all_for_user(user_id, None)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to revoke sessions: {e}")))?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeletedUserResponse {
+    pub id: Uuid,
+    pub username: String,
+    pub email: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub deleted_at: chrono::DateTime<chrono::Utc>,
+    /// When the purge job removes the user for good
+    pub purge_after: chrono::DateTime<chrono::Utc>,
+}
+
+pub async fn list_deleted_users(
+    repos: web::Data<Repositories>,
+    config: web::Data<Config>,
+) -> AppResult<impl Responder> {
+    let users = repos
+        .users
+        .list_deleted()
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to list deleted users: {e}")))?;
+
+    let retention = chrono::Duration::days(config.user_retention_days);
+    let response: Vec<DeletedUserResponse> = users
+        .into_iter()
+        .filter_map(|user| {
+            let deleted_at = user.deleted_at?;
+            Some(DeletedUserResponse {
+                id: user.id,
+                username: user.username,
+                email: user.email,
+                created_at: user.created_at,
+                deleted_at,
+                purge_after: deleted_at + retention,
+            })
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(ApiResponse::new(response)))
+}
+
+/// Brings back a soft-deleted user with their roles and group memberships.
+/// Their sessions stay revoked, so they have to sign in again.
+pub async fn restore_user(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+) -> AppResult<impl Responder> {
+    let user_id = path.into_inner();
+
+    let user = repos
+        .users
+        .restore(user_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to restore user: {e}")))?
+        .ok_or_else(|| AppError::NotFound(format!("No deleted user with id {user_id}")))?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(user.version))
+        .json(ApiResponse::new(UserResponse::from(user))))
+}
+
+// Linked identity endpoints
+
+/// The user's accounts at upstream identity providers
+pub async fn list_user_identities(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+) -> AppResult<impl Responder> {
+    let user_id = path.into_inner();
+
+    repos
+        .users
+        .find_by_id(user_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get user: {e}")))?
+        .ok_or_else(|| AppError::NotFound(format!("User with id {user_id} not found")))?;
+
+    let identities = repos
+        .identities
+        .list_for_user(user_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to list identities: {e}")))?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::new(identities)))
+}
+
+/// Unlinks an identity; signing in with it again links or provisions anew.
+/// Sessions it started stay valid until revoked.
+pub async fn unlink_user_identity(
+    repos: web::Data<Repositories>,
+    path: web::Path<(Uuid, Uuid)>,
+) -> AppResult<impl Responder> {
+    let (user_id, identity_id) = path.into_inner();
+
+    let deleted = repos
+        .identities
+        .delete(user_id, identity_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to unlink identity: {e}")))?;
+
+    if !deleted {
+        return Err(AppError::NotFound(format!(
+            "Identity with id {identity_id} not found"
+        )));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// User session endpoints
+
+pub async fn list_user_sessions(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+) -> AppResult<impl Responder> {
+    let user_id = path.into_inner();
+
+    repos
+        .users
+        .find_by_id(user_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get user: {e}")))?
+        .ok_or_else(|| AppError::NotFound(format!("User with id {user_id} not found")))?;
+
+    let sessions = repos
+        .sessions
+        .list_active_for_user(user_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to list sessions: {e}")))?;
+
+    let response: Vec<SessionResponse> = sessions
+        .into_iter()
+        .map(|s| SessionResponse::new(s, None))
+        .collect();
+    Ok(HttpResponse::Ok().json(ApiResponse::new(response)))
+}
+
+pub async fn revoke_user_session(
+    repos: web::Data<Repositories>,
+    path: web::Path<(Uuid, Uuid)>,
+) -> AppResult<impl Responder> {
+    let (user_id, session_id) = path.into_inner();
+
+    let revoked = repos
+        .sessions
+        .revoke(user_id, session_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to revoke session: {e}")))?;
+
+    if !revoked {
+        return Err(AppError::NotFound(format!(
+            "Session with id {session_id} not found"
+        )));
+    }
+
     Ok(HttpResponse::NoContent().finish())
 }
 
+pub async fn revoke_user_sessions(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+) -> AppResult<impl Responder> {
+    let user_id = path.into_inner();
+
+    let revoked = repos
+        .sessions
+        .revoke_all_for_user(user_id, None)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to revoke sessions: {e}")))?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::new(RevokedSessionsResponse { revoked })))
+}
+
 // Role management endpoints
 
 #[derive(Debug, Serialize)]
@@ -170,8 +438,10 @@ impl From<crate::models::Role> for RoleResponse {
     }
 }
 
-pub async fn list_roles(pool: web::Data<PgPool>) -> AppResult<impl Responder> {
-    let roles = crate::models::Role::list(&pool)
+pub async fn list_roles(repos: web::Data<Repositories>) -> AppResult<impl Responder> {
+    let roles = repos
+        .roles
+        .list()
         .await
         .map_err(|e| AppError::Internal(format!("Failed to list roles: {e}")))?;
 
@@ -186,25 +456,125 @@ pub struct CreateRoleRequest {
 }
 
 pub async fn create_role(
-    pool: web::Data<PgPool>,
+    repos: web::Data<Repositories>,
     req: web::Json<CreateRoleRequest>,
 ) -> AppResult<impl Responder> {
     if req.name.is_empty() {
         return Err(AppError::BadRequest("Role name is required".to_string()));
     }
 
-    let role = crate::models::Role::create(&pool, &req.name, req.description.as_deref())
+    let role = repos
+        .roles
+        .create(&req.name, req.description.as_deref())
         .await
         .map_err(|e| {
-            if e.to_string().contains("unique") {
+            if e.is_unique_violation_on("roles.name") {
                 AppError::Conflict(format!("Role '{}' already exists", req.name))
             } else {
                 AppError::Internal(format!("Failed to create role: {e}"))
             }
         })?;
 
-    let response: RoleResponse = role.into();
-    Ok(HttpResponse::Created().json(ApiResponse::new(response)))
+    Ok(HttpResponse::Created()
+        .insert_header(etag(role.version))
+        .json(ApiResponse::new(RoleResponse::from(role))))
+}
+
+pub async fn get_role(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+) -> AppResult<impl Responder> {
+    let role = find_role(&repos, path.into_inner()).await?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(role.version))
+        .json(ApiResponse::new(RoleResponse::from(role))))
+}
+
+pub async fn update_role(
+    repos: web::Data<Repositories>,
+    http_req: HttpRequest,
+    path: web::Path<Uuid>,
+    req: web::Json<CreateRoleRequest>,
+) -> AppResult<impl Responder> {
+    let role_id = path.into_inner();
+    let expected_version = required_version(&http_req, "Role")?;
+
+    if req.name.is_empty() {
+        return Err(AppError::BadRequest("Role name is required".to_string()));
+    }
+    let role = find_role(&repos, role_id).await?;
+    if role.name != req.name && BUILTIN_ROLES.contains(&role.name.as_str()) {
+        return Err(AppError::BadRequest(format!(
+            "Built-in role '{}' cannot be renamed",
+            role.name
+        )));
+    }
+
+    let role = repos
+        .roles
+        .update(
+            role_id,
+            &req.name,
+            req.description.as_deref(),
+            expected_version,
+        )
+        .await
+        .map_err(|e| match e {
+            RepositoryError::VersionMismatch => stale("Role"),
+            e if e.is_unique_violation_on("roles.name") => {
+                AppError::Conflict(format!("Role '{}' already exists", req.name))
+            }
+            e => AppError::Internal(format!("Failed to update role: {e}")),
+        })?
+        .ok_or_else(|| AppError::NotFound(format!("Role with id {role_id} not found")))?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(role.version))
+        .json(ApiResponse::new(RoleResponse::from(role))))
+}
+
+pub async fn delete_role(
+    repos: web::Data<Repositories>,
+    http_req: HttpRequest,
+    path: web::Path<Uuid>,
+) -> AppResult<impl Responder> {
+    let role_id = path.into_inner();
+    let expected_version = required_version(&http_req, "Role")?;
+
+    let role = find_role(&repos, role_id).await?;
+    if BUILTIN_ROLES.contains(&role.name.as_str()) {
+        return Err(AppError::BadRequest(format!(
+            "Built-in role '{}' cannot be deleted",
+            role.name
+        )));
+    }
+
+    let deleted = repos
+        .roles
+        .delete(role_id, expected_version)
+        .await
+        .map_err(|e| match e {
+            RepositoryError::VersionMismatch => stale("Role"),
+            e => AppError::Internal(format!("Failed to delete role: {e}")),
+        })?;
+
+    if !deleted {
+        return Err(AppError::NotFound(format!(
+            "Role with id {role_id} not found"
+        )));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn find_role(repos: &Repositories, role_id: Uuid) -> AppResult<crate::models::Role> {
+    repos
+        .roles
+        .find_by_id(role_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get role: {e}")))?
+        .ok_or_else(|| AppError::NotFound(format!("Role with id {role_id} not found")))
 }
 
 // Permission management endpoints
@@ -230,8 +600,10 @@ impl From<crate::models::Permission> for PermissionResponse {
     }
 }
 
-pub async fn list_permissions(pool: web::Data<PgPool>) -> AppResult<impl Responder> {
-    let permissions = crate::models::Permission::list(&pool)
+pub async fn list_permissions(repos: web::Data<Repositories>) -> AppResult<impl Responder> {
+    let permissions = repos
+        .permissions
+        .list()
         .await
         .map_err(|e| AppError::Internal(format!("Failed to list permissions: {e}")))?;
 
@@ -247,7 +619,7 @@ pub struct CreatePermissionRequest {
 }
 
 pub async fn create_permission(
-    pool: web::Data<PgPool>,
+    repos: web::Data<Repositories>,
     req: web::Json<CreatePermissionRequest>,
 ) -> AppResult<impl Responder> {
     if req.name.is_empty() || req.resource.is_empty() || req.action.is_empty() {
@@ -256,19 +628,108 @@ pub async fn create_permission(
         ));
     }
 
-    let permission =
-        crate::models::Permission::create(&pool, &req.name, &req.resource, &req.action)
-            .await
-            .map_err(|e| {
-                if e.to_string().contains("unique") {
-                    AppError::Conflict(format!("Permission '{}' already exists", req.name))
-                } else {
-                    AppError::Internal(format!("Failed to create permission: {e}"))
-                }
-            })?;
+    let permission = repos
+        .permissions
+        .create(&req.name, &req.resource, &req.action)
+        .await
+        .map_err(|e| {
+            if e.is_unique_violation_on("permissions.name") {
+                AppError::Conflict(format!("Permission '{}' already exists", req.name))
+            } else {
+                AppError::Internal(format!("Failed to create permission: {e}"))
+            }
+        })?;
 
-    let response: PermissionResponse = permission.into();
-    Ok(HttpResponse::Created().json(ApiResponse::new(response)))
+    Ok(HttpResponse::Created()
+        .insert_header(etag(permission.version))
+        .json(ApiResponse::new(PermissionResponse::from(permission))))
+}
+
+pub async fn get_permission(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+) -> AppResult<impl Responder> {
+    let permission_id = path.into_inner();
+
+    let permission = repos
+        .permissions
+        .find_by_id(permission_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get permission: {e}")))?
+        .ok_or_else(|| {
+            AppError::NotFound(format!("Permission with id {permission_id} not found"))
+        })?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(permission.version))
+        .json(ApiResponse::new(PermissionResponse::from(permission))))
+}
+
+pub async fn update_permission(
+    repos: web::Data<Repositories>,
+    http_req: HttpRequest,
+    path: web::Path<Uuid>,
+    req: web::Json<CreatePermissionRequest>,
+) -> AppResult<impl Responder> {
+    let permission_id = path.into_inner();
+    let expected_version = required_version(&http_req, "Permission")?;
+
+    if req.name.is_empty() || req.resource.is_empty() || req.action.is_empty() {
+        return Err(AppError::BadRequest(
+            "Permission name, resource, and action are required".to_string(),
+        ));
+    }
+
+    let permission = repos
+        .permissions
+        .update(
+            permission_id,
+            &req.name,
+            &req.resource,
+            &req.action,
+            expected_version,
+        )
+        .await
+        .map_err(|e| match e {
+            RepositoryError::VersionMismatch => stale("Permission"),
+            e if e.is_unique_violation_on("permissions.name") => {
+                AppError::Conflict(format!("Permission '{}' already exists", req.name))
+            }
+            e => AppError::Internal(format!("Failed to update permission: {e}")),
+        })?
+        .ok_or_else(|| {
+            AppError::NotFound(format!("Permission with id {permission_id} not found"))
+        })?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(permission.version))
+        .json(ApiResponse::new(PermissionResponse::from(permission))))
+}
+
+pub async fn delete_permission(
+    repos: web::Data<Repositories>,
+    http_req: HttpRequest,
+    path: web::Path<Uuid>,
+) -> AppResult<impl Responder> {
+    let permission_id = path.into_inner();
+    let expected_version = required_version(&http_req, "Permission")?;
+
+    let deleted = repos
+        .permissions
+        .delete(permission_id, expected_version)
+        .await
+        .map_err(|e| match e {
+            RepositoryError::VersionMismatch => stale("Permission"),
+            e => AppError::Internal(format!("Failed to delete permission: {e}")),
+        })?;
+
+    if !deleted {
+        return Err(AppError::NotFound(format!(
+            "Permission with id {permission_id} not found"
+        )));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
 }
 
 // User-Role assignment endpoints
@@ -279,34 +740,41 @@ pub struct AssignRoleRequest {
 }
 
 pub async fn assign_role_to_user(
-    pool: web::Data<PgPool>,
+    repos: web::Data<Repositories>,
     path: web::Path<Uuid>,
     req: web::Json<AssignRoleRequest>,
 ) -> AppResult<impl Responder> {
     let user_id = path.into_inner();
     let role_id = req.role_id;
 
-    // Verify user exists
-    User::find_by_id(&pool, user_id)
-        .await
-        .map_err(|e| AppError::Internal(format!("Database error: {e}")))?
-        .ok_or_else(|| AppError::NotFound(format!("User with id {user_id} not found")))?;
-
-    // Verify role exists
-    crate::models::Role::find_by_id(&pool, role_id)
+    // The foreign keys reject a missing user or role; work out which only
+    // when the insert fails
+    match repos
+        .assignments
+        .assign_role_to_user(user_id, role_id)
         .await
-        .map_err(|e| AppError::Internal(format!("Database error: {e}")))?
-        .ok_or_else(|| AppError::NotFound(format!("Role with id {role_id} not found")))?;
-
-    // Assign role
-    sqlx::query!(
-        "INSERT INTO user_roles (user_id, role_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
-        user_id,
-        role_id
-    )
-    .execute(&**pool)
-    .await
-    .map_err(|e| AppError::Internal(format!("Failed to assign role: {e}")))?;
+    {
+        Ok(()) => {}
+        Err(RepositoryError::ForeignKeyViolation(column)) => {
+            let user_missing = match column.as_str() {
+                "user_roles.user_id" => true,
+                "user_roles.role_id" => false,
+                // SQLite does not report which reference failed
+                _ => repos
+                    .users
+                    .find_by_id(user_id)
+                    .await
+                    .map_err(|e| AppError::Internal(format!("Database error: {e}")))?
+                    .is_none(),
+            };
+            return Err(if user_missing {
+                AppError::NotFound(format!("User with id {user_id} not found"))
+            } else {
+                AppError::NotFound(format!("Role with id {role_id} not found"))
+            });
+        }
+        Err(e) => return Err(AppError::Internal(format!("Failed to assign role: {e}"))),
+    }
 
     Ok(HttpResponse::Created().json(ApiResponse::with_message(
         (),
@@ -315,21 +783,18 @@ pub async fn assign_role_to_user(
 }
 
 pub async fn remove_role_from_user(
-    pool: web::Data<PgPool>,
+    repos: web::Data<Repositories>,
     path: web::Path<(Uuid, Uuid)>,
 ) -> AppResult<impl Responder> {
     let (user_id, role_id) = path.into_inner();
 
-    let result = sqlx::query!(
-        "DELETE FROM user_roles WHERE user_id = $1 AND role_id = $2",
-        user_id,
-        role_id
-    )
-    .execute(&**pool)
-    .await
-    .map_err(|e| AppError::Internal(format!("Failed to remove role: {e}")))?;
+    let removed = repos
+        .assignments
+        .remove_role_from_user(user_id, role_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to remove role: {e}")))?;
 
-    if result.rows_affected() == 0 {
+    if !removed {
         return Err(AppError::NotFound(
             "User-role assignment not found".to_string(),
         ));
@@ -346,34 +811,702 @@ pub struct AssignPermissionRequest {
 }
 
 pub async fn assign_permission_to_role(
-    pool: web::Data<PgPool>,
+    repos: web::Data<Repositories>,
     path: web::Path<Uuid>,
     req: web::Json<AssignPermissionRequest>,
 ) -> AppResult<impl Responder> {
     let role_id = path.into_inner();
     let permission_id = req.permission_id;
 
-    // Verify role exists
-    crate::models::Role::find_by_id(&pool, role_id)
+    // As for user roles, the foreign keys decide whether both sides exist
+    match repos
+        .assignments
+        .assign_permission_to_role(role_id, permission_id)
         .await
-        .map_err(|e| AppError::Internal(format!("Database error: {e}")))?
-        .ok_or_else(|| AppError::NotFound(format!("Role with id {role_id} not found")))?;
+    {
+        Ok(()) => {}
+        Err(RepositoryError::ForeignKeyViolation(column)) => {
+            let role_missing = match column.as_str() {
+                "role_permissions.role_id" => true,
+                "role_permissions.permission_id" => false,
+                // SQLite does not report which reference failed
+                _ => repos
+                    .roles
+                    .find_by_id(role_id)
+                    .await
+                    .map_err(|e| AppError::Internal(format!("Database error: {e}")))?
+                    .is_none(),
+            };
+            return Err(if role_missing {
+                AppError::NotFound(format!("Role with id {role_id} not found"))
+            } else {
+                AppError::NotFound(format!("Permission with id {permission_id} not found"))
+            });
+        }
+        Err(e) => {
+            return Err(AppError::Internal(format!(
+                "Failed to assign permission: {e}"
+            )))
+        }
+    }
 
-    // Verify permission exists
-    crate::models::Permission::find_by_id(&pool, permission_id)
+    Ok(HttpResponse::Created().json(ApiResponse::with_message(
+        (),
+        "Permission assigned to role successfully".to_string(),
+    )))
+}
+
+// Group management endpoints
+
+#[derive(Debug, Serialize)]
+pub struct GroupResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<Group> for GroupResponse {
+    fn from(group: Group) -> Self {
+        Self {
+            id: group.id,
+            name: group.name,
+            description: group.description,
+            created_at: group.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct GroupDetailResponse {
+    #[serde(flatten)]
+    pub group: GroupResponse,
+    pub roles: Vec<RoleResponse>,
+    pub members: Vec<UserResponse>,
+    pub subgroups: Vec<GroupResponse>,
+}
+
+async fn find_group(repos: &Repositories, group_id: Uuid) -> AppResult<Group> {
+    repos
+        .groups
+        .find_by_id(group_id)
         .await
         .map_err(|e| AppError::Internal(format!("Database error: {e}")))?
-        .ok_or_else(|| {
-            AppError::NotFound(format!("Permission with id {permission_id} not found"))
+        .ok_or_else(|| AppError::NotFound(format!("Group with id {group_id} not found")))
+}
+
+pub async fn list_groups(repos: web::Data<Repositories>) -> AppResult<impl Responder> {
+    let groups = repos
+        .groups
+        .list()
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to list groups: {e}")))?;
+
+    let response: Vec<GroupResponse> = groups.into_iter().map(|g| g.into()).collect();
+    Ok(HttpResponse::Ok().json(ApiResponse::new(response)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateGroupRequest {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+pub async fn create_group(
+    repos: web::Data<Repositories>,
+    req: web::Json<CreateGroupRequest>,
+) -> AppResult<impl Responder> {
+    if req.name.is_empty() {
+        return Err(AppError::BadRequest("Group name is required".to_string()));
+    }
+
+    let group = repos
+        .groups
+        .create(&req.name, req.description.as_deref())
+        .await
+        .map_err(|e| match e {
+            RepositoryError::UniqueViolation(_) => {
+                AppError::Conflict(format!("Group '{}' already exists", req.name))
+            }
+            e => AppError::Internal(format!("Failed to create group: {e}")),
         })?;
 
-    // Assign permission
-    crate::models::Role::assign_permission(&pool, role_id, permission_id)
+    let response: GroupResponse = group.into();
+    Ok(HttpResponse::Created().json(ApiResponse::new(response)))
+}
+
+pub async fn get_group(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+) -> AppResult<impl Responder> {
+    let group = find_group(&repos, path.into_inner()).await?;
+
+    let roles = repos
+        .groups
+        .get_roles(group.id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get group roles: {e}")))?;
+    let members = repos
+        .groups
+        .get_members(group.id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get group members: {e}")))?;
+    let subgroups = repos
+        .groups
+        .get_subgroups(group.id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get subgroups: {e}")))?;
+
+    let response = GroupDetailResponse {
+        group: group.into(),
+        roles: roles.into_iter().map(|r| r.into()).collect(),
+        members: members.into_iter().map(|u| u.into()).collect(),
+        subgroups: subgroups.into_iter().map(|g| g.into()).collect(),
+    };
+    Ok(HttpResponse::Ok().json(ApiResponse::new(response)))
+}
+
+pub async fn delete_group(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+) -> AppResult<impl Responder> {
+    let group_id = path.into_inner();
+
+    let deleted = repos
+        .groups
+        .delete(group_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to delete group: {e}")))?;
+
+    if !deleted {
+        return Err(AppError::NotFound(format!(
+            "Group with id {group_id} not found"
+        )));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Group membership endpoints
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GroupMembersRequest {
+    pub user_ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GroupMembersResponse {
+    pub group_id: Uuid,
+    pub affected: u64,
+}
+
+pub async fn add_group_members(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+    req: web::Json<GroupMembersRequest>,
+) -> AppResult<impl Responder> {
+    let group = find_group(&repos, path.into_inner()).await?;
+
+    if req.user_ids.is_empty() {
+        return Err(AppError::BadRequest(
+            "user_ids must not be empty".to_string(),
+        ));
+    }
+
+    // Reject the whole batch if any user does not exist
+    let found = repos
+        .users
+        .find_by_ids(&req.user_ids)
+        .await
+        .map_err(|e| AppError::Internal(format!("Database error: {e}")))?;
+    let missing: Vec<String> = req
+        .user_ids
+        .iter()
+        .filter(|id| !found.iter().any(|u| u.id == **id))
+        .map(|id| id.to_string())
+        .collect();
+    if !missing.is_empty() {
+        return Err(AppError::NotFound(format!(
+            "Users not found: {}",
+            missing.join(", ")
+        )));
+    }
+
+    let affected = repos
+        .groups
+        .add_members(group.id, &req.user_ids)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to add group members: {e}")))?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::with_message(
+        GroupMembersResponse {
+            group_id: group.id,
+            affected,
+        },
+        "Users added to group successfully".to_string(),
+    )))
+}
+
+pub async fn remove_group_members(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+    req: web::Json<GroupMembersRequest>,
+) -> AppResult<impl Responder> {
+    let group = find_group(&repos, path.into_inner()).await?;
+
+    if req.user_ids.is_empty() {
+        return Err(AppError::BadRequest(
+            "user_ids must not be empty".to_string(),
+        ));
+    }
+
+    let affected = repos
+        .groups
+        .remove_members(group.id, &req.user_ids)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to remove group members: {e}")))?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::with_message(
+        GroupMembersResponse {
+            group_id: group.id,
+            affected,
+        },
+        "Users removed from group successfully".to_string(),
+    )))
+}
+
+// Nested group endpoints
+
+#[derive(Debug, Deserialize)]
+pub struct AddSubgroupRequest {
+    pub group_id: Uuid,
+}
+
+pub async fn add_subgroup(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+    req: web::Json<AddSubgroupRequest>,
+) -> AppResult<impl Responder> {
+    let parent = find_group(&repos, path.into_inner()).await?;
+    let child = find_group(&repos, req.group_id).await?;
+
+    // Nesting the parent inside one of its own descendants would create a cycle
+    let added = repos
+        .groups
+        .add_subgroup(parent.id, child.id)
         .await
-        .map_err(|e| AppError::Internal(format!("Failed to assign permission: {e}")))?;
+        .map_err(|e| AppError::Internal(format!("Failed to add subgroup: {e}")))?;
+    if !added {
+        return Err(AppError::Conflict(format!(
+            "Group '{}' already contains group '{}'",
+            child.name, parent.name
+        )));
+    }
 
     Ok(HttpResponse::Created().json(ApiResponse::with_message(
         (),
-        "Permission assigned to role successfully".to_string(),
+        "Subgroup added to group successfully".to_string(),
+    )))
+}
+
+pub async fn remove_subgroup(
+    repos: web::Data<Repositories>,
+    path: web::Path<(Uuid, Uuid)>,
+) -> AppResult<impl Responder> {
+    let (group_id, child_group_id) = path.into_inner();
+
+    let removed = repos
+        .groups
+        .remove_subgroup(group_id, child_group_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to remove subgroup: {e}")))?;
+
+    if !removed {
+        return Err(AppError::NotFound(
+            "Subgroup assignment not found".to_string(),
+        ));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Group-Role assignment endpoints
+
+pub async fn assign_role_to_group(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+    req: web::Json<AssignRoleRequest>,
+) -> AppResult<impl Responder> {
+    let group = find_group(&repos, path.into_inner()).await?;
+    let role_id = req.role_id;
+
+    // Verify role exists
+    find_role(&repos, role_id).await?;
+
+    repos
+        .groups
+        .assign_role(group.id, role_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to assign role: {e}")))?;
+
+    Ok(HttpResponse::Created().json(ApiResponse::with_message(
+        (),
+        "Role assigned to group successfully".to_string(),
     )))
 }
+
+pub async fn remove_role_from_group(
+    repos: web::Data<Repositories>,
+    path: web::Path<(Uuid, Uuid)>,
+) -> AppResult<impl Responder> {
+    let (group_id, role_id) = path.into_inner();
+
+    let removed = repos
+        .groups
+        .remove_role(group_id, role_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to remove role: {e}")))?;
+
+    if !removed {
+        return Err(AppError::NotFound(
+            "Group-role assignment not found".to_string(),
+        ));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Invitation endpoints
+
+/// Invitations are valid for three days unless the admin asks otherwise
+pub const DEFAULT_INVITATION_TTL_HOURS: i64 = 72;
+
+#[derive(Debug, Serialize)]
+pub struct InvitationResponse {
+    pub id: Uuid,
+    pub email: String,
+    pub status: InvitationStatus,
+    pub roles: Vec<RoleResponse>,
+    pub invited_by: Option<Uuid>,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub accepted_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub accepted_user_id: Option<Uuid>,
+    pub revoked_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl InvitationResponse {
+    pub(crate) fn new(invitation: Invitation, roles: Vec<crate::models::Role>) -> Self {
+        Self {
+            id: invitation.id,
+            status: invitation.status(),
+            email: invitation.email,
+            roles: roles.into_iter().map(|r| r.into()).collect(),
+            invited_by: invitation.invited_by,
+            expires_at: invitation.expires_at,
+            created_at: invitation.created_at,
+            accepted_at: invitation.accepted_at,
+            accepted_user_id: invitation.accepted_user_id,
+            revoked_at: invitation.revoked_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedInvitationResponse {
+    #[serde(flatten)]
+    pub invitation: InvitationResponse,
+    /// The invitation token; it is not stored and cannot be retrieved again
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateInvitationRequest {
+    pub email: String,
+    #[serde(default)]
+    pub role_ids: Vec<Uuid>,
+    pub expires_in_hours: Option<i64>,
+}
+
+pub async fn create_invitation(
+    repos: web::Data<Repositories>,
+    claims: web::ReqData<Claims>,
+    req: web::Json<CreateInvitationRequest>,
+) -> AppResult<impl Responder> {
+    if req.email.is_empty() {
+        return Err(AppError::BadRequest("Email is required".to_string()));
+    }
+
+    let ttl_hours = req.expires_in_hours.unwrap_or(DEFAULT_INVITATION_TTL_HOURS);
+    if ttl_hours <= 0 {
+        return Err(AppError::BadRequest(
+            "expires_in_hours must be positive".to_string(),
+        ));
+    }
+
+    if repos
+        .users
+        .find_by_email(&req.email)
+        .await
+        .map_err(|e| AppError::Internal(format!("Database error: {e}")))?
+        .is_some()
+    {
+        return Err(AppError::Conflict("Email already exists".to_string()));
+    }
+
+    // Resolve the roles up front so the invitation records exactly what will be granted
+    let mut roles = Vec::new();
+    if req.role_ids.is_empty() {
+        let user_role = repos
+            .roles
+            .find_by_name("user")
+            .await
+            .map_err(|e| AppError::Internal(format!("Failed to find user role: {e}")))?
+            .ok_or_else(|| AppError::Internal("Default user role not found".to_string()))?;
+        roles.push(user_role);
+    } else {
+        for role_id in &req.role_ids {
+            roles.push(find_role(&repos, *role_id).await?);
+        }
+    }
+    let role_ids: Vec<Uuid> = roles.iter().map(|r| r.id).collect();
+
+    let token = generate_opaque_token();
+    let expires_at = chrono::Utc::now() + chrono::Duration::hours(ttl_hours);
+
+    let invitation = repos
+        .invitations
+        .create(
+            &req.email,
+            &hash_opaque_token(&token),
+            Some(claims.sub),
+            expires_at,
+            &role_ids,
+        )
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to create invitation: {e}")))?;
+
+    let response = CreatedInvitationResponse {
+        invitation: InvitationResponse::new(invitation, roles),
+        token,
+    };
+    Ok(HttpResponse::Created().json(ApiResponse::new(response)))
+}
+
+pub async fn list_invitations(repos: web::Data<Repositories>) -> AppResult<impl Responder> {
+    let invitations = repos
+        .invitations
+        .list()
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to list invitations: {e}")))?;
+
+    let mut response = Vec::with_capacity(invitations.len());
+    for invitation in invitations {
+        let roles = repos
+            .invitations
+            .get_roles(invitation.id)
+            .await
+            .map_err(|e| AppError::Internal(format!("Failed to get invitation roles: {e}")))?;
+        response.push(InvitationResponse::new(invitation, roles));
+    }
+
+    Ok(HttpResponse::Ok().json(ApiResponse::new(response)))
+}
+
+pub async fn revoke_invitation(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+) -> AppResult<impl Responder> {
+    let invitation_id = path.into_inner();
+
+    let revoked = repos
+        .invitations
+        .revoke(invitation_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to revoke invitation: {e}")))?;
+
+    if !revoked {
+        // Either there is no such invitation or it is no longer pending
+        let invitation = repos
+            .invitations
+            .find_by_id(invitation_id)
+            .await
+            .map_err(|e| AppError::Internal(format!("Database error: {e}")))?
+            .ok_or_else(|| {
+                AppError::NotFound(format!("Invitation with id {invitation_id} not found"))
+            })?;
+        return Err(AppError::Conflict(format!(
+            "Invitation has already been {}",
+            match invitation.status() {
+                InvitationStatus::Accepted => "accepted",
+                _ => "revoked",
+            }
+        )));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Impersonation endpoints
+
+/// Permission an admin needs, in addition to the admin role, to impersonate users
+pub const IMPERSONATE_PERMISSION: &str = "user:impersonate";
+
+/// Impersonation tokens are short-lived: 15 minutes by default, at most an hour
+pub const DEFAULT_IMPERSONATION_TTL_MINUTES: i64 = 15;
+pub const MAX_IMPERSONATION_TTL_MINUTES: i64 = 60;
+
+#[derive(Debug, Deserialize)]
+pub struct ImpersonateRequest {
+    /// Why the admin needs to act as the user, e.g. a support ticket reference
+    pub reason: String,
+    pub expires_in_minutes: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImpersonationResponse {
+    pub token: String,
+    pub user_id: Uuid,
+    pub username: String,
+    pub roles: Vec<String>,
+    pub permissions: Vec<String>,
+    pub actor: Actor,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    pub event_id: Uuid,
+}
+
+pub async fn impersonate_user(
+    repos: web::Data<Repositories>,
+    config: web::Data<Config>,
+    claims: web::ReqData<Claims>,
+    path: web::Path<Uuid>,
+    req: web::Json<ImpersonateRequest>,
+) -> AppResult<impl Responder> {
+    let user_id = path.into_inner();
+
+    if !claims
+        .permissions
+        .iter()
+        .any(|p| p == IMPERSONATE_PERMISSION)
+    {
+        return Err(AppError::Forbidden(format!(
+            "Permission '{IMPERSONATE_PERMISSION}' required"
+        )));
+    }
+
+    // AdminAuth already rejects impersonation tokens; checked again so that
+    // impersonation can never be chained even if this route moves
+    if claims.is_impersonated() {
+        return Err(AppError::Forbidden(
+            "Impersonation tokens cannot be used to impersonate".to_string(),
+        ));
+    }
+
+    // Impersonation is recorded against a human actor
+    if claims.is_service() {
+        return Err(AppError::Forbidden(
+            "Service accounts cannot impersonate users".to_string(),
+        ));
+    }
+
+    let reason = req.reason.trim();
+    if reason.is_empty() {
+        return Err(AppError::BadRequest("A reason is required".to_string()));
+    }
+
+    let ttl_minutes = req
+        .expires_in_minutes
+        .unwrap_or(DEFAULT_IMPERSONATION_TTL_MINUTES);
+    if !(1..=MAX_IMPERSONATION_TTL_MINUTES).contains(&ttl_minutes) {
+        return Err(AppError::BadRequest(format!(
+            "expires_in_minutes must be between 1 and {MAX_IMPERSONATION_TTL_MINUTES}"
+        )));
+    }
+
+    if user_id == claims.sub {
+        return Err(AppError::BadRequest(
+            "Cannot impersonate yourself".to_string(),
+        ));
+    }
+
+    let user = repos
+        .users
+        .find_by_id(user_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get user: {e}")))?
+        .ok_or_else(|| AppError::NotFound(format!("User with id {user_id} not found")))?;
+
+    if !user.is_active {
+        return Err(AppError::Forbidden("User account is inactive".to_string()));
+    }
+
+    let (roles, permissions) = load_roles_and_permissions(&repos, user.id).await?;
+
+    let actor = Actor {
+        sub: claims.sub,
+        username: claims.username.clone(),
+        principal_type: PrincipalType::User,
+        act: None,
+    };
+    let mut token_claims = create_impersonation_claims(
+        user.id,
+        user.username.clone(),
+        roles.clone(),
+        permissions.clone(),
+        actor.clone(),
+        chrono::Duration::minutes(ttl_minutes),
+    );
+    apply_token_format(&repos, &config, &mut token_claims).await?;
+    let expires_at = chrono::DateTime::from_timestamp(token_claims.exp, 0)
+        .ok_or_else(|| AppError::Internal("Invalid token expiry".to_string()))?;
+
+    // Record the event before handing out the token so nothing is issued unaudited
+    let event = repos
+        .impersonations
+        .create(actor.sub, &actor.username, &user, reason, expires_at)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to record impersonation: {e}")))?;
+
+    let token = generate_token(&token_claims, &config.jwt_secret)
+        .map_err(|e| AppError::Internal(format!("Failed to generate token: {e}")))?;
+
+    log::info!(
+        "{} ({}) started impersonating {} ({}) until {}: {}",
+        actor.username,
+        actor.sub,
+        user.username,
+        user.id,
+        expires_at,
+        reason
+    );
+
+    let response = ImpersonationResponse {
+        token,
+        user_id: user.id,
+        username: user.username,
+        roles,
+        permissions,
+        actor,
+        expires_at,
+        event_id: event.id,
+    };
+    Ok(HttpResponse::Created().json(ApiResponse::new(response)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImpersonationListQuery {
+    pub user_id: Option<Uuid>,
+}
+
+pub async fn list_impersonations(
+    repos: web::Data<Repositories>,
+    query: web::Query<ImpersonationListQuery>,
+) -> AppResult<impl Responder> {
+    let events = repos
+        .impersonations
+        .list(query.user_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to list impersonations: {e}")))?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::new(events)))
+}
@@ -1,10 +1,16 @@
-use crate::models::permission::Role;
-use crate::models::User;
-use crate::services::{create_claims, generate_token, hash_password, verify_password};
-use actix_web::{web, HttpResponse, Responder};
+use crate::config::{Config, RegistrationMode, TokenFormat};
+use crate::handlers::conditional::stale;
+use crate::models::{InvitationStatus, Role, Session, User};
+use crate::repositories::{Repositories, RepositoryError};
+use crate::services::auth_backend::{auth_backends, AuthBackendError};
+use crate::services::{
+    bootstrap, create_claims, generate_token, hash_opaque_token, hash_password, is_token_active,
+    validate_token_with_previous, LoginThrottle,
+};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use log::warn;
 use serde::{Deserialize, Serialize};
-use shared::{ApiResponse, AppError, AppResult};
-use sqlx::PgPool;
+use shared::{ApiResponse, AppError, AppResult, Claims};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RegisterRequest {
@@ -26,12 +32,26 @@ pub struct LoginResponse {
     pub user_id: uuid::Uuid,
     pub username: String,
     pub roles: Vec<String>,
+    pub session_id: uuid::Uuid,
 }
 
 pub async fn register(
-    pool: web::Data<PgPool>,
+    repos: web::Data<Repositories>,
+    config: web::Data<Config>,
     req: web::Json<RegisterRequest>,
 ) -> AppResult<impl Responder> {
+    match config.registration_mode {
+        RegistrationMode::Open => {}
+        RegistrationMode::InviteOnly => {
+            return Err(AppError::Forbidden(
+                "Registration requires an invitation".to_string(),
+            ))
+        }
+        RegistrationMode::Closed => {
+            return Err(AppError::Forbidden("Registration is closed".to_string()))
+        }
+    }
+
     // Validate input
     if req.username.is_empty() || req.email.is_empty() || req.password.is_empty() {
         return Err(AppError::BadRequest(
@@ -45,46 +65,23 @@ pub async fn register(
         ));
     }
 
-    // Check if username or email already exists
-    if User::find_by_username(&pool, &req.username)
-        .await
-        .map_err(|e| AppError::Internal(format!("Database error: {e}")))?
-        .is_some()
-    {
-        return Err(AppError::Conflict("Username already exists".to_string()));
-    }
-
-    if User::find_by_email(&pool, &req.email)
-        .await
-        .map_err(|e| AppError::Internal(format!("Database error: {e}")))?
-        .is_some()
-    {
-        return Err(AppError::Conflict("Email already exists".to_string()));
-    }
-
     // Hash password
     let password_hash = hash_password(&req.password)
         .map_err(|e| AppError::Internal(format!("Failed to hash password: {e}")))?;
 
-    // Create user
-    let user = User::create(&pool, &req.username, &req.email, &password_hash)
-        .await
-        .map_err(|e| AppError::Internal(format!("Failed to create user: {e}")))?;
-
-    // Assign default "user" role
-    let user_role = Role::find_by_name(&pool, "user")
+    let user_role = repos
+        .roles
+        .find_by_name("user")
         .await
         .map_err(|e| AppError::Internal(format!("Failed to find user role: {e}")))?
         .ok_or_else(|| AppError::Internal("Default user role not found".to_string()))?;
 
-    sqlx::query!(
-        "INSERT INTO user_roles (user_id, role_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
-        user.id,
-        user_role.id
-    )
-    .execute(&**pool)
-    .await
-    .map_err(|e| AppError::Internal(format!("Failed to assign role: {e}")))?;
+    // Create the user with the default "user" role in one transaction
+    let user = repos
+        .users
+        .create_with_roles(&req.username, &req.email, &password_hash, &[user_role.id])
+        .await
+        .map_err(|e| user_write_error(e, "create"))?;
 
     let response = RegisterResponse {
         user_id: user.id,
@@ -96,44 +93,100 @@ pub async fn register(
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct LoginRequest {
+pub struct SetupRequest {
+    /// The one-time token printed at startup
+    pub setup_token: String,
     pub username: String,
+    pub email: String,
     pub password: String,
 }
 
-pub async fn login(
-    pool: web::Data<PgPool>,
-    config: web::Data<crate::config::Config>,
-    req: web::Json<LoginRequest>,
+/// Creates the first admin in exchange for the setup token printed at startup
+pub async fn complete_setup(
+    repos: web::Data<Repositories>,
+    req: web::Json<SetupRequest>,
 ) -> AppResult<impl Responder> {
-    // Find user by username
-    let user = User::find_by_username(&pool, &req.username)
+    if bootstrap::admin_exists(&repos).await? {
+        return Err(AppError::Conflict(
+            "Setup has already been completed".to_string(),
+        ));
+    }
+
+    // Reject bad input before spending the token, which only a restart can replace
+    bootstrap::validate_new_admin(&repos, &req.username, &req.email, &req.password).await?;
+
+    if !repos
+        .setup_tokens
+        .consume(&hash_opaque_token(&req.setup_token))
         .await
         .map_err(|e| AppError::Internal(format!("Database error: {e}")))?
-        .ok_or_else(|| AppError::Unauthorized("Invalid username or password".to_string()))?;
-
-    // Check if user is active
-    if !user.is_active {
-        return Err(AppError::Forbidden("User account is inactive".to_string()));
+    {
+        return Err(AppError::Unauthorized(
+            "Invalid or expired setup token".to_string(),
+        ));
     }
 
-    // Verify password
-    verify_password(&req.password, &user.password_hash)
-        .map_err(|e| AppError::Internal(format!("Password verification error: {e}")))?
-        .then_some(())
-        .ok_or_else(|| AppError::Unauthorized("Invalid username or password".to_string()))?;
+    let user = bootstrap::create_admin(&repos, &req.username, &req.email, &req.password).await?;
+
+    let response = RegisterResponse {
+        user_id: user.id,
+        username: user.username,
+        email: user.email,
+    };
+
+    Ok(HttpResponse::Created().json(ApiResponse::new(response)))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LoginRequest {
+    /// The username or the email address, both compared ignoring case
+    pub username: String,
+    pub password: String,
+}
 
-    // Get user roles
-    let roles = Role::get_user_roles(&pool, user.id)
+/// Maps a failed user insert or update to a response. Duplicates are caught
+/// by the unique constraints rather than looked up beforehand, which would
+/// race with concurrent writes.
+pub(crate) fn user_write_error(e: RepositoryError, action: &str) -> AppError {
+    if matches!(e, RepositoryError::VersionMismatch) {
+        stale("User")
+    } else if e.is_unique_violation_on("users.username") {
+        AppError::Conflict("Username already exists".to_string())
+    } else if e.is_unique_violation_on("users.email") {
+        AppError::Conflict("Email already exists".to_string())
+    } else {
+        AppError::Internal(format!("Failed to {action} user: {e}"))
+    }
+}
+
+/// Loads the role names (direct and inherited through groups) and the
+/// deduplicated permission names a token for `user_id` should carry
+pub(crate) async fn load_roles_and_permissions(
+    repos: &Repositories,
+    user_id: uuid::Uuid,
+) -> AppResult<(Vec<String>, Vec<String>)> {
+    let roles = repos
+        .assignments
+        .get_effective_user_roles(user_id)
         .await
         .map_err(|e| AppError::Internal(format!("Failed to get user roles: {e}")))?;
 
     let role_names: Vec<String> = roles.iter().map(|r| r.name.clone()).collect();
+    let permissions = load_permissions(repos, &roles).await?;
 
-    // Get all permissions for user (from all their roles)
+    Ok((role_names, permissions))
+}
+
+/// The deduplicated names of the permissions `roles` grant
+pub(crate) async fn load_permissions(
+    repos: &Repositories,
+    roles: &[Role],
+) -> AppResult<Vec<String>> {
     let mut permissions = Vec::new();
-    for role in &roles {
-        let role_permissions = Role::get_permissions(&pool, role.id)
+    for role in roles {
+        let role_permissions = repos
+            .assignments
+            .get_role_permissions(role.id)
             .await
             .map_err(|e| AppError::Internal(format!("Failed to get role permissions: {e}")))?;
         for perm in role_permissions {
@@ -143,22 +196,414 @@ pub async fn login(
         }
     }
 
-    // Generate JWT token
-    let claims = create_claims(
+    Ok(permissions)
+}
+
+/// Strips the permissions from `claims` when tokens are issued compact,
+/// recording instead the permissions version they would have been read at
+pub(crate) async fn apply_token_format(
+    repos: &Repositories,
+    config: &Config,
+    claims: &mut Claims,
+) -> AppResult<()> {
+    if config.token_format == TokenFormat::Compact {
+        let version =
+            repos.assignments.permissions_version().await.map_err(|e| {
+                AppError::Internal(format!("Failed to get permissions version: {e}"))
+            })?;
+        claims.permissions.clear();
+        claims.pv = Some(version);
+    }
+    Ok(())
+}
+
+/// A signed-in user's new session and the token bound to it
+pub(crate) struct StartedSession {
+    pub token: String,
+    pub session_id: uuid::Uuid,
+    pub roles: Vec<String>,
+    /// Seconds until the token expires
+    pub expires_in: i64,
+}
+
+/// Opens a session for `user` on the client making `http_req` and signs a
+/// token bound to it
+pub(crate) async fn start_session(
+    repos: &Repositories,
+    config: &Config,
+    http_req: &HttpRequest,
+    user: &User,
+) -> AppResult<StartedSession> {
+    let (role_names, permissions) = load_roles_and_permissions(repos, user.id).await?;
+
+    // Generate JWT token bound to a new session
+    let mut claims = create_claims(
         user.id,
         user.username.clone(),
         role_names.clone(),
-        permissions.clone(),
+        permissions,
     );
+    let expires_at = chrono::DateTime::from_timestamp(claims.exp, 0)
+        .ok_or_else(|| AppError::Internal("Invalid token expiry".to_string()))?;
+    let user_agent = http_req
+        .headers()
+        .get("User-Agent")
+        .and_then(|h| h.to_str().ok());
+    let ip_address = http_req
+        .connection_info()
+        .realip_remote_addr()
+        .map(str::to_string);
+    let session = repos
+        .sessions
+        .create(user.id, user_agent, ip_address.as_deref(), expires_at)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to create session: {e}")))?;
+    claims.sid = Some(session.id);
+    apply_token_format(repos, config, &mut claims).await?;
     let token = generate_token(&claims, &config.jwt_secret)
         .map_err(|e| AppError::Internal(format!("Failed to generate token: {e}")))?;
 
-    let response = LoginResponse {
+    Ok(StartedSession {
         token,
+        session_id: session.id,
+        roles: role_names,
+        expires_in: claims.exp - claims.iat,
+    })
+}
+
+pub async fn login(
+    repos: web::Data<Repositories>,
+    config: web::Data<Config>,
+    throttle: web::Data<LoginThrottle>,
+    http_req: HttpRequest,
+    req: web::Json<LoginRequest>,
+) -> AppResult<impl Responder> {
+    if let Some(retry_after) = throttle.locked_for(&req.username) {
+        return Err(AppError::TooManyRequests(
+            "Too many failed logins; try again later".to_string(),
+            retry_after,
+        ));
+    }
+
+    // Each configured backend in turn; the first to accept the password wins
+    let mut unavailable = None;
+    let mut authenticated = None;
+    for backend in auth_backends(&config) {
+        match backend
+            .authenticate(&repos, &req.username, &req.password)
+            .await
+        {
+            Ok(Some(user)) => {
+                authenticated = Some(user);
+                break;
+            }
+            Ok(None) => {}
+            Err(AuthBackendError::Unavailable(e)) => {
+                warn!("Login backend unavailable: {e}");
+                unavailable = Some(e);
+            }
+            Err(AuthBackendError::Failed(e)) => return Err(e),
+        }
+    }
+    let user = match (authenticated, unavailable) {
+        (Some(user), _) => user,
+        // The password might have been right
+        (None, Some(_)) => {
+            return Err(AppError::ServiceUnavailable(
+                "Could not check the password; try again later".to_string(),
+            ))
+        }
+        (None, None) => {
+            throttle.record_failure(&req.username);
+            return Err(AppError::Unauthorized(
+                "Invalid username or password".to_string(),
+            ));
+        }
+    };
+    throttle.clear(&req.username);
+
+    // Check if user is active
+    if !user.is_active {
+        return Err(AppError::Forbidden("User account is inactive".to_string()));
+    }
+
+    let session = start_session(&repos, &config, &http_req, &user).await?;
+
+    let response = LoginResponse {
+        token: session.token,
         user_id: user.id,
         username: user.username,
-        roles: role_names,
+        roles: session.roles,
+        session_id: session.session_id,
     };
 
     Ok(HttpResponse::Ok().json(ApiResponse::new(response)))
 }
+
+#[derive(Debug, Deserialize)]
+pub struct IntrospectionRequest {
+    pub token: String,
+}
+
+/// Token introspection response (RFC 7662); everything but `active` is
+/// omitted for tokens that are not active
+#[derive(Debug, Serialize)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<uuid::Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<i64>,
+    /// The service an exchanged token is restricted to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    /// The current permissions version; services holding role permissions
+    /// older than this should fetch them again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permissions_version: Option<i64>,
+}
+
+/// `POST /auth/introspect`: lets the other services check that a token has
+/// not been revoked since it was signed. Unauthenticated on purpose: the
+/// caller must already hold the token, and its claims are readable anyway.
+pub async fn introspect(
+    repos: web::Data<Repositories>,
+    config: web::Data<Config>,
+    req: web::Form<IntrospectionRequest>,
+) -> AppResult<impl Responder> {
+    let claims = validate_token_with_previous(
+        &req.token,
+        &config.jwt_secret,
+        config.jwt_previous_secret.as_ref(),
+    )
+    .ok();
+    let active = match &claims {
+        Some(claims) => is_token_active(&repos, claims)
+            .await
+            .map_err(|e| AppError::Internal(format!("Database error: {e}")))?,
+        None => false,
+    };
+
+    let response = match claims.filter(|_| active) {
+        Some(claims) => IntrospectionResponse {
+            active: true,
+            sub: Some(claims.sub),
+            username: Some(claims.username),
+            exp: Some(claims.exp),
+            iat: Some(claims.iat),
+            aud: claims.aud,
+            permissions_version: Some(repos.assignments.permissions_version().await.map_err(
+                |e| AppError::Internal(format!("Failed to get permissions version: {e}")),
+            )?),
+        },
+        None => IntrospectionResponse {
+            active: false,
+            sub: None,
+            username: None,
+            exp: None,
+            iat: None,
+            aud: None,
+            permissions_version: None,
+        },
+    };
+
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-store"))
+        .json(response))
+}
+
+/// `GET /auth/role-permissions`: what each role grants, for services
+/// resolving the permissions of compact tokens. Only service accounts may
+/// read it; users have no need to see every role's grants.
+pub async fn role_permissions(
+    repos: web::Data<Repositories>,
+    claims: web::ReqData<Claims>,
+) -> AppResult<impl Responder> {
+    if !claims.is_service() {
+        return Err(AppError::Forbidden(
+            "Only service accounts may read the role permissions".to_string(),
+        ));
+    }
+
+    let role_permissions = repos
+        .assignments
+        .role_permissions()
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get role permissions: {e}")))?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-store"))
+        .json(ApiResponse::new(role_permissions)))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AcceptInvitationRequest {
+    pub token: String,
+    pub username: String,
+    pub password: String,
+}
+
+pub async fn accept_invitation(
+    repos: web::Data<Repositories>,
+    config: web::Data<Config>,
+    req: web::Json<AcceptInvitationRequest>,
+) -> AppResult<impl Responder> {
+    if config.registration_mode == RegistrationMode::Closed {
+        return Err(AppError::Forbidden("Registration is closed".to_string()));
+    }
+
+    // Validate input
+    if req.token.is_empty() || req.username.is_empty() || req.password.is_empty() {
+        return Err(AppError::BadRequest(
+            "Token, username, and password are required".to_string(),
+        ));
+    }
+
+    if req.password.len() < 8 {
+        return Err(AppError::BadRequest(
+            "Password must be at least 8 characters long".to_string(),
+        ));
+    }
+
+    let invitation = repos
+        .invitations
+        .find_by_token_hash(&hash_opaque_token(&req.token))
+        .await
+        .map_err(|e| AppError::Internal(format!("Database error: {e}")))?
+        .ok_or_else(|| AppError::NotFound("Invitation not found".to_string()))?;
+
+    match invitation.status() {
+        InvitationStatus::Pending => {}
+        InvitationStatus::Accepted => {
+            return Err(AppError::Conflict(
+                "Invitation has already been accepted".to_string(),
+            ))
+        }
+        InvitationStatus::Revoked | InvitationStatus::Expired => {
+            return Err(AppError::BadRequest(
+                "Invitation is no longer valid".to_string(),
+            ))
+        }
+    }
+
+    // Hash password
+    let password_hash = hash_password(&req.password)
+        .map_err(|e| AppError::Internal(format!("Failed to hash password: {e}")))?;
+
+    // Creates the user with the invited email address and grants the roles
+    // chosen by the inviting admin; if someone else claimed the invitation
+    // first, nothing is created
+    let user = repos
+        .invitations
+        .accept(invitation.id, &req.username, &password_hash)
+        .await
+        .map_err(|e| user_write_error(e, "create"))?
+        .ok_or_else(|| AppError::Conflict("Invitation has already been accepted".to_string()))?;
+
+    let response = RegisterResponse {
+        user_id: user.id,
+        username: user.username,
+        email: user.email,
+    };
+
+    Ok(HttpResponse::Created().json(ApiResponse::new(response)))
+}
+
+// Session endpoints for the authenticated user
+
+#[derive(Debug, Serialize)]
+pub struct SessionResponse {
+    pub id: uuid::Uuid,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub last_used_at: chrono::DateTime<chrono::Utc>,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    /// True for the session the request was made with
+    pub current: bool,
+}
+
+impl SessionResponse {
+    pub fn new(session: Session, current_sid: Option<uuid::Uuid>) -> Self {
+        Self {
+            current: current_sid == Some(session.id),
+            id: session.id,
+            user_agent: session.user_agent,
+            ip_address: session.ip_address,
+            created_at: session.created_at,
+            last_used_at: session.last_used_at,
+            expires_at: session.expires_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RevokedSessionsResponse {
+    pub revoked: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevokeSessionsQuery {
+    /// Keep the session the request was made with
+    #[serde(default)]
+    pub keep_current: bool,
+}
+
+pub async fn list_my_sessions(
+    repos: web::Data<Repositories>,
+    claims: web::ReqData<Claims>,
+) -> AppResult<impl Responder> {
+    let sessions = repos
+        .sessions
+        .list_active_for_user(claims.sub)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to list sessions: {e}")))?;
+
+    let response: Vec<SessionResponse> = sessions
+        .into_iter()
+        .map(|s| SessionResponse::new(s, claims.sid))
+        .collect();
+    Ok(HttpResponse::Ok().json(ApiResponse::new(response)))
+}
+
+pub async fn revoke_my_session(
+    repos: web::Data<Repositories>,
+    claims: web::ReqData<Claims>,
+    path: web::Path<uuid::Uuid>,
+) -> AppResult<impl Responder> {
+    let session_id = path.into_inner();
+
+    let revoked = repos
+        .sessions
+        .revoke(claims.sub, session_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to revoke session: {e}")))?;
+
+    if !revoked {
+        return Err(AppError::NotFound(format!(
+            "Session with id {session_id} not found"
+        )));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub async fn revoke_my_sessions(
+    repos: web::Data<Repositories>,
+    claims: web::ReqData<Claims>,
+    query: web::Query<RevokeSessionsQuery>,
+) -> AppResult<impl Responder> {
+    let except = if query.keep_current { claims.sid } else { None };
+
+    let revoked = repos
+        .sessions
+        .revoke_all_for_user(claims.sub, except)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to revoke sessions: {e}")))?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::new(RevokedSessionsResponse { revoked })))
+}
@@ -0,0 +1,678 @@
+use crate::handlers::admin::DEFAULT_INVITATION_TTL_HOURS;
+use crate::models::user::UserWithRoles;
+use crate::models::{fold_username, normalize_email, normalize_username};
+use crate::repositories::{NewAccount, ProvisioningRepository, Repositories, RepositoryError};
+use crate::services::{generate_opaque_token, hash_opaque_token, is_supported_password_hash};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use shared::{ApiResponse, AppError, AppResult, Claims, FieldError};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Upper bound on rows accepted by a single import request
+const MAX_IMPORT_ROWS: usize = 10_000;
+
+/// Request body limit for the import route; the default 256 KiB is too small
+/// for files approaching `MAX_IMPORT_ROWS`
+pub const IMPORT_PAYLOAD_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Number of users fetched per database round trip while exporting
+const EXPORT_PAGE_SIZE: i64 = 500;
+
+// User import
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// All rows are applied in one transaction; any failure aborts the import
+    #[default]
+    Transactional,
+    /// Each valid row is applied on its own; failing rows are reported and skipped
+    BestEffort,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default)]
+    pub mode: ImportMode,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportRowStatus {
+    /// Dry run only: the row would be imported
+    Valid,
+    Created,
+    Invited,
+    Failed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportRowResult {
+    pub row: usize,
+    pub username: Option<String>,
+    pub email: Option<String>,
+    pub status: ImportRowStatus,
+    pub user_id: Option<Uuid>,
+    /// Only present for invited rows; it is not stored and cannot be retrieved again
+    pub invitation_token: Option<String>,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub mode: ImportMode,
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub rows: Vec<ImportRowResult>,
+}
+
+/// One row of an import file after parsing, before validation
+#[derive(Debug)]
+struct ImportRecord {
+    username: Option<String>,
+    email: String,
+    password_hash: Option<String>,
+    roles: Vec<String>,
+    is_active: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct CsvRecord {
+    #[serde(default)]
+    username: Option<String>,
+    email: String,
+    #[serde(default)]
+    password_hash: Option<String>,
+    /// Role names separated by `;`
+    #[serde(default)]
+    roles: Option<String>,
+    #[serde(default)]
+    is_active: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonRecord {
+    #[serde(default)]
+    username: Option<String>,
+    email: String,
+    #[serde(default)]
+    password_hash: Option<String>,
+    #[serde(default)]
+    roles: Vec<String>,
+    #[serde(default)]
+    is_active: Option<bool>,
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+fn parse_csv(body: &[u8]) -> Vec<Result<ImportRecord, String>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(body);
+
+    reader
+        .deserialize::<CsvRecord>()
+        .map(|record| {
+            record
+                .map(|r| ImportRecord {
+                    username: non_empty(r.username),
+                    email: r.email,
+                    password_hash: non_empty(r.password_hash),
+                    roles: r
+                        .roles
+                        .unwrap_or_default()
+                        .split(';')
+                        .map(|s| s.trim().to_string())
+                        .filter(|s| !s.is_empty())
+                        .collect(),
+                    is_active: r.is_active.unwrap_or(true),
+                })
+                .map_err(|e| format!("Invalid CSV row: {e}"))
+        })
+        .collect()
+}
+
+fn parse_ndjson(body: &[u8]) -> Vec<Result<ImportRecord, String>> {
+    String::from_utf8_lossy(body)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str::<JsonRecord>(line)
+                .map(|r| ImportRecord {
+                    username: non_empty(r.username),
+                    email: r.email.trim().to_string(),
+                    password_hash: non_empty(r.password_hash),
+                    roles: r.roles,
+                    is_active: r.is_active.unwrap_or(true),
+                })
+                .map_err(|e| format!("Invalid JSON row: {e}"))
+        })
+        .collect()
+}
+
+/// What a validated row becomes: a user when a password hash is supplied,
+/// otherwise an invitation for the email address
+enum RowAction {
+    CreateUser {
+        username: String,
+        password_hash: String,
+    },
+    Invite {
+        token: String,
+        token_hash: String,
+    },
+}
+
+/// A row that passed validation, with role names resolved to ids
+struct ValidRow {
+    record: ImportRecord,
+    role_ids: Vec<Uuid>,
+    action: RowAction,
+}
+
+impl ValidRow {
+    fn account(&self, invited_by: Uuid, expires_at: DateTime<Utc>) -> NewAccount<'_> {
+        match &self.action {
+            RowAction::CreateUser {
+                username,
+                password_hash,
+            } => NewAccount::User {
+                username,
+                email: &self.record.email,
+                password_hash,
+                is_active: self.record.is_active,
+                role_ids: &self.role_ids,
+            },
+            RowAction::Invite { token_hash, .. } => NewAccount::Invitation {
+                email: &self.record.email,
+                token_hash,
+                invited_by: Some(invited_by),
+                expires_at,
+                role_ids: &self.role_ids,
+            },
+        }
+    }
+
+    /// The outcome once the row's account was written under `id`
+    fn outcome(&self, id: Uuid) -> RowOutcome {
+        match &self.action {
+            RowAction::CreateUser { .. } => RowOutcome::Created(id),
+            RowAction::Invite { token, .. } => RowOutcome::Invited(token.clone()),
+        }
+    }
+}
+
+enum RowOutcome {
+    Created(Uuid),
+    Invited(String),
+}
+
+fn record_outcome(result: &mut ImportRowResult, outcome: RowOutcome) {
+    match outcome {
+        RowOutcome::Created(user_id) => {
+            result.status = ImportRowStatus::Created;
+            result.user_id = Some(user_id);
+        }
+        RowOutcome::Invited(token) => {
+            result.status = ImportRowStatus::Invited;
+            result.invitation_token = Some(token);
+        }
+    }
+}
+
+pub async fn import_users(
+    repos: web::Data<Repositories>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
+    query: web::Query<ImportQuery>,
+    body: web::Bytes,
+) -> AppResult<impl Responder> {
+    let content_type = http_req
+        .headers()
+        .get("Content-Type")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default()
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    let parsed = match content_type.as_str() {
+        "text/csv" => parse_csv(&body),
+        "application/x-ndjson" | "application/jsonl" | "application/ndjson" => parse_ndjson(&body),
+        _ => {
+            return Err(AppError::BadRequest(
+                "Content-Type must be text/csv or application/x-ndjson".to_string(),
+            ))
+        }
+    };
+
+    if parsed.is_empty() {
+        return Err(AppError::BadRequest(
+            "Import file contains no rows".to_string(),
+        ));
+    }
+    if parsed.len() > MAX_IMPORT_ROWS {
+        return Err(AppError::BadRequest(format!(
+            "Import is limited to {MAX_IMPORT_ROWS} rows per request"
+        )));
+    }
+
+    // Look up everything validation needs in two queries rather than per row
+    let roles: HashMap<String, Uuid> = repos
+        .roles
+        .list()
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to list roles: {e}")))?
+        .into_iter()
+        .map(|r| (r.name, r.id))
+        .collect();
+    let default_role_id = *roles
+        .get("user")
+        .ok_or_else(|| AppError::Internal("Default user role not found".to_string()))?;
+
+    let usernames: Vec<String> = parsed
+        .iter()
+        .filter_map(|r| r.as_ref().ok().and_then(|r| r.username.clone()))
+        .collect();
+    let emails: Vec<String> = parsed
+        .iter()
+        .filter_map(|r| r.as_ref().ok().map(|r| r.email.clone()))
+        .collect();
+    let existing = repos
+        .provisioning
+        .find_by_usernames_or_emails(&usernames, &emails)
+        .await
+        .map_err(|e| AppError::Internal(format!("Database error: {e}")))?;
+    let existing_usernames: HashSet<String> = existing
+        .iter()
+        .map(|u| fold_username(&u.username))
+        .collect();
+    let existing_emails: HashSet<String> = existing.into_iter().map(|u| u.email).collect();
+
+    let mut seen_usernames = HashSet::new();
+    let mut seen_emails = HashSet::new();
+    let mut results = Vec::with_capacity(parsed.len());
+    let mut valid_rows = Vec::new();
+
+    for (index, parsed_row) in parsed.into_iter().enumerate() {
+        let mut result = ImportRowResult {
+            row: index + 1,
+            username: None,
+            email: None,
+            status: ImportRowStatus::Valid,
+            user_id: None,
+            invitation_token: None,
+            errors: Vec::new(),
+        };
+
+        let mut record = match parsed_row {
+            Ok(record) => record,
+            Err(e) => {
+                result.status = ImportRowStatus::Failed;
+                result.errors.push(e);
+                results.push(result);
+                continue;
+            }
+        };
+        // Compared and stored the way the user repositories would
+        record.username = record.username.as_deref().map(normalize_username);
+        record.email = normalize_email(&record.email);
+        result.username = record.username.clone();
+        result.email = Some(record.email.clone());
+
+        if record.email.is_empty() || !record.email.contains('@') {
+            result.errors.push("A valid email is required".to_string());
+        } else if existing_emails.contains(&record.email) {
+            result.errors.push("Email already exists".to_string());
+        } else if !seen_emails.insert(record.email.clone()) {
+            result
+                .errors
+                .push("Email appears more than once in the import".to_string());
+        }
+
+        match (&record.username, &record.password_hash) {
+            (Some(username), Some(password_hash)) => {
+                if existing_usernames.contains(&fold_username(username)) {
+                    result.errors.push("Username already exists".to_string());
+                } else if !seen_usernames.insert(fold_username(username)) {
+                    result
+                        .errors
+                        .push("Username appears more than once in the import".to_string());
+                }
+                if !is_supported_password_hash(password_hash) {
+                    result
+                        .errors
+                        .push("password_hash must be a bcrypt or argon2 hash".to_string());
+                }
+            }
+            (None, Some(_)) => {
+                result
+                    .errors
+                    .push("Username is required when a password hash is given".to_string());
+            }
+            // Invitees choose their own username when they accept
+            (Some(_), None) => {
+                result.errors.push(
+                    "A password hash is required when a username is given; leave the username \
+                     empty to invite"
+                        .to_string(),
+                );
+            }
+            // Rows without a password hash become invitations
+            (None, None) => {}
+        }
+
+        let mut role_ids = Vec::with_capacity(record.roles.len());
+        for name in &record.roles {
+            match roles.get(name) {
+                Some(id) => role_ids.push(*id),
+                None => result.errors.push(format!("Role '{name}' not found")),
+            }
+        }
+        if role_ids.is_empty() {
+            role_ids.push(default_role_id);
+        }
+
+        if result.errors.is_empty() {
+            let action = match (&record.username, &record.password_hash) {
+                (Some(username), Some(password_hash)) => RowAction::CreateUser {
+                    username: username.clone(),
+                    password_hash: password_hash.clone(),
+                },
+                _ => {
+                    let token = generate_opaque_token();
+                    RowAction::Invite {
+                        token_hash: hash_opaque_token(&token),
+                        token,
+                    }
+                }
+            };
+            let row = ValidRow {
+                record,
+                role_ids,
+                action,
+            };
+            valid_rows.push((results.len(), row));
+        } else {
+            result.status = ImportRowStatus::Failed;
+        }
+        results.push(result);
+    }
+
+    let invalid = results.len() - valid_rows.len();
+    let mut aborted = false;
+    let expires_at = Utc::now() + chrono::Duration::hours(DEFAULT_INVITATION_TTL_HOURS);
+
+    if !query.dry_run {
+        match query.mode {
+            ImportMode::Transactional if invalid > 0 => aborted = true,
+            ImportMode::Transactional => {
+                let accounts: Vec<NewAccount> = valid_rows
+                    .iter()
+                    .map(|(_, row)| row.account(claims.sub, expires_at))
+                    .collect();
+
+                match repos.provisioning.import(&accounts).await {
+                    Ok(ids) => {
+                        for ((index, row), id) in valid_rows.iter().zip(ids) {
+                            record_outcome(&mut results[*index], row.outcome(id));
+                        }
+                    }
+                    Err(e) => {
+                        let Some(failed) = e.index else {
+                            return Err(AppError::Internal(format!("Database error: {e}")));
+                        };
+                        let index = valid_rows[failed].0;
+                        results[index].status = ImportRowStatus::Failed;
+                        results[index].errors.push(row_write_error(&e.source));
+                        aborted = true;
+                    }
+                }
+            }
+            ImportMode::BestEffort => {
+                for (index, row) in &valid_rows {
+                    // Each row is written on its own so a user never lacks their roles
+                    let account = row.account(claims.sub, expires_at);
+                    match repos.provisioning.import(&[account]).await {
+                        Ok(ids) => record_outcome(&mut results[*index], row.outcome(ids[0])),
+                        Err(e) => {
+                            results[*index].status = ImportRowStatus::Failed;
+                            results[*index].errors.push(row_write_error(&e.source));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if aborted {
+        // Nothing was written, so the failed rows are all there is to report
+        let errors = results
+            .iter()
+            .flat_map(|result| {
+                result
+                    .errors
+                    .iter()
+                    .map(|message| FieldError::new(format!("row {}", result.row), message))
+            })
+            .collect();
+        return Err(AppError::Validation(
+            "Import aborted; no rows were written".to_string(),
+            errors,
+        ));
+    }
+
+    let failed = results
+        .iter()
+        .filter(|r| r.status == ImportRowStatus::Failed)
+        .count();
+    let succeeded = results
+        .iter()
+        .filter(|r| {
+            matches!(
+                r.status,
+                ImportRowStatus::Valid | ImportRowStatus::Created | ImportRowStatus::Invited
+            )
+        })
+        .count();
+
+    let report = ImportReport {
+        dry_run: query.dry_run,
+        mode: query.mode,
+        total: results.len(),
+        succeeded,
+        failed,
+        rows: results,
+    };
+
+    Ok(HttpResponse::Ok().json(ApiResponse::new(report)))
+}
+
+/// The error reported for a row the database refused. Rows are checked for
+/// taken usernames and emails up front, so these are mostly races with other
+/// writers; anything else is logged rather than shown.
+fn row_write_error(e: &RepositoryError) -> String {
+    if e.is_unique_violation_on("users.username") {
+        "Username already exists".to_string()
+    } else if e.is_unique_violation_on("users.email") {
+        "Email already exists".to_string()
+    } else {
+        log::error!("Failed to import user: {e}");
+        "The row could not be written".to_string()
+    }
+}
+
+// User export
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Ndjson,
+    Csv,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+    /// Password hashes are omitted unless explicitly requested
+    #[serde(default)]
+    pub include_password_hash: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct ExportRecord {
+    id: Uuid,
+    username: String,
+    email: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    password_hash: Option<String>,
+    is_active: bool,
+    created_at: chrono::DateTime<chrono::Utc>,
+    roles: Vec<String>,
+}
+
+fn encode_page(
+    users: Vec<UserWithRoles>,
+    format: ExportFormat,
+    include_password_hash: bool,
+    write_header: bool,
+) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+
+    match format {
+        ExportFormat::Ndjson => {
+            for user in users {
+                let record = ExportRecord {
+                    id: user.id,
+                    username: user.username,
+                    email: user.email,
+                    password_hash: include_password_hash.then_some(user.password_hash),
+                    is_active: user.is_active,
+                    created_at: user.created_at,
+                    roles: user.roles,
+                };
+                serde_json::to_writer(&mut out, &record).map_err(|e| e.to_string())?;
+                out.push(b'\n');
+            }
+        }
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(&mut out);
+            if write_header {
+                writer
+                    .write_record([
+                        "id",
+                        "username",
+                        "email",
+                        "password_hash",
+                        "is_active",
+                        "created_at",
+                        "roles",
+                    ])
+                    .map_err(|e| e.to_string())?;
+            }
+            for user in users {
+                writer
+                    .write_record([
+                        user.id.to_string(),
+                        user.username,
+                        user.email,
+                        if include_password_hash {
+                            user.password_hash
+                        } else {
+                            String::new()
+                        },
+                        user.is_active.to_string(),
+                        user.created_at.to_rfc3339(),
+                        user.roles.join(";"),
+                    ])
+                    .map_err(|e| e.to_string())?;
+            }
+            writer.flush().map_err(|e| e.to_string())?;
+        }
+    }
+
+    Ok(out)
+}
+
+/// Export state carried between streamed chunks
+struct ExportCursor {
+    provisioning: Arc<dyn ProvisioningRepository>,
+    after: Option<Uuid>,
+    first: bool,
+    done: bool,
+}
+
+pub async fn export_users(
+    repos: web::Data<Repositories>,
+    query: web::Query<ExportQuery>,
+) -> AppResult<impl Responder> {
+    let format = query.format;
+    let include_password_hash = query.include_password_hash;
+
+    let cursor = ExportCursor {
+        provisioning: repos.provisioning.clone(),
+        after: None,
+        first: true,
+        done: false,
+    };
+
+    // Page through users by id so memory use stays flat regardless of user count
+    let stream = futures_util::stream::unfold(cursor, move |mut cursor| async move {
+        if cursor.done {
+            return None;
+        }
+
+        let page = match cursor
+            .provisioning
+            .export_page(cursor.after, EXPORT_PAGE_SIZE)
+            .await
+        {
+            Ok(page) => page,
+            Err(e) => {
+                cursor.done = true;
+                let error = AppError::Internal(format!("Failed to export users: {e}"));
+                return Some((Err(actix_web::Error::from(error)), cursor));
+            }
+        };
+
+        if (page.len() as i64) < EXPORT_PAGE_SIZE {
+            cursor.done = true;
+        }
+        cursor.after = page.last().map(|u| u.id);
+
+        let chunk = encode_page(page, format, include_password_hash, cursor.first)
+            .map(web::Bytes::from)
+            .map_err(|e| actix_web::Error::from(AppError::Internal(e)));
+        cursor.first = false;
+
+        Some((chunk, cursor))
+    });
+
+    let (content_type, extension) = match format {
+        ExportFormat::Ndjson => ("application/x-ndjson", "ndjson"),
+        ExportFormat::Csv => ("text/csv", "csv"),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"users.{extension}\""),
+        ))
+        .streaming(stream))
+}
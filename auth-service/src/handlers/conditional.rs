@@ -0,0 +1,62 @@
+//! Optimistic concurrency for the admin API. Users, roles and permissions
+//! carry a version that is sent as a strong `ETag`; writes that replace or
+//! delete them must echo it in `If-Match`, so an admin working from a stale
+//! copy gets `412 Precondition Failed` instead of overwriting someone else.
+
+use actix_web::http::header::{ETag, EntityTag, Header, IfMatch, IF_MATCH};
+use actix_web::HttpRequest;
+use shared::{AppError, AppResult};
+
+/// The `ETag` header for a resource at `version`
+pub fn etag(version: i32) -> ETag {
+    ETag(EntityTag::new_strong(version.to_string()))
+}
+
+/// The version a conditional write must find, from `If-Match`; `None` for
+/// `If-Match: *`, which only requires the resource to exist.
+///
+/// A missing header is rejected with 428, and a tag that cannot be one of
+/// ours (weak, or not a version number) with 412 since it can never match.
+pub fn required_version(req: &HttpRequest, resource: &str) -> AppResult<Option<i32>> {
+    if !req.headers().contains_key(IF_MATCH) {
+        return Err(AppError::PreconditionRequired(format!(
+            "If-Match with the {}'s ETag is required",
+            resource.to_lowercase()
+        )));
+    }
+    optional_version(req, resource)
+}
+
+/// Like [`required_version`], but a missing header means no precondition
+pub fn optional_version(req: &HttpRequest, resource: &str) -> AppResult<Option<i32>> {
+    if !req.headers().contains_key(IF_MATCH) {
+        return Ok(None);
+    }
+
+    let tags = match IfMatch::parse(req) {
+        Ok(IfMatch::Any) => return Ok(None),
+        Ok(IfMatch::Items(tags)) => tags,
+        Err(_) => {
+            return Err(AppError::BadRequest(
+                "Malformed If-Match header".to_string(),
+            ))
+        }
+    };
+    let [tag] = tags.as_slice() else {
+        return Err(AppError::BadRequest(
+            "If-Match must carry a single entity tag".to_string(),
+        ));
+    };
+
+    if tag.weak {
+        return Err(stale(resource));
+    }
+    tag.tag().parse().map(Some).map_err(|_| stale(resource))
+}
+
+/// The response for a conditional write that found another version
+pub fn stale(resource: &str) -> AppError {
+    AppError::PreconditionFailed(format!(
+        "{resource} has been modified; fetch it again to get the current ETag"
+    ))
+}
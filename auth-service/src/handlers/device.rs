@@ -0,0 +1,229 @@
+//! The OAuth 2.0 device authorization grant (RFC 8628), which lets a
+//! command-line tool sign a user in without handling their password: the
+//! tool shows a user code, the user approves it here from a signed-in
+//! browser, and the tool meanwhile polls the token endpoint.
+
+use crate::config::Config;
+use crate::handlers::token::OAuthError;
+use crate::models::{DeviceAuthorization, DeviceAuthorizationStatus};
+use crate::repositories::{Repositories, RepositoryError};
+use crate::services::{
+    generate_opaque_token, generate_user_code, hash_opaque_token, normalize_user_code,
+};
+use actix_web::{web, HttpResponse, Responder};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use shared::{ApiResponse, AppError, AppResult, Claims};
+
+/// `grant_type` with which the device polls the token endpoint
+pub const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Seconds the user has to approve a request
+pub const DEVICE_CODE_TTL_SECONDS: i64 = 600;
+
+/// Seconds a device initially waits between polls
+pub const DEVICE_POLL_INTERVAL_SECONDS: i32 = 5;
+
+/// Seconds added to a device's polling interval each time it polls too fast
+pub const DEVICE_SLOW_DOWN_SECONDS: i32 = 5;
+
+/// Attempts at drawing a user code not already in use
+const USER_CODE_ATTEMPTS: usize = 5;
+
+#[derive(Debug, Deserialize)]
+pub struct DeviceAuthorizationRequest {
+    /// Names the tool to the user; public clients are not registered
+    pub client_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceAuthorizationResponse {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    /// The verification URI with the user code filled in
+    pub verification_uri_complete: String,
+    pub expires_in: i64,
+    pub interval: i32,
+}
+
+/// `POST /oauth/device_authorization`: starts a device flow, returning the
+/// device code to poll with and the user code to show the user
+pub async fn device_authorization(
+    repos: web::Data<Repositories>,
+    config: web::Data<Config>,
+    req: web::Form<DeviceAuthorizationRequest>,
+) -> Result<HttpResponse, OAuthError> {
+    // Users are sent to the page where they approve the request, which this
+    // service does not serve. Its URL is configured rather than taken from
+    // the request, whose Host the device controls.
+    let verification_uri = config.device_verification_uri.clone().ok_or_else(|| {
+        OAuthError::unauthorized_client("The device flow is not enabled on this server")
+    })?;
+    let client_id = req
+        .client_id
+        .as_deref()
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .ok_or_else(|| OAuthError::invalid_request("client_id is required"))?;
+    if client_id.len() > 255 {
+        return Err(OAuthError::invalid_request(
+            "client_id must be at most 255 characters",
+        ));
+    }
+
+    let device_code = generate_opaque_token();
+    let device_code_hash = hash_opaque_token(&device_code);
+    let expires_at = Utc::now() + Duration::seconds(DEVICE_CODE_TTL_SECONDS);
+
+    // User codes are short, so a collision with a pending one is possible
+    let mut attempts = 0;
+    let authorization = loop {
+        attempts += 1;
+        match repos
+            .device_authorizations
+            .create(
+                &device_code_hash,
+                &generate_user_code(),
+                client_id,
+                DEVICE_POLL_INTERVAL_SECONDS,
+                expires_at,
+            )
+            .await
+        {
+            Ok(authorization) => break authorization,
+            Err(e)
+                if attempts < USER_CODE_ATTEMPTS
+                    && e.is_unique_violation_on("device_authorizations.user_code") => {}
+            Err(e) => {
+                return Err(AppError::Internal(format!(
+                    "Failed to create device authorization: {e}"
+                ))
+                .into())
+            }
+        }
+    };
+
+    let response = DeviceAuthorizationResponse {
+        device_code,
+        verification_uri_complete: format!(
+            "{verification_uri}?user_code={}",
+            authorization.user_code
+        ),
+        user_code: authorization.user_code,
+        verification_uri,
+        expires_in: DEVICE_CODE_TTL_SECONDS,
+        interval: authorization.interval_seconds,
+    };
+
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-store"))
+        .json(response))
+}
+
+/// What the user is shown before deciding on a request
+#[derive(Debug, Serialize)]
+pub struct DeviceRequestResponse {
+    pub user_code: String,
+    pub client_id: String,
+    pub status: DeviceAuthorizationStatus,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl From<DeviceAuthorization> for DeviceRequestResponse {
+    fn from(authorization: DeviceAuthorization) -> Self {
+        Self {
+            status: authorization.status(),
+            user_code: authorization.user_code,
+            client_id: authorization.client_id,
+            created_at: authorization.created_at,
+            expires_at: authorization.expires_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeviceRequestQuery {
+    pub user_code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeviceDecisionRequest {
+    pub user_code: String,
+    pub approve: bool,
+}
+
+/// Only the user themselves can sign a device in as them
+fn require_user(claims: &Claims) -> AppResult<()> {
+    if claims.is_service() || claims.is_impersonated() {
+        return Err(AppError::Forbidden(
+            "Only users signed in as themselves can approve devices".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+async fn find_request(repos: &Repositories, user_code: &str) -> AppResult<DeviceAuthorization> {
+    let not_found = || AppError::NotFound("No device request with this code".to_string());
+    let user_code = normalize_user_code(user_code).ok_or_else(not_found)?;
+    repos
+        .device_authorizations
+        .find_by_user_code(&user_code)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get device request: {e}")))?
+        .ok_or_else(not_found)
+}
+
+/// `GET /oauth/device?user_code=`: the request the user is about to decide on
+pub async fn get_device_request(
+    repos: web::Data<Repositories>,
+    claims: web::ReqData<Claims>,
+    query: web::Query<DeviceRequestQuery>,
+) -> AppResult<impl Responder> {
+    require_user(&claims)?;
+    let authorization = find_request(&repos, &query.user_code).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::new(DeviceRequestResponse::from(authorization))))
+}
+
+/// `POST /oauth/device`: the signed-in user approves or denies a request;
+/// the device collects the outcome on its next poll
+pub async fn decide_device_request(
+    repos: web::Data<Repositories>,
+    claims: web::ReqData<Claims>,
+    req: web::Json<DeviceDecisionRequest>,
+) -> AppResult<impl Responder> {
+    require_user(&claims)?;
+    let authorization = find_request(&repos, &req.user_code).await?;
+
+    let decided = match authorization.status() {
+        DeviceAuthorizationStatus::Pending => repos
+            .device_authorizations
+            .decide(&authorization.user_code, claims.sub, req.approve)
+            .await
+            .map_err(|e| match e {
+                RepositoryError::ForeignKeyViolation(_) => {
+                    AppError::Unauthorized("User no longer exists".to_string())
+                }
+                e => AppError::Internal(format!("Failed to decide device request: {e}")),
+            })?,
+        _ => None,
+    };
+    // Someone else decided first, or the request expired meanwhile
+    let Some(decided) = decided else {
+        return Err(AppError::Conflict(
+            "The device request is no longer pending".to_string(),
+        ));
+    };
+
+    let message = if req.approve {
+        "Device approved"
+    } else {
+        "Device denied"
+    };
+    Ok(HttpResponse::Ok().json(ApiResponse::with_message(
+        DeviceRequestResponse::from(decided),
+        message.to_string(),
+    )))
+}
@@ -1,5 +1,15 @@
 pub mod admin;
 pub mod auth;
+pub mod bulk;
+pub mod conditional;
+pub mod device;
+pub mod oidc;
+pub mod preferences;
+pub mod privacy;
+pub mod scim;
+pub mod service_accounts;
+pub mod token;
+pub mod webhooks;
 
 pub use admin::*;
 pub use auth::*;
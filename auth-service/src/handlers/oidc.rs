@@ -0,0 +1,178 @@
+//! Sign-in through an upstream OpenID Connect provider. The user is sent to
+//! the provider and comes back to the callback, which signs them in as the
+//! local user linked to their identity there, linking or creating one on
+//! their first visit.
+
+use crate::config::{Config, OidcProvider};
+use crate::handlers::auth::{start_session, LoginResponse};
+use crate::repositories::Repositories;
+use crate::services::federation::Federation;
+use crate::services::oidc::{OidcClient, OidcError};
+use actix_web::cookie::{time::Duration as CookieDuration, Cookie, SameSite};
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use shared::{ApiResponse, AppError, AppResult};
+
+/// Holds the state, nonce and PKCE verifier of a sign-in in progress
+const LOGIN_COOKIE: &str = "oidc_login";
+
+/// Seconds the user has to sign in at the provider
+const LOGIN_TTL_SECONDS: i64 = 600;
+
+fn find_provider<'a>(config: &'a Config, name: &str) -> AppResult<&'a OidcProvider> {
+    config
+        .oidc_providers
+        .iter()
+        .find(|p| p.name == name)
+        .ok_or_else(|| AppError::NotFound(format!("Identity provider '{name}' not found")))
+}
+
+/// The callback registered at the provider, by default on the host the
+/// sign-in started on
+fn redirect_uri(provider: &OidcProvider, http_req: &HttpRequest) -> String {
+    if let Some(uri) = &provider.redirect_uri {
+        return uri.clone();
+    }
+    let connection = http_req.connection_info();
+    format!(
+        "{}://{}/auth/oidc/{}/callback",
+        connection.scheme(),
+        connection.host(),
+        provider.name
+    )
+}
+
+fn login_cookie(provider: &OidcProvider, http_req: &HttpRequest, value: String) -> Cookie<'static> {
+    Cookie::build(LOGIN_COOKIE, value)
+        .path(format!("/auth/oidc/{}", provider.name))
+        .http_only(true)
+        .secure(http_req.connection_info().scheme() == "https")
+        // Sent along when the provider redirects back, unlike `Strict`
+        .same_site(SameSite::Lax)
+        .max_age(CookieDuration::seconds(LOGIN_TTL_SECONDS))
+        .finish()
+}
+
+fn provider_error(provider: &OidcProvider, e: OidcError) -> AppError {
+    match e {
+        OidcError::InvalidIdToken(_) | OidcError::Rejected(_) => {
+            AppError::Unauthorized(format!("Sign-in with {} failed: {e}", provider.name))
+        }
+        OidcError::Provider(_) => {
+            AppError::ServiceUnavailable(format!("Sign-in with {} failed: {e}", provider.name))
+        }
+        OidcError::Timeout(_) => {
+            AppError::GatewayTimeout(format!("Sign-in with {} failed: {e}", provider.name))
+        }
+    }
+}
+
+/// `GET /auth/oidc/{provider}/login`: redirects the user to the provider
+pub async fn oidc_login(
+    config: web::Data<Config>,
+    oidc: web::Data<OidcClient>,
+    http_req: HttpRequest,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    let provider = find_provider(&config, &path)?;
+    let request = oidc
+        .authorization_request(provider, &redirect_uri(provider, &http_req))
+        .await
+        .map_err(|e| provider_error(provider, e))?;
+
+    // The values are alphanumeric, so a dot separates them unambiguously
+    let value = format!(
+        "{}.{}.{}",
+        request.state, request.nonce, request.code_verifier
+    );
+    Ok(HttpResponse::Found()
+        .insert_header(("Location", request.url))
+        .insert_header(("Cache-Control", "no-store"))
+        .cookie(login_cookie(provider, &http_req, value))
+        .finish())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    /// Set instead of `code` when the provider refused the sign-in
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+/// `GET /auth/oidc/{provider}/callback`: where the provider sends the user
+/// back. Responds like `POST /auth/login`.
+pub async fn oidc_callback(
+    repos: web::Data<Repositories>,
+    config: web::Data<Config>,
+    oidc: web::Data<OidcClient>,
+    http_req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<CallbackQuery>,
+) -> AppResult<HttpResponse> {
+    let provider = find_provider(&config, &path)?;
+
+    if let Some(error) = &query.error {
+        let description = query.error_description.as_deref().unwrap_or(error);
+        return Err(AppError::Unauthorized(format!(
+            "{} refused the sign-in: {description}",
+            provider.name
+        )));
+    }
+
+    // The state ties the callback to a sign-in this browser started, so no
+    // one can sign a victim in to the attacker's account
+    let cookie = http_req.cookie(LOGIN_COOKIE).ok_or_else(|| {
+        AppError::BadRequest("No sign-in is in progress, or it has expired".to_string())
+    })?;
+    let mut parts = cookie.value().split('.');
+    let (Some(state), Some(nonce), Some(code_verifier), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(AppError::BadRequest("Malformed sign-in cookie".to_string()));
+    };
+    if query.state.as_deref() != Some(state) {
+        return Err(AppError::BadRequest(
+            "The sign-in state does not match; start again".to_string(),
+        ));
+    }
+    let code = query
+        .code
+        .as_deref()
+        .ok_or_else(|| AppError::BadRequest("code is required".to_string()))?;
+
+    let identity = oidc
+        .complete(
+            provider,
+            code,
+            &redirect_uri(provider, &http_req),
+            code_verifier,
+            nonce,
+        )
+        .await
+        .map_err(|e| provider_error(provider, e))?;
+
+    let federation = Federation {
+        source: &provider.name,
+        group_roles: &provider.group_roles,
+        auto_provision: provider.auto_provision,
+    };
+    let user = federation.sign_in(&repos, &identity).await?;
+
+    let session = start_session(&repos, &config, &http_req, &user).await?;
+    let response = LoginResponse {
+        token: session.token,
+        user_id: user.id,
+        username: user.username,
+        roles: session.roles,
+        session_id: session.session_id,
+    };
+
+    let mut removal = login_cookie(provider, &http_req, String::new());
+    removal.make_removal();
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-store"))
+        .cookie(removal)
+        .json(ApiResponse::new(response)))
+}
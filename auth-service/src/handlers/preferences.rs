@@ -0,0 +1,186 @@
+//! The signed-in user's preferences. The weather and time services read them
+//! with the caller's token to fill in query parameters the request left out.
+
+use crate::repositories::Repositories;
+use actix_web::{web, HttpResponse, Responder};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use shared::{ApiResponse, AppError, AppResult, Claims, FieldError, Preferences};
+use uuid::Uuid;
+
+const MAX_FAVOURITE_CITIES: usize = 20;
+const MAX_CITY_LENGTH: usize = 100;
+
+fn reject_service(claims: &Claims) -> AppResult<()> {
+    if claims.is_service() {
+        return Err(AppError::Forbidden(
+            "Service accounts have no preferences".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks the free-text fields and tidies the city list: names are trimmed,
+/// and a city listed twice keeps only its first place
+fn validate(mut preferences: Preferences) -> AppResult<Preferences> {
+    let mut errors = Vec::new();
+
+    if let Some(locale) = &preferences.locale {
+        let valid = (2..=35).contains(&locale.len())
+            && locale
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-');
+        if !valid {
+            errors.push(FieldError::new(
+                "locale",
+                "Must be a language tag such as en-GB",
+            ));
+        }
+    }
+
+    if let Some(timezone) = &preferences.home_timezone {
+        let valid = (1..=64).contains(&timezone.len())
+            && timezone
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '_' | '+' | '-'));
+        if !valid {
+            errors.push(FieldError::new(
+                "home_timezone",
+                "Must be a time zone name such as Europe/London",
+            ));
+        }
+    }
+
+    let mut cities: Vec<String> = Vec::new();
+    for city in &preferences.favourite_cities {
+        let city = city.trim();
+        if city.is_empty() || city.chars().count() > MAX_CITY_LENGTH {
+            errors.push(FieldError::new(
+                "favourite_cities",
+                format!("City names must be between 1 and {MAX_CITY_LENGTH} characters"),
+            ));
+            break;
+        }
+        if !cities.iter().any(|c| c.eq_ignore_ascii_case(city)) {
+            cities.push(city.to_string());
+        }
+    }
+    if cities.len() > MAX_FAVOURITE_CITIES {
+        errors.push(FieldError::new(
+            "favourite_cities",
+            format!("At most {MAX_FAVOURITE_CITIES} cities can be saved"),
+        ));
+    }
+
+    if !errors.is_empty() {
+        return Err(AppError::Validation(
+            "The preferences are not valid".to_string(),
+            errors,
+        ));
+    }
+    preferences.favourite_cities = cities;
+
+    Ok(preferences)
+}
+
+/// A merge patch member; `null` parses as `None` for the optional fields
+fn parse<T: DeserializeOwned>(name: &str, value: Value) -> AppResult<T> {
+    serde_json::from_value(value)
+        .map_err(|_| AppError::BadRequest(format!("Invalid value for '{name}'")))
+}
+
+async fn load(repos: &Repositories, user_id: Uuid) -> AppResult<Preferences> {
+    let preferences = repos
+        .preferences
+        .get(user_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get preferences: {e}")))?;
+
+    Ok(preferences.unwrap_or_default())
+}
+
+async fn save(
+    repos: &Repositories,
+    user_id: Uuid,
+    preferences: Preferences,
+) -> AppResult<HttpResponse> {
+    let preferences = validate(preferences)?;
+    repos
+        .preferences
+        .put(user_id, &preferences)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to save preferences: {e}")))?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::new(preferences)))
+}
+
+/// `GET /auth/me/preferences`: every field is empty until the user sets it
+pub async fn get_my_preferences(
+    repos: web::Data<Repositories>,
+    claims: web::ReqData<Claims>,
+) -> AppResult<impl Responder> {
+    reject_service(&claims)?;
+
+    let preferences = load(&repos, claims.sub).await?;
+    Ok(HttpResponse::Ok().json(ApiResponse::new(preferences)))
+}
+
+/// `PUT /auth/me/preferences`: replaces them all; fields left out are cleared
+pub async fn put_my_preferences(
+    repos: web::Data<Repositories>,
+    claims: web::ReqData<Claims>,
+    req: web::Json<Preferences>,
+) -> AppResult<impl Responder> {
+    reject_service(&claims)?;
+
+    save(&repos, claims.sub, req.into_inner()).await
+}
+
+/// `PATCH /auth/me/preferences`: a JSON Merge Patch (RFC 7396); `null`
+/// clears a field
+pub async fn patch_my_preferences(
+    repos: web::Data<Repositories>,
+    claims: web::ReqData<Claims>,
+    patch: web::Json<Value>,
+) -> AppResult<impl Responder> {
+    reject_service(&claims)?;
+
+    let Value::Object(members) = patch.into_inner() else {
+        return Err(AppError::BadRequest(
+            "A merge patch must be a JSON object".to_string(),
+        ));
+    };
+
+    let mut preferences = load(&repos, claims.sub).await?;
+    for (name, value) in members {
+        match name.as_str() {
+            "units" => preferences.units = parse(&name, value)?,
+            "locale" => preferences.locale = parse(&name, value)?,
+            "time_format" => preferences.time_format = parse(&name, value)?,
+            "home_timezone" => preferences.home_timezone = parse(&name, value)?,
+            "favourite_cities" => {
+                preferences.favourite_cities =
+                    parse::<Option<Vec<String>>>(&name, value)?.unwrap_or_default()
+            }
+            _ => return Err(AppError::BadRequest(format!("'{name}' cannot be changed"))),
+        }
+    }
+
+    save(&repos, claims.sub, preferences).await
+}
+
+/// `DELETE /auth/me/preferences`: back to the services' defaults
+pub async fn delete_my_preferences(
+    repos: web::Data<Repositories>,
+    claims: web::ReqData<Claims>,
+) -> AppResult<impl Responder> {
+    reject_service(&claims)?;
+
+    repos
+        .preferences
+        .delete(claims.sub)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to delete preferences: {e}")))?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
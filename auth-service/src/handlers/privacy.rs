@@ -0,0 +1,264 @@
+//! Data subject requests: a user, or an admin on their behalf, can download
+//! everything held about them, and an admin can erase a user on request.
+//! Erasure deletes the user and what belongs to them, and replaces them with
+//! a pseudonym in the audit records that must be kept.
+
+use crate::handlers::admin::{GroupResponse, InvitationResponse};
+use crate::handlers::auth::SessionResponse;
+use crate::handlers::service_accounts::{account_response, ServiceAccountResponse};
+use crate::models::{Erasure, ImpersonationEvent, User, UserIdentity, WebhookEvent};
+use crate::repositories::Repositories;
+use actix_web::{web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use shared::{ApiResponse, AppError, AppResult, Claims, Preferences};
+use uuid::Uuid;
+
+#[derive(Debug, Serialize)]
+pub struct ExportedProfile {
+    pub id: Uuid,
+    pub username: String,
+    pub email: String,
+    pub is_active: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// Set while the user is deleted but not yet purged
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl From<User> for ExportedProfile {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            email: user.email,
+            is_active: user.is_active,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+            deleted_at: user.deleted_at,
+        }
+    }
+}
+
+/// An audit event about the user, as webhooks receive it
+#[derive(Debug, Serialize)]
+pub struct ExportedEvent {
+    pub id: Uuid,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub created_at: DateTime<Utc>,
+    pub data: Value,
+}
+
+impl From<WebhookEvent> for ExportedEvent {
+    fn from(event: WebhookEvent) -> Self {
+        Self {
+            id: event.id,
+            event_type: event.event_type,
+            created_at: event.created_at,
+            data: serde_json::from_str(&event.payload).unwrap_or(Value::Null),
+        }
+    }
+}
+
+/// Everything held about a user. Password hashes and client secrets are
+/// left out; they are only ever checked against, never shown.
+#[derive(Debug, Serialize)]
+pub struct DataExport {
+    pub exported_at: DateTime<Utc>,
+    pub profile: ExportedProfile,
+    /// Roles assigned to the user directly
+    pub roles: Vec<String>,
+    /// Empty fields if the user has saved none
+    pub preferences: Preferences,
+    /// Direct roles plus those inherited through groups
+    pub effective_roles: Vec<String>,
+    /// Groups the user is a direct member of
+    pub groups: Vec<GroupResponse>,
+    /// Invitations sent to the user's email address or accepted by them
+    pub invitations: Vec<InvitationResponse>,
+    /// Every session, revoked and expired ones included
+    pub sessions: Vec<SessionResponse>,
+    pub identities: Vec<UserIdentity>,
+    /// Service accounts the user owns, with their credentials' metadata
+    pub service_accounts: Vec<ServiceAccountResponse>,
+    pub events: Vec<ExportedEvent>,
+    pub impersonations: Vec<ImpersonationEvent>,
+}
+
+async fn export(repos: &Repositories, user_id: Uuid) -> AppResult<HttpResponse> {
+    // Soft-deleted users can still ask for their data until they are purged
+    let user = repos
+        .privacy
+        .find_user(user_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get user: {e}")))?
+        .ok_or_else(|| AppError::NotFound(format!("User with id {user_id} not found")))?;
+
+    let roles = repos
+        .assignments
+        .get_user_roles(user_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get user roles: {e}")))?;
+    let effective_roles = repos
+        .assignments
+        .get_effective_user_roles(user_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get user roles: {e}")))?;
+    let preferences = repos
+        .preferences
+        .get(user_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get preferences: {e}")))?
+        .unwrap_or_default();
+    let groups = repos
+        .privacy
+        .user_groups(user_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to list groups: {e}")))?;
+    let invitations = repos
+        .privacy
+        .user_invitations(user_id, &user.email)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to list invitations: {e}")))?;
+    let sessions = repos
+        .sessions
+        .list_for_user(user_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to list sessions: {e}")))?;
+    let identities = repos
+        .identities
+        .list_for_user(user_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to list identities: {e}")))?;
+    let accounts = repos
+        .service_accounts
+        .list()
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to list service accounts: {e}")))?;
+    let events = repos
+        .privacy
+        .user_events(user_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to list events: {e}")))?;
+    let impersonations = repos
+        .privacy
+        .user_impersonations(user_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to list impersonations: {e}")))?;
+
+    let mut exported_invitations = Vec::new();
+    for invitation in invitations {
+        let roles = repos
+            .invitations
+            .get_roles(invitation.id)
+            .await
+            .map_err(|e| AppError::Internal(format!("Failed to get invitation roles: {e}")))?;
+        exported_invitations.push(InvitationResponse::new(invitation, roles));
+    }
+
+    let mut service_accounts = Vec::new();
+    for account in accounts {
+        if account.owner_id == Some(user_id) {
+            service_accounts.push(account_response(repos, account).await?);
+        }
+    }
+
+    let export = DataExport {
+        exported_at: Utc::now(),
+        profile: user.into(),
+        roles: roles.into_iter().map(|r| r.name).collect(),
+        preferences,
+        effective_roles: effective_roles.into_iter().map(|r| r.name).collect(),
+        groups: groups.into_iter().map(GroupResponse::from).collect(),
+        invitations: exported_invitations,
+        sessions: sessions
+            .into_iter()
+            .map(|s| SessionResponse::new(s, None))
+            .collect(),
+        identities,
+        service_accounts,
+        events: events.into_iter().map(ExportedEvent::from).collect(),
+        impersonations,
+    };
+
+    Ok(HttpResponse::Ok()
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"user-{user_id}.json\""),
+        ))
+        .insert_header(("Cache-Control", "no-store"))
+        .json(export))
+}
+
+/// `GET /auth/me/export`: the signed-in user's own data
+pub async fn export_my_data(
+    repos: web::Data<Repositories>,
+    claims: web::ReqData<Claims>,
+) -> AppResult<impl Responder> {
+    // The export goes to the person it is about, not to someone acting as them
+    if claims.is_impersonated() {
+        return Err(AppError::Forbidden(
+            "Impersonation tokens cannot export the user's data".to_string(),
+        ));
+    }
+    if claims.is_service() {
+        return Err(AppError::Forbidden(
+            "Service accounts have no personal data to export".to_string(),
+        ));
+    }
+
+    export(&repos, claims.sub).await
+}
+
+/// `GET /admin/users/{id}/export`: a user's data, for answering their request
+pub async fn export_user_data(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+) -> AppResult<impl Responder> {
+    export(&repos, path.into_inner()).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EraseUserRequest {
+    /// Kept with the record of the erasure, such as a ticket reference; it
+    /// should not itself identify the user
+    pub reason: Option<String>,
+}
+
+/// `POST /admin/users/{id}/erase`: erases a user for good, soft-deleted or
+/// not. There is no undo.
+pub async fn erase_user(
+    repos: web::Data<Repositories>,
+    claims: web::ReqData<Claims>,
+    path: web::Path<Uuid>,
+    req: web::Json<EraseUserRequest>,
+) -> AppResult<impl Responder> {
+    let user_id = path.into_inner();
+    let reason = req
+        .reason
+        .as_deref()
+        .map(str::trim)
+        .filter(|r| !r.is_empty());
+
+    let erasure = repos
+        .privacy
+        .erase_user(user_id, claims.sub, reason)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to erase user: {e}")))?
+        .ok_or_else(|| AppError::NotFound(format!("User with id {user_id} not found")))?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::new(erasure)))
+}
+
+/// `GET /admin/erasures`: the record of erasures carried out, newest first
+pub async fn list_erasures(repos: web::Data<Repositories>) -> AppResult<impl Responder> {
+    let erasures: Vec<Erasure> = repos
+        .privacy
+        .list_erasures()
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to list erasures: {e}")))?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::new(erasures)))
+}
@@ -0,0 +1,1193 @@
+//! SCIM 2.0 provisioning API (RFC 7643 / RFC 7644).
+//!
+//! SCIM Users map onto the `users` table and SCIM Groups map onto `roles`,
+//! with group membership stored as direct `user_roles` assignments.
+
+use crate::handlers::admin::BUILTIN_ROLES;
+use crate::models::user::UpdateUser;
+use crate::models::{Role, User};
+use crate::repositories::{
+    FilterValue, NewAccount, Repositories, RepositoryError, UserAttribute, UserFilter,
+};
+use crate::services::scim_filter::CompareOp;
+use crate::services::{generate_opaque_token, hash_password, ScimFilter};
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+use uuid::Uuid;
+
+const USER_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+const GROUP_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+const LIST_RESPONSE_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+const ERROR_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+const SCIM_CONTENT_TYPE: &str = "application/scim+json";
+
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// Error rendered in the SCIM error format (RFC 7644 section 3.12)
+#[derive(Debug)]
+pub struct ScimError {
+    status: StatusCode,
+    scim_type: Option<&'static str>,
+    detail: String,
+}
+
+impl ScimError {
+    pub fn new(
+        status: StatusCode,
+        scim_type: Option<&'static str>,
+        detail: impl Into<String>,
+    ) -> Self {
+        Self {
+            status,
+            scim_type,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn unauthorized(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, None, detail)
+    }
+
+    pub fn bad_request(scim_type: &'static str, detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, Some(scim_type), detail)
+    }
+
+    pub fn not_found(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, None, detail)
+    }
+
+    pub fn conflict(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::CONFLICT, Some("uniqueness"), detail)
+    }
+
+    pub fn internal(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, None, detail)
+    }
+}
+
+impl fmt::Display for ScimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.detail)
+    }
+}
+
+impl ResponseError for ScimError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut body = json!({
+            "schemas": [ERROR_SCHEMA],
+            "status": self.status.as_u16().to_string(),
+            "detail": self.detail,
+        });
+        if let Some(scim_type) = self.scim_type {
+            body["scimType"] = json!(scim_type);
+        }
+
+        HttpResponse::build(self.status)
+            .content_type(SCIM_CONTENT_TYPE)
+            .json(body)
+    }
+}
+
+pub type ScimResult<T> = Result<T, ScimError>;
+
+fn db_error(e: RepositoryError) -> ScimError {
+    ScimError::internal(format!("Database error: {e}"))
+}
+
+fn scim_response<T: Serialize>(status: StatusCode, body: &T) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type(SCIM_CONTENT_TYPE)
+        .json(body)
+}
+
+fn base_url(req: &HttpRequest) -> String {
+    let conn = req.connection_info();
+    format!("{}://{}/scim/v2", conn.scheme(), conn.host())
+}
+
+fn parse_id(id: &str, resource: &str) -> ScimResult<Uuid> {
+    id.parse::<Uuid>()
+        .map_err(|_| ScimError::not_found(format!("{resource} {id} not found")))
+}
+
+/// Strips a schema URN prefix from an attribute path and lowercases it
+fn normalize_attr(path: &str) -> String {
+    let path = match path.rfind(':') {
+        Some(i) if path.to_ascii_lowercase().starts_with("urn:") => &path[i + 1..],
+        _ => path,
+    };
+    path.to_ascii_lowercase()
+}
+
+// Resource representations
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScimEmail {
+    pub value: String,
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub email_type: Option<String>,
+    #[serde(default)]
+    pub primary: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScimMemberRef {
+    pub value: Uuid,
+    pub display: String,
+    #[serde(rename = "$ref")]
+    pub reference: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimMeta {
+    pub resource_type: &'static str,
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub last_modified: chrono::DateTime<chrono::Utc>,
+    pub location: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUser {
+    pub schemas: Vec<&'static str>,
+    pub id: Uuid,
+    pub user_name: String,
+    pub emails: Vec<ScimEmail>,
+    pub active: bool,
+    pub groups: Vec<ScimMemberRef>,
+    pub meta: ScimMeta,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimGroup {
+    pub schemas: Vec<&'static str>,
+    pub id: Uuid,
+    pub display_name: String,
+    pub members: Vec<ScimMemberRef>,
+    pub meta: ScimMeta,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimListResponse<T> {
+    pub schemas: Vec<&'static str>,
+    pub total_results: usize,
+    pub start_index: usize,
+    pub items_per_page: usize,
+    #[serde(rename = "Resources")]
+    pub resources: Vec<T>,
+}
+
+async fn to_scim_user(repos: &Repositories, base: &str, user: User) -> ScimResult<ScimUser> {
+    let roles = repos
+        .assignments
+        .get_user_roles(user.id)
+        .await
+        .map_err(db_error)?;
+
+    Ok(scim_user(base, user, roles))
+}
+
+/// `roles` are the roles the user holds directly, as their SCIM groups
+fn scim_user(base: &str, user: User, roles: Vec<Role>) -> ScimUser {
+    ScimUser {
+        schemas: vec![USER_SCHEMA],
+        id: user.id,
+        user_name: user.username,
+        emails: vec![ScimEmail {
+            value: user.email,
+            email_type: Some("work".to_string()),
+            primary: true,
+        }],
+        active: user.is_active,
+        groups: roles
+            .into_iter()
+            .map(|r| ScimMemberRef {
+                value: r.id,
+                reference: format!("{base}/Groups/{}", r.id),
+                display: r.name,
+            })
+            .collect(),
+        meta: ScimMeta {
+            resource_type: "User",
+            created: user.created_at,
+            last_modified: user.updated_at,
+            location: format!("{base}/Users/{}", user.id),
+        },
+    }
+}
+
+async fn to_scim_group(repos: &Repositories, base: &str, role: Role) -> ScimResult<ScimGroup> {
+    let members = repos
+        .assignments
+        .get_role_members(role.id)
+        .await
+        .map_err(db_error)?;
+
+    Ok(ScimGroup {
+        schemas: vec![GROUP_SCHEMA],
+        id: role.id,
+        display_name: role.name,
+        members: members
+            .into_iter()
+            .map(|u| ScimMemberRef {
+                value: u.id,
+                reference: format!("{base}/Users/{}", u.id),
+                display: u.username,
+            })
+            .collect(),
+        meta: ScimMeta {
+            resource_type: "Group",
+            created: role.created_at,
+            // Roles have no modification timestamp
+            last_modified: role.created_at,
+            location: format!("{base}/Groups/{}", role.id),
+        },
+    })
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListQuery {
+    pub filter: Option<String>,
+    pub start_index: Option<usize>,
+    pub count: Option<usize>,
+}
+
+fn parse_filter(query: &ListQuery) -> ScimResult<Option<ScimFilter>> {
+    query
+        .filter
+        .as_deref()
+        .map(ScimFilter::parse)
+        .transpose()
+        .map_err(|e| ScimError::bad_request("invalidFilter", e))
+}
+
+/// The 1-based `startIndex` and the page size asked for, within bounds
+fn page_bounds(query: &ListQuery) -> (usize, usize) {
+    let start_index = query.start_index.unwrap_or(1).max(1);
+    let count = query.count.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+    (start_index, count)
+}
+
+/// Applies the filter and 1-based pagination to serialized resources. Only
+/// used for groups: they are roles, of which there are few.
+fn paginate<T: Serialize>(resources: Vec<T>, query: &ListQuery) -> ScimResult<ScimListResponse<T>> {
+    let filter = parse_filter(query)?;
+
+    let matching: Vec<T> = match filter {
+        Some(filter) => resources
+            .into_iter()
+            .filter(|r| serde_json::to_value(r).is_ok_and(|v| filter.matches(&v)))
+            .collect(),
+        None => resources,
+    };
+
+    let total_results = matching.len();
+    let (start_index, count) = page_bounds(query);
+
+    let page: Vec<T> = matching
+        .into_iter()
+        .skip(start_index - 1)
+        .take(count)
+        .collect();
+
+    Ok(ScimListResponse {
+        schemas: vec![LIST_RESPONSE_SCHEMA],
+        total_results,
+        start_index,
+        items_per_page: page.len(),
+        resources: page,
+    })
+}
+
+/// Translates a filter on SCIM Users into one the repositories evaluate in
+/// the database. Only the attributes users are stored with can be filtered on.
+fn user_filter(filter: &ScimFilter) -> Result<UserFilter, String> {
+    Ok(match filter {
+        ScimFilter::Compare { path, op, value } => {
+            compare_user_attribute(user_attribute(path)?, *op, value)
+                .ok_or_else(|| format!("Cannot compare '{}' with {value}", path.join(".")))?
+        }
+        // Every stored attribute is always set, except for the groups
+        ScimFilter::Present(path) => match user_attribute(path)? {
+            UserAttribute::RoleId | UserAttribute::RoleName => UserFilter::HasRoles,
+            _ => UserFilter::All(true),
+        },
+        ScimFilter::And(a, b) => {
+            UserFilter::And(Box::new(user_filter(a)?), Box::new(user_filter(b)?))
+        }
+        ScimFilter::Or(a, b) => {
+            UserFilter::Or(Box::new(user_filter(a)?), Box::new(user_filter(b)?))
+        }
+        ScimFilter::Not(inner) => UserFilter::Not(Box::new(user_filter(inner)?)),
+    })
+}
+
+fn user_attribute(path: &[String]) -> Result<UserAttribute, String> {
+    let path: Vec<&str> = path.iter().map(String::as_str).collect();
+    Ok(match path.as_slice() {
+        ["id"] => UserAttribute::Id,
+        ["username"] => UserAttribute::Username,
+        ["emails"] | ["emails", "value"] => UserAttribute::Email,
+        ["active"] => UserAttribute::Active,
+        ["groups"] | ["groups", "value"] => UserAttribute::RoleId,
+        ["groups", "display"] => UserAttribute::RoleName,
+        ["meta", "created"] => UserAttribute::CreatedAt,
+        ["meta", "lastmodified"] => UserAttribute::UpdatedAt,
+        _ => return Err(format!("Cannot filter on '{}'", path.join("."))),
+    })
+}
+
+/// None if the value or operator does not suit the attribute
+fn compare_user_attribute(
+    attribute: UserAttribute,
+    op: CompareOp,
+    value: &Value,
+) -> Option<UserFilter> {
+    let value = match attribute {
+        UserAttribute::Username | UserAttribute::Email | UserAttribute::RoleName => {
+            FilterValue::Text(value.as_str()?.to_string())
+        }
+        UserAttribute::Id | UserAttribute::RoleId => match (value.as_str()?.parse(), op) {
+            (Ok(id), CompareOp::Eq | CompareOp::Ne) => FilterValue::Uuid(id),
+            // Nothing has an id that is not a UUID
+            (Err(_), CompareOp::Eq) => return Some(UserFilter::All(false)),
+            (Err(_), CompareOp::Ne) if attribute == UserAttribute::Id => {
+                return Some(UserFilter::All(true))
+            }
+            (Err(_), CompareOp::Ne) => return Some(UserFilter::HasRoles),
+            _ => return None,
+        },
+        UserAttribute::Active => match (value, op) {
+            (Value::Bool(active), CompareOp::Eq | CompareOp::Ne) => FilterValue::Bool(*active),
+            _ => return None,
+        },
+        UserAttribute::CreatedAt | UserAttribute::UpdatedAt => {
+            if matches!(op, CompareOp::Co | CompareOp::Sw | CompareOp::Ew) {
+                return None;
+            }
+            let time = chrono::DateTime::parse_from_rfc3339(value.as_str()?).ok()?;
+            FilterValue::Time(time.with_timezone(&chrono::Utc))
+        }
+    };
+    Some(UserFilter::Compare(attribute, op, value))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PatchRequest {
+    #[serde(rename = "Operations")]
+    pub operations: Vec<PatchOperation>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PatchOperation {
+    pub op: String,
+    pub path: Option<String>,
+    pub value: Option<Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PatchOp {
+    Add,
+    Replace,
+    Remove,
+}
+
+impl PatchOperation {
+    fn kind(&self) -> ScimResult<PatchOp> {
+        match self.op.to_ascii_lowercase().as_str() {
+            "add" => Ok(PatchOp::Add),
+            "replace" => Ok(PatchOp::Replace),
+            "remove" => Ok(PatchOp::Remove),
+            other => Err(ScimError::bad_request(
+                "invalidSyntax",
+                format!("Unsupported patch operation '{other}'"),
+            )),
+        }
+    }
+}
+
+// Discovery endpoints
+
+pub async fn service_provider_config() -> HttpResponse {
+    scim_response(
+        StatusCode::OK,
+        &json!({
+            "schemas": ["urn:ietf:params:scim:schemas:core:2.0:ServiceProviderConfig"],
+            "patch": { "supported": true },
+            "bulk": { "supported": false, "maxOperations": 0, "maxPayloadSize": 0 },
+            "filter": { "supported": true, "maxResults": MAX_PAGE_SIZE },
+            "changePassword": { "supported": true },
+            "sort": { "supported": false },
+            "etag": { "supported": false },
+            "authenticationSchemes": [{
+                "type": "oauthbearertoken",
+                "name": "Bearer Token",
+                "description": "Static bearer token configured with SCIM_BEARER_TOKEN",
+                "primary": true
+            }],
+            "meta": { "resourceType": "ServiceProviderConfig" }
+        }),
+    )
+}
+
+fn attribute(name: &str, kind: &str, required: bool, mutability: &str, uniqueness: &str) -> Value {
+    json!({
+        "name": name,
+        "type": kind,
+        "multiValued": false,
+        "required": required,
+        "caseExact": false,
+        "mutability": mutability,
+        "returned": if name == "password" { "never" } else { "default" },
+        "uniqueness": uniqueness,
+    })
+}
+
+fn reference_list(name: &str, mutability: &str) -> Value {
+    json!({
+        "name": name,
+        "type": "complex",
+        "multiValued": true,
+        "required": false,
+        "mutability": mutability,
+        "returned": "default",
+        "subAttributes": [
+            attribute("value", "string", false, "immutable", "none"),
+            attribute("display", "string", false, "readOnly", "none"),
+            attribute("$ref", "reference", false, "immutable", "none"),
+        ],
+    })
+}
+
+fn user_schema() -> Value {
+    json!({
+        "schemas": ["urn:ietf:params:scim:schemas:core:2.0:Schema"],
+        "id": USER_SCHEMA,
+        "name": "User",
+        "description": "User Account",
+        "attributes": [
+            attribute("userName", "string", true, "readWrite", "server"),
+            attribute("active", "boolean", false, "readWrite", "none"),
+            attribute("password", "string", false, "writeOnly", "none"),
+            {
+                "name": "emails",
+                "type": "complex",
+                "multiValued": true,
+                "required": true,
+                "mutability": "readWrite",
+                "returned": "default",
+                "subAttributes": [
+                    attribute("value", "string", true, "readWrite", "server"),
+                    attribute("type", "string", false, "readWrite", "none"),
+                    attribute("primary", "boolean", false, "readWrite", "none"),
+                ],
+            },
+            reference_list("groups", "readOnly"),
+        ],
+        "meta": { "resourceType": "Schema", "location": format!("/scim/v2/Schemas/{USER_SCHEMA}") },
+    })
+}
+
+fn group_schema() -> Value {
+    json!({
+        "schemas": ["urn:ietf:params:scim:schemas:core:2.0:Schema"],
+        "id": GROUP_SCHEMA,
+        "name": "Group",
+        "description": "Group, mapped onto an auth-service role",
+        "attributes": [
+            attribute("displayName", "string", true, "readWrite", "server"),
+            reference_list("members", "readWrite"),
+        ],
+        "meta": { "resourceType": "Schema", "location": format!("/scim/v2/Schemas/{GROUP_SCHEMA}") },
+    })
+}
+
+pub async fn list_schemas() -> HttpResponse {
+    let schemas = vec![user_schema(), group_schema()];
+    scim_response(
+        StatusCode::OK,
+        &ScimListResponse {
+            schemas: vec![LIST_RESPONSE_SCHEMA],
+            total_results: schemas.len(),
+            start_index: 1,
+            items_per_page: schemas.len(),
+            resources: schemas,
+        },
+    )
+}
+
+pub async fn get_schema(path: web::Path<String>) -> ScimResult<HttpResponse> {
+    let id = path.into_inner();
+    let schema = match id.as_str() {
+        USER_SCHEMA => user_schema(),
+        GROUP_SCHEMA => group_schema(),
+        _ => return Err(ScimError::not_found(format!("Schema {id} not found"))),
+    };
+    Ok(scim_response(StatusCode::OK, &schema))
+}
+
+pub async fn list_resource_types() -> HttpResponse {
+    let types = vec![
+        json!({
+            "schemas": ["urn:ietf:params:scim:schemas:core:2.0:ResourceType"],
+            "id": "User",
+            "name": "User",
+            "endpoint": "/Users",
+            "schema": USER_SCHEMA,
+            "meta": { "resourceType": "ResourceType", "location": "/scim/v2/ResourceTypes/User" },
+        }),
+        json!({
+            "schemas": ["urn:ietf:params:scim:schemas:core:2.0:ResourceType"],
+            "id": "Group",
+            "name": "Group",
+            "endpoint": "/Groups",
+            "schema": GROUP_SCHEMA,
+            "meta": { "resourceType": "ResourceType", "location": "/scim/v2/ResourceTypes/Group" },
+        }),
+    ];
+    scim_response(
+        StatusCode::OK,
+        &ScimListResponse {
+            schemas: vec![LIST_RESPONSE_SCHEMA],
+            total_results: types.len(),
+            start_index: 1,
+            items_per_page: types.len(),
+            resources: types,
+        },
+    )
+}
+
+// User endpoints
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUserRequest {
+    pub user_name: String,
+    #[serde(default)]
+    pub emails: Vec<ScimEmail>,
+    pub active: Option<bool>,
+    pub password: Option<String>,
+}
+
+fn primary_email(emails: &[ScimEmail]) -> Option<&str> {
+    emails
+        .iter()
+        .find(|e| e.primary)
+        .or_else(|| emails.first())
+        .map(|e| e.value.as_str())
+}
+
+fn map_unique_violation(e: RepositoryError, detail: String) -> ScimError {
+    match e {
+        RepositoryError::UniqueViolation(_) => ScimError::conflict(detail),
+        e => db_error(e),
+    }
+}
+
+async fn find_user(repos: &Repositories, id: &str) -> ScimResult<User> {
+    let user_id = parse_id(id, "User")?;
+    repos
+        .users
+        .find_by_id(user_id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| ScimError::not_found(format!("User {user_id} not found")))
+}
+
+pub async fn list_users(
+    repos: web::Data<Repositories>,
+    http_req: HttpRequest,
+    query: web::Query<ListQuery>,
+) -> ScimResult<HttpResponse> {
+    let base = base_url(&http_req);
+    let filter = match parse_filter(&query)? {
+        Some(filter) => {
+            user_filter(&filter).map_err(|e| ScimError::bad_request("invalidFilter", e))?
+        }
+        None => UserFilter::All(true),
+    };
+    let (start_index, count) = page_bounds(&query);
+
+    let (users, total_results) = repos
+        .provisioning
+        .search(&filter, start_index as i64 - 1, count as i64)
+        .await
+        .map_err(db_error)?;
+    let user_ids: Vec<Uuid> = users.iter().map(|u| u.id).collect();
+    let mut roles = repos
+        .provisioning
+        .roles_of(&user_ids)
+        .await
+        .map_err(db_error)?;
+    let resources: Vec<ScimUser> = users
+        .into_iter()
+        .map(|user| {
+            let user_roles = roles.remove(&user.id).unwrap_or_default();
+            scim_user(&base, user, user_roles)
+        })
+        .collect();
+
+    let list = ScimListResponse {
+        schemas: vec![LIST_RESPONSE_SCHEMA],
+        total_results: total_results as usize,
+        start_index,
+        items_per_page: resources.len(),
+        resources,
+    };
+    Ok(scim_response(StatusCode::OK, &list))
+}
+
+pub async fn get_user(
+    repos: web::Data<Repositories>,
+    http_req: HttpRequest,
+    path: web::Path<String>,
+) -> ScimResult<HttpResponse> {
+    let user = find_user(&repos, &path).await?;
+    let resource = to_scim_user(&repos, &base_url(&http_req), user).await?;
+    Ok(scim_response(StatusCode::OK, &resource))
+}
+
+pub async fn create_user(
+    repos: web::Data<Repositories>,
+    http_req: HttpRequest,
+    req: web::Json<ScimUserRequest>,
+) -> ScimResult<HttpResponse> {
+    if req.user_name.is_empty() {
+        return Err(ScimError::bad_request(
+            "invalidValue",
+            "userName is required",
+        ));
+    }
+    let email = primary_email(&req.emails)
+        .ok_or_else(|| ScimError::bad_request("invalidValue", "An email address is required"))?;
+
+    // Provisioned users without a password get an unguessable one; they are
+    // expected to sign in through the identity provider or a password reset
+    let password = req.password.clone().unwrap_or_else(generate_opaque_token);
+    let password_hash = hash_password(&password)
+        .map_err(|e| ScimError::internal(format!("Failed to hash password: {e}")))?;
+
+    let account = NewAccount::User {
+        username: &req.user_name,
+        email,
+        password_hash: &password_hash,
+        is_active: req.active != Some(false),
+        role_ids: &[],
+    };
+    let ids = repos.provisioning.import(&[account]).await.map_err(|e| {
+        map_unique_violation(e.source, "userName or email already exists".to_string())
+    })?;
+    let user = repos
+        .users
+        .find_by_id(ids[0])
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| ScimError::internal("User disappeared after creation"))?;
+
+    let resource = to_scim_user(&repos, &base_url(&http_req), user).await?;
+    Ok(scim_response(StatusCode::CREATED, &resource))
+}
+
+pub async fn replace_user(
+    repos: web::Data<Repositories>,
+    http_req: HttpRequest,
+    path: web::Path<String>,
+    req: web::Json<ScimUserRequest>,
+) -> ScimResult<HttpResponse> {
+    let user = find_user(&repos, &path).await?;
+
+    if req.user_name.is_empty() {
+        return Err(ScimError::bad_request(
+            "invalidValue",
+            "userName is required",
+        ));
+    }
+    let email = primary_email(&req.emails)
+        .ok_or_else(|| ScimError::bad_request("invalidValue", "An email address is required"))?;
+
+    let password = req
+        .password
+        .as_deref()
+        .map(hash_password)
+        .transpose()
+        .map_err(|e| ScimError::internal(format!("Failed to hash password: {e}")))?;
+
+    let update = UpdateUser {
+        username: Some(req.user_name.clone()),
+        email: Some(email.to_string()),
+        password,
+        is_active: Some(req.active.unwrap_or(true)),
+    };
+
+    let user = update_user(&repos, &user, &update).await?;
+    revoke_sessions_if_inactive(&repos, &user).await?;
+
+    let resource = to_scim_user(&repos, &base_url(&http_req), user).await?;
+    Ok(scim_response(StatusCode::OK, &resource))
+}
+
+fn parse_bool(value: &Value) -> ScimResult<bool> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        // Some identity providers send booleans as strings
+        Value::String(s) if s.eq_ignore_ascii_case("true") => Ok(true),
+        Value::String(s) if s.eq_ignore_ascii_case("false") => Ok(false),
+        _ => Err(ScimError::bad_request("invalidValue", "Expected a boolean")),
+    }
+}
+
+fn parse_string(value: &Value, attr: &str) -> ScimResult<String> {
+    value
+        .as_str()
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .ok_or_else(|| ScimError::bad_request("invalidValue", format!("{attr} must be a string")))
+}
+
+/// Applies a single attribute assignment from a PATCH operation
+fn apply_user_attribute(update: &mut UpdateUser, attr: &str, value: &Value) -> ScimResult<()> {
+    let attr = normalize_attr(attr);
+    match attr.as_str() {
+        "username" => update.username = Some(parse_string(value, "userName")?),
+        "active" => update.is_active = Some(parse_bool(value)?),
+        "password" => {
+            let password = parse_string(value, "password")?;
+            update.password = Some(
+                hash_password(&password)
+                    .map_err(|e| ScimError::internal(format!("Failed to hash password: {e}")))?,
+            );
+        }
+        // `emails`, `emails.value` and `emails[type eq "work"].value`
+        a if a.starts_with("emails") => {
+            let email = match value {
+                Value::String(s) => s.clone(),
+                Value::Array(_) => {
+                    let emails: Vec<ScimEmail> = serde_json::from_value(value.clone())
+                        .map_err(|e| ScimError::bad_request("invalidValue", e.to_string()))?;
+                    primary_email(&emails)
+                        .ok_or_else(|| {
+                            ScimError::bad_request("invalidValue", "An email address is required")
+                        })?
+                        .to_string()
+                }
+                Value::Object(_) => {
+                    let email: ScimEmail = serde_json::from_value(value.clone())
+                        .map_err(|e| ScimError::bad_request("invalidValue", e.to_string()))?;
+                    email.value
+                }
+                _ => {
+                    return Err(ScimError::bad_request(
+                        "invalidValue",
+                        "Invalid email value",
+                    ))
+                }
+            };
+            update.email = Some(email);
+        }
+        // Read-only or unsupported attributes are ignored, as permitted by RFC 7644
+        _ => {}
+    }
+    Ok(())
+}
+
+pub async fn patch_user(
+    repos: web::Data<Repositories>,
+    http_req: HttpRequest,
+    path: web::Path<String>,
+    req: web::Json<PatchRequest>,
+) -> ScimResult<HttpResponse> {
+    let user = find_user(&repos, &path).await?;
+
+    let mut update = UpdateUser {
+        username: None,
+        email: None,
+        password: None,
+        is_active: None,
+    };
+
+    for operation in &req.operations {
+        match operation.kind()? {
+            PatchOp::Add | PatchOp::Replace => {
+                let value = operation.value.as_ref().ok_or_else(|| {
+                    ScimError::bad_request("invalidValue", "Patch operation requires a value")
+                })?;
+                match operation.path.as_deref() {
+                    Some(path) => apply_user_attribute(&mut update, path, value)?,
+                    None => {
+                        let attributes = value.as_object().ok_or_else(|| {
+                            ScimError::bad_request(
+                                "invalidValue",
+                                "Patch operation without a path requires an object value",
+                            )
+                        })?;
+                        for (attr, value) in attributes {
+                            apply_user_attribute(&mut update, attr, value)?;
+                        }
+                    }
+                }
+            }
+            PatchOp::Remove => {
+                return Err(ScimError::bad_request(
+                    "mutability",
+                    "User attributes cannot be removed",
+                ))
+            }
+        }
+    }
+
+    let user = update_user(&repos, &user, &update).await?;
+    revoke_sessions_if_inactive(&repos, &user).await?;
+
+    let resource = to_scim_user(&repos, &base_url(&http_req), user).await?;
+    Ok(scim_response(StatusCode::OK, &resource))
+}
+
+/// Applies `update` to `before`; the repository records an activation event
+/// if it flips `active`
+async fn update_user(repos: &Repositories, before: &User, update: &UpdateUser) -> ScimResult<User> {
+    repos
+        .users
+        .update(before.id, update, None)
+        .await
+        .map_err(|e| map_unique_violation(e, "userName or email already exists".to_string()))?
+        .ok_or_else(|| ScimError::not_found(format!("User {} not found", before.id)))
+}
+
+/// A deactivated user is signed out everywhere, as through the admin API
+async fn revoke_sessions_if_inactive(repos: &Repositories, user: &User) -> ScimResult<()> {
+    if !user.is_active {
+        repos
+            .sessions
+            .revoke_all_for_user(user.id, None)
+            .await
+            .map_err(db_error)?;
+    }
+    Ok(())
+}
+
+pub async fn delete_user(
+    repos: web::Data<Repositories>,
+    path: web::Path<String>,
+) -> ScimResult<HttpResponse> {
+    let user_id = parse_id(&path, "User")?;
+
+    let deleted = repos.users.delete(user_id, None).await.map_err(db_error)?;
+    if !deleted {
+        return Err(ScimError::not_found(format!("User {user_id} not found")));
+    }
+
+    repos
+        .sessions
+        .revoke_all_for_user(user_id, None)
+        .await
+        .map_err(db_error)?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Group endpoints
+
+#[derive(Debug, Deserialize)]
+pub struct ScimMemberInput {
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimGroupRequest {
+    pub display_name: String,
+    #[serde(default)]
+    pub members: Vec<ScimMemberInput>,
+}
+
+fn ensure_renamable(role: &Role, new_name: &str) -> ScimResult<()> {
+    if role.name != new_name && BUILTIN_ROLES.contains(&role.name.as_str()) {
+        return Err(ScimError::bad_request(
+            "mutability",
+            format!("Built-in group '{}' cannot be renamed", role.name),
+        ));
+    }
+    Ok(())
+}
+
+async fn find_group(repos: &Repositories, id: &str) -> ScimResult<Role> {
+    let role_id = parse_id(id, "Group")?;
+    repos
+        .roles
+        .find_by_id(role_id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| ScimError::not_found(format!("Group {role_id} not found")))
+}
+
+/// Parses member references and verifies that every referenced user exists
+async fn resolve_members(
+    repos: &Repositories,
+    members: &[ScimMemberInput],
+) -> ScimResult<Vec<Uuid>> {
+    let ids = members
+        .iter()
+        .map(|m| {
+            m.value.parse::<Uuid>().map_err(|_| {
+                ScimError::bad_request("invalidValue", format!("Invalid member id '{}'", m.value))
+            })
+        })
+        .collect::<ScimResult<Vec<Uuid>>>()?;
+
+    let found = repos.users.find_by_ids(&ids).await.map_err(db_error)?;
+    if let Some(missing) = ids.iter().find(|id| !found.iter().any(|u| u.id == **id)) {
+        return Err(ScimError::bad_request(
+            "invalidValue",
+            format!("User {missing} not found"),
+        ));
+    }
+
+    Ok(ids)
+}
+
+fn parse_members(value: &Value) -> ScimResult<Vec<ScimMemberInput>> {
+    match value {
+        Value::Array(_) => serde_json::from_value(value.clone())
+            .map_err(|e| ScimError::bad_request("invalidValue", e.to_string())),
+        Value::Object(_) => serde_json::from_value(value.clone())
+            .map(|m| vec![m])
+            .map_err(|e| ScimError::bad_request("invalidValue", e.to_string())),
+        _ => Err(ScimError::bad_request(
+            "invalidValue",
+            "Invalid members value",
+        )),
+    }
+}
+
+pub async fn list_groups(
+    repos: web::Data<Repositories>,
+    http_req: HttpRequest,
+    query: web::Query<ListQuery>,
+) -> ScimResult<HttpResponse> {
+    let base = base_url(&http_req);
+    let roles = repos.roles.list().await.map_err(db_error)?;
+
+    let mut resources = Vec::with_capacity(roles.len());
+    for role in roles {
+        resources.push(to_scim_group(&repos, &base, role).await?);
+    }
+
+    let list = paginate(resources, &query)?;
+    Ok(scim_response(StatusCode::OK, &list))
+}
+
+pub async fn get_group(
+    repos: web::Data<Repositories>,
+    http_req: HttpRequest,
+    path: web::Path<String>,
+) -> ScimResult<HttpResponse> {
+    let role = find_group(&repos, &path).await?;
+    let resource = to_scim_group(&repos, &base_url(&http_req), role).await?;
+    Ok(scim_response(StatusCode::OK, &resource))
+}
+
+pub async fn create_group(
+    repos: web::Data<Repositories>,
+    http_req: HttpRequest,
+    req: web::Json<ScimGroupRequest>,
+) -> ScimResult<HttpResponse> {
+    if req.display_name.is_empty() {
+        return Err(ScimError::bad_request(
+            "invalidValue",
+            "displayName is required",
+        ));
+    }
+    let member_ids = resolve_members(&repos, &req.members).await?;
+
+    let role = repos
+        .roles
+        .create(&req.display_name, None)
+        .await
+        .map_err(|e| {
+            map_unique_violation(e, format!("Group '{}' already exists", req.display_name))
+        })?;
+    repos
+        .assignments
+        .set_role_members(role.id, &member_ids)
+        .await
+        .map_err(db_error)?;
+
+    let resource = to_scim_group(&repos, &base_url(&http_req), role).await?;
+    Ok(scim_response(StatusCode::CREATED, &resource))
+}
+
+pub async fn replace_group(
+    repos: web::Data<Repositories>,
+    http_req: HttpRequest,
+    path: web::Path<String>,
+    req: web::Json<ScimGroupRequest>,
+) -> ScimResult<HttpResponse> {
+    let role = find_group(&repos, &path).await?;
+
+    if req.display_name.is_empty() {
+        return Err(ScimError::bad_request(
+            "invalidValue",
+            "displayName is required",
+        ));
+    }
+    ensure_renamable(&role, &req.display_name)?;
+    let member_ids = resolve_members(&repos, &req.members).await?;
+
+    let role = rename_group(&repos, role, &req.display_name).await?;
+    repos
+        .assignments
+        .set_role_members(role.id, &member_ids)
+        .await
+        .map_err(db_error)?;
+
+    let resource = to_scim_group(&repos, &base_url(&http_req), role).await?;
+    Ok(scim_response(StatusCode::OK, &resource))
+}
+
+pub async fn patch_group(
+    repos: web::Data<Repositories>,
+    http_req: HttpRequest,
+    path: web::Path<String>,
+    req: web::Json<PatchRequest>,
+) -> ScimResult<HttpResponse> {
+    let role = find_group(&repos, &path).await?;
+
+    // The operations apply all together or not at all: they are worked out
+    // against a copy of the group, which is written once every one is valid
+    let mut name = role.name.clone();
+    let mut members: Vec<Uuid> = repos
+        .assignments
+        .get_role_members(role.id)
+        .await
+        .map_err(db_error)?
+        .into_iter()
+        .map(|u| u.id)
+        .collect();
+
+    for operation in &req.operations {
+        let kind = operation.kind()?;
+        let path = operation.path.as_deref().map(normalize_attr);
+
+        // Expand a path-less operation into one operation per attribute
+        let assignments: Vec<(Option<String>, Option<&Value>)> = match (&path, &operation.value) {
+            (None, Some(Value::Object(attributes))) => attributes
+                .iter()
+                .map(|(attr, value)| (Some(normalize_attr(attr)), Some(value)))
+                .collect(),
+            _ => vec![(path.clone(), operation.value.as_ref())],
+        };
+
+        for (attr, value) in assignments {
+            let attr = attr.unwrap_or_default();
+            match (kind, attr.as_str(), value) {
+                (PatchOp::Add | PatchOp::Replace, "displayname", Some(value)) => {
+                    name = parse_string(value, "displayName")?;
+                    ensure_renamable(&role, &name)?;
+                }
+                (PatchOp::Add, "members", Some(value)) => {
+                    for id in resolve_members(&repos, &parse_members(value)?).await? {
+                        if !members.contains(&id) {
+                            members.push(id);
+                        }
+                    }
+                }
+                (PatchOp::Replace, "members", Some(value)) => {
+                    members = resolve_members(&repos, &parse_members(value)?).await?;
+                }
+                (PatchOp::Remove, "members", None) => members.clear(),
+                (PatchOp::Remove, "members", Some(value)) => {
+                    let ids = parse_members(value)?
+                        .iter()
+                        .filter_map(|m| m.value.parse::<Uuid>().ok())
+                        .collect::<Vec<_>>();
+                    members.retain(|id| !ids.contains(id));
+                }
+                // members[value eq "..."]
+                (PatchOp::Remove, a, _) if a.starts_with("members[") && a.ends_with(']') => {
+                    let original = operation.path.as_deref().unwrap_or_default();
+                    let expr = &original[original.find('[').unwrap_or(0) + 1..original.len() - 1];
+                    let filter = ScimFilter::parse(expr)
+                        .map_err(|e| ScimError::bad_request("invalidFilter", e))?;
+                    let ids: Vec<Uuid> = repos
+                        .users
+                        .find_by_ids(&members)
+                        .await
+                        .map_err(db_error)?
+                        .into_iter()
+                        .filter(|u| {
+                            filter.matches(&json!({ "value": u.id, "display": u.username }))
+                        })
+                        .map(|u| u.id)
+                        .collect();
+                    members.retain(|id| !ids.contains(id));
+                }
+                (_, "", _) => {
+                    return Err(ScimError::bad_request(
+                        "noTarget",
+                        "Patch operation requires a path or an object value",
+                    ))
+                }
+                (_, other, _) => {
+                    return Err(ScimError::bad_request(
+                        "invalidPath",
+                        format!("Unsupported patch path '{other}'"),
+                    ))
+                }
+            }
+        }
+    }
+
+    // Rename first, so that a name clash leaves the members untouched
+    let role = rename_group(&repos, role, &name).await?;
+    repos
+        .assignments
+        .set_role_members(role.id, &members)
+        .await
+        .map_err(db_error)?;
+
+    let resource = to_scim_group(&repos, &base_url(&http_req), role).await?;
+    Ok(scim_response(StatusCode::OK, &resource))
+}
+
+pub async fn delete_group(
+    repos: web::Data<Repositories>,
+    path: web::Path<String>,
+) -> ScimResult<HttpResponse> {
+    let role = find_group(&repos, &path).await?;
+
+    if BUILTIN_ROLES.contains(&role.name.as_str()) {
+        return Err(ScimError::bad_request(
+            "mutability",
+            format!("Built-in group '{}' cannot be deleted", role.name),
+        ));
+    }
+
+    // Deleting the role records a removal event for each of its members
+    repos.roles.delete(role.id, None).await.map_err(db_error)?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Renames the role behind a group, unless it already has that name
+async fn rename_group(repos: &Repositories, role: Role, name: &str) -> ScimResult<Role> {
+    if role.name == name {
+        return Ok(role);
+    }
+    repos
+        .roles
+        .update(role.id, name, role.description.as_deref(), None)
+        .await
+        .map_err(|e| map_unique_violation(e, format!("Group '{name}' already exists")))?
+        .ok_or_else(|| ScimError::not_found(format!("Group {} not found", role.id)))
+}
@@ -0,0 +1,332 @@
+use crate::handlers::admin::AssignRoleRequest;
+use crate::models::{NewCredential, ServiceAccount, ServiceAccountCredential};
+use crate::repositories::{Repositories, RepositoryError};
+use crate::services::client_assertion::public_key;
+use crate::services::{generate_opaque_token, hash_opaque_token};
+use actix_web::{web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use shared::{ApiResponse, AppError, AppResult, Claims};
+use uuid::Uuid;
+
+#[derive(Debug, Serialize)]
+pub struct ServiceAccountResponse {
+    #[serde(flatten)]
+    pub account: ServiceAccount,
+    pub roles: Vec<String>,
+    pub credentials: Vec<ServiceAccountCredential>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateServiceAccountRequest {
+    pub name: String,
+    pub description: Option<String>,
+    /// Defaults to the admin creating the account
+    pub owner_id: Option<Uuid>,
+}
+
+fn not_found(id: Uuid) -> AppError {
+    AppError::NotFound(format!("Service account with id {id} not found"))
+}
+
+fn write_error(e: RepositoryError, action: &str) -> AppError {
+    match e {
+        e if e.is_unique_violation_on("service_accounts.name") => {
+            AppError::Conflict("Service account name already exists".to_string())
+        }
+        RepositoryError::ForeignKeyViolation(_) => {
+            AppError::BadRequest("owner_id does not name an existing user".to_string())
+        }
+        e => AppError::Internal(format!("Failed to {action} service account: {e}")),
+    }
+}
+
+async fn find_account(repos: &Repositories, id: Uuid) -> AppResult<ServiceAccount> {
+    repos
+        .service_accounts
+        .find_by_id(id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get service account: {e}")))?
+        .ok_or_else(|| not_found(id))
+}
+
+pub(crate) async fn account_response(
+    repos: &Repositories,
+    account: ServiceAccount,
+) -> AppResult<ServiceAccountResponse> {
+    let roles = repos
+        .service_accounts
+        .get_roles(account.id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get service account roles: {e}")))?;
+    let credentials = repos
+        .service_accounts
+        .list_credentials(account.id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get credentials: {e}")))?;
+
+    Ok(ServiceAccountResponse {
+        account,
+        roles: roles.into_iter().map(|r| r.name).collect(),
+        credentials,
+    })
+}
+
+pub async fn create_service_account(
+    repos: web::Data<Repositories>,
+    claims: web::ReqData<Claims>,
+    req: web::Json<CreateServiceAccountRequest>,
+) -> AppResult<impl Responder> {
+    let name = req.name.trim();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("name is required".to_string()));
+    }
+
+    let owner_id = req
+        .owner_id
+        .or_else(|| (!claims.is_service()).then_some(claims.sub));
+
+    let account = repos
+        .service_accounts
+        .create(name, req.description.as_deref(), owner_id)
+        .await
+        .map_err(|e| write_error(e, "create"))?;
+
+    let response = account_response(&repos, account).await?;
+    Ok(HttpResponse::Created().json(ApiResponse::new(response)))
+}
+
+pub async fn list_service_accounts(repos: web::Data<Repositories>) -> AppResult<impl Responder> {
+    let accounts = repos
+        .service_accounts
+        .list()
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to list service accounts: {e}")))?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::new(accounts)))
+}
+
+pub async fn get_service_account(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+) -> AppResult<impl Responder> {
+    let account = find_account(&repos, path.into_inner()).await?;
+    let response = account_response(&repos, account).await?;
+    Ok(HttpResponse::Ok().json(ApiResponse::new(response)))
+}
+
+/// `PATCH /admin/service-accounts/{id}` with a JSON Merge Patch (RFC 7396)
+/// document. Deactivating the account cuts off the tokens it holds.
+pub async fn patch_service_account(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+    patch: web::Json<Value>,
+) -> AppResult<impl Responder> {
+    let account = find_account(&repos, path.into_inner()).await?;
+
+    let Value::Object(members) = patch.into_inner() else {
+        return Err(AppError::BadRequest(
+            "A merge patch must be a JSON object".to_string(),
+        ));
+    };
+
+    let mut description = account.description;
+    let mut owner_id = account.owner_id;
+    let mut is_active = account.is_active;
+    for (name, value) in members {
+        match (name.as_str(), value) {
+            ("description", Value::Null) => description = None,
+            ("description", Value::String(v)) => description = Some(v),
+            ("owner_id", Value::Null) => owner_id = None,
+            ("owner_id", Value::String(v)) => {
+                let id = v
+                    .parse()
+                    .map_err(|_| AppError::BadRequest(format!("Invalid value for '{name}'")))?;
+                owner_id = Some(id);
+            }
+            ("is_active", Value::Bool(v)) => is_active = v,
+            ("is_active", Value::Null) => {
+                return Err(AppError::BadRequest(format!("'{name}' cannot be removed")))
+            }
+            ("description" | "owner_id" | "is_active", _) => {
+                return Err(AppError::BadRequest(format!("Invalid value for '{name}'")))
+            }
+            _ => return Err(AppError::BadRequest(format!("'{name}' cannot be changed"))),
+        }
+    }
+
+    let account = repos
+        .service_accounts
+        .update(account.id, description.as_deref(), owner_id, is_active)
+        .await
+        .map_err(|e| write_error(e, "update"))?
+        .ok_or_else(|| not_found(account.id))?;
+
+    let response = account_response(&repos, account).await?;
+    Ok(HttpResponse::Ok().json(ApiResponse::new(response)))
+}
+
+/// Deletes the account with its credentials; its tokens stop working
+pub async fn delete_service_account(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+) -> AppResult<impl Responder> {
+    let id = path.into_inner();
+
+    let deleted = repos
+        .service_accounts
+        .delete(id)
+        .await
+        .map_err(|e| write_error(e, "delete"))?;
+
+    if !deleted {
+        return Err(not_found(id));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub async fn assign_role_to_service_account(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+    req: web::Json<AssignRoleRequest>,
+) -> AppResult<impl Responder> {
+    let account = find_account(&repos, path.into_inner()).await?;
+    let role_id = req.role_id;
+
+    match repos
+        .service_accounts
+        .assign_role(account.id, role_id)
+        .await
+    {
+        Ok(()) => {}
+        // The account was found above, so the role is what is missing
+        Err(RepositoryError::ForeignKeyViolation(_)) => {
+            return Err(AppError::NotFound(format!(
+                "Role with id {role_id} not found"
+            )))
+        }
+        Err(e) => return Err(AppError::Internal(format!("Failed to assign role: {e}"))),
+    }
+
+    Ok(HttpResponse::Created().json(ApiResponse::with_message(
+        (),
+        "Role assigned to service account successfully".to_string(),
+    )))
+}
+
+pub async fn remove_role_from_service_account(
+    repos: web::Data<Repositories>,
+    path: web::Path<(Uuid, Uuid)>,
+) -> AppResult<impl Responder> {
+    let (id, role_id) = path.into_inner();
+
+    let removed = repos
+        .service_accounts
+        .remove_role(id, role_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to remove role: {e}")))?;
+
+    if !removed {
+        return Err(AppError::NotFound(
+            "Service account role assignment not found".to_string(),
+        ));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NewCredentialRequest {
+    /// A client secret, generated by the server
+    Secret,
+    /// A public key for signed JWT assertions
+    PublicKey {
+        public_key: String,
+        algorithm: String,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateCredentialRequest {
+    #[serde(flatten)]
+    pub credential: NewCredentialRequest,
+    /// Never expires when not given
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedCredentialResponse {
+    #[serde(flatten)]
+    pub credential: ServiceAccountCredential,
+    /// The client secret; it cannot be retrieved again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+pub async fn create_credential(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+    req: web::Json<CreateCredentialRequest>,
+) -> AppResult<impl Responder> {
+    let account = find_account(&repos, path.into_inner()).await?;
+
+    let expires_at = match req.expires_in_days {
+        Some(days) if days < 1 => {
+            return Err(AppError::BadRequest(
+                "expires_in_days must be positive".to_string(),
+            ))
+        }
+        Some(days) => Some(chrono::Utc::now() + chrono::Duration::days(days)),
+        None => None,
+    };
+
+    let mut secret = None;
+    let secret_hash;
+    let new_credential = match &req.credential {
+        NewCredentialRequest::Secret => {
+            let generated = generate_opaque_token();
+            secret_hash = hash_opaque_token(&generated);
+            secret = Some(generated);
+            NewCredential::Secret { hash: &secret_hash }
+        }
+        NewCredentialRequest::PublicKey {
+            public_key: pem,
+            algorithm,
+        } => {
+            public_key(pem, algorithm).map_err(AppError::BadRequest)?;
+            NewCredential::PublicKey { pem, algorithm }
+        }
+    };
+
+    let credential = repos
+        .service_accounts
+        .add_credential(account.id, new_credential, expires_at)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to add credential: {e}")))?;
+
+    let response = CreatedCredentialResponse { credential, secret };
+    Ok(HttpResponse::Created().json(ApiResponse::new(response)))
+}
+
+pub async fn delete_credential(
+    repos: web::Data<Repositories>,
+    path: web::Path<(Uuid, Uuid)>,
+) -> AppResult<impl Responder> {
+    let (id, credential_id) = path.into_inner();
+
+    let deleted = repos
+        .service_accounts
+        .delete_credential(id, credential_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to delete credential: {e}")))?;
+
+    if !deleted {
+        return Err(AppError::NotFound(format!(
+            "Service account {id} has no credential with id {credential_id}"
+        )));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,573 @@
+//! The OAuth 2.0 token endpoint (RFC 6749 section 3.2), through which
+//! service accounts get access tokens, either their own or, by token
+//! exchange (RFC 8693), ones to act on behalf of another principal, and
+//! devices collect the user tokens of the device authorization grant.
+
+use crate::config::Config;
+use crate::handlers::auth::{apply_token_format, load_permissions, start_session};
+use crate::handlers::device::{DEVICE_CODE_GRANT_TYPE, DEVICE_SLOW_DOWN_SECONDS};
+use crate::models::{
+    CredentialKind, DeviceAuthorizationStatus, ServiceAccount, ServiceAccountCredential,
+};
+use crate::repositories::Repositories;
+use crate::services::client_assertion::{
+    read_assertion, verify_assertion, JWT_BEARER_ASSERTION_TYPE,
+};
+use crate::services::{
+    create_exchanged_claims, create_service_claims, generate_token, hash_opaque_token,
+    is_token_active, validate_token_with_previous, SERVICE_TOKEN_TTL_SECONDS,
+};
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse, ResponseError};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use shared::{Actor, AppError, PrincipalType};
+use uuid::Uuid;
+
+/// An error response as defined by RFC 6749 section 5.2
+#[derive(Debug, thiserror::Error)]
+#[error("{error}: {description}")]
+pub struct OAuthError {
+    error: &'static str,
+    description: String,
+}
+
+impl OAuthError {
+    fn new(error: &'static str, description: impl Into<String>) -> Self {
+        Self {
+            error,
+            description: description.into(),
+        }
+    }
+
+    pub fn invalid_request(description: impl Into<String>) -> Self {
+        Self::new("invalid_request", description)
+    }
+
+    pub fn invalid_client(description: impl Into<String>) -> Self {
+        Self::new("invalid_client", description)
+    }
+
+    pub fn invalid_grant(description: impl Into<String>) -> Self {
+        Self::new("invalid_grant", description)
+    }
+
+    pub fn invalid_scope(description: impl Into<String>) -> Self {
+        Self::new("invalid_scope", description)
+    }
+
+    pub fn unauthorized_client(description: impl Into<String>) -> Self {
+        Self::new("unauthorized_client", description)
+    }
+
+    pub fn unsupported_grant_type(description: impl Into<String>) -> Self {
+        Self::new("unsupported_grant_type", description)
+    }
+
+    /// Device flow (RFC 8628 section 3.5): the user has not decided yet
+    pub fn authorization_pending() -> Self {
+        Self::new(
+            "authorization_pending",
+            "The user has not yet approved the request",
+        )
+    }
+
+    /// Device flow: the device polled too soon and must wait longer
+    pub fn slow_down(interval_seconds: i32) -> Self {
+        Self::new(
+            "slow_down",
+            format!("Polling too fast; wait {interval_seconds} seconds between requests"),
+        )
+    }
+
+    /// Device flow: the user denied the request
+    pub fn access_denied() -> Self {
+        Self::new("access_denied", "The user denied the request")
+    }
+
+    /// Device flow: the device code expired before the user decided
+    pub fn expired_token() -> Self {
+        Self::new("expired_token", "The device code has expired")
+    }
+}
+
+impl From<AppError> for OAuthError {
+    /// Errors about the request keep their message; anything else is logged
+    /// and answered with a fixed description, so no internals reach clients
+    fn from(e: AppError) -> Self {
+        match e {
+            AppError::Unauthorized(description) => Self::invalid_client(description),
+            AppError::BadRequest(description) | AppError::Validation(description, _) => {
+                Self::invalid_request(description)
+            }
+            e => {
+                log::error!("Token request failed: {e}");
+                Self::new("server_error", "The request could not be completed")
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct OAuthErrorBody<'a> {
+    error: &'a str,
+    error_description: &'a str,
+}
+
+impl ResponseError for OAuthError {
+    fn status_code(&self) -> StatusCode {
+        match self.error {
+            "invalid_client" => StatusCode::UNAUTHORIZED,
+            "server_error" => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code())
+            .insert_header(("Cache-Control", "no-store"))
+            .json(OAuthErrorBody {
+                error: self.error,
+                error_description: &self.description,
+            })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub client_assertion_type: Option<String>,
+    pub client_assertion: Option<String>,
+    /// Token exchange: the token to act on behalf of
+    pub subject_token: Option<String>,
+    pub subject_token_type: Option<String>,
+    pub requested_token_type: Option<String>,
+    /// Token exchange: the service the new token is for
+    pub audience: Option<String>,
+    /// Token exchange: space-separated permissions to keep
+    pub scope: Option<String>,
+    /// Device flow: the code from the device authorization response
+    pub device_code: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    /// Set for token exchange
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issued_token_type: Option<String>,
+    pub token_type: String,
+    pub expires_in: i64,
+    /// Set for token exchange: the permissions the token holds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+}
+
+/// `grant_type` of token exchange (RFC 8693)
+pub const TOKEN_EXCHANGE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+
+/// Token type of the access tokens this service issues
+pub const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+
+/// Token type of any JWT; the access tokens issued here are JWTs too
+pub const JWT_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:jwt";
+
+/// `POST /auth/token`, also served as `/oauth/token`. Supports the
+/// `client_credentials` grant, and token exchange for a service to act on
+/// behalf of the subject of a token it was given; for both the client
+/// authenticates with a secret (HTTP Basic or form parameters) or a signed
+/// JWT assertion. Devices, which are public clients, poll it with the
+/// device code grant.
+pub async fn token(
+    repos: web::Data<Repositories>,
+    config: web::Data<Config>,
+    http_req: HttpRequest,
+    req: web::Form<TokenRequest>,
+) -> Result<HttpResponse, OAuthError> {
+    let response = match req.grant_type.as_str() {
+        "client_credentials" => {
+            let account = authenticate_client(&repos, &config, &http_req, &req).await?;
+            client_credentials(&repos, &config, account).await?
+        }
+        TOKEN_EXCHANGE_GRANT_TYPE => {
+            let account = authenticate_client(&repos, &config, &http_req, &req).await?;
+            exchange_token(&repos, &config, account, &req).await?
+        }
+        DEVICE_CODE_GRANT_TYPE => device_code(&repos, &config, &http_req, &req).await?,
+        other => {
+            return Err(OAuthError::unsupported_grant_type(format!(
+                "grant_type '{other}' is not supported"
+            )))
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-store"))
+        .json(response))
+}
+
+async fn client_credentials(
+    repos: &Repositories,
+    config: &Config,
+    account: ServiceAccount,
+) -> Result<TokenResponse, OAuthError> {
+    let roles = repos
+        .service_accounts
+        .get_roles(account.id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get service account roles: {e}")))?;
+    let permissions = load_permissions(repos, &roles).await?;
+    let mut claims = create_service_claims(
+        account.id,
+        account.name,
+        roles.into_iter().map(|r| r.name).collect(),
+        permissions,
+    );
+    apply_token_format(repos, config, &mut claims).await?;
+    let access_token = generate_token(&claims, &config.jwt_secret)
+        .map_err(|e| AppError::Internal(format!("Failed to generate token: {e}")))?;
+
+    Ok(TokenResponse {
+        access_token,
+        issued_token_type: None,
+        token_type: "Bearer".to_string(),
+        expires_in: SERVICE_TOKEN_TTL_SECONDS,
+        scope: None,
+    })
+}
+
+/// Token exchange (RFC 8693): `account` presents a token it received and
+/// gets one to act for that token's subject at `audience`, holding the
+/// permissions asked for in `scope` (by default all the subject's). The
+/// result has an `act` claim naming the account, nested over any actor
+/// the subject token already had.
+async fn exchange_token(
+    repos: &Repositories,
+    config: &Config,
+    account: ServiceAccount,
+    req: &TokenRequest,
+) -> Result<TokenResponse, OAuthError> {
+    let subject_token = req
+        .subject_token
+        .as_deref()
+        .ok_or_else(|| OAuthError::invalid_request("subject_token is required"))?;
+    match req.subject_token_type.as_deref() {
+        Some(ACCESS_TOKEN_TYPE | JWT_TOKEN_TYPE) => {}
+        _ => {
+            return Err(OAuthError::invalid_request(format!(
+                "subject_token_type must be {ACCESS_TOKEN_TYPE} or {JWT_TOKEN_TYPE}"
+            )))
+        }
+    }
+    if let Some(requested) = req.requested_token_type.as_deref() {
+        if requested != ACCESS_TOKEN_TYPE && requested != JWT_TOKEN_TYPE {
+            return Err(OAuthError::invalid_request(format!(
+                "requested_token_type '{requested}' is not supported"
+            )));
+        }
+    }
+    let audience = req
+        .audience
+        .as_deref()
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .ok_or_else(|| OAuthError::invalid_request("audience is required"))?;
+
+    let mut subject = validate_token_with_previous(
+        subject_token,
+        &config.jwt_secret,
+        config.jwt_previous_secret.as_ref(),
+    )
+    .map_err(|_| OAuthError::invalid_grant("subject_token is invalid or expired"))?;
+    let active = is_token_active(repos, &subject)
+        .await
+        .map_err(|e| AppError::Internal(format!("Database error: {e}")))?;
+    if !active {
+        return Err(OAuthError::invalid_grant("subject_token has been revoked"));
+    }
+    // A token restricted to a service can only be passed on by that service
+    if subject.aud.as_ref().is_some_and(|aud| *aud != account.name) {
+        return Err(OAuthError::invalid_grant(
+            "subject_token was issued for another audience",
+        ));
+    }
+    if subject.is_compact() {
+        let role_permissions = repos
+            .assignments
+            .role_permissions()
+            .await
+            .map_err(|e| AppError::Internal(format!("Failed to get role permissions: {e}")))?;
+        subject.permissions = role_permissions.permissions_for(&subject.roles);
+    }
+
+    let permissions = match req.scope.as_deref() {
+        Some(scope) => {
+            let mut requested: Vec<String> = Vec::new();
+            for permission in scope.split_whitespace() {
+                if !subject.permissions.iter().any(|p| p == permission) {
+                    return Err(OAuthError::invalid_scope(format!(
+                        "The subject does not hold '{permission}'"
+                    )));
+                }
+                if !requested.iter().any(|p| p == permission) {
+                    requested.push(permission.to_string());
+                }
+            }
+            requested
+        }
+        None => subject.permissions.clone(),
+    };
+
+    let actor = Actor {
+        sub: account.id,
+        username: account.name,
+        principal_type: PrincipalType::Service,
+        act: subject.act.clone().map(Box::new),
+    };
+    let claims = create_exchanged_claims(&subject, actor, audience.to_string(), permissions);
+    let access_token = generate_token(&claims, &config.jwt_secret)
+        .map_err(|e| AppError::Internal(format!("Failed to generate token: {e}")))?;
+
+    Ok(TokenResponse {
+        access_token,
+        issued_token_type: Some(ACCESS_TOKEN_TYPE.to_string()),
+        token_type: "Bearer".to_string(),
+        expires_in: claims.exp - claims.iat,
+        scope: Some(claims.permissions.join(" ")),
+    })
+}
+
+/// Device flow (RFC 8628 section 3.4): the device polls until the user has
+/// decided, and gets a token for a new session of the approving user. The
+/// request is deleted once its outcome is collected.
+async fn device_code(
+    repos: &Repositories,
+    config: &Config,
+    http_req: &HttpRequest,
+    req: &TokenRequest,
+) -> Result<TokenResponse, OAuthError> {
+    let device_code = req
+        .device_code
+        .as_deref()
+        .ok_or_else(|| OAuthError::invalid_request("device_code is required"))?;
+    let client_id = req
+        .client_id
+        .as_deref()
+        .ok_or_else(|| OAuthError::invalid_request("client_id is required"))?;
+
+    let authorization = repos
+        .device_authorizations
+        .find_by_device_code(&hash_opaque_token(device_code))
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get device authorization: {e}")))?
+        .filter(|authorization| authorization.client_id == client_id)
+        .ok_or_else(|| OAuthError::invalid_grant("device_code is invalid"))?;
+    let delete = || async {
+        repos
+            .device_authorizations
+            .delete(authorization.id)
+            .await
+            .map_err(|e| AppError::Internal(format!("Failed to delete device authorization: {e}")))
+    };
+
+    let user_id = match authorization.status() {
+        DeviceAuthorizationStatus::Pending => {
+            let mut interval = authorization.interval_seconds;
+            let too_soon = authorization.last_polled_at.is_some_and(|polled_at| {
+                Utc::now() < polled_at + Duration::seconds(interval.into())
+            });
+            if too_soon {
+                interval += DEVICE_SLOW_DOWN_SECONDS;
+            }
+            repos
+                .device_authorizations
+                .record_poll(authorization.id, interval)
+                .await
+                .map_err(|e| AppError::Internal(format!("Failed to record poll: {e}")))?;
+            return Err(if too_soon {
+                OAuthError::slow_down(interval)
+            } else {
+                OAuthError::authorization_pending()
+            });
+        }
+        DeviceAuthorizationStatus::Expired => {
+            delete().await?;
+            return Err(OAuthError::expired_token());
+        }
+        DeviceAuthorizationStatus::Denied => {
+            delete().await?;
+            return Err(OAuthError::access_denied());
+        }
+        DeviceAuthorizationStatus::Approved => authorization.user_id,
+    };
+
+    // Of concurrent polls, only the one that deletes the request gets a token
+    if !delete().await? {
+        return Err(OAuthError::invalid_grant(
+            "device_code has already been used",
+        ));
+    }
+    let user = match user_id {
+        Some(user_id) => repos
+            .users
+            .find_by_id(user_id)
+            .await
+            .map_err(|e| AppError::Internal(format!("Database error: {e}")))?,
+        None => None,
+    }
+    .filter(|user| user.is_active)
+    .ok_or_else(|| OAuthError::invalid_grant("The approving user is no longer active"))?;
+
+    let session = start_session(repos, config, http_req, &user).await?;
+
+    Ok(TokenResponse {
+        access_token: session.token,
+        issued_token_type: None,
+        token_type: "Bearer".to_string(),
+        expires_in: session.expires_in,
+        scope: None,
+    })
+}
+
+/// Client credentials sent in an `Authorization: Basic` header
+fn basic_credentials(http_req: &HttpRequest) -> Result<Option<(String, String)>, OAuthError> {
+    let Some(header) = http_req.headers().get("Authorization") else {
+        return Ok(None);
+    };
+    let invalid = || OAuthError::invalid_client("Malformed Basic credentials");
+    let encoded = header
+        .to_str()
+        .ok()
+        .and_then(|h| h.strip_prefix("Basic "))
+        .ok_or_else(invalid)?;
+    let decoded = STANDARD
+        .decode(encoded.trim())
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or_else(invalid)?;
+    let (id, secret) = decoded.split_once(':').ok_or_else(invalid)?;
+    Ok(Some((id.to_string(), secret.to_string())))
+}
+
+/// Identifies the calling service account by exactly one of its credentials
+async fn authenticate_client(
+    repos: &Repositories,
+    config: &Config,
+    http_req: &HttpRequest,
+    req: &TokenRequest,
+) -> Result<ServiceAccount, OAuthError> {
+    let basic = basic_credentials(http_req)?;
+    let methods = [
+        basic.is_some(),
+        req.client_secret.is_some(),
+        req.client_assertion.is_some(),
+    ];
+    if methods.iter().filter(|used| **used).count() != 1 {
+        return Err(OAuthError::invalid_request(
+            "Exactly one client authentication method must be used",
+        ));
+    }
+
+    if let Some(assertion) = &req.client_assertion {
+        if req.client_assertion_type.as_deref() != Some(JWT_BEARER_ASSERTION_TYPE) {
+            return Err(OAuthError::invalid_request(format!(
+                "client_assertion_type must be {JWT_BEARER_ASSERTION_TYPE}"
+            )));
+        }
+        let claims = read_assertion(assertion)
+            .ok_or_else(|| OAuthError::invalid_client("Malformed client assertion"))?;
+        // The assertion must be addressed to this endpoint. Its URL is
+        // configured rather than taken from the request, whose Host the
+        // client controls.
+        let audience = config.token_endpoint_url.as_deref().ok_or_else(|| {
+            OAuthError::invalid_client("Client assertions are not accepted by this server")
+        })?;
+        let client_id = claims.sub;
+        let account = verify_client(repos, client_id, req.client_id.as_deref(), |c| {
+            c.kind == CredentialKind::PublicKey.as_str()
+                && matches!(
+                    (&c.public_key, &c.algorithm),
+                    (Some(pem), Some(algorithm))
+                        if verify_assertion(assertion, pem, algorithm, client_id, audience)
+                )
+        })
+        .await?;
+
+        // Each assertion is good for one token (RFC 7523 section 3); its
+        // jti is remembered until it expires
+        let jti = claims.jti.as_deref().unwrap_or_default();
+        let expires_at = DateTime::from_timestamp(claims.exp, 0).unwrap_or_else(Utc::now);
+        let fresh = repos
+            .service_accounts
+            .use_assertion(account.id, jti, expires_at)
+            .await
+            .map_err(|e| AppError::Internal(format!("Failed to record client assertion: {e}")))?;
+        if !fresh {
+            return Err(OAuthError::invalid_client(
+                "Client assertion has already been used",
+            ));
+        }
+        return Ok(account);
+    }
+
+    let (client_id, secret) = match basic {
+        Some((id, secret)) => (id, secret),
+        None => (
+            req.client_id
+                .clone()
+                .ok_or_else(|| OAuthError::invalid_request("client_id is required"))?,
+            req.client_secret.clone().unwrap_or_default(),
+        ),
+    };
+    let client_id = client_id
+        .parse::<Uuid>()
+        .map_err(|_| OAuthError::invalid_client("Client authentication failed"))?;
+    let secret_hash = hash_opaque_token(&secret);
+    verify_client(repos, client_id, None, |c| {
+        c.kind == CredentialKind::Secret.as_str()
+            && c.secret_hash.as_deref() == Some(secret_hash.as_str())
+    })
+    .await
+}
+
+/// Loads the active service account `client_id` and finds an unexpired
+/// credential of it that `matches`; a `claimed_id` from the form must agree
+async fn verify_client(
+    repos: &Repositories,
+    client_id: Uuid,
+    claimed_id: Option<&str>,
+    matches: impl Fn(&ServiceAccountCredential) -> bool,
+) -> Result<ServiceAccount, OAuthError> {
+    let failed = || OAuthError::invalid_client("Client authentication failed");
+    if claimed_id.is_some_and(|id| id != client_id.to_string()) {
+        return Err(failed());
+    }
+
+    let account = repos
+        .service_accounts
+        .find_by_id(client_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get service account: {e}")))?
+        .filter(|account| account.is_active)
+        .ok_or_else(failed)?;
+    let credentials = repos
+        .service_accounts
+        .list_credentials(account.id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get credentials: {e}")))?;
+
+    let credential = credentials
+        .iter()
+        .filter(|c| !c.is_expired())
+        .find(|c| matches(c))
+        .ok_or_else(failed)?;
+    repos
+        .service_accounts
+        .touch_credential(credential.id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to record credential use: {e}")))?;
+
+    Ok(account)
+}
@@ -0,0 +1,193 @@
+use crate::models::{EventType, Webhook};
+use crate::repositories::Repositories;
+use crate::services::generate_opaque_token;
+use actix_web::{web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use shared::{ApiResponse, AppError, AppResult};
+use uuid::Uuid;
+
+/// Secrets chosen by the admin must be at least this long
+pub const MIN_SECRET_LENGTH: usize = 16;
+
+/// Deliveries listed when the request does not say, and the most it may ask for
+const DEFAULT_DELIVERY_LIMIT: i64 = 50;
+const MAX_DELIVERY_LIMIT: i64 = 500;
+
+#[derive(Debug, Serialize)]
+pub struct WebhookResponse {
+    pub id: Uuid,
+    pub url: String,
+    /// Event types delivered to the webhook; empty means all of them
+    pub event_types: Vec<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<Webhook> for WebhookResponse {
+    fn from(webhook: Webhook) -> Self {
+        Self {
+            id: webhook.id,
+            url: webhook.url,
+            event_types: webhook.event_types,
+            created_at: webhook.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedWebhookResponse {
+    #[serde(flatten)]
+    pub webhook: WebhookResponse,
+    /// Key for verifying delivery signatures; it cannot be retrieved again
+    pub secret: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateWebhookRequest {
+    pub url: String,
+    #[serde(default)]
+    pub event_types: Vec<String>,
+    /// Generated when not given
+    pub secret: Option<String>,
+}
+
+pub async fn create_webhook(
+    repos: web::Data<Repositories>,
+    req: web::Json<CreateWebhookRequest>,
+) -> AppResult<impl Responder> {
+    let url = reqwest::Url::parse(&req.url)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https") && url.has_host())
+        .ok_or_else(|| AppError::BadRequest("url must be an absolute http(s) URL".to_string()))?;
+
+    let mut event_types = Vec::with_capacity(req.event_types.len());
+    for event_type in &req.event_types {
+        let event_type = event_type
+            .parse::<EventType>()
+            .map_err(AppError::BadRequest)?;
+        if !event_types.contains(&event_type.as_str().to_string()) {
+            event_types.push(event_type.as_str().to_string());
+        }
+    }
+
+    let secret = match &req.secret {
+        Some(secret) if secret.len() < MIN_SECRET_LENGTH => {
+            return Err(AppError::BadRequest(format!(
+                "secret must be at least {MIN_SECRET_LENGTH} characters"
+            )))
+        }
+        Some(secret) => secret.clone(),
+        None => generate_opaque_token(),
+    };
+
+    let webhook = repos
+        .webhooks
+        .create(url.as_str(), &secret, &event_types)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to create webhook: {e}")))?;
+
+    let response = CreatedWebhookResponse {
+        webhook: webhook.into(),
+        secret,
+    };
+    Ok(HttpResponse::Created().json(ApiResponse::new(response)))
+}
+
+pub async fn list_webhooks(repos: web::Data<Repositories>) -> AppResult<impl Responder> {
+    let webhooks = repos
+        .webhooks
+        .list()
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to list webhooks: {e}")))?;
+
+    let response: Vec<WebhookResponse> = webhooks.into_iter().map(|w| w.into()).collect();
+    Ok(HttpResponse::Ok().json(ApiResponse::new(response)))
+}
+
+async fn find_webhook(repos: &Repositories, webhook_id: Uuid) -> AppResult<Webhook> {
+    repos
+        .webhooks
+        .find_by_id(webhook_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to get webhook: {e}")))?
+        .ok_or_else(|| AppError::NotFound(format!("Webhook with id {webhook_id} not found")))
+}
+
+pub async fn get_webhook(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+) -> AppResult<impl Responder> {
+    let webhook = find_webhook(&repos, path.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(ApiResponse::new(WebhookResponse::from(webhook))))
+}
+
+/// Stops deliveries to the webhook and discards its delivery log
+pub async fn delete_webhook(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+) -> AppResult<impl Responder> {
+    let webhook_id = path.into_inner();
+
+    let deleted = repos
+        .webhooks
+        .delete(webhook_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to delete webhook: {e}")))?;
+
+    if !deleted {
+        return Err(AppError::NotFound(format!(
+            "Webhook with id {webhook_id} not found"
+        )));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeliveryListQuery {
+    pub limit: Option<i64>,
+}
+
+pub async fn list_deliveries(
+    repos: web::Data<Repositories>,
+    path: web::Path<Uuid>,
+    query: web::Query<DeliveryListQuery>,
+) -> AppResult<impl Responder> {
+    let webhook = find_webhook(&repos, path.into_inner()).await?;
+
+    let limit = query.limit.unwrap_or(DEFAULT_DELIVERY_LIMIT);
+    if !(1..=MAX_DELIVERY_LIMIT).contains(&limit) {
+        return Err(AppError::BadRequest(format!(
+            "limit must be between 1 and {MAX_DELIVERY_LIMIT}"
+        )));
+    }
+
+    let deliveries = repos
+        .webhooks
+        .list_deliveries(webhook.id, limit)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to list deliveries: {e}")))?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::new(deliveries)))
+}
+
+/// Sends the event of a past delivery again, as a new delivery with its own
+/// attempts; the dispatcher picks it up on its next pass
+pub async fn redeliver(
+    repos: web::Data<Repositories>,
+    path: web::Path<(Uuid, Uuid)>,
+) -> AppResult<impl Responder> {
+    let (webhook_id, delivery_id) = path.into_inner();
+
+    let delivery = repos
+        .webhooks
+        .redeliver(webhook_id, delivery_id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to redeliver: {e}")))?
+        .ok_or_else(|| {
+            AppError::NotFound(format!(
+                "Webhook {webhook_id} has no delivery with id {delivery_id}"
+            ))
+        })?;
+
+    Ok(HttpResponse::Accepted().json(ApiResponse::new(delivery)))
+}
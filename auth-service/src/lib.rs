@@ -1,12 +1,21 @@
+pub mod cli;
 pub mod config;
 pub mod db;
 pub mod handlers;
 pub mod middleware;
 pub mod models;
+pub mod repositories;
 pub mod services;
 
-pub use config::Config;
-pub use db::create_pool;
-pub use models::{Permission, Role, User};
-pub use services::{create_claims, generate_token, hash_password, validate_token, verify_password};
+pub use config::{
+    AuthBackendKind, BootstrapAdmin, Config, LdapConfig, OidcProvider, RegistrationMode,
+    TokenFormat,
+};
+pub use db::{create_pool, create_sqlite_pool, DatabaseBackend};
+pub use models::{Group, Permission, Role, User};
+pub use repositories::Repositories;
+pub use services::{
+    create_claims, generate_token, hash_password, validate_token, validate_token_with_previous,
+    verify_password,
+};
 pub use shared::Claims;
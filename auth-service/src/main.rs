@@ -1,16 +1,101 @@
 use actix_web::{web, App, HttpServer, Responder};
+use auth_service::db::{run_migrations, run_sqlite_migrations, StartupLock};
 use auth_service::handlers;
-use auth_service::{create_pool, Config};
-use log::info;
+use auth_service::services::oidc::OidcClient;
+use auth_service::services::{
+    bootstrap, bootstrap_admin, BootstrapOutcome, LoginThrottle, WebhookDispatcher,
+};
+use auth_service::{create_pool, create_sqlite_pool, Config, DatabaseBackend, Repositories};
+use log::{info, warn};
 
 async fn health_check() -> impl Responder {
     "OK"
 }
 
+/// Makes sure an admin can sign in, printing a setup token if none exists
+async fn bootstrap(repos: &Repositories, config: &Config) {
+    match bootstrap_admin(repos, config.bootstrap_admin.as_ref())
+        .await
+        .expect("Failed to bootstrap admin user")
+    {
+        BootstrapOutcome::AdminExists => {}
+        BootstrapOutcome::AdminCreated(user) => {
+            info!("Created bootstrap admin '{}' ({})", user.username, user.id);
+        }
+        BootstrapOutcome::SetupTokenIssued(token) => {
+            warn!(
+                "No admin user exists. Create one within {} hours by POSTing this one-time \
+                 setup token with a username, email and password to /auth/setup; it replaces any \
+                 setup token printed earlier: {token}",
+                bootstrap::SETUP_TOKEN_TTL_HOURS
+            );
+        }
+    }
+}
+
+/// How often soft-deleted users past the retention period are purged
+const PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Days delivered webhook events and their delivery logs are kept
+const WEBHOOK_LOG_RETENTION_DAYS: i64 = 30;
+
+/// Permanently removes users deleted more than `retention_days` ago, old
+/// webhook delivery logs, expired device authorization requests and the ids
+/// of expired client assertions, now and then every `PURGE_INTERVAL`.
+/// Replicas may overlap; purging is idempotent.
+async fn purge_deleted_users(repos: Repositories, retention_days: i64) {
+    let mut interval = actix_web::rt::time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let before = chrono::Utc::now() - chrono::Duration::days(retention_days);
+        match repos.users.purge_deleted(before).await {
+            Ok(0) => {}
+            Ok(purged) => info!("Purged {purged} users deleted before {before}"),
+            Err(e) => warn!("Failed to purge deleted users: {e}"),
+        }
+
+        let before = chrono::Utc::now() - chrono::Duration::days(WEBHOOK_LOG_RETENTION_DAYS);
+        match repos.webhooks.purge_events(before).await {
+            Ok(0) => {}
+            Ok(purged) => info!("Purged {purged} webhook events dispatched before {before}"),
+            Err(e) => warn!("Failed to purge webhook events: {e}"),
+        }
+
+        let now = chrono::Utc::now();
+        match repos.device_authorizations.purge_expired(now).await {
+            Ok(0) => {}
+            Ok(purged) => info!("Purged {purged} expired device authorization requests"),
+            Err(e) => warn!("Failed to purge device authorization requests: {e}"),
+        }
+
+        match repos.service_accounts.purge_used_assertions(now).await {
+            Ok(0) => {}
+            Ok(purged) => info!("Purged {purged} expired client assertion ids"),
+            Err(e) => warn!("Failed to purge client assertion ids: {e}"),
+        }
+    }
+}
+
+/// How often the outbox is checked for events and due webhook deliveries
+const DISPATCH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Delivers webhook events every `DISPATCH_INTERVAL`; replicas share the work
+async fn dispatch_webhooks(dispatcher: WebhookDispatcher) {
+    let mut interval = actix_web::rt::time::interval(DISPATCH_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = dispatcher.run_once().await {
+            warn!("Failed to dispatch webhooks: {e}");
+        }
+    }
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv::dotenv().ok();
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+    env_logger::Builder::from_env(env_logger::Env::new().default_filter_or("info"))
+        .format(shared::correlation::format_log)
+        .init();
 
     let config = Config::from_env();
     let port = config.port;
@@ -19,57 +104,391 @@ async fn main() -> std::io::Result<()> {
     info!("Starting auth-service on port {port}");
 
     // Initialize database connection pool
-    let pool = create_pool(&database_url)
-        .await
-        .expect("Failed to create database pool");
+    let repos = match DatabaseBackend::from_url(&database_url) {
+        Some(DatabaseBackend::Postgres) => {
+            let pool = create_pool(&database_url)
+                .await
+                .expect("Failed to create database pool");
+            let repos = Repositories::postgres(pool.clone());
+
+            // Replicas starting together take turns to migrate and bootstrap
+            let lock = StartupLock::acquire(&database_url)
+                .await
+                .expect("Failed to acquire startup lock");
+            if config.run_migrations {
+                run_migrations(&pool)
+                    .await
+                    .expect("Failed to run database migrations");
+            }
+            bootstrap(&repos, &config).await;
+            lock.release()
+                .await
+                .expect("Failed to release startup lock");
+
+            repos
+        }
+        Some(DatabaseBackend::Sqlite) => {
+            let pool = create_sqlite_pool(&database_url)
+                .await
+                .expect("Failed to open SQLite database");
+            let repos = Repositories::sqlite(pool.clone());
+
+            if config.run_migrations {
+                run_sqlite_migrations(&pool)
+                    .await
+                    .expect("Failed to run SQLite migrations");
+            }
+            bootstrap(&repos, &config).await;
+            repos
+        }
+        None => panic!("DATABASE_URL must start with postgres://, postgresql:// or sqlite:"),
+    };
+
+    actix_web::rt::spawn(purge_deleted_users(
+        repos.clone(),
+        config.user_retention_days,
+    ));
+    actix_web::rt::spawn(dispatch_webhooks(WebhookDispatcher::new(
+        repos.webhooks.clone(),
+        config.webhook_max_attempts,
+    )));
+
+    // Shared so that provider metadata is discovered once per process
+    let oidc = web::Data::new(OidcClient::new());
+    let login_throttle = web::Data::new(LoginThrottle::default());
 
     HttpServer::new(move || {
         App::new()
+            // Errors, those of middleware and extractors included, are
+            // answered as problem+json naming the failed request
+            .wrap(shared::ErrorContext)
+            .wrap(shared::LoggingMiddleware)
+            // Outermost, so the request id is known to everything inside, logs
+            // and upstream calls included
+            .wrap(shared::Correlation)
+            .app_data(web::JsonConfig::default().error_handler(shared::errors::extractor_error))
+            .app_data(web::FormConfig::default().error_handler(shared::errors::extractor_error))
+            .app_data(web::QueryConfig::default().error_handler(shared::errors::extractor_error))
+            .app_data(web::PathConfig::default().error_handler(shared::errors::extractor_error))
             .app_data(web::Data::new(config.clone()))
-            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(repos.clone()))
+            .app_data(oidc.clone())
+            .app_data(login_throttle.clone())
             .route("/health", web::get().to(health_check))
             .service(
                 web::scope("/auth")
                     .route("/register", web::post().to(handlers::auth::register))
-                    .route("/login", web::post().to(handlers::auth::login)),
+                    .route("/login", web::post().to(handlers::auth::login))
+                    .route("/introspect", web::post().to(handlers::auth::introspect))
+                    .route("/token", web::post().to(handlers::token::token))
+                    .service(
+                        web::resource("/role-permissions")
+                            .wrap(
+                                auth_service::middleware::JwtAuth::new(config.jwt_secret.clone())
+                                    .with_previous_secret(config.jwt_previous_secret.clone()),
+                            )
+                            .route(web::get().to(handlers::auth::role_permissions)),
+                    )
+                    .route("/setup", web::post().to(handlers::auth::complete_setup))
+                    .route(
+                        "/oidc/{provider}/login",
+                        web::get().to(handlers::oidc::oidc_login),
+                    )
+                    .route(
+                        "/oidc/{provider}/callback",
+                        web::get().to(handlers::oidc::oidc_callback),
+                    )
+                    .route(
+                        "/invitations/accept",
+                        web::post().to(handlers::auth::accept_invitation),
+                    )
+                    .service(
+                        web::scope("/me")
+                            .wrap(
+                                auth_service::middleware::JwtAuth::new(config.jwt_secret.clone())
+                                    .with_previous_secret(config.jwt_previous_secret.clone()),
+                            )
+                            .route("/export", web::get().to(handlers::privacy::export_my_data))
+                            .service(
+                                web::resource("/preferences")
+                                    .route(web::get().to(handlers::preferences::get_my_preferences))
+                                    .route(web::put().to(handlers::preferences::put_my_preferences))
+                                    .route(
+                                        web::patch()
+                                            .to(handlers::preferences::patch_my_preferences),
+                                    )
+                                    .route(
+                                        web::delete()
+                                            .to(handlers::preferences::delete_my_preferences),
+                                    ),
+                            )
+                            .route("/sessions", web::get().to(handlers::auth::list_my_sessions))
+                            .route(
+                                "/sessions",
+                                web::delete().to(handlers::auth::revoke_my_sessions),
+                            )
+                            .route(
+                                "/sessions/{id}",
+                                web::delete().to(handlers::auth::revoke_my_session),
+                            ),
+                    ),
+            )
+            .service(
+                web::scope("/oauth")
+                    .route(
+                        "/device_authorization",
+                        web::post().to(handlers::device::device_authorization),
+                    )
+                    .route("/token", web::post().to(handlers::token::token))
+                    .service(
+                        web::resource("/device")
+                            .wrap(
+                                auth_service::middleware::JwtAuth::new(config.jwt_secret.clone())
+                                    .with_previous_secret(config.jwt_previous_secret.clone()),
+                            )
+                            .route(web::get().to(handlers::device::get_device_request))
+                            .route(web::post().to(handlers::device::decide_device_request)),
+                    ),
+            )
+            .service(
+                web::scope("/scim/v2")
+                    .route(
+                        "/ServiceProviderConfig",
+                        web::get().to(handlers::scim::service_provider_config),
+                    )
+                    .route("/Schemas", web::get().to(handlers::scim::list_schemas))
+                    .route("/Schemas/{id}", web::get().to(handlers::scim::get_schema))
+                    .route(
+                        "/ResourceTypes",
+                        web::get().to(handlers::scim::list_resource_types),
+                    )
+                    .service(
+                        web::scope("/Users")
+                            .wrap(auth_service::middleware::ScimAuth::new(
+                                config.scim_bearer_token.clone(),
+                            ))
+                            .route("", web::get().to(handlers::scim::list_users))
+                            .route("", web::post().to(handlers::scim::create_user))
+                            .route("/{id}", web::get().to(handlers::scim::get_user))
+                            .route("/{id}", web::put().to(handlers::scim::replace_user))
+                            .route("/{id}", web::patch().to(handlers::scim::patch_user))
+                            .route("/{id}", web::delete().to(handlers::scim::delete_user)),
+                    )
+                    .service(
+                        web::scope("/Groups")
+                            .wrap(auth_service::middleware::ScimAuth::new(
+                                config.scim_bearer_token.clone(),
+                            ))
+                            .route("", web::get().to(handlers::scim::list_groups))
+                            .route("", web::post().to(handlers::scim::create_group))
+                            .route("/{id}", web::get().to(handlers::scim::get_group))
+                            .route("/{id}", web::put().to(handlers::scim::replace_group))
+                            .route("/{id}", web::patch().to(handlers::scim::patch_group))
+                            .route("/{id}", web::delete().to(handlers::scim::delete_group)),
+                    ),
             )
             .service(
                 web::scope("/admin")
-                    .wrap(auth_service::middleware::JwtAuth::new(
-                        config.jwt_secret.clone(),
-                    ))
+                    // Middleware registered last runs first: JwtAuth must attach
+                    // the claims before AdminAuth inspects them
                     .wrap(auth_service::middleware::AdminAuth)
+                    .wrap(
+                        auth_service::middleware::JwtAuth::new(config.jwt_secret.clone())
+                            .with_previous_secret(config.jwt_previous_secret.clone()),
+                    )
                     .service(
                         web::scope("/users")
                             .route("", web::get().to(handlers::admin::list_users))
                             .route("", web::post().to(handlers::admin::create_user))
+                            .route(
+                                "/deleted",
+                                web::get().to(handlers::admin::list_deleted_users),
+                            )
+                            .service(
+                                web::resource("/import")
+                                    .app_data(web::PayloadConfig::new(
+                                        handlers::bulk::IMPORT_PAYLOAD_LIMIT,
+                                    ))
+                                    .route(web::post().to(handlers::bulk::import_users)),
+                            )
+                            .route("/export", web::get().to(handlers::bulk::export_users))
                             .route("/{id}", web::get().to(handlers::admin::get_user))
                             .route("/{id}", web::put().to(handlers::admin::update_user))
-                            .route("/{id}", web::delete().to(handlers::admin::delete_user)),
+                            .route("/{id}", web::patch().to(handlers::admin::patch_user))
+                            .route("/{id}", web::delete().to(handlers::admin::delete_user))
+                            .route(
+                                "/{id}/restore",
+                                web::post().to(handlers::admin::restore_user),
+                            )
+                            .route(
+                                "/{id}/export",
+                                web::get().to(handlers::privacy::export_user_data),
+                            )
+                            .route("/{id}/erase", web::post().to(handlers::privacy::erase_user))
+                            .route(
+                                "/{id}/impersonate",
+                                web::post().to(handlers::admin::impersonate_user),
+                            )
+                            .route(
+                                "/{id}/identities",
+                                web::get().to(handlers::admin::list_user_identities),
+                            )
+                            .route(
+                                "/{id}/identities/{identity_id}",
+                                web::delete().to(handlers::admin::unlink_user_identity),
+                            )
+                            .route(
+                                "/{id}/sessions",
+                                web::get().to(handlers::admin::list_user_sessions),
+                            )
+                            .route(
+                                "/{id}/sessions",
+                                web::delete().to(handlers::admin::revoke_user_sessions),
+                            )
+                            .route(
+                                "/{id}/sessions/{session_id}",
+                                web::delete().to(handlers::admin::revoke_user_session),
+                            )
+                            .route(
+                                "/{id}/roles",
+                                web::post().to(handlers::admin::assign_role_to_user),
+                            )
+                            .route(
+                                "/{id}/roles/{role_id}",
+                                web::delete().to(handlers::admin::remove_role_from_user),
+                            ),
                     )
                     .service(
                         web::scope("/roles")
                             .route("", web::get().to(handlers::admin::list_roles))
-                            .route("", web::post().to(handlers::admin::create_role)),
+                            .route("", web::post().to(handlers::admin::create_role))
+                            .route("/{id}", web::get().to(handlers::admin::get_role))
+                            .route("/{id}", web::put().to(handlers::admin::update_role))
+                            .route("/{id}", web::delete().to(handlers::admin::delete_role))
+                            .route(
+                                "/{id}/permissions",
+                                web::post().to(handlers::admin::assign_permission_to_role),
+                            ),
+                    )
+                    .route("/erasures", web::get().to(handlers::privacy::list_erasures))
+                    .service(
+                        web::scope("/webhooks")
+                            .route("", web::get().to(handlers::webhooks::list_webhooks))
+                            .route("", web::post().to(handlers::webhooks::create_webhook))
+                            .route("/{id}", web::get().to(handlers::webhooks::get_webhook))
+                            .route(
+                                "/{id}",
+                                web::delete().to(handlers::webhooks::delete_webhook),
+                            )
+                            .route(
+                                "/{id}/deliveries",
+                                web::get().to(handlers::webhooks::list_deliveries),
+                            )
+                            .route(
+                                "/{id}/deliveries/{delivery_id}/redeliver",
+                                web::post().to(handlers::webhooks::redeliver),
+                            ),
                     )
                     .service(
                         web::scope("/permissions")
                             .route("", web::get().to(handlers::admin::list_permissions))
-                            .route("", web::post().to(handlers::admin::create_permission)),
+                            .route("", web::post().to(handlers::admin::create_permission))
+                            .route("/{id}", web::get().to(handlers::admin::get_permission))
+                            .route("/{id}", web::put().to(handlers::admin::update_permission))
+                            .route(
+                                "/{id}",
+                                web::delete().to(handlers::admin::delete_permission),
+                            ),
                     )
                     .service(
-                        web::scope("/users/{user_id}/roles")
-                            .route("", web::post().to(handlers::admin::assign_role_to_user))
+                        web::scope("/service-accounts")
                             .route(
-                                "/{role_id}",
-                                web::delete().to(handlers::admin::remove_role_from_user),
+                                "",
+                                web::get().to(handlers::service_accounts::list_service_accounts),
+                            )
+                            .route(
+                                "",
+                                web::post().to(handlers::service_accounts::create_service_account),
+                            )
+                            .route(
+                                "/{id}",
+                                web::get().to(handlers::service_accounts::get_service_account),
+                            )
+                            .route(
+                                "/{id}",
+                                web::patch().to(handlers::service_accounts::patch_service_account),
+                            )
+                            .route(
+                                "/{id}",
+                                web::delete()
+                                    .to(handlers::service_accounts::delete_service_account),
+                            )
+                            .route(
+                                "/{id}/roles",
+                                web::post()
+                                    .to(handlers::service_accounts::assign_role_to_service_account),
+                            )
+                            .route(
+                                "/{id}/roles/{role_id}",
+                                web::delete().to(
+                                    handlers::service_accounts::remove_role_from_service_account,
+                                ),
+                            )
+                            .route(
+                                "/{id}/credentials",
+                                web::post().to(handlers::service_accounts::create_credential),
+                            )
+                            .route(
+                                "/{id}/credentials/{credential_id}",
+                                web::delete().to(handlers::service_accounts::delete_credential),
                             ),
                     )
-                    .service(web::scope("/roles/{role_id}/permissions").route(
-                        "",
-                        web::post().to(handlers::admin::assign_permission_to_role),
-                    )),
+                    .route(
+                        "/impersonations",
+                        web::get().to(handlers::admin::list_impersonations),
+                    )
+                    .service(
+                        web::scope("/invitations")
+                            .route("", web::get().to(handlers::admin::list_invitations))
+                            .route("", web::post().to(handlers::admin::create_invitation))
+                            .route(
+                                "/{id}",
+                                web::delete().to(handlers::admin::revoke_invitation),
+                            ),
+                    )
+                    .service(
+                        web::scope("/groups")
+                            .route("", web::get().to(handlers::admin::list_groups))
+                            .route("", web::post().to(handlers::admin::create_group))
+                            .route("/{id}", web::get().to(handlers::admin::get_group))
+                            .route("/{id}", web::delete().to(handlers::admin::delete_group))
+                            .route(
+                                "/{id}/members",
+                                web::post().to(handlers::admin::add_group_members),
+                            )
+                            .route(
+                                "/{id}/members",
+                                web::delete().to(handlers::admin::remove_group_members),
+                            )
+                            .route(
+                                "/{id}/subgroups",
+                                web::post().to(handlers::admin::add_subgroup),
+                            )
+                            .route(
+                                "/{id}/subgroups/{child_id}",
+                                web::delete().to(handlers::admin::remove_subgroup),
+                            )
+                            .route(
+                                "/{id}/roles",
+                                web::post().to(handlers::admin::assign_role_to_group),
+                            )
+                            .route(
+                                "/{id}/roles/{role_id}",
+                                web::delete().to(handlers::admin::remove_role_from_group),
+                            ),
+                    ),
             )
+            .default_service(web::route().to(shared::errors::no_route))
     })
     .bind(("0.0.0.0", port))?
     .run()
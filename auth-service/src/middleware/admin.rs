@@ -52,14 +52,27 @@ where
 
         Box::pin(async move {
             // Get claims from request extensions (set by JwtAuth middleware)
-            let has_admin = {
+            let (has_admin, impersonated) = {
                 let extensions = req.extensions();
                 let claims = extensions
                     .get::<Claims>()
                     .ok_or_else(|| AppError::Unauthorized("Missing authentication".to_string()))?;
-                claims.roles.contains(&"admin".to_string())
+                (
+                    claims.roles.contains(&"admin".to_string()),
+                    claims.is_impersonated(),
+                )
             };
 
+            // An impersonation token carries the target's roles, which may include
+            // admin; it must never be usable for administration, and neither
+            // may a token a service holds on someone's behalf
+            if impersonated {
+                return Err(AppError::Forbidden(
+                    "Impersonation and delegated tokens cannot access admin endpoints".to_string(),
+                )
+                .into());
+            }
+
             if !has_admin {
                 return Err(AppError::Forbidden("Admin access required".to_string()).into());
             }
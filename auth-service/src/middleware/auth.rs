@@ -1,10 +1,12 @@
-use crate::services::validate_token;
+use crate::repositories::Repositories;
+use crate::services::{is_token_active, validate_token_with_previous};
 use actix_web::{
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    Error, HttpMessage,
+    web, Error, HttpMessage,
 };
 use futures_util::future::LocalBoxFuture;
-use shared::AppError;
+use log::info;
+use shared::{AppError, PreviousSecret};
 use std::{
     future::{ready, Ready},
     rc::Rc,
@@ -12,11 +14,22 @@ use std::{
 
 pub struct JwtAuth {
     jwt_secret: String,
+    previous_secret: Option<PreviousSecret>,
 }
 
 impl JwtAuth {
     pub fn new(jwt_secret: String) -> Self {
-        Self { jwt_secret }
+        Self {
+            jwt_secret,
+            previous_secret: None,
+        }
+    }
+
+    /// Also accepts tokens signed with the secret `JWT_SECRET` replaced,
+    /// until its grace window ends
+    pub fn with_previous_secret(mut self, previous: Option<PreviousSecret>) -> Self {
+        self.previous_secret = previous;
+        self
     }
 }
 
@@ -36,6 +49,7 @@ where
         ready(Ok(JwtAuthMiddleware {
             service: Rc::new(service),
             jwt_secret: self.jwt_secret.clone(),
+            previous_secret: self.previous_secret.clone(),
         }))
     }
 }
@@ -43,6 +57,7 @@ where
 pub struct JwtAuthMiddleware<S> {
     service: Rc<S>,
     jwt_secret: String,
+    previous_secret: Option<PreviousSecret>,
 }
 
 impl<S, B> Service<ServiceRequest> for JwtAuthMiddleware<S>
@@ -60,6 +75,7 @@ where
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let svc = self.service.clone();
         let jwt_secret = self.jwt_secret.clone();
+        let previous_secret = self.previous_secret.clone();
 
         Box::pin(async move {
             // Extract token from Authorization header
@@ -81,8 +97,52 @@ where
             let token = auth_header.strip_prefix("Bearer ").unwrap();
 
             // Validate token
-            let claims = validate_token(token, &jwt_secret)
-                .map_err(|_| AppError::Unauthorized("Invalid or expired token".to_string()))?;
+            let mut claims =
+                validate_token_with_previous(token, &jwt_secret, previous_secret.as_ref())
+                    .map_err(|_| AppError::Unauthorized("Invalid or expired token".to_string()))?;
+
+            // Exchanged tokens are for the service they name, never this one
+            if claims.aud.is_some() {
+                return Err(AppError::Unauthorized(
+                    "Token is restricted to another audience".to_string(),
+                )
+                .into());
+            }
+
+            // Tokens stop working as soon as their session is revoked or their
+            // user is deactivated or deleted
+            let repos = req
+                .app_data::<web::Data<Repositories>>()
+                .ok_or_else(|| AppError::Internal("Repositories not configured".to_string()))?;
+            let active = is_token_active(repos, &claims)
+                .await
+                .map_err(|e| AppError::Internal(format!("Database error: {e}")))?;
+            if !active {
+                return Err(AppError::Unauthorized("Token has been revoked".to_string()).into());
+            }
+
+            // Compact tokens carry only roles; handlers see the permissions
+            // those roles grant right now
+            if claims.is_compact() {
+                let role_permissions = repos.assignments.role_permissions().await.map_err(|e| {
+                    AppError::Internal(format!("Failed to get role permissions: {e}"))
+                })?;
+                claims.permissions = role_permissions.permissions_for(&claims.roles);
+            }
+
+            // Impersonated requests are logged with both identities for the audit trail
+            if let Some(actor) = &claims.act {
+                info!(
+                    "{} {} by {} ({}) {} {} ({})",
+                    req.method(),
+                    req.path(),
+                    claims.username,
+                    claims.sub,
+                    actor.relation(),
+                    actor.username,
+                    actor.sub
+                );
+            }
 
             // Attach claims to request extensions
             req.extensions_mut().insert(claims);
@@ -1,5 +1,7 @@
 pub mod admin;
 pub mod auth;
+pub mod scim;
 
 pub use admin::AdminAuth;
 pub use auth::JwtAuth;
+pub use scim::ScimAuth;
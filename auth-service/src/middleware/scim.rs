@@ -0,0 +1,86 @@
+use crate::handlers::scim::ScimError;
+use crate::services::hash_opaque_token;
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    Error,
+};
+use futures_util::future::LocalBoxFuture;
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+};
+
+/// Authenticates the SCIM provisioning client with a static bearer token.
+/// All requests are rejected when no token is configured.
+pub struct ScimAuth {
+    token: Option<String>,
+}
+
+impl ScimAuth {
+    pub fn new(token: Option<String>) -> Self {
+        Self { token }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ScimAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ScimAuthMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ScimAuthMiddleware {
+            service: Rc::new(service),
+            // Compare digests rather than the raw tokens
+            token_hash: self.token.as_deref().map(hash_opaque_token),
+        }))
+    }
+}
+
+pub struct ScimAuthMiddleware<S> {
+    service: Rc<S>,
+    token_hash: Option<String>,
+}
+
+impl<S, B> Service<ServiceRequest> for ScimAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let token_hash = self.token_hash.clone();
+
+        Box::pin(async move {
+            let expected = token_hash
+                .ok_or_else(|| ScimError::unauthorized("SCIM provisioning is not configured"))?;
+
+            let provided = req
+                .headers()
+                .get("Authorization")
+                .and_then(|h| h.to_str().ok())
+                .and_then(|h| h.strip_prefix("Bearer "))
+                .ok_or_else(|| ScimError::unauthorized("Missing bearer token"))?;
+
+            if hash_opaque_token(provided) != expected {
+                return Err(ScimError::unauthorized("Invalid bearer token").into());
+            }
+
+            let res = svc.call(req).await?;
+            Ok(res)
+        })
+    }
+}
@@ -0,0 +1,194 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A device's request to sign a user in (RFC 8628), waiting for the user to
+/// enter its user code and decide
+#[derive(Debug, Clone, FromRow)]
+pub struct DeviceAuthorization {
+    pub id: Uuid,
+    pub device_code_hash: String,
+    /// Short code the user types in, as `XXXX-XXXX`
+    pub user_code: String,
+    /// The client the device says it is, shown to the user
+    pub client_id: String,
+    pub interval_seconds: i32,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub last_polled_at: Option<DateTime<Utc>>,
+    pub user_id: Option<Uuid>,
+    pub approved_at: Option<DateTime<Utc>>,
+    pub denied_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceAuthorizationStatus {
+    Pending,
+    Approved,
+    Denied,
+    Expired,
+}
+
+impl DeviceAuthorization {
+    pub fn status(&self) -> DeviceAuthorizationStatus {
+        if self.approved_at.is_some() {
+            DeviceAuthorizationStatus::Approved
+        } else if self.denied_at.is_some() {
+            DeviceAuthorizationStatus::Denied
+        } else if self.expires_at <= Utc::now() {
+            DeviceAuthorizationStatus::Expired
+        } else {
+            DeviceAuthorizationStatus::Pending
+        }
+    }
+
+    pub async fn create(
+        executor: impl sqlx::PgExecutor<'_>,
+        device_code_hash: &str,
+        user_code: &str,
+        client_id: &str,
+        interval_seconds: i32,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Self, sqlx::Error> {
+        let authorization = sqlx::query_as!(
+            DeviceAuthorization,
+            r#"
+            INSERT INTO device_authorizations
+                (device_code_hash, user_code, client_id, interval_seconds, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, device_code_hash, user_code, client_id, interval_seconds, created_at,
+                      expires_at, last_polled_at, user_id, approved_at, denied_at
+            "#,
+            device_code_hash,
+            user_code,
+            client_id,
+            interval_seconds,
+            expires_at
+        )
+        .fetch_one(executor)
+        .await?;
+
+        Ok(authorization)
+    }
+
+    pub async fn find_by_user_code(
+        executor: impl sqlx::PgExecutor<'_>,
+        user_code: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let authorization = sqlx::query_as!(
+            DeviceAuthorization,
+            r#"
+            SELECT id, device_code_hash, user_code, client_id, interval_seconds, created_at,
+                   expires_at, last_polled_at, user_id, approved_at, denied_at
+            FROM device_authorizations
+            WHERE user_code = $1
+            "#,
+            user_code
+        )
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(authorization)
+    }
+
+    pub async fn find_by_device_code(
+        executor: impl sqlx::PgExecutor<'_>,
+        device_code_hash: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let authorization = sqlx::query_as!(
+            DeviceAuthorization,
+            r#"
+            SELECT id, device_code_hash, user_code, client_id, interval_seconds, created_at,
+                   expires_at, last_polled_at, user_id, approved_at, denied_at
+            FROM device_authorizations
+            WHERE device_code_hash = $1
+            "#,
+            device_code_hash
+        )
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(authorization)
+    }
+
+    /// Records `user_id`'s decision on a pending, unexpired request; None if
+    /// there is no such request, so a request is decided only once
+    pub async fn decide(
+        executor: impl sqlx::PgExecutor<'_>,
+        user_code: &str,
+        user_id: Uuid,
+        approve: bool,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let authorization = sqlx::query_as!(
+            DeviceAuthorization,
+            r#"
+            UPDATE device_authorizations
+            SET user_id = $2,
+                approved_at = CASE WHEN $3 THEN NOW() END,
+                denied_at = CASE WHEN $3 THEN NULL ELSE NOW() END
+            WHERE user_code = $1
+              AND approved_at IS NULL AND denied_at IS NULL AND expires_at > NOW()
+            RETURNING id, device_code_hash, user_code, client_id, interval_seconds, created_at,
+                      expires_at, last_polled_at, user_id, approved_at, denied_at
+            "#,
+            user_code,
+            user_id,
+            approve
+        )
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(authorization)
+    }
+
+    /// Records a poll by the device, with the interval it must now keep to
+    pub async fn record_poll(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+        interval_seconds: i32,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE device_authorizations
+            SET last_polled_at = NOW(), interval_seconds = $2
+            WHERE id = $1
+            "#,
+            id,
+            interval_seconds
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Deletes the request once the device has collected its outcome.
+    /// Returns false if it was already gone, so concurrent polls of an
+    /// approved request get a token only once.
+    pub async fn delete(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!("DELETE FROM device_authorizations WHERE id = $1", id)
+            .execute(executor)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn purge_expired(
+        executor: impl sqlx::PgExecutor<'_>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM device_authorizations WHERE expires_at <= $1",
+            before
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,159 @@
+use crate::models::{NewEvent, WebhookEvent};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// The record of a user erased on request. It names the pseudonym that now
+/// stands in for the user in audit records, never the user.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct Erasure {
+    pub id: Uuid,
+    pub pseudonym_id: Uuid,
+    /// The admin who carried out the request
+    pub requested_by: Uuid,
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Erasure {
+    /// The username audit records show for the erased user
+    pub fn pseudonym_username(pseudonym_id: Uuid) -> String {
+        format!("erased-{pseudonym_id}")
+    }
+
+    /// Deletes the user, soft-deleted or not, with everything cascading from
+    /// them, and rewrites the records that outlive them to refer to a new
+    /// pseudonym. Records `user.erased` so webhook consumers can follow
+    /// suit. None if there is no such user.
+    pub async fn erase_user(
+        conn: impl sqlx::Acquire<'_, Database = sqlx::Postgres>,
+        user_id: Uuid,
+        requested_by: Uuid,
+        reason: Option<&str>,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let mut tx = conn.begin().await?;
+
+        let Some(email) =
+            sqlx::query_scalar!("DELETE FROM users WHERE id = $1 RETURNING email", user_id)
+                .fetch_optional(&mut *tx)
+                .await?
+        else {
+            return Ok(None);
+        };
+
+        let pseudonym_id = Uuid::new_v4();
+        let pseudonym_username = Self::pseudonym_username(pseudonym_id);
+
+        sqlx::query!(
+            r#"
+            UPDATE impersonation_events
+            SET actor_id = $2, actor_username = $3
+            WHERE actor_id = $1
+            "#,
+            user_id,
+            pseudonym_id,
+            pseudonym_username
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE impersonation_events
+            SET target_user_id = $2, target_username = $3
+            WHERE target_user_id = $1
+            "#,
+            user_id,
+            pseudonym_id,
+            pseudonym_username
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE webhook_events
+            SET payload = jsonb_set(
+                payload,
+                '{user}',
+                (payload -> 'user')
+                    || jsonb_build_object('id', $2::uuid, 'username', $3::text, 'email', NULL)
+            )
+            WHERE payload -> 'user' ->> 'id' = $1::text
+            "#,
+            user_id.to_string(),
+            pseudonym_id,
+            pseudonym_username
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        // Invitations to the user hold their email address; the ones they
+        // sent only need the inviter's pseudonym
+        sqlx::query!(
+            "DELETE FROM invitations WHERE accepted_user_id = $1 OR email = $2",
+            user_id,
+            email
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "UPDATE invitations SET invited_by = $2 WHERE invited_by = $1",
+            user_id,
+            pseudonym_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "UPDATE erasures SET requested_by = $2 WHERE requested_by = $1",
+            user_id,
+            pseudonym_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let erasure = sqlx::query_as!(
+            Erasure,
+            r#"
+            INSERT INTO erasures (pseudonym_id, requested_by, reason)
+            VALUES ($1, $2, $3)
+            RETURNING id, pseudonym_id, requested_by, reason, created_at
+            "#,
+            pseudonym_id,
+            // An admin erasing themselves is recorded under their pseudonym
+            if requested_by == user_id {
+                pseudonym_id
+            } else {
+                requested_by
+            },
+            reason
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        WebhookEvent::record(&mut *tx, &[NewEvent::erasure(user_id)]).await?;
+
+        tx.commit().await?;
+
+        Ok(Some(erasure))
+    }
+
+    /// Lists erasures newest first
+    pub async fn list(executor: impl sqlx::PgExecutor<'_>) -> Result<Vec<Self>, sqlx::Error> {
+        let erasures = sqlx::query_as!(
+            Erasure,
+            r#"
+            SELECT id, pseudonym_id, requested_by, reason, created_at
+            FROM erasures
+            ORDER BY created_at DESC
+            "#
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(erasures)
+    }
+}
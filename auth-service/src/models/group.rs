@@ -0,0 +1,388 @@
+use crate::models::{Role, User};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Group {
+    pub id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Group {
+    pub async fn create(
+        executor: impl sqlx::PgExecutor<'_>,
+        name: &str,
+        description: Option<&str>,
+    ) -> Result<Self, sqlx::Error> {
+        let group = sqlx::query_as!(
+            Group,
+            r#"
+            INSERT INTO groups (name, description)
+            VALUES ($1, $2)
+            RETURNING id, name, description, created_at
+            "#,
+            name,
+            description
+        )
+        .fetch_one(executor)
+        .await?;
+
+        Ok(group)
+    }
+
+    pub async fn find_by_id(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let group = sqlx::query_as!(
+            Group,
+            r#"
+            SELECT id, name, description, created_at
+            FROM groups
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(group)
+    }
+
+    pub async fn list(executor: impl sqlx::PgExecutor<'_>) -> Result<Vec<Self>, sqlx::Error> {
+        let groups = sqlx::query_as!(
+            Group,
+            r#"
+            SELECT id, name, description, created_at
+            FROM groups
+            ORDER BY name
+            "#
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(groups)
+    }
+
+    /// The groups `user_id` is a direct member of
+    pub async fn list_for_user(
+        executor: impl sqlx::PgExecutor<'_>,
+        user_id: Uuid,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let groups = sqlx::query_as!(
+            Group,
+            r#"
+            SELECT g.id, g.name, g.description, g.created_at
+            FROM groups g
+            INNER JOIN group_members gm ON g.id = gm.group_id
+            WHERE gm.user_id = $1
+            ORDER BY g.name
+            "#,
+            user_id
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(groups)
+    }
+
+    pub async fn delete(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM groups
+            WHERE id = $1
+            "#,
+            id
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_members(
+        executor: impl sqlx::PgExecutor<'_>,
+        group_id: Uuid,
+    ) -> Result<Vec<User>, sqlx::Error> {
+        let users = sqlx::query_as!(
+            User,
+            r#"
+            SELECT u.id, u.username, u.email, u.password_hash, u.created_at, u.updated_at, u.is_active,
+                   u.version, u.deleted_at
+            FROM users u
+            INNER JOIN group_members gm ON u.id = gm.user_id
+            WHERE gm.group_id = $1 AND u.deleted_at IS NULL
+            ORDER BY u.username
+            "#,
+            group_id
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(users)
+    }
+
+    /// Adds every user in `user_ids` to the group, ignoring existing memberships.
+    /// Returns the number of memberships that were newly created.
+    pub async fn add_members(
+        executor: impl sqlx::PgExecutor<'_>,
+        group_id: Uuid,
+        user_ids: &[Uuid],
+    ) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO group_members (group_id, user_id)
+            SELECT $1, UNNEST($2::uuid[])
+            ON CONFLICT (group_id, user_id) DO NOTHING
+            "#,
+            group_id,
+            user_ids
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Removes every user in `user_ids` from the group.
+    /// Returns the number of memberships that were removed.
+    pub async fn remove_members(
+        executor: impl sqlx::PgExecutor<'_>,
+        group_id: Uuid,
+        user_ids: &[Uuid],
+    ) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM group_members
+            WHERE group_id = $1 AND user_id = ANY($2::uuid[])
+            "#,
+            group_id,
+            user_ids
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn get_subgroups(
+        executor: impl sqlx::PgExecutor<'_>,
+        group_id: Uuid,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let groups = sqlx::query_as!(
+            Group,
+            r#"
+            SELECT g.id, g.name, g.description, g.created_at
+            FROM groups g
+            INNER JOIN group_subgroups gs ON g.id = gs.child_group_id
+            WHERE gs.parent_group_id = $1
+            ORDER BY g.name
+            "#,
+            group_id
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(groups)
+    }
+
+    /// Returns true if `descendant_id` is `group_id` itself or is nested
+    /// (directly or transitively) inside it.
+    pub async fn contains_group(
+        executor: impl sqlx::PgExecutor<'_>,
+        group_id: Uuid,
+        descendant_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            WITH RECURSIVE descendants AS (
+                SELECT $1::uuid AS group_id
+                UNION
+                SELECT gs.child_group_id
+                FROM group_subgroups gs
+                INNER JOIN descendants d ON gs.parent_group_id = d.group_id
+            )
+            SELECT EXISTS (
+                SELECT 1 FROM descendants WHERE group_id = $2
+            ) AS "contains!"
+            "#,
+            group_id,
+            descendant_id
+        )
+        .fetch_one(executor)
+        .await?;
+
+        Ok(row.contains)
+    }
+
+    /// Serializes changes to group nesting until the transaction ends. Row
+    /// locks on the two groups would not be enough: nesting two disjoint
+    /// pairs at once can close a cycle that neither check sees.
+    pub async fn lock_nesting(executor: impl sqlx::PgExecutor<'_>) -> Result<(), sqlx::Error> {
+        sqlx::query!("LOCK TABLE group_subgroups IN SHARE ROW EXCLUSIVE MODE")
+            .execute(executor)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Members of the groups in `group_ids` and of every group nested in
+    /// them: the users whose inherited roles change with those groups
+    pub async fn subtree_members(
+        executor: impl sqlx::PgExecutor<'_>,
+        group_ids: &[Uuid],
+    ) -> Result<Vec<User>, sqlx::Error> {
+        let users = sqlx::query_as!(
+            User,
+            r#"
+            WITH RECURSIVE subtree AS (
+                SELECT unnest($1::uuid[]) AS group_id
+                UNION
+                SELECT gs.child_group_id
+                FROM group_subgroups gs
+                INNER JOIN subtree s ON gs.parent_group_id = s.group_id
+            )
+            SELECT u.id, u.username, u.email, u.password_hash, u.created_at, u.updated_at, u.is_active,
+                   u.version, u.deleted_at
+            FROM users u
+            WHERE u.deleted_at IS NULL
+              AND u.id IN (
+                  SELECT gm.user_id
+                  FROM group_members gm
+                  INNER JOIN subtree s ON gm.group_id = s.group_id
+              )
+            ORDER BY u.username
+            "#,
+            group_ids as &[Uuid]
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(users)
+    }
+
+    /// Groups that hold `role_id`
+    pub async fn with_role(
+        executor: impl sqlx::PgExecutor<'_>,
+        role_id: Uuid,
+    ) -> Result<Vec<Uuid>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT group_id
+            FROM group_roles
+            WHERE role_id = $1
+            "#,
+            role_id
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.group_id).collect())
+    }
+
+    pub async fn add_subgroup(
+        executor: impl sqlx::PgExecutor<'_>,
+        parent_group_id: Uuid,
+        child_group_id: Uuid,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO group_subgroups (parent_group_id, child_group_id)
+            VALUES ($1, $2)
+            ON CONFLICT (parent_group_id, child_group_id) DO NOTHING
+            "#,
+            parent_group_id,
+            child_group_id
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_subgroup(
+        executor: impl sqlx::PgExecutor<'_>,
+        parent_group_id: Uuid,
+        child_group_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM group_subgroups
+            WHERE parent_group_id = $1 AND child_group_id = $2
+            "#,
+            parent_group_id,
+            child_group_id
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_roles(
+        executor: impl sqlx::PgExecutor<'_>,
+        group_id: Uuid,
+    ) -> Result<Vec<Role>, sqlx::Error> {
+        let roles = sqlx::query_as!(
+            Role,
+            r#"
+            SELECT r.id, r.name, r.description, r.created_at, r.version
+            FROM roles r
+            INNER JOIN group_roles gr ON r.id = gr.role_id
+            WHERE gr.group_id = $1
+            ORDER BY r.name
+            "#,
+            group_id
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(roles)
+    }
+
+    pub async fn assign_role(
+        executor: impl sqlx::PgExecutor<'_>,
+        group_id: Uuid,
+        role_id: Uuid,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO group_roles (group_id, role_id)
+            VALUES ($1, $2)
+            ON CONFLICT (group_id, role_id) DO NOTHING
+            "#,
+            group_id,
+            role_id
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_role(
+        executor: impl sqlx::PgExecutor<'_>,
+        group_id: Uuid,
+        role_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM group_roles
+            WHERE group_id = $1 AND role_id = $2
+            "#,
+            group_id,
+            role_id
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
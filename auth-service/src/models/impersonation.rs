@@ -0,0 +1,94 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A record of an admin minting a token to act as another user
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ImpersonationEvent {
+    pub id: Uuid,
+    pub actor_id: Uuid,
+    pub actor_username: String,
+    pub target_user_id: Uuid,
+    pub target_username: String,
+    pub reason: String,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ImpersonationEvent {
+    pub async fn create(
+        executor: impl sqlx::PgExecutor<'_>,
+        actor_id: Uuid,
+        actor_username: &str,
+        target_user_id: Uuid,
+        target_username: &str,
+        reason: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Self, sqlx::Error> {
+        let event = sqlx::query_as!(
+            ImpersonationEvent,
+            r#"
+            INSERT INTO impersonation_events
+                (actor_id, actor_username, target_user_id, target_username, reason, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, actor_id, actor_username, target_user_id, target_username,
+                      reason, expires_at, created_at
+            "#,
+            actor_id,
+            actor_username,
+            target_user_id,
+            target_username,
+            reason,
+            expires_at
+        )
+        .fetch_one(executor)
+        .await?;
+
+        Ok(event)
+    }
+
+    /// Lists events newest first, optionally restricted to one target user
+    pub async fn list(
+        executor: impl sqlx::PgExecutor<'_>,
+        target_user_id: Option<Uuid>,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let events = sqlx::query_as!(
+            ImpersonationEvent,
+            r#"
+            SELECT id, actor_id, actor_username, target_user_id, target_username,
+                   reason, expires_at, created_at
+            FROM impersonation_events
+            WHERE $1::uuid IS NULL OR target_user_id = $1
+            ORDER BY created_at DESC
+            "#,
+            target_user_id
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(events)
+    }
+
+    /// Lists events where the user acted or was acted as, newest first
+    pub async fn list_for_user(
+        executor: impl sqlx::PgExecutor<'_>,
+        user_id: Uuid,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let events = sqlx::query_as!(
+            ImpersonationEvent,
+            r#"
+            SELECT id, actor_id, actor_username, target_user_id, target_username,
+                   reason, expires_at, created_at
+            FROM impersonation_events
+            WHERE actor_id = $1 OR target_user_id = $1
+            ORDER BY created_at DESC
+            "#,
+            user_id
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(events)
+    }
+}
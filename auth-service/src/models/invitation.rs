@@ -0,0 +1,227 @@
+use crate::models::{normalize_email, Role};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Invitation {
+    pub id: Uuid,
+    pub email: String,
+    pub token_hash: String,
+    pub invited_by: Option<Uuid>,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub accepted_at: Option<DateTime<Utc>>,
+    pub accepted_user_id: Option<Uuid>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InvitationStatus {
+    Pending,
+    Accepted,
+    Revoked,
+    Expired,
+}
+
+impl Invitation {
+    pub fn status(&self) -> InvitationStatus {
+        if self.accepted_at.is_some() {
+            InvitationStatus::Accepted
+        } else if self.revoked_at.is_some() {
+            InvitationStatus::Revoked
+        } else if self.expires_at <= Utc::now() {
+            InvitationStatus::Expired
+        } else {
+            InvitationStatus::Pending
+        }
+    }
+
+    pub async fn create(
+        conn: impl sqlx::Acquire<'_, Database = sqlx::Postgres>,
+        email: &str,
+        token_hash: &str,
+        invited_by: Option<Uuid>,
+        expires_at: DateTime<Utc>,
+        role_ids: &[Uuid],
+    ) -> Result<Self, sqlx::Error> {
+        let mut tx = conn.begin().await?;
+
+        let invitation = sqlx::query_as!(
+            Invitation,
+            r#"
+            INSERT INTO invitations (email, token_hash, invited_by, expires_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, email, token_hash, invited_by, expires_at, created_at,
+                      accepted_at, accepted_user_id, revoked_at
+            "#,
+            normalize_email(email),
+            token_hash,
+            invited_by,
+            expires_at
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO invitation_roles (invitation_id, role_id)
+            SELECT $1, UNNEST($2::uuid[])
+            ON CONFLICT (invitation_id, role_id) DO NOTHING
+            "#,
+            invitation.id,
+            role_ids
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(invitation)
+    }
+
+    pub async fn find_by_id(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let invitation = sqlx::query_as!(
+            Invitation,
+            r#"
+            SELECT id, email, token_hash, invited_by, expires_at, created_at,
+                   accepted_at, accepted_user_id, revoked_at
+            FROM invitations
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(invitation)
+    }
+
+    pub async fn find_by_token_hash(
+        executor: impl sqlx::PgExecutor<'_>,
+        token_hash: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let invitation = sqlx::query_as!(
+            Invitation,
+            r#"
+            SELECT id, email, token_hash, invited_by, expires_at, created_at,
+                   accepted_at, accepted_user_id, revoked_at
+            FROM invitations
+            WHERE token_hash = $1
+            "#,
+            token_hash
+        )
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(invitation)
+    }
+
+    pub async fn list(executor: impl sqlx::PgExecutor<'_>) -> Result<Vec<Self>, sqlx::Error> {
+        let invitations = sqlx::query_as!(
+            Invitation,
+            r#"
+            SELECT id, email, token_hash, invited_by, expires_at, created_at,
+                   accepted_at, accepted_user_id, revoked_at
+            FROM invitations
+            ORDER BY created_at DESC
+            "#
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(invitations)
+    }
+
+    /// Invitations sent to `email` or accepted by `user_id`, newest first
+    pub async fn list_for_user(
+        executor: impl sqlx::PgExecutor<'_>,
+        user_id: Uuid,
+        email: &str,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let invitations = sqlx::query_as!(
+            Invitation,
+            r#"
+            SELECT id, email, token_hash, invited_by, expires_at, created_at,
+                   accepted_at, accepted_user_id, revoked_at
+            FROM invitations
+            WHERE accepted_user_id = $1 OR email = $2
+            ORDER BY created_at DESC
+            "#,
+            user_id,
+            email
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(invitations)
+    }
+
+    pub async fn get_roles(
+        executor: impl sqlx::PgExecutor<'_>,
+        invitation_id: Uuid,
+    ) -> Result<Vec<Role>, sqlx::Error> {
+        let roles = sqlx::query_as!(
+            Role,
+            r#"
+            SELECT r.id, r.name, r.description, r.created_at, r.version
+            FROM roles r
+            INNER JOIN invitation_roles ir ON r.id = ir.role_id
+            WHERE ir.invitation_id = $1
+            ORDER BY r.name
+            "#,
+            invitation_id
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(roles)
+    }
+
+    /// Marks a pending invitation as revoked. Returns false if the invitation
+    /// was already accepted or revoked.
+    pub async fn revoke(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE invitations
+            SET revoked_at = NOW()
+            WHERE id = $1 AND accepted_at IS NULL AND revoked_at IS NULL
+            "#,
+            id
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Claims a pending, unexpired invitation for `user_id`. Returns false if
+    /// the invitation was accepted, revoked or expired in the meantime.
+    pub async fn mark_accepted(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+        user_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE invitations
+            SET accepted_at = NOW(), accepted_user_id = $2
+            WHERE id = $1 AND accepted_at IS NULL AND revoked_at IS NULL AND expires_at > NOW()
+            "#,
+            id,
+            user_id
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -1,5 +1,39 @@
+//! Postgres row types and their queries.
+//!
+//! Single-statement queries take any `PgExecutor`, so they run equally on the
+//! pool or inside a caller's transaction. Functions issuing several statements
+//! take an `Acquire` and wrap them in their own transaction (a savepoint when
+//! given one that is already open).
+
+pub mod device_authorization;
+pub mod erasure;
+pub mod group;
+pub mod impersonation;
+pub mod invitation;
 pub mod permission;
+pub mod preferences;
+pub mod service_account;
+pub mod session;
+pub mod setup_token;
 pub mod user;
+pub mod user_identity;
+pub mod webhook;
 
+pub use device_authorization::{DeviceAuthorization, DeviceAuthorizationStatus};
+pub use erasure::Erasure;
+pub use group::Group;
+pub use impersonation::ImpersonationEvent;
+pub use invitation::{Invitation, InvitationStatus};
 pub use permission::{Permission, Role};
-pub use user::User;
+pub use preferences::UserPreferences;
+pub use service_account::{
+    CredentialKind, NewCredential, ServiceAccount, ServiceAccountCredential,
+};
+pub use session::Session;
+pub use setup_token::SetupToken;
+pub use user::{fold_username, normalize_email, normalize_username, User};
+pub use user_identity::UserIdentity;
+pub use webhook::{
+    same_roles, DeliveryAttempt, DeliveryStatus, DueDelivery, EventType, NewEvent, Webhook,
+    WebhookDelivery, WebhookEvent,
+};
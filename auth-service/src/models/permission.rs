@@ -1,5 +1,7 @@
+use crate::models::User;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use shared::RolePermissions;
 use sqlx::FromRow;
 use uuid::Uuid;
 
@@ -9,6 +11,8 @@ pub struct Role {
     pub name: String,
     pub description: Option<String>,
     pub created_at: DateTime<Utc>,
+    /// Incremented by every update; the admin API's ETag
+    pub version: i32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -18,6 +22,8 @@ pub struct Permission {
     pub resource: String,
     pub action: String,
     pub created_at: DateTime<Utc>,
+    /// Incremented by every update; the admin API's ETag
+    pub version: i32,
 }
 
 #[derive(Debug, Deserialize)]
@@ -35,7 +41,7 @@ pub struct CreatePermission {
 
 impl Role {
     pub async fn create(
-        pool: &sqlx::PgPool,
+        executor: impl sqlx::PgExecutor<'_>,
         name: &str,
         description: Option<&str>,
     ) -> Result<Self, sqlx::Error> {
@@ -44,75 +50,78 @@ impl Role {
             r#"
             INSERT INTO roles (name, description)
             VALUES ($1, $2)
-            RETURNING id, name, description, created_at
+            RETURNING id, name, description, created_at, version
             "#,
             name,
             description
         )
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await?;
 
         Ok(role)
     }
 
-    pub async fn find_by_id(pool: &sqlx::PgPool, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
+    pub async fn find_by_id(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
         let role = sqlx::query_as!(
             Role,
             r#"
-            SELECT id, name, description, created_at
+            SELECT id, name, description, created_at, version
             FROM roles
             WHERE id = $1
             "#,
             id
         )
-        .fetch_optional(pool)
+        .fetch_optional(executor)
         .await?;
 
         Ok(role)
     }
 
     pub async fn find_by_name(
-        pool: &sqlx::PgPool,
+        executor: impl sqlx::PgExecutor<'_>,
         name: &str,
     ) -> Result<Option<Self>, sqlx::Error> {
         let role = sqlx::query_as!(
             Role,
             r#"
-            SELECT id, name, description, created_at
+            SELECT id, name, description, created_at, version
             FROM roles
             WHERE name = $1
             "#,
             name
         )
-        .fetch_optional(pool)
+        .fetch_optional(executor)
         .await?;
 
         Ok(role)
     }
 
-    pub async fn list(pool: &sqlx::PgPool) -> Result<Vec<Self>, sqlx::Error> {
+    pub async fn list(executor: impl sqlx::PgExecutor<'_>) -> Result<Vec<Self>, sqlx::Error> {
         let roles = sqlx::query_as!(
             Role,
             r#"
-            SELECT id, name, description, created_at
+            SELECT id, name, description, created_at, version
             FROM roles
             ORDER BY name
             "#
         )
-        .fetch_all(pool)
+        .fetch_all(executor)
         .await?;
 
         Ok(roles)
     }
 
     pub async fn get_user_roles(
-        pool: &sqlx::PgPool,
+        executor: impl sqlx::PgExecutor<'_>,
         user_id: Uuid,
     ) -> Result<Vec<Self>, sqlx::Error> {
         let roles = sqlx::query_as!(
             Role,
             r#"
-            SELECT r.id, r.name, r.description, r.created_at
+            SELECT r.id, r.name, r.description, r.created_at, r.version
             FROM roles r
             INNER JOIN user_roles ur ON r.id = ur.role_id
             WHERE ur.user_id = $1
@@ -120,20 +129,91 @@ impl Role {
             "#,
             user_id
         )
-        .fetch_all(pool)
+        .fetch_all(executor)
+        .await?;
+
+        Ok(roles)
+    }
+
+    /// The roles each of `user_ids` holds directly, as (user id, role)
+    /// pairs ordered by role name
+    pub async fn get_roles_of_users(
+        executor: impl sqlx::PgExecutor<'_>,
+        user_ids: &[Uuid],
+    ) -> Result<Vec<(Uuid, Self)>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT ur.user_id, r.id, r.name, r.description, r.created_at, r.version
+            FROM roles r
+            INNER JOIN user_roles ur ON r.id = ur.role_id
+            WHERE ur.user_id = ANY($1)
+            ORDER BY r.name
+            "#,
+            user_ids
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let role = Role {
+                    id: row.id,
+                    name: row.name,
+                    description: row.description,
+                    created_at: row.created_at,
+                    version: row.version,
+                };
+                (row.user_id, role)
+            })
+            .collect())
+    }
+
+    /// Returns the roles a user holds directly together with the roles granted
+    /// through group membership, including groups nested inside other groups.
+    pub async fn get_effective_user_roles(
+        executor: impl sqlx::PgExecutor<'_>,
+        user_id: Uuid,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let roles = sqlx::query_as!(
+            Role,
+            r#"
+            WITH RECURSIVE user_groups AS (
+                SELECT gm.group_id
+                FROM group_members gm
+                WHERE gm.user_id = $1
+                UNION
+                SELECT gs.parent_group_id
+                FROM group_subgroups gs
+                INNER JOIN user_groups ug ON gs.child_group_id = ug.group_id
+            )
+            SELECT r.id, r.name, r.description, r.created_at, r.version
+            FROM roles r
+            WHERE r.id IN (
+                SELECT ur.role_id FROM user_roles ur WHERE ur.user_id = $1
+                UNION
+                SELECT gr.role_id
+                FROM group_roles gr
+                INNER JOIN user_groups ug ON gr.group_id = ug.group_id
+            )
+            ORDER BY r.name
+            "#,
+            user_id
+        )
+        .fetch_all(executor)
         .await?;
 
         Ok(roles)
     }
 
     pub async fn get_permissions(
-        pool: &sqlx::PgPool,
+        executor: impl sqlx::PgExecutor<'_>,
         role_id: Uuid,
     ) -> Result<Vec<Permission>, sqlx::Error> {
         let permissions = sqlx::query_as!(
             Permission,
             r#"
-            SELECT p.id, p.name, p.resource, p.action, p.created_at
+            SELECT p.id, p.name, p.resource, p.action, p.created_at, p.version
             FROM permissions p
             INNER JOIN role_permissions rp ON p.id = rp.permission_id
             WHERE rp.role_id = $1
@@ -141,14 +221,55 @@ impl Role {
             "#,
             role_id
         )
-        .fetch_all(pool)
+        .fetch_all(executor)
         .await?;
 
         Ok(permissions)
     }
 
+    /// The current permissions version, which triggers bump whenever role
+    /// permissions, role names or permission names change
+    pub async fn permissions_version(
+        executor: impl sqlx::PgExecutor<'_>,
+    ) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!("SELECT version FROM permissions_version")
+            .fetch_one(executor)
+            .await
+    }
+
+    /// What every role grants. The version is read first, so the mapping is
+    /// never older than the version it is labelled with.
+    pub async fn permission_map(
+        conn: impl sqlx::Acquire<'_, Database = sqlx::Postgres>,
+    ) -> Result<RolePermissions, sqlx::Error> {
+        let mut conn = conn.acquire().await?;
+
+        let version = Self::permissions_version(&mut *conn).await?;
+        let rows = sqlx::query!(
+            r#"
+            SELECT r.name AS role, p.name AS "permission?"
+            FROM roles r
+            LEFT JOIN role_permissions rp ON rp.role_id = r.id
+            LEFT JOIN permissions p ON p.id = rp.permission_id
+            ORDER BY r.name, p.name
+            "#
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let mut map = RolePermissions {
+            version,
+            ..Default::default()
+        };
+        for row in rows {
+            let permissions = map.roles.entry(row.role).or_default();
+            permissions.extend(row.permission);
+        }
+        Ok(map)
+    }
+
     pub async fn assign_permission(
-        pool: &sqlx::PgPool,
+        executor: impl sqlx::PgExecutor<'_>,
         role_id: Uuid,
         permission_id: Uuid,
     ) -> Result<(), sqlx::Error> {
@@ -161,14 +282,14 @@ impl Role {
             role_id,
             permission_id
         )
-        .execute(pool)
+        .execute(executor)
         .await?;
 
         Ok(())
     }
 
     pub async fn remove_permission(
-        pool: &sqlx::PgPool,
+        executor: impl sqlx::PgExecutor<'_>,
         role_id: Uuid,
         permission_id: Uuid,
     ) -> Result<bool, sqlx::Error> {
@@ -180,16 +301,178 @@ impl Role {
             role_id,
             permission_id
         )
-        .execute(pool)
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Returns None if the role does not exist or, when `expected_version`
+    /// is given, no longer has that version
+    pub async fn update(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+        name: &str,
+        description: Option<&str>,
+        expected_version: Option<i32>,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let role = sqlx::query_as!(
+            Role,
+            r#"
+            UPDATE roles
+            SET name = $1, description = $2, version = version + 1
+            WHERE id = $3 AND ($4::int IS NULL OR version = $4)
+            RETURNING id, name, description, created_at, version
+            "#,
+            name,
+            description,
+            id,
+            expected_version
+        )
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(role)
+    }
+
+    /// Like `update`, false also covers a version mismatch
+    pub async fn delete(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+        expected_version: Option<i32>,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM roles
+            WHERE id = $1 AND ($2::int IS NULL OR version = $2)
+            "#,
+            id,
+            expected_version
+        )
+        .execute(executor)
         .await?;
 
         Ok(result.rows_affected() > 0)
     }
+
+    /// Whether any user that is not deleted holds the role directly
+    pub async fn has_members(
+        executor: impl sqlx::PgExecutor<'_>,
+        role_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let exists = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS(
+                SELECT 1 FROM user_roles ur
+                INNER JOIN users u ON u.id = ur.user_id
+                WHERE ur.role_id = $1 AND u.deleted_at IS NULL
+            ) AS "exists!"
+            "#,
+            role_id
+        )
+        .fetch_one(executor)
+        .await?;
+
+        Ok(exists)
+    }
+
+    /// Returns the users the role is assigned to directly (not through groups)
+    pub async fn get_members(
+        executor: impl sqlx::PgExecutor<'_>,
+        role_id: Uuid,
+    ) -> Result<Vec<User>, sqlx::Error> {
+        let users = sqlx::query_as!(
+            User,
+            r#"
+            SELECT u.id, u.username, u.email, u.password_hash, u.created_at, u.updated_at, u.is_active,
+                   u.version, u.deleted_at
+            FROM users u
+            INNER JOIN user_roles ur ON u.id = ur.user_id
+            WHERE ur.role_id = $1 AND u.deleted_at IS NULL
+            ORDER BY u.username
+            "#,
+            role_id
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(users)
+    }
+
+    /// Grants the role to each of `user_ids`; returns the users not already holding it
+    pub async fn add_members(
+        executor: impl sqlx::PgExecutor<'_>,
+        role_id: Uuid,
+        user_ids: &[Uuid],
+    ) -> Result<Vec<Uuid>, sqlx::Error> {
+        let added = sqlx::query_scalar!(
+            r#"
+            INSERT INTO user_roles (user_id, role_id)
+            SELECT UNNEST($2::uuid[]), $1
+            ON CONFLICT DO NOTHING
+            RETURNING user_id
+            "#,
+            role_id,
+            user_ids
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(added)
+    }
+
+    /// Takes the role from each of `user_ids`; returns the users that held it
+    pub async fn remove_members(
+        executor: impl sqlx::PgExecutor<'_>,
+        role_id: Uuid,
+        user_ids: &[Uuid],
+    ) -> Result<Vec<Uuid>, sqlx::Error> {
+        let removed = sqlx::query_scalar!(
+            r#"
+            DELETE FROM user_roles
+            WHERE role_id = $1 AND user_id = ANY($2::uuid[])
+            RETURNING user_id
+            "#,
+            role_id,
+            user_ids
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(removed)
+    }
+
+    /// Replaces the direct members of the role with exactly `user_ids`.
+    /// Returns the users added and the users removed.
+    pub async fn set_members(
+        conn: impl sqlx::Acquire<'_, Database = sqlx::Postgres>,
+        role_id: Uuid,
+        user_ids: &[Uuid],
+    ) -> Result<(Vec<Uuid>, Vec<Uuid>), sqlx::Error> {
+        let mut tx = conn.begin().await?;
+
+        let removed = sqlx::query_scalar!(
+            r#"
+            DELETE FROM user_roles
+            WHERE role_id = $1 AND NOT (user_id = ANY($2::uuid[]))
+            RETURNING user_id
+            "#,
+            role_id,
+            user_ids
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let added = Self::add_members(&mut *tx, role_id, user_ids).await?;
+
+        tx.commit().await?;
+        Ok((added, removed))
+    }
 }
 
 impl Permission {
     pub async fn create(
-        pool: &sqlx::PgPool,
+        executor: impl sqlx::PgExecutor<'_>,
         name: &str,
         resource: &str,
         action: &str,
@@ -199,65 +482,117 @@ impl Permission {
             r#"
             INSERT INTO permissions (name, resource, action)
             VALUES ($1, $2, $3)
-            RETURNING id, name, resource, action, created_at
+            RETURNING id, name, resource, action, created_at, version
             "#,
             name,
             resource,
             action
         )
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await?;
 
         Ok(permission)
     }
 
-    pub async fn find_by_id(pool: &sqlx::PgPool, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
+    pub async fn find_by_id(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
         let permission = sqlx::query_as!(
             Permission,
             r#"
-            SELECT id, name, resource, action, created_at
+            SELECT id, name, resource, action, created_at, version
             FROM permissions
             WHERE id = $1
             "#,
             id
         )
-        .fetch_optional(pool)
+        .fetch_optional(executor)
         .await?;
 
         Ok(permission)
     }
 
     pub async fn find_by_name(
-        pool: &sqlx::PgPool,
+        executor: impl sqlx::PgExecutor<'_>,
         name: &str,
     ) -> Result<Option<Self>, sqlx::Error> {
         let permission = sqlx::query_as!(
             Permission,
             r#"
-            SELECT id, name, resource, action, created_at
+            SELECT id, name, resource, action, created_at, version
             FROM permissions
             WHERE name = $1
             "#,
             name
         )
-        .fetch_optional(pool)
+        .fetch_optional(executor)
         .await?;
 
         Ok(permission)
     }
 
-    pub async fn list(pool: &sqlx::PgPool) -> Result<Vec<Self>, sqlx::Error> {
+    pub async fn list(executor: impl sqlx::PgExecutor<'_>) -> Result<Vec<Self>, sqlx::Error> {
         let permissions = sqlx::query_as!(
             Permission,
             r#"
-            SELECT id, name, resource, action, created_at
+            SELECT id, name, resource, action, created_at, version
             FROM permissions
             ORDER BY resource, action
             "#
         )
-        .fetch_all(pool)
+        .fetch_all(executor)
         .await?;
 
         Ok(permissions)
     }
+    /// Returns None if the permission does not exist or, when
+    /// `expected_version` is given, no longer has that version
+    pub async fn update(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+        name: &str,
+        resource: &str,
+        action: &str,
+        expected_version: Option<i32>,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let permission = sqlx::query_as!(
+            Permission,
+            r#"
+            UPDATE permissions
+            SET name = $1, resource = $2, action = $3, version = version + 1
+            WHERE id = $4 AND ($5::int IS NULL OR version = $5)
+            RETURNING id, name, resource, action, created_at, version
+            "#,
+            name,
+            resource,
+            action,
+            id,
+            expected_version
+        )
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(permission)
+    }
+
+    /// Like `update`, false also covers a version mismatch
+    pub async fn delete(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+        expected_version: Option<i32>,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM permissions
+            WHERE id = $1 AND ($2::int IS NULL OR version = $2)
+            "#,
+            id,
+            expected_version
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
 }
@@ -0,0 +1,99 @@
+use chrono::{DateTime, Utc};
+use shared::Preferences;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A user's saved preferences as stored; the API deals in
+/// [`shared::Preferences`]
+#[derive(Debug, Clone, FromRow)]
+pub struct UserPreferences {
+    pub user_id: Uuid,
+    pub units: Option<String>,
+    pub locale: Option<String>,
+    pub time_format: Option<String>,
+    pub home_timezone: Option<String>,
+    pub favourite_cities: Vec<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<UserPreferences> for Preferences {
+    fn from(row: UserPreferences) -> Self {
+        Self {
+            // Written from the validated enums, so these always parse
+            units: row.units.and_then(|u| u.parse().ok()),
+            locale: row.locale,
+            time_format: row.time_format.and_then(|f| f.parse().ok()),
+            home_timezone: row.home_timezone,
+            favourite_cities: row.favourite_cities,
+        }
+    }
+}
+
+impl UserPreferences {
+    pub async fn find(
+        executor: impl sqlx::PgExecutor<'_>,
+        user_id: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let preferences = sqlx::query_as!(
+            UserPreferences,
+            r#"
+            SELECT user_id, units, locale, time_format, home_timezone, favourite_cities,
+                   updated_at
+            FROM user_preferences
+            WHERE user_id = $1
+            "#,
+            user_id
+        )
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(preferences)
+    }
+
+    /// Replaces the user's preferences, creating them if they have none
+    pub async fn upsert(
+        executor: impl sqlx::PgExecutor<'_>,
+        user_id: Uuid,
+        preferences: &Preferences,
+    ) -> Result<Self, sqlx::Error> {
+        let preferences = sqlx::query_as!(
+            UserPreferences,
+            r#"
+            INSERT INTO user_preferences
+                (user_id, units, locale, time_format, home_timezone, favourite_cities)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (user_id) DO UPDATE
+            SET units = EXCLUDED.units,
+                locale = EXCLUDED.locale,
+                time_format = EXCLUDED.time_format,
+                home_timezone = EXCLUDED.home_timezone,
+                favourite_cities = EXCLUDED.favourite_cities,
+                updated_at = NOW()
+            RETURNING user_id, units, locale, time_format, home_timezone, favourite_cities,
+                      updated_at
+            "#,
+            user_id,
+            preferences.units.map(|u| u.as_str()),
+            preferences.locale.as_deref(),
+            preferences.time_format.map(|f| f.as_str()),
+            preferences.home_timezone.as_deref(),
+            &preferences.favourite_cities
+        )
+        .fetch_one(executor)
+        .await?;
+
+        Ok(preferences)
+    }
+
+    /// Returns false if the user had no saved preferences
+    pub async fn delete(
+        executor: impl sqlx::PgExecutor<'_>,
+        user_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!("DELETE FROM user_preferences WHERE user_id = $1", user_id)
+            .execute(executor)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
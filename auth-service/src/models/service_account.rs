@@ -0,0 +1,359 @@
+use crate::models::Role;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A non-human principal: it holds roles but has no password, and gets its
+/// tokens with one of its credentials
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ServiceAccount {
+    pub id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    /// The user answerable for the account; None once that user is purged
+    pub owner_id: Option<Uuid>,
+    pub is_active: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CredentialKind {
+    /// A generated client secret, stored hashed
+    Secret,
+    /// A public key verifying signed JWT assertions (RFC 7523)
+    PublicKey,
+}
+
+impl CredentialKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Secret => "secret",
+            Self::PublicKey => "public_key",
+        }
+    }
+}
+
+/// A credential to be added to a service account
+#[derive(Debug, Clone, Copy)]
+pub enum NewCredential<'a> {
+    Secret { hash: &'a str },
+    PublicKey { pem: &'a str, algorithm: &'a str },
+}
+
+impl NewCredential<'_> {
+    pub fn kind(&self) -> CredentialKind {
+        match self {
+            Self::Secret { .. } => CredentialKind::Secret,
+            Self::PublicKey { .. } => CredentialKind::PublicKey,
+        }
+    }
+
+    /// Values for the `secret_hash`, `public_key` and `algorithm` columns
+    pub fn columns(&self) -> (Option<&str>, Option<&str>, Option<&str>) {
+        match *self {
+            Self::Secret { hash } => (Some(hash), None, None),
+            Self::PublicKey { pem, algorithm } => (None, Some(pem), Some(algorithm)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ServiceAccountCredential {
+    pub id: Uuid,
+    pub service_account_id: Uuid,
+    /// `secret` or `public_key`
+    pub kind: String,
+    #[serde(skip_serializing)]
+    pub secret_hash: Option<String>,
+    /// PEM-encoded public key
+    pub public_key: Option<String>,
+    /// JWS algorithm of the key, such as RS256 or ES256
+    pub algorithm: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl ServiceAccountCredential {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| at <= Utc::now())
+    }
+}
+
+impl ServiceAccount {
+    pub async fn create(
+        executor: impl sqlx::PgExecutor<'_>,
+        name: &str,
+        description: Option<&str>,
+        owner_id: Option<Uuid>,
+    ) -> Result<Self, sqlx::Error> {
+        let account = sqlx::query_as!(
+            ServiceAccount,
+            r#"
+            INSERT INTO service_accounts (name, description, owner_id)
+            VALUES ($1, $2, $3)
+            RETURNING id, name, description, owner_id, is_active, created_at, updated_at
+            "#,
+            name,
+            description,
+            owner_id
+        )
+        .fetch_one(executor)
+        .await?;
+
+        Ok(account)
+    }
+
+    pub async fn find_by_id(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let account = sqlx::query_as!(
+            ServiceAccount,
+            r#"
+            SELECT id, name, description, owner_id, is_active, created_at, updated_at
+            FROM service_accounts
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(account)
+    }
+
+    pub async fn list(executor: impl sqlx::PgExecutor<'_>) -> Result<Vec<Self>, sqlx::Error> {
+        let accounts = sqlx::query_as!(
+            ServiceAccount,
+            r#"
+            SELECT id, name, description, owner_id, is_active, created_at, updated_at
+            FROM service_accounts
+            ORDER BY name
+            "#
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(accounts)
+    }
+
+    pub async fn update(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+        description: Option<&str>,
+        owner_id: Option<Uuid>,
+        is_active: bool,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let account = sqlx::query_as!(
+            ServiceAccount,
+            r#"
+            UPDATE service_accounts
+            SET description = $2, owner_id = $3, is_active = $4, updated_at = NOW()
+            WHERE id = $1
+            RETURNING id, name, description, owner_id, is_active, created_at, updated_at
+            "#,
+            id,
+            description,
+            owner_id,
+            is_active
+        )
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(account)
+    }
+
+    /// Deletes the account along with its roles and credentials
+    pub async fn delete(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!("DELETE FROM service_accounts WHERE id = $1", id)
+            .execute(executor)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn assign_role(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+        role_id: Uuid,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO service_account_roles (service_account_id, role_id)
+            VALUES ($1, $2)
+            ON CONFLICT (service_account_id, role_id) DO NOTHING
+            "#,
+            id,
+            role_id
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_role(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+        role_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM service_account_roles WHERE service_account_id = $1 AND role_id = $2",
+            id,
+            role_id
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_roles(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+    ) -> Result<Vec<Role>, sqlx::Error> {
+        let roles = sqlx::query_as!(
+            Role,
+            r#"
+            SELECT r.id, r.name, r.description, r.created_at, r.version
+            FROM roles r
+            INNER JOIN service_account_roles sar ON sar.role_id = r.id
+            WHERE sar.service_account_id = $1
+            ORDER BY r.name
+            "#,
+            id
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(roles)
+    }
+
+    pub async fn add_credential(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+        credential: NewCredential<'_>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<ServiceAccountCredential, sqlx::Error> {
+        let (secret_hash, public_key, algorithm) = credential.columns();
+        let credential = sqlx::query_as!(
+            ServiceAccountCredential,
+            r#"
+            INSERT INTO service_account_credentials
+                (service_account_id, kind, secret_hash, public_key, algorithm, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, service_account_id, kind, secret_hash, public_key, algorithm,
+                      created_at, expires_at, last_used_at
+            "#,
+            id,
+            credential.kind().as_str(),
+            secret_hash,
+            public_key,
+            algorithm,
+            expires_at
+        )
+        .fetch_one(executor)
+        .await?;
+
+        Ok(credential)
+    }
+
+    /// The account's credentials, expired ones included, newest first
+    pub async fn list_credentials(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+    ) -> Result<Vec<ServiceAccountCredential>, sqlx::Error> {
+        let credentials = sqlx::query_as!(
+            ServiceAccountCredential,
+            r#"
+            SELECT id, service_account_id, kind, secret_hash, public_key, algorithm,
+                   created_at, expires_at, last_used_at
+            FROM service_account_credentials
+            WHERE service_account_id = $1
+            ORDER BY created_at DESC
+            "#,
+            id
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(credentials)
+    }
+
+    pub async fn delete_credential(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+        credential_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM service_account_credentials WHERE id = $1 AND service_account_id = $2",
+            credential_id,
+            id
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn touch_credential(
+        executor: impl sqlx::PgExecutor<'_>,
+        credential_id: Uuid,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE service_account_credentials SET last_used_at = NOW() WHERE id = $1",
+            credential_id
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Records a client assertion's `jti`; false if an unexpired assertion
+    /// of the account already used it. An expired record is taken over, as
+    /// the assertion it belonged to can no longer be replayed anyway.
+    pub async fn use_assertion(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO used_client_assertions (service_account_id, jti, expires_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (service_account_id, jti) DO UPDATE
+                SET expires_at = EXCLUDED.expires_at
+                WHERE used_client_assertions.expires_at <= NOW()
+            "#,
+            id,
+            jti,
+            expires_at
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub async fn purge_used_assertions(
+        executor: impl sqlx::PgExecutor<'_>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM used_client_assertions WHERE expires_at <= $1",
+            before
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,151 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl Session {
+    pub async fn create(
+        executor: impl sqlx::PgExecutor<'_>,
+        user_id: Uuid,
+        user_agent: Option<&str>,
+        ip_address: Option<&str>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Self, sqlx::Error> {
+        let session = sqlx::query_as!(
+            Session,
+            r#"
+            INSERT INTO sessions (user_id, user_agent, ip_address, expires_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, user_agent, ip_address, created_at, last_used_at,
+                      expires_at, revoked_at
+            "#,
+            user_id,
+            user_agent,
+            ip_address,
+            expires_at
+        )
+        .fetch_one(executor)
+        .await?;
+
+        Ok(session)
+    }
+
+    /// Lists all of a user's sessions, revoked and expired ones included,
+    /// newest first
+    pub async fn list_for_user(
+        executor: impl sqlx::PgExecutor<'_>,
+        user_id: Uuid,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let sessions = sqlx::query_as!(
+            Session,
+            r#"
+            SELECT id, user_id, user_agent, ip_address, created_at, last_used_at,
+                   expires_at, revoked_at
+            FROM sessions
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+            "#,
+            user_id
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(sessions)
+    }
+
+    /// Lists a user's sessions that are neither revoked nor expired, most recently used first
+    pub async fn list_active_for_user(
+        executor: impl sqlx::PgExecutor<'_>,
+        user_id: Uuid,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let sessions = sqlx::query_as!(
+            Session,
+            r#"
+            SELECT id, user_id, user_agent, ip_address, created_at, last_used_at,
+                   expires_at, revoked_at
+            FROM sessions
+            WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > NOW()
+            ORDER BY last_used_at DESC
+            "#,
+            user_id
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(sessions)
+    }
+
+    /// Records use of a session. Returns false if the session is revoked,
+    /// expired or does not exist, in which case its tokens must be rejected.
+    pub async fn touch(executor: impl sqlx::PgExecutor<'_>, id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET last_used_at = NOW()
+            WHERE id = $1 AND revoked_at IS NULL AND expires_at > NOW()
+            "#,
+            id
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Revokes one of a user's sessions. Returns false if it does not belong
+    /// to the user or is already revoked.
+    pub async fn revoke(
+        executor: impl sqlx::PgExecutor<'_>,
+        user_id: Uuid,
+        id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET revoked_at = NOW()
+            WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+            "#,
+            id,
+            user_id
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Revokes every active session of a user, optionally sparing one.
+    /// Returns the number of sessions revoked.
+    pub async fn revoke_all_for_user(
+        executor: impl sqlx::PgExecutor<'_>,
+        user_id: Uuid,
+        except: Option<Uuid>,
+    ) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET revoked_at = NOW()
+            WHERE user_id = $1 AND revoked_at IS NULL
+              AND ($2::uuid IS NULL OR id <> $2)
+            "#,
+            user_id,
+            except
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
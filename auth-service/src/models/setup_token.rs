@@ -0,0 +1,60 @@
+use chrono::{DateTime, Utc};
+
+/// The outstanding one-time token for creating the first admin; at most one
+/// exists at a time and only its hash is stored
+pub struct SetupToken;
+
+impl SetupToken {
+    /// Replaces any outstanding setup token with a new one
+    pub async fn replace(
+        conn: impl sqlx::Acquire<'_, Database = sqlx::Postgres>,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = conn.begin().await?;
+
+        sqlx::query!("DELETE FROM setup_tokens")
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO setup_tokens (token_hash, expires_at)
+            VALUES ($1, $2)
+            "#,
+            token_hash,
+            expires_at
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await
+    }
+
+    /// Deletes the token if it matches and has not expired. Returns false
+    /// otherwise, so concurrent attempts with the same token succeed only once.
+    pub async fn consume(
+        executor: impl sqlx::PgExecutor<'_>,
+        token_hash: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM setup_tokens
+            WHERE token_hash = $1 AND expires_at > NOW()
+            "#,
+            token_hash
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn clear(executor: impl sqlx::PgExecutor<'_>) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM setup_tokens")
+            .execute(executor)
+            .await?;
+
+        Ok(())
+    }
+}
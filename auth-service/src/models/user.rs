@@ -1,6 +1,7 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use unicode_normalization::UnicodeNormalization;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -12,6 +13,23 @@ pub struct User {
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub is_active: bool,
+    /// Incremented by every update; the admin API's ETag
+    pub version: i32,
+    /// Set while the user is soft-deleted. Lookups skip such users; they can
+    /// be restored until purged after the retention period.
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+/// A user together with the names of their directly assigned roles
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct UserWithRoles {
+    pub id: Uuid,
+    pub username: String,
+    pub email: String,
+    pub password_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub is_active: bool,
+    pub roles: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -29,9 +47,25 @@ pub struct UpdateUser {
     pub is_active: Option<bool>,
 }
 
+/// Usernames are stored NFKC-normalized, keeping the case they were given in
+pub fn normalize_username(username: &str) -> String {
+    username.nfkc().collect()
+}
+
+/// The form usernames are unique by and looked up by: NFKC, then lowercased
+pub fn fold_username(username: &str) -> String {
+    normalize_username(username).to_lowercase().nfkc().collect()
+}
+
+/// Emails are stored trimmed, NFKC-normalized and lowercased, so addresses
+/// differing only in case belong to the same account
+pub fn normalize_email(email: &str) -> String {
+    email.trim().nfkc().collect::<String>().to_lowercase()
+}
+
 impl User {
     pub async fn create(
-        pool: &sqlx::PgPool,
+        executor: impl sqlx::PgExecutor<'_>,
         username: &str,
         email: &str,
         password_hash: &str,
@@ -39,144 +73,384 @@ impl User {
         let user = sqlx::query_as!(
             User,
             r#"
-            INSERT INTO users (username, email, password_hash)
-            VALUES ($1, $2, $3)
-            RETURNING id, username, email, password_hash, created_at, updated_at, is_active
+            INSERT INTO users (username, username_folded, email, password_hash)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, username, email, password_hash, created_at, updated_at, is_active,
+                   version, deleted_at
             "#,
-            username,
-            email,
+            normalize_username(username),
+            fold_username(username),
+            normalize_email(email),
             password_hash
         )
-        .fetch_one(pool)
+        .fetch_one(executor)
+        .await?;
+
+        Ok(user)
+    }
+
+    /// Creates a user carried over from another system, keeping its
+    /// password hash and active flag
+    pub async fn create_imported(
+        executor: impl sqlx::PgExecutor<'_>,
+        username: &str,
+        email: &str,
+        password_hash: &str,
+        is_active: bool,
+    ) -> Result<Self, sqlx::Error> {
+        let user = sqlx::query_as!(
+            User,
+            r#"
+            INSERT INTO users (username, username_folded, email, password_hash, is_active)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, username, email, password_hash, created_at, updated_at, is_active,
+                   version, deleted_at
+            "#,
+            normalize_username(username),
+            fold_username(username),
+            normalize_email(email),
+            password_hash,
+            is_active
+        )
+        .fetch_one(executor)
         .await?;
 
         Ok(user)
     }
 
-    pub async fn find_by_id(pool: &sqlx::PgPool, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
+    pub async fn find_by_id(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
         let user = sqlx::query_as!(
             User,
             r#"
-            SELECT id, username, email, password_hash, created_at, updated_at, is_active
+            SELECT id, username, email, password_hash, created_at, updated_at, is_active,
+                   version, deleted_at
+            FROM users
+            WHERE id = $1 AND deleted_at IS NULL
+            "#,
+            id
+        )
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(user)
+    }
+
+    /// Like `find_by_id`, soft-deleted users included
+    pub async fn find_including_deleted(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let user = sqlx::query_as!(
+            User,
+            r#"
+            SELECT id, username, email, password_hash, created_at, updated_at, is_active,
+                   version, deleted_at
             FROM users
             WHERE id = $1
             "#,
             id
         )
-        .fetch_optional(pool)
+        .fetch_optional(executor)
         .await?;
 
         Ok(user)
     }
 
     pub async fn find_by_username(
-        pool: &sqlx::PgPool,
+        executor: impl sqlx::PgExecutor<'_>,
         username: &str,
     ) -> Result<Option<Self>, sqlx::Error> {
         let user = sqlx::query_as!(
             User,
             r#"
-            SELECT id, username, email, password_hash, created_at, updated_at, is_active
+            SELECT id, username, email, password_hash, created_at, updated_at, is_active,
+                   version, deleted_at
             FROM users
-            WHERE username = $1
+            WHERE username_folded = $1 AND deleted_at IS NULL
             "#,
-            username
+            fold_username(username)
         )
-        .fetch_optional(pool)
+        .fetch_optional(executor)
         .await?;
 
         Ok(user)
     }
 
     pub async fn find_by_email(
-        pool: &sqlx::PgPool,
+        executor: impl sqlx::PgExecutor<'_>,
         email: &str,
     ) -> Result<Option<Self>, sqlx::Error> {
         let user = sqlx::query_as!(
             User,
             r#"
-            SELECT id, username, email, password_hash, created_at, updated_at, is_active
+            SELECT id, username, email, password_hash, created_at, updated_at, is_active,
+                   version, deleted_at
             FROM users
-            WHERE email = $1
+            WHERE email = $1 AND deleted_at IS NULL
             "#,
-            email
+            normalize_email(email)
         )
-        .fetch_optional(pool)
+        .fetch_optional(executor)
         .await?;
 
         Ok(user)
     }
 
-    pub async fn list(pool: &sqlx::PgPool) -> Result<Vec<Self>, sqlx::Error> {
+    pub async fn find_by_ids(
+        executor: impl sqlx::PgExecutor<'_>,
+        ids: &[Uuid],
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let users = sqlx::query_as!(
+            User,
+            r#"
+            SELECT id, username, email, password_hash, created_at, updated_at, is_active,
+                   version, deleted_at
+            FROM users
+            WHERE id = ANY($1::uuid[]) AND deleted_at IS NULL
+            "#,
+            ids
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(users)
+    }
+
+    /// Returns users whose username or email is in the given lists, ignoring
+    /// case, including soft-deleted ones since they still hold their username
+    /// and email
+    pub async fn find_by_usernames_or_emails(
+        executor: impl sqlx::PgExecutor<'_>,
+        usernames: &[String],
+        emails: &[String],
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let users = sqlx::query_as!(
+            User,
+            r#"
+            SELECT id, username, email, password_hash, created_at, updated_at, is_active,
+                   version, deleted_at
+            FROM users
+            WHERE username_folded = ANY($1::varchar[]) OR email = ANY($2::varchar[])
+            "#,
+            &usernames
+                .iter()
+                .map(|u| fold_username(u))
+                .collect::<Vec<_>>(),
+            &emails
+                .iter()
+                .map(|e| normalize_email(e))
+                .collect::<Vec<_>>()
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(users)
+    }
+
+    pub async fn list(executor: impl sqlx::PgExecutor<'_>) -> Result<Vec<Self>, sqlx::Error> {
         let users = sqlx::query_as!(
             User,
             r#"
-            SELECT id, username, email, password_hash, created_at, updated_at, is_active
+            SELECT id, username, email, password_hash, created_at, updated_at, is_active,
+                   version, deleted_at
             FROM users
+            WHERE deleted_at IS NULL
             ORDER BY created_at DESC
             "#
         )
-        .fetch_all(pool)
+        .fetch_all(executor)
+        .await?;
+
+        Ok(users)
+    }
+
+    /// Returns up to `limit` users ordered by id, starting after `after`,
+    /// for paging through every user without holding a long-lived cursor
+    pub async fn list_with_roles_page(
+        executor: impl sqlx::PgExecutor<'_>,
+        after: Option<Uuid>,
+        limit: i64,
+    ) -> Result<Vec<UserWithRoles>, sqlx::Error> {
+        let users = sqlx::query_as!(
+            UserWithRoles,
+            r#"
+            SELECT u.id, u.username, u.email, u.password_hash, u.created_at, u.is_active,
+                   COALESCE(
+                       ARRAY_AGG(r.name ORDER BY r.name) FILTER (WHERE r.name IS NOT NULL),
+                       '{}'
+                   ) AS "roles!"
+            FROM users u
+            LEFT JOIN user_roles ur ON u.id = ur.user_id
+            LEFT JOIN roles r ON r.id = ur.role_id
+            WHERE u.deleted_at IS NULL AND ($1::uuid IS NULL OR u.id > $1)
+            GROUP BY u.id
+            ORDER BY u.id
+            LIMIT $2
+            "#,
+            after,
+            limit
+        )
+        .fetch_all(executor)
         .await?;
 
         Ok(users)
     }
 
+    /// Applies the fields that are set in `update`; `password` must already
+    /// be hashed. Returns None if the user does not exist or, when
+    /// `expected_version` is given, no longer has that version.
     pub async fn update(
-        pool: &sqlx::PgPool,
+        executor: impl sqlx::PgExecutor<'_>,
         id: Uuid,
         update: &UpdateUser,
+        expected_version: Option<i32>,
     ) -> Result<Option<Self>, sqlx::Error> {
-        let mut user = match Self::find_by_id(pool, id).await? {
-            Some(u) => u,
-            None => return Ok(None),
-        };
-
-        if let Some(ref username) = update.username {
-            user.username = username.clone();
-        }
-        if let Some(ref email) = update.email {
-            user.email = email.clone();
-        }
-        if let Some(ref password_hash) = update.password {
-            user.password_hash = password_hash.clone();
-        }
-        if let Some(is_active) = update.is_active {
-            user.is_active = is_active;
-        }
-
         let updated = sqlx::query_as!(
             User,
             r#"
             UPDATE users
-            SET username = $1, email = $2, password_hash = COALESCE($3, password_hash),
-                is_active = $4, updated_at = NOW()
-            WHERE id = $5
-            RETURNING id, username, email, password_hash, created_at, updated_at, is_active
+            SET username = COALESCE($1, username),
+                username_folded = COALESCE($2, username_folded),
+                email = COALESCE($3, email), password_hash = COALESCE($4, password_hash),
+                is_active = COALESCE($5, is_active), updated_at = NOW(),
+                version = version + 1
+            WHERE id = $6 AND deleted_at IS NULL AND ($7::int IS NULL OR version = $7)
+            RETURNING id, username, email, password_hash, created_at, updated_at, is_active,
+                   version, deleted_at
             "#,
-            user.username,
-            user.email,
+            update.username.as_deref().map(normalize_username),
+            update.username.as_deref().map(fold_username),
+            update.email.as_deref().map(normalize_email),
             update.password.as_ref(),
-            user.is_active,
-            id
+            update.is_active,
+            id,
+            expected_version
         )
-        .fetch_optional(pool)
+        .fetch_optional(executor)
         .await?;
 
         Ok(updated)
     }
 
-    pub async fn delete(pool: &sqlx::PgPool, id: Uuid) -> Result<bool, sqlx::Error> {
+    /// Grants every role in `role_ids` to the user, ignoring roles already
+    /// held. Returns the roles that were newly granted.
+    pub async fn assign_roles(
+        executor: impl sqlx::PgExecutor<'_>,
+        user_id: Uuid,
+        role_ids: &[Uuid],
+    ) -> Result<Vec<Uuid>, sqlx::Error> {
+        let granted = sqlx::query_scalar!(
+            r#"
+            INSERT INTO user_roles (user_id, role_id)
+            SELECT $1, UNNEST($2::uuid[])
+            ON CONFLICT DO NOTHING
+            RETURNING role_id
+            "#,
+            user_id,
+            role_ids
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(granted)
+    }
+
+    pub async fn remove_role(
+        executor: impl sqlx::PgExecutor<'_>,
+        user_id: Uuid,
+        role_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM user_roles WHERE user_id = $1 AND role_id = $2",
+            user_id,
+            role_id
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Marks the user deleted. Like `update`, false also covers a version
+    /// mismatch; an already deleted user is treated as missing.
+    pub async fn soft_delete(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+        expected_version: Option<i32>,
+    ) -> Result<bool, sqlx::Error> {
         let result = sqlx::query!(
             r#"
-            DELETE FROM users
-            WHERE id = $1
+            UPDATE users
+            SET deleted_at = NOW(), updated_at = NOW(), version = version + 1
+            WHERE id = $1 AND deleted_at IS NULL AND ($2::int IS NULL OR version = $2)
             "#,
-            id
+            id,
+            expected_version
         )
-        .execute(pool)
+        .execute(executor)
         .await?;
 
         Ok(result.rows_affected() > 0)
     }
+
+    /// Undoes `soft_delete`; None if no deleted user has this id
+    pub async fn restore(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let user = sqlx::query_as!(
+            User,
+            r#"
+            UPDATE users
+            SET deleted_at = NULL, updated_at = NOW(), version = version + 1
+            WHERE id = $1 AND deleted_at IS NOT NULL
+            RETURNING id, username, email, password_hash, created_at, updated_at, is_active,
+                   version, deleted_at
+            "#,
+            id
+        )
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(user)
+    }
+
+    /// Soft-deleted users, most recently deleted first
+    pub async fn list_deleted(
+        executor: impl sqlx::PgExecutor<'_>,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let users = sqlx::query_as!(
+            User,
+            r#"
+            SELECT id, username, email, password_hash, created_at, updated_at, is_active,
+                   version, deleted_at
+            FROM users
+            WHERE deleted_at IS NOT NULL
+            ORDER BY deleted_at DESC
+            "#
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(users)
+    }
+
+    /// Permanently removes users soft-deleted before `before`, cascading to
+    /// their role assignments, group memberships and sessions
+    pub async fn purge_deleted(
+        executor: impl sqlx::PgExecutor<'_>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM users WHERE deleted_at IS NOT NULL AND deleted_at < $1",
+            before
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
 }
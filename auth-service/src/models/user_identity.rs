@@ -0,0 +1,115 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// An account at an upstream OpenID Connect provider, linked to a local user
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct UserIdentity {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    /// The provider's configured name
+    pub provider: String,
+    /// The provider's identifier for the account (`sub`)
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_login_at: Option<DateTime<Utc>>,
+}
+
+impl UserIdentity {
+    pub async fn create(
+        executor: impl sqlx::PgExecutor<'_>,
+        user_id: Uuid,
+        provider: &str,
+        subject: &str,
+        email: Option<&str>,
+    ) -> Result<Self, sqlx::Error> {
+        let identity = sqlx::query_as!(
+            UserIdentity,
+            r#"
+            INSERT INTO user_identities (user_id, provider, subject, email)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, provider, subject, email, created_at, last_login_at
+            "#,
+            user_id,
+            provider,
+            subject,
+            email
+        )
+        .fetch_one(executor)
+        .await?;
+
+        Ok(identity)
+    }
+
+    pub async fn find(
+        executor: impl sqlx::PgExecutor<'_>,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let identity = sqlx::query_as!(
+            UserIdentity,
+            r#"
+            SELECT id, user_id, provider, subject, email, created_at, last_login_at
+            FROM user_identities
+            WHERE provider = $1 AND subject = $2
+            "#,
+            provider,
+            subject
+        )
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(identity)
+    }
+
+    pub async fn list_for_user(
+        executor: impl sqlx::PgExecutor<'_>,
+        user_id: Uuid,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let identities = sqlx::query_as!(
+            UserIdentity,
+            r#"
+            SELECT id, user_id, provider, subject, email, created_at, last_login_at
+            FROM user_identities
+            WHERE user_id = $1
+            ORDER BY created_at
+            "#,
+            user_id
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(identities)
+    }
+
+    /// Records a sign-in through the identity
+    pub async fn touch(executor: impl sqlx::PgExecutor<'_>, id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE user_identities SET last_login_at = NOW() WHERE id = $1",
+            id
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Unlinks one of `user_id`'s identities; false if it has no such one
+    pub async fn delete(
+        executor: impl sqlx::PgExecutor<'_>,
+        user_id: Uuid,
+        id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM user_identities WHERE id = $1 AND user_id = $2",
+            id,
+            user_id
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,623 @@
+use crate::models::{Role, User};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{FromRow, PgConnection};
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// The identity lifecycle events webhooks can subscribe to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EventType {
+    #[serde(rename = "user.created")]
+    UserCreated,
+    #[serde(rename = "user.activated")]
+    UserActivated,
+    #[serde(rename = "user.deactivated")]
+    UserDeactivated,
+    #[serde(rename = "user.deleted")]
+    UserDeleted,
+    #[serde(rename = "user.restored")]
+    UserRestored,
+    #[serde(rename = "user.role_assigned")]
+    UserRoleAssigned,
+    #[serde(rename = "user.role_removed")]
+    UserRoleRemoved,
+    #[serde(rename = "user.roles_changed")]
+    UserRolesChanged,
+    #[serde(rename = "user.erased")]
+    UserErased,
+}
+
+impl EventType {
+    pub const ALL: [EventType; 9] = [
+        Self::UserCreated,
+        Self::UserActivated,
+        Self::UserDeactivated,
+        Self::UserDeleted,
+        Self::UserRestored,
+        Self::UserRoleAssigned,
+        Self::UserRoleRemoved,
+        Self::UserRolesChanged,
+        Self::UserErased,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::UserCreated => "user.created",
+            Self::UserActivated => "user.activated",
+            Self::UserDeactivated => "user.deactivated",
+            Self::UserDeleted => "user.deleted",
+            Self::UserRestored => "user.restored",
+            Self::UserRoleAssigned => "user.role_assigned",
+            Self::UserRoleRemoved => "user.role_removed",
+            Self::UserRolesChanged => "user.roles_changed",
+            Self::UserErased => "user.erased",
+        }
+    }
+}
+
+impl FromStr for EventType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|t| t.as_str() == s)
+            .ok_or_else(|| format!("unknown event type '{s}'"))
+    }
+}
+
+/// An event to add to the outbox, in the transaction making the change it describes
+#[derive(Debug, Clone)]
+pub struct NewEvent {
+    pub event_type: EventType,
+    /// The `data` of the delivered event
+    pub payload: Value,
+}
+
+fn user_json(user: &User) -> Value {
+    json!({
+        "id": user.id,
+        "username": user.username,
+        "email": user.email,
+        "is_active": user.is_active,
+    })
+}
+
+/// Whether two lists of effective roles name the same roles, in any order
+pub fn same_roles(a: &[Role], b: &[Role]) -> bool {
+    let mut a: Vec<Uuid> = a.iter().map(|role| role.id).collect();
+    let mut b: Vec<Uuid> = b.iter().map(|role| role.id).collect();
+    a.sort();
+    b.sort();
+    a == b
+}
+
+impl NewEvent {
+    pub fn user(event_type: EventType, user: &User) -> Self {
+        Self {
+            event_type,
+            payload: json!({ "user": user_json(user) }),
+        }
+    }
+
+    pub fn role(event_type: EventType, user: &User, role: &Role) -> Self {
+        Self {
+            event_type,
+            payload: json!({
+                "user": user_json(user),
+                "role": { "id": role.id, "name": role.name },
+            }),
+        }
+    }
+
+    /// `user.roles_changed`, with the effective roles the user holds now
+    pub fn roles_changed(user: &User, roles: &[Role]) -> Self {
+        let roles: Vec<Value> = roles
+            .iter()
+            .map(|role| json!({ "id": role.id, "name": role.name }))
+            .collect();
+        Self {
+            event_type: EventType::UserRolesChanged,
+            payload: json!({ "user": user_json(user), "roles": roles }),
+        }
+    }
+
+    /// `user.erased`, naming only the user's id so consumers can erase
+    /// their own copies
+    pub fn erasure(user_id: Uuid) -> Self {
+        Self {
+            event_type: EventType::UserErased,
+            payload: json!({ "user": { "id": user_id } }),
+        }
+    }
+
+    /// `user.activated` or `user.deactivated` if an update flipped `is_active`
+    pub fn activation(before: &User, after: &User) -> Option<Self> {
+        match (before.is_active, after.is_active) {
+            (false, true) => Some(Self::user(EventType::UserActivated, after)),
+            (true, false) => Some(Self::user(EventType::UserDeactivated, after)),
+            _ => None,
+        }
+    }
+}
+
+/// An endpoint that lifecycle events are posted to
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    pub id: Uuid,
+    pub url: String,
+    /// Key for the delivery signatures
+    pub secret: String,
+    /// Event types delivered to the webhook; empty means all of them
+    pub event_types: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Webhook {
+    pub fn subscribes_to(&self, event_type: &str) -> bool {
+        self.event_types.is_empty() || self.event_types.iter().any(|t| t == event_type)
+    }
+}
+
+/// A row of the outbox
+#[derive(Debug, Clone, FromRow)]
+pub struct WebhookEvent {
+    pub id: Uuid,
+    pub event_type: String,
+    /// The event's `data` as JSON text
+    pub payload: String,
+    pub created_at: DateTime<Utc>,
+    /// When the event was fanned out into deliveries
+    pub dispatched_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    /// Waiting for its first attempt or a retry
+    Pending,
+    Succeeded,
+    /// Out of attempts; it is only sent again if redelivered
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// The delivery log entry for one event sent to one webhook
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    pub event_id: Uuid,
+    pub event_type: String,
+    pub status: String,
+    pub attempts: i32,
+    /// When a pending delivery is next tried
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    /// HTTP status of the last attempt, if the endpoint answered
+    pub response_status: Option<i32>,
+    /// Why the last attempt failed
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A delivery claimed by the dispatcher, with what it needs to send it
+#[derive(Debug, Clone, FromRow)]
+pub struct DueDelivery {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    pub url: String,
+    pub secret: String,
+    pub event_id: Uuid,
+    pub event_type: String,
+    pub payload: String,
+    pub event_created_at: DateTime<Utc>,
+    /// Attempts made so far, including the one being made now
+    pub attempts: i32,
+}
+
+/// The outcome of one attempt at a delivery
+#[derive(Debug, Clone)]
+pub struct DeliveryAttempt {
+    pub status: DeliveryStatus,
+    /// Set while the delivery stays pending
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub response_status: Option<i32>,
+    pub error: Option<String>,
+}
+
+impl Webhook {
+    pub async fn create(
+        executor: impl sqlx::PgExecutor<'_>,
+        url: &str,
+        secret: &str,
+        event_types: &[String],
+    ) -> Result<Self, sqlx::Error> {
+        let webhook = sqlx::query_as!(
+            Webhook,
+            r#"
+            INSERT INTO webhooks (url, secret, event_types)
+            VALUES ($1, $2, $3)
+            RETURNING id, url, secret, event_types, created_at
+            "#,
+            url,
+            secret,
+            event_types
+        )
+        .fetch_one(executor)
+        .await?;
+
+        Ok(webhook)
+    }
+
+    pub async fn find_by_id(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let webhook = sqlx::query_as!(
+            Webhook,
+            "SELECT id, url, secret, event_types, created_at FROM webhooks WHERE id = $1",
+            id
+        )
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(webhook)
+    }
+
+    pub async fn list(executor: impl sqlx::PgExecutor<'_>) -> Result<Vec<Self>, sqlx::Error> {
+        let webhooks = sqlx::query_as!(
+            Webhook,
+            r#"
+            SELECT id, url, secret, event_types, created_at
+            FROM webhooks
+            ORDER BY created_at DESC
+            "#
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(webhooks)
+    }
+
+    /// Deletes the webhook along with its delivery log
+    pub async fn delete(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!("DELETE FROM webhooks WHERE id = $1", id)
+            .execute(executor)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+impl WebhookEvent {
+    /// Adds events to the outbox
+    pub async fn record(
+        executor: impl sqlx::PgExecutor<'_>,
+        events: &[NewEvent],
+    ) -> Result<(), sqlx::Error> {
+        let event_types: Vec<String> = events
+            .iter()
+            .map(|e| e.event_type.as_str().to_string())
+            .collect();
+        let payloads: Vec<String> = events.iter().map(|e| e.payload.to_string()).collect();
+
+        sqlx::query!(
+            r#"
+            INSERT INTO webhook_events (event_type, payload)
+            SELECT event_type, payload::jsonb
+            FROM UNNEST($1::text[], $2::text[]) AS e(event_type, payload)
+            "#,
+            &event_types,
+            &payloads
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Records `event_type` for `user_id` gaining or losing `role_id`; nothing
+    /// if either no longer exists
+    pub async fn record_role_change(
+        conn: &mut PgConnection,
+        event_type: EventType,
+        user_id: Uuid,
+        role_id: Uuid,
+    ) -> Result<(), sqlx::Error> {
+        let user = User::find_by_id(&mut *conn, user_id).await?;
+        let role = Role::find_by_id(&mut *conn, role_id).await?;
+        if let (Some(user), Some(role)) = (user, role) {
+            Self::record(&mut *conn, &[NewEvent::role(event_type, &user, &role)]).await?;
+        }
+        Ok(())
+    }
+
+    /// Records `user.role_assigned` for each role in `role_ids` granted to `user`
+    pub async fn record_role_grants(
+        conn: &mut PgConnection,
+        user: &User,
+        role_ids: &[Uuid],
+    ) -> Result<(), sqlx::Error> {
+        let mut events = Vec::with_capacity(role_ids.len());
+        for role_id in role_ids {
+            if let Some(role) = Role::find_by_id(&mut *conn, *role_id).await? {
+                events.push(NewEvent::role(EventType::UserRoleAssigned, user, &role));
+            }
+        }
+        Self::record(&mut *conn, &events).await
+    }
+
+    /// Records `event_type` for each of `user_ids` gaining or losing `role`
+    pub async fn record_membership_changes(
+        conn: &mut PgConnection,
+        event_type: EventType,
+        role: &Role,
+        user_ids: &[Uuid],
+    ) -> Result<(), sqlx::Error> {
+        let events: Vec<NewEvent> = User::find_by_ids(&mut *conn, user_ids)
+            .await?
+            .iter()
+            .map(|user| NewEvent::role(event_type, user, role))
+            .collect();
+        Self::record(&mut *conn, &events).await
+    }
+
+    /// Effective roles of each of `users`, taken before a group change so
+    /// that `record_roles_changes` can tell whose roles it changed
+    pub async fn snapshot_roles(
+        conn: &mut PgConnection,
+        users: Vec<User>,
+    ) -> Result<Vec<(User, Vec<Role>)>, sqlx::Error> {
+        let mut snapshot = Vec::with_capacity(users.len());
+        for user in users {
+            let roles = Role::get_effective_user_roles(&mut *conn, user.id).await?;
+            snapshot.push((user, roles));
+        }
+        Ok(snapshot)
+    }
+
+    /// Records `user.roles_changed` for each user in `before` whose effective
+    /// roles are no longer the ones snapshotted
+    pub async fn record_roles_changes(
+        conn: &mut PgConnection,
+        before: Vec<(User, Vec<Role>)>,
+    ) -> Result<(), sqlx::Error> {
+        let mut events = Vec::new();
+        for (user, roles) in before {
+            let now = Role::get_effective_user_roles(&mut *conn, user.id).await?;
+            if !same_roles(&roles, &now) {
+                events.push(NewEvent::roles_changed(&user, &now));
+            }
+        }
+        Self::record(&mut *conn, &events).await
+    }
+
+    /// Fans up to `limit` undispatched events out into a pending delivery per
+    /// subscribed webhook. Events being fanned out by another replica are
+    /// skipped. Returns the number of deliveries created.
+    pub async fn dispatch(
+        executor: impl sqlx::PgExecutor<'_>,
+        limit: i64,
+    ) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            WITH events AS (
+                UPDATE webhook_events
+                SET dispatched_at = NOW()
+                WHERE id IN (
+                    SELECT id FROM webhook_events
+                    WHERE dispatched_at IS NULL
+                    ORDER BY created_at
+                    LIMIT $1
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING id, event_type
+            )
+            INSERT INTO webhook_deliveries (webhook_id, event_id)
+            SELECT w.id, e.id
+            FROM events e
+            INNER JOIN webhooks w
+                ON cardinality(w.event_types) = 0 OR e.event_type = ANY(w.event_types)
+            "#,
+            limit
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Every event still in the outbox, oldest first
+    pub async fn list_all(executor: impl sqlx::PgExecutor<'_>) -> Result<Vec<Self>, sqlx::Error> {
+        let events = sqlx::query_as!(
+            WebhookEvent,
+            r#"
+            SELECT id, event_type, payload::text AS "payload!", created_at, dispatched_at
+            FROM webhook_events
+            ORDER BY created_at, id
+            "#
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(events)
+    }
+
+    /// The events about `user_id` still in the outbox, oldest first
+    pub async fn list_for_user(
+        executor: impl sqlx::PgExecutor<'_>,
+        user_id: Uuid,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let events = sqlx::query_as!(
+            WebhookEvent,
+            r#"
+            SELECT id, event_type, payload::text AS "payload!", created_at, dispatched_at
+            FROM webhook_events
+            WHERE payload -> 'user' ->> 'id' = $1
+            ORDER BY created_at
+            "#,
+            user_id.to_string()
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(events)
+    }
+
+    /// Deletes events dispatched before `before` that have no pending
+    /// deliveries, cascading to their delivery logs
+    pub async fn purge(
+        executor: impl sqlx::PgExecutor<'_>,
+        before: DateTime<Utc>,
+    ) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM webhook_events e
+            WHERE e.dispatched_at < $1
+              AND NOT EXISTS (
+                  SELECT 1 FROM webhook_deliveries d
+                  WHERE d.event_id = e.id AND d.status = 'pending'
+              )
+            "#,
+            before
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+impl WebhookDelivery {
+    /// The webhook's most recent deliveries, newest first
+    pub async fn list_for_webhook(
+        executor: impl sqlx::PgExecutor<'_>,
+        webhook_id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let deliveries = sqlx::query_as!(
+            WebhookDelivery,
+            r#"
+            SELECT d.id, d.webhook_id, d.event_id, e.event_type, d.status, d.attempts,
+                   d.next_attempt_at, d.response_status, d.error, d.created_at, d.updated_at
+            FROM webhook_deliveries d
+            INNER JOIN webhook_events e ON e.id = d.event_id
+            WHERE d.webhook_id = $1
+            ORDER BY d.created_at DESC
+            LIMIT $2
+            "#,
+            webhook_id,
+            limit
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(deliveries)
+    }
+
+    /// Queues a new delivery of the same event to the same webhook; None if
+    /// the webhook has no delivery with this id
+    pub async fn redeliver(
+        executor: impl sqlx::PgExecutor<'_>,
+        webhook_id: Uuid,
+        id: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let delivery = sqlx::query_as!(
+            WebhookDelivery,
+            r#"
+            WITH d AS (
+                INSERT INTO webhook_deliveries (webhook_id, event_id)
+                SELECT webhook_id, event_id FROM webhook_deliveries
+                WHERE id = $1 AND webhook_id = $2
+                RETURNING *
+            )
+            SELECT d.id AS "id!", d.webhook_id AS "webhook_id!", d.event_id AS "event_id!",
+                   e.event_type, d.status AS "status!", d.attempts AS "attempts!",
+                   d.next_attempt_at, d.response_status, d.error,
+                   d.created_at AS "created_at!", d.updated_at AS "updated_at!"
+            FROM d
+            INNER JOIN webhook_events e ON e.id = d.event_id
+            "#,
+            id,
+            webhook_id
+        )
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(delivery)
+    }
+
+    /// Claims up to `limit` due deliveries, counting the attempt about to be
+    /// made and postponing them by `lease_seconds` so no other dispatcher
+    /// picks them up meanwhile; if this one dies they are retried after that
+    pub async fn claim_due(
+        executor: impl sqlx::PgExecutor<'_>,
+        limit: i64,
+        lease_seconds: i32,
+    ) -> Result<Vec<DueDelivery>, sqlx::Error> {
+        let deliveries = sqlx::query_as!(
+            DueDelivery,
+            r#"
+            UPDATE webhook_deliveries d
+            SET attempts = d.attempts + 1,
+                next_attempt_at = NOW() + $2 * INTERVAL '1 second',
+                updated_at = NOW()
+            FROM webhooks w, webhook_events e
+            WHERE d.id IN (
+                    SELECT id FROM webhook_deliveries
+                    WHERE status = 'pending' AND next_attempt_at <= NOW()
+                    ORDER BY next_attempt_at
+                    LIMIT $1
+                    FOR UPDATE SKIP LOCKED
+                )
+              AND w.id = d.webhook_id AND e.id = d.event_id
+            RETURNING d.id, d.webhook_id, w.url, w.secret, d.event_id, e.event_type,
+                      e.payload::text AS "payload!", e.created_at AS event_created_at, d.attempts
+            "#,
+            limit,
+            f64::from(lease_seconds)
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(deliveries)
+    }
+
+    pub async fn record_attempt(
+        executor: impl sqlx::PgExecutor<'_>,
+        id: Uuid,
+        attempt: &DeliveryAttempt,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE webhook_deliveries
+            SET status = $2, next_attempt_at = $3, response_status = $4, error = $5,
+                updated_at = NOW()
+            WHERE id = $1
+            "#,
+            id,
+            attempt.status.as_str(),
+            attempt.next_attempt_at,
+            attempt.response_status,
+            attempt.error.as_deref()
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,2361 @@
+use super::{
+    AssignmentRepository, DeviceAuthorizationRepository, GroupRepository, IdentityRepository,
+    ImpersonationRepository, ImportError, InvitationRepository, NewAccount, PermissionRepository,
+    PreferenceRepository, PrivacyRepository, ProvisioningRepository, RepositoryError,
+    RepositoryResult, RoleRepository, ServiceAccountRepository, SessionRepository,
+    SetupTokenRepository, UserFilter, UserRepository, WebhookRepository,
+};
+use crate::models::user::{UpdateUser, UserWithRoles};
+use crate::models::{
+    fold_username, normalize_email, normalize_username, same_roles, DeliveryAttempt,
+    DeliveryStatus, DeviceAuthorization, DueDelivery, Erasure, EventType, Group,
+    ImpersonationEvent, Invitation, InvitationStatus, NewCredential, NewEvent, Permission, Role,
+    ServiceAccount, ServiceAccountCredential, Session, User, UserIdentity, Webhook,
+    WebhookDelivery, WebhookEvent,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use shared::{Preferences, RolePermissions};
+use std::collections::{HashMap, HashSet};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use uuid::Uuid;
+
+#[derive(Default, Clone)]
+struct Store {
+    users: HashMap<Uuid, User>,
+    roles: HashMap<Uuid, Role>,
+    permissions: HashMap<Uuid, Permission>,
+    user_roles: HashSet<(Uuid, Uuid)>,
+    role_permissions: HashSet<(Uuid, Uuid)>,
+    /// Bumped whenever what a role name grants may have changed
+    permissions_version: i64,
+    sessions: HashMap<Uuid, Session>,
+    /// Hash and expiry of the outstanding setup token
+    setup_token: Option<(String, DateTime<Utc>)>,
+    webhooks: HashMap<Uuid, Webhook>,
+    /// The outbox, oldest first
+    webhook_events: Vec<WebhookEvent>,
+    webhook_deliveries: HashMap<Uuid, WebhookDelivery>,
+    service_accounts: HashMap<Uuid, ServiceAccount>,
+    service_account_roles: HashSet<(Uuid, Uuid)>,
+    service_account_credentials: HashMap<Uuid, ServiceAccountCredential>,
+    /// Expiry of each (service account, jti) client assertion used
+    used_assertions: HashMap<(Uuid, String), DateTime<Utc>>,
+    device_authorizations: HashMap<Uuid, DeviceAuthorization>,
+    identities: HashMap<Uuid, UserIdentity>,
+    /// Newest last
+    erasures: Vec<Erasure>,
+    preferences: HashMap<Uuid, Preferences>,
+    groups: HashMap<Uuid, Group>,
+    /// (group, user) pairs
+    group_members: HashSet<(Uuid, Uuid)>,
+    /// (parent group, child group) pairs
+    group_subgroups: HashSet<(Uuid, Uuid)>,
+    /// (group, role) pairs
+    group_roles: HashSet<(Uuid, Uuid)>,
+    invitations: HashMap<Uuid, Invitation>,
+    /// (invitation, role) pairs
+    invitation_roles: HashSet<(Uuid, Uuid)>,
+    /// Oldest first
+    impersonations: Vec<ImpersonationEvent>,
+}
+
+impl Store {
+    fn record_events(&mut self, events: impl IntoIterator<Item = NewEvent>) {
+        let now = Utc::now();
+        self.webhook_events
+            .extend(events.into_iter().map(|event| WebhookEvent {
+                id: Uuid::new_v4(),
+                event_type: event.event_type.as_str().to_string(),
+                payload: event.payload.to_string(),
+                created_at: now,
+                dispatched_at: None,
+            }));
+    }
+
+    /// Records `event_type` for `user_id` gaining or losing `role_id`
+    fn record_role_change(&mut self, event_type: EventType, user_id: Uuid, role_id: Uuid) {
+        let user = self.users.get(&user_id).filter(|u| u.deleted_at.is_none());
+        if let (Some(user), Some(role)) = (user, self.roles.get(&role_id)) {
+            let event = NewEvent::role(event_type, user, role);
+            self.record_events([event]);
+        }
+    }
+
+    /// Whether `descendant_id` is `group_id` or nested in it at any depth
+    fn contains_group(&self, group_id: Uuid, descendant_id: Uuid) -> bool {
+        let mut seen = HashSet::from([group_id]);
+        let mut pending = vec![group_id];
+        while let Some(id) = pending.pop() {
+            if id == descendant_id {
+                return true;
+            }
+            for (parent, child) in &self.group_subgroups {
+                if *parent == id && seen.insert(*child) {
+                    pending.push(*child);
+                }
+            }
+        }
+        false
+    }
+
+    /// The groups the user is a member of, directly or through nesting
+    fn user_groups(&self, user_id: Uuid) -> HashSet<Uuid> {
+        let mut groups: HashSet<Uuid> = self
+            .group_members
+            .iter()
+            .filter(|(_, u)| *u == user_id)
+            .map(|(g, _)| *g)
+            .collect();
+        let mut pending: Vec<Uuid> = groups.iter().copied().collect();
+        while let Some(id) = pending.pop() {
+            for (parent, child) in &self.group_subgroups {
+                if *child == id && groups.insert(*parent) {
+                    pending.push(*parent);
+                }
+            }
+        }
+        groups
+    }
+
+    /// Roles the user holds directly, ordered by name
+    fn direct_roles(&self, user_id: Uuid) -> Vec<Role> {
+        sorted_roles(
+            self.user_roles
+                .iter()
+                .filter(|(u, _)| *u == user_id)
+                .filter_map(|(_, r)| self.roles.get(r)),
+        )
+    }
+
+    /// Roles the user holds directly or through a group, nested ones included
+    fn effective_roles(&self, user_id: Uuid) -> Vec<Role> {
+        let groups = self.user_groups(user_id);
+        let role_ids: HashSet<Uuid> = self
+            .user_roles
+            .iter()
+            .filter(|(u, _)| *u == user_id)
+            .map(|(_, r)| *r)
+            .chain(
+                self.group_roles
+                    .iter()
+                    .filter(|(g, _)| groups.contains(g))
+                    .map(|(_, r)| *r),
+            )
+            .collect();
+        sorted_roles(role_ids.iter().filter_map(|r| self.roles.get(r)))
+    }
+
+    /// Members of `group_ids` and of every group nested in them: the users
+    /// whose inherited roles change with those groups
+    fn subtree_members(&self, group_ids: &[Uuid]) -> Vec<Uuid> {
+        let members: HashSet<Uuid> = self
+            .group_members
+            .iter()
+            .filter(|(g, _)| group_ids.iter().any(|id| self.contains_group(*id, *g)))
+            .map(|(_, u)| *u)
+            .collect();
+        members.into_iter().collect()
+    }
+
+    /// Effective roles of each of `user_ids`, taken before a group change so
+    /// that `record_roles_changes` can tell whose roles it changed
+    fn snapshot_roles(&self, user_ids: impl IntoIterator<Item = Uuid>) -> Vec<(Uuid, Vec<Role>)> {
+        user_ids
+            .into_iter()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .map(|user_id| (user_id, self.effective_roles(user_id)))
+            .collect()
+    }
+
+    /// Records `user.roles_changed` for each user in `before` whose effective
+    /// roles are no longer the ones snapshotted
+    fn record_roles_changes(&mut self, before: Vec<(Uuid, Vec<Role>)>) {
+        let events: Vec<NewEvent> = before
+            .into_iter()
+            .filter_map(|(user_id, roles)| {
+                let user = self
+                    .users
+                    .get(&user_id)
+                    .filter(|u| u.deleted_at.is_none())?;
+                let now = self.effective_roles(user_id);
+                (!same_roles(&roles, &now)).then(|| NewEvent::roles_changed(user, &now))
+            })
+            .collect();
+        self.record_events(events);
+    }
+
+    /// Removes users for good, with the rows that reference them
+    fn remove_users(&mut self, ids: &HashSet<Uuid>) {
+        self.users.retain(|id, _| !ids.contains(id));
+        self.user_roles
+            .retain(|(user_id, _)| !ids.contains(user_id));
+        self.group_members
+            .retain(|(_, user_id)| !ids.contains(user_id));
+        for invitation in self.invitations.values_mut() {
+            if invitation
+                .accepted_user_id
+                .is_some_and(|user_id| ids.contains(&user_id))
+            {
+                invitation.accepted_user_id = None;
+            }
+        }
+        self.sessions.retain(|_, s| !ids.contains(&s.user_id));
+        self.device_authorizations
+            .retain(|_, d| !d.user_id.is_some_and(|user_id| ids.contains(&user_id)));
+        self.identities.retain(|_, i| !ids.contains(&i.user_id));
+        self.preferences.retain(|user_id, _| !ids.contains(user_id));
+        for account in self.service_accounts.values_mut() {
+            if account.owner_id.is_some_and(|owner| ids.contains(&owner)) {
+                account.owner_id = None;
+            }
+        }
+    }
+
+    fn new_delivery(&self, webhook_id: Uuid, event: &WebhookEvent) -> WebhookDelivery {
+        let now = Utc::now();
+        WebhookDelivery {
+            id: Uuid::new_v4(),
+            webhook_id,
+            event_id: event.id,
+            event_type: event.event_type.clone(),
+            status: DeliveryStatus::Pending.as_str().to_string(),
+            attempts: 0,
+            next_attempt_at: Some(now),
+            response_status: None,
+            error: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+/// In-process backend for tests. Enforces the same uniqueness and reference
+/// rules and orderings as the Postgres schema.
+#[derive(Default)]
+pub struct InMemoryRepository {
+    store: RwLock<Store>,
+}
+
+impl InMemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Seeds the roles, permissions and grants the migrations create
+    pub fn with_defaults() -> Self {
+        let repo = Self::new();
+        {
+            let mut store = repo.write();
+            let now = Utc::now();
+            store.permissions_version = 1;
+
+            let mut role_ids = HashMap::new();
+            for (name, description) in [
+                ("admin", "Administrator with user management permissions"),
+                ("user", "Regular user with weather and time access"),
+            ] {
+                let role = Role {
+                    id: Uuid::new_v4(),
+                    name: name.to_string(),
+                    description: Some(description.to_string()),
+                    created_at: now,
+                    version: 1,
+                };
+                role_ids.insert(name, role.id);
+                store.roles.insert(role.id, role);
+            }
+
+            let mut permission_ids = HashMap::new();
+            for (name, resource, action) in [
+                ("user:read", "user", "read"),
+                ("user:write", "user", "write"),
+                ("weather:read", "weather", "read"),
+                ("time:read", "time", "read"),
+                ("user:impersonate", "user", "impersonate"),
+            ] {
+                let permission = Permission {
+                    id: Uuid::new_v4(),
+                    name: name.to_string(),
+                    resource: resource.to_string(),
+                    action: action.to_string(),
+                    created_at: now,
+                    version: 1,
+                };
+                permission_ids.insert(name, permission.id);
+                store.permissions.insert(permission.id, permission);
+            }
+
+            for (role, permission) in [
+                ("admin", "user:read"),
+                ("admin", "user:write"),
+                ("user", "weather:read"),
+                ("user", "time:read"),
+            ] {
+                store
+                    .role_permissions
+                    .insert((role_ids[role], permission_ids[permission]));
+            }
+        }
+        repo
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Store> {
+        self.store.read().expect("in-memory store lock poisoned")
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Store> {
+        self.store.write().expect("in-memory store lock poisoned")
+    }
+}
+
+fn sorted_roles<'a>(roles: impl Iterator<Item = &'a Role>) -> Vec<Role> {
+    let mut roles: Vec<Role> = roles.cloned().collect();
+    roles.sort_by(|a, b| a.name.cmp(&b.name));
+    roles
+}
+
+/// Rejects a link to a row that does not exist, as the foreign keys would
+fn check_reference<K: std::hash::Hash + Eq, V>(
+    rows: &HashMap<K, V>,
+    key: &K,
+    column: &str,
+) -> RepositoryResult<()> {
+    if rows.contains_key(key) {
+        Ok(())
+    } else {
+        Err(RepositoryError::ForeignKeyViolation(column.to_string()))
+    }
+}
+
+/// Rejects a conditional write to a row that has moved on since it was read
+fn check_version(current: i32, expected: Option<i32>) -> RepositoryResult<()> {
+    match expected {
+        Some(expected) if expected != current => Err(RepositoryError::VersionMismatch),
+        _ => Ok(()),
+    }
+}
+
+fn sorted_users<'a>(users: impl Iterator<Item = &'a User>) -> Vec<User> {
+    let mut users: Vec<User> = users.filter(|u| u.deleted_at.is_none()).cloned().collect();
+    users.sort_by(|a, b| a.username.cmp(&b.username));
+    users
+}
+
+fn sorted_groups<'a>(groups: impl Iterator<Item = &'a Group>) -> Vec<Group> {
+    let mut groups: Vec<Group> = groups.cloned().collect();
+    groups.sort_by(|a, b| a.name.cmp(&b.name));
+    groups
+}
+
+fn insert_user(
+    store: &mut Store,
+    username: &str,
+    email: &str,
+    password_hash: &str,
+) -> RepositoryResult<User> {
+    let folded = fold_username(username);
+    let email = normalize_email(email);
+    if store
+        .users
+        .values()
+        .any(|u| fold_username(&u.username) == folded)
+    {
+        return Err(RepositoryError::UniqueViolation(
+            "users.username".to_string(),
+        ));
+    }
+    if store.users.values().any(|u| u.email == email) {
+        return Err(RepositoryError::UniqueViolation("users.email".to_string()));
+    }
+
+    let now = Utc::now();
+    let user = User {
+        id: Uuid::new_v4(),
+        username: normalize_username(username),
+        email,
+        password_hash: password_hash.to_string(),
+        created_at: now,
+        updated_at: now,
+        is_active: true,
+        version: 1,
+        deleted_at: None,
+    };
+    store.users.insert(user.id, user.clone());
+
+    Ok(user)
+}
+
+#[async_trait]
+impl UserRepository for InMemoryRepository {
+    async fn create(
+        &self,
+        username: &str,
+        email: &str,
+        password_hash: &str,
+    ) -> RepositoryResult<User> {
+        self.create_with_roles(username, email, password_hash, &[])
+            .await
+    }
+
+    async fn create_with_roles(
+        &self,
+        username: &str,
+        email: &str,
+        password_hash: &str,
+        role_ids: &[Uuid],
+    ) -> RepositoryResult<User> {
+        let mut store = self.write();
+
+        // Check every role before inserting so a failure leaves nothing behind
+        for role_id in role_ids {
+            check_reference(&store.roles, role_id, "user_roles.role_id")?;
+        }
+        let user = insert_user(&mut store, username, email, password_hash)?;
+        let mut events = vec![NewEvent::user(EventType::UserCreated, &user)];
+        for role_id in role_ids {
+            if store.user_roles.insert((user.id, *role_id)) {
+                let role = &store.roles[role_id];
+                events.push(NewEvent::role(EventType::UserRoleAssigned, &user, role));
+            }
+        }
+        store.record_events(events);
+
+        Ok(user)
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<User>> {
+        Ok(self
+            .read()
+            .users
+            .get(&id)
+            .filter(|u| u.deleted_at.is_none())
+            .cloned())
+    }
+
+    async fn find_by_username(&self, username: &str) -> RepositoryResult<Option<User>> {
+        let folded = fold_username(username);
+        Ok(self
+            .read()
+            .users
+            .values()
+            .find(|u| fold_username(&u.username) == folded && u.deleted_at.is_none())
+            .cloned())
+    }
+
+    async fn find_by_email(&self, email: &str) -> RepositoryResult<Option<User>> {
+        let email = normalize_email(email);
+        Ok(self
+            .read()
+            .users
+            .values()
+            .find(|u| u.email == email && u.deleted_at.is_none())
+            .cloned())
+    }
+
+    async fn find_by_ids(&self, ids: &[Uuid]) -> RepositoryResult<Vec<User>> {
+        let store = self.read();
+        Ok(ids
+            .iter()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .filter_map(|id| store.users.get(id))
+            .filter(|u| u.deleted_at.is_none())
+            .cloned()
+            .collect())
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<User>> {
+        let mut users: Vec<User> = self
+            .read()
+            .users
+            .values()
+            .filter(|u| u.deleted_at.is_none())
+            .cloned()
+            .collect();
+        users.sort_by_key(|u| std::cmp::Reverse(u.created_at));
+        Ok(users)
+    }
+
+    async fn update(
+        &self,
+        id: Uuid,
+        update: &UpdateUser,
+        expected_version: Option<i32>,
+    ) -> RepositoryResult<Option<User>> {
+        let mut store = self.write();
+
+        let Some(user) = store.users.get(&id).filter(|u| u.deleted_at.is_none()) else {
+            return Ok(None);
+        };
+        check_version(user.version, expected_version)?;
+
+        if let Some(ref username) = update.username {
+            let folded = fold_username(username);
+            if store
+                .users
+                .values()
+                .any(|u| u.id != id && fold_username(&u.username) == folded)
+            {
+                return Err(RepositoryError::UniqueViolation(
+                    "users.username".to_string(),
+                ));
+            }
+        }
+        if let Some(ref email) = update.email {
+            let email = normalize_email(email);
+            if store.users.values().any(|u| u.id != id && u.email == email) {
+                return Err(RepositoryError::UniqueViolation("users.email".to_string()));
+            }
+        }
+
+        let user = store.users.get_mut(&id).expect("checked above");
+        let was_active = user.is_active;
+        if let Some(ref username) = update.username {
+            user.username = normalize_username(username);
+        }
+        if let Some(ref email) = update.email {
+            user.email = normalize_email(email);
+        }
+        if let Some(ref password_hash) = update.password {
+            user.password_hash = password_hash.clone();
+        }
+        if let Some(is_active) = update.is_active {
+            user.is_active = is_active;
+        }
+        user.updated_at = Utc::now();
+        user.version += 1;
+
+        let user = user.clone();
+        let before = User {
+            is_active: was_active,
+            ..user.clone()
+        };
+        store.record_events(NewEvent::activation(&before, &user));
+
+        Ok(Some(user))
+    }
+
+    async fn delete(&self, id: Uuid, expected_version: Option<i32>) -> RepositoryResult<bool> {
+        let mut store = self.write();
+
+        let Some(user) = store.users.get_mut(&id).filter(|u| u.deleted_at.is_none()) else {
+            return Ok(false);
+        };
+        check_version(user.version, expected_version)?;
+
+        let now = Utc::now();
+        user.deleted_at = Some(now);
+        user.updated_at = now;
+        user.version += 1;
+
+        let event = NewEvent::user(EventType::UserDeleted, user);
+        store.record_events([event]);
+
+        Ok(true)
+    }
+
+    async fn restore(&self, id: Uuid) -> RepositoryResult<Option<User>> {
+        let mut store = self.write();
+
+        let Some(user) = store.users.get_mut(&id).filter(|u| u.deleted_at.is_some()) else {
+            return Ok(None);
+        };
+        user.deleted_at = None;
+        user.updated_at = Utc::now();
+        user.version += 1;
+
+        let user = user.clone();
+        store.record_events([NewEvent::user(EventType::UserRestored, &user)]);
+
+        Ok(Some(user))
+    }
+
+    async fn list_deleted(&self) -> RepositoryResult<Vec<User>> {
+        let mut users: Vec<User> = self
+            .read()
+            .users
+            .values()
+            .filter(|u| u.deleted_at.is_some())
+            .cloned()
+            .collect();
+        users.sort_by_key(|u| std::cmp::Reverse(u.deleted_at));
+        Ok(users)
+    }
+
+    async fn purge_deleted(&self, before: DateTime<Utc>) -> RepositoryResult<u64> {
+        let mut store = self.write();
+
+        let purged: HashSet<Uuid> = store
+            .users
+            .values()
+            .filter(|u| u.deleted_at.is_some_and(|at| at < before))
+            .map(|u| u.id)
+            .collect();
+        store.remove_users(&purged);
+
+        Ok(purged.len() as u64)
+    }
+}
+
+#[async_trait]
+impl RoleRepository for InMemoryRepository {
+    async fn create(&self, name: &str, description: Option<&str>) -> RepositoryResult<Role> {
+        let mut store = self.write();
+
+        if store.roles.values().any(|r| r.name == name) {
+            return Err(RepositoryError::UniqueViolation("roles.name".to_string()));
+        }
+
+        let role = Role {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            description: description.map(str::to_string),
+            created_at: Utc::now(),
+            version: 1,
+        };
+        store.roles.insert(role.id, role.clone());
+
+        Ok(role)
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<Role>> {
+        Ok(self.read().roles.get(&id).cloned())
+    }
+
+    async fn find_by_name(&self, name: &str) -> RepositoryResult<Option<Role>> {
+        Ok(self.read().roles.values().find(|r| r.name == name).cloned())
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<Role>> {
+        Ok(sorted_roles(self.read().roles.values()))
+    }
+
+    async fn update(
+        &self,
+        id: Uuid,
+        name: &str,
+        description: Option<&str>,
+        expected_version: Option<i32>,
+    ) -> RepositoryResult<Option<Role>> {
+        let mut store = self.write();
+
+        let Some(role) = store.roles.get(&id) else {
+            return Ok(None);
+        };
+        check_version(role.version, expected_version)?;
+        if store.roles.values().any(|r| r.id != id && r.name == name) {
+            return Err(RepositoryError::UniqueViolation("roles.name".to_string()));
+        }
+
+        let role = store.roles.get_mut(&id).expect("checked above");
+        let renamed = role.name != name;
+        role.name = name.to_string();
+        role.description = description.map(str::to_string);
+        role.version += 1;
+        let role = role.clone();
+        if renamed {
+            store.permissions_version += 1;
+        }
+
+        Ok(Some(role))
+    }
+
+    async fn delete(&self, id: Uuid, expected_version: Option<i32>) -> RepositoryResult<bool> {
+        let mut store = self.write();
+
+        let Some(role) = store.roles.get(&id) else {
+            return Ok(false);
+        };
+        check_version(role.version, expected_version)?;
+
+        // Deleting the role takes it from its members, and from everyone
+        // holding it through a group
+        let groups: Vec<Uuid> = store
+            .group_roles
+            .iter()
+            .filter(|(_, role_id)| *role_id == id)
+            .map(|(group_id, _)| *group_id)
+            .collect();
+        let before = store.snapshot_roles(store.subtree_members(&groups));
+        let role = store.roles.remove(&id).expect("checked above");
+        let events: Vec<NewEvent> = store
+            .user_roles
+            .iter()
+            .filter(|(_, role_id)| *role_id == id)
+            .filter_map(|(user_id, _)| store.users.get(user_id))
+            .filter(|user| user.deleted_at.is_none())
+            .map(|user| NewEvent::role(EventType::UserRoleRemoved, user, &role))
+            .collect();
+        store.record_events(events);
+        store.user_roles.retain(|(_, role_id)| *role_id != id);
+        store.group_roles.retain(|(_, role_id)| *role_id != id);
+        store.invitation_roles.retain(|(_, role_id)| *role_id != id);
+        store
+            .service_account_roles
+            .retain(|(_, role_id)| *role_id != id);
+        store.role_permissions.retain(|(role_id, _)| *role_id != id);
+        store.permissions_version += 1;
+        store.record_roles_changes(before);
+
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl PermissionRepository for InMemoryRepository {
+    async fn create(
+        &self,
+        name: &str,
+        resource: &str,
+        action: &str,
+    ) -> RepositoryResult<Permission> {
+        let mut store = self.write();
+
+        if store.permissions.values().any(|p| p.name == name) {
+            return Err(RepositoryError::UniqueViolation(
+                "permissions.name".to_string(),
+            ));
+        }
+
+        let permission = Permission {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            resource: resource.to_string(),
+            action: action.to_string(),
+            created_at: Utc::now(),
+            version: 1,
+        };
+        store.permissions.insert(permission.id, permission.clone());
+
+        Ok(permission)
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<Permission>> {
+        Ok(self.read().permissions.get(&id).cloned())
+    }
+
+    async fn find_by_name(&self, name: &str) -> RepositoryResult<Option<Permission>> {
+        Ok(self
+            .read()
+            .permissions
+            .values()
+            .find(|p| p.name == name)
+            .cloned())
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<Permission>> {
+        let mut permissions: Vec<Permission> = self.read().permissions.values().cloned().collect();
+        permissions.sort_by(|a, b| (&a.resource, &a.action).cmp(&(&b.resource, &b.action)));
+        Ok(permissions)
+    }
+
+    async fn update(
+        &self,
+        id: Uuid,
+        name: &str,
+        resource: &str,
+        action: &str,
+        expected_version: Option<i32>,
+    ) -> RepositoryResult<Option<Permission>> {
+        let mut store = self.write();
+
+        let Some(permission) = store.permissions.get(&id) else {
+            return Ok(None);
+        };
+        check_version(permission.version, expected_version)?;
+        if store
+            .permissions
+            .values()
+            .any(|p| p.id != id && p.name == name)
+        {
+            return Err(RepositoryError::UniqueViolation(
+                "permissions.name".to_string(),
+            ));
+        }
+
+        let permission = store.permissions.get_mut(&id).expect("checked above");
+        let renamed = permission.name != name;
+        permission.name = name.to_string();
+        permission.resource = resource.to_string();
+        permission.action = action.to_string();
+        permission.version += 1;
+        let permission = permission.clone();
+        if renamed {
+            store.permissions_version += 1;
+        }
+
+        Ok(Some(permission))
+    }
+
+    async fn delete(&self, id: Uuid, expected_version: Option<i32>) -> RepositoryResult<bool> {
+        let mut store = self.write();
+
+        let Some(permission) = store.permissions.get(&id) else {
+            return Ok(false);
+        };
+        check_version(permission.version, expected_version)?;
+
+        store.permissions.remove(&id);
+        store
+            .role_permissions
+            .retain(|(_, permission_id)| *permission_id != id);
+        store.permissions_version += 1;
+
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl AssignmentRepository for InMemoryRepository {
+    async fn assign_role_to_user(&self, user_id: Uuid, role_id: Uuid) -> RepositoryResult<()> {
+        let mut store = self.write();
+        check_reference(&store.users, &user_id, "user_roles.user_id")?;
+        check_reference(&store.roles, &role_id, "user_roles.role_id")?;
+        if store.user_roles.insert((user_id, role_id)) {
+            store.record_role_change(EventType::UserRoleAssigned, user_id, role_id);
+        }
+        Ok(())
+    }
+
+    async fn remove_role_from_user(&self, user_id: Uuid, role_id: Uuid) -> RepositoryResult<bool> {
+        let mut store = self.write();
+        let removed = store.user_roles.remove(&(user_id, role_id));
+        if removed {
+            store.record_role_change(EventType::UserRoleRemoved, user_id, role_id);
+        }
+        Ok(removed)
+    }
+
+    async fn get_user_roles(&self, user_id: Uuid) -> RepositoryResult<Vec<Role>> {
+        Ok(self.read().direct_roles(user_id))
+    }
+
+    async fn get_effective_user_roles(&self, user_id: Uuid) -> RepositoryResult<Vec<Role>> {
+        Ok(self.read().effective_roles(user_id))
+    }
+
+    async fn assign_permission_to_role(
+        &self,
+        role_id: Uuid,
+        permission_id: Uuid,
+    ) -> RepositoryResult<()> {
+        let mut store = self.write();
+        check_reference(&store.roles, &role_id, "role_permissions.role_id")?;
+        check_reference(
+            &store.permissions,
+            &permission_id,
+            "role_permissions.permission_id",
+        )?;
+        if store.role_permissions.insert((role_id, permission_id)) {
+            store.permissions_version += 1;
+        }
+        Ok(())
+    }
+
+    async fn remove_permission_from_role(
+        &self,
+        role_id: Uuid,
+        permission_id: Uuid,
+    ) -> RepositoryResult<bool> {
+        let mut store = self.write();
+        let removed = store.role_permissions.remove(&(role_id, permission_id));
+        if removed {
+            store.permissions_version += 1;
+        }
+        Ok(removed)
+    }
+
+    async fn get_role_permissions(&self, role_id: Uuid) -> RepositoryResult<Vec<Permission>> {
+        let store = self.read();
+        let mut permissions: Vec<Permission> = store
+            .role_permissions
+            .iter()
+            .filter(|(r, _)| *r == role_id)
+            .filter_map(|(_, p)| store.permissions.get(p).cloned())
+            .collect();
+        permissions.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(permissions)
+    }
+
+    async fn permissions_version(&self) -> RepositoryResult<i64> {
+        Ok(self.read().permissions_version)
+    }
+
+    async fn role_permissions(&self) -> RepositoryResult<RolePermissions> {
+        let store = self.read();
+        let mut map = RolePermissions {
+            version: store.permissions_version,
+            ..Default::default()
+        };
+        for role in store.roles.values() {
+            map.roles.insert(role.name.clone(), Vec::new());
+        }
+        for (role_id, permission_id) in &store.role_permissions {
+            if let (Some(role), Some(permission)) = (
+                store.roles.get(role_id),
+                store.permissions.get(permission_id),
+            ) {
+                map.roles
+                    .get_mut(&role.name)
+                    .expect("inserted above")
+                    .push(permission.name.clone());
+            }
+        }
+        map.roles
+            .values_mut()
+            .for_each(|permissions| permissions.sort());
+        Ok(map)
+    }
+
+    async fn role_has_users(&self, role_id: Uuid) -> RepositoryResult<bool> {
+        let store = self.read();
+        Ok(store.user_roles.iter().any(|(user_id, r)| {
+            *r == role_id
+                && store
+                    .users
+                    .get(user_id)
+                    .is_some_and(|u| u.deleted_at.is_none())
+        }))
+    }
+
+    async fn get_role_members(&self, role_id: Uuid) -> RepositoryResult<Vec<User>> {
+        let store = self.read();
+        Ok(sorted_users(
+            store
+                .user_roles
+                .iter()
+                .filter(|(_, r)| *r == role_id)
+                .filter_map(|(u, _)| store.users.get(u)),
+        ))
+    }
+
+    async fn set_role_members(
+        &self,
+        role_id: Uuid,
+        user_ids: &[Uuid],
+    ) -> RepositoryResult<(Vec<Uuid>, Vec<Uuid>)> {
+        let mut store = self.write();
+        let wanted: HashSet<Uuid> = user_ids.iter().copied().collect();
+        if !wanted.is_empty() {
+            check_reference(&store.roles, &role_id, "user_roles.role_id")?;
+        }
+        for user_id in &wanted {
+            check_reference(&store.users, user_id, "user_roles.user_id")?;
+        }
+
+        let removed: Vec<Uuid> = store
+            .user_roles
+            .iter()
+            .filter(|(u, r)| *r == role_id && !wanted.contains(u))
+            .map(|(u, _)| *u)
+            .collect();
+        let added: Vec<Uuid> = wanted
+            .into_iter()
+            .filter(|u| !store.user_roles.contains(&(*u, role_id)))
+            .collect();
+        for user_id in &removed {
+            store.user_roles.remove(&(*user_id, role_id));
+            store.record_role_change(EventType::UserRoleRemoved, *user_id, role_id);
+        }
+        for user_id in &added {
+            store.user_roles.insert((*user_id, role_id));
+            store.record_role_change(EventType::UserRoleAssigned, *user_id, role_id);
+        }
+
+        Ok((added, removed))
+    }
+}
+
+#[async_trait]
+impl SessionRepository for InMemoryRepository {
+    async fn create(
+        &self,
+        user_id: Uuid,
+        user_agent: Option<&str>,
+        ip_address: Option<&str>,
+        expires_at: DateTime<Utc>,
+    ) -> RepositoryResult<Session> {
+        let now = Utc::now();
+        let session = Session {
+            id: Uuid::new_v4(),
+            user_id,
+            user_agent: user_agent.map(str::to_string),
+            ip_address: ip_address.map(str::to_string),
+            created_at: now,
+            last_used_at: now,
+            expires_at,
+            revoked_at: None,
+        };
+        self.write().sessions.insert(session.id, session.clone());
+
+        Ok(session)
+    }
+
+    async fn list_active_for_user(&self, user_id: Uuid) -> RepositoryResult<Vec<Session>> {
+        let now = Utc::now();
+        let mut sessions: Vec<Session> = self
+            .read()
+            .sessions
+            .values()
+            .filter(|s| s.user_id == user_id && s.revoked_at.is_none() && s.expires_at > now)
+            .cloned()
+            .collect();
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.last_used_at));
+        Ok(sessions)
+    }
+
+    async fn list_for_user(&self, user_id: Uuid) -> RepositoryResult<Vec<Session>> {
+        let mut sessions: Vec<Session> = self
+            .read()
+            .sessions
+            .values()
+            .filter(|s| s.user_id == user_id)
+            .cloned()
+            .collect();
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.created_at));
+        Ok(sessions)
+    }
+
+    async fn touch(&self, id: Uuid) -> RepositoryResult<bool> {
+        let now = Utc::now();
+        let mut store = self.write();
+
+        match store.sessions.get_mut(&id) {
+            Some(session) if session.revoked_at.is_none() && session.expires_at > now => {
+                session.last_used_at = now;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn revoke(&self, user_id: Uuid, id: Uuid) -> RepositoryResult<bool> {
+        let mut store = self.write();
+
+        match store.sessions.get_mut(&id) {
+            Some(session) if session.user_id == user_id && session.revoked_at.is_none() => {
+                session.revoked_at = Some(Utc::now());
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn revoke_all_for_user(
+        &self,
+        user_id: Uuid,
+        except: Option<Uuid>,
+    ) -> RepositoryResult<u64> {
+        let now = Utc::now();
+        let mut revoked = 0;
+
+        for session in self.write().sessions.values_mut() {
+            if session.user_id == user_id
+                && session.revoked_at.is_none()
+                && Some(session.id) != except
+            {
+                session.revoked_at = Some(now);
+                revoked += 1;
+            }
+        }
+
+        Ok(revoked)
+    }
+}
+
+#[async_trait]
+impl SetupTokenRepository for InMemoryRepository {
+    async fn replace(&self, token_hash: &str, expires_at: DateTime<Utc>) -> RepositoryResult<()> {
+        self.write().setup_token = Some((token_hash.to_string(), expires_at));
+        Ok(())
+    }
+
+    async fn consume(&self, token_hash: &str) -> RepositoryResult<bool> {
+        let mut store = self.write();
+        let matches = store
+            .setup_token
+            .as_ref()
+            .is_some_and(|(hash, expires_at)| hash == token_hash && *expires_at > Utc::now());
+        if matches {
+            store.setup_token = None;
+        }
+        Ok(matches)
+    }
+
+    async fn clear(&self) -> RepositoryResult<()> {
+        self.write().setup_token = None;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for InMemoryRepository {
+    async fn create(
+        &self,
+        url: &str,
+        secret: &str,
+        event_types: &[String],
+    ) -> RepositoryResult<Webhook> {
+        let webhook = Webhook {
+            id: Uuid::new_v4(),
+            url: url.to_string(),
+            secret: secret.to_string(),
+            event_types: event_types.to_vec(),
+            created_at: Utc::now(),
+        };
+        self.write().webhooks.insert(webhook.id, webhook.clone());
+
+        Ok(webhook)
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<Webhook>> {
+        Ok(self.read().webhooks.get(&id).cloned())
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<Webhook>> {
+        let mut webhooks: Vec<Webhook> = self.read().webhooks.values().cloned().collect();
+        webhooks.sort_by_key(|w| std::cmp::Reverse(w.created_at));
+        Ok(webhooks)
+    }
+
+    async fn delete(&self, id: Uuid) -> RepositoryResult<bool> {
+        let mut store = self.write();
+        if store.webhooks.remove(&id).is_none() {
+            return Ok(false);
+        }
+        store.webhook_deliveries.retain(|_, d| d.webhook_id != id);
+        Ok(true)
+    }
+
+    async fn list_deliveries(
+        &self,
+        webhook_id: Uuid,
+        limit: i64,
+    ) -> RepositoryResult<Vec<WebhookDelivery>> {
+        let mut deliveries: Vec<WebhookDelivery> = self
+            .read()
+            .webhook_deliveries
+            .values()
+            .filter(|d| d.webhook_id == webhook_id)
+            .cloned()
+            .collect();
+        deliveries.sort_by_key(|d| std::cmp::Reverse(d.created_at));
+        deliveries.truncate(usize::try_from(limit).unwrap_or(0));
+        Ok(deliveries)
+    }
+
+    async fn redeliver(
+        &self,
+        webhook_id: Uuid,
+        delivery_id: Uuid,
+    ) -> RepositoryResult<Option<WebhookDelivery>> {
+        let mut store = self.write();
+
+        let Some(event_id) = store
+            .webhook_deliveries
+            .get(&delivery_id)
+            .filter(|d| d.webhook_id == webhook_id)
+            .map(|d| d.event_id)
+        else {
+            return Ok(None);
+        };
+        let event = store
+            .webhook_events
+            .iter()
+            .find(|e| e.id == event_id)
+            .expect("deliveries are removed with their event");
+        let delivery = store.new_delivery(webhook_id, event);
+        store
+            .webhook_deliveries
+            .insert(delivery.id, delivery.clone());
+
+        Ok(Some(delivery))
+    }
+
+    async fn dispatch_events(&self, limit: i64) -> RepositoryResult<u64> {
+        let mut store = self.write();
+        let now = Utc::now();
+
+        let events: Vec<WebhookEvent> = store
+            .webhook_events
+            .iter_mut()
+            .filter(|e| e.dispatched_at.is_none())
+            .take(usize::try_from(limit).unwrap_or(0))
+            .map(|e| {
+                e.dispatched_at = Some(now);
+                e.clone()
+            })
+            .collect();
+
+        let mut deliveries = Vec::new();
+        for event in &events {
+            for webhook in store.webhooks.values() {
+                if webhook.subscribes_to(&event.event_type) {
+                    deliveries.push(store.new_delivery(webhook.id, event));
+                }
+            }
+        }
+        let created = deliveries.len() as u64;
+        store
+            .webhook_deliveries
+            .extend(deliveries.into_iter().map(|d| (d.id, d)));
+
+        Ok(created)
+    }
+
+    async fn claim_due_deliveries(
+        &self,
+        limit: i64,
+        lease_seconds: i32,
+    ) -> RepositoryResult<Vec<DueDelivery>> {
+        let mut store = self.write();
+        let now = Utc::now();
+
+        let mut due: Vec<(DateTime<Utc>, Uuid)> = store
+            .webhook_deliveries
+            .values()
+            .filter(|d| d.status == DeliveryStatus::Pending.as_str())
+            .filter_map(|d| {
+                d.next_attempt_at
+                    .filter(|at| *at <= now)
+                    .map(|at| (at, d.id))
+            })
+            .collect();
+        due.sort();
+        due.truncate(usize::try_from(limit).unwrap_or(0));
+
+        let mut claimed = Vec::with_capacity(due.len());
+        for (_, id) in due {
+            let delivery = store.webhook_deliveries.get_mut(&id).expect("listed above");
+            delivery.attempts += 1;
+            delivery.next_attempt_at = Some(now + chrono::Duration::seconds(lease_seconds.into()));
+            delivery.updated_at = now;
+            let delivery = delivery.clone();
+
+            let webhook = &store.webhooks[&delivery.webhook_id];
+            let event = store
+                .webhook_events
+                .iter()
+                .find(|e| e.id == delivery.event_id)
+                .expect("deliveries are removed with their event");
+            claimed.push(DueDelivery {
+                id: delivery.id,
+                webhook_id: webhook.id,
+                url: webhook.url.clone(),
+                secret: webhook.secret.clone(),
+                event_id: event.id,
+                event_type: event.event_type.clone(),
+                payload: event.payload.clone(),
+                event_created_at: event.created_at,
+                attempts: delivery.attempts,
+            });
+        }
+
+        Ok(claimed)
+    }
+
+    async fn record_attempt(
+        &self,
+        delivery_id: Uuid,
+        attempt: &DeliveryAttempt,
+    ) -> RepositoryResult<()> {
+        if let Some(delivery) = self.write().webhook_deliveries.get_mut(&delivery_id) {
+            delivery.status = attempt.status.as_str().to_string();
+            delivery.next_attempt_at = attempt.next_attempt_at;
+            delivery.response_status = attempt.response_status;
+            delivery.error = attempt.error.clone();
+            delivery.updated_at = Utc::now();
+        }
+        Ok(())
+    }
+
+    async fn purge_events(&self, before: DateTime<Utc>) -> RepositoryResult<u64> {
+        let mut store = self.write();
+
+        let pending: HashSet<Uuid> = store
+            .webhook_deliveries
+            .values()
+            .filter(|d| d.status == DeliveryStatus::Pending.as_str())
+            .map(|d| d.event_id)
+            .collect();
+        let purged: HashSet<Uuid> = store
+            .webhook_events
+            .iter()
+            .filter(|e| e.dispatched_at.is_some_and(|at| at < before) && !pending.contains(&e.id))
+            .map(|e| e.id)
+            .collect();
+        store.webhook_events.retain(|e| !purged.contains(&e.id));
+        store
+            .webhook_deliveries
+            .retain(|_, d| !purged.contains(&d.event_id));
+
+        Ok(purged.len() as u64)
+    }
+
+    async fn list_events(&self) -> RepositoryResult<Vec<WebhookEvent>> {
+        Ok(self.read().webhook_events.clone())
+    }
+}
+
+#[async_trait]
+impl ServiceAccountRepository for InMemoryRepository {
+    async fn create(
+        &self,
+        name: &str,
+        description: Option<&str>,
+        owner_id: Option<Uuid>,
+    ) -> RepositoryResult<ServiceAccount> {
+        let mut store = self.write();
+
+        if store.service_accounts.values().any(|a| a.name == name) {
+            return Err(RepositoryError::UniqueViolation(
+                "service_accounts.name".to_string(),
+            ));
+        }
+        if let Some(owner_id) = owner_id {
+            check_reference(&store.users, &owner_id, "service_accounts.owner_id")?;
+        }
+
+        let now = Utc::now();
+        let account = ServiceAccount {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            description: description.map(str::to_string),
+            owner_id,
+            is_active: true,
+            created_at: now,
+            updated_at: now,
+        };
+        store.service_accounts.insert(account.id, account.clone());
+        Ok(account)
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<ServiceAccount>> {
+        Ok(self.read().service_accounts.get(&id).cloned())
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<ServiceAccount>> {
+        let mut accounts: Vec<ServiceAccount> =
+            self.read().service_accounts.values().cloned().collect();
+        accounts.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(accounts)
+    }
+
+    async fn update(
+        &self,
+        id: Uuid,
+        description: Option<&str>,
+        owner_id: Option<Uuid>,
+        is_active: bool,
+    ) -> RepositoryResult<Option<ServiceAccount>> {
+        let mut store = self.write();
+
+        if !store.service_accounts.contains_key(&id) {
+            return Ok(None);
+        }
+        if let Some(owner_id) = owner_id {
+            check_reference(&store.users, &owner_id, "service_accounts.owner_id")?;
+        }
+
+        let account = store.service_accounts.get_mut(&id).expect("checked above");
+        account.description = description.map(str::to_string);
+        account.owner_id = owner_id;
+        account.is_active = is_active;
+        account.updated_at = Utc::now();
+        Ok(Some(account.clone()))
+    }
+
+    async fn delete(&self, id: Uuid) -> RepositoryResult<bool> {
+        let mut store = self.write();
+        if store.service_accounts.remove(&id).is_none() {
+            return Ok(false);
+        }
+        store
+            .service_account_roles
+            .retain(|(account_id, _)| *account_id != id);
+        store
+            .service_account_credentials
+            .retain(|_, c| c.service_account_id != id);
+        store
+            .used_assertions
+            .retain(|(account_id, _), _| *account_id != id);
+        Ok(true)
+    }
+
+    async fn assign_role(&self, id: Uuid, role_id: Uuid) -> RepositoryResult<()> {
+        let mut store = self.write();
+        check_reference(
+            &store.service_accounts,
+            &id,
+            "service_account_roles.service_account_id",
+        )?;
+        check_reference(&store.roles, &role_id, "service_account_roles.role_id")?;
+        store.service_account_roles.insert((id, role_id));
+        Ok(())
+    }
+
+    async fn remove_role(&self, id: Uuid, role_id: Uuid) -> RepositoryResult<bool> {
+        Ok(self.write().service_account_roles.remove(&(id, role_id)))
+    }
+
+    async fn get_roles(&self, id: Uuid) -> RepositoryResult<Vec<Role>> {
+        let store = self.read();
+        Ok(sorted_roles(
+            store
+                .service_account_roles
+                .iter()
+                .filter(|(a, _)| *a == id)
+                .filter_map(|(_, r)| store.roles.get(r)),
+        ))
+    }
+
+    async fn add_credential(
+        &self,
+        id: Uuid,
+        credential: NewCredential<'_>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> RepositoryResult<ServiceAccountCredential> {
+        let mut store = self.write();
+        check_reference(
+            &store.service_accounts,
+            &id,
+            "service_account_credentials.service_account_id",
+        )?;
+
+        let (secret_hash, public_key, algorithm) = credential.columns();
+        let credential = ServiceAccountCredential {
+            id: Uuid::new_v4(),
+            service_account_id: id,
+            kind: credential.kind().as_str().to_string(),
+            secret_hash: secret_hash.map(str::to_string),
+            public_key: public_key.map(str::to_string),
+            algorithm: algorithm.map(str::to_string),
+            created_at: Utc::now(),
+            expires_at,
+            last_used_at: None,
+        };
+        store
+            .service_account_credentials
+            .insert(credential.id, credential.clone());
+        Ok(credential)
+    }
+
+    async fn list_credentials(&self, id: Uuid) -> RepositoryResult<Vec<ServiceAccountCredential>> {
+        let mut credentials: Vec<ServiceAccountCredential> = self
+            .read()
+            .service_account_credentials
+            .values()
+            .filter(|c| c.service_account_id == id)
+            .cloned()
+            .collect();
+        credentials.sort_by_key(|c| std::cmp::Reverse(c.created_at));
+        Ok(credentials)
+    }
+
+    async fn delete_credential(&self, id: Uuid, credential_id: Uuid) -> RepositoryResult<bool> {
+        let mut store = self.write();
+        let owned = store
+            .service_account_credentials
+            .get(&credential_id)
+            .is_some_and(|c| c.service_account_id == id);
+        if owned {
+            store.service_account_credentials.remove(&credential_id);
+        }
+        Ok(owned)
+    }
+
+    async fn touch_credential(&self, credential_id: Uuid) -> RepositoryResult<()> {
+        if let Some(credential) = self
+            .write()
+            .service_account_credentials
+            .get_mut(&credential_id)
+        {
+            credential.last_used_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+
+    async fn use_assertion(
+        &self,
+        id: Uuid,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> RepositoryResult<bool> {
+        let mut store = self.write();
+        check_reference(
+            &store.service_accounts,
+            &id,
+            "used_client_assertions.service_account_id",
+        )?;
+        let key = (id, jti.to_string());
+        if store
+            .used_assertions
+            .get(&key)
+            .is_some_and(|used_until| *used_until > Utc::now())
+        {
+            return Ok(false);
+        }
+        store.used_assertions.insert(key, expires_at);
+        Ok(true)
+    }
+
+    async fn purge_used_assertions(&self, before: DateTime<Utc>) -> RepositoryResult<u64> {
+        let mut store = self.write();
+        let count = store.used_assertions.len();
+        store
+            .used_assertions
+            .retain(|_, expires_at| *expires_at > before);
+        Ok((count - store.used_assertions.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl DeviceAuthorizationRepository for InMemoryRepository {
+    async fn create(
+        &self,
+        device_code_hash: &str,
+        user_code: &str,
+        client_id: &str,
+        interval_seconds: i32,
+        expires_at: DateTime<Utc>,
+    ) -> RepositoryResult<DeviceAuthorization> {
+        let mut store = self.write();
+
+        if store
+            .device_authorizations
+            .values()
+            .any(|d| d.device_code_hash == device_code_hash)
+        {
+            return Err(RepositoryError::UniqueViolation(
+                "device_authorizations.device_code_hash".to_string(),
+            ));
+        }
+        if store
+            .device_authorizations
+            .values()
+            .any(|d| d.user_code == user_code)
+        {
+            return Err(RepositoryError::UniqueViolation(
+                "device_authorizations.user_code".to_string(),
+            ));
+        }
+
+        let authorization = DeviceAuthorization {
+            id: Uuid::new_v4(),
+            device_code_hash: device_code_hash.to_string(),
+            user_code: user_code.to_string(),
+            client_id: client_id.to_string(),
+            interval_seconds,
+            created_at: Utc::now(),
+            expires_at,
+            last_polled_at: None,
+            user_id: None,
+            approved_at: None,
+            denied_at: None,
+        };
+        store
+            .device_authorizations
+            .insert(authorization.id, authorization.clone());
+        Ok(authorization)
+    }
+
+    async fn find_by_user_code(
+        &self,
+        user_code: &str,
+    ) -> RepositoryResult<Option<DeviceAuthorization>> {
+        Ok(self
+            .read()
+            .device_authorizations
+            .values()
+            .find(|d| d.user_code == user_code)
+            .cloned())
+    }
+
+    async fn find_by_device_code(
+        &self,
+        device_code_hash: &str,
+    ) -> RepositoryResult<Option<DeviceAuthorization>> {
+        Ok(self
+            .read()
+            .device_authorizations
+            .values()
+            .find(|d| d.device_code_hash == device_code_hash)
+            .cloned())
+    }
+
+    async fn decide(
+        &self,
+        user_code: &str,
+        user_id: Uuid,
+        approve: bool,
+    ) -> RepositoryResult<Option<DeviceAuthorization>> {
+        let mut store = self.write();
+        check_reference(&store.users, &user_id, "device_authorizations.user_id")?;
+
+        let now = Utc::now();
+        let Some(authorization) = store.device_authorizations.values_mut().find(|d| {
+            d.user_code == user_code
+                && d.approved_at.is_none()
+                && d.denied_at.is_none()
+                && d.expires_at > now
+        }) else {
+            return Ok(None);
+        };
+        authorization.user_id = Some(user_id);
+        if approve {
+            authorization.approved_at = Some(now);
+        } else {
+            authorization.denied_at = Some(now);
+        }
+        Ok(Some(authorization.clone()))
+    }
+
+    async fn record_poll(&self, id: Uuid, interval_seconds: i32) -> RepositoryResult<()> {
+        if let Some(authorization) = self.write().device_authorizations.get_mut(&id) {
+            authorization.last_polled_at = Some(Utc::now());
+            authorization.interval_seconds = interval_seconds;
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: Uuid) -> RepositoryResult<bool> {
+        Ok(self.write().device_authorizations.remove(&id).is_some())
+    }
+
+    async fn purge_expired(&self, before: DateTime<Utc>) -> RepositoryResult<u64> {
+        let mut store = self.write();
+        let count = store.device_authorizations.len();
+        store
+            .device_authorizations
+            .retain(|_, d| d.expires_at > before);
+        Ok((count - store.device_authorizations.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl IdentityRepository for InMemoryRepository {
+    async fn create(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        subject: &str,
+        email: Option<&str>,
+    ) -> RepositoryResult<UserIdentity> {
+        let mut store = self.write();
+
+        check_reference(&store.users, &user_id, "user_identities.user_id")?;
+        if store
+            .identities
+            .values()
+            .any(|i| i.provider == provider && i.subject == subject)
+        {
+            return Err(RepositoryError::UniqueViolation(
+                "user_identities.provider_subject".to_string(),
+            ));
+        }
+
+        let identity = UserIdentity {
+            id: Uuid::new_v4(),
+            user_id,
+            provider: provider.to_string(),
+            subject: subject.to_string(),
+            email: email.map(str::to_string),
+            created_at: Utc::now(),
+            last_login_at: None,
+        };
+        store.identities.insert(identity.id, identity.clone());
+        Ok(identity)
+    }
+
+    async fn find(&self, provider: &str, subject: &str) -> RepositoryResult<Option<UserIdentity>> {
+        Ok(self
+            .read()
+            .identities
+            .values()
+            .find(|i| i.provider == provider && i.subject == subject)
+            .cloned())
+    }
+
+    async fn list_for_user(&self, user_id: Uuid) -> RepositoryResult<Vec<UserIdentity>> {
+        let mut identities: Vec<UserIdentity> = self
+            .read()
+            .identities
+            .values()
+            .filter(|i| i.user_id == user_id)
+            .cloned()
+            .collect();
+        identities.sort_by_key(|i| i.created_at);
+        Ok(identities)
+    }
+
+    async fn touch(&self, id: Uuid) -> RepositoryResult<()> {
+        if let Some(identity) = self.write().identities.get_mut(&id) {
+            identity.last_login_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, user_id: Uuid, id: Uuid) -> RepositoryResult<bool> {
+        let mut store = self.write();
+        if store
+            .identities
+            .get(&id)
+            .is_none_or(|i| i.user_id != user_id)
+        {
+            return Ok(false);
+        }
+        store.identities.remove(&id);
+        Ok(true)
+    }
+}
+
+/// The `user.id` of an outbox event's payload
+fn event_user_id(payload: &Value) -> Option<&str> {
+    payload.get("user")?.get("id")?.as_str()
+}
+
+#[async_trait]
+impl PrivacyRepository for InMemoryRepository {
+    async fn find_user(&self, user_id: Uuid) -> RepositoryResult<Option<User>> {
+        Ok(self.read().users.get(&user_id).cloned())
+    }
+
+    async fn user_groups(&self, user_id: Uuid) -> RepositoryResult<Vec<Group>> {
+        let store = self.read();
+        let mut groups: Vec<Group> = store
+            .group_members
+            .iter()
+            .filter(|(_, member)| *member == user_id)
+            .filter_map(|(group_id, _)| store.groups.get(group_id).cloned())
+            .collect();
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(groups)
+    }
+
+    async fn user_invitations(
+        &self,
+        user_id: Uuid,
+        email: &str,
+    ) -> RepositoryResult<Vec<Invitation>> {
+        let mut invitations: Vec<Invitation> = self
+            .read()
+            .invitations
+            .values()
+            .filter(|i| i.accepted_user_id == Some(user_id) || i.email == email)
+            .cloned()
+            .collect();
+        invitations.sort_by_key(|i| std::cmp::Reverse(i.created_at));
+        Ok(invitations)
+    }
+
+    async fn user_events(&self, user_id: Uuid) -> RepositoryResult<Vec<WebhookEvent>> {
+        let user_id = user_id.to_string();
+        Ok(self
+            .read()
+            .webhook_events
+            .iter()
+            .filter(|e| {
+                serde_json::from_str::<Value>(&e.payload)
+                    .is_ok_and(|payload| event_user_id(&payload) == Some(user_id.as_str()))
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn user_impersonations(
+        &self,
+        user_id: Uuid,
+    ) -> RepositoryResult<Vec<ImpersonationEvent>> {
+        Ok(self
+            .read()
+            .impersonations
+            .iter()
+            .rev()
+            .filter(|e| e.actor_id == user_id || e.target_user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn erase_user(
+        &self,
+        user_id: Uuid,
+        requested_by: Uuid,
+        reason: Option<&str>,
+    ) -> RepositoryResult<Option<Erasure>> {
+        let mut store = self.write();
+
+        let Some(email) = store.users.get(&user_id).map(|u| u.email.clone()) else {
+            return Ok(None);
+        };
+        // Invitations addressed to the user carry their email address
+        let addressed: HashSet<Uuid> = store
+            .invitations
+            .values()
+            .filter(|i| i.accepted_user_id == Some(user_id) || i.email == email)
+            .map(|i| i.id)
+            .collect();
+        store.invitations.retain(|id, _| !addressed.contains(id));
+        store
+            .invitation_roles
+            .retain(|(invitation_id, _)| !addressed.contains(invitation_id));
+        store.remove_users(&HashSet::from([user_id]));
+
+        let pseudonym_id = Uuid::new_v4();
+        let pseudonym_username = Erasure::pseudonym_username(pseudonym_id);
+        for invitation in store.invitations.values_mut() {
+            if invitation.invited_by == Some(user_id) {
+                invitation.invited_by = Some(pseudonym_id);
+            }
+        }
+        let user_id_text = user_id.to_string();
+        for event in &mut store.webhook_events {
+            let Ok(mut payload) = serde_json::from_str::<Value>(&event.payload) else {
+                continue;
+            };
+            if event_user_id(&payload) != Some(user_id_text.as_str()) {
+                continue;
+            }
+            if let Some(user) = payload.get_mut("user").and_then(Value::as_object_mut) {
+                user.insert("id".to_string(), Value::from(pseudonym_id.to_string()));
+                user.insert(
+                    "username".to_string(),
+                    Value::from(pseudonym_username.clone()),
+                );
+                user.insert("email".to_string(), Value::Null);
+            }
+            event.payload = payload.to_string();
+        }
+
+        for event in &mut store.impersonations {
+            if event.actor_id == user_id {
+                event.actor_id = pseudonym_id;
+                event.actor_username = pseudonym_username.clone();
+            }
+            if event.target_user_id == user_id {
+                event.target_user_id = pseudonym_id;
+                event.target_username = pseudonym_username.clone();
+            }
+        }
+        for erasure in &mut store.erasures {
+            if erasure.requested_by == user_id {
+                erasure.requested_by = pseudonym_id;
+            }
+        }
+        let erasure = Erasure {
+            id: Uuid::new_v4(),
+            pseudonym_id,
+            // An admin erasing themselves is recorded under their pseudonym
+            requested_by: if requested_by == user_id {
+                pseudonym_id
+            } else {
+                requested_by
+            },
+            reason: reason.map(str::to_string),
+            created_at: Utc::now(),
+        };
+        store.erasures.push(erasure.clone());
+        store.record_events([NewEvent::erasure(user_id)]);
+
+        Ok(Some(erasure))
+    }
+
+    async fn list_erasures(&self) -> RepositoryResult<Vec<Erasure>> {
+        Ok(self.read().erasures.iter().rev().cloned().collect())
+    }
+}
+
+#[async_trait]
+impl PreferenceRepository for InMemoryRepository {
+    async fn get(&self, user_id: Uuid) -> RepositoryResult<Option<Preferences>> {
+        Ok(self.read().preferences.get(&user_id).cloned())
+    }
+
+    async fn put(&self, user_id: Uuid, preferences: &Preferences) -> RepositoryResult<()> {
+        self.write()
+            .preferences
+            .insert(user_id, preferences.clone());
+        Ok(())
+    }
+
+    async fn delete(&self, user_id: Uuid) -> RepositoryResult<bool> {
+        Ok(self.write().preferences.remove(&user_id).is_some())
+    }
+}
+
+#[async_trait]
+impl GroupRepository for InMemoryRepository {
+    async fn create(&self, name: &str, description: Option<&str>) -> RepositoryResult<Group> {
+        let mut store = self.write();
+        if store.groups.values().any(|g| g.name == name) {
+            return Err(RepositoryError::UniqueViolation("groups.name".to_string()));
+        }
+        let group = Group {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            description: description.map(str::to_string),
+            created_at: Utc::now(),
+        };
+        store.groups.insert(group.id, group.clone());
+        Ok(group)
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<Group>> {
+        Ok(self.read().groups.get(&id).cloned())
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<Group>> {
+        Ok(sorted_groups(self.read().groups.values()))
+    }
+
+    async fn delete(&self, id: Uuid) -> RepositoryResult<bool> {
+        let mut store = self.write();
+        let before = store.snapshot_roles(store.subtree_members(&[id]));
+        if store.groups.remove(&id).is_none() {
+            return Ok(false);
+        }
+        store.group_members.retain(|(group_id, _)| *group_id != id);
+        store
+            .group_subgroups
+            .retain(|(parent, child)| *parent != id && *child != id);
+        store.group_roles.retain(|(group_id, _)| *group_id != id);
+        store.record_roles_changes(before);
+        Ok(true)
+    }
+
+    async fn get_members(&self, id: Uuid) -> RepositoryResult<Vec<User>> {
+        let store = self.read();
+        Ok(sorted_users(
+            store
+                .group_members
+                .iter()
+                .filter(|(g, _)| *g == id)
+                .filter_map(|(_, u)| store.users.get(u)),
+        ))
+    }
+
+    async fn add_members(&self, id: Uuid, user_ids: &[Uuid]) -> RepositoryResult<u64> {
+        let mut store = self.write();
+        if !user_ids.is_empty() {
+            check_reference(&store.groups, &id, "group_members.group_id")?;
+        }
+        for user_id in user_ids {
+            check_reference(&store.users, user_id, "group_members.user_id")?;
+        }
+        let before = store.snapshot_roles(user_ids.iter().copied());
+        let added = user_ids
+            .iter()
+            .filter(|user_id| store.group_members.insert((id, **user_id)))
+            .count();
+        store.record_roles_changes(before);
+        Ok(added as u64)
+    }
+
+    async fn remove_members(&self, id: Uuid, user_ids: &[Uuid]) -> RepositoryResult<u64> {
+        let mut store = self.write();
+        let before = store.snapshot_roles(user_ids.iter().copied());
+        let removed = user_ids
+            .iter()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .filter(|user_id| store.group_members.remove(&(id, **user_id)))
+            .count();
+        store.record_roles_changes(before);
+        Ok(removed as u64)
+    }
+
+    async fn get_subgroups(&self, id: Uuid) -> RepositoryResult<Vec<Group>> {
+        let store = self.read();
+        Ok(sorted_groups(
+            store
+                .group_subgroups
+                .iter()
+                .filter(|(parent, _)| *parent == id)
+                .filter_map(|(_, child)| store.groups.get(child)),
+        ))
+    }
+
+    async fn add_subgroup(&self, id: Uuid, child_id: Uuid) -> RepositoryResult<bool> {
+        let mut store = self.write();
+        if store.contains_group(child_id, id) {
+            return Ok(false);
+        }
+        check_reference(&store.groups, &id, "group_subgroups.parent_group_id")?;
+        check_reference(&store.groups, &child_id, "group_subgroups.child_group_id")?;
+        let before = store.snapshot_roles(store.subtree_members(&[child_id]));
+        store.group_subgroups.insert((id, child_id));
+        store.record_roles_changes(before);
+        Ok(true)
+    }
+
+    async fn remove_subgroup(&self, id: Uuid, child_id: Uuid) -> RepositoryResult<bool> {
+        let mut store = self.write();
+        let before = store.snapshot_roles(store.subtree_members(&[child_id]));
+        let removed = store.group_subgroups.remove(&(id, child_id));
+        store.record_roles_changes(before);
+        Ok(removed)
+    }
+
+    async fn get_roles(&self, id: Uuid) -> RepositoryResult<Vec<Role>> {
+        let store = self.read();
+        Ok(sorted_roles(
+            store
+                .group_roles
+                .iter()
+                .filter(|(g, _)| *g == id)
+                .filter_map(|(_, r)| store.roles.get(r)),
+        ))
+    }
+
+    async fn assign_role(&self, id: Uuid, role_id: Uuid) -> RepositoryResult<()> {
+        let mut store = self.write();
+        check_reference(&store.groups, &id, "group_roles.group_id")?;
+        check_reference(&store.roles, &role_id, "group_roles.role_id")?;
+        let before = store.snapshot_roles(store.subtree_members(&[id]));
+        store.group_roles.insert((id, role_id));
+        store.record_roles_changes(before);
+        Ok(())
+    }
+
+    async fn remove_role(&self, id: Uuid, role_id: Uuid) -> RepositoryResult<bool> {
+        let mut store = self.write();
+        let before = store.snapshot_roles(store.subtree_members(&[id]));
+        let removed = store.group_roles.remove(&(id, role_id));
+        store.record_roles_changes(before);
+        Ok(removed)
+    }
+}
+
+fn insert_invitation(
+    store: &mut Store,
+    email: &str,
+    token_hash: &str,
+    invited_by: Option<Uuid>,
+    expires_at: DateTime<Utc>,
+    role_ids: &[Uuid],
+) -> RepositoryResult<Invitation> {
+    if store
+        .invitations
+        .values()
+        .any(|i| i.token_hash == token_hash)
+    {
+        return Err(RepositoryError::UniqueViolation(
+            "invitations.token_hash".to_string(),
+        ));
+    }
+    for role_id in role_ids {
+        check_reference(&store.roles, role_id, "invitation_roles.role_id")?;
+    }
+
+    let invitation = Invitation {
+        id: Uuid::new_v4(),
+        email: normalize_email(email),
+        token_hash: token_hash.to_string(),
+        invited_by,
+        expires_at,
+        created_at: Utc::now(),
+        accepted_at: None,
+        accepted_user_id: None,
+        revoked_at: None,
+    };
+    store.invitations.insert(invitation.id, invitation.clone());
+    for role_id in role_ids {
+        store.invitation_roles.insert((invitation.id, *role_id));
+    }
+
+    Ok(invitation)
+}
+
+#[async_trait]
+impl InvitationRepository for InMemoryRepository {
+    async fn create(
+        &self,
+        email: &str,
+        token_hash: &str,
+        invited_by: Option<Uuid>,
+        expires_at: DateTime<Utc>,
+        role_ids: &[Uuid],
+    ) -> RepositoryResult<Invitation> {
+        let mut store = self.write();
+        insert_invitation(
+            &mut store, email, token_hash, invited_by, expires_at, role_ids,
+        )
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<Invitation>> {
+        Ok(self.read().invitations.get(&id).cloned())
+    }
+
+    async fn find_by_token_hash(&self, token_hash: &str) -> RepositoryResult<Option<Invitation>> {
+        Ok(self
+            .read()
+            .invitations
+            .values()
+            .find(|i| i.token_hash == token_hash)
+            .cloned())
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<Invitation>> {
+        let mut invitations: Vec<Invitation> = self.read().invitations.values().cloned().collect();
+        invitations.sort_by_key(|i| std::cmp::Reverse(i.created_at));
+        Ok(invitations)
+    }
+
+    async fn get_roles(&self, id: Uuid) -> RepositoryResult<Vec<Role>> {
+        let store = self.read();
+        Ok(sorted_roles(
+            store
+                .invitation_roles
+                .iter()
+                .filter(|(i, _)| *i == id)
+                .filter_map(|(_, r)| store.roles.get(r)),
+        ))
+    }
+
+    async fn revoke(&self, id: Uuid) -> RepositoryResult<bool> {
+        let mut store = self.write();
+        match store.invitations.get_mut(&id) {
+            Some(invitation)
+                if invitation.accepted_at.is_none() && invitation.revoked_at.is_none() =>
+            {
+                invitation.revoked_at = Some(Utc::now());
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn accept(
+        &self,
+        id: Uuid,
+        username: &str,
+        password_hash: &str,
+    ) -> RepositoryResult<Option<User>> {
+        let mut store = self.write();
+        let Some(invitation) = store
+            .invitations
+            .get(&id)
+            .filter(|i| i.status() == InvitationStatus::Pending)
+        else {
+            return Ok(None);
+        };
+        let email = invitation.email.clone();
+
+        let user = insert_user(&mut store, username, &email, password_hash)?;
+        let invitation = store.invitations.get_mut(&id).expect("checked above");
+        invitation.accepted_at = Some(Utc::now());
+        invitation.accepted_user_id = Some(user.id);
+
+        let role_ids: Vec<Uuid> = store
+            .invitation_roles
+            .iter()
+            .filter(|(i, _)| *i == id)
+            .map(|(_, r)| *r)
+            .collect();
+        let mut events = vec![NewEvent::user(EventType::UserCreated, &user)];
+        for role_id in role_ids {
+            store.user_roles.insert((user.id, role_id));
+            events.push(NewEvent::role(
+                EventType::UserRoleAssigned,
+                &user,
+                &store.roles[&role_id],
+            ));
+        }
+        store.record_events(events);
+
+        Ok(Some(user))
+    }
+}
+
+#[async_trait]
+impl ImpersonationRepository for InMemoryRepository {
+    async fn create(
+        &self,
+        actor_id: Uuid,
+        actor_username: &str,
+        target: &User,
+        reason: &str,
+        expires_at: DateTime<Utc>,
+    ) -> RepositoryResult<ImpersonationEvent> {
+        let event = ImpersonationEvent {
+            id: Uuid::new_v4(),
+            actor_id,
+            actor_username: actor_username.to_string(),
+            target_user_id: target.id,
+            target_username: target.username.clone(),
+            reason: reason.to_string(),
+            expires_at,
+            created_at: Utc::now(),
+        };
+        self.write().impersonations.push(event.clone());
+        Ok(event)
+    }
+
+    async fn list(
+        &self,
+        target_user_id: Option<Uuid>,
+    ) -> RepositoryResult<Vec<ImpersonationEvent>> {
+        Ok(self
+            .read()
+            .impersonations
+            .iter()
+            .rev()
+            .filter(|e| target_user_id.is_none_or(|id| e.target_user_id == id))
+            .cloned()
+            .collect())
+    }
+}
+
+fn import_account(store: &mut Store, account: &NewAccount<'_>) -> RepositoryResult<Uuid> {
+    match *account {
+        NewAccount::User {
+            username,
+            email,
+            password_hash,
+            is_active,
+            role_ids,
+        } => {
+            for role_id in role_ids {
+                check_reference(&store.roles, role_id, "user_roles.role_id")?;
+            }
+            let mut user = insert_user(store, username, email, password_hash)?;
+            user.is_active = is_active;
+            store.users.insert(user.id, user.clone());
+
+            let mut events = vec![NewEvent::user(EventType::UserCreated, &user)];
+            for role_id in role_ids {
+                if store.user_roles.insert((user.id, *role_id)) {
+                    let role = &store.roles[role_id];
+                    events.push(NewEvent::role(EventType::UserRoleAssigned, &user, role));
+                }
+            }
+            store.record_events(events);
+            Ok(user.id)
+        }
+        NewAccount::Invitation {
+            email,
+            token_hash,
+            invited_by,
+            expires_at,
+            role_ids,
+        } => {
+            let invitation =
+                insert_invitation(store, email, token_hash, invited_by, expires_at, role_ids)?;
+            Ok(invitation.id)
+        }
+    }
+}
+
+#[async_trait]
+impl ProvisioningRepository for InMemoryRepository {
+    async fn import(&self, accounts: &[NewAccount<'_>]) -> Result<Vec<Uuid>, ImportError> {
+        let mut store = self.write();
+        // Work on a copy so a failure part way leaves nothing behind
+        let mut staged = store.clone();
+        let ids = accounts
+            .iter()
+            .enumerate()
+            .map(|(index, account)| {
+                import_account(&mut staged, account).map_err(|source| ImportError {
+                    index: Some(index),
+                    source,
+                })
+            })
+            .collect::<Result<Vec<Uuid>, ImportError>>()?;
+        *store = staged;
+
+        Ok(ids)
+    }
+
+    async fn find_by_usernames_or_emails(
+        &self,
+        usernames: &[String],
+        emails: &[String],
+    ) -> RepositoryResult<Vec<User>> {
+        let usernames: HashSet<String> = usernames.iter().map(|u| fold_username(u)).collect();
+        let emails: HashSet<String> = emails.iter().map(|e| normalize_email(e)).collect();
+        Ok(self
+            .read()
+            .users
+            .values()
+            .filter(|u| {
+                usernames.contains(&fold_username(&u.username)) || emails.contains(&u.email)
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn search(
+        &self,
+        filter: &UserFilter,
+        offset: i64,
+        limit: i64,
+    ) -> RepositoryResult<(Vec<User>, i64)> {
+        let store = self.read();
+        let mut users: Vec<&User> = store
+            .users
+            .values()
+            .filter(|u| u.deleted_at.is_none())
+            .filter(|u| filter.matches(u, &store.direct_roles(u.id)))
+            .collect();
+        users.sort_by_key(|u| (u.created_at, u.id));
+
+        let total = users.len() as i64;
+        let page = users
+            .into_iter()
+            .skip(usize::try_from(offset).unwrap_or(0))
+            .take(usize::try_from(limit).unwrap_or(0))
+            .cloned()
+            .collect();
+        Ok((page, total))
+    }
+
+    async fn roles_of(&self, user_ids: &[Uuid]) -> RepositoryResult<HashMap<Uuid, Vec<Role>>> {
+        let store = self.read();
+        Ok(user_ids
+            .iter()
+            .map(|user_id| (*user_id, store.direct_roles(*user_id)))
+            .filter(|(_, roles)| !roles.is_empty())
+            .collect())
+    }
+
+    async fn export_page(
+        &self,
+        after: Option<Uuid>,
+        limit: i64,
+    ) -> RepositoryResult<Vec<UserWithRoles>> {
+        let store = self.read();
+        let mut users: Vec<&User> = store
+            .users
+            .values()
+            .filter(|u| u.deleted_at.is_none() && after.is_none_or(|after| u.id > after))
+            .collect();
+        users.sort_by_key(|u| u.id);
+
+        Ok(users
+            .into_iter()
+            .take(usize::try_from(limit).unwrap_or(0))
+            .map(|user| {
+                let mut roles: Vec<String> = store
+                    .user_roles
+                    .iter()
+                    .filter(|(u, _)| *u == user.id)
+                    .filter_map(|(_, r)| store.roles.get(r))
+                    .map(|r| r.name.clone())
+                    .collect();
+                roles.sort();
+                UserWithRoles {
+                    id: user.id,
+                    username: user.username.clone(),
+                    email: user.email.clone(),
+                    password_hash: user.password_hash.clone(),
+                    created_at: user.created_at,
+                    is_active: user.is_active,
+                    roles,
+                }
+            })
+            .collect())
+    }
+}
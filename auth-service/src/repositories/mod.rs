@@ -0,0 +1,906 @@
+//! Storage abstraction for the core identity data.
+//!
+//! Handlers for users, roles, permissions, their assignments, groups,
+//! invitations and login sessions go through these traits (bundled in
+//! [`Repositories`]) rather than a `PgPool`, so they can run against Postgres in production, against SQLite
+//! for single-node installs, and against the in-memory backend in tests that
+//! must not need a database.
+//!
+//! Writes that change a user's lifecycle state or direct roles also add the
+//! matching webhook events to the outbox, atomically with the change.
+
+pub mod memory;
+pub mod postgres;
+pub mod sqlite;
+
+use crate::models::user::{UpdateUser, UserWithRoles};
+use crate::models::{
+    DeliveryAttempt, DeviceAuthorization, DueDelivery, Erasure, Group, ImpersonationEvent,
+    Invitation, NewCredential, Permission, Role, ServiceAccount, ServiceAccountCredential, Session,
+    User, UserIdentity, Webhook, WebhookDelivery, WebhookEvent,
+};
+use crate::services::scim_filter::CompareOp;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use shared::{Preferences, RolePermissions};
+use sqlx::{PgPool, SqlitePool};
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+pub use memory::InMemoryRepository;
+pub use postgres::PgRepository;
+pub use sqlite::SqliteRepository;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RepositoryError {
+    #[error("{0}")]
+    Database(sqlx::Error),
+
+    /// A unique constraint failed; holds the `table.column` it covers
+    #[error("duplicate key value violates unique constraint on {0}")]
+    UniqueViolation(String),
+
+    /// A referenced row does not exist; holds the referencing `table.column`
+    /// where the backend reports it (SQLite does not, leaving it empty)
+    #[error("foreign key constraint failed")]
+    ForeignKeyViolation(String),
+
+    /// A conditional write found the row at a different version than expected
+    #[error("row has been modified since it was read")]
+    VersionMismatch,
+}
+
+impl RepositoryError {
+    /// Whether this is a unique violation on `target`, e.g. `"users.email"`
+    pub fn is_unique_violation_on(&self, target: &str) -> bool {
+        matches!(self, Self::UniqueViolation(t) if t == target)
+    }
+}
+
+impl From<sqlx::Error> for RepositoryError {
+    /// Classifies constraint failures so callers can tell a conflict from an
+    /// outage without checking first (which would race) or parsing messages
+    fn from(e: sqlx::Error) -> Self {
+        let sqlx::Error::Database(db) = &e else {
+            return Self::Database(e);
+        };
+        if db.is_unique_violation() {
+            Self::UniqueViolation(constraint_target(db.as_ref()))
+        } else if db.is_foreign_key_violation() {
+            Self::ForeignKeyViolation(constraint_target(db.as_ref()))
+        } else {
+            Self::Database(e)
+        }
+    }
+}
+
+/// Names the constrained column as `table.column`. Postgres reports the
+/// constraint (`users_email_key`, `user_roles_role_id_fkey`); SQLite spells it
+/// out in the message for unique constraints only. A clash on a folded column
+/// (`users.username_folded`) is reported on the column it folds.
+fn constraint_target(db: &dyn sqlx::error::DatabaseError) -> String {
+    if let (Some(table), Some(constraint)) = (db.table(), db.constraint()) {
+        let column = constraint
+            .strip_prefix(table)
+            .and_then(|c| c.strip_prefix('_'))
+            .and_then(|c| c.strip_suffix("_fkey").or_else(|| c.strip_suffix("_key")));
+        return match column {
+            Some(column) => format!("{table}.{column}"),
+            None => constraint.to_string(),
+        };
+    }
+    let target = db
+        .message()
+        .strip_prefix("UNIQUE constraint failed: ")
+        .unwrap_or_default();
+    target.strip_suffix("_folded").unwrap_or(target).to_string()
+}
+
+pub type RepositoryResult<T> = Result<T, RepositoryError>;
+
+/// Interprets a conditional write that matched no row: if the row still
+/// exists the version must have been wrong
+fn check_version<T>(
+    written: Option<T>,
+    expected_version: Option<i32>,
+    exists: bool,
+) -> RepositoryResult<Option<T>> {
+    match written {
+        None if expected_version.is_some() && exists => Err(RepositoryError::VersionMismatch),
+        written => Ok(written),
+    }
+}
+
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    async fn create(
+        &self,
+        username: &str,
+        email: &str,
+        password_hash: &str,
+    ) -> RepositoryResult<User>;
+    /// Creates the user holding `role_ids`, or nothing if any step fails
+    async fn create_with_roles(
+        &self,
+        username: &str,
+        email: &str,
+        password_hash: &str,
+        role_ids: &[Uuid],
+    ) -> RepositoryResult<User>;
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<User>>;
+    async fn find_by_username(&self, username: &str) -> RepositoryResult<Option<User>>;
+    async fn find_by_email(&self, email: &str) -> RepositoryResult<Option<User>>;
+    /// Those of `ids` that exist, in no particular order
+    async fn find_by_ids(&self, ids: &[Uuid]) -> RepositoryResult<Vec<User>>;
+    async fn list(&self) -> RepositoryResult<Vec<User>>;
+    /// None if the user does not exist. Given `expected_version`, fails with
+    /// `VersionMismatch` unless the user is still at that version; the same
+    /// applies to the other conditional writes below.
+    async fn update(
+        &self,
+        id: Uuid,
+        update: &UpdateUser,
+        expected_version: Option<i32>,
+    ) -> RepositoryResult<Option<User>>;
+    /// Soft-deletes the user: every lookup above skips them from now on
+    async fn delete(&self, id: Uuid, expected_version: Option<i32>) -> RepositoryResult<bool>;
+    /// Undoes `delete`; None if no deleted user has this id
+    async fn restore(&self, id: Uuid) -> RepositoryResult<Option<User>>;
+    /// Soft-deleted users, most recently deleted first
+    async fn list_deleted(&self) -> RepositoryResult<Vec<User>>;
+    /// Permanently removes users deleted before `before`; returns how many
+    async fn purge_deleted(&self, before: DateTime<Utc>) -> RepositoryResult<u64>;
+}
+
+#[async_trait]
+pub trait RoleRepository: Send + Sync {
+    async fn create(&self, name: &str, description: Option<&str>) -> RepositoryResult<Role>;
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<Role>>;
+    async fn find_by_name(&self, name: &str) -> RepositoryResult<Option<Role>>;
+    async fn list(&self) -> RepositoryResult<Vec<Role>>;
+    async fn update(
+        &self,
+        id: Uuid,
+        name: &str,
+        description: Option<&str>,
+        expected_version: Option<i32>,
+    ) -> RepositoryResult<Option<Role>>;
+    async fn delete(&self, id: Uuid, expected_version: Option<i32>) -> RepositoryResult<bool>;
+}
+
+#[async_trait]
+pub trait PermissionRepository: Send + Sync {
+    async fn create(
+        &self,
+        name: &str,
+        resource: &str,
+        action: &str,
+    ) -> RepositoryResult<Permission>;
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<Permission>>;
+    async fn find_by_name(&self, name: &str) -> RepositoryResult<Option<Permission>>;
+    async fn list(&self) -> RepositoryResult<Vec<Permission>>;
+    async fn update(
+        &self,
+        id: Uuid,
+        name: &str,
+        resource: &str,
+        action: &str,
+        expected_version: Option<i32>,
+    ) -> RepositoryResult<Option<Permission>>;
+    async fn delete(&self, id: Uuid, expected_version: Option<i32>) -> RepositoryResult<bool>;
+}
+
+/// User-role and role-permission links
+#[async_trait]
+pub trait AssignmentRepository: Send + Sync {
+    async fn assign_role_to_user(&self, user_id: Uuid, role_id: Uuid) -> RepositoryResult<()>;
+    /// Returns false if the user did not have the role
+    async fn remove_role_from_user(&self, user_id: Uuid, role_id: Uuid) -> RepositoryResult<bool>;
+    /// Roles assigned directly to the user
+    async fn get_user_roles(&self, user_id: Uuid) -> RepositoryResult<Vec<Role>>;
+    /// Direct roles plus any inherited through group membership
+    async fn get_effective_user_roles(&self, user_id: Uuid) -> RepositoryResult<Vec<Role>>;
+    async fn assign_permission_to_role(
+        &self,
+        role_id: Uuid,
+        permission_id: Uuid,
+    ) -> RepositoryResult<()>;
+    /// Returns false if the role did not have the permission
+    async fn remove_permission_from_role(
+        &self,
+        role_id: Uuid,
+        permission_id: Uuid,
+    ) -> RepositoryResult<bool>;
+    async fn get_role_permissions(&self, role_id: Uuid) -> RepositoryResult<Vec<Permission>>;
+    /// Bumped whenever what a role name grants may have changed
+    async fn permissions_version(&self) -> RepositoryResult<i64>;
+    /// The permission names granted by every role, labelled with a version
+    /// no newer than the mapping
+    async fn role_permissions(&self) -> RepositoryResult<RolePermissions>;
+    /// Whether any user holds the role directly
+    async fn role_has_users(&self, role_id: Uuid) -> RepositoryResult<bool>;
+    /// The users holding the role directly, ordered by username
+    async fn get_role_members(&self, role_id: Uuid) -> RepositoryResult<Vec<User>>;
+    /// Makes `user_ids` exactly the users holding the role directly.
+    /// Returns the users added and the users removed.
+    async fn set_role_members(
+        &self,
+        role_id: Uuid,
+        user_ids: &[Uuid],
+    ) -> RepositoryResult<(Vec<Uuid>, Vec<Uuid>)>;
+}
+
+#[async_trait]
+pub trait SessionRepository: Send + Sync {
+    async fn create(
+        &self,
+        user_id: Uuid,
+        user_agent: Option<&str>,
+        ip_address: Option<&str>,
+        expires_at: DateTime<Utc>,
+    ) -> RepositoryResult<Session>;
+    async fn list_active_for_user(&self, user_id: Uuid) -> RepositoryResult<Vec<Session>>;
+    /// Every session of the user, revoked and expired ones included, newest first
+    async fn list_for_user(&self, user_id: Uuid) -> RepositoryResult<Vec<Session>>;
+    /// Records use of a session; false if it is revoked, expired or unknown
+    async fn touch(&self, id: Uuid) -> RepositoryResult<bool>;
+    async fn revoke(&self, user_id: Uuid, id: Uuid) -> RepositoryResult<bool>;
+    async fn revoke_all_for_user(
+        &self,
+        user_id: Uuid,
+        except: Option<Uuid>,
+    ) -> RepositoryResult<u64>;
+}
+
+/// The one-time token for creating the first admin; at most one is outstanding
+#[async_trait]
+pub trait SetupTokenRepository: Send + Sync {
+    /// Replaces any outstanding token
+    async fn replace(&self, token_hash: &str, expires_at: DateTime<Utc>) -> RepositoryResult<()>;
+    /// Deletes the token if it matches and is unexpired; false otherwise
+    async fn consume(&self, token_hash: &str) -> RepositoryResult<bool>;
+    async fn clear(&self) -> RepositoryResult<()>;
+}
+
+/// Webhook endpoints, and the outbox and delivery log behind them
+#[async_trait]
+pub trait WebhookRepository: Send + Sync {
+    async fn create(
+        &self,
+        url: &str,
+        secret: &str,
+        event_types: &[String],
+    ) -> RepositoryResult<Webhook>;
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<Webhook>>;
+    async fn list(&self) -> RepositoryResult<Vec<Webhook>>;
+    /// Deletes the webhook with its delivery log
+    async fn delete(&self, id: Uuid) -> RepositoryResult<bool>;
+    /// The webhook's most recent deliveries, newest first
+    async fn list_deliveries(
+        &self,
+        webhook_id: Uuid,
+        limit: i64,
+    ) -> RepositoryResult<Vec<WebhookDelivery>>;
+    /// Queues a new delivery of the event behind one of the webhook's
+    /// deliveries; None if it has no such delivery
+    async fn redeliver(
+        &self,
+        webhook_id: Uuid,
+        delivery_id: Uuid,
+    ) -> RepositoryResult<Option<WebhookDelivery>>;
+    /// Fans up to `limit` new events out into a pending delivery per
+    /// subscribed webhook; returns the number of deliveries created
+    async fn dispatch_events(&self, limit: i64) -> RepositoryResult<u64>;
+    /// Claims up to `limit` deliveries that are due, counting the attempt
+    /// about to be made and hiding them from other dispatchers for
+    /// `lease_seconds`
+    async fn claim_due_deliveries(
+        &self,
+        limit: i64,
+        lease_seconds: i32,
+    ) -> RepositoryResult<Vec<DueDelivery>>;
+    async fn record_attempt(
+        &self,
+        delivery_id: Uuid,
+        attempt: &DeliveryAttempt,
+    ) -> RepositoryResult<()>;
+    /// Deletes events dispatched before `before` that have no pending
+    /// deliveries, with their delivery logs; returns how many
+    async fn purge_events(&self, before: DateTime<Utc>) -> RepositoryResult<u64>;
+    /// Every event still in the outbox, oldest first
+    async fn list_events(&self) -> RepositoryResult<Vec<WebhookEvent>>;
+}
+
+/// Service accounts with their roles and credentials
+#[async_trait]
+pub trait ServiceAccountRepository: Send + Sync {
+    async fn create(
+        &self,
+        name: &str,
+        description: Option<&str>,
+        owner_id: Option<Uuid>,
+    ) -> RepositoryResult<ServiceAccount>;
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<ServiceAccount>>;
+    /// Ordered by name
+    async fn list(&self) -> RepositoryResult<Vec<ServiceAccount>>;
+    /// None if the account does not exist
+    async fn update(
+        &self,
+        id: Uuid,
+        description: Option<&str>,
+        owner_id: Option<Uuid>,
+        is_active: bool,
+    ) -> RepositoryResult<Option<ServiceAccount>>;
+    /// Deletes the account with its roles and credentials
+    async fn delete(&self, id: Uuid) -> RepositoryResult<bool>;
+    async fn assign_role(&self, id: Uuid, role_id: Uuid) -> RepositoryResult<()>;
+    /// Returns false if the account did not have the role
+    async fn remove_role(&self, id: Uuid, role_id: Uuid) -> RepositoryResult<bool>;
+    async fn get_roles(&self, id: Uuid) -> RepositoryResult<Vec<Role>>;
+    async fn add_credential(
+        &self,
+        id: Uuid,
+        credential: NewCredential<'_>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> RepositoryResult<ServiceAccountCredential>;
+    /// The account's credentials, expired ones included, newest first
+    async fn list_credentials(&self, id: Uuid) -> RepositoryResult<Vec<ServiceAccountCredential>>;
+    /// Returns false if the account has no such credential
+    async fn delete_credential(&self, id: Uuid, credential_id: Uuid) -> RepositoryResult<bool>;
+    /// Records that the credential was just used to get a token
+    async fn touch_credential(&self, credential_id: Uuid) -> RepositoryResult<()>;
+    /// Records the `jti` of a client assertion the account signed, until it
+    /// expires; false if an unexpired one with that `jti` was already used
+    async fn use_assertion(
+        &self,
+        id: Uuid,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> RepositoryResult<bool>;
+    /// Forgets assertions that expired before `before`; returns how many
+    async fn purge_used_assertions(&self, before: DateTime<Utc>) -> RepositoryResult<u64>;
+}
+
+/// Pending device authorization requests (RFC 8628)
+#[async_trait]
+pub trait DeviceAuthorizationRepository: Send + Sync {
+    async fn create(
+        &self,
+        device_code_hash: &str,
+        user_code: &str,
+        client_id: &str,
+        interval_seconds: i32,
+        expires_at: DateTime<Utc>,
+    ) -> RepositoryResult<DeviceAuthorization>;
+    async fn find_by_user_code(
+        &self,
+        user_code: &str,
+    ) -> RepositoryResult<Option<DeviceAuthorization>>;
+    async fn find_by_device_code(
+        &self,
+        device_code_hash: &str,
+    ) -> RepositoryResult<Option<DeviceAuthorization>>;
+    /// Records the user's decision on a pending, unexpired request; None if
+    /// there is none, so each request is decided once
+    async fn decide(
+        &self,
+        user_code: &str,
+        user_id: Uuid,
+        approve: bool,
+    ) -> RepositoryResult<Option<DeviceAuthorization>>;
+    /// Records a poll by the device and the interval it must keep to
+    async fn record_poll(&self, id: Uuid, interval_seconds: i32) -> RepositoryResult<()>;
+    /// False if already deleted, so an outcome is collected only once
+    async fn delete(&self, id: Uuid) -> RepositoryResult<bool>;
+    /// Deletes requests that expired before `before`; returns how many
+    async fn purge_expired(&self, before: DateTime<Utc>) -> RepositoryResult<u64>;
+}
+
+/// Local users' accounts at upstream OpenID Connect providers
+#[async_trait]
+pub trait IdentityRepository: Send + Sync {
+    /// Links the account `subject` at `provider` to `user_id`; fails with a
+    /// unique violation if it is already linked
+    async fn create(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        subject: &str,
+        email: Option<&str>,
+    ) -> RepositoryResult<UserIdentity>;
+    async fn find(&self, provider: &str, subject: &str) -> RepositoryResult<Option<UserIdentity>>;
+    async fn list_for_user(&self, user_id: Uuid) -> RepositoryResult<Vec<UserIdentity>>;
+    /// Records a sign-in through the identity
+    async fn touch(&self, id: Uuid) -> RepositoryResult<()>;
+    /// Returns false if `user_id` has no identity `id`
+    async fn delete(&self, user_id: Uuid, id: Uuid) -> RepositoryResult<bool>;
+}
+
+/// What is held about a user across the audit records, for data exports,
+/// and erasing the user on request
+#[async_trait]
+pub trait PrivacyRepository: Send + Sync {
+    /// The user, soft-deleted or not, as they can still ask for their data
+    /// until they are purged
+    async fn find_user(&self, user_id: Uuid) -> RepositoryResult<Option<User>>;
+    /// Groups the user is a direct member of, by name
+    async fn user_groups(&self, user_id: Uuid) -> RepositoryResult<Vec<Group>>;
+    /// Invitations sent to `email` or accepted by the user, newest first
+    async fn user_invitations(
+        &self,
+        user_id: Uuid,
+        email: &str,
+    ) -> RepositoryResult<Vec<Invitation>>;
+    /// Outbox events about the user, oldest first
+    async fn user_events(&self, user_id: Uuid) -> RepositoryResult<Vec<WebhookEvent>>;
+    /// Impersonations the user carried out or was the target of, newest first
+    async fn user_impersonations(&self, user_id: Uuid)
+        -> RepositoryResult<Vec<ImpersonationEvent>>;
+    /// Deletes the user, soft-deleted or not, with their sessions, roles and
+    /// linked identities, and replaces them with a new pseudonym in the
+    /// records kept for audit; records `user.erased`. None if there is no
+    /// such user.
+    async fn erase_user(
+        &self,
+        user_id: Uuid,
+        requested_by: Uuid,
+        reason: Option<&str>,
+    ) -> RepositoryResult<Option<Erasure>>;
+    /// Newest first
+    async fn list_erasures(&self) -> RepositoryResult<Vec<Erasure>>;
+}
+
+/// Groups of users. Members of a group, and of the groups nested in it,
+/// inherit the roles granted to it.
+#[async_trait]
+pub trait GroupRepository: Send + Sync {
+    async fn create(&self, name: &str, description: Option<&str>) -> RepositoryResult<Group>;
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<Group>>;
+    /// Ordered by name
+    async fn list(&self) -> RepositoryResult<Vec<Group>>;
+    async fn delete(&self, id: Uuid) -> RepositoryResult<bool>;
+    /// Direct members, ordered by username
+    async fn get_members(&self, id: Uuid) -> RepositoryResult<Vec<User>>;
+    /// Returns how many of the users were not members yet
+    async fn add_members(&self, id: Uuid, user_ids: &[Uuid]) -> RepositoryResult<u64>;
+    /// Returns how many of the users were members
+    async fn remove_members(&self, id: Uuid, user_ids: &[Uuid]) -> RepositoryResult<u64>;
+    /// Groups nested directly in the group, ordered by name
+    async fn get_subgroups(&self, id: Uuid) -> RepositoryResult<Vec<Group>>;
+    /// Nests `child_id` in the group. Returns false, changing nothing, if
+    /// the group is `child_id` or already nested in it, as that would make
+    /// a cycle.
+    async fn add_subgroup(&self, id: Uuid, child_id: Uuid) -> RepositoryResult<bool>;
+    async fn remove_subgroup(&self, id: Uuid, child_id: Uuid) -> RepositoryResult<bool>;
+    /// Ordered by name
+    async fn get_roles(&self, id: Uuid) -> RepositoryResult<Vec<Role>>;
+    async fn assign_role(&self, id: Uuid, role_id: Uuid) -> RepositoryResult<()>;
+    /// Returns false if the group did not have the role
+    async fn remove_role(&self, id: Uuid, role_id: Uuid) -> RepositoryResult<bool>;
+}
+
+/// Invitations to sign up with a given email address and roles
+#[async_trait]
+pub trait InvitationRepository: Send + Sync {
+    async fn create(
+        &self,
+        email: &str,
+        token_hash: &str,
+        invited_by: Option<Uuid>,
+        expires_at: DateTime<Utc>,
+        role_ids: &[Uuid],
+    ) -> RepositoryResult<Invitation>;
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<Invitation>>;
+    async fn find_by_token_hash(&self, token_hash: &str) -> RepositoryResult<Option<Invitation>>;
+    /// Newest first
+    async fn list(&self) -> RepositoryResult<Vec<Invitation>>;
+    /// The roles granted on acceptance, ordered by name
+    async fn get_roles(&self, id: Uuid) -> RepositoryResult<Vec<Role>>;
+    /// Revokes a pending invitation; false if it was accepted or revoked already
+    async fn revoke(&self, id: Uuid) -> RepositoryResult<bool>;
+    /// Creates the invited user, holding the invitation's roles, and marks
+    /// the invitation accepted by them. None, creating nothing, if the
+    /// invitation is no longer pending.
+    async fn accept(
+        &self,
+        id: Uuid,
+        username: &str,
+        password_hash: &str,
+    ) -> RepositoryResult<Option<User>>;
+}
+
+/// The audit trail of admins acting as other users
+#[async_trait]
+pub trait ImpersonationRepository: Send + Sync {
+    async fn create(
+        &self,
+        actor_id: Uuid,
+        actor_username: &str,
+        target: &User,
+        reason: &str,
+        expires_at: DateTime<Utc>,
+    ) -> RepositoryResult<ImpersonationEvent>;
+    /// Newest first, optionally only those targeting one user
+    async fn list(&self, target_user_id: Option<Uuid>)
+        -> RepositoryResult<Vec<ImpersonationEvent>>;
+}
+
+/// An account written by [`ProvisioningRepository::import`]
+#[derive(Debug, Clone)]
+pub enum NewAccount<'a> {
+    /// A user whose password was hashed by another system
+    User {
+        username: &'a str,
+        email: &'a str,
+        password_hash: &'a str,
+        is_active: bool,
+        role_ids: &'a [Uuid],
+    },
+    /// An invitation for someone to choose their own username and password
+    Invitation {
+        email: &'a str,
+        token_hash: &'a str,
+        invited_by: Option<Uuid>,
+        expires_at: DateTime<Utc>,
+        role_ids: &'a [Uuid],
+    },
+}
+
+/// An import that failed, naming the account it failed on
+#[derive(Debug, thiserror::Error)]
+#[error("{source}")]
+pub struct ImportError {
+    /// Position of the account in the import; None if the import failed
+    /// before or after writing the accounts
+    pub index: Option<usize>,
+    #[source]
+    pub source: RepositoryError,
+}
+
+impl From<sqlx::Error> for ImportError {
+    fn from(e: sqlx::Error) -> Self {
+        Self {
+            index: None,
+            source: e.into(),
+        }
+    }
+}
+
+/// A user attribute a [`UserFilter`] can compare
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserAttribute {
+    Id,
+    Username,
+    Email,
+    Active,
+    CreatedAt,
+    UpdatedAt,
+    /// The id of any role the user holds directly
+    RoleId,
+    /// The name of any role the user holds directly
+    RoleName,
+}
+
+impl UserAttribute {
+    /// The column on `users u` or, for role attributes, `roles r`
+    fn column(self) -> &'static str {
+        match self {
+            Self::Id => "u.id",
+            Self::Username => "u.username",
+            Self::Email => "u.email",
+            Self::Active => "u.is_active",
+            Self::CreatedAt => "u.created_at",
+            Self::UpdatedAt => "u.updated_at",
+            Self::RoleId => "r.id",
+            Self::RoleName => "r.name",
+        }
+    }
+
+    fn is_role(self) -> bool {
+        matches!(self, Self::RoleId | Self::RoleName)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterValue {
+    /// Compared ignoring case
+    Text(String),
+    Uuid(Uuid),
+    Bool(bool),
+    Time(DateTime<Utc>),
+}
+
+/// Which users [`ProvisioningRepository::search`] returns; the SCIM filters
+/// the backends can evaluate in the database. The handler checks that each
+/// comparison suits its attribute: text attributes take any operator on
+/// `Text`, ids `Eq`/`Ne` on `Uuid`, `Active` `Eq`/`Ne` on `Bool` and the
+/// timestamps the orderings on `Time`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UserFilter {
+    /// Every user, or none
+    All(bool),
+    Compare(UserAttribute, CompareOp, FilterValue),
+    /// Holds any role directly
+    HasRoles,
+    And(Box<UserFilter>, Box<UserFilter>),
+    Or(Box<UserFilter>, Box<UserFilter>),
+    Not(Box<UserFilter>),
+}
+
+impl UserFilter {
+    /// Renders the filter as an SQL condition on `users u`, naming each value
+    /// pushed onto `params` as `{prefix}{position}` (`$1` for Postgres, `?1`
+    /// for SQLite). Role comparisons become `EXISTS` over the user's roles.
+    fn to_sql(&self, prefix: &str, params: &mut Vec<FilterValue>) -> String {
+        match self {
+            Self::All(true) => "1 = 1".to_string(),
+            Self::All(false) => "1 = 0".to_string(),
+            Self::Compare(attribute, op, value) => {
+                let condition = compare_sql(*attribute, *op, value, prefix, params);
+                if attribute.is_role() {
+                    format!(
+                        "EXISTS (SELECT 1 FROM user_roles ur INNER JOIN roles r ON r.id = ur.role_id \
+                         WHERE ur.user_id = u.id AND {condition})"
+                    )
+                } else {
+                    condition
+                }
+            }
+            Self::HasRoles => {
+                "EXISTS (SELECT 1 FROM user_roles ur WHERE ur.user_id = u.id)".to_string()
+            }
+            Self::And(a, b) => format!(
+                "({} AND {})",
+                a.to_sql(prefix, params),
+                b.to_sql(prefix, params)
+            ),
+            Self::Or(a, b) => format!(
+                "({} OR {})",
+                a.to_sql(prefix, params),
+                b.to_sql(prefix, params)
+            ),
+            Self::Not(inner) => format!("NOT ({})", inner.to_sql(prefix, params)),
+        }
+    }
+
+    /// Evaluates the filter against a user and the roles they hold directly,
+    /// for backends without SQL
+    fn matches(&self, user: &User, roles: &[Role]) -> bool {
+        match self {
+            Self::All(all) => *all,
+            Self::Compare(UserAttribute::RoleId, op, value) => roles
+                .iter()
+                .any(|role| compare_values(&FilterValue::Uuid(role.id), *op, value)),
+            Self::Compare(UserAttribute::RoleName, op, value) => roles
+                .iter()
+                .any(|role| compare_values(&FilterValue::Text(role.name.clone()), *op, value)),
+            Self::Compare(attribute, op, value) => {
+                let actual = match attribute {
+                    UserAttribute::Id => FilterValue::Uuid(user.id),
+                    UserAttribute::Username => FilterValue::Text(user.username.clone()),
+                    UserAttribute::Email => FilterValue::Text(user.email.clone()),
+                    UserAttribute::Active => FilterValue::Bool(user.is_active),
+                    UserAttribute::CreatedAt => FilterValue::Time(user.created_at),
+                    UserAttribute::UpdatedAt => FilterValue::Time(user.updated_at),
+                    UserAttribute::RoleId | UserAttribute::RoleName => unreachable!(),
+                };
+                compare_values(&actual, *op, value)
+            }
+            Self::HasRoles => !roles.is_empty(),
+            Self::And(a, b) => a.matches(user, roles) && b.matches(user, roles),
+            Self::Or(a, b) => a.matches(user, roles) || b.matches(user, roles),
+            Self::Not(inner) => !inner.matches(user, roles),
+        }
+    }
+}
+
+fn compare_sql(
+    attribute: UserAttribute,
+    op: CompareOp,
+    value: &FilterValue,
+    prefix: &str,
+    params: &mut Vec<FilterValue>,
+) -> String {
+    let column = attribute.column();
+    let mut param = |value: FilterValue| {
+        params.push(value);
+        format!("{prefix}{}", params.len())
+    };
+    let FilterValue::Text(text) = value else {
+        let operator = match op {
+            CompareOp::Eq => "=",
+            CompareOp::Ne => "<>",
+            CompareOp::Gt => ">",
+            CompareOp::Ge => ">=",
+            CompareOp::Lt => "<",
+            CompareOp::Le => "<=",
+            // Substring operators only apply to text
+            CompareOp::Co | CompareOp::Sw | CompareOp::Ew => return "1 = 0".to_string(),
+        };
+        return format!("{column} {operator} {}", param(value.clone()));
+    };
+
+    let text = text.to_lowercase();
+    let (operator, operand) = match op {
+        CompareOp::Eq => ("=", text),
+        CompareOp::Ne => ("<>", text),
+        CompareOp::Gt => (">", text),
+        CompareOp::Ge => (">=", text),
+        CompareOp::Lt => ("<", text),
+        CompareOp::Le => ("<=", text),
+        CompareOp::Co => ("LIKE", format!("%{}%", escape_like(&text))),
+        CompareOp::Sw => ("LIKE", format!("{}%", escape_like(&text))),
+        CompareOp::Ew => ("LIKE", format!("%{}", escape_like(&text))),
+    };
+    let placeholder = param(FilterValue::Text(operand));
+    if operator == "LIKE" {
+        format!("LOWER({column}) LIKE {placeholder} ESCAPE '\\'")
+    } else {
+        format!("LOWER({column}) {operator} {placeholder}")
+    }
+}
+
+/// Escapes the `LIKE` wildcards in `text`, with backslash as the escape
+fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn compare_values(actual: &FilterValue, op: CompareOp, expected: &FilterValue) -> bool {
+    use std::cmp::Ordering;
+
+    let ordering = match (actual, expected) {
+        (FilterValue::Text(a), FilterValue::Text(b)) => {
+            let (a, b) = (a.to_lowercase(), b.to_lowercase());
+            match op {
+                CompareOp::Co => return a.contains(&b),
+                CompareOp::Sw => return a.starts_with(&b),
+                CompareOp::Ew => return a.ends_with(&b),
+                _ => a.cmp(&b),
+            }
+        }
+        (FilterValue::Uuid(a), FilterValue::Uuid(b)) => a.cmp(b),
+        (FilterValue::Bool(a), FilterValue::Bool(b)) => a.cmp(b),
+        (FilterValue::Time(a), FilterValue::Time(b)) => a.cmp(b),
+        _ => return false,
+    };
+    match op {
+        CompareOp::Eq => ordering == Ordering::Equal,
+        CompareOp::Ne => ordering != Ordering::Equal,
+        CompareOp::Gt => ordering == Ordering::Greater,
+        CompareOp::Ge => ordering != Ordering::Less,
+        CompareOp::Lt => ordering == Ordering::Less,
+        CompareOp::Le => ordering != Ordering::Greater,
+        CompareOp::Co | CompareOp::Sw | CompareOp::Ew => false,
+    }
+}
+
+/// Users written and read in bulk, by imports, exports and SCIM clients
+#[async_trait]
+pub trait ProvisioningRepository: Send + Sync {
+    /// Writes every account or, if any fails, none; returns the ids of the
+    /// users and invitations created, in order
+    async fn import(&self, accounts: &[NewAccount<'_>]) -> Result<Vec<Uuid>, ImportError>;
+    /// Users whose username or email is among those given, ignoring case.
+    /// Soft-deleted users are included as they keep their username and email.
+    async fn find_by_usernames_or_emails(
+        &self,
+        usernames: &[String],
+        emails: &[String],
+    ) -> RepositoryResult<Vec<User>>;
+    /// The users matching `filter`, oldest first, skipping the first
+    /// `offset` and returning at most `limit`; with how many match in all
+    async fn search(
+        &self,
+        filter: &UserFilter,
+        offset: i64,
+        limit: i64,
+    ) -> RepositoryResult<(Vec<User>, i64)>;
+    /// The roles each of `user_ids` holds directly, ordered by name; users
+    /// without any are left out
+    async fn roles_of(&self, user_ids: &[Uuid]) -> RepositoryResult<HashMap<Uuid, Vec<Role>>>;
+    /// Up to `limit` users with their direct roles, ordered by id and
+    /// starting after `after`, for paging through every user
+    async fn export_page(
+        &self,
+        after: Option<Uuid>,
+        limit: i64,
+    ) -> RepositoryResult<Vec<UserWithRoles>>;
+}
+
+/// Users' defaults for the weather and time services
+#[async_trait]
+pub trait PreferenceRepository: Send + Sync {
+    /// None if the user has saved none
+    async fn get(&self, user_id: Uuid) -> RepositoryResult<Option<Preferences>>;
+    /// Replaces the user's preferences wholesale
+    async fn put(&self, user_id: Uuid, preferences: &Preferences) -> RepositoryResult<()>;
+    /// Returns false if the user had saved none
+    async fn delete(&self, user_id: Uuid) -> RepositoryResult<bool>;
+}
+
+/// The repositories handlers depend on, registered once as `web::Data<Repositories>`
+#[derive(Clone)]
+pub struct Repositories {
+    pub users: Arc<dyn UserRepository>,
+    pub roles: Arc<dyn RoleRepository>,
+    pub permissions: Arc<dyn PermissionRepository>,
+    pub assignments: Arc<dyn AssignmentRepository>,
+    pub sessions: Arc<dyn SessionRepository>,
+    pub setup_tokens: Arc<dyn SetupTokenRepository>,
+    pub webhooks: Arc<dyn WebhookRepository>,
+    pub service_accounts: Arc<dyn ServiceAccountRepository>,
+    pub device_authorizations: Arc<dyn DeviceAuthorizationRepository>,
+    pub identities: Arc<dyn IdentityRepository>,
+    pub privacy: Arc<dyn PrivacyRepository>,
+    pub preferences: Arc<dyn PreferenceRepository>,
+    pub groups: Arc<dyn GroupRepository>,
+    pub invitations: Arc<dyn InvitationRepository>,
+    pub impersonations: Arc<dyn ImpersonationRepository>,
+    pub provisioning: Arc<dyn ProvisioningRepository>,
+}
+
+impl Repositories {
+    /// Builds every repository from one backend implementing all the traits
+    pub fn from_backend<B>(backend: B) -> Self
+    where
+        B: UserRepository
+            + RoleRepository
+            + PermissionRepository
+            + AssignmentRepository
+            + SessionRepository
+            + SetupTokenRepository
+            + WebhookRepository
+            + ServiceAccountRepository
+            + DeviceAuthorizationRepository
+            + IdentityRepository
+            + PrivacyRepository
+            + PreferenceRepository
+            + GroupRepository
+            + InvitationRepository
+            + ImpersonationRepository
+            + ProvisioningRepository
+            + 'static,
+    {
+        let backend = Arc::new(backend);
+        Self {
+            users: backend.clone(),
+            roles: backend.clone(),
+            permissions: backend.clone(),
+            assignments: backend.clone(),
+            sessions: backend.clone(),
+            setup_tokens: backend.clone(),
+            webhooks: backend.clone(),
+            service_accounts: backend.clone(),
+            device_authorizations: backend.clone(),
+            identities: backend.clone(),
+            privacy: backend.clone(),
+            preferences: backend.clone(),
+            groups: backend.clone(),
+            invitations: backend.clone(),
+            impersonations: backend.clone(),
+            provisioning: backend,
+        }
+    }
+
+    pub fn postgres(pool: PgPool) -> Self {
+        Self::from_backend(PgRepository::new(pool))
+    }
+
+    /// Expects a database migrated with `migrations_sqlite`
+    pub fn sqlite(pool: SqlitePool) -> Self {
+        Self::from_backend(SqliteRepository::new(pool))
+    }
+
+    /// An empty in-memory store seeded with the default roles and permissions
+    pub fn in_memory() -> Self {
+        Self::from_backend(InMemoryRepository::with_defaults())
+    }
+}
@@ -0,0 +1,1026 @@
+use super::{
+    check_version, AssignmentRepository, DeviceAuthorizationRepository, FilterValue,
+    GroupRepository, IdentityRepository, ImpersonationRepository, ImportError,
+    InvitationRepository, NewAccount, PermissionRepository, PreferenceRepository,
+    PrivacyRepository, ProvisioningRepository, RepositoryResult, RoleRepository,
+    ServiceAccountRepository, SessionRepository, SetupTokenRepository, UserFilter, UserRepository,
+    WebhookRepository,
+};
+use crate::models::user::{UpdateUser, UserWithRoles};
+use crate::models::{
+    DeliveryAttempt, DeviceAuthorization, DueDelivery, Erasure, EventType, Group,
+    ImpersonationEvent, Invitation, NewCredential, NewEvent, Permission, Role, ServiceAccount,
+    ServiceAccountCredential, Session, SetupToken, User, UserIdentity, UserPreferences, Webhook,
+    WebhookDelivery, WebhookEvent,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use shared::{Preferences, RolePermissions};
+use sqlx::postgres::PgArguments;
+use sqlx::{Arguments, PgConnection, PgPool};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Postgres backend; delegates to the query functions on the models, adding
+/// webhook events in the same transaction as the writes they describe
+#[derive(Clone)]
+pub struct PgRepository {
+    pool: PgPool,
+}
+
+impl PgRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl UserRepository for PgRepository {
+    async fn create(
+        &self,
+        username: &str,
+        email: &str,
+        password_hash: &str,
+    ) -> RepositoryResult<User> {
+        self.create_with_roles(username, email, password_hash, &[])
+            .await
+    }
+
+    async fn create_with_roles(
+        &self,
+        username: &str,
+        email: &str,
+        password_hash: &str,
+        role_ids: &[Uuid],
+    ) -> RepositoryResult<User> {
+        let mut tx = self.pool.begin().await?;
+        let user = User::create(&mut *tx, username, email, password_hash).await?;
+        let granted = User::assign_roles(&mut *tx, user.id, role_ids).await?;
+        WebhookEvent::record(&mut *tx, &[NewEvent::user(EventType::UserCreated, &user)]).await?;
+        WebhookEvent::record_role_grants(&mut tx, &user, &granted).await?;
+        tx.commit().await?;
+
+        Ok(user)
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<User>> {
+        Ok(User::find_by_id(&self.pool, id).await?)
+    }
+
+    async fn find_by_username(&self, username: &str) -> RepositoryResult<Option<User>> {
+        Ok(User::find_by_username(&self.pool, username).await?)
+    }
+
+    async fn find_by_email(&self, email: &str) -> RepositoryResult<Option<User>> {
+        Ok(User::find_by_email(&self.pool, email).await?)
+    }
+
+    async fn find_by_ids(&self, ids: &[Uuid]) -> RepositoryResult<Vec<User>> {
+        Ok(User::find_by_ids(&self.pool, ids).await?)
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<User>> {
+        Ok(User::list(&self.pool).await?)
+    }
+
+    async fn update(
+        &self,
+        id: Uuid,
+        update: &UpdateUser,
+        expected_version: Option<i32>,
+    ) -> RepositoryResult<Option<User>> {
+        let mut tx = self.pool.begin().await?;
+        let Some(before) = User::find_by_id(&mut *tx, id).await? else {
+            return Ok(None);
+        };
+        let updated = User::update(&mut *tx, id, update, expected_version).await?;
+        let Some(user) = check_version(updated, expected_version, true)? else {
+            return Ok(None);
+        };
+        if let Some(event) = NewEvent::activation(&before, &user) {
+            WebhookEvent::record(&mut *tx, &[event]).await?;
+        }
+        tx.commit().await?;
+
+        Ok(Some(user))
+    }
+
+    async fn delete(&self, id: Uuid, expected_version: Option<i32>) -> RepositoryResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        let Some(user) = User::find_by_id(&mut *tx, id).await? else {
+            return Ok(false);
+        };
+        let deleted = User::soft_delete(&mut *tx, id, expected_version).await?;
+        if check_version(deleted.then_some(()), expected_version, true)?.is_none() {
+            return Ok(false);
+        }
+        WebhookEvent::record(&mut *tx, &[NewEvent::user(EventType::UserDeleted, &user)]).await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    async fn restore(&self, id: Uuid) -> RepositoryResult<Option<User>> {
+        let mut tx = self.pool.begin().await?;
+        let user = User::restore(&mut *tx, id).await?;
+        if let Some(user) = &user {
+            WebhookEvent::record(&mut *tx, &[NewEvent::user(EventType::UserRestored, user)])
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(user)
+    }
+
+    async fn list_deleted(&self) -> RepositoryResult<Vec<User>> {
+        Ok(User::list_deleted(&self.pool).await?)
+    }
+
+    async fn purge_deleted(&self, before: DateTime<Utc>) -> RepositoryResult<u64> {
+        Ok(User::purge_deleted(&self.pool, before).await?)
+    }
+}
+
+#[async_trait]
+impl RoleRepository for PgRepository {
+    async fn create(&self, name: &str, description: Option<&str>) -> RepositoryResult<Role> {
+        Ok(Role::create(&self.pool, name, description).await?)
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<Role>> {
+        Ok(Role::find_by_id(&self.pool, id).await?)
+    }
+
+    async fn find_by_name(&self, name: &str) -> RepositoryResult<Option<Role>> {
+        Ok(Role::find_by_name(&self.pool, name).await?)
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<Role>> {
+        Ok(Role::list(&self.pool).await?)
+    }
+
+    async fn update(
+        &self,
+        id: Uuid,
+        name: &str,
+        description: Option<&str>,
+        expected_version: Option<i32>,
+    ) -> RepositoryResult<Option<Role>> {
+        let role = Role::update(&self.pool, id, name, description, expected_version).await?;
+        let exists = role.is_none() && Role::find_by_id(&self.pool, id).await?.is_some();
+        check_version(role, expected_version, exists)
+    }
+
+    async fn delete(&self, id: Uuid, expected_version: Option<i32>) -> RepositoryResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        let Some(role) = Role::find_by_id(&mut *tx, id).await? else {
+            return Ok(false);
+        };
+        // Deleting the role takes it from its members, and from everyone
+        // holding it through a group
+        let members = Role::get_members(&mut *tx, id).await?;
+        let groups = Group::with_role(&mut *tx, id).await?;
+        let inheritors = Group::subtree_members(&mut *tx, &groups).await?;
+        let before = WebhookEvent::snapshot_roles(&mut tx, inheritors).await?;
+        let deleted = Role::delete(&mut *tx, id, expected_version).await?;
+        if check_version(deleted.then_some(()), expected_version, true)?.is_none() {
+            return Ok(false);
+        }
+        let events: Vec<NewEvent> = members
+            .iter()
+            .map(|user| NewEvent::role(EventType::UserRoleRemoved, user, &role))
+            .collect();
+        WebhookEvent::record(&mut *tx, &events).await?;
+        WebhookEvent::record_roles_changes(&mut tx, before).await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl PermissionRepository for PgRepository {
+    async fn create(
+        &self,
+        name: &str,
+        resource: &str,
+        action: &str,
+    ) -> RepositoryResult<Permission> {
+        Ok(Permission::create(&self.pool, name, resource, action).await?)
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<Permission>> {
+        Ok(Permission::find_by_id(&self.pool, id).await?)
+    }
+
+    async fn find_by_name(&self, name: &str) -> RepositoryResult<Option<Permission>> {
+        Ok(Permission::find_by_name(&self.pool, name).await?)
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<Permission>> {
+        Ok(Permission::list(&self.pool).await?)
+    }
+
+    async fn update(
+        &self,
+        id: Uuid,
+        name: &str,
+        resource: &str,
+        action: &str,
+        expected_version: Option<i32>,
+    ) -> RepositoryResult<Option<Permission>> {
+        let permission =
+            Permission::update(&self.pool, id, name, resource, action, expected_version).await?;
+        let exists =
+            permission.is_none() && Permission::find_by_id(&self.pool, id).await?.is_some();
+        check_version(permission, expected_version, exists)
+    }
+
+    async fn delete(&self, id: Uuid, expected_version: Option<i32>) -> RepositoryResult<bool> {
+        let deleted = Permission::delete(&self.pool, id, expected_version).await?;
+        let exists = !deleted && Permission::find_by_id(&self.pool, id).await?.is_some();
+        Ok(check_version(deleted.then_some(()), expected_version, exists)?.is_some())
+    }
+}
+
+#[async_trait]
+impl AssignmentRepository for PgRepository {
+    async fn assign_role_to_user(&self, user_id: Uuid, role_id: Uuid) -> RepositoryResult<()> {
+        let mut tx = self.pool.begin().await?;
+        let granted = User::assign_roles(&mut *tx, user_id, &[role_id]).await?;
+        if !granted.is_empty() {
+            WebhookEvent::record_role_change(
+                &mut tx,
+                EventType::UserRoleAssigned,
+                user_id,
+                role_id,
+            )
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn remove_role_from_user(&self, user_id: Uuid, role_id: Uuid) -> RepositoryResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        let removed = User::remove_role(&mut *tx, user_id, role_id).await?;
+        if removed {
+            WebhookEvent::record_role_change(&mut tx, EventType::UserRoleRemoved, user_id, role_id)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(removed)
+    }
+
+    async fn get_user_roles(&self, user_id: Uuid) -> RepositoryResult<Vec<Role>> {
+        Ok(Role::get_user_roles(&self.pool, user_id).await?)
+    }
+
+    async fn get_effective_user_roles(&self, user_id: Uuid) -> RepositoryResult<Vec<Role>> {
+        Ok(Role::get_effective_user_roles(&self.pool, user_id).await?)
+    }
+
+    async fn assign_permission_to_role(
+        &self,
+        role_id: Uuid,
+        permission_id: Uuid,
+    ) -> RepositoryResult<()> {
+        Ok(Role::assign_permission(&self.pool, role_id, permission_id).await?)
+    }
+
+    async fn remove_permission_from_role(
+        &self,
+        role_id: Uuid,
+        permission_id: Uuid,
+    ) -> RepositoryResult<bool> {
+        Ok(Role::remove_permission(&self.pool, role_id, permission_id).await?)
+    }
+
+    async fn get_role_permissions(&self, role_id: Uuid) -> RepositoryResult<Vec<Permission>> {
+        Ok(Role::get_permissions(&self.pool, role_id).await?)
+    }
+
+    async fn permissions_version(&self) -> RepositoryResult<i64> {
+        Ok(Role::permissions_version(&self.pool).await?)
+    }
+
+    async fn role_permissions(&self) -> RepositoryResult<RolePermissions> {
+        Ok(Role::permission_map(&self.pool).await?)
+    }
+
+    async fn role_has_users(&self, role_id: Uuid) -> RepositoryResult<bool> {
+        Ok(Role::has_members(&self.pool, role_id).await?)
+    }
+
+    async fn get_role_members(&self, role_id: Uuid) -> RepositoryResult<Vec<User>> {
+        Ok(Role::get_members(&self.pool, role_id).await?)
+    }
+
+    async fn set_role_members(
+        &self,
+        role_id: Uuid,
+        user_ids: &[Uuid],
+    ) -> RepositoryResult<(Vec<Uuid>, Vec<Uuid>)> {
+        let mut tx = self.pool.begin().await?;
+        let (added, removed) = Role::set_members(&mut *tx, role_id, user_ids).await?;
+        if let Some(role) = Role::find_by_id(&mut *tx, role_id).await? {
+            WebhookEvent::record_membership_changes(
+                &mut tx,
+                EventType::UserRoleAssigned,
+                &role,
+                &added,
+            )
+            .await?;
+            WebhookEvent::record_membership_changes(
+                &mut tx,
+                EventType::UserRoleRemoved,
+                &role,
+                &removed,
+            )
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok((added, removed))
+    }
+}
+
+#[async_trait]
+impl SessionRepository for PgRepository {
+    async fn create(
+        &self,
+        user_id: Uuid,
+        user_agent: Option<&str>,
+        ip_address: Option<&str>,
+        expires_at: DateTime<Utc>,
+    ) -> RepositoryResult<Session> {
+        Ok(Session::create(&self.pool, user_id, user_agent, ip_address, expires_at).await?)
+    }
+
+    async fn list_active_for_user(&self, user_id: Uuid) -> RepositoryResult<Vec<Session>> {
+        Ok(Session::list_active_for_user(&self.pool, user_id).await?)
+    }
+
+    async fn list_for_user(&self, user_id: Uuid) -> RepositoryResult<Vec<Session>> {
+        Ok(Session::list_for_user(&self.pool, user_id).await?)
+    }
+
+    async fn touch(&self, id: Uuid) -> RepositoryResult<bool> {
+        Ok(Session::touch(&self.pool, id).await?)
+    }
+
+    async fn revoke(&self, user_id: Uuid, id: Uuid) -> RepositoryResult<bool> {
+        Ok(Session::revoke(&self.pool, user_id, id).await?)
+    }
+
+    async fn revoke_all_for_user(
+        &self,
+        user_id: Uuid,
+        except: Option<Uuid>,
+    ) -> RepositoryResult<u64> {
+        Ok(Session::revoke_all_for_user(&self.pool, user_id, except).await?)
+    }
+}
+
+#[async_trait]
+impl SetupTokenRepository for PgRepository {
+    async fn replace(&self, token_hash: &str, expires_at: DateTime<Utc>) -> RepositoryResult<()> {
+        Ok(SetupToken::replace(&self.pool, token_hash, expires_at).await?)
+    }
+
+    async fn consume(&self, token_hash: &str) -> RepositoryResult<bool> {
+        Ok(SetupToken::consume(&self.pool, token_hash).await?)
+    }
+
+    async fn clear(&self) -> RepositoryResult<()> {
+        Ok(SetupToken::clear(&self.pool).await?)
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for PgRepository {
+    async fn create(
+        &self,
+        url: &str,
+        secret: &str,
+        event_types: &[String],
+    ) -> RepositoryResult<Webhook> {
+        Ok(Webhook::create(&self.pool, url, secret, event_types).await?)
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<Webhook>> {
+        Ok(Webhook::find_by_id(&self.pool, id).await?)
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<Webhook>> {
+        Ok(Webhook::list(&self.pool).await?)
+    }
+
+    async fn delete(&self, id: Uuid) -> RepositoryResult<bool> {
+        Ok(Webhook::delete(&self.pool, id).await?)
+    }
+
+    async fn list_deliveries(
+        &self,
+        webhook_id: Uuid,
+        limit: i64,
+    ) -> RepositoryResult<Vec<WebhookDelivery>> {
+        Ok(WebhookDelivery::list_for_webhook(&self.pool, webhook_id, limit).await?)
+    }
+
+    async fn redeliver(
+        &self,
+        webhook_id: Uuid,
+        delivery_id: Uuid,
+    ) -> RepositoryResult<Option<WebhookDelivery>> {
+        Ok(WebhookDelivery::redeliver(&self.pool, webhook_id, delivery_id).await?)
+    }
+
+    async fn dispatch_events(&self, limit: i64) -> RepositoryResult<u64> {
+        Ok(WebhookEvent::dispatch(&self.pool, limit).await?)
+    }
+
+    async fn claim_due_deliveries(
+        &self,
+        limit: i64,
+        lease_seconds: i32,
+    ) -> RepositoryResult<Vec<DueDelivery>> {
+        Ok(WebhookDelivery::claim_due(&self.pool, limit, lease_seconds).await?)
+    }
+
+    async fn record_attempt(
+        &self,
+        delivery_id: Uuid,
+        attempt: &DeliveryAttempt,
+    ) -> RepositoryResult<()> {
+        Ok(WebhookDelivery::record_attempt(&self.pool, delivery_id, attempt).await?)
+    }
+
+    async fn purge_events(&self, before: DateTime<Utc>) -> RepositoryResult<u64> {
+        Ok(WebhookEvent::purge(&self.pool, before).await?)
+    }
+
+    async fn list_events(&self) -> RepositoryResult<Vec<WebhookEvent>> {
+        Ok(WebhookEvent::list_all(&self.pool).await?)
+    }
+}
+
+#[async_trait]
+impl ServiceAccountRepository for PgRepository {
+    async fn create(
+        &self,
+        name: &str,
+        description: Option<&str>,
+        owner_id: Option<Uuid>,
+    ) -> RepositoryResult<ServiceAccount> {
+        Ok(ServiceAccount::create(&self.pool, name, description, owner_id).await?)
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<ServiceAccount>> {
+        Ok(ServiceAccount::find_by_id(&self.pool, id).await?)
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<ServiceAccount>> {
+        Ok(ServiceAccount::list(&self.pool).await?)
+    }
+
+    async fn update(
+        &self,
+        id: Uuid,
+        description: Option<&str>,
+        owner_id: Option<Uuid>,
+        is_active: bool,
+    ) -> RepositoryResult<Option<ServiceAccount>> {
+        Ok(ServiceAccount::update(&self.pool, id, description, owner_id, is_active).await?)
+    }
+
+    async fn delete(&self, id: Uuid) -> RepositoryResult<bool> {
+        Ok(ServiceAccount::delete(&self.pool, id).await?)
+    }
+
+    async fn assign_role(&self, id: Uuid, role_id: Uuid) -> RepositoryResult<()> {
+        Ok(ServiceAccount::assign_role(&self.pool, id, role_id).await?)
+    }
+
+    async fn remove_role(&self, id: Uuid, role_id: Uuid) -> RepositoryResult<bool> {
+        Ok(ServiceAccount::remove_role(&self.pool, id, role_id).await?)
+    }
+
+    async fn get_roles(&self, id: Uuid) -> RepositoryResult<Vec<Role>> {
+        Ok(ServiceAccount::get_roles(&self.pool, id).await?)
+    }
+
+    async fn add_credential(
+        &self,
+        id: Uuid,
+        credential: NewCredential<'_>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> RepositoryResult<ServiceAccountCredential> {
+        Ok(ServiceAccount::add_credential(&self.pool, id, credential, expires_at).await?)
+    }
+
+    async fn list_credentials(&self, id: Uuid) -> RepositoryResult<Vec<ServiceAccountCredential>> {
+        Ok(ServiceAccount::list_credentials(&self.pool, id).await?)
+    }
+
+    async fn delete_credential(&self, id: Uuid, credential_id: Uuid) -> RepositoryResult<bool> {
+        Ok(ServiceAccount::delete_credential(&self.pool, id, credential_id).await?)
+    }
+
+    async fn touch_credential(&self, credential_id: Uuid) -> RepositoryResult<()> {
+        Ok(ServiceAccount::touch_credential(&self.pool, credential_id).await?)
+    }
+
+    async fn use_assertion(
+        &self,
+        id: Uuid,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> RepositoryResult<bool> {
+        Ok(ServiceAccount::use_assertion(&self.pool, id, jti, expires_at).await?)
+    }
+
+    async fn purge_used_assertions(&self, before: DateTime<Utc>) -> RepositoryResult<u64> {
+        Ok(ServiceAccount::purge_used_assertions(&self.pool, before).await?)
+    }
+}
+
+#[async_trait]
+impl DeviceAuthorizationRepository for PgRepository {
+    async fn create(
+        &self,
+        device_code_hash: &str,
+        user_code: &str,
+        client_id: &str,
+        interval_seconds: i32,
+        expires_at: DateTime<Utc>,
+    ) -> RepositoryResult<DeviceAuthorization> {
+        Ok(DeviceAuthorization::create(
+            &self.pool,
+            device_code_hash,
+            user_code,
+            client_id,
+            interval_seconds,
+            expires_at,
+        )
+        .await?)
+    }
+
+    async fn find_by_user_code(
+        &self,
+        user_code: &str,
+    ) -> RepositoryResult<Option<DeviceAuthorization>> {
+        Ok(DeviceAuthorization::find_by_user_code(&self.pool, user_code).await?)
+    }
+
+    async fn find_by_device_code(
+        &self,
+        device_code_hash: &str,
+    ) -> RepositoryResult<Option<DeviceAuthorization>> {
+        Ok(DeviceAuthorization::find_by_device_code(&self.pool, device_code_hash).await?)
+    }
+
+    async fn decide(
+        &self,
+        user_code: &str,
+        user_id: Uuid,
+        approve: bool,
+    ) -> RepositoryResult<Option<DeviceAuthorization>> {
+        Ok(DeviceAuthorization::decide(&self.pool, user_code, user_id, approve).await?)
+    }
+
+    async fn record_poll(&self, id: Uuid, interval_seconds: i32) -> RepositoryResult<()> {
+        Ok(DeviceAuthorization::record_poll(&self.pool, id, interval_seconds).await?)
+    }
+
+    async fn delete(&self, id: Uuid) -> RepositoryResult<bool> {
+        Ok(DeviceAuthorization::delete(&self.pool, id).await?)
+    }
+
+    async fn purge_expired(&self, before: DateTime<Utc>) -> RepositoryResult<u64> {
+        Ok(DeviceAuthorization::purge_expired(&self.pool, before).await?)
+    }
+}
+
+#[async_trait]
+impl IdentityRepository for PgRepository {
+    async fn create(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        subject: &str,
+        email: Option<&str>,
+    ) -> RepositoryResult<UserIdentity> {
+        Ok(UserIdentity::create(&self.pool, user_id, provider, subject, email).await?)
+    }
+
+    async fn find(&self, provider: &str, subject: &str) -> RepositoryResult<Option<UserIdentity>> {
+        Ok(UserIdentity::find(&self.pool, provider, subject).await?)
+    }
+
+    async fn list_for_user(&self, user_id: Uuid) -> RepositoryResult<Vec<UserIdentity>> {
+        Ok(UserIdentity::list_for_user(&self.pool, user_id).await?)
+    }
+
+    async fn touch(&self, id: Uuid) -> RepositoryResult<()> {
+        Ok(UserIdentity::touch(&self.pool, id).await?)
+    }
+
+    async fn delete(&self, user_id: Uuid, id: Uuid) -> RepositoryResult<bool> {
+        Ok(UserIdentity::delete(&self.pool, user_id, id).await?)
+    }
+}
+
+#[async_trait]
+impl PrivacyRepository for PgRepository {
+    async fn find_user(&self, user_id: Uuid) -> RepositoryResult<Option<User>> {
+        Ok(User::find_including_deleted(&self.pool, user_id).await?)
+    }
+
+    async fn user_groups(&self, user_id: Uuid) -> RepositoryResult<Vec<Group>> {
+        Ok(Group::list_for_user(&self.pool, user_id).await?)
+    }
+
+    async fn user_invitations(
+        &self,
+        user_id: Uuid,
+        email: &str,
+    ) -> RepositoryResult<Vec<Invitation>> {
+        Ok(Invitation::list_for_user(&self.pool, user_id, email).await?)
+    }
+
+    async fn user_events(&self, user_id: Uuid) -> RepositoryResult<Vec<WebhookEvent>> {
+        Ok(WebhookEvent::list_for_user(&self.pool, user_id).await?)
+    }
+
+    async fn user_impersonations(
+        &self,
+        user_id: Uuid,
+    ) -> RepositoryResult<Vec<ImpersonationEvent>> {
+        Ok(ImpersonationEvent::list_for_user(&self.pool, user_id).await?)
+    }
+
+    async fn erase_user(
+        &self,
+        user_id: Uuid,
+        requested_by: Uuid,
+        reason: Option<&str>,
+    ) -> RepositoryResult<Option<Erasure>> {
+        Ok(Erasure::erase_user(&self.pool, user_id, requested_by, reason).await?)
+    }
+
+    async fn list_erasures(&self) -> RepositoryResult<Vec<Erasure>> {
+        Ok(Erasure::list(&self.pool).await?)
+    }
+}
+
+#[async_trait]
+impl PreferenceRepository for PgRepository {
+    async fn get(&self, user_id: Uuid) -> RepositoryResult<Option<Preferences>> {
+        Ok(UserPreferences::find(&self.pool, user_id)
+            .await?
+            .map(Preferences::from))
+    }
+
+    async fn put(&self, user_id: Uuid, preferences: &Preferences) -> RepositoryResult<()> {
+        UserPreferences::upsert(&self.pool, user_id, preferences).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, user_id: Uuid) -> RepositoryResult<bool> {
+        Ok(UserPreferences::delete(&self.pool, user_id).await?)
+    }
+}
+
+#[async_trait]
+impl GroupRepository for PgRepository {
+    async fn create(&self, name: &str, description: Option<&str>) -> RepositoryResult<Group> {
+        Ok(Group::create(&self.pool, name, description).await?)
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<Group>> {
+        Ok(Group::find_by_id(&self.pool, id).await?)
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<Group>> {
+        Ok(Group::list(&self.pool).await?)
+    }
+
+    async fn delete(&self, id: Uuid) -> RepositoryResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        let members = Group::subtree_members(&mut *tx, &[id]).await?;
+        let before = WebhookEvent::snapshot_roles(&mut tx, members).await?;
+        let deleted = Group::delete(&mut *tx, id).await?;
+        WebhookEvent::record_roles_changes(&mut tx, before).await?;
+        tx.commit().await?;
+        Ok(deleted)
+    }
+
+    async fn get_members(&self, id: Uuid) -> RepositoryResult<Vec<User>> {
+        Ok(Group::get_members(&self.pool, id).await?)
+    }
+
+    async fn add_members(&self, id: Uuid, user_ids: &[Uuid]) -> RepositoryResult<u64> {
+        let mut tx = self.pool.begin().await?;
+        let users = User::find_by_ids(&mut *tx, user_ids).await?;
+        let before = WebhookEvent::snapshot_roles(&mut tx, users).await?;
+        let added = Group::add_members(&mut *tx, id, user_ids).await?;
+        WebhookEvent::record_roles_changes(&mut tx, before).await?;
+        tx.commit().await?;
+        Ok(added)
+    }
+
+    async fn remove_members(&self, id: Uuid, user_ids: &[Uuid]) -> RepositoryResult<u64> {
+        let mut tx = self.pool.begin().await?;
+        let users = User::find_by_ids(&mut *tx, user_ids).await?;
+        let before = WebhookEvent::snapshot_roles(&mut tx, users).await?;
+        let removed = Group::remove_members(&mut *tx, id, user_ids).await?;
+        WebhookEvent::record_roles_changes(&mut tx, before).await?;
+        tx.commit().await?;
+        Ok(removed)
+    }
+
+    async fn get_subgroups(&self, id: Uuid) -> RepositoryResult<Vec<Group>> {
+        Ok(Group::get_subgroups(&self.pool, id).await?)
+    }
+
+    async fn add_subgroup(&self, id: Uuid, child_id: Uuid) -> RepositoryResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        Group::lock_nesting(&mut *tx).await?;
+        if Group::contains_group(&mut *tx, child_id, id).await? {
+            return Ok(false);
+        }
+        let members = Group::subtree_members(&mut *tx, &[child_id]).await?;
+        let before = WebhookEvent::snapshot_roles(&mut tx, members).await?;
+        Group::add_subgroup(&mut *tx, id, child_id).await?;
+        WebhookEvent::record_roles_changes(&mut tx, before).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn remove_subgroup(&self, id: Uuid, child_id: Uuid) -> RepositoryResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        Group::lock_nesting(&mut *tx).await?;
+        let members = Group::subtree_members(&mut *tx, &[child_id]).await?;
+        let before = WebhookEvent::snapshot_roles(&mut tx, members).await?;
+        let removed = Group::remove_subgroup(&mut *tx, id, child_id).await?;
+        WebhookEvent::record_roles_changes(&mut tx, before).await?;
+        tx.commit().await?;
+        Ok(removed)
+    }
+
+    async fn get_roles(&self, id: Uuid) -> RepositoryResult<Vec<Role>> {
+        Ok(Group::get_roles(&self.pool, id).await?)
+    }
+
+    async fn assign_role(&self, id: Uuid, role_id: Uuid) -> RepositoryResult<()> {
+        let mut tx = self.pool.begin().await?;
+        let members = Group::subtree_members(&mut *tx, &[id]).await?;
+        let before = WebhookEvent::snapshot_roles(&mut tx, members).await?;
+        Group::assign_role(&mut *tx, id, role_id).await?;
+        WebhookEvent::record_roles_changes(&mut tx, before).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn remove_role(&self, id: Uuid, role_id: Uuid) -> RepositoryResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        let members = Group::subtree_members(&mut *tx, &[id]).await?;
+        let before = WebhookEvent::snapshot_roles(&mut tx, members).await?;
+        let removed = Group::remove_role(&mut *tx, id, role_id).await?;
+        WebhookEvent::record_roles_changes(&mut tx, before).await?;
+        tx.commit().await?;
+        Ok(removed)
+    }
+}
+
+#[async_trait]
+impl InvitationRepository for PgRepository {
+    async fn create(
+        &self,
+        email: &str,
+        token_hash: &str,
+        invited_by: Option<Uuid>,
+        expires_at: DateTime<Utc>,
+        role_ids: &[Uuid],
+    ) -> RepositoryResult<Invitation> {
+        Ok(Invitation::create(
+            &self.pool, email, token_hash, invited_by, expires_at, role_ids,
+        )
+        .await?)
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<Invitation>> {
+        Ok(Invitation::find_by_id(&self.pool, id).await?)
+    }
+
+    async fn find_by_token_hash(&self, token_hash: &str) -> RepositoryResult<Option<Invitation>> {
+        Ok(Invitation::find_by_token_hash(&self.pool, token_hash).await?)
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<Invitation>> {
+        Ok(Invitation::list(&self.pool).await?)
+    }
+
+    async fn get_roles(&self, id: Uuid) -> RepositoryResult<Vec<Role>> {
+        Ok(Invitation::get_roles(&self.pool, id).await?)
+    }
+
+    async fn revoke(&self, id: Uuid) -> RepositoryResult<bool> {
+        Ok(Invitation::revoke(&self.pool, id).await?)
+    }
+
+    async fn accept(
+        &self,
+        id: Uuid,
+        username: &str,
+        password_hash: &str,
+    ) -> RepositoryResult<Option<User>> {
+        let mut tx = self.pool.begin().await?;
+        let Some(invitation) = Invitation::find_by_id(&mut *tx, id).await? else {
+            return Ok(None);
+        };
+        let user = User::create(&mut *tx, username, &invitation.email, password_hash).await?;
+        // If someone else claimed the invitation first, the user is rolled
+        // back with the transaction
+        if !Invitation::mark_accepted(&mut *tx, id, user.id).await? {
+            return Ok(None);
+        }
+        let role_ids: Vec<Uuid> = Invitation::get_roles(&mut *tx, id)
+            .await?
+            .into_iter()
+            .map(|r| r.id)
+            .collect();
+        let granted = User::assign_roles(&mut *tx, user.id, &role_ids).await?;
+        WebhookEvent::record(&mut *tx, &[NewEvent::user(EventType::UserCreated, &user)]).await?;
+        WebhookEvent::record_role_grants(&mut tx, &user, &granted).await?;
+        tx.commit().await?;
+
+        Ok(Some(user))
+    }
+}
+
+#[async_trait]
+impl ImpersonationRepository for PgRepository {
+    async fn create(
+        &self,
+        actor_id: Uuid,
+        actor_username: &str,
+        target: &User,
+        reason: &str,
+        expires_at: DateTime<Utc>,
+    ) -> RepositoryResult<ImpersonationEvent> {
+        Ok(ImpersonationEvent::create(
+            &self.pool,
+            actor_id,
+            actor_username,
+            target.id,
+            &target.username,
+            reason,
+            expires_at,
+        )
+        .await?)
+    }
+
+    async fn list(
+        &self,
+        target_user_id: Option<Uuid>,
+    ) -> RepositoryResult<Vec<ImpersonationEvent>> {
+        Ok(ImpersonationEvent::list(&self.pool, target_user_id).await?)
+    }
+}
+
+/// Writes one imported account, returning the id of the user or invitation
+async fn import_account(
+    conn: &mut PgConnection,
+    account: &NewAccount<'_>,
+) -> Result<Uuid, sqlx::Error> {
+    match *account {
+        NewAccount::User {
+            username,
+            email,
+            password_hash,
+            is_active,
+            role_ids,
+        } => {
+            let user = User::create_imported(&mut *conn, username, email, password_hash, is_active)
+                .await?;
+            let granted = User::assign_roles(&mut *conn, user.id, role_ids).await?;
+            WebhookEvent::record(&mut *conn, &[NewEvent::user(EventType::UserCreated, &user)])
+                .await?;
+            WebhookEvent::record_role_grants(conn, &user, &granted).await?;
+            Ok(user.id)
+        }
+        NewAccount::Invitation {
+            email,
+            token_hash,
+            invited_by,
+            expires_at,
+            role_ids,
+        } => {
+            let invitation = Invitation::create(
+                &mut *conn, email, token_hash, invited_by, expires_at, role_ids,
+            )
+            .await?;
+            Ok(invitation.id)
+        }
+    }
+}
+
+/// Binds the values a [`UserFilter`] rendered for Postgres refers to
+fn filter_arguments(params: &[FilterValue]) -> PgArguments {
+    let mut arguments = PgArguments::default();
+    for param in params {
+        match param {
+            FilterValue::Text(text) => arguments.add(text.clone()),
+            FilterValue::Uuid(id) => arguments.add(*id),
+            FilterValue::Bool(value) => arguments.add(*value),
+            FilterValue::Time(time) => arguments.add(*time),
+        }
+    }
+    arguments
+}
+
+#[async_trait]
+impl ProvisioningRepository for PgRepository {
+    async fn import(&self, accounts: &[NewAccount<'_>]) -> Result<Vec<Uuid>, ImportError> {
+        let mut tx = self.pool.begin().await?;
+        let mut ids = Vec::with_capacity(accounts.len());
+        for (index, account) in accounts.iter().enumerate() {
+            let id = import_account(&mut tx, account)
+                .await
+                .map_err(|e| ImportError {
+                    index: Some(index),
+                    source: e.into(),
+                })?;
+            ids.push(id);
+        }
+        tx.commit().await?;
+
+        Ok(ids)
+    }
+
+    async fn find_by_usernames_or_emails(
+        &self,
+        usernames: &[String],
+        emails: &[String],
+    ) -> RepositoryResult<Vec<User>> {
+        Ok(User::find_by_usernames_or_emails(&self.pool, usernames, emails).await?)
+    }
+
+    async fn search(
+        &self,
+        filter: &UserFilter,
+        offset: i64,
+        limit: i64,
+    ) -> RepositoryResult<(Vec<User>, i64)> {
+        let mut params = Vec::new();
+        let condition = filter.to_sql("$", &mut params);
+
+        let total: i64 = sqlx::query_scalar_with(
+            &format!("SELECT COUNT(*) FROM users u WHERE u.deleted_at IS NULL AND {condition}"),
+            filter_arguments(&params),
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        let mut arguments = filter_arguments(&params);
+        arguments.add(limit);
+        arguments.add(offset);
+        let users = sqlx::query_as_with::<_, User, _>(
+            &format!(
+                "SELECT u.id, u.username, u.email, u.password_hash, u.created_at, u.updated_at,
+                        u.is_active, u.version, u.deleted_at
+                 FROM users u
+                 WHERE u.deleted_at IS NULL AND {condition}
+                 ORDER BY u.created_at, u.id
+                 LIMIT ${} OFFSET ${}",
+                params.len() + 1,
+                params.len() + 2
+            ),
+            arguments,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok((users, total))
+    }
+
+    async fn roles_of(&self, user_ids: &[Uuid]) -> RepositoryResult<HashMap<Uuid, Vec<Role>>> {
+        let mut roles: HashMap<Uuid, Vec<Role>> = HashMap::new();
+        for (user_id, role) in Role::get_roles_of_users(&self.pool, user_ids).await? {
+            roles.entry(user_id).or_default().push(role);
+        }
+        Ok(roles)
+    }
+
+    async fn export_page(
+        &self,
+        after: Option<Uuid>,
+        limit: i64,
+    ) -> RepositoryResult<Vec<UserWithRoles>> {
+        Ok(User::list_with_roles_page(&self.pool, after, limit).await?)
+    }
+}
@@ -0,0 +1,2581 @@
+use super::{
+    check_version, AssignmentRepository, DeviceAuthorizationRepository, FilterValue,
+    GroupRepository, IdentityRepository, ImpersonationRepository, ImportError,
+    InvitationRepository, NewAccount, PermissionRepository, PreferenceRepository,
+    PrivacyRepository, ProvisioningRepository, RepositoryError, RepositoryResult, RoleRepository,
+    ServiceAccountRepository, SessionRepository, SetupTokenRepository, UserFilter, UserRepository,
+    WebhookRepository,
+};
+use crate::models::user::{UpdateUser, UserWithRoles};
+use crate::models::{
+    fold_username, normalize_email, normalize_username, same_roles, DeliveryAttempt,
+    DeliveryStatus, DeviceAuthorization, DueDelivery, Erasure, EventType, Group,
+    ImpersonationEvent, Invitation, NewCredential, NewEvent, Permission, Role, ServiceAccount,
+    ServiceAccountCredential, Session, User, UserIdentity, UserPreferences, Webhook,
+    WebhookDelivery, WebhookEvent,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use shared::{Preferences, RolePermissions};
+use sqlx::sqlite::SqliteArguments;
+use sqlx::{Arguments, FromRow, SqliteConnection, SqliteExecutor, SqlitePool};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const USER_COLUMNS: &str =
+    "id, username, email, password_hash, created_at, updated_at, is_active, version, deleted_at";
+const ROLE_COLUMNS: &str = "id, name, description, created_at, version";
+const PERMISSION_COLUMNS: &str = "id, name, resource, action, created_at, version";
+const SESSION_COLUMNS: &str =
+    "id, user_id, user_agent, ip_address, created_at, last_used_at, expires_at, revoked_at";
+const SERVICE_ACCOUNT_COLUMNS: &str =
+    "id, name, description, owner_id, is_active, created_at, updated_at";
+const CREDENTIAL_COLUMNS: &str = "id, service_account_id, kind, secret_hash, public_key, \
+     algorithm, created_at, expires_at, last_used_at";
+const DEVICE_AUTHORIZATION_COLUMNS: &str = "id, device_code_hash, user_code, client_id, \
+     interval_seconds, created_at, expires_at, last_polled_at, user_id, approved_at, denied_at";
+const IDENTITY_COLUMNS: &str = "id, user_id, provider, subject, email, created_at, last_login_at";
+const ERASURE_COLUMNS: &str = "id, pseudonym_id, requested_by, reason, created_at";
+const IMPERSONATION_COLUMNS: &str = "id, actor_id, actor_username, target_user_id, \
+     target_username, reason, expires_at, created_at";
+const GROUP_COLUMNS: &str = "id, name, description, created_at";
+const INVITATION_COLUMNS: &str = "id, email, token_hash, invited_by, expires_at, created_at, \
+     accepted_at, accepted_user_id, revoked_at";
+const PREFERENCES_COLUMNS: &str = "user_id, units, locale, time_format, home_timezone, \
+     favourite_cities, updated_at";
+const WEBHOOK_COLUMNS: &str = "id, url, secret, event_types, created_at";
+const DELIVERY_COLUMNS: &str = "d.id, d.webhook_id, d.event_id, e.event_type, d.status, \
+     d.attempts, d.next_attempt_at, d.response_status, d.error, d.created_at, d.updated_at";
+
+/// SQLite backend for the schema in `migrations_sqlite`.
+///
+/// The query macros are checked against Postgres only, so queries here are
+/// built at runtime. Ids and timestamps are generated in Rust rather than by
+/// column defaults so rows match what the Postgres backend returns (v4 UUIDs,
+/// full-precision UTC timestamps); timestamps are stored as RFC 3339 text,
+/// which compares correctly as long as every value is UTC.
+#[derive(Clone)]
+pub struct SqliteRepository {
+    pool: SqlitePool,
+}
+
+impl SqliteRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+async fn insert_user(
+    executor: impl SqliteExecutor<'_>,
+    username: &str,
+    email: &str,
+    password_hash: &str,
+) -> RepositoryResult<User> {
+    insert_user_with_status(executor, username, email, password_hash, true).await
+}
+
+async fn insert_user_with_status(
+    executor: impl SqliteExecutor<'_>,
+    username: &str,
+    email: &str,
+    password_hash: &str,
+    is_active: bool,
+) -> RepositoryResult<User> {
+    let now = Utc::now();
+    Ok(sqlx::query_as::<_, User>(&format!(
+        "INSERT INTO users (id, username, username_folded, email, password_hash, created_at,
+                            updated_at, is_active)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+         RETURNING {USER_COLUMNS}"
+    ))
+    .bind(Uuid::new_v4())
+    .bind(normalize_username(username))
+    .bind(fold_username(username))
+    .bind(normalize_email(email))
+    .bind(password_hash)
+    .bind(now)
+    .bind(now)
+    .bind(is_active)
+    .fetch_all(executor)
+    .await
+    .and_then(returned_row)?)
+}
+
+/// `?, ?, ...` binding `n` values to an `IN` list
+fn placeholders(n: usize) -> String {
+    vec!["?"; n].join(", ")
+}
+
+/// Ids with duplicates dropped, keeping the first of each
+fn distinct(ids: &[Uuid]) -> Vec<Uuid> {
+    let mut seen = std::collections::HashSet::new();
+    ids.iter().copied().filter(|id| seen.insert(*id)).collect()
+}
+
+async fn find_user(executor: impl SqliteExecutor<'_>, id: Uuid) -> RepositoryResult<Option<User>> {
+    Ok(sqlx::query_as::<_, User>(&format!(
+        "SELECT {USER_COLUMNS} FROM users WHERE id = ? AND deleted_at IS NULL"
+    ))
+    .bind(id)
+    .fetch_optional(executor)
+    .await?)
+}
+
+async fn find_users(
+    executor: impl SqliteExecutor<'_>,
+    ids: &[Uuid],
+) -> RepositoryResult<Vec<User>> {
+    let sql = format!(
+        "SELECT {USER_COLUMNS} FROM users WHERE deleted_at IS NULL AND id IN ({})",
+        placeholders(ids.len())
+    );
+    let mut query = sqlx::query_as::<_, User>(&sql);
+    for id in ids {
+        query = query.bind(id);
+    }
+    Ok(query.fetch_all(executor).await?)
+}
+
+async fn find_role(executor: impl SqliteExecutor<'_>, id: Uuid) -> RepositoryResult<Option<Role>> {
+    Ok(
+        sqlx::query_as::<_, Role>(&format!("SELECT {ROLE_COLUMNS} FROM roles WHERE id = ?"))
+            .bind(id)
+            .fetch_optional(executor)
+            .await?,
+    )
+}
+
+/// The row a write's `RETURNING` clause produced. Such writes are run with
+/// `fetch_all`: `fetch_one` and `fetch_optional` return at the first row and
+/// can leave the statement unfinished, its changes not yet committed when
+/// the next query runs on another connection.
+fn returned_row<T>(rows: Vec<T>) -> Result<T, sqlx::Error> {
+    rows.into_iter().next().ok_or(sqlx::Error::RowNotFound)
+}
+
+/// Adds events to the outbox; call inside the transaction making the change
+async fn record_events(conn: &mut SqliteConnection, events: &[NewEvent]) -> RepositoryResult<()> {
+    for event in events {
+        sqlx::query(
+            "INSERT INTO webhook_events (id, event_type, payload, created_at) VALUES (?, ?, ?, ?)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(event.event_type.as_str())
+        .bind(event.payload.to_string())
+        .bind(Utc::now())
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Records `event_type` for `user_id` gaining or losing `role_id`
+async fn record_role_change(
+    conn: &mut SqliteConnection,
+    event_type: EventType,
+    user_id: Uuid,
+    role_id: Uuid,
+) -> RepositoryResult<()> {
+    let user = find_user(&mut *conn, user_id).await?;
+    let role = find_role(&mut *conn, role_id).await?;
+    if let (Some(user), Some(role)) = (user, role) {
+        record_events(conn, &[NewEvent::role(event_type, &user, &role)]).await?;
+    }
+    Ok(())
+}
+
+/// Roles `user_id` holds directly or through a group, nested ones included
+async fn effective_roles(
+    executor: impl SqliteExecutor<'_>,
+    user_id: Uuid,
+) -> RepositoryResult<Vec<Role>> {
+    Ok(sqlx::query_as::<_, Role>(
+        "WITH RECURSIVE user_groups AS (
+             SELECT gm.group_id
+             FROM group_members gm
+             WHERE gm.user_id = ?1
+             UNION
+             SELECT gs.parent_group_id
+             FROM group_subgroups gs
+             INNER JOIN user_groups ug ON gs.child_group_id = ug.group_id
+         )
+         SELECT r.id, r.name, r.description, r.created_at, r.version
+         FROM roles r
+         WHERE r.id IN (
+             SELECT ur.role_id FROM user_roles ur WHERE ur.user_id = ?1
+             UNION
+             SELECT gr.role_id
+             FROM group_roles gr
+             INNER JOIN user_groups ug ON gr.group_id = ug.group_id
+         )
+         ORDER BY r.name",
+    )
+    .bind(user_id)
+    .fetch_all(executor)
+    .await?)
+}
+
+/// Members of `group_ids` and of every group nested in them: the users whose
+/// inherited roles change with those groups
+async fn subtree_members(
+    conn: &mut SqliteConnection,
+    group_ids: &[Uuid],
+) -> RepositoryResult<Vec<User>> {
+    let sql = format!(
+        "WITH RECURSIVE subtree AS (
+             SELECT id AS group_id FROM groups WHERE id IN ({})
+             UNION
+             SELECT gs.child_group_id
+             FROM group_subgroups gs
+             INNER JOIN subtree s ON gs.parent_group_id = s.group_id
+         )
+         SELECT {USER_COLUMNS} FROM users
+         WHERE deleted_at IS NULL
+           AND id IN (
+               SELECT gm.user_id
+               FROM group_members gm
+               INNER JOIN subtree s ON gm.group_id = s.group_id
+           )
+         ORDER BY username",
+        placeholders(group_ids.len())
+    );
+    let mut query = sqlx::query_as::<_, User>(&sql);
+    for group_id in group_ids {
+        query = query.bind(group_id);
+    }
+
+    Ok(query.fetch_all(&mut *conn).await?)
+}
+
+/// Effective roles of each of `users`, taken before a group change so that
+/// `record_roles_changes` can tell whose roles it changed
+async fn snapshot_roles(
+    conn: &mut SqliteConnection,
+    users: Vec<User>,
+) -> RepositoryResult<Vec<(User, Vec<Role>)>> {
+    let mut snapshot = Vec::with_capacity(users.len());
+    for user in users {
+        let roles = effective_roles(&mut *conn, user.id).await?;
+        snapshot.push((user, roles));
+    }
+    Ok(snapshot)
+}
+
+/// Records `user.roles_changed` for each user in `before` whose effective
+/// roles are no longer the ones snapshotted
+async fn record_roles_changes(
+    conn: &mut SqliteConnection,
+    before: Vec<(User, Vec<Role>)>,
+) -> RepositoryResult<()> {
+    let mut events = Vec::new();
+    for (user, roles) in before {
+        let now = effective_roles(&mut *conn, user.id).await?;
+        if !same_roles(&roles, &now) {
+            events.push(NewEvent::roles_changed(&user, &now));
+        }
+    }
+    record_events(conn, &events).await
+}
+
+#[async_trait]
+impl UserRepository for SqliteRepository {
+    async fn create(
+        &self,
+        username: &str,
+        email: &str,
+        password_hash: &str,
+    ) -> RepositoryResult<User> {
+        self.create_with_roles(username, email, password_hash, &[])
+            .await
+    }
+
+    async fn create_with_roles(
+        &self,
+        username: &str,
+        email: &str,
+        password_hash: &str,
+        role_ids: &[Uuid],
+    ) -> RepositoryResult<User> {
+        let mut tx = self.pool.begin().await?;
+        let user = insert_user(&mut *tx, username, email, password_hash).await?;
+        let mut events = vec![NewEvent::user(EventType::UserCreated, &user)];
+        for role_id in role_ids {
+            let granted = sqlx::query(
+                "INSERT INTO user_roles (user_id, role_id) VALUES (?, ?) ON CONFLICT DO NOTHING",
+            )
+            .bind(user.id)
+            .bind(role_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected()
+                > 0;
+            if let Some(role) = find_role(&mut *tx, *role_id).await?.filter(|_| granted) {
+                events.push(NewEvent::role(EventType::UserRoleAssigned, &user, &role));
+            }
+        }
+        record_events(&mut tx, &events).await?;
+        tx.commit().await?;
+
+        Ok(user)
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<User>> {
+        find_user(&self.pool, id).await
+    }
+
+    async fn find_by_username(&self, username: &str) -> RepositoryResult<Option<User>> {
+        Ok(sqlx::query_as::<_, User>(&format!(
+            "SELECT {USER_COLUMNS} FROM users WHERE username_folded = ? AND deleted_at IS NULL"
+        ))
+        .bind(fold_username(username))
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn find_by_email(&self, email: &str) -> RepositoryResult<Option<User>> {
+        Ok(sqlx::query_as::<_, User>(&format!(
+            "SELECT {USER_COLUMNS} FROM users WHERE email = ? AND deleted_at IS NULL"
+        ))
+        .bind(normalize_email(email))
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn find_by_ids(&self, ids: &[Uuid]) -> RepositoryResult<Vec<User>> {
+        find_users(&self.pool, ids).await
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<User>> {
+        Ok(sqlx::query_as::<_, User>(&format!(
+            "SELECT {USER_COLUMNS} FROM users WHERE deleted_at IS NULL ORDER BY created_at DESC"
+        ))
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn update(
+        &self,
+        id: Uuid,
+        update: &UpdateUser,
+        expected_version: Option<i32>,
+    ) -> RepositoryResult<Option<User>> {
+        let mut tx = self.pool.begin().await?;
+        let Some(before) = find_user(&mut *tx, id).await? else {
+            return Ok(None);
+        };
+        let updated = sqlx::query_as::<_, User>(&format!(
+            "UPDATE users
+             SET username = COALESCE(?1, username), username_folded = COALESCE(?2, username_folded),
+                 email = COALESCE(?3, email), password_hash = COALESCE(?4, password_hash),
+                 is_active = COALESCE(?5, is_active), updated_at = ?6, version = version + 1
+             WHERE id = ?7 AND deleted_at IS NULL AND (?8 IS NULL OR version = ?8)
+             RETURNING {USER_COLUMNS}"
+        ))
+        .bind(update.username.as_deref().map(normalize_username))
+        .bind(update.username.as_deref().map(fold_username))
+        .bind(update.email.as_deref().map(normalize_email))
+        .bind(&update.password)
+        .bind(update.is_active)
+        .bind(Utc::now())
+        .bind(id)
+        .bind(expected_version)
+        .fetch_all(&mut *tx)
+        .await
+        .map(|rows| rows.into_iter().next())?;
+
+        let Some(user) = check_version(updated, expected_version, true)? else {
+            return Ok(None);
+        };
+        if let Some(event) = NewEvent::activation(&before, &user) {
+            record_events(&mut tx, &[event]).await?;
+        }
+        tx.commit().await?;
+
+        Ok(Some(user))
+    }
+
+    async fn delete(&self, id: Uuid, expected_version: Option<i32>) -> RepositoryResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        let Some(user) = find_user(&mut *tx, id).await? else {
+            return Ok(false);
+        };
+        let now = Utc::now();
+        let result = sqlx::query(
+            "UPDATE users SET deleted_at = ?1, updated_at = ?1, version = version + 1
+             WHERE id = ?2 AND deleted_at IS NULL AND (?3 IS NULL OR version = ?3)",
+        )
+        .bind(now)
+        .bind(id)
+        .bind(expected_version)
+        .execute(&mut *tx)
+        .await?;
+
+        let deleted = result.rows_affected() > 0;
+        if check_version(deleted.then_some(()), expected_version, true)?.is_none() {
+            return Ok(false);
+        }
+        record_events(&mut tx, &[NewEvent::user(EventType::UserDeleted, &user)]).await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    async fn restore(&self, id: Uuid) -> RepositoryResult<Option<User>> {
+        let mut tx = self.pool.begin().await?;
+        let user = sqlx::query_as::<_, User>(&format!(
+            "UPDATE users SET deleted_at = NULL, updated_at = ?, version = version + 1
+             WHERE id = ? AND deleted_at IS NOT NULL
+             RETURNING {USER_COLUMNS}"
+        ))
+        .bind(Utc::now())
+        .bind(id)
+        .fetch_all(&mut *tx)
+        .await
+        .map(|rows| rows.into_iter().next())?;
+        if let Some(user) = &user {
+            record_events(&mut tx, &[NewEvent::user(EventType::UserRestored, user)]).await?;
+        }
+        tx.commit().await?;
+
+        Ok(user)
+    }
+
+    async fn list_deleted(&self) -> RepositoryResult<Vec<User>> {
+        Ok(sqlx::query_as::<_, User>(&format!(
+            "SELECT {USER_COLUMNS} FROM users WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC"
+        ))
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn purge_deleted(&self, before: DateTime<Utc>) -> RepositoryResult<u64> {
+        let result =
+            sqlx::query("DELETE FROM users WHERE deleted_at IS NOT NULL AND deleted_at < ?")
+                .bind(before)
+                .execute(&self.pool)
+                .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl RoleRepository for SqliteRepository {
+    async fn create(&self, name: &str, description: Option<&str>) -> RepositoryResult<Role> {
+        sqlx::query_as::<_, Role>(&format!(
+            "INSERT INTO roles (id, name, description, created_at)
+             VALUES (?, ?, ?, ?)
+             RETURNING {ROLE_COLUMNS}"
+        ))
+        .bind(Uuid::new_v4())
+        .bind(name)
+        .bind(description)
+        .bind(Utc::now())
+        .fetch_all(&self.pool)
+        .await
+        .and_then(returned_row)
+        .map_err(RepositoryError::from)
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<Role>> {
+        find_role(&self.pool, id).await
+    }
+
+    async fn find_by_name(&self, name: &str) -> RepositoryResult<Option<Role>> {
+        Ok(
+            sqlx::query_as::<_, Role>(&format!("SELECT {ROLE_COLUMNS} FROM roles WHERE name = ?"))
+                .bind(name)
+                .fetch_optional(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<Role>> {
+        Ok(
+            sqlx::query_as::<_, Role>(&format!("SELECT {ROLE_COLUMNS} FROM roles ORDER BY name"))
+                .fetch_all(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn update(
+        &self,
+        id: Uuid,
+        name: &str,
+        description: Option<&str>,
+        expected_version: Option<i32>,
+    ) -> RepositoryResult<Option<Role>> {
+        let role = sqlx::query_as::<_, Role>(&format!(
+            "UPDATE roles SET name = ?1, description = ?2, version = version + 1
+             WHERE id = ?3 AND (?4 IS NULL OR version = ?4)
+             RETURNING {ROLE_COLUMNS}"
+        ))
+        .bind(name)
+        .bind(description)
+        .bind(id)
+        .bind(expected_version)
+        .fetch_all(&self.pool)
+        .await
+        .map(|rows| rows.into_iter().next())?;
+
+        let exists = role.is_none() && RoleRepository::find_by_id(self, id).await?.is_some();
+        check_version(role, expected_version, exists)
+    }
+
+    async fn delete(&self, id: Uuid, expected_version: Option<i32>) -> RepositoryResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        let Some(role) = find_role(&mut *tx, id).await? else {
+            return Ok(false);
+        };
+        // Deleting the role takes it from its members, and from everyone
+        // holding it through a group
+        let members = sqlx::query_as::<_, User>(&format!(
+            "SELECT {USER_COLUMNS} FROM users
+             WHERE deleted_at IS NULL
+               AND id IN (SELECT user_id FROM user_roles WHERE role_id = ?)"
+        ))
+        .bind(id)
+        .fetch_all(&mut *tx)
+        .await?;
+        let groups: Vec<Uuid> =
+            sqlx::query_scalar("SELECT group_id FROM group_roles WHERE role_id = ?")
+                .bind(id)
+                .fetch_all(&mut *tx)
+                .await?;
+        let inheritors = subtree_members(&mut tx, &groups).await?;
+        let before = snapshot_roles(&mut tx, inheritors).await?;
+
+        let result =
+            sqlx::query("DELETE FROM roles WHERE id = ?1 AND (?2 IS NULL OR version = ?2)")
+                .bind(id)
+                .bind(expected_version)
+                .execute(&mut *tx)
+                .await?;
+
+        let deleted = result.rows_affected() > 0;
+        if check_version(deleted.then_some(()), expected_version, true)?.is_none() {
+            return Ok(false);
+        }
+        let events: Vec<NewEvent> = members
+            .iter()
+            .map(|user| NewEvent::role(EventType::UserRoleRemoved, user, &role))
+            .collect();
+        record_events(&mut tx, &events).await?;
+        record_roles_changes(&mut tx, before).await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl PermissionRepository for SqliteRepository {
+    async fn create(
+        &self,
+        name: &str,
+        resource: &str,
+        action: &str,
+    ) -> RepositoryResult<Permission> {
+        sqlx::query_as::<_, Permission>(&format!(
+            "INSERT INTO permissions (id, name, resource, action, created_at)
+             VALUES (?, ?, ?, ?, ?)
+             RETURNING {PERMISSION_COLUMNS}"
+        ))
+        .bind(Uuid::new_v4())
+        .bind(name)
+        .bind(resource)
+        .bind(action)
+        .bind(Utc::now())
+        .fetch_all(&self.pool)
+        .await
+        .and_then(returned_row)
+        .map_err(RepositoryError::from)
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<Permission>> {
+        Ok(sqlx::query_as::<_, Permission>(&format!(
+            "SELECT {PERMISSION_COLUMNS} FROM permissions WHERE id = ?"
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn find_by_name(&self, name: &str) -> RepositoryResult<Option<Permission>> {
+        Ok(sqlx::query_as::<_, Permission>(&format!(
+            "SELECT {PERMISSION_COLUMNS} FROM permissions WHERE name = ?"
+        ))
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<Permission>> {
+        Ok(sqlx::query_as::<_, Permission>(&format!(
+            "SELECT {PERMISSION_COLUMNS} FROM permissions ORDER BY resource, action"
+        ))
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn update(
+        &self,
+        id: Uuid,
+        name: &str,
+        resource: &str,
+        action: &str,
+        expected_version: Option<i32>,
+    ) -> RepositoryResult<Option<Permission>> {
+        let permission = sqlx::query_as::<_, Permission>(&format!(
+            "UPDATE permissions SET name = ?1, resource = ?2, action = ?3, version = version + 1
+             WHERE id = ?4 AND (?5 IS NULL OR version = ?5)
+             RETURNING {PERMISSION_COLUMNS}"
+        ))
+        .bind(name)
+        .bind(resource)
+        .bind(action)
+        .bind(id)
+        .bind(expected_version)
+        .fetch_all(&self.pool)
+        .await
+        .map(|rows| rows.into_iter().next())?;
+
+        let exists =
+            permission.is_none() && PermissionRepository::find_by_id(self, id).await?.is_some();
+        check_version(permission, expected_version, exists)
+    }
+
+    async fn delete(&self, id: Uuid, expected_version: Option<i32>) -> RepositoryResult<bool> {
+        let result =
+            sqlx::query("DELETE FROM permissions WHERE id = ?1 AND (?2 IS NULL OR version = ?2)")
+                .bind(id)
+                .bind(expected_version)
+                .execute(&self.pool)
+                .await?;
+
+        let deleted = result.rows_affected() > 0;
+        let exists = !deleted && PermissionRepository::find_by_id(self, id).await?.is_some();
+        Ok(check_version(deleted.then_some(()), expected_version, exists)?.is_some())
+    }
+}
+
+#[async_trait]
+impl AssignmentRepository for SqliteRepository {
+    async fn assign_role_to_user(&self, user_id: Uuid, role_id: Uuid) -> RepositoryResult<()> {
+        let mut tx = self.pool.begin().await?;
+        let result = sqlx::query(
+            "INSERT INTO user_roles (user_id, role_id) VALUES (?, ?) ON CONFLICT DO NOTHING",
+        )
+        .bind(user_id)
+        .bind(role_id)
+        .execute(&mut *tx)
+        .await?;
+
+        if result.rows_affected() > 0 {
+            record_role_change(&mut tx, EventType::UserRoleAssigned, user_id, role_id).await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn remove_role_from_user(&self, user_id: Uuid, role_id: Uuid) -> RepositoryResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        let result = sqlx::query("DELETE FROM user_roles WHERE user_id = ? AND role_id = ?")
+            .bind(user_id)
+            .bind(role_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let removed = result.rows_affected() > 0;
+        if removed {
+            record_role_change(&mut tx, EventType::UserRoleRemoved, user_id, role_id).await?;
+        }
+        tx.commit().await?;
+
+        Ok(removed)
+    }
+
+    async fn get_user_roles(&self, user_id: Uuid) -> RepositoryResult<Vec<Role>> {
+        Ok(sqlx::query_as::<_, Role>(
+            "SELECT r.id, r.name, r.description, r.created_at, r.version
+             FROM roles r
+             INNER JOIN user_roles ur ON r.id = ur.role_id
+             WHERE ur.user_id = ?
+             ORDER BY r.name",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn get_effective_user_roles(&self, user_id: Uuid) -> RepositoryResult<Vec<Role>> {
+        effective_roles(&self.pool, user_id).await
+    }
+
+    async fn assign_permission_to_role(
+        &self,
+        role_id: Uuid,
+        permission_id: Uuid,
+    ) -> RepositoryResult<()> {
+        sqlx::query(
+            "INSERT INTO role_permissions (role_id, permission_id) VALUES (?, ?)
+             ON CONFLICT (role_id, permission_id) DO NOTHING",
+        )
+        .bind(role_id)
+        .bind(permission_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn remove_permission_from_role(
+        &self,
+        role_id: Uuid,
+        permission_id: Uuid,
+    ) -> RepositoryResult<bool> {
+        let result =
+            sqlx::query("DELETE FROM role_permissions WHERE role_id = ? AND permission_id = ?")
+                .bind(role_id)
+                .bind(permission_id)
+                .execute(&self.pool)
+                .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_role_permissions(&self, role_id: Uuid) -> RepositoryResult<Vec<Permission>> {
+        Ok(sqlx::query_as::<_, Permission>(
+            "SELECT p.id, p.name, p.resource, p.action, p.created_at, p.version
+             FROM permissions p
+             INNER JOIN role_permissions rp ON p.id = rp.permission_id
+             WHERE rp.role_id = ?
+             ORDER BY p.name",
+        )
+        .bind(role_id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn permissions_version(&self) -> RepositoryResult<i64> {
+        Ok(
+            sqlx::query_scalar("SELECT version FROM permissions_version")
+                .fetch_one(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn role_permissions(&self) -> RepositoryResult<RolePermissions> {
+        // Read in one transaction so the version labels this exact mapping
+        let mut tx = self.pool.begin().await?;
+        let version = sqlx::query_scalar("SELECT version FROM permissions_version")
+            .fetch_one(&mut *tx)
+            .await?;
+        let rows: Vec<(String, Option<String>)> = sqlx::query_as(
+            "SELECT r.name, p.name
+             FROM roles r
+             LEFT JOIN role_permissions rp ON rp.role_id = r.id
+             LEFT JOIN permissions p ON p.id = rp.permission_id
+             ORDER BY r.name, p.name",
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        let mut map = RolePermissions {
+            version,
+            ..Default::default()
+        };
+        for (role, permission) in rows {
+            map.roles.entry(role).or_default().extend(permission);
+        }
+        Ok(map)
+    }
+
+    async fn role_has_users(&self, role_id: Uuid) -> RepositoryResult<bool> {
+        Ok(sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(
+                SELECT 1 FROM user_roles ur
+                INNER JOIN users u ON u.id = ur.user_id
+                WHERE ur.role_id = ? AND u.deleted_at IS NULL
+            )",
+        )
+        .bind(role_id)
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    async fn get_role_members(&self, role_id: Uuid) -> RepositoryResult<Vec<User>> {
+        Ok(sqlx::query_as::<_, User>(&format!(
+            "SELECT {USER_COLUMNS} FROM users
+             WHERE deleted_at IS NULL
+               AND id IN (SELECT user_id FROM user_roles WHERE role_id = ?)
+             ORDER BY username"
+        ))
+        .bind(role_id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn set_role_members(
+        &self,
+        role_id: Uuid,
+        user_ids: &[Uuid],
+    ) -> RepositoryResult<(Vec<Uuid>, Vec<Uuid>)> {
+        let user_ids = distinct(user_ids);
+        let mut tx = self.pool.begin().await?;
+
+        let sql = format!(
+            "DELETE FROM user_roles WHERE role_id = ? AND user_id NOT IN ({})
+             RETURNING user_id",
+            placeholders(user_ids.len())
+        );
+        let mut query = sqlx::query_scalar::<_, Uuid>(&sql).bind(role_id);
+        for user_id in &user_ids {
+            query = query.bind(user_id);
+        }
+        let removed = query.fetch_all(&mut *tx).await?;
+
+        let mut added = Vec::new();
+        for user_id in user_ids {
+            let result = sqlx::query(
+                "INSERT INTO user_roles (user_id, role_id) VALUES (?, ?) ON CONFLICT DO NOTHING",
+            )
+            .bind(user_id)
+            .bind(role_id)
+            .execute(&mut *tx)
+            .await?;
+            if result.rows_affected() > 0 {
+                added.push(user_id);
+            }
+        }
+
+        for user_id in &added {
+            record_role_change(&mut tx, EventType::UserRoleAssigned, *user_id, role_id).await?;
+        }
+        for user_id in &removed {
+            record_role_change(&mut tx, EventType::UserRoleRemoved, *user_id, role_id).await?;
+        }
+        tx.commit().await?;
+
+        Ok((added, removed))
+    }
+}
+
+#[async_trait]
+impl SessionRepository for SqliteRepository {
+    async fn create(
+        &self,
+        user_id: Uuid,
+        user_agent: Option<&str>,
+        ip_address: Option<&str>,
+        expires_at: DateTime<Utc>,
+    ) -> RepositoryResult<Session> {
+        let now = Utc::now();
+        Ok(sqlx::query_as::<_, Session>(&format!(
+            "INSERT INTO sessions
+                 (id, user_id, user_agent, ip_address, created_at, last_used_at, expires_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?)
+             RETURNING {SESSION_COLUMNS}"
+        ))
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(user_agent)
+        .bind(ip_address)
+        .bind(now)
+        .bind(now)
+        .bind(expires_at)
+        .fetch_all(&self.pool)
+        .await
+        .and_then(returned_row)?)
+    }
+
+    async fn list_active_for_user(&self, user_id: Uuid) -> RepositoryResult<Vec<Session>> {
+        Ok(sqlx::query_as::<_, Session>(&format!(
+            "SELECT {SESSION_COLUMNS}
+             FROM sessions
+             WHERE user_id = ? AND revoked_at IS NULL AND expires_at > ?
+             ORDER BY last_used_at DESC"
+        ))
+        .bind(user_id)
+        .bind(Utc::now())
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn list_for_user(&self, user_id: Uuid) -> RepositoryResult<Vec<Session>> {
+        Ok(sqlx::query_as::<_, Session>(&format!(
+            "SELECT {SESSION_COLUMNS} FROM sessions WHERE user_id = ? ORDER BY created_at DESC"
+        ))
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn touch(&self, id: Uuid) -> RepositoryResult<bool> {
+        let result = sqlx::query(
+            "UPDATE sessions
+             SET last_used_at = ?1
+             WHERE id = ?2 AND revoked_at IS NULL AND expires_at > ?1",
+        )
+        .bind(Utc::now())
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn revoke(&self, user_id: Uuid, id: Uuid) -> RepositoryResult<bool> {
+        let result = sqlx::query(
+            "UPDATE sessions
+             SET revoked_at = ?
+             WHERE id = ? AND user_id = ? AND revoked_at IS NULL",
+        )
+        .bind(Utc::now())
+        .bind(id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn revoke_all_for_user(
+        &self,
+        user_id: Uuid,
+        except: Option<Uuid>,
+    ) -> RepositoryResult<u64> {
+        let result = sqlx::query(
+            "UPDATE sessions
+             SET revoked_at = ?1
+             WHERE user_id = ?2 AND revoked_at IS NULL AND (?3 IS NULL OR id <> ?3)",
+        )
+        .bind(Utc::now())
+        .bind(user_id)
+        .bind(except)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl SetupTokenRepository for SqliteRepository {
+    async fn replace(&self, token_hash: &str, expires_at: DateTime<Utc>) -> RepositoryResult<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM setup_tokens")
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(
+            "INSERT INTO setup_tokens (token_hash, created_at, expires_at) VALUES (?, ?, ?)",
+        )
+        .bind(token_hash)
+        .bind(Utc::now())
+        .bind(expires_at)
+        .execute(&mut *tx)
+        .await?;
+
+        Ok(tx.commit().await?)
+    }
+
+    async fn consume(&self, token_hash: &str) -> RepositoryResult<bool> {
+        let result =
+            sqlx::query("DELETE FROM setup_tokens WHERE token_hash = ? AND expires_at > ?")
+                .bind(token_hash)
+                .bind(Utc::now())
+                .execute(&self.pool)
+                .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn clear(&self) -> RepositoryResult<()> {
+        sqlx::query("DELETE FROM setup_tokens")
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// `webhooks` row; the event types are stored as a JSON array
+#[derive(FromRow)]
+struct WebhookRow {
+    id: Uuid,
+    url: String,
+    secret: String,
+    event_types: String,
+    created_at: DateTime<Utc>,
+}
+
+impl TryFrom<WebhookRow> for Webhook {
+    type Error = RepositoryError;
+
+    fn try_from(row: WebhookRow) -> RepositoryResult<Self> {
+        let event_types = serde_json::from_str(&row.event_types)
+            .map_err(|e| RepositoryError::Database(sqlx::Error::Decode(e.into())))?;
+        Ok(Self {
+            id: row.id,
+            url: row.url,
+            secret: row.secret,
+            event_types,
+            created_at: row.created_at,
+        })
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for SqliteRepository {
+    async fn create(
+        &self,
+        url: &str,
+        secret: &str,
+        event_types: &[String],
+    ) -> RepositoryResult<Webhook> {
+        sqlx::query_as::<_, WebhookRow>(&format!(
+            "INSERT INTO webhooks (id, url, secret, event_types, created_at)
+             VALUES (?, ?, ?, ?, ?)
+             RETURNING {WEBHOOK_COLUMNS}"
+        ))
+        .bind(Uuid::new_v4())
+        .bind(url)
+        .bind(secret)
+        .bind(serde_json::json!(event_types).to_string())
+        .bind(Utc::now())
+        .fetch_all(&self.pool)
+        .await
+        .and_then(returned_row)?
+        .try_into()
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<Webhook>> {
+        sqlx::query_as::<_, WebhookRow>(&format!(
+            "SELECT {WEBHOOK_COLUMNS} FROM webhooks WHERE id = ?"
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .map(Webhook::try_from)
+        .transpose()
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<Webhook>> {
+        sqlx::query_as::<_, WebhookRow>(&format!(
+            "SELECT {WEBHOOK_COLUMNS} FROM webhooks ORDER BY created_at DESC"
+        ))
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(Webhook::try_from)
+        .collect()
+    }
+
+    async fn delete(&self, id: Uuid) -> RepositoryResult<bool> {
+        let result = sqlx::query("DELETE FROM webhooks WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_deliveries(
+        &self,
+        webhook_id: Uuid,
+        limit: i64,
+    ) -> RepositoryResult<Vec<WebhookDelivery>> {
+        Ok(sqlx::query_as::<_, WebhookDelivery>(&format!(
+            "SELECT {DELIVERY_COLUMNS}
+             FROM webhook_deliveries d
+             INNER JOIN webhook_events e ON e.id = d.event_id
+             WHERE d.webhook_id = ?
+             ORDER BY d.created_at DESC
+             LIMIT ?"
+        ))
+        .bind(webhook_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn redeliver(
+        &self,
+        webhook_id: Uuid,
+        delivery_id: Uuid,
+    ) -> RepositoryResult<Option<WebhookDelivery>> {
+        let mut tx = self.pool.begin().await?;
+        let id = Uuid::new_v4();
+        let now = Utc::now();
+        let result = sqlx::query(
+            "INSERT INTO webhook_deliveries
+                 (id, webhook_id, event_id, status, next_attempt_at, created_at, updated_at)
+             SELECT ?1, webhook_id, event_id, ?2, ?3, ?3, ?3
+             FROM webhook_deliveries
+             WHERE id = ?4 AND webhook_id = ?5",
+        )
+        .bind(id)
+        .bind(DeliveryStatus::Pending.as_str())
+        .bind(now)
+        .bind(delivery_id)
+        .bind(webhook_id)
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let delivery = sqlx::query_as::<_, WebhookDelivery>(&format!(
+            "SELECT {DELIVERY_COLUMNS}
+             FROM webhook_deliveries d
+             INNER JOIN webhook_events e ON e.id = d.event_id
+             WHERE d.id = ?"
+        ))
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(Some(delivery))
+    }
+
+    async fn dispatch_events(&self, limit: i64) -> RepositoryResult<u64> {
+        let mut tx = self.pool.begin().await?;
+        let now = Utc::now();
+
+        // Writers are serialized, so nothing else claims these meanwhile
+        let events: Vec<(Uuid, String)> = sqlx::query_as(
+            "UPDATE webhook_events SET dispatched_at = ?1
+             WHERE id IN (
+                 SELECT id FROM webhook_events
+                 WHERE dispatched_at IS NULL
+                 ORDER BY created_at
+                 LIMIT ?2
+             )
+             RETURNING id, event_type",
+        )
+        .bind(now)
+        .bind(limit)
+        .fetch_all(&mut *tx)
+        .await?;
+        if events.is_empty() {
+            return Ok(0);
+        }
+
+        let webhooks: Vec<Webhook> =
+            sqlx::query_as::<_, WebhookRow>(&format!("SELECT {WEBHOOK_COLUMNS} FROM webhooks"))
+                .fetch_all(&mut *tx)
+                .await?
+                .into_iter()
+                .map(Webhook::try_from)
+                .collect::<RepositoryResult<_>>()?;
+
+        let mut created = 0;
+        for (event_id, event_type) in &events {
+            for webhook in webhooks.iter().filter(|w| w.subscribes_to(event_type)) {
+                sqlx::query(
+                    "INSERT INTO webhook_deliveries
+                         (id, webhook_id, event_id, status, next_attempt_at, created_at, updated_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?5, ?5)",
+                )
+                .bind(Uuid::new_v4())
+                .bind(webhook.id)
+                .bind(event_id)
+                .bind(DeliveryStatus::Pending.as_str())
+                .bind(now)
+                .execute(&mut *tx)
+                .await?;
+                created += 1;
+            }
+        }
+        tx.commit().await?;
+
+        Ok(created)
+    }
+
+    async fn claim_due_deliveries(
+        &self,
+        limit: i64,
+        lease_seconds: i32,
+    ) -> RepositoryResult<Vec<DueDelivery>> {
+        let mut tx = self.pool.begin().await?;
+        let now = Utc::now();
+
+        let ids: Vec<Uuid> = sqlx::query_scalar(
+            "UPDATE webhook_deliveries
+             SET attempts = attempts + 1, next_attempt_at = ?1, updated_at = ?2
+             WHERE id IN (
+                 SELECT id FROM webhook_deliveries
+                 WHERE status = ?3 AND next_attempt_at <= ?2
+                 ORDER BY next_attempt_at
+                 LIMIT ?4
+             )
+             RETURNING id",
+        )
+        .bind(now + chrono::Duration::seconds(lease_seconds.into()))
+        .bind(now)
+        .bind(DeliveryStatus::Pending.as_str())
+        .bind(limit)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let mut deliveries = Vec::with_capacity(ids.len());
+        for id in ids {
+            let delivery = sqlx::query_as::<_, DueDelivery>(
+                "SELECT d.id, d.webhook_id, w.url, w.secret, d.event_id, e.event_type, e.payload,
+                        e.created_at AS event_created_at, d.attempts
+                 FROM webhook_deliveries d
+                 INNER JOIN webhooks w ON w.id = d.webhook_id
+                 INNER JOIN webhook_events e ON e.id = d.event_id
+                 WHERE d.id = ?",
+            )
+            .bind(id)
+            .fetch_one(&mut *tx)
+            .await?;
+            deliveries.push(delivery);
+        }
+        tx.commit().await?;
+
+        Ok(deliveries)
+    }
+
+    async fn record_attempt(
+        &self,
+        delivery_id: Uuid,
+        attempt: &DeliveryAttempt,
+    ) -> RepositoryResult<()> {
+        sqlx::query(
+            "UPDATE webhook_deliveries
+             SET status = ?, next_attempt_at = ?, response_status = ?, error = ?, updated_at = ?
+             WHERE id = ?",
+        )
+        .bind(attempt.status.as_str())
+        .bind(attempt.next_attempt_at)
+        .bind(attempt.response_status)
+        .bind(&attempt.error)
+        .bind(Utc::now())
+        .bind(delivery_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn purge_events(&self, before: DateTime<Utc>) -> RepositoryResult<u64> {
+        let result = sqlx::query(
+            "DELETE FROM webhook_events
+             WHERE dispatched_at < ?1
+               AND NOT EXISTS (
+                   SELECT 1 FROM webhook_deliveries d
+                   WHERE d.event_id = webhook_events.id AND d.status = ?2
+               )",
+        )
+        .bind(before)
+        .bind(DeliveryStatus::Pending.as_str())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn list_events(&self) -> RepositoryResult<Vec<WebhookEvent>> {
+        Ok(sqlx::query_as::<_, WebhookEvent>(
+            "SELECT id, event_type, payload, created_at, dispatched_at
+             FROM webhook_events
+             ORDER BY created_at, id",
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+}
+
+#[async_trait]
+impl ServiceAccountRepository for SqliteRepository {
+    async fn create(
+        &self,
+        name: &str,
+        description: Option<&str>,
+        owner_id: Option<Uuid>,
+    ) -> RepositoryResult<ServiceAccount> {
+        let now = Utc::now();
+        Ok(sqlx::query_as::<_, ServiceAccount>(&format!(
+            "INSERT INTO service_accounts (id, name, description, owner_id, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?)
+             RETURNING {SERVICE_ACCOUNT_COLUMNS}"
+        ))
+        .bind(Uuid::new_v4())
+        .bind(name)
+        .bind(description)
+        .bind(owner_id)
+        .bind(now)
+        .bind(now)
+        .fetch_all(&self.pool)
+        .await
+        .and_then(returned_row)?)
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<ServiceAccount>> {
+        Ok(sqlx::query_as::<_, ServiceAccount>(&format!(
+            "SELECT {SERVICE_ACCOUNT_COLUMNS} FROM service_accounts WHERE id = ?"
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<ServiceAccount>> {
+        Ok(sqlx::query_as::<_, ServiceAccount>(&format!(
+            "SELECT {SERVICE_ACCOUNT_COLUMNS} FROM service_accounts ORDER BY name"
+        ))
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn update(
+        &self,
+        id: Uuid,
+        description: Option<&str>,
+        owner_id: Option<Uuid>,
+        is_active: bool,
+    ) -> RepositoryResult<Option<ServiceAccount>> {
+        Ok(sqlx::query_as::<_, ServiceAccount>(&format!(
+            "UPDATE service_accounts
+             SET description = ?, owner_id = ?, is_active = ?, updated_at = ?
+             WHERE id = ?
+             RETURNING {SERVICE_ACCOUNT_COLUMNS}"
+        ))
+        .bind(description)
+        .bind(owner_id)
+        .bind(is_active)
+        .bind(Utc::now())
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await
+        .map(|rows| rows.into_iter().next())?)
+    }
+
+    async fn delete(&self, id: Uuid) -> RepositoryResult<bool> {
+        let result = sqlx::query("DELETE FROM service_accounts WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn assign_role(&self, id: Uuid, role_id: Uuid) -> RepositoryResult<()> {
+        sqlx::query(
+            "INSERT INTO service_account_roles (service_account_id, role_id, assigned_at)
+             VALUES (?, ?, ?)
+             ON CONFLICT (service_account_id, role_id) DO NOTHING",
+        )
+        .bind(id)
+        .bind(role_id)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn remove_role(&self, id: Uuid, role_id: Uuid) -> RepositoryResult<bool> {
+        let result = sqlx::query(
+            "DELETE FROM service_account_roles WHERE service_account_id = ? AND role_id = ?",
+        )
+        .bind(id)
+        .bind(role_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_roles(&self, id: Uuid) -> RepositoryResult<Vec<Role>> {
+        Ok(sqlx::query_as::<_, Role>(
+            "SELECT r.id, r.name, r.description, r.created_at, r.version
+             FROM roles r
+             INNER JOIN service_account_roles sar ON sar.role_id = r.id
+             WHERE sar.service_account_id = ?
+             ORDER BY r.name",
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn add_credential(
+        &self,
+        id: Uuid,
+        credential: NewCredential<'_>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> RepositoryResult<ServiceAccountCredential> {
+        let (secret_hash, public_key, algorithm) = credential.columns();
+        Ok(sqlx::query_as::<_, ServiceAccountCredential>(&format!(
+            "INSERT INTO service_account_credentials
+                 (id, service_account_id, kind, secret_hash, public_key, algorithm,
+                  created_at, expires_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+             RETURNING {CREDENTIAL_COLUMNS}"
+        ))
+        .bind(Uuid::new_v4())
+        .bind(id)
+        .bind(credential.kind().as_str())
+        .bind(secret_hash)
+        .bind(public_key)
+        .bind(algorithm)
+        .bind(Utc::now())
+        .bind(expires_at)
+        .fetch_all(&self.pool)
+        .await
+        .and_then(returned_row)?)
+    }
+
+    async fn list_credentials(&self, id: Uuid) -> RepositoryResult<Vec<ServiceAccountCredential>> {
+        Ok(sqlx::query_as::<_, ServiceAccountCredential>(&format!(
+            "SELECT {CREDENTIAL_COLUMNS}
+             FROM service_account_credentials
+             WHERE service_account_id = ?
+             ORDER BY created_at DESC"
+        ))
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn delete_credential(&self, id: Uuid, credential_id: Uuid) -> RepositoryResult<bool> {
+        let result = sqlx::query(
+            "DELETE FROM service_account_credentials WHERE id = ? AND service_account_id = ?",
+        )
+        .bind(credential_id)
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn touch_credential(&self, credential_id: Uuid) -> RepositoryResult<()> {
+        sqlx::query("UPDATE service_account_credentials SET last_used_at = ? WHERE id = ?")
+            .bind(Utc::now())
+            .bind(credential_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn use_assertion(
+        &self,
+        id: Uuid,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> RepositoryResult<bool> {
+        let result = sqlx::query(
+            "INSERT INTO used_client_assertions (service_account_id, jti, expires_at)
+             VALUES (?1, ?2, ?3)
+             ON CONFLICT (service_account_id, jti) DO UPDATE
+                 SET expires_at = excluded.expires_at
+                 WHERE used_client_assertions.expires_at <= ?4",
+        )
+        .bind(id)
+        .bind(jti)
+        .bind(expires_at)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn purge_used_assertions(&self, before: DateTime<Utc>) -> RepositoryResult<u64> {
+        let result = sqlx::query("DELETE FROM used_client_assertions WHERE expires_at <= ?")
+            .bind(before)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl DeviceAuthorizationRepository for SqliteRepository {
+    async fn create(
+        &self,
+        device_code_hash: &str,
+        user_code: &str,
+        client_id: &str,
+        interval_seconds: i32,
+        expires_at: DateTime<Utc>,
+    ) -> RepositoryResult<DeviceAuthorization> {
+        Ok(sqlx::query_as::<_, DeviceAuthorization>(&format!(
+            "INSERT INTO device_authorizations
+                 (id, device_code_hash, user_code, client_id, interval_seconds, created_at,
+                  expires_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?)
+             RETURNING {DEVICE_AUTHORIZATION_COLUMNS}"
+        ))
+        .bind(Uuid::new_v4())
+        .bind(device_code_hash)
+        .bind(user_code)
+        .bind(client_id)
+        .bind(interval_seconds)
+        .bind(Utc::now())
+        .bind(expires_at)
+        .fetch_all(&self.pool)
+        .await
+        .and_then(returned_row)?)
+    }
+
+    async fn find_by_user_code(
+        &self,
+        user_code: &str,
+    ) -> RepositoryResult<Option<DeviceAuthorization>> {
+        Ok(sqlx::query_as::<_, DeviceAuthorization>(&format!(
+            "SELECT {DEVICE_AUTHORIZATION_COLUMNS} FROM device_authorizations WHERE user_code = ?"
+        ))
+        .bind(user_code)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn find_by_device_code(
+        &self,
+        device_code_hash: &str,
+    ) -> RepositoryResult<Option<DeviceAuthorization>> {
+        Ok(sqlx::query_as::<_, DeviceAuthorization>(&format!(
+            "SELECT {DEVICE_AUTHORIZATION_COLUMNS} FROM device_authorizations
+             WHERE device_code_hash = ?"
+        ))
+        .bind(device_code_hash)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn decide(
+        &self,
+        user_code: &str,
+        user_id: Uuid,
+        approve: bool,
+    ) -> RepositoryResult<Option<DeviceAuthorization>> {
+        let now = Utc::now();
+        Ok(sqlx::query_as::<_, DeviceAuthorization>(&format!(
+            "UPDATE device_authorizations
+             SET user_id = ?, approved_at = ?, denied_at = ?
+             WHERE user_code = ?
+               AND approved_at IS NULL AND denied_at IS NULL AND expires_at > ?
+             RETURNING {DEVICE_AUTHORIZATION_COLUMNS}"
+        ))
+        .bind(user_id)
+        .bind(approve.then_some(now))
+        .bind((!approve).then_some(now))
+        .bind(user_code)
+        .bind(now)
+        .fetch_all(&self.pool)
+        .await
+        .map(|rows| rows.into_iter().next())?)
+    }
+
+    async fn record_poll(&self, id: Uuid, interval_seconds: i32) -> RepositoryResult<()> {
+        sqlx::query(
+            "UPDATE device_authorizations SET last_polled_at = ?, interval_seconds = ? WHERE id = ?",
+        )
+        .bind(Utc::now())
+        .bind(interval_seconds)
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: Uuid) -> RepositoryResult<bool> {
+        let result = sqlx::query("DELETE FROM device_authorizations WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn purge_expired(&self, before: DateTime<Utc>) -> RepositoryResult<u64> {
+        let result = sqlx::query("DELETE FROM device_authorizations WHERE expires_at <= ?")
+            .bind(before)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl IdentityRepository for SqliteRepository {
+    async fn create(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        subject: &str,
+        email: Option<&str>,
+    ) -> RepositoryResult<UserIdentity> {
+        Ok(sqlx::query_as::<_, UserIdentity>(&format!(
+            "INSERT INTO user_identities (id, user_id, provider, subject, email, created_at)
+             VALUES (?, ?, ?, ?, ?, ?)
+             RETURNING {IDENTITY_COLUMNS}"
+        ))
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(provider)
+        .bind(subject)
+        .bind(email)
+        .bind(Utc::now())
+        .fetch_all(&self.pool)
+        .await
+        .and_then(returned_row)?)
+    }
+
+    async fn find(&self, provider: &str, subject: &str) -> RepositoryResult<Option<UserIdentity>> {
+        Ok(sqlx::query_as::<_, UserIdentity>(&format!(
+            "SELECT {IDENTITY_COLUMNS} FROM user_identities WHERE provider = ? AND subject = ?"
+        ))
+        .bind(provider)
+        .bind(subject)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn list_for_user(&self, user_id: Uuid) -> RepositoryResult<Vec<UserIdentity>> {
+        Ok(sqlx::query_as::<_, UserIdentity>(&format!(
+            "SELECT {IDENTITY_COLUMNS} FROM user_identities WHERE user_id = ? ORDER BY created_at"
+        ))
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn touch(&self, id: Uuid) -> RepositoryResult<()> {
+        sqlx::query("UPDATE user_identities SET last_login_at = ? WHERE id = ?")
+            .bind(Utc::now())
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, user_id: Uuid, id: Uuid) -> RepositoryResult<bool> {
+        let result = sqlx::query("DELETE FROM user_identities WHERE id = ? AND user_id = ?")
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl PrivacyRepository for SqliteRepository {
+    async fn find_user(&self, user_id: Uuid) -> RepositoryResult<Option<User>> {
+        Ok(
+            sqlx::query_as::<_, User>(&format!("SELECT {USER_COLUMNS} FROM users WHERE id = ?"))
+                .bind(user_id)
+                .fetch_optional(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn user_groups(&self, user_id: Uuid) -> RepositoryResult<Vec<Group>> {
+        Ok(sqlx::query_as::<_, Group>(
+            "SELECT g.id, g.name, g.description, g.created_at
+             FROM groups g
+             INNER JOIN group_members gm ON g.id = gm.group_id
+             WHERE gm.user_id = ?
+             ORDER BY g.name",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn user_invitations(
+        &self,
+        user_id: Uuid,
+        email: &str,
+    ) -> RepositoryResult<Vec<Invitation>> {
+        Ok(sqlx::query_as::<_, Invitation>(&format!(
+            "SELECT {INVITATION_COLUMNS}
+             FROM invitations
+             WHERE accepted_user_id = ? OR email = ?
+             ORDER BY created_at DESC"
+        ))
+        .bind(user_id)
+        .bind(email)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn user_events(&self, user_id: Uuid) -> RepositoryResult<Vec<WebhookEvent>> {
+        Ok(sqlx::query_as::<_, WebhookEvent>(
+            "SELECT id, event_type, payload, created_at, dispatched_at
+             FROM webhook_events
+             WHERE json_extract(payload, '$.user.id') = ?
+             ORDER BY created_at",
+        )
+        .bind(user_id.to_string())
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn user_impersonations(
+        &self,
+        user_id: Uuid,
+    ) -> RepositoryResult<Vec<ImpersonationEvent>> {
+        Ok(sqlx::query_as::<_, ImpersonationEvent>(&format!(
+            "SELECT {IMPERSONATION_COLUMNS}
+             FROM impersonation_events
+             WHERE actor_id = ?1 OR target_user_id = ?1
+             ORDER BY created_at DESC"
+        ))
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn erase_user(
+        &self,
+        user_id: Uuid,
+        requested_by: Uuid,
+        reason: Option<&str>,
+    ) -> RepositoryResult<Option<Erasure>> {
+        let mut tx = self.pool.begin().await?;
+
+        let email: Option<String> =
+            sqlx::query_scalar("DELETE FROM users WHERE id = ? RETURNING email")
+                .bind(user_id)
+                .fetch_all(&mut *tx)
+                .await
+                .map(|rows| rows.into_iter().next())?;
+        let Some(email) = email else {
+            return Ok(None);
+        };
+
+        let pseudonym_id = Uuid::new_v4();
+        let pseudonym_username = Erasure::pseudonym_username(pseudonym_id);
+
+        sqlx::query(
+            "UPDATE impersonation_events SET actor_id = ?2, actor_username = ?3
+             WHERE actor_id = ?1",
+        )
+        .bind(user_id)
+        .bind(pseudonym_id)
+        .bind(&pseudonym_username)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            "UPDATE impersonation_events SET target_user_id = ?2, target_username = ?3
+             WHERE target_user_id = ?1",
+        )
+        .bind(user_id)
+        .bind(pseudonym_id)
+        .bind(&pseudonym_username)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            "UPDATE webhook_events
+             SET payload = json_set(payload, '$.user.id', ?2, '$.user.username', ?3,
+                                    '$.user.email', NULL)
+             WHERE json_extract(payload, '$.user.id') = ?1",
+        )
+        .bind(user_id.to_string())
+        .bind(pseudonym_id.to_string())
+        .bind(&pseudonym_username)
+        .execute(&mut *tx)
+        .await?;
+
+        // Invitations to the user hold their email address; the ones they
+        // sent only need the inviter's pseudonym
+        sqlx::query("DELETE FROM invitations WHERE accepted_user_id = ? OR email = ?")
+            .bind(user_id)
+            .bind(&email)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("UPDATE invitations SET invited_by = ? WHERE invited_by = ?")
+            .bind(pseudonym_id)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("UPDATE erasures SET requested_by = ? WHERE requested_by = ?")
+            .bind(pseudonym_id)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let erasure = sqlx::query_as::<_, Erasure>(&format!(
+            "INSERT INTO erasures (id, pseudonym_id, requested_by, reason, created_at)
+             VALUES (?, ?, ?, ?, ?)
+             RETURNING {ERASURE_COLUMNS}"
+        ))
+        .bind(Uuid::new_v4())
+        .bind(pseudonym_id)
+        // An admin erasing themselves is recorded under their pseudonym
+        .bind(if requested_by == user_id {
+            pseudonym_id
+        } else {
+            requested_by
+        })
+        .bind(reason)
+        .bind(Utc::now())
+        .fetch_all(&mut *tx)
+        .await
+        .and_then(returned_row)?;
+
+        record_events(&mut tx, &[NewEvent::erasure(user_id)]).await?;
+        tx.commit().await?;
+
+        Ok(Some(erasure))
+    }
+
+    async fn list_erasures(&self) -> RepositoryResult<Vec<Erasure>> {
+        Ok(sqlx::query_as::<_, Erasure>(&format!(
+            "SELECT {ERASURE_COLUMNS} FROM erasures ORDER BY created_at DESC"
+        ))
+        .fetch_all(&self.pool)
+        .await?)
+    }
+}
+
+/// `user_preferences` row; the favourite cities are stored as a JSON array
+#[derive(FromRow)]
+struct PreferencesRow {
+    user_id: Uuid,
+    units: Option<String>,
+    locale: Option<String>,
+    time_format: Option<String>,
+    home_timezone: Option<String>,
+    favourite_cities: String,
+    updated_at: DateTime<Utc>,
+}
+
+impl TryFrom<PreferencesRow> for UserPreferences {
+    type Error = RepositoryError;
+
+    fn try_from(row: PreferencesRow) -> RepositoryResult<Self> {
+        let favourite_cities = serde_json::from_str(&row.favourite_cities)
+            .map_err(|e| RepositoryError::Database(sqlx::Error::Decode(e.into())))?;
+        Ok(Self {
+            user_id: row.user_id,
+            units: row.units,
+            locale: row.locale,
+            time_format: row.time_format,
+            home_timezone: row.home_timezone,
+            favourite_cities,
+            updated_at: row.updated_at,
+        })
+    }
+}
+
+#[async_trait]
+impl PreferenceRepository for SqliteRepository {
+    async fn get(&self, user_id: Uuid) -> RepositoryResult<Option<Preferences>> {
+        let row = sqlx::query_as::<_, PreferencesRow>(&format!(
+            "SELECT {PREFERENCES_COLUMNS} FROM user_preferences WHERE user_id = ?"
+        ))
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(|row| UserPreferences::try_from(row).map(Preferences::from))
+            .transpose()
+    }
+
+    async fn put(&self, user_id: Uuid, preferences: &Preferences) -> RepositoryResult<()> {
+        sqlx::query(
+            "INSERT INTO user_preferences
+                 (user_id, units, locale, time_format, home_timezone, favourite_cities, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?)
+             ON CONFLICT (user_id) DO UPDATE
+             SET units = excluded.units,
+                 locale = excluded.locale,
+                 time_format = excluded.time_format,
+                 home_timezone = excluded.home_timezone,
+                 favourite_cities = excluded.favourite_cities,
+                 updated_at = excluded.updated_at",
+        )
+        .bind(user_id)
+        .bind(preferences.units.map(|u| u.as_str()))
+        .bind(preferences.locale.as_deref())
+        .bind(preferences.time_format.map(|f| f.as_str()))
+        .bind(preferences.home_timezone.as_deref())
+        .bind(serde_json::json!(preferences.favourite_cities).to_string())
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, user_id: Uuid) -> RepositoryResult<bool> {
+        let result = sqlx::query("DELETE FROM user_preferences WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl GroupRepository for SqliteRepository {
+    async fn create(&self, name: &str, description: Option<&str>) -> RepositoryResult<Group> {
+        Ok(sqlx::query_as::<_, Group>(&format!(
+            "INSERT INTO groups (id, name, description, created_at)
+             VALUES (?, ?, ?, ?)
+             RETURNING {GROUP_COLUMNS}"
+        ))
+        .bind(Uuid::new_v4())
+        .bind(name)
+        .bind(description)
+        .bind(Utc::now())
+        .fetch_all(&self.pool)
+        .await
+        .and_then(returned_row)?)
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<Group>> {
+        Ok(
+            sqlx::query_as::<_, Group>(&format!("SELECT {GROUP_COLUMNS} FROM groups WHERE id = ?"))
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<Group>> {
+        Ok(
+            sqlx::query_as::<_, Group>(&format!(
+                "SELECT {GROUP_COLUMNS} FROM groups ORDER BY name"
+            ))
+            .fetch_all(&self.pool)
+            .await?,
+        )
+    }
+
+    async fn delete(&self, id: Uuid) -> RepositoryResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        let members = subtree_members(&mut tx, &[id]).await?;
+        let before = snapshot_roles(&mut tx, members).await?;
+        let result = sqlx::query("DELETE FROM groups WHERE id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        record_roles_changes(&mut tx, before).await?;
+        tx.commit().await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_members(&self, id: Uuid) -> RepositoryResult<Vec<User>> {
+        Ok(sqlx::query_as::<_, User>(&format!(
+            "SELECT {USER_COLUMNS} FROM users
+             WHERE deleted_at IS NULL
+               AND id IN (SELECT user_id FROM group_members WHERE group_id = ?)
+             ORDER BY username"
+        ))
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn add_members(&self, id: Uuid, user_ids: &[Uuid]) -> RepositoryResult<u64> {
+        let mut tx = self.pool.begin().await?;
+        let users = find_users(&mut *tx, user_ids).await?;
+        let before = snapshot_roles(&mut tx, users).await?;
+        let mut added = 0;
+        for user_id in user_ids {
+            added += sqlx::query(
+                "INSERT INTO group_members (group_id, user_id) VALUES (?, ?)
+                 ON CONFLICT (group_id, user_id) DO NOTHING",
+            )
+            .bind(id)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        }
+        record_roles_changes(&mut tx, before).await?;
+        tx.commit().await?;
+
+        Ok(added)
+    }
+
+    async fn remove_members(&self, id: Uuid, user_ids: &[Uuid]) -> RepositoryResult<u64> {
+        let mut tx = self.pool.begin().await?;
+        let users = find_users(&mut *tx, user_ids).await?;
+        let before = snapshot_roles(&mut tx, users).await?;
+        let sql = format!(
+            "DELETE FROM group_members WHERE group_id = ? AND user_id IN ({})",
+            placeholders(user_ids.len())
+        );
+        let mut query = sqlx::query(&sql).bind(id);
+        for user_id in user_ids {
+            query = query.bind(user_id);
+        }
+        let removed = query.execute(&mut *tx).await?.rows_affected();
+        record_roles_changes(&mut tx, before).await?;
+        tx.commit().await?;
+
+        Ok(removed)
+    }
+
+    async fn get_subgroups(&self, id: Uuid) -> RepositoryResult<Vec<Group>> {
+        Ok(sqlx::query_as::<_, Group>(&format!(
+            "SELECT {GROUP_COLUMNS} FROM groups
+             WHERE id IN (SELECT child_group_id FROM group_subgroups WHERE parent_group_id = ?)
+             ORDER BY name"
+        ))
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn add_subgroup(&self, id: Uuid, child_id: Uuid) -> RepositoryResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        let members = subtree_members(&mut tx, &[child_id]).await?;
+        let before = snapshot_roles(&mut tx, members).await?;
+        // Inserting first takes the database's write lock, so no other
+        // nesting can slip in between the insert and the cycle check below
+        sqlx::query(
+            "INSERT INTO group_subgroups (parent_group_id, child_group_id) VALUES (?, ?)
+             ON CONFLICT (parent_group_id, child_group_id) DO NOTHING",
+        )
+        .bind(id)
+        .bind(child_id)
+        .execute(&mut *tx)
+        .await?;
+        // The nesting made a cycle if the parent is now nested in itself
+        let creates_cycle: bool = sqlx::query_scalar(
+            "WITH RECURSIVE descendants AS (
+                 SELECT ?1 AS group_id
+                 UNION
+                 SELECT gs.child_group_id
+                 FROM group_subgroups gs
+                 INNER JOIN descendants d ON gs.parent_group_id = d.group_id
+             )
+             SELECT EXISTS (SELECT 1 FROM descendants WHERE group_id = ?2)",
+        )
+        .bind(child_id)
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if creates_cycle {
+            return Ok(false);
+        }
+        record_roles_changes(&mut tx, before).await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    async fn remove_subgroup(&self, id: Uuid, child_id: Uuid) -> RepositoryResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        let members = subtree_members(&mut tx, &[child_id]).await?;
+        let before = snapshot_roles(&mut tx, members).await?;
+        let result = sqlx::query(
+            "DELETE FROM group_subgroups WHERE parent_group_id = ? AND child_group_id = ?",
+        )
+        .bind(id)
+        .bind(child_id)
+        .execute(&mut *tx)
+        .await?;
+        record_roles_changes(&mut tx, before).await?;
+        tx.commit().await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_roles(&self, id: Uuid) -> RepositoryResult<Vec<Role>> {
+        Ok(sqlx::query_as::<_, Role>(&format!(
+            "SELECT {ROLE_COLUMNS} FROM roles
+             WHERE id IN (SELECT role_id FROM group_roles WHERE group_id = ?)
+             ORDER BY name"
+        ))
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn assign_role(&self, id: Uuid, role_id: Uuid) -> RepositoryResult<()> {
+        let mut tx = self.pool.begin().await?;
+        let members = subtree_members(&mut tx, &[id]).await?;
+        let before = snapshot_roles(&mut tx, members).await?;
+        sqlx::query(
+            "INSERT INTO group_roles (group_id, role_id) VALUES (?, ?)
+             ON CONFLICT (group_id, role_id) DO NOTHING",
+        )
+        .bind(id)
+        .bind(role_id)
+        .execute(&mut *tx)
+        .await?;
+        record_roles_changes(&mut tx, before).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn remove_role(&self, id: Uuid, role_id: Uuid) -> RepositoryResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        let members = subtree_members(&mut tx, &[id]).await?;
+        let before = snapshot_roles(&mut tx, members).await?;
+        let result = sqlx::query("DELETE FROM group_roles WHERE group_id = ? AND role_id = ?")
+            .bind(id)
+            .bind(role_id)
+            .execute(&mut *tx)
+            .await?;
+        record_roles_changes(&mut tx, before).await?;
+        tx.commit().await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+async fn insert_invitation(
+    conn: &mut SqliteConnection,
+    email: &str,
+    token_hash: &str,
+    invited_by: Option<Uuid>,
+    expires_at: DateTime<Utc>,
+    role_ids: &[Uuid],
+) -> RepositoryResult<Invitation> {
+    let invitation = sqlx::query_as::<_, Invitation>(&format!(
+        "INSERT INTO invitations (id, email, token_hash, invited_by, expires_at, created_at)
+         VALUES (?, ?, ?, ?, ?, ?)
+         RETURNING {INVITATION_COLUMNS}"
+    ))
+    .bind(Uuid::new_v4())
+    .bind(normalize_email(email))
+    .bind(token_hash)
+    .bind(invited_by)
+    .bind(expires_at)
+    .bind(Utc::now())
+    .fetch_all(&mut *conn)
+    .await
+    .and_then(returned_row)?;
+
+    for role_id in role_ids {
+        sqlx::query(
+            "INSERT INTO invitation_roles (invitation_id, role_id) VALUES (?, ?)
+             ON CONFLICT (invitation_id, role_id) DO NOTHING",
+        )
+        .bind(invitation.id)
+        .bind(role_id)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(invitation)
+}
+
+async fn find_invitation(
+    executor: impl SqliteExecutor<'_>,
+    id: Uuid,
+) -> RepositoryResult<Option<Invitation>> {
+    Ok(sqlx::query_as::<_, Invitation>(&format!(
+        "SELECT {INVITATION_COLUMNS} FROM invitations WHERE id = ?"
+    ))
+    .bind(id)
+    .fetch_optional(executor)
+    .await?)
+}
+
+async fn invitation_roles(
+    executor: impl SqliteExecutor<'_>,
+    id: Uuid,
+) -> RepositoryResult<Vec<Role>> {
+    Ok(sqlx::query_as::<_, Role>(&format!(
+        "SELECT {ROLE_COLUMNS} FROM roles
+         WHERE id IN (SELECT role_id FROM invitation_roles WHERE invitation_id = ?)
+         ORDER BY name"
+    ))
+    .bind(id)
+    .fetch_all(executor)
+    .await?)
+}
+
+#[async_trait]
+impl InvitationRepository for SqliteRepository {
+    async fn create(
+        &self,
+        email: &str,
+        token_hash: &str,
+        invited_by: Option<Uuid>,
+        expires_at: DateTime<Utc>,
+        role_ids: &[Uuid],
+    ) -> RepositoryResult<Invitation> {
+        let mut tx = self.pool.begin().await?;
+        let invitation =
+            insert_invitation(&mut tx, email, token_hash, invited_by, expires_at, role_ids).await?;
+        tx.commit().await?;
+
+        Ok(invitation)
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> RepositoryResult<Option<Invitation>> {
+        find_invitation(&self.pool, id).await
+    }
+
+    async fn find_by_token_hash(&self, token_hash: &str) -> RepositoryResult<Option<Invitation>> {
+        Ok(sqlx::query_as::<_, Invitation>(&format!(
+            "SELECT {INVITATION_COLUMNS} FROM invitations WHERE token_hash = ?"
+        ))
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<Invitation>> {
+        Ok(sqlx::query_as::<_, Invitation>(&format!(
+            "SELECT {INVITATION_COLUMNS} FROM invitations ORDER BY created_at DESC"
+        ))
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn get_roles(&self, id: Uuid) -> RepositoryResult<Vec<Role>> {
+        invitation_roles(&self.pool, id).await
+    }
+
+    async fn revoke(&self, id: Uuid) -> RepositoryResult<bool> {
+        let result = sqlx::query(
+            "UPDATE invitations SET revoked_at = ?
+             WHERE id = ? AND accepted_at IS NULL AND revoked_at IS NULL",
+        )
+        .bind(Utc::now())
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn accept(
+        &self,
+        id: Uuid,
+        username: &str,
+        password_hash: &str,
+    ) -> RepositoryResult<Option<User>> {
+        let mut tx = self.pool.begin().await?;
+        let Some(invitation) = find_invitation(&mut *tx, id).await? else {
+            return Ok(None);
+        };
+        let user = insert_user(&mut *tx, username, &invitation.email, password_hash).await?;
+
+        // If someone else claimed the invitation first, the user is rolled
+        // back with the transaction
+        let claimed = sqlx::query(
+            "UPDATE invitations SET accepted_at = ?1, accepted_user_id = ?2
+             WHERE id = ?3 AND accepted_at IS NULL AND revoked_at IS NULL AND expires_at > ?1",
+        )
+        .bind(Utc::now())
+        .bind(user.id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            > 0;
+        if !claimed {
+            return Ok(None);
+        }
+
+        let roles = invitation_roles(&mut *tx, id).await?;
+        let mut events = vec![NewEvent::user(EventType::UserCreated, &user)];
+        for role in &roles {
+            sqlx::query(
+                "INSERT INTO user_roles (user_id, role_id) VALUES (?, ?) ON CONFLICT DO NOTHING",
+            )
+            .bind(user.id)
+            .bind(role.id)
+            .execute(&mut *tx)
+            .await?;
+            events.push(NewEvent::role(EventType::UserRoleAssigned, &user, role));
+        }
+        record_events(&mut tx, &events).await?;
+        tx.commit().await?;
+
+        Ok(Some(user))
+    }
+}
+
+#[async_trait]
+impl ImpersonationRepository for SqliteRepository {
+    async fn create(
+        &self,
+        actor_id: Uuid,
+        actor_username: &str,
+        target: &User,
+        reason: &str,
+        expires_at: DateTime<Utc>,
+    ) -> RepositoryResult<ImpersonationEvent> {
+        Ok(sqlx::query_as::<_, ImpersonationEvent>(&format!(
+            "INSERT INTO impersonation_events
+                 (id, actor_id, actor_username, target_user_id, target_username, reason,
+                  expires_at, created_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+             RETURNING {IMPERSONATION_COLUMNS}"
+        ))
+        .bind(Uuid::new_v4())
+        .bind(actor_id)
+        .bind(actor_username)
+        .bind(target.id)
+        .bind(&target.username)
+        .bind(reason)
+        .bind(expires_at)
+        .bind(Utc::now())
+        .fetch_all(&self.pool)
+        .await
+        .and_then(returned_row)?)
+    }
+
+    async fn list(
+        &self,
+        target_user_id: Option<Uuid>,
+    ) -> RepositoryResult<Vec<ImpersonationEvent>> {
+        Ok(sqlx::query_as::<_, ImpersonationEvent>(&format!(
+            "SELECT {IMPERSONATION_COLUMNS}
+             FROM impersonation_events
+             WHERE ?1 IS NULL OR target_user_id = ?1
+             ORDER BY created_at DESC"
+        ))
+        .bind(target_user_id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+}
+
+/// Writes one imported account, returning the id of the user or invitation
+async fn import_account(
+    conn: &mut SqliteConnection,
+    account: &NewAccount<'_>,
+) -> RepositoryResult<Uuid> {
+    match *account {
+        NewAccount::User {
+            username,
+            email,
+            password_hash,
+            is_active,
+            role_ids,
+        } => {
+            let user =
+                insert_user_with_status(&mut *conn, username, email, password_hash, is_active)
+                    .await?;
+            let mut events = vec![NewEvent::user(EventType::UserCreated, &user)];
+            for role_id in distinct(role_ids) {
+                sqlx::query("INSERT INTO user_roles (user_id, role_id) VALUES (?, ?)")
+                    .bind(user.id)
+                    .bind(role_id)
+                    .execute(&mut *conn)
+                    .await?;
+                if let Some(role) = find_role(&mut *conn, role_id).await? {
+                    events.push(NewEvent::role(EventType::UserRoleAssigned, &user, &role));
+                }
+            }
+            record_events(conn, &events).await?;
+            Ok(user.id)
+        }
+        NewAccount::Invitation {
+            email,
+            token_hash,
+            invited_by,
+            expires_at,
+            role_ids,
+        } => {
+            let invitation =
+                insert_invitation(conn, email, token_hash, invited_by, expires_at, role_ids)
+                    .await?;
+            Ok(invitation.id)
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct ExportRow {
+    id: Uuid,
+    username: String,
+    email: String,
+    password_hash: String,
+    created_at: DateTime<Utc>,
+    is_active: bool,
+    /// JSON array of role names
+    roles: String,
+}
+
+impl TryFrom<ExportRow> for UserWithRoles {
+    type Error = RepositoryError;
+
+    fn try_from(row: ExportRow) -> RepositoryResult<Self> {
+        let mut roles: Vec<String> = serde_json::from_str(&row.roles)
+            .map_err(|e| RepositoryError::Database(sqlx::Error::Decode(e.into())))?;
+        roles.sort();
+        Ok(Self {
+            id: row.id,
+            username: row.username,
+            email: row.email,
+            password_hash: row.password_hash,
+            created_at: row.created_at,
+            is_active: row.is_active,
+            roles,
+        })
+    }
+}
+
+/// Binds the values a [`UserFilter`] rendered for SQLite refers to
+fn filter_arguments(params: &[FilterValue]) -> SqliteArguments<'static> {
+    let mut arguments = SqliteArguments::default();
+    for param in params {
+        match param {
+            FilterValue::Text(text) => arguments.add(text.clone()),
+            FilterValue::Uuid(id) => arguments.add(*id),
+            FilterValue::Bool(value) => arguments.add(*value),
+            FilterValue::Time(time) => arguments.add(*time),
+        }
+    }
+    arguments
+}
+
+#[derive(FromRow)]
+struct UserRoleRow {
+    user_id: Uuid,
+    #[sqlx(flatten)]
+    role: Role,
+}
+
+#[async_trait]
+impl ProvisioningRepository for SqliteRepository {
+    async fn import(&self, accounts: &[NewAccount<'_>]) -> Result<Vec<Uuid>, ImportError> {
+        let mut tx = self.pool.begin().await?;
+        let mut ids = Vec::with_capacity(accounts.len());
+        for (index, account) in accounts.iter().enumerate() {
+            let id = import_account(&mut tx, account)
+                .await
+                .map_err(|source| ImportError {
+                    index: Some(index),
+                    source,
+                })?;
+            ids.push(id);
+        }
+        tx.commit().await?;
+
+        Ok(ids)
+    }
+
+    async fn find_by_usernames_or_emails(
+        &self,
+        usernames: &[String],
+        emails: &[String],
+    ) -> RepositoryResult<Vec<User>> {
+        let sql = format!(
+            "SELECT {USER_COLUMNS} FROM users
+             WHERE username_folded IN ({}) OR email IN ({})",
+            placeholders(usernames.len()),
+            placeholders(emails.len())
+        );
+        let mut query = sqlx::query_as::<_, User>(&sql);
+        for username in usernames {
+            query = query.bind(fold_username(username));
+        }
+        for email in emails {
+            query = query.bind(normalize_email(email));
+        }
+
+        Ok(query.fetch_all(&self.pool).await?)
+    }
+
+    async fn search(
+        &self,
+        filter: &UserFilter,
+        offset: i64,
+        limit: i64,
+    ) -> RepositoryResult<(Vec<User>, i64)> {
+        let mut params = Vec::new();
+        let condition = filter.to_sql("?", &mut params);
+
+        let total: i64 = sqlx::query_scalar_with(
+            &format!("SELECT COUNT(*) FROM users u WHERE u.deleted_at IS NULL AND {condition}"),
+            filter_arguments(&params),
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        let mut arguments = filter_arguments(&params);
+        arguments.add(limit);
+        arguments.add(offset);
+        let users = sqlx::query_as_with::<_, User, _>(
+            &format!(
+                "SELECT u.id, u.username, u.email, u.password_hash, u.created_at, u.updated_at,
+                        u.is_active, u.version, u.deleted_at
+                 FROM users u
+                 WHERE u.deleted_at IS NULL AND {condition}
+                 ORDER BY u.created_at, u.id
+                 LIMIT ?{} OFFSET ?{}",
+                params.len() + 1,
+                params.len() + 2
+            ),
+            arguments,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok((users, total))
+    }
+
+    async fn roles_of(&self, user_ids: &[Uuid]) -> RepositoryResult<HashMap<Uuid, Vec<Role>>> {
+        let sql = format!(
+            "SELECT ur.user_id, r.id, r.name, r.description, r.created_at, r.version
+             FROM roles r
+             INNER JOIN user_roles ur ON r.id = ur.role_id
+             WHERE ur.user_id IN ({})
+             ORDER BY r.name",
+            placeholders(user_ids.len())
+        );
+        let mut query = sqlx::query_as::<_, UserRoleRow>(&sql);
+        for user_id in user_ids {
+            query = query.bind(user_id);
+        }
+
+        let mut roles: HashMap<Uuid, Vec<Role>> = HashMap::new();
+        for row in query.fetch_all(&self.pool).await? {
+            roles.entry(row.user_id).or_default().push(row.role);
+        }
+        Ok(roles)
+    }
+
+    async fn export_page(
+        &self,
+        after: Option<Uuid>,
+        limit: i64,
+    ) -> RepositoryResult<Vec<UserWithRoles>> {
+        let rows = sqlx::query_as::<_, ExportRow>(
+            "SELECT u.id, u.username, u.email, u.password_hash, u.created_at, u.is_active,
+                    (SELECT json_group_array(r.name)
+                     FROM roles r
+                     INNER JOIN user_roles ur ON r.id = ur.role_id
+                     WHERE ur.user_id = u.id) AS roles
+             FROM users u
+             WHERE u.deleted_at IS NULL AND (?1 IS NULL OR u.id > ?1)
+             ORDER BY u.id
+             LIMIT ?2",
+        )
+        .bind(after)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(UserWithRoles::try_from).collect()
+    }
+}
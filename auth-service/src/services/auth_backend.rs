@@ -0,0 +1,221 @@
+//! The ways `POST /auth/login` can check a username and password: against
+//! the hash stored with the local user, or by binding to an LDAP directory
+//! as the user. `AUTH_BACKENDS` sets which are tried, in order.
+
+use crate::config::{AuthBackendKind, Config, LdapConfig};
+use crate::models::User;
+use crate::repositories::Repositories;
+use crate::services::federation::{FederatedIdentity, Federation};
+use crate::services::verify_password;
+use async_trait::async_trait;
+use ldap3::{dn_escape, drive, Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use shared::AppError;
+use std::time::Duration;
+
+/// How long the directory has to answer
+const LDAP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Names linked LDAP identities in `user_identities`
+pub const LDAP_IDENTITY_SOURCE: &str = "ldap";
+
+/// LDAP result codes meaning the DN or password is wrong (RFC 4511)
+const LDAP_INVALID_CREDENTIALS: u32 = 49;
+const LDAP_NO_SUCH_OBJECT: u32 = 32;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthBackendError {
+    /// The backend could not check the password; the next one is tried
+    #[error("{0}")]
+    Unavailable(String),
+
+    /// Signing in failed for good, for instance because the user is
+    /// inactive; no other backend is tried
+    #[error(transparent)]
+    Failed(#[from] AppError),
+}
+
+#[async_trait]
+pub trait AuthBackend: Send + Sync {
+    /// The user `login` and `password` belong to, or None if this backend
+    /// does not accept them
+    async fn authenticate(
+        &self,
+        repos: &Repositories,
+        login: &str,
+        password: &str,
+    ) -> Result<Option<User>, AuthBackendError>;
+}
+
+/// The backends configured by `AUTH_BACKENDS`, in the order to try them
+pub fn auth_backends(config: &Config) -> Vec<Box<dyn AuthBackend + '_>> {
+    config
+        .auth_backends
+        .iter()
+        .filter_map(|kind| -> Option<Box<dyn AuthBackend + '_>> {
+            match kind {
+                AuthBackendKind::Local => Some(Box::new(LocalBackend)),
+                AuthBackendKind::Ldap => config
+                    .ldap
+                    .as_ref()
+                    .map(|ldap| Box::new(LdapBackend::new(ldap)) as Box<dyn AuthBackend>),
+            }
+        })
+        .collect()
+}
+
+/// Checks the password hash stored with the local user
+pub struct LocalBackend;
+
+#[async_trait]
+impl AuthBackend for LocalBackend {
+    async fn authenticate(
+        &self,
+        repos: &Repositories,
+        login: &str,
+        password: &str,
+    ) -> Result<Option<User>, AuthBackendError> {
+        // Find user by username, then by email; a username that looks like an
+        // email address wins over another account's email
+        let mut user = repos
+            .users
+            .find_by_username(login)
+            .await
+            .map_err(|e| AppError::Internal(format!("Database error: {e}")))?;
+        if user.is_none() && login.contains('@') {
+            user = repos
+                .users
+                .find_by_email(login)
+                .await
+                .map_err(|e| AppError::Internal(format!("Database error: {e}")))?;
+        }
+        let Some(user) = user else {
+            return Ok(None);
+        };
+
+        let verified = verify_password(password, &user.password_hash)
+            .map_err(|e| AppError::Internal(format!("Password verification error: {e}")))?;
+        Ok(verified.then_some(user))
+    }
+}
+
+/// Binds to the directory as the user, then reads their email address and
+/// groups from their entry. Users are linked to local ones like users of an
+/// OpenID Connect provider, with the directory vouching for their email.
+pub struct LdapBackend<'a> {
+    config: &'a LdapConfig,
+}
+
+impl<'a> LdapBackend<'a> {
+    pub fn new(config: &'a LdapConfig) -> Self {
+        Self { config }
+    }
+
+    async fn connect(&self) -> Result<Ldap, AuthBackendError> {
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(LDAP_TIMEOUT)
+            .set_starttls(self.config.starttls);
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.config.url)
+            .await
+            .map_err(|e| unavailable(&self.config.url, e))?;
+        drive!(conn);
+        ldap.with_timeout(LDAP_TIMEOUT);
+        Ok(ldap)
+    }
+
+    /// The user's identity, if the directory accepts the password
+    async fn bind(
+        &self,
+        ldap: &mut Ldap,
+        login: &str,
+        password: &str,
+    ) -> Result<Option<FederatedIdentity>, AuthBackendError> {
+        let url = &self.config.url;
+        // Escaped so the name cannot add RDNs of its own to the DN
+        let dn = self
+            .config
+            .user_dn_template
+            .replace("{username}", &dn_escape(login));
+
+        let bind = ldap
+            .simple_bind(&dn, password)
+            .await
+            .map_err(|e| unavailable(url, e))?;
+        match bind.rc {
+            0 => {}
+            LDAP_INVALID_CREDENTIALS | LDAP_NO_SUCH_OBJECT => return Ok(None),
+            _ => return Err(unavailable(url, bind)),
+        }
+
+        let attributes = [
+            self.config.email_attribute.as_str(),
+            self.config.groups_attribute.as_str(),
+        ];
+        let (entries, _) = ldap
+            .search(&dn, Scope::Base, "(objectClass=*)", attributes)
+            .await
+            .and_then(|result| result.success())
+            .map_err(|e| unavailable(url, e))?;
+        let Some(entry) = entries.into_iter().next() else {
+            return Err(AuthBackendError::Unavailable(format!(
+                "LDAP entry {dn} not found after binding as it"
+            )));
+        };
+        let entry = SearchEntry::construct(entry);
+
+        // Attribute names are case-insensitive
+        let values = |name: &str| {
+            entry
+                .attrs
+                .iter()
+                .find(|(attribute, _)| attribute.eq_ignore_ascii_case(name))
+                .map(|(_, values)| values.clone())
+                .unwrap_or_default()
+        };
+        Ok(Some(FederatedIdentity {
+            // DNs compare case-insensitively, so any capitalization of the
+            // username finds the same identity
+            subject: entry.dn.to_lowercase(),
+            username: Some(login.to_string()),
+            email: values(&self.config.email_attribute).into_iter().next(),
+            email_verified: self.config.trust_email,
+            groups: values(&self.config.groups_attribute)
+                .iter()
+                .map(|group| group.to_lowercase())
+                .collect(),
+        }))
+    }
+}
+
+fn unavailable(url: &str, e: impl std::fmt::Display) -> AuthBackendError {
+    AuthBackendError::Unavailable(format!("LDAP directory {url}: {e}"))
+}
+
+#[async_trait]
+impl AuthBackend for LdapBackend<'_> {
+    async fn authenticate(
+        &self,
+        repos: &Repositories,
+        login: &str,
+        password: &str,
+    ) -> Result<Option<User>, AuthBackendError> {
+        // A bind without a password is anonymous, and would succeed for any DN
+        if login.is_empty() || password.is_empty() {
+            return Ok(None);
+        }
+
+        let mut ldap = self.connect().await?;
+        let identity = self.bind(&mut ldap, login, password).await;
+        // The outcome is already known; a failed unbind changes nothing
+        let _ = ldap.unbind().await;
+        let Some(identity) = identity? else {
+            return Ok(None);
+        };
+
+        let federation = Federation {
+            source: LDAP_IDENTITY_SOURCE,
+            group_roles: &self.config.group_roles,
+            auto_provision: self.config.auto_provision,
+        };
+        Ok(Some(federation.sign_in(repos, &identity).await?))
+    }
+}
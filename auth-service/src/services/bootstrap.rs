@@ -0,0 +1,144 @@
+use crate::config::BootstrapAdmin;
+use crate::models::{Role, User};
+use crate::repositories::{Repositories, RepositoryError};
+use crate::services::{generate_opaque_token, hash_opaque_token, hash_password};
+use chrono::{Duration, Utc};
+use shared::{AppError, AppResult};
+
+/// How long a printed setup token can be exchanged for the first admin
+pub const SETUP_TOKEN_TTL_HOURS: i64 = 24;
+
+const ADMIN_ROLE: &str = "admin";
+
+/// What the startup bootstrap did
+#[derive(Debug)]
+pub enum BootstrapOutcome {
+    /// A user already holds the admin role; nothing was changed
+    AdminExists,
+    /// The admin from the configuration was created
+    AdminCreated(User),
+    /// No admin exists; this token must be exchanged at `POST /auth/setup`
+    SetupTokenIssued(String),
+}
+
+/// Whether any user holds the admin role directly
+pub async fn admin_exists(repos: &Repositories) -> AppResult<bool> {
+    let role = find_admin_role(repos).await?;
+
+    repos
+        .assignments
+        .role_has_users(role.id)
+        .await
+        .map_err(|e| AppError::Internal(format!("Database error: {e}")))
+}
+
+/// Ensures an admin exists: creates the configured one, or issues a setup
+/// token (superseding any earlier one) when no admin is configured.
+pub async fn bootstrap_admin(
+    repos: &Repositories,
+    admin: Option<&BootstrapAdmin>,
+) -> AppResult<BootstrapOutcome> {
+    if admin_exists(repos).await? {
+        repos
+            .setup_tokens
+            .clear()
+            .await
+            .map_err(|e| AppError::Internal(format!("Failed to clear setup token: {e}")))?;
+        return Ok(BootstrapOutcome::AdminExists);
+    }
+
+    if let Some(admin) = admin {
+        let user = create_admin(repos, &admin.username, &admin.email, &admin.password).await?;
+        return Ok(BootstrapOutcome::AdminCreated(user));
+    }
+
+    let token = generate_opaque_token();
+    repos
+        .setup_tokens
+        .replace(
+            &hash_opaque_token(&token),
+            Utc::now() + Duration::hours(SETUP_TOKEN_TTL_HOURS),
+        )
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to store setup token: {e}")))?;
+
+    Ok(BootstrapOutcome::SetupTokenIssued(token))
+}
+
+/// Checks the new admin's credentials and that the username and email are
+/// free, so `POST /auth/setup` can refuse a request before spending its token
+pub async fn validate_new_admin(
+    repos: &Repositories,
+    username: &str,
+    email: &str,
+    password: &str,
+) -> AppResult<()> {
+    if username.is_empty() || email.is_empty() || password.is_empty() {
+        return Err(AppError::BadRequest(
+            "Username, email, and password are required".to_string(),
+        ));
+    }
+
+    if password.len() < 8 {
+        return Err(AppError::BadRequest(
+            "Password must be at least 8 characters long".to_string(),
+        ));
+    }
+
+    if repos
+        .users
+        .find_by_username(username)
+        .await
+        .map_err(|e| AppError::Internal(format!("Database error: {e}")))?
+        .is_some()
+    {
+        return Err(AppError::Conflict("Username already exists".to_string()));
+    }
+
+    if repos
+        .users
+        .find_by_email(email)
+        .await
+        .map_err(|e| AppError::Internal(format!("Database error: {e}")))?
+        .is_some()
+    {
+        return Err(AppError::Conflict("Email already exists".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Creates a user holding the admin role
+pub async fn create_admin(
+    repos: &Repositories,
+    username: &str,
+    email: &str,
+    password: &str,
+) -> AppResult<User> {
+    validate_new_admin(repos, username, email, password).await?;
+    let role = find_admin_role(repos).await?;
+
+    let password_hash = hash_password(password)
+        .map_err(|e| AppError::Internal(format!("Failed to hash password: {e}")))?;
+
+    // The checks above can race a concurrent insert; the constraints cannot
+    repos
+        .users
+        .create_with_roles(username, email, &password_hash, &[role.id])
+        .await
+        .map_err(|e| match e {
+            RepositoryError::UniqueViolation(_) => {
+                AppError::Conflict("Username or email already exists".to_string())
+            }
+            e => AppError::Internal(format!("Failed to create user: {e}")),
+        })
+}
+
+async fn find_admin_role(repos: &Repositories) -> AppResult<Role> {
+    repos
+        .roles
+        .find_by_name(ADMIN_ROLE)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to find admin role: {e}")))?
+        .ok_or_else(|| AppError::Internal("Default admin role not found".to_string()))
+}
@@ -0,0 +1,84 @@
+//! Signed JWT client assertions (RFC 7523 section 2.2): a service account
+//! proves itself by signing a short-lived JWT with a private key whose public
+//! half is registered as one of its credentials.
+
+use chrono::Utc;
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// `client_assertion_type` for signed JWT assertions
+pub const JWT_BEARER_ASSERTION_TYPE: &str =
+    "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
+
+/// Assertions expiring further ahead than this are refused, which bounds how
+/// long a leaked one can be replayed
+pub const ASSERTION_MAX_LIFETIME_SECONDS: i64 = 300;
+
+#[derive(Debug, Deserialize)]
+pub struct AssertionClaims {
+    pub sub: Uuid,
+    pub exp: i64,
+    /// Unique per assertion, so a used one can be told from a replay
+    #[serde(default)]
+    pub jti: Option<String>,
+}
+
+/// Parses a public key for `algorithm`, which must be asymmetric
+pub fn public_key(pem: &str, algorithm: &str) -> Result<(Algorithm, DecodingKey), String> {
+    let algorithm =
+        Algorithm::from_str(algorithm).map_err(|_| format!("Unknown algorithm '{algorithm}'"))?;
+    let key = match algorithm {
+        Algorithm::RS256
+        | Algorithm::RS384
+        | Algorithm::RS512
+        | Algorithm::PS256
+        | Algorithm::PS384
+        | Algorithm::PS512 => DecodingKey::from_rsa_pem(pem.as_bytes()),
+        Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(pem.as_bytes()),
+        Algorithm::EdDSA => DecodingKey::from_ed_pem(pem.as_bytes()),
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+            return Err(format!("{algorithm:?} is not a public key algorithm"))
+        }
+    }
+    .map_err(|_| format!("public_key is not a PEM-encoded {algorithm:?} public key"))?;
+    Ok((algorithm, key))
+}
+
+/// The claims of an assertion, read without checking the signature; only
+/// trust them once `verify_assertion` has accepted the assertion
+pub fn read_assertion(assertion: &str) -> Option<AssertionClaims> {
+    let mut validation = Validation::default();
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    validation.validate_aud = false;
+    validation.required_spec_claims.clear();
+    decode::<AssertionClaims>(assertion, &DecodingKey::from_secret(&[]), &validation)
+        .ok()
+        .map(|data| data.claims)
+}
+
+/// Whether `assertion` is signed with the key, names `client_id` as both
+/// issuer and subject, is addressed to `audience`, is current and has a `jti`
+pub fn verify_assertion(
+    assertion: &str,
+    pem: &str,
+    algorithm: &str,
+    client_id: Uuid,
+    audience: &str,
+) -> bool {
+    let Ok((algorithm, key)) = public_key(pem, algorithm) else {
+        return false;
+    };
+    let mut validation = Validation::new(algorithm);
+    validation.set_required_spec_claims(&["exp", "iss", "sub", "aud"]);
+    validation.set_issuer(&[client_id.to_string()]);
+    validation.set_audience(&[audience]);
+    validation.sub = Some(client_id.to_string());
+
+    decode::<AssertionClaims>(assertion, &key, &validation).is_ok_and(|data| {
+        data.claims.exp <= Utc::now().timestamp() + ASSERTION_MAX_LIFETIME_SECONDS
+            && data.claims.jti.is_some_and(|jti| !jti.is_empty())
+    })
+}
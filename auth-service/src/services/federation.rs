@@ -0,0 +1,248 @@
+//! Signing in users whose identity an external source vouches for, such as
+//! an OpenID Connect provider or an LDAP directory: the external identity is
+//! linked to a local user, created on first sign-in if allowed, and the
+//! user's groups there are mapped to roles here.
+
+use crate::models::{User, UserIdentity};
+use crate::repositories::{Repositories, RepositoryError};
+use crate::services::{generate_opaque_token, hash_password};
+use log::warn;
+use shared::{AppError, AppResult};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// Attempts at finding a free username for a new user
+const USERNAME_ATTEMPTS: usize = 3;
+
+/// Users holding this role are never linked to an identity by email address
+const ADMIN_ROLE: &str = "admin";
+
+/// Who an external source says signed in
+#[derive(Debug, Clone)]
+pub struct FederatedIdentity {
+    /// Stable identifier of the user at the source
+    pub subject: String,
+    pub username: Option<String>,
+    pub email: Option<String>,
+    /// Whether the source vouches that the user controls `email`
+    pub email_verified: bool,
+    pub groups: Vec<String>,
+}
+
+/// How identities from one source are turned into local users
+pub struct Federation<'a> {
+    /// Names the source in linked identities and messages
+    pub source: &'a str,
+    /// Roles granted for each group, as (group, role name) pairs
+    pub group_roles: &'a [(String, String)],
+    /// Create users signing in for the first time
+    pub auto_provision: bool,
+}
+
+impl Federation<'_> {
+    /// The active local user `identity` signs in as, with their mapped roles
+    /// brought up to date
+    pub async fn sign_in(
+        &self,
+        repos: &Repositories,
+        identity: &FederatedIdentity,
+    ) -> AppResult<User> {
+        let (user, linked) = self.linked_user(repos, identity).await?;
+        if !user.is_active {
+            return Err(AppError::Forbidden("User account is inactive".to_string()));
+        }
+        self.sync_group_roles(repos, user.id, &identity.groups)
+            .await?;
+        repos
+            .identities
+            .touch(linked.id)
+            .await
+            .map_err(|e| AppError::Internal(format!("Failed to record sign-in: {e}")))?;
+        Ok(user)
+    }
+
+    /// The local user linked to `identity`. On a first sign-in the identity
+    /// is linked to the user with the same email address if the source has
+    /// verified it and the user is not an admin, or else to a new user if
+    /// the source allows it.
+    async fn linked_user(
+        &self,
+        repos: &Repositories,
+        identity: &FederatedIdentity,
+    ) -> AppResult<(User, UserIdentity)> {
+        let linked = repos
+            .identities
+            .find(self.source, &identity.subject)
+            .await
+            .map_err(|e| AppError::Internal(format!("Database error: {e}")))?;
+        if let Some(linked) = linked {
+            let user = repos
+                .users
+                .find_by_id(linked.user_id)
+                .await
+                .map_err(|e| AppError::Internal(format!("Database error: {e}")))?
+                .ok_or_else(|| AppError::Forbidden("User account has been deleted".to_string()))?;
+            return Ok((user, linked));
+        }
+
+        let existing = match (&identity.email, identity.email_verified) {
+            (Some(email), true) => repos
+                .users
+                .find_by_email(email)
+                .await
+                .map_err(|e| AppError::Internal(format!("Database error: {e}")))?,
+            _ => None,
+        };
+        let user = match existing {
+            // Whoever controls the address at the source would control the
+            // admin account; an admin signs in with their own credentials
+            Some(user) if self.is_admin(repos, user.id).await? => {
+                return Err(AppError::Forbidden(format!(
+                    "The account with this email address is an administrator's, which {} \
+                     identities are not linked to",
+                    self.source
+                )))
+            }
+            Some(user) => user,
+            None if self.auto_provision => self.provision_user(repos, identity).await?,
+            None => {
+                return Err(AppError::Forbidden(format!(
+                    "No account is linked to this {} identity",
+                    self.source
+                )))
+            }
+        };
+
+        let linked = repos
+            .identities
+            .create(
+                user.id,
+                self.source,
+                &identity.subject,
+                identity.email.as_deref(),
+            )
+            .await
+            .map_err(|e| match e {
+                RepositoryError::UniqueViolation(_) => AppError::Conflict(
+                    "This identity was linked by a concurrent sign-in; sign in again".to_string(),
+                ),
+                e => AppError::Internal(format!("Failed to link identity: {e}")),
+            })?;
+        Ok((user, linked))
+    }
+
+    /// Whether the user holds the admin role, directly or through a group
+    async fn is_admin(&self, repos: &Repositories, user_id: Uuid) -> AppResult<bool> {
+        let roles = repos
+            .assignments
+            .get_effective_user_roles(user_id)
+            .await
+            .map_err(|e| AppError::Internal(format!("Failed to get user roles: {e}")))?;
+        Ok(roles.iter().any(|role| role.name == ADMIN_ROLE))
+    }
+
+    /// Creates a user for a first sign-in, with the default `user` role and
+    /// an unguessable password; they sign in through the source
+    async fn provision_user(
+        &self,
+        repos: &Repositories,
+        identity: &FederatedIdentity,
+    ) -> AppResult<User> {
+        let email = identity.email.as_deref().ok_or_else(|| {
+            AppError::BadRequest(format!(
+                "{} did not share an email address, which new accounts need",
+                self.source
+            ))
+        })?;
+        let username = identity
+            .username
+            .clone()
+            .unwrap_or_else(|| email.split('@').next().unwrap_or(email).to_string());
+
+        let password_hash = hash_password(&generate_opaque_token())
+            .map_err(|e| AppError::Internal(format!("Failed to hash password: {e}")))?;
+        let user_role = repos
+            .roles
+            .find_by_name("user")
+            .await
+            .map_err(|e| AppError::Internal(format!("Failed to find user role: {e}")))?
+            .ok_or_else(|| AppError::Internal("Default user role not found".to_string()))?;
+
+        // A local account may already use the name; fall back to suffixed ones
+        let mut candidate = username.clone();
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match repos
+                .users
+                .create_with_roles(&candidate, email, &password_hash, &[user_role.id])
+                .await
+            {
+                Ok(user) => return Ok(user),
+                Err(e)
+                    if e.is_unique_violation_on("users.username")
+                        && attempts < USERNAME_ATTEMPTS =>
+                {
+                    let suffix = generate_opaque_token();
+                    candidate = format!("{username}-{}", suffix[..6].to_ascii_lowercase());
+                }
+                // An unverified address must not take over the account using it
+                Err(e) if e.is_unique_violation_on("users.email") => {
+                    return Err(AppError::Conflict(format!(
+                        "An account with this email address already exists, and {} has not \
+                         verified the address",
+                        self.source
+                    )))
+                }
+                Err(e) if e.is_unique_violation_on("users.username") => {
+                    return Err(AppError::Conflict("Username already exists".to_string()))
+                }
+                Err(e) => return Err(AppError::Internal(format!("Failed to create user: {e}"))),
+            }
+        }
+    }
+
+    /// Grants the roles mapped to the user's groups and removes the other
+    /// mapped roles. Roles no group maps to are left alone, so they can
+    /// still be assigned by hand.
+    async fn sync_group_roles(
+        &self,
+        repos: &Repositories,
+        user_id: Uuid,
+        groups: &[String],
+    ) -> AppResult<()> {
+        let mut granted: BTreeMap<&str, bool> = BTreeMap::new();
+        for (group, role) in self.group_roles {
+            *granted.entry(role.as_str()).or_default() |= groups.contains(group);
+        }
+
+        for (role_name, grant) in granted {
+            let role = repos
+                .roles
+                .find_by_name(role_name)
+                .await
+                .map_err(|e| AppError::Internal(format!("Failed to find role: {e}")))?;
+            let Some(role) = role else {
+                warn!(
+                    "Role '{role_name}' mapped from {} groups does not exist",
+                    self.source
+                );
+                continue;
+            };
+            if grant {
+                repos
+                    .assignments
+                    .assign_role_to_user(user_id, role.id)
+                    .await
+                    .map_err(|e| AppError::Internal(format!("Failed to assign role: {e}")))?;
+            } else {
+                repos
+                    .assignments
+                    .remove_role_from_user(user_id, role.id)
+                    .await
+                    .map_err(|e| AppError::Internal(format!("Failed to remove role: {e}")))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,45 @@
+use crate::repositories::{Repositories, RepositoryResult};
+use shared::{Claims, PrincipalType};
+use uuid::Uuid;
+
+/// Whether a correctly signed, unexpired token may still be used: its
+/// session (if any) must be live and its subject, and every admin or service
+/// acting through `act`, must exist and be active. Deactivating or deleting a
+/// user or service account therefore cuts off every token issued to them, or
+/// exchanged by them, at once.
+pub async fn is_token_active(repos: &Repositories, claims: &Claims) -> RepositoryResult<bool> {
+    if let Some(sid) = claims.sid {
+        if !repos.sessions.touch(sid).await? {
+            return Ok(false);
+        }
+    }
+
+    if !is_principal_active(repos, claims.sub, claims.principal_type).await? {
+        return Ok(false);
+    }
+    for actor in claims.act.iter().flat_map(|actor| actor.chain()) {
+        if !is_principal_active(repos, actor.sub, actor.principal_type).await? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+async fn is_principal_active(
+    repos: &Repositories,
+    id: Uuid,
+    principal_type: PrincipalType,
+) -> RepositoryResult<bool> {
+    Ok(match principal_type {
+        PrincipalType::User => repos
+            .users
+            .find_by_id(id)
+            .await?
+            .is_some_and(|user| user.is_active),
+        PrincipalType::Service => repos
+            .service_accounts
+            .find_by_id(id)
+            .await?
+            .is_some_and(|account| account.is_active),
+    })
+}
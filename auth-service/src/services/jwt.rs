@@ -1,11 +1,15 @@
 use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
-use shared::Claims as SharedClaims;
+use jsonwebtoken::{encode, EncodingKey, Header, Validation};
+use shared::{decode_claims, Actor, Claims as SharedClaims, PreviousSecret, PrincipalType};
 use uuid::Uuid;
 
 /// Re-export shared Claims for convenience
 pub use shared::Claims;
 
+/// How long a user's access token lasts, and so how long the previous
+/// secret must stay accepted after `JWT_SECRET` is rotated
+pub const USER_TOKEN_TTL_SECONDS: i64 = 24 * 3600;
+
 /// Helper function to create Claims with expiration set to 24 hours from now
 pub fn create_claims(
     user_id: Uuid,
@@ -14,7 +18,7 @@ pub fn create_claims(
     permissions: Vec<String>,
 ) -> SharedClaims {
     let now = Utc::now();
-    let exp = now + Duration::hours(24); // Token expires in 24 hours
+    let exp = now + Duration::seconds(USER_TOKEN_TTL_SECONDS);
 
     SharedClaims {
         sub: user_id,
@@ -23,6 +27,97 @@ pub fn create_claims(
         permissions,
         exp: exp.timestamp(),
         iat: now.timestamp(),
+        sid: None,
+        act: None,
+        aud: None,
+        pv: None,
+        principal_type: PrincipalType::User,
+    }
+}
+
+/// Create Claims for `user_id` on behalf of `actor`, expiring after `ttl`
+pub fn create_impersonation_claims(
+    user_id: Uuid,
+    username: String,
+    roles: Vec<String>,
+    permissions: Vec<String>,
+    actor: Actor,
+    ttl: Duration,
+) -> SharedClaims {
+    let now = Utc::now();
+
+    SharedClaims {
+        sub: user_id,
+        username,
+        roles,
+        permissions,
+        exp: (now + ttl).timestamp(),
+        iat: now.timestamp(),
+        sid: None,
+        act: Some(actor),
+        aud: None,
+        pv: None,
+        principal_type: PrincipalType::User,
+    }
+}
+
+/// How long a service account token lasts; clients get a new one with their
+/// credential rather than holding a session
+pub const SERVICE_TOKEN_TTL_SECONDS: i64 = 3600;
+
+/// Create Claims for a service account
+pub fn create_service_claims(
+    service_account_id: Uuid,
+    name: String,
+    roles: Vec<String>,
+    permissions: Vec<String>,
+) -> SharedClaims {
+    let now = Utc::now();
+
+    SharedClaims {
+        sub: service_account_id,
+        username: name,
+        roles,
+        permissions,
+        exp: (now + Duration::seconds(SERVICE_TOKEN_TTL_SECONDS)).timestamp(),
+        iat: now.timestamp(),
+        sid: None,
+        act: None,
+        aud: None,
+        pv: None,
+        principal_type: PrincipalType::Service,
+    }
+}
+
+/// Longest an exchanged token lasts; it never outlives the token it was
+/// exchanged for
+pub const EXCHANGED_TOKEN_TTL_SECONDS: i64 = 300;
+
+/// Create Claims for `actor` to act as the subject of `subject` at
+/// `audience`, holding only `permissions`. Roles are left out so that the
+/// token grants nothing beyond them. It shares the subject's session, so it
+/// is revoked along with it.
+pub fn create_exchanged_claims(
+    subject: &SharedClaims,
+    actor: Actor,
+    audience: String,
+    permissions: Vec<String>,
+) -> SharedClaims {
+    let now = Utc::now();
+    let exp = (now + Duration::seconds(EXCHANGED_TOKEN_TTL_SECONDS)).timestamp();
+
+    SharedClaims {
+        sub: subject.sub,
+        username: subject.username.clone(),
+        roles: Vec::new(),
+        permissions,
+        exp: exp.min(subject.exp),
+        iat: now.timestamp(),
+        sid: subject.sid,
+        act: Some(actor),
+        aud: Some(audience),
+        pv: None,
+        principal_type: subject.principal_type,
     }
 }
 
@@ -37,14 +132,24 @@ pub fn generate_token(
     encode(&header, claims, &encoding_key)
 }
 
-/// Validate a JWT token and extract claims
+/// Validate a JWT token and extract claims. Tokens restricted to an
+/// audience are accepted too; callers check `aud` where it matters.
 pub fn validate_token(
     token: &str,
     secret: &str,
 ) -> Result<SharedClaims, jsonwebtoken::errors::Error> {
-    let decoding_key = DecodingKey::from_secret(secret.as_ref());
-    let validation = Validation::default();
+    validate_token_with_previous(token, secret, None)
+}
+
+/// Like `validate_token`, also accepting tokens signed with the secret
+/// `secret` replaced while its grace window lasts
+pub fn validate_token_with_previous(
+    token: &str,
+    secret: &str,
+    previous: Option<&PreviousSecret>,
+) -> Result<SharedClaims, jsonwebtoken::errors::Error> {
+    let mut validation = Validation::default();
+    validation.validate_aud = false;
 
-    let token_data = decode::<SharedClaims>(token, &decoding_key, &validation)?;
-    Ok(token_data.claims)
+    decode_claims(token, secret, previous, &validation)
 }
@@ -0,0 +1,74 @@
+//! Locks a login name out after repeated wrong passwords, so passwords cannot
+//! be guessed at the speed of the login endpoint. Counts are kept in memory,
+//! per instance.
+
+use crate::models::fold_username;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Wrong passwords allowed for one login name before it is locked
+pub const LOGIN_MAX_FAILURES: u32 = 5;
+
+/// Seconds a login name stays locked, counted from its first wrong password
+pub const LOGIN_LOCKOUT_SECONDS: u64 = 900;
+
+struct Failures {
+    count: u32,
+    since: Instant,
+}
+
+pub struct LoginThrottle {
+    failures: Mutex<HashMap<String, Failures>>,
+    max_failures: u32,
+    window: Duration,
+}
+
+impl Default for LoginThrottle {
+    fn default() -> Self {
+        Self::new(
+            LOGIN_MAX_FAILURES,
+            Duration::from_secs(LOGIN_LOCKOUT_SECONDS),
+        )
+    }
+}
+
+impl LoginThrottle {
+    pub fn new(max_failures: u32, window: Duration) -> Self {
+        Self {
+            failures: Mutex::default(),
+            max_failures,
+            window,
+        }
+    }
+
+    /// Seconds until `login` may be tried again, if it is locked
+    pub fn locked_for(&self, login: &str) -> Option<u64> {
+        let failures = self.failures.lock().expect("login throttle lock poisoned");
+        let entry = failures.get(&fold_username(login))?;
+        let remaining = self.window.checked_sub(entry.since.elapsed())?;
+        (entry.count >= self.max_failures).then(|| remaining.as_secs().max(1))
+    }
+
+    /// Counts a wrong password for `login`
+    pub fn record_failure(&self, login: &str) {
+        let mut failures = self.failures.lock().expect("login throttle lock poisoned");
+        // Forget names whose window has passed, so the map stays small
+        failures.retain(|_, entry| entry.since.elapsed() < self.window);
+        failures
+            .entry(fold_username(login))
+            .or_insert_with(|| Failures {
+                count: 0,
+                since: Instant::now(),
+            })
+            .count += 1;
+    }
+
+    /// Forgets the wrong passwords for `login` after it signs in
+    pub fn clear(&self, login: &str) {
+        self.failures
+            .lock()
+            .expect("login throttle lock poisoned")
+            .remove(&fold_username(login));
+    }
+}
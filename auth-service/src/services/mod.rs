@@ -1,5 +1,27 @@
+pub mod auth_backend;
+pub mod bootstrap;
+pub mod client_assertion;
+pub mod federation;
+pub mod introspection;
 pub mod jwt;
+pub mod login_throttle;
+pub mod oidc;
 pub mod password;
+pub mod scim_filter;
+pub mod token;
+pub mod webhooks;
 
-pub use jwt::{create_claims, generate_token, validate_token};
-pub use password::{hash_password, verify_password};
+pub use bootstrap::{bootstrap_admin, BootstrapOutcome};
+pub use introspection::is_token_active;
+pub use jwt::{
+    create_claims, create_exchanged_claims, create_impersonation_claims, create_service_claims,
+    generate_token, validate_token, validate_token_with_previous, EXCHANGED_TOKEN_TTL_SECONDS,
+    SERVICE_TOKEN_TTL_SECONDS, USER_TOKEN_TTL_SECONDS,
+};
+pub use login_throttle::LoginThrottle;
+pub use password::{hash_password, is_supported_password_hash, verify_password, PasswordError};
+pub use scim_filter::ScimFilter;
+pub use token::{
+    generate_opaque_token, generate_user_code, hash_opaque_token, normalize_user_code,
+};
+pub use webhooks::WebhookDispatcher;
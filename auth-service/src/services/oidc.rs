@@ -0,0 +1,338 @@
+//! Sign-in through upstream OpenID Connect providers, with the authorization
+//! code flow and PKCE (RFC 7636). Each provider's metadata and signing keys
+//! are discovered from its issuer and cached.
+
+use crate::config::OidcProvider;
+use crate::services::federation::FederatedIdentity;
+use crate::services::generate_opaque_token;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use shared::correlation::Correlated;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// How long an identity provider has to answer
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long discovered metadata and keys are used before fetching them again
+const METADATA_TTL: Duration = Duration::from_secs(60 * 60);
+/// Keys are fetched early for a token signed with an unknown key, which
+/// happens after the provider rotates them, but no more often than this
+const KEY_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, thiserror::Error)]
+pub enum OidcError {
+    #[error("identity provider request failed: {0}")]
+    Provider(String),
+
+    #[error("identity provider did not answer in time: {0}")]
+    Timeout(String),
+
+    /// The provider refused the authorization code, e.g. as already used
+    #[error("identity provider refused the sign-in: {0}")]
+    Rejected(String),
+
+    #[error("invalid ID token: {0}")]
+    InvalidIdToken(String),
+}
+
+impl OidcError {
+    /// A request to the provider that could not be completed
+    fn request(what: String, e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Self::Timeout(format!("{what}: {e}"))
+        } else {
+            Self::Provider(format!("{what}: {e}"))
+        }
+    }
+}
+
+/// The parts of the provider metadata (OpenID Connect Discovery 1.0) used here
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+struct Discovered {
+    metadata: ProviderMetadata,
+    keys: JwkSet,
+    fetched_at: Instant,
+}
+
+/// Where to send the user to sign in, and what to keep until they return
+pub struct AuthorizationRequest {
+    pub url: String,
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+/// Talks to the configured providers; shared by all workers
+pub struct OidcClient {
+    http: reqwest::Client,
+    discovered: RwLock<HashMap<String, Arc<Discovered>>>,
+}
+
+impl Default for OidcClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OidcClient {
+    pub fn new() -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("Failed to build OIDC HTTP client");
+        Self {
+            http,
+            discovered: RwLock::default(),
+        }
+    }
+
+    /// The provider's metadata and keys, fetched again once older than
+    /// `max_age`
+    async fn discover(
+        &self,
+        provider: &OidcProvider,
+        max_age: Duration,
+    ) -> Result<Arc<Discovered>, OidcError> {
+        let cached = self
+            .discovered
+            .read()
+            .expect("OIDC cache lock poisoned")
+            .get(&provider.name)
+            .cloned();
+        if let Some(discovered) = cached.filter(|d| d.fetched_at.elapsed() < max_age) {
+            return Ok(discovered);
+        }
+
+        let url = format!("{}/.well-known/openid-configuration", provider.issuer);
+        let metadata: ProviderMetadata = self.get_json(&url).await?;
+        // Discovery section 4.3: the metadata must be for the configured issuer
+        if metadata.issuer.trim_end_matches('/') != provider.issuer {
+            return Err(OidcError::Provider(format!(
+                "metadata names issuer '{}' instead of '{}'",
+                metadata.issuer, provider.issuer
+            )));
+        }
+        let keys: JwkSet = self.get_json(&metadata.jwks_uri).await?;
+
+        let discovered = Arc::new(Discovered {
+            metadata,
+            keys,
+            fetched_at: Instant::now(),
+        });
+        self.discovered
+            .write()
+            .expect("OIDC cache lock poisoned")
+            .insert(provider.name.clone(), discovered.clone());
+        Ok(discovered)
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T, OidcError> {
+        self.http
+            .get(url)
+            .correlated()
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| OidcError::request(format!("GET {url}"), e))?
+            .json()
+            .await
+            .map_err(|e| OidcError::request(format!("GET {url}"), e))
+    }
+
+    /// Starts a sign-in: the provider URL to redirect the user to, with a
+    /// fresh state, nonce and PKCE challenge
+    pub async fn authorization_request(
+        &self,
+        provider: &OidcProvider,
+        redirect_uri: &str,
+    ) -> Result<AuthorizationRequest, OidcError> {
+        let discovered = self.discover(provider, METADATA_TTL).await?;
+
+        let state = generate_opaque_token();
+        let nonce = generate_opaque_token();
+        let code_verifier = generate_opaque_token();
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+        let url = reqwest::Url::parse_with_params(
+            &discovered.metadata.authorization_endpoint,
+            [
+                ("response_type", "code"),
+                ("client_id", provider.client_id.as_str()),
+                ("redirect_uri", redirect_uri),
+                ("scope", provider.scopes.as_str()),
+                ("state", state.as_str()),
+                ("nonce", nonce.as_str()),
+                ("code_challenge", code_challenge.as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| OidcError::Provider(format!("invalid authorization_endpoint: {e}")))?;
+
+        Ok(AuthorizationRequest {
+            url: url.into(),
+            state,
+            nonce,
+            code_verifier,
+        })
+    }
+
+    /// Redeems the authorization code the user came back with, returning the
+    /// verified identity
+    pub async fn complete(
+        &self,
+        provider: &OidcProvider,
+        code: &str,
+        redirect_uri: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<FederatedIdentity, OidcError> {
+        let discovered = self.discover(provider, METADATA_TTL).await?;
+        let token_endpoint = &discovered.metadata.token_endpoint;
+
+        let response = self
+            .http
+            .post(token_endpoint)
+            .basic_auth(&provider.client_id, Some(&provider.client_secret))
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", redirect_uri),
+                ("code_verifier", code_verifier),
+            ])
+            .correlated()
+            .send()
+            .await
+            .map_err(|e| OidcError::request(format!("POST {token_endpoint}"), e))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            let message = format!("POST {token_endpoint}: {status}: {body}");
+            return Err(if status.is_client_error() {
+                OidcError::Rejected(message)
+            } else {
+                OidcError::Provider(message)
+            });
+        }
+        let id_token = response
+            .json::<TokenResponse>()
+            .await
+            .map_err(|e| OidcError::request(format!("POST {token_endpoint}"), e))?
+            .id_token
+            .ok_or_else(|| OidcError::Provider("token response has no id_token".to_string()))?;
+
+        let claims = self.verify_id_token(provider, &id_token, nonce).await?;
+        identity_from_claims(provider, &claims)
+    }
+
+    /// Checks the ID token's signature against the provider's keys, and its
+    /// issuer, audience, expiry and nonce
+    async fn verify_id_token(
+        &self,
+        provider: &OidcProvider,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<Map<String, Value>, OidcError> {
+        let invalid = |e: jsonwebtoken::errors::Error| OidcError::InvalidIdToken(e.to_string());
+        let header = decode_header(id_token).map_err(invalid)?;
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(OidcError::InvalidIdToken(format!(
+                "{:?} is not a public key algorithm",
+                header.alg
+            )));
+        }
+
+        let mut discovered = self.discover(provider, METADATA_TTL).await?;
+        let find_key = |keys: &JwkSet| match &header.kid {
+            Some(kid) => keys.find(kid).cloned(),
+            None if keys.keys.len() == 1 => keys.keys.first().cloned(),
+            None => None,
+        };
+        let jwk = match find_key(&discovered.keys) {
+            Some(jwk) => jwk,
+            None => {
+                discovered = self.discover(provider, KEY_REFRESH_INTERVAL).await?;
+                find_key(&discovered.keys).ok_or_else(|| {
+                    OidcError::InvalidIdToken("signed with an unknown key".to_string())
+                })?
+            }
+        };
+        let key = DecodingKey::from_jwk(&jwk).map_err(invalid)?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        validation.set_issuer(&[&discovered.metadata.issuer]);
+        validation.set_audience(&[&provider.client_id]);
+        let claims = decode::<Map<String, Value>>(id_token, &key, &validation)
+            .map_err(invalid)?
+            .claims;
+
+        if claims.get("nonce").and_then(Value::as_str) != Some(nonce) {
+            return Err(OidcError::InvalidIdToken(
+                "nonce does not match the sign-in".to_string(),
+            ));
+        }
+        Ok(claims)
+    }
+}
+
+/// Reads the identity from ID token claims, with the claim names configured
+/// for `provider`
+fn identity_from_claims(
+    provider: &OidcProvider,
+    claims: &Map<String, Value>,
+) -> Result<FederatedIdentity, OidcError> {
+    let string = |name: &str| {
+        claims
+            .get(name)
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+    };
+    let subject = string("sub")
+        .ok_or_else(|| OidcError::InvalidIdToken("sub claim is missing".to_string()))?;
+    // Some providers send a single group as a string
+    let groups = match claims.get(&provider.groups_claim) {
+        Some(Value::Array(groups)) => groups
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        Some(Value::String(group)) => vec![group.clone()],
+        _ => Vec::new(),
+    };
+    let email_verified = match claims.get("email_verified") {
+        Some(Value::Bool(verified)) => *verified,
+        Some(Value::String(verified)) => verified == "true",
+        _ => false,
+    };
+
+    Ok(FederatedIdentity {
+        subject,
+        username: string(&provider.username_claim),
+        email: string(&provider.email_claim),
+        email_verified,
+        groups,
+    })
+}
@@ -1,9 +1,40 @@
+use argon2::{password_hash::PasswordHash, Argon2, PasswordVerifier};
 use bcrypt::{hash, verify, DEFAULT_COST};
 
+#[derive(Debug, thiserror::Error)]
+pub enum PasswordError {
+    #[error(transparent)]
+    Bcrypt(#[from] bcrypt::BcryptError),
+
+    #[error("invalid argon2 hash: {0}")]
+    Argon2(argon2::password_hash::Error),
+}
+
 pub fn hash_password(password: &str) -> Result<String, bcrypt::BcryptError> {
     hash(password, DEFAULT_COST)
 }
 
-pub fn verify_password(password: &str, hash: &str) -> Result<bool, bcrypt::BcryptError> {
-    verify(password, hash)
+/// Verify a password against a stored hash. New passwords are always hashed
+/// with bcrypt, but argon2 hashes brought in by a user import are accepted too.
+pub fn verify_password(password: &str, hash: &str) -> Result<bool, PasswordError> {
+    if hash.starts_with("$argon2") {
+        let parsed = PasswordHash::new(hash).map_err(PasswordError::Argon2)?;
+        return Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok());
+    }
+
+    Ok(verify(password, hash)?)
+}
+
+/// Returns true if `hash` is a well-formed bcrypt or argon2 password hash
+pub fn is_supported_password_hash(hash: &str) -> bool {
+    if hash.starts_with("$argon2") {
+        return PasswordHash::new(hash).is_ok();
+    }
+
+    ["$2a$", "$2b$", "$2x$", "$2y$"]
+        .iter()
+        .any(|prefix| hash.starts_with(prefix))
+        && hash.len() == 60
 }
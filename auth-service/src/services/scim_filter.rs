@@ -0,0 +1,334 @@
+use serde_json::Value;
+
+/// Longest filter accepted, in bytes
+pub const MAX_FILTER_LENGTH: usize = 4096;
+
+/// Deepest nesting of parentheses and `not` accepted; the parser recurses
+/// once per level
+pub const MAX_FILTER_DEPTH: usize = 16;
+
+/// A parsed SCIM filter expression (RFC 7644 section 3.4.2.2).
+///
+/// Supports attribute comparisons (`eq`, `ne`, `co`, `sw`, `ew`, `gt`, `ge`,
+/// `lt`, `le`), presence (`pr`), `and`/`or`/`not` and parentheses. Attribute
+/// names are matched case-insensitively and multi-valued attributes match if
+/// any of their values match, so `emails.value eq "a@b.c"` and
+/// `emails eq "a@b.c"` are equivalent.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScimFilter {
+    Compare {
+        path: Vec<String>,
+        op: CompareOp,
+        value: Value,
+    },
+    Present(Vec<String>),
+    And(Box<ScimFilter>, Box<ScimFilter>),
+    Or(Box<ScimFilter>, Box<ScimFilter>),
+    Not(Box<ScimFilter>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Co,
+    Sw,
+    Ew,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Literal(Value),
+    OpenParen,
+    CloseParen,
+}
+
+impl ScimFilter {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        if input.len() > MAX_FILTER_LENGTH {
+            return Err(format!("Filter is longer than {MAX_FILTER_LENGTH} bytes"));
+        }
+        let tokens = tokenize(input)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            depth: 0,
+        };
+        let filter = parser.parse_or()?;
+        if parser.pos != parser.tokens.len() {
+            return Err(format!("Unexpected token at position {}", parser.pos));
+        }
+        Ok(filter)
+    }
+
+    /// Evaluates the filter against the JSON representation of a resource
+    pub fn matches(&self, resource: &Value) -> bool {
+        match self {
+            ScimFilter::Compare { path, op, value } => resolve(resource, path)
+                .iter()
+                .any(|candidate| compare(candidate, *op, value)),
+            ScimFilter::Present(path) => resolve(resource, path)
+                .iter()
+                .any(|v| !v.is_null() && v.as_str() != Some("")),
+            ScimFilter::And(a, b) => a.matches(resource) && b.matches(resource),
+            ScimFilter::Or(a, b) => a.matches(resource) || b.matches(resource),
+            ScimFilter::Not(inner) => !inner.matches(resource),
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::OpenParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::CloseParen);
+            }
+            '"' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => s.push(escaped),
+                            None => return Err("Unterminated string".to_string()),
+                        },
+                        Some('"') => break,
+                        Some(ch) => s.push(ch),
+                        None => return Err("Unterminated string".to_string()),
+                    }
+                }
+                tokens.push(Token::Literal(Value::String(s)));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&ch) = chars.peek() {
+                    if ch.is_whitespace() || ch == '(' || ch == ')' || ch == '"' {
+                        break;
+                    }
+                    word.push(ch);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Parentheses and `not`s enclosing the current position
+    depth: usize,
+}
+
+impl Parser {
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.tokens.get(self.pos), Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<ScimFilter, String> {
+        let mut left = self.parse_and()?;
+        while self.peek_keyword("or") {
+            self.pos += 1;
+            let right = self.parse_and()?;
+            left = ScimFilter::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<ScimFilter, String> {
+        let mut left = self.parse_term()?;
+        while self.peek_keyword("and") {
+            self.pos += 1;
+            let right = self.parse_term()?;
+            left = ScimFilter::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_term(&mut self) -> Result<ScimFilter, String> {
+        if self.peek_keyword("not") {
+            self.pos += 1;
+            self.enter()?;
+            let inner = self.parse_group();
+            self.depth -= 1;
+            return Ok(ScimFilter::Not(Box::new(inner?)));
+        }
+
+        if matches!(self.tokens.get(self.pos), Some(Token::OpenParen)) {
+            return self.parse_group();
+        }
+
+        let path = match self.next() {
+            Some(Token::Word(attr)) => parse_path(&attr),
+            _ => return Err("Expected attribute name".to_string()),
+        };
+
+        let op = match self.next() {
+            Some(Token::Word(op)) => op.to_ascii_lowercase(),
+            _ => return Err("Expected comparison operator".to_string()),
+        };
+
+        if op == "pr" {
+            return Ok(ScimFilter::Present(path));
+        }
+
+        let op = match op.as_str() {
+            "eq" => CompareOp::Eq,
+            "ne" => CompareOp::Ne,
+            "co" => CompareOp::Co,
+            "sw" => CompareOp::Sw,
+            "ew" => CompareOp::Ew,
+            "gt" => CompareOp::Gt,
+            "ge" => CompareOp::Ge,
+            "lt" => CompareOp::Lt,
+            "le" => CompareOp::Le,
+            other => return Err(format!("Unsupported operator '{other}'")),
+        };
+
+        let value = match self.next() {
+            Some(Token::Literal(v)) => v,
+            Some(Token::Word(w)) => match w.to_ascii_lowercase().as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                "null" => Value::Null,
+                _ => w
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(serde_json::Number::from_f64)
+                    .map(Value::Number)
+                    .ok_or_else(|| format!("Invalid comparison value '{w}'"))?,
+            },
+            _ => return Err("Expected comparison value".to_string()),
+        };
+
+        Ok(ScimFilter::Compare { path, op, value })
+    }
+
+    fn parse_group(&mut self) -> Result<ScimFilter, String> {
+        if self.next() != Some(Token::OpenParen) {
+            return Err("Expected '('".to_string());
+        }
+        self.enter()?;
+        let inner = self.parse_or();
+        self.depth -= 1;
+        let inner = inner?;
+        if self.next() != Some(Token::CloseParen) {
+            return Err("Expected ')'".to_string());
+        }
+        Ok(inner)
+    }
+
+    fn enter(&mut self) -> Result<(), String> {
+        if self.depth == MAX_FILTER_DEPTH {
+            return Err(format!(
+                "Filter is nested more than {MAX_FILTER_DEPTH} levels deep"
+            ));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+}
+
+/// Splits an attribute path into its lowercase components, dropping any
+/// schema URN prefix (e.g. `urn:ietf:params:scim:schemas:core:2.0:User:userName`)
+fn parse_path(attr: &str) -> Vec<String> {
+    let attr = match attr.rfind(':') {
+        Some(i) if attr.to_ascii_lowercase().starts_with("urn:") => &attr[i + 1..],
+        _ => attr,
+    };
+    attr.split('.').map(|s| s.to_ascii_lowercase()).collect()
+}
+
+fn get_case_insensitive<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    value
+        .as_object()?
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        .map(|(_, v)| v)
+}
+
+/// Resolves a path to every value it reaches, flattening multi-valued attributes
+fn resolve<'a>(resource: &'a Value, path: &[String]) -> Vec<&'a Value> {
+    let mut current = vec![resource];
+    for segment in path {
+        let mut next = Vec::new();
+        for value in current {
+            if let Some(found) = get_case_insensitive(value, segment) {
+                match found {
+                    Value::Array(items) => next.extend(items.iter()),
+                    other => next.push(other),
+                }
+            }
+        }
+        current = next;
+    }
+
+    // A bare multi-valued complex attribute compares against its "value" sub-attribute
+    current
+        .into_iter()
+        .map(|v| get_case_insensitive(v, "value").unwrap_or(v))
+        .collect()
+}
+
+fn compare(candidate: &Value, op: CompareOp, expected: &Value) -> bool {
+    match (candidate, expected) {
+        (Value::String(a), Value::String(b)) => {
+            // Attributes used for filtering (userName, emails, displayName) are case-insensitive
+            let a = a.to_lowercase();
+            let b = b.to_lowercase();
+            match op {
+                CompareOp::Eq => a == b,
+                CompareOp::Ne => a != b,
+                CompareOp::Co => a.contains(&b),
+                CompareOp::Sw => a.starts_with(&b),
+                CompareOp::Ew => a.ends_with(&b),
+                CompareOp::Gt => a > b,
+                CompareOp::Ge => a >= b,
+                CompareOp::Lt => a < b,
+                CompareOp::Le => a <= b,
+            }
+        }
+        (Value::Number(a), Value::Number(b)) => {
+            let (a, b) = (a.as_f64().unwrap_or(0.0), b.as_f64().unwrap_or(0.0));
+            match op {
+                CompareOp::Eq => a == b,
+                CompareOp::Ne => a != b,
+                CompareOp::Gt => a > b,
+                CompareOp::Ge => a >= b,
+                CompareOp::Lt => a < b,
+                CompareOp::Le => a <= b,
+                _ => false,
+            }
+        }
+        (a, b) => match op {
+            CompareOp::Eq => a == b,
+            CompareOp::Ne => a != b,
+            _ => false,
+        },
+    }
+}
@@ -0,0 +1,55 @@
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+
+/// Generate a random opaque token (about 256 bits of entropy) for one-time
+/// secrets such as invitation links. Only its hash should be persisted.
+pub fn generate_opaque_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(43)
+        .map(char::from)
+        .collect()
+}
+
+/// Hash an opaque token for storage and lookup
+pub fn hash_opaque_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Characters of device user codes (RFC 8628 section 6.1): consonants only,
+/// so codes cannot spell words or be mistaken for digits
+const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+
+/// Generate a device user code such as `WDJB-MJHT`, short enough to type.
+/// Its 8 characters give about 34 bits of entropy, enough for a code that
+/// only lives for minutes.
+pub fn generate_user_code() -> String {
+    let mut rng = rand::thread_rng();
+    let mut code = String::with_capacity(9);
+    for i in 0..8 {
+        if i == 4 {
+            code.push('-');
+        }
+        code.push(USER_CODE_ALPHABET[rng.gen_range(0..USER_CODE_ALPHABET.len())] as char);
+    }
+    code
+}
+
+/// A user code as typed, in any case and with or without separators, in the
+/// form it is stored in; None if it cannot be a user code
+pub fn normalize_user_code(input: &str) -> Option<String> {
+    let chars: Vec<char> = input
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    if chars.len() != 8 {
+        return None;
+    }
+    let (first, second) = chars.split_at(4);
+    Some(format!(
+        "{}-{}",
+        first.iter().collect::<String>(),
+        second.iter().collect::<String>()
+    ))
+}
@@ -0,0 +1,145 @@
+use crate::models::{DeliveryAttempt, DeliveryStatus, DueDelivery};
+use crate::repositories::{RepositoryResult, WebhookRepository};
+use chrono::{Duration, Utc};
+use futures_util::future::join_all;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::sync::Arc;
+
+/// `sha256=<hex>`: HMAC-SHA256 of `<timestamp>.<body>` keyed with the webhook secret
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+/// Unix time the delivery was signed at; receivers should reject stale ones
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+/// The event id, the same across retries and redeliveries, for deduplication
+pub const EVENT_ID_HEADER: &str = "X-Webhook-Id";
+pub const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+
+/// Events fanned out and deliveries attempted per dispatcher pass
+const BATCH_SIZE: i64 = 50;
+/// How long an endpoint has to answer
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+/// How long a claimed delivery is hidden from other dispatchers; comfortably
+/// longer than a request can take
+const LEASE_SECONDS: i32 = 60;
+
+/// Wait before retrying after `attempts` failed attempts: 30 seconds,
+/// doubling each time, at most an hour
+pub fn retry_delay(attempts: i32) -> Duration {
+    let exponent = attempts.clamp(1, 8) - 1;
+    Duration::seconds(30 << exponent).min(Duration::hours(1))
+}
+
+/// The signature header value for a body sent at `timestamp`
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// The JSON posted for a delivery
+pub fn delivery_body(delivery: &DueDelivery) -> Value {
+    json!({
+        "id": delivery.event_id,
+        "type": delivery.event_type,
+        "created_at": delivery.event_created_at,
+        "data": serde_json::from_str::<Value>(&delivery.payload).unwrap_or(Value::Null),
+    })
+}
+
+/// Moves events from the outbox to the registered endpoints. Any number of
+/// dispatchers may run against the same database.
+#[derive(Clone)]
+pub struct WebhookDispatcher {
+    webhooks: Arc<dyn WebhookRepository>,
+    client: reqwest::Client,
+    max_attempts: i32,
+}
+
+impl WebhookDispatcher {
+    /// Deliveries still failing after `max_attempts` are marked failed
+    pub fn new(webhooks: Arc<dyn WebhookRepository>, max_attempts: i32) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("Failed to build webhook HTTP client");
+        Self {
+            webhooks,
+            client,
+            max_attempts,
+        }
+    }
+
+    /// Fans out new events, then makes one attempt at each due delivery.
+    /// Returns the number of attempts made.
+    pub async fn run_once(&self) -> RepositoryResult<usize> {
+        self.webhooks.dispatch_events(BATCH_SIZE).await?;
+
+        let deliveries = self
+            .webhooks
+            .claim_due_deliveries(BATCH_SIZE, LEASE_SECONDS)
+            .await?;
+        let attempts = join_all(deliveries.iter().map(|delivery| async move {
+            let attempt = self.attempt(delivery).await;
+            self.webhooks.record_attempt(delivery.id, &attempt).await
+        }))
+        .await;
+
+        attempts.into_iter().collect::<RepositoryResult<Vec<_>>>()?;
+        Ok(deliveries.len())
+    }
+
+    async fn attempt(&self, delivery: &DueDelivery) -> DeliveryAttempt {
+        let body = delivery_body(delivery).to_string();
+        let timestamp = Utc::now().timestamp();
+
+        let result = self
+            .client
+            .post(&delivery.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(
+                SIGNATURE_HEADER,
+                sign(&delivery.secret, timestamp, body.as_bytes()),
+            )
+            .header(TIMESTAMP_HEADER, timestamp)
+            .header(EVENT_HEADER, &delivery.event_type)
+            .header(EVENT_ID_HEADER, delivery.event_id.to_string())
+            .header(DELIVERY_HEADER, delivery.id.to_string())
+            .body(body)
+            .send()
+            .await;
+
+        let (response_status, error) = match result {
+            Ok(response) if response.status().is_success() => {
+                return DeliveryAttempt {
+                    status: DeliveryStatus::Succeeded,
+                    next_attempt_at: None,
+                    response_status: Some(response.status().as_u16().into()),
+                    error: None,
+                };
+            }
+            Ok(response) => (
+                Some(response.status().as_u16().into()),
+                format!("Endpoint responded with {}", response.status()),
+            ),
+            Err(e) => (None, format!("Request failed: {e}")),
+        };
+
+        let retry = delivery.attempts < self.max_attempts;
+        DeliveryAttempt {
+            status: if retry {
+                DeliveryStatus::Pending
+            } else {
+                DeliveryStatus::Failed
+            },
+            next_attempt_at: retry.then(|| Utc::now() + retry_delay(delivery.attempts)),
+            response_status,
+            error: Some(error),
+        }
+    }
+}
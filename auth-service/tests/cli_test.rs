@@ -0,0 +1,224 @@
+//! Tests for the `karl-auth-admin` commands, run against a throwaway SQLite
+//! database so no database server is needed.
+
+use auth_service::cli::{parse_args, rotate_key, Admin, Cli, Command};
+use auth_service::{create_claims, generate_token, validate_token, validate_token_with_previous};
+use chrono::{DateTime, Duration, Utc};
+use shared::PreviousSecret;
+use uuid::Uuid;
+
+fn args(line: &str) -> Vec<String> {
+    line.split_whitespace().map(str::to_string).collect()
+}
+
+async fn run(admin: &Admin, line: &str, stdin: &str) -> anyhow::Result<String> {
+    let command = parse_args(args(line)).expect("valid command").command;
+    let mut out = Vec::new();
+    admin.run(command, &mut stdin.as_bytes(), &mut out).await?;
+    Ok(String::from_utf8(out).unwrap())
+}
+
+#[test]
+fn test_parse_args() {
+    assert_eq!(
+        parse_args(args(
+            "--database-url sqlite:x.db user create alice alice@example.com --admin"
+        )),
+        Ok(Cli {
+            database_url: Some("sqlite:x.db".to_string()),
+            command: Command::UserCreate {
+                username: "alice".to_string(),
+                email: "alice@example.com".to_string(),
+                password: None,
+                admin: true,
+            },
+        })
+    );
+    assert_eq!(
+        parse_args(args("audit export --user alice"))
+            .unwrap()
+            .command,
+        Command::AuditExport {
+            user: Some("alice".to_string())
+        }
+    );
+    assert_eq!(
+        parse_args(args("permission update audit:read --action list"))
+            .unwrap()
+            .command,
+        Command::PermissionUpdate {
+            permission: "audit:read".to_string(),
+            name: None,
+            resource: None,
+            action: Some("list".to_string()),
+        }
+    );
+    assert_eq!(parse_args(args("")).unwrap().command, Command::Help);
+    assert_eq!(
+        parse_args(args("role list -h")).unwrap().command,
+        Command::Help
+    );
+
+    assert!(parse_args(args("user frobnicate")).is_err());
+    assert!(parse_args(args("user show")).is_err());
+    assert!(parse_args(args("user set-password alice --password")).is_err());
+    assert!(parse_args(args("role list --password secret123")).is_err());
+    assert!(parse_args(args("user list --verbose")).is_err());
+    assert!(parse_args(args("role delete auditor --name x")).is_err());
+    assert_eq!(
+        parse_args(args("key rotate")).unwrap().command,
+        Command::KeyRotate
+    );
+    assert!(parse_args(args("key rotate --user alice")).is_err());
+}
+
+#[test]
+fn test_key_rotate() {
+    let mut out = Vec::new();
+    rotate_key(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    let new_secret = out.lines().next().unwrap();
+    assert_eq!(new_secret.len(), 43);
+    assert!(out.contains(&format!("JWT_SECRET={new_secret}")));
+    let until = out
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("JWT_PREVIOUS_SECRET_UNTIL="))
+        .expect("grace window deadline");
+    let until = DateTime::parse_from_rfc3339(until)
+        .unwrap()
+        .with_timezone(&Utc);
+    assert!(until > Utc::now() + Duration::hours(23));
+
+    // A token signed before the rotation keeps working until the deadline
+    let old_secret = "the-secret-being-replaced";
+    let claims = create_claims(Uuid::new_v4(), "alice".to_string(), vec![], vec![]);
+    let token = generate_token(&claims, old_secret).unwrap();
+    let previous = PreviousSecret {
+        secret: old_secret.to_string(),
+        until,
+    };
+    assert!(validate_token(&token, new_secret).is_err());
+    assert!(validate_token_with_previous(&token, new_secret, Some(&previous)).is_ok());
+
+    let expired = PreviousSecret {
+        until: Utc::now() - Duration::seconds(1),
+        ..previous.clone()
+    };
+    assert!(validate_token_with_previous(&token, new_secret, Some(&expired)).is_err());
+    let forged = generate_token(&claims, "some-other-secret").unwrap();
+    assert!(validate_token_with_previous(&forged, new_secret, Some(&previous)).is_err());
+}
+
+#[tokio::test]
+async fn test_admin_commands_sqlite() {
+    let path = std::env::temp_dir().join(format!("karl-auth-admin-{}.db", Uuid::new_v4()));
+    let admin = Admin::connect(&format!("sqlite:{}", path.display()))
+        .await
+        .unwrap();
+
+    let status = run(&admin, "migrate status", "").await.unwrap();
+    assert!(status.lines().all(|l| l.contains("pending")));
+    run(&admin, "migrate apply", "").await.unwrap();
+    let status = run(&admin, "migrate status", "").await.unwrap();
+    assert!(status.lines().all(|l| l.contains("applied")));
+
+    // Passwords come from stdin unless given with --password
+    let created = run(
+        &admin,
+        "user create ops ops@example.com --admin",
+        "opspassword123\n",
+    )
+    .await
+    .unwrap();
+    assert!(created.contains("with role 'admin'"));
+    assert!(
+        run(&admin, "user create short short@example.com", "short\n")
+            .await
+            .is_err()
+    );
+
+    run(&admin, "role create auditor --description Audits", "")
+        .await
+        .unwrap();
+    run(&admin, "permission create audit:read audit read", "")
+        .await
+        .unwrap();
+    run(&admin, "permission grant auditor audit:read", "")
+        .await
+        .unwrap();
+    run(&admin, "role update auditor --name reviewer", "")
+        .await
+        .unwrap();
+    run(&admin, "permission update audit:read --action list", "")
+        .await
+        .unwrap();
+    let permissions = run(&admin, "permission list", "").await.unwrap();
+    assert!(permissions.contains("audit:list"));
+    run(&admin, "role update reviewer --name auditor", "")
+        .await
+        .unwrap();
+    assert!(run(&admin, "role update admin --name root", "")
+        .await
+        .is_err());
+    assert!(run(&admin, "role delete user", "").await.is_err());
+    run(&admin, "role assign ops auditor", "").await.unwrap();
+
+    let shown = run(&admin, "user show ops", "").await.unwrap();
+    assert!(shown.contains("roles:       admin, auditor"));
+    assert!(shown.contains("audit:read"));
+    assert!(shown.contains("user:write"));
+
+    run(&admin, "role unassign ops auditor", "").await.unwrap();
+    assert!(run(&admin, "role unassign ops auditor", "").await.is_err());
+    run(&admin, "permission revoke auditor audit:read", "")
+        .await
+        .unwrap();
+
+    assert!(run(&admin, "user edit ops", "").await.is_err());
+    run(&admin, "user edit ops --email ops@corp.example", "")
+        .await
+        .unwrap();
+    let shown = run(&admin, "user show ops", "").await.unwrap();
+    assert!(shown.contains("ops@corp.example"));
+
+    let deactivated = run(&admin, "user deactivate ops", "").await.unwrap();
+    assert!(deactivated.contains("Deactivated 'ops'"));
+    let listed = run(&admin, "user list", "").await.unwrap();
+    assert!(listed.contains("ops") && listed.contains("inactive"));
+
+    run(
+        &admin,
+        "user set-password ops --password newopspassword",
+        "",
+    )
+    .await
+    .unwrap();
+    run(&admin, "user delete ops", "").await.unwrap();
+    assert!(run(&admin, "user show ops", "").await.is_err());
+    run(&admin, "user restore ops", "").await.unwrap();
+    assert!(run(&admin, "user restore ops", "").await.is_err());
+    run(&admin, "user show ops", "").await.unwrap();
+
+    run(&admin, "permission delete audit:read", "")
+        .await
+        .unwrap();
+    run(&admin, "role delete auditor", "").await.unwrap();
+    assert!(run(&admin, "role delete auditor", "").await.is_err());
+
+    // One JSON object per line, oldest first
+    let exported = run(&admin, "audit export", "").await.unwrap();
+    let records: Vec<serde_json::Value> = exported
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert!(!records.is_empty());
+    assert!(records.iter().all(|r| r["record"] == "event"));
+    let exported = run(&admin, "audit export --user ops", "").await.unwrap();
+    assert!(exported.contains("\"user.created\""));
+    assert!(exported.lines().all(|line| line.contains("\"ops\"")));
+
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+    }
+}
@@ -1,6 +1,14 @@
 use actix_web::{http::StatusCode, test, web, App};
-use auth_service::handlers::auth::{login, register, LoginRequest, RegisterRequest};
-use auth_service::{create_pool, Config};
+use auth_service::handlers::auth::{
+    accept_invitation, list_my_sessions, login, register, revoke_my_session, revoke_my_sessions,
+    LoginRequest, RegisterRequest,
+};
+use auth_service::handlers::{admin, bulk, scim};
+use auth_service::models::NewCredential;
+use auth_service::services::LoginThrottle;
+use auth_service::{
+    create_pool, hash_password, Config, RegistrationMode, Repositories, Role, User,
+};
 use sqlx::PgPool;
 use std::env;
 
@@ -21,6 +29,7 @@ async fn test_register_user() {
     let app = test::init_service(
         App::new()
             .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(Repositories::postgres(pool.clone())))
             .app_data(web::Data::new(config.clone()))
             .route("/register", web::post().to(register)),
     )
@@ -56,6 +65,7 @@ async fn test_login_user() {
     let app_register = test::init_service(
         App::new()
             .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(Repositories::postgres(pool.clone())))
             .app_data(web::Data::new(config.clone()))
             .route("/register", web::post().to(register)),
     )
@@ -77,7 +87,9 @@ async fn test_login_user() {
     let app_login = test::init_service(
         App::new()
             .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(Repositories::postgres(pool.clone())))
             .app_data(web::Data::new(config.clone()))
+            .app_data(web::Data::new(LoginThrottle::default()))
             .route("/login", web::post().to(login)),
     )
     .await;
@@ -97,16 +109,17 @@ async fn test_admin_list_users() {
     let config = Config::from_env();
 
     // Create admin user and get token (simplified - in real test would create admin user)
-    let app = test::init_service(
+    let _app = test::init_service(
         App::new()
             .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(Repositories::postgres(pool.clone())))
             .app_data(web::Data::new(config.clone()))
             .service(
                 web::scope("/admin")
+                    .wrap(auth_service::middleware::AdminAuth)
                     .wrap(auth_service::middleware::JwtAuth::new(
                         config.jwt_secret.clone(),
                     ))
-                    .wrap(auth_service::middleware::AdminAuth)
                     .service(
                         web::scope("/users")
                             .route("", web::get().to(auth_service::handlers::admin::list_users)),
@@ -125,16 +138,17 @@ async fn test_admin_create_user() {
     let pool = setup_test_pool().await;
     let config = Config::from_env();
 
-    let app = test::init_service(
+    let _app = test::init_service(
         App::new()
             .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(Repositories::postgres(pool.clone())))
             .app_data(web::Data::new(config.clone()))
             .service(
                 web::scope("/admin")
+                    .wrap(auth_service::middleware::AdminAuth)
                     .wrap(auth_service::middleware::JwtAuth::new(
                         config.jwt_secret.clone(),
                     ))
-                    .wrap(auth_service::middleware::AdminAuth)
                     .service(web::scope("/users").route(
                         "",
                         web::post().to(auth_service::handlers::admin::create_user),
@@ -145,3 +159,1442 @@ async fn test_admin_create_user() {
 
     // Note: This test would need a valid admin JWT token
 }
+
+/// Signs an admin token for a new user; tokens of unknown users are refused
+async fn admin_token(pool: &PgPool, config: &Config) -> String {
+    let suffix = uuid::Uuid::new_v4().simple();
+    let user = User::create(
+        pool,
+        &format!("test_admin_{suffix}"),
+        &format!("test_admin_{suffix}@example.com"),
+        "unused-hash",
+    )
+    .await
+    .expect("Failed to create test admin");
+    let claims = auth_service::create_claims(
+        user.id,
+        user.username,
+        vec!["admin".to_string()],
+        vec!["user:read".to_string(), "user:write".to_string()],
+    );
+    auth_service::generate_token(&claims, &config.jwt_secret).expect("Failed to sign test token")
+}
+
+#[tokio::test]
+async fn test_group_roles_are_inherited_through_nested_groups() {
+    let pool = setup_test_pool().await;
+    let config = Config::from_env();
+    let token = admin_token(&pool, &config).await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(Repositories::postgres(pool.clone())))
+            .app_data(web::Data::new(config.clone()))
+            .app_data(web::Data::new(LoginThrottle::default()))
+            .route("/login", web::post().to(login))
+            .service(
+                web::scope("/admin")
+                    .wrap(auth_service::middleware::AdminAuth)
+                    .wrap(auth_service::middleware::JwtAuth::new(
+                        config.jwt_secret.clone(),
+                    ))
+                    .service(
+                        web::scope("/groups")
+                            .route("", web::post().to(admin::create_group))
+                            .route("/{id}", web::get().to(admin::get_group))
+                            .route("/{id}/members", web::post().to(admin::add_group_members))
+                            .route("/{id}/subgroups", web::post().to(admin::add_subgroup))
+                            .route("/{id}/roles", web::post().to(admin::assign_role_to_group)),
+                    ),
+            ),
+    )
+    .await;
+
+    let suffix = uuid::Uuid::new_v4();
+    let role = Role::create(&pool, &format!("group_role_{suffix}"), None)
+        .await
+        .unwrap();
+    let password = "grouppassword123";
+    let user = User::create(
+        &pool,
+        &format!("groupuser_{suffix}"),
+        &format!("group_{suffix}@example.com"),
+        &hash_password(password).unwrap(),
+    )
+    .await
+    .unwrap();
+
+    let mut group_ids = Vec::new();
+    for name in ["parent", "child"] {
+        let req = test::TestRequest::post()
+            .uri("/admin/groups")
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .set_json(serde_json::json!({ "name": format!("{name}_{suffix}") }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        group_ids.push(body["data"]["id"].as_str().unwrap().to_string());
+    }
+    let (parent_id, child_id) = (&group_ids[0], &group_ids[1]);
+
+    // Role on the parent, user only in the nested child group
+    let requests = [
+        (
+            format!("/admin/groups/{parent_id}/roles"),
+            serde_json::json!({ "role_id": role.id }),
+        ),
+        (
+            format!("/admin/groups/{parent_id}/subgroups"),
+            serde_json::json!({ "group_id": child_id }),
+        ),
+        (
+            format!("/admin/groups/{child_id}/members"),
+            serde_json::json!({ "user_ids": [user.id] }),
+        ),
+    ];
+    for (uri, body) in requests {
+        let req = test::TestRequest::post()
+            .uri(&uri)
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .set_json(body)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success(), "POST {uri} failed");
+    }
+
+    // Nesting the parent back inside the child would form a cycle
+    let req = test::TestRequest::post()
+        .uri(&format!("/admin/groups/{child_id}/subgroups"))
+        .insert_header(("Authorization", format!("Bearer {token}")))
+        .set_json(serde_json::json!({ "group_id": parent_id }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+    let login_req = LoginRequest {
+        username: user.username.clone(),
+        password: password.to_string(),
+    };
+    let req = test::TestRequest::post()
+        .uri("/login")
+        .set_json(&login_req)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let roles: Vec<&str> = body["data"]["roles"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|r| r.as_str())
+        .collect();
+    assert!(roles.contains(&role.name.as_str()));
+
+    // Joining the child group gave the user the parent's role
+    let events = Repositories::postgres(pool.clone())
+        .privacy
+        .user_events(user.id)
+        .await
+        .unwrap();
+    let changed: Vec<serde_json::Value> = events
+        .iter()
+        .filter(|event| event.event_type == "user.roles_changed")
+        .map(|event| serde_json::from_str(&event.payload).unwrap())
+        .collect();
+    assert_eq!(changed.len(), 1);
+    assert_eq!(changed[0]["roles"][0]["name"], role.name.as_str());
+}
+
+#[tokio::test]
+async fn test_invitation_onboarding_in_invite_only_mode() {
+    let pool = setup_test_pool().await;
+    let mut config = Config::from_env();
+    config.registration_mode = RegistrationMode::InviteOnly;
+    let token = admin_token(&pool, &config).await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(Repositories::postgres(pool.clone())))
+            .app_data(web::Data::new(config.clone()))
+            .route("/register", web::post().to(register))
+            .route("/invitations/accept", web::post().to(accept_invitation))
+            .service(
+                web::scope("/admin")
+                    .wrap(auth_service::middleware::AdminAuth)
+                    .wrap(auth_service::middleware::JwtAuth::new(
+                        config.jwt_secret.clone(),
+                    ))
+                    .route("/invitations", web::post().to(admin::create_invitation)),
+            ),
+    )
+    .await;
+
+    let suffix = uuid::Uuid::new_v4();
+
+    // Self-registration is rejected
+    let register_req = RegisterRequest {
+        username: format!("selfreg_{suffix}"),
+        email: format!("selfreg_{suffix}@example.com"),
+        password: "selfregpassword123".to_string(),
+    };
+    let req = test::TestRequest::post()
+        .uri("/register")
+        .set_json(&register_req)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    // Admin invites with a pre-assigned role
+    let role = Role::create(&pool, &format!("invited_role_{suffix}"), None)
+        .await
+        .unwrap();
+    let req = test::TestRequest::post()
+        .uri("/admin/invitations")
+        .insert_header(("Authorization", format!("Bearer {token}")))
+        .set_json(serde_json::json!({
+            "email": format!("invitee_{suffix}@example.com"),
+            "role_ids": [role.id],
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["data"]["status"], "pending");
+    let invite_token = body["data"]["token"].as_str().unwrap().to_string();
+
+    let accept = serde_json::json!({
+        "token": invite_token,
+        "username": format!("invitee_{suffix}"),
+        "password": "inviteepassword123",
+    });
+    let req = test::TestRequest::post()
+        .uri("/invitations/accept")
+        .set_json(&accept)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let user_id: uuid::Uuid = body["data"]["user_id"].as_str().unwrap().parse().unwrap();
+    assert_eq!(
+        body["data"]["email"],
+        format!("invitee_{suffix}@example.com")
+    );
+
+    let roles = Role::get_user_roles(&pool, user_id).await.unwrap();
+    assert!(roles.iter().any(|r| r.id == role.id));
+
+    // The token is single-use
+    let req = test::TestRequest::post()
+        .uri("/invitations/accept")
+        .set_json(&accept)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn test_scim_user_and_group_provisioning() {
+    let pool = setup_test_pool().await;
+    let mut config = Config::from_env();
+    config.scim_bearer_token = Some("scim-test-token".to_string());
+    let auth = ("Authorization", "Bearer scim-test-token");
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(Repositories::postgres(pool.clone())))
+            .service(
+                web::scope("/scim/v2")
+                    .service(
+                        web::scope("/Users")
+                            .wrap(auth_service::middleware::ScimAuth::new(
+                                config.scim_bearer_token.clone(),
+                            ))
+                            .route("", web::get().to(scim::list_users))
+                            .route("", web::post().to(scim::create_user))
+                            .route("/{id}", web::get().to(scim::get_user))
+                            .route("/{id}", web::patch().to(scim::patch_user)),
+                    )
+                    .service(
+                        web::scope("/Groups")
+                            .wrap(auth_service::middleware::ScimAuth::new(
+                                config.scim_bearer_token.clone(),
+                            ))
+                            .route("", web::post().to(scim::create_group))
+                            .route("/{id}", web::patch().to(scim::patch_group)),
+                    ),
+            ),
+    )
+    .await;
+
+    // Wrong token
+    let req = test::TestRequest::get()
+        .uri("/scim/v2/Users")
+        .insert_header(("Authorization", "Bearer wrong"))
+        .to_request();
+    let err = test::try_call_service(&app, req).await.unwrap_err();
+    assert_eq!(
+        err.as_response_error().status_code(),
+        StatusCode::UNAUTHORIZED
+    );
+
+    let suffix = uuid::Uuid::new_v4();
+    let user_name = format!("Scim.User_{suffix}");
+    let req = test::TestRequest::post()
+        .uri("/scim/v2/Users")
+        .insert_header(auth)
+        .set_json(serde_json::json!({
+            "schemas": ["urn:ietf:params:scim:schemas:core:2.0:User"],
+            "userName": user_name,
+            "emails": [{ "value": format!("scim_{suffix}@example.com"), "primary": true }],
+            "active": true,
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let user_id = body["id"].as_str().unwrap().to_string();
+
+    // Filter matches case-insensitively on userName
+    let filter = format!("userName eq \"{}\"", user_name.to_lowercase());
+    let req = test::TestRequest::get()
+        .uri(&format!("/scim/v2/Users?filter={}", urlencode(&filter)))
+        .insert_header(auth)
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["totalResults"], 1);
+    assert_eq!(body["Resources"][0]["id"], user_id.as_str());
+
+    // Deactivate with a PATCH as sent by common identity providers
+    let req = test::TestRequest::patch()
+        .uri(&format!("/scim/v2/Users/{user_id}"))
+        .insert_header(auth)
+        .set_json(serde_json::json!({
+            "schemas": ["urn:ietf:params:scim:api:messages:2.0:PatchOp"],
+            "Operations": [{ "op": "Replace", "path": "active", "value": "False" }],
+        }))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["active"], false);
+
+    // Groups map onto roles
+    let req = test::TestRequest::post()
+        .uri("/scim/v2/Groups")
+        .insert_header(auth)
+        .set_json(serde_json::json!({
+            "displayName": format!("scim_group_{suffix}"),
+            "members": [{ "value": user_id }],
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let group_id: uuid::Uuid = body["id"].as_str().unwrap().parse().unwrap();
+    assert_eq!(body["members"][0]["value"], user_id.as_str());
+
+    let user_uuid: uuid::Uuid = user_id.parse().unwrap();
+    let roles = Role::get_user_roles(&pool, user_uuid).await.unwrap();
+    assert!(roles.iter().any(|r| r.id == group_id));
+
+    // Filters on groups, substrings and activity are evaluated in SQL
+    let filter = format!(
+        "groups.display eq \"SCIM_GROUP_{suffix}\" and userName co \"{}\" and active eq false",
+        &user_name[2..]
+    );
+    let req = test::TestRequest::get()
+        .uri(&format!("/scim/v2/Users?filter={}", urlencode(&filter)))
+        .insert_header(auth)
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["totalResults"], 1);
+    assert_eq!(body["Resources"][0]["id"], user_id.as_str());
+    assert_eq!(
+        body["Resources"][0]["groups"][0]["value"],
+        group_id.to_string()
+    );
+
+    let filter = format!("userName eq \"{user_name}\" and not (groups pr)");
+    let req = test::TestRequest::get()
+        .uri(&format!(
+            "/scim/v2/Users?filter={}&count=5",
+            urlencode(&filter)
+        ))
+        .insert_header(auth)
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["totalResults"], 0);
+
+    let req = test::TestRequest::patch()
+        .uri(&format!("/scim/v2/Groups/{group_id}"))
+        .insert_header(auth)
+        .set_json(serde_json::json!({
+            "Operations": [{ "op": "remove", "path": format!("members[value eq \"{user_id}\"]") }],
+        }))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["members"].as_array().unwrap().len(), 0);
+}
+
+#[tokio::test]
+async fn test_bulk_user_import_and_export() {
+    let pool = setup_test_pool().await;
+    let config = Config::from_env();
+    let token = admin_token(&pool, &config).await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(Repositories::postgres(pool.clone())))
+            .app_data(web::Data::new(config.clone()))
+            .app_data(web::Data::new(LoginThrottle::default()))
+            .route("/login", web::post().to(login))
+            .service(
+                web::scope("/admin")
+                    .wrap(auth_service::middleware::AdminAuth)
+                    .wrap(auth_service::middleware::JwtAuth::new(
+                        config.jwt_secret.clone(),
+                    ))
+                    .route("/users/import", web::post().to(bulk::import_users))
+                    .route("/users/export", web::get().to(bulk::export_users)),
+            ),
+    )
+    .await;
+
+    let suffix = uuid::Uuid::new_v4();
+    let password_hash = hash_password("importedpassword123").unwrap();
+    let csv = format!(
+        "username,email,password_hash,roles,is_active\n\
+         imported_{suffix},imported_{suffix}@example.com,{password_hash},user,true\n\
+         ,pending_{suffix}@example.com,,,\n\
+         broken_{suffix},broken_{suffix}@example.com,not-a-hash,no_such_role,true\n"
+    );
+
+    // A transactional import with an invalid row writes nothing
+    let req = test::TestRequest::post()
+        .uri("/admin/users/import")
+        .insert_header(("Authorization", format!("Bearer {token}")))
+        .insert_header(("Content-Type", "text/csv"))
+        .set_payload(csv.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "validation_failed");
+    let errors = body["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 2);
+    assert!(errors.iter().all(|e| e["field"] == "row 3"));
+    assert!(User::find_by_username(&pool, &format!("imported_{suffix}"))
+        .await
+        .unwrap()
+        .is_none());
+
+    // Best effort applies the valid rows and reports the rest
+    let req = test::TestRequest::post()
+        .uri("/admin/users/import?mode=best_effort")
+        .insert_header(("Authorization", format!("Bearer {token}")))
+        .insert_header(("Content-Type", "text/csv"))
+        .set_payload(csv)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["data"]["succeeded"], 2);
+    assert_eq!(body["data"]["rows"][0]["status"], "created");
+    assert_eq!(body["data"]["rows"][1]["status"], "invited");
+    assert!(body["data"]["rows"][1]["invitation_token"].is_string());
+    assert_eq!(body["data"]["rows"][2]["status"], "failed");
+
+    // The imported hash is usable for login
+    let req = test::TestRequest::post()
+        .uri("/login")
+        .set_json(&LoginRequest {
+            username: format!("imported_{suffix}"),
+            password: "importedpassword123".to_string(),
+        })
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // A duplicate row is rejected in a dry run
+    let ndjson = format!(
+        "{}\n",
+        serde_json::json!({
+            "username": format!("imported_{suffix}"),
+            "email": format!("other_{suffix}@example.com"),
+            "password_hash": password_hash,
+        })
+    );
+    let req = test::TestRequest::post()
+        .uri("/admin/users/import?dry_run=true")
+        .insert_header(("Authorization", format!("Bearer {token}")))
+        .insert_header(("Content-Type", "application/x-ndjson"))
+        .set_payload(ndjson)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["data"]["rows"][0]["status"], "failed");
+    assert_eq!(
+        body["data"]["rows"][0]["errors"][0],
+        "Username already exists"
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/admin/users/export?format=csv")
+        .insert_header(("Authorization", format!("Bearer {token}")))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(body.starts_with("id,username,email,password_hash,is_active,created_at,roles\n"));
+    assert!(body.contains(&format!(
+        "imported_{suffix},imported_{suffix}@example.com,,true"
+    )));
+    assert!(!body.contains(&password_hash));
+}
+
+#[tokio::test]
+async fn test_admin_impersonation() {
+    let pool = setup_test_pool().await;
+    let config = Config::from_env();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(Repositories::postgres(pool.clone())))
+            .app_data(web::Data::new(config.clone()))
+            .service(
+                web::scope("/admin")
+                    .wrap(auth_service::middleware::AdminAuth)
+                    .wrap(auth_service::middleware::JwtAuth::new(
+                        config.jwt_secret.clone(),
+                    ))
+                    .route("/users", web::get().to(admin::list_users))
+                    .route(
+                        "/users/{id}/impersonate",
+                        web::post().to(admin::impersonate_user),
+                    )
+                    .route("/impersonations", web::get().to(admin::list_impersonations)),
+            ),
+    )
+    .await;
+
+    let suffix = uuid::Uuid::new_v4();
+    let user = User::create(
+        &pool,
+        &format!("support_target_{suffix}"),
+        &format!("support_target_{suffix}@example.com"),
+        &hash_password("targetpassword123").unwrap(),
+    )
+    .await
+    .unwrap();
+    let user_role = Role::find_by_name(&pool, "user").await.unwrap().unwrap();
+    User::assign_roles(&pool, user.id, &[user_role.id])
+        .await
+        .unwrap();
+    let uri = format!("/admin/users/{}/impersonate", user.id);
+    let body = serde_json::json!({ "reason": "Ticket 1234: 403 on weather" });
+
+    // The admin role alone is not enough
+    let req = test::TestRequest::post()
+        .uri(&uri)
+        .insert_header((
+            "Authorization",
+            format!("Bearer {}", admin_token(&pool, &config).await),
+        ))
+        .set_json(&body)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let support_admin = User::create(
+        &pool,
+        &format!("support_admin_{suffix}"),
+        &format!("support_admin_{suffix}@example.com"),
+        "unused-hash",
+    )
+    .await
+    .unwrap();
+    let admin_id = support_admin.id;
+    let support_claims = auth_service::create_claims(
+        admin_id,
+        support_admin.username.clone(),
+        vec!["admin".to_string()],
+        vec!["user:impersonate".to_string()],
+    );
+    let support_token = auth_service::generate_token(&support_claims, &config.jwt_secret).unwrap();
+
+    let req = test::TestRequest::post()
+        .uri(&uri)
+        .insert_header(("Authorization", format!("Bearer {support_token}")))
+        .set_json(&body)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let token = body["data"]["token"].as_str().unwrap().to_string();
+
+    let claims = auth_service::validate_token(&token, &config.jwt_secret).unwrap();
+    assert_eq!(claims.sub, user.id);
+    assert!(claims.permissions.contains(&"weather:read".to_string()));
+    let actor = claims.act.expect("impersonation token carries an actor");
+    assert_eq!(actor.sub, admin_id);
+    assert_eq!(actor.username, support_admin.username);
+    assert!(claims.exp - claims.iat <= 15 * 60);
+
+    // The impersonation token cannot reach admin endpoints or chain impersonation
+    let req = test::TestRequest::get()
+        .uri("/admin/users")
+        .insert_header(("Authorization", format!("Bearer {token}")))
+        .to_request();
+    let err = test::try_call_service(&app, req).await.unwrap_err();
+    assert_eq!(err.error_response().status(), StatusCode::FORBIDDEN);
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/admin/impersonations?user_id={}", user.id))
+        .insert_header(("Authorization", format!("Bearer {support_token}")))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let events = body["data"].as_array().unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["actor_id"], admin_id.to_string());
+    assert_eq!(events[0]["reason"], "Ticket 1234: 403 on weather");
+}
+
+#[tokio::test]
+async fn test_session_listing_and_revocation() {
+    let pool = setup_test_pool().await;
+    let config = Config::from_env();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(Repositories::postgres(pool.clone())))
+            .app_data(web::Data::new(config.clone()))
+            .app_data(web::Data::new(LoginThrottle::default()))
+            .route("/login", web::post().to(login))
+            .service(
+                web::scope("/me")
+                    .wrap(auth_service::middleware::JwtAuth::new(
+                        config.jwt_secret.clone(),
+                    ))
+                    .route("/sessions", web::get().to(list_my_sessions))
+                    .route("/sessions", web::delete().to(revoke_my_sessions))
+                    .route("/sessions/{id}", web::delete().to(revoke_my_session)),
+            )
+            .service(
+                web::scope("/admin")
+                    .wrap(auth_service::middleware::AdminAuth)
+                    .wrap(auth_service::middleware::JwtAuth::new(
+                        config.jwt_secret.clone(),
+                    ))
+                    .route("/users/{id}", web::put().to(admin::update_user))
+                    .route("/users/{id}", web::patch().to(admin::patch_user))
+                    .route(
+                        "/users/{id}/sessions",
+                        web::get().to(admin::list_user_sessions),
+                    ),
+            ),
+    )
+    .await;
+
+    let suffix = uuid::Uuid::new_v4();
+    let username = format!("sessionuser_{suffix}");
+    let user = User::create(
+        &pool,
+        &username,
+        &format!("sessionuser_{suffix}@example.com"),
+        &hash_password("sessionpassword123").unwrap(),
+    )
+    .await
+    .unwrap();
+
+    let mut tokens = Vec::new();
+    for agent in ["laptop-browser", "phone-app", "tablet-app"] {
+        let req = test::TestRequest::post()
+            .uri("/login")
+            .insert_header(("User-Agent", agent))
+            .set_json(&LoginRequest {
+                username: username.clone(),
+                password: "sessionpassword123".to_string(),
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        tokens.push((
+            body["data"]["token"].as_str().unwrap().to_string(),
+            body["data"]["session_id"].as_str().unwrap().to_string(),
+        ));
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/me/sessions")
+        .insert_header(("Authorization", format!("Bearer {}", tokens[0].0)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let sessions = body["data"].as_array().unwrap();
+    assert_eq!(sessions.len(), 3);
+    let current: Vec<_> = sessions.iter().filter(|s| s["current"] == true).collect();
+    assert_eq!(current.len(), 1);
+    assert_eq!(current[0]["id"], tokens[0].1.as_str());
+    assert_eq!(current[0]["user_agent"], "laptop-browser");
+
+    // Revoking the phone session makes its token unusable
+    let req = test::TestRequest::delete()
+        .uri(&format!("/me/sessions/{}", tokens[1].1))
+        .insert_header(("Authorization", format!("Bearer {}", tokens[0].0)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+    let req = test::TestRequest::get()
+        .uri("/me/sessions")
+        .insert_header(("Authorization", format!("Bearer {}", tokens[1].0)))
+        .to_request();
+    let err = test::try_call_service(&app, req).await.unwrap_err();
+    assert_eq!(err.error_response().status(), StatusCode::UNAUTHORIZED);
+
+    // An admin password change revokes the remaining sessions
+    let token = admin_token(&pool, &config).await;
+    let req = test::TestRequest::get()
+        .uri(&format!("/admin/users/{}/sessions", user.id))
+        .insert_header(("Authorization", format!("Bearer {token}")))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["data"].as_array().unwrap().len(), 2);
+
+    let req = test::TestRequest::patch()
+        .uri(&format!("/admin/users/{}", user.id))
+        .insert_header(("Authorization", format!("Bearer {token}")))
+        .set_json(serde_json::json!({ "password": "changedpassword123" }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    for (token, _) in [&tokens[0], &tokens[2]] {
+        let req = test::TestRequest::get()
+            .uri("/me/sessions")
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .to_request();
+        let err = test::try_call_service(&app, req).await.unwrap_err();
+        assert_eq!(err.error_response().status(), StatusCode::UNAUTHORIZED);
+    }
+}
+
+fn urlencode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_constraint_errors_are_classified_postgres() {
+    use auth_service::repositories::RepositoryError;
+
+    let pool = setup_test_pool().await;
+    let repos = Repositories::postgres(pool.clone());
+    let suffix = uuid::Uuid::new_v4();
+    let username = format!("constraint_{suffix}");
+    let email = format!("constraint_{suffix}@example.com");
+
+    let user = repos.users.create(&username, &email, "hash").await.unwrap();
+
+    let err = repos
+        .users
+        .create(&format!("other_{suffix}"), &email, "hash")
+        .await
+        .unwrap_err();
+    assert!(err.is_unique_violation_on("users.email"), "{err:?}");
+
+    let err = repos
+        .users
+        .create(&username, &format!("other_{suffix}@example.com"), "hash")
+        .await
+        .unwrap_err();
+    assert!(err.is_unique_violation_on("users.username"), "{err:?}");
+
+    let missing = uuid::Uuid::new_v4();
+    let err = repos
+        .assignments
+        .assign_role_to_user(user.id, missing)
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(err, RepositoryError::ForeignKeyViolation(ref c) if c == "user_roles.role_id"),
+        "{err:?}"
+    );
+
+    // The insert is rolled back when a role cannot be granted
+    let role = Role::find_by_name(&pool, "user").await.unwrap().unwrap();
+    let orphan = format!("orphan_{suffix}");
+    assert!(repos
+        .users
+        .create_with_roles(
+            &orphan,
+            &format!("orphan_{suffix}@example.com"),
+            "hash",
+            &[role.id, missing],
+        )
+        .await
+        .is_err());
+    assert!(User::find_by_username(&pool, &orphan)
+        .await
+        .unwrap()
+        .is_none());
+
+    // Uniqueness ignores case, and lookups fold the same way
+    let err = repos
+        .users
+        .create(
+            &username.to_uppercase(),
+            &format!("upper_{suffix}@example.com"),
+            "hash",
+        )
+        .await
+        .unwrap_err();
+    assert!(err.is_unique_violation_on("users.username"), "{err:?}");
+    let err = repos
+        .users
+        .create(&format!("upper_{suffix}"), &email.to_uppercase(), "hash")
+        .await
+        .unwrap_err();
+    assert!(err.is_unique_violation_on("users.email"), "{err:?}");
+    let found = repos
+        .users
+        .find_by_username(&username.to_uppercase())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.id, user.id);
+    let found = repos
+        .users
+        .find_by_email(&email.to_uppercase())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.id, user.id);
+
+    User::soft_delete(&pool, user.id, None).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_webhook_outbox_postgres() {
+    use auth_service::models::{DeliveryAttempt, DeliveryStatus};
+
+    let pool = setup_test_pool().await;
+    let repos = Repositories::postgres(pool.clone());
+    let suffix = uuid::Uuid::new_v4();
+
+    let webhook = repos
+        .webhooks
+        .create(
+            "http://127.0.0.1:9/hook",
+            "outbox-test-secret",
+            &["user.deleted".to_string()],
+        )
+        .await
+        .unwrap();
+    let user = repos
+        .users
+        .create(
+            &format!("outbox_{suffix}"),
+            &format!("outbox_{suffix}@example.com"),
+            "hash",
+        )
+        .await
+        .unwrap();
+    assert!(repos.users.delete(user.id, None).await.unwrap());
+
+    // Other tests share the database, so only look at this webhook's deliveries
+    repos.webhooks.dispatch_events(10_000).await.unwrap();
+    let claim = || async {
+        repos
+            .webhooks
+            .claim_due_deliveries(10_000, 60)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|d| d.webhook_id == webhook.id)
+            .collect::<Vec<_>>()
+    };
+    let claimed = claim().await;
+    assert_eq!(claimed.len(), 1);
+    assert_eq!(claimed[0].event_type, "user.deleted");
+    assert_eq!(claimed[0].attempts, 1);
+    assert!(claimed[0].payload.contains(&user.id.to_string()));
+    // Claimed deliveries are leased to their dispatcher
+    assert!(claim().await.is_empty());
+
+    repos
+        .webhooks
+        .record_attempt(
+            claimed[0].id,
+            &DeliveryAttempt {
+                status: DeliveryStatus::Failed,
+                next_attempt_at: None,
+                response_status: Some(500),
+                error: Some("Endpoint responded with 500".to_string()),
+            },
+        )
+        .await
+        .unwrap();
+    let log = repos
+        .webhooks
+        .list_deliveries(webhook.id, 10)
+        .await
+        .unwrap();
+    assert_eq!(log.len(), 1);
+    assert_eq!(log[0].status, "failed");
+    assert_eq!(log[0].response_status, Some(500));
+
+    let redelivery = repos
+        .webhooks
+        .redeliver(webhook.id, claimed[0].id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(redelivery.status, "pending");
+    assert_eq!(redelivery.event_id, claimed[0].event_id);
+    assert_eq!(claim().await.len(), 1);
+
+    assert!(repos.webhooks.delete(webhook.id).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_permissions_version_postgres() {
+    let pool = setup_test_pool().await;
+    let repos = Repositories::postgres(pool.clone());
+    let suffix = uuid::Uuid::new_v4();
+
+    let role = repos
+        .roles
+        .create(&format!("versioned_{suffix}"), None)
+        .await
+        .unwrap();
+    let permission = repos
+        .permissions
+        .create(&format!("versioned:{suffix}"), "versioned", "read")
+        .await
+        .unwrap();
+
+    // Other tests share the database, so the version may move further than
+    // these writes alone would move it
+    let before = repos.assignments.permissions_version().await.unwrap();
+    repos
+        .assignments
+        .assign_permission_to_role(role.id, permission.id)
+        .await
+        .unwrap();
+    let mapping = repos.assignments.role_permissions().await.unwrap();
+    assert!(mapping.version > before);
+    assert_eq!(mapping.roles[&role.name], vec![permission.name.clone()]);
+
+    let renamed = format!("renamed:{suffix}");
+    repos
+        .permissions
+        .update(permission.id, &renamed, "versioned", "read", None)
+        .await
+        .unwrap()
+        .unwrap();
+    let mapping_after_rename = repos.assignments.role_permissions().await.unwrap();
+    assert!(mapping_after_rename.version > mapping.version);
+    assert_eq!(
+        mapping_after_rename.permissions_for(std::slice::from_ref(&role.name)),
+        vec![renamed]
+    );
+
+    assert!(repos.roles.delete(role.id, None).await.unwrap());
+    let version = repos.assignments.permissions_version().await.unwrap();
+    assert!(version > mapping_after_rename.version);
+    assert!(repos.permissions.delete(permission.id, None).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_service_accounts_postgres() {
+    let pool = setup_test_pool().await;
+    let repos = Repositories::postgres(pool.clone());
+    let suffix = uuid::Uuid::new_v4();
+    let name = format!("svc_{suffix}");
+
+    let account = repos
+        .service_accounts
+        .create(&name, Some("Integration test"), None)
+        .await
+        .unwrap();
+    assert!(account.is_active);
+    let duplicate = repos.service_accounts.create(&name, None, None).await;
+    assert!(duplicate
+        .unwrap_err()
+        .is_unique_violation_on("service_accounts.name"));
+
+    let role = repos
+        .roles
+        .create(&format!("svc_role_{suffix}"), None)
+        .await
+        .unwrap();
+    repos
+        .service_accounts
+        .assign_role(account.id, role.id)
+        .await
+        .unwrap();
+    let roles = repos.service_accounts.get_roles(account.id).await.unwrap();
+    assert_eq!(roles.len(), 1);
+    assert_eq!(roles[0].id, role.id);
+
+    let credential = repos
+        .service_accounts
+        .add_credential(
+            account.id,
+            NewCredential::Secret {
+                hash: "secret-hash",
+            },
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(credential.kind, "secret");
+    assert!(credential.last_used_at.is_none());
+    repos
+        .service_accounts
+        .touch_credential(credential.id)
+        .await
+        .unwrap();
+    let credentials = repos
+        .service_accounts
+        .list_credentials(account.id)
+        .await
+        .unwrap();
+    assert!(credentials[0].last_used_at.is_some());
+
+    // A client assertion id is single-use until it expires
+    let now = chrono::Utc::now();
+    let jti = uuid::Uuid::new_v4().to_string();
+    let later = now + chrono::Duration::minutes(5);
+    assert!(repos
+        .service_accounts
+        .use_assertion(account.id, &jti, later)
+        .await
+        .unwrap());
+    assert!(!repos
+        .service_accounts
+        .use_assertion(account.id, &jti, later)
+        .await
+        .unwrap());
+    let expired = uuid::Uuid::new_v4().to_string();
+    let earlier = now - chrono::Duration::minutes(1);
+    for _ in 0..2 {
+        assert!(repos
+            .service_accounts
+            .use_assertion(account.id, &expired, earlier)
+            .await
+            .unwrap());
+    }
+
+    let updated = repos
+        .service_accounts
+        .update(account.id, None, None, false)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!updated.is_active);
+    assert!(updated.description.is_none());
+
+    // Deleting the role or the account takes its assignments along
+    assert!(repos.roles.delete(role.id, None).await.unwrap());
+    assert!(repos
+        .service_accounts
+        .get_roles(account.id)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(repos.service_accounts.delete(account.id).await.unwrap());
+    assert!(repos
+        .service_accounts
+        .list_credentials(account.id)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_device_authorizations_postgres() {
+    let pool = setup_test_pool().await;
+    let repos = Repositories::postgres(pool.clone());
+    let suffix = uuid::Uuid::new_v4();
+    let user = repos
+        .users
+        .create(
+            &format!("device_{suffix}"),
+            &format!("device_{suffix}@example.com"),
+            "unused-hash",
+        )
+        .await
+        .unwrap();
+
+    let device_code_hash = format!("device-code-{suffix}");
+    let user_code = auth_service::services::generate_user_code();
+    let authorization = repos
+        .device_authorizations
+        .create(
+            &device_code_hash,
+            &user_code,
+            "deploy-cli",
+            5,
+            chrono::Utc::now() + chrono::Duration::minutes(10),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        authorization.status(),
+        auth_service::models::DeviceAuthorizationStatus::Pending
+    );
+    let duplicate = repos
+        .device_authorizations
+        .create(
+            &format!("other-{device_code_hash}"),
+            &user_code,
+            "deploy-cli",
+            5,
+            chrono::Utc::now() + chrono::Duration::minutes(10),
+        )
+        .await;
+    assert!(duplicate
+        .unwrap_err()
+        .is_unique_violation_on("device_authorizations.user_code"));
+
+    repos
+        .device_authorizations
+        .record_poll(authorization.id, 10)
+        .await
+        .unwrap();
+    let polled = repos
+        .device_authorizations
+        .find_by_device_code(&device_code_hash)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(polled.interval_seconds, 10);
+    assert!(polled.last_polled_at.is_some());
+
+    // A request is decided once
+    let approved = repos
+        .device_authorizations
+        .decide(&user_code, user.id, true)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(approved.user_id, Some(user.id));
+    assert!(approved.approved_at.is_some());
+    assert!(repos
+        .device_authorizations
+        .decide(&user_code, user.id, false)
+        .await
+        .unwrap()
+        .is_none());
+
+    assert!(repos
+        .device_authorizations
+        .delete(authorization.id)
+        .await
+        .unwrap());
+    assert!(!repos
+        .device_authorizations
+        .delete(authorization.id)
+        .await
+        .unwrap());
+
+    let expired_code = auth_service::services::generate_user_code();
+    let expired = repos
+        .device_authorizations
+        .create(
+            &format!("expired-{device_code_hash}"),
+            &expired_code,
+            "deploy-cli",
+            5,
+            chrono::Utc::now() - chrono::Duration::seconds(1),
+        )
+        .await
+        .unwrap();
+    assert!(repos
+        .device_authorizations
+        .decide(&expired_code, user.id, true)
+        .await
+        .unwrap()
+        .is_none());
+    let purged = repos
+        .device_authorizations
+        .purge_expired(chrono::Utc::now())
+        .await
+        .unwrap();
+    assert!(purged >= 1);
+    assert!(repos
+        .device_authorizations
+        .find_by_user_code(&expired.user_code)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_user_identities_postgres() {
+    let pool = setup_test_pool().await;
+    let repos = Repositories::postgres(pool.clone());
+    let suffix = uuid::Uuid::new_v4();
+    let user = repos
+        .users
+        .create(
+            &format!("federated_{suffix}"),
+            &format!("federated_{suffix}@example.com"),
+            "unused-hash",
+        )
+        .await
+        .unwrap();
+
+    let subject = format!("subject-{suffix}");
+    let identity = repos
+        .identities
+        .create(user.id, "corp", &subject, Some("federated@corp.example"))
+        .await
+        .unwrap();
+    assert_eq!(identity.user_id, user.id);
+    assert!(identity.last_login_at.is_none());
+
+    // A provider's subject links to one user only
+    let duplicate = repos
+        .identities
+        .create(user.id, "corp", &subject, None)
+        .await;
+    assert!(matches!(
+        duplicate,
+        Err(auth_service::repositories::RepositoryError::UniqueViolation(_))
+    ));
+    repos
+        .identities
+        .create(user.id, "partners", &subject, None)
+        .await
+        .unwrap();
+
+    repos.identities.touch(identity.id).await.unwrap();
+    let found = repos
+        .identities
+        .find("corp", &subject)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.id, identity.id);
+    assert!(found.last_login_at.is_some());
+    assert_eq!(
+        repos.identities.list_for_user(user.id).await.unwrap().len(),
+        2
+    );
+
+    let missing = repos
+        .identities
+        .create(uuid::Uuid::new_v4(), "corp", "orphan", None)
+        .await;
+    assert!(matches!(
+        missing,
+        Err(auth_service::repositories::RepositoryError::ForeignKeyViolation(_))
+    ));
+
+    assert!(!repos
+        .identities
+        .delete(uuid::Uuid::new_v4(), identity.id)
+        .await
+        .unwrap());
+    assert!(repos.identities.delete(user.id, identity.id).await.unwrap());
+    assert!(repos
+        .identities
+        .find("corp", &subject)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_user_erasure_postgres() {
+    use auth_service::models::{ImpersonationEvent, Invitation};
+
+    let pool = setup_test_pool().await;
+    let repos = Repositories::postgres(pool.clone());
+    let suffix = uuid::Uuid::new_v4();
+    let user = repos
+        .users
+        .create(
+            &format!("erased_{suffix}"),
+            &format!("erased_{suffix}@example.com"),
+            "unused-hash",
+        )
+        .await
+        .unwrap();
+    let admin = repos
+        .users
+        .create(
+            &format!("eraser_{suffix}"),
+            &format!("eraser_{suffix}@example.com"),
+            "unused-hash",
+        )
+        .await
+        .unwrap();
+
+    let expires_at = chrono::Utc::now() + chrono::Duration::minutes(15);
+    ImpersonationEvent::create(
+        &pool,
+        admin.id,
+        &admin.username,
+        user.id,
+        &user.username,
+        "support ticket",
+        expires_at,
+    )
+    .await
+    .unwrap();
+    let sent = Invitation::create(
+        &pool,
+        &format!("invitee_{suffix}@example.com"),
+        &format!("sent-{suffix}"),
+        Some(user.id),
+        expires_at,
+        &[],
+    )
+    .await
+    .unwrap();
+    let received = Invitation::create(
+        &pool,
+        &user.email,
+        &format!("received-{suffix}"),
+        Some(admin.id),
+        expires_at,
+        &[],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        repos
+            .privacy
+            .user_impersonations(user.id)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+
+    let erasure = repos
+        .privacy
+        .erase_user(user.id, admin.id, Some("Request #7"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(erasure.requested_by, admin.id);
+    let pseudonym = erasure.pseudonym_id;
+
+    // The audit records now name the pseudonym
+    assert!(repos
+        .privacy
+        .user_impersonations(user.id)
+        .await
+        .unwrap()
+        .is_empty());
+    let events = repos.privacy.user_impersonations(pseudonym).await.unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].target_username, format!("erased-{pseudonym}"));
+    assert_eq!(events[0].actor_id, admin.id);
+
+    let events = repos.privacy.user_events(pseudonym).await.unwrap();
+    assert_eq!(events[0].event_type, "user.created");
+    let payload: serde_json::Value = serde_json::from_str(&events[0].payload).unwrap();
+    assert!(payload["user"]["email"].is_null());
+    let events = repos.privacy.user_events(user.id).await.unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].event_type, "user.erased");
+
+    let sent = Invitation::find_by_id(&pool, sent.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(sent.invited_by, Some(pseudonym));
+    assert!(Invitation::find_by_id(&pool, received.id)
+        .await
+        .unwrap()
+        .is_none());
+
+    // Erasing the admin in turn pseudonymizes them in the record too
+    let second = repos
+        .privacy
+        .erase_user(admin.id, admin.id, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(second.requested_by, second.pseudonym_id);
+    let erasures = repos.privacy.list_erasures().await.unwrap();
+    let first = erasures.iter().find(|e| e.id == erasure.id).unwrap();
+    assert_eq!(first.requested_by, second.pseudonym_id);
+    assert!(repos
+        .privacy
+        .erase_user(user.id, admin.id, None)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_user_preferences_postgres() {
+    let pool = setup_test_pool().await;
+    let repos = Repositories::postgres(pool.clone());
+    let suffix = uuid::Uuid::new_v4();
+    let user = repos
+        .users
+        .create(
+            &format!("prefs_{suffix}"),
+            &format!("prefs_{suffix}@example.com"),
+            "unused-hash",
+        )
+        .await
+        .unwrap();
+
+    assert!(repos.preferences.get(user.id).await.unwrap().is_none());
+    assert!(!repos.preferences.delete(user.id).await.unwrap());
+
+    let mut preferences = shared::Preferences {
+        units: Some(shared::Units::Imperial),
+        locale: Some("en-US".to_string()),
+        time_format: Some(shared::TimeFormat::TwelveHour),
+        home_timezone: Some("America/Chicago".to_string()),
+        favourite_cities: vec!["Chicago".to_string(), "Tokyo".to_string()],
+    };
+    repos.preferences.put(user.id, &preferences).await.unwrap();
+    assert_eq!(
+        repos.preferences.get(user.id).await.unwrap(),
+        Some(preferences.clone())
+    );
+
+    // A second save replaces the first
+    preferences.units = None;
+    preferences.favourite_cities = vec!["Osaka".to_string()];
+    repos.preferences.put(user.id, &preferences).await.unwrap();
+    assert_eq!(
+        repos.preferences.get(user.id).await.unwrap(),
+        Some(preferences)
+    );
+
+    // They go with the user
+    repos
+        .privacy
+        .erase_user(user.id, user.id, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(repos.preferences.get(user.id).await.unwrap().is_none());
+}
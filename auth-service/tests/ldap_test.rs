@@ -0,0 +1,410 @@
+//! Password login against an LDAP directory, played by a stand-in that speaks
+//! just enough of the protocol (simple bind, base-object search, unbind) on a
+//! local port, with the in-memory repositories and an in-memory SQLite
+//! database.
+
+use actix_web::{http::StatusCode, test, web, App};
+use auth_service::handlers::auth::{login, LoginRequest};
+use auth_service::models::user::UpdateUser;
+use auth_service::services::auth_backend::LDAP_IDENTITY_SOURCE;
+use auth_service::services::LoginThrottle;
+use auth_service::{
+    validate_token, AuthBackendKind, Config, LdapConfig, RegistrationMode, Repositories,
+    TokenFormat,
+};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const PEOPLE: &str = "ou=people,dc=example,dc=com";
+const EDITORS: &str = "cn=Editors,ou=groups,dc=example,dc=com";
+
+struct Entry {
+    dn: String,
+    password: String,
+    attributes: Vec<(String, Vec<String>)>,
+}
+
+/// The directory the stand-in serves, and the DNs it was asked to bind as
+#[derive(Default)]
+struct Directory {
+    entries: Mutex<Vec<Entry>>,
+    binds: Mutex<Vec<String>>,
+}
+
+impl Directory {
+    fn add(&self, uid: &str, password: &str, mail: &str, groups: &[&str]) {
+        self.entries.lock().unwrap().push(Entry {
+            dn: format!("uid={uid},{PEOPLE}"),
+            password: password.to_string(),
+            attributes: vec![
+                ("mail".to_string(), vec![mail.to_string()]),
+                (
+                    "memberOf".to_string(),
+                    groups.iter().map(|g| g.to_string()).collect(),
+                ),
+            ],
+        });
+    }
+
+    fn set_groups(&self, uid: &str, groups: &[&str]) {
+        let dn = format!("uid={uid},{PEOPLE}");
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.iter_mut().find(|e| e.dn == dn).unwrap();
+        entry.attributes.retain(|(name, _)| name != "memberOf");
+        entry.attributes.push((
+            "memberOf".to_string(),
+            groups.iter().map(|g| g.to_string()).collect(),
+        ));
+    }
+
+    fn bind_count(&self) -> usize {
+        self.binds.lock().unwrap().len()
+    }
+}
+
+/// BER-encodes a value with `tag`
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    match content.len() {
+        len @ 0..=127 => out.push(len as u8),
+        len @ 128..=255 => out.extend([0x81, len as u8]),
+        len => out.extend([0x82, (len >> 8) as u8, len as u8]),
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+/// Splits the first BER value off `buf`: its tag, content and what follows
+fn read_tlv(buf: &[u8]) -> (u8, &[u8], &[u8]) {
+    let tag = buf[0];
+    let (len, header) = match buf[1] {
+        len @ 0..=127 => (len as usize, 2),
+        long => {
+            let n = (long & 0x7f) as usize;
+            let len = buf[2..2 + n]
+                .iter()
+                .fold(0, |len, byte| (len << 8) | *byte as usize);
+            (len, 2 + n)
+        }
+    };
+    (tag, &buf[header..header + len], &buf[header + len..])
+}
+
+/// Reads one LDAP message off the connection; None once it is closed
+async fn read_message(stream: &mut TcpStream) -> Option<Vec<u8>> {
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await.ok()?;
+    let mut message = header.to_vec();
+    let len = if header[1] & 0x80 == 0 {
+        header[1] as usize
+    } else {
+        let mut bytes = vec![0u8; (header[1] & 0x7f) as usize];
+        stream.read_exact(&mut bytes).await.ok()?;
+        message.extend(&bytes);
+        bytes
+            .iter()
+            .fold(0, |len, byte| (len << 8) | *byte as usize)
+    };
+    let mut content = vec![0u8; len];
+    stream.read_exact(&mut content).await.ok()?;
+    message.extend(content);
+    Some(message)
+}
+
+/// An LDAPResult with `result_code`
+fn ldap_result(result_code: u8) -> Vec<u8> {
+    [tlv(0x0a, &[result_code]), tlv(0x04, b""), tlv(0x04, b"")].concat()
+}
+
+fn response(message_id: &[u8], op: Vec<u8>) -> Vec<u8> {
+    tlv(0x30, &[tlv(0x02, message_id), op].concat())
+}
+
+async fn serve(directory: Arc<Directory>, mut stream: TcpStream) {
+    while let Some(message) = read_message(&mut stream).await {
+        let (_, message, _) = read_tlv(&message);
+        let (_, message_id, rest) = read_tlv(message);
+        let (op, body, _) = read_tlv(rest);
+
+        let mut out = Vec::new();
+        match op {
+            // BindRequest: version, name, simple password
+            0x60 => {
+                let (_, _version, rest) = read_tlv(body);
+                let (_, name, rest) = read_tlv(rest);
+                let (_, password, _) = read_tlv(rest);
+                let name = String::from_utf8_lossy(name).to_string();
+                let password = String::from_utf8_lossy(password).to_string();
+                directory.binds.lock().unwrap().push(name.clone());
+
+                let entries = directory.entries.lock().unwrap();
+                let accepted = entries
+                    .iter()
+                    .any(|e| e.dn.eq_ignore_ascii_case(&name) && e.password == password);
+                let result_code = if accepted { 0 } else { 49 };
+                out.extend(response(message_id, tlv(0x61, &ldap_result(result_code))));
+            }
+            // SearchRequest; only base-object searches are supported
+            0x63 => {
+                let (_, base, _) = read_tlv(body);
+                let base = String::from_utf8_lossy(base).to_string();
+
+                let entries = directory.entries.lock().unwrap();
+                let result_code = match entries.iter().find(|e| e.dn.eq_ignore_ascii_case(&base)) {
+                    Some(entry) => {
+                        let attributes: Vec<u8> = entry
+                            .attributes
+                            .iter()
+                            .flat_map(|(name, values)| {
+                                let values: Vec<u8> = values
+                                    .iter()
+                                    .flat_map(|v| tlv(0x04, v.as_bytes()))
+                                    .collect();
+                                tlv(
+                                    0x30,
+                                    &[tlv(0x04, name.as_bytes()), tlv(0x31, &values)].concat(),
+                                )
+                            })
+                            .collect();
+                        let found =
+                            [tlv(0x04, entry.dn.as_bytes()), tlv(0x30, &attributes)].concat();
+                        out.extend(response(message_id, tlv(0x64, &found)));
+                        0
+                    }
+                    None => 32,
+                };
+                out.extend(response(message_id, tlv(0x65, &ldap_result(result_code))));
+            }
+            // UnbindRequest
+            0x42 => return,
+            other => panic!("Unexpected LDAP operation {other:#x}"),
+        }
+        if stream.write_all(&out).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Starts the stand-in on a free local port, returning its URL
+async fn start_directory(directory: Arc<Directory>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ldap://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(serve(directory.clone(), stream));
+        }
+    });
+    url
+}
+
+fn test_config(url: &str, auth_backends: Vec<AuthBackendKind>) -> Config {
+    Config {
+        database_url: String::new(),
+        jwt_secret: "ldap-test-secret".to_string(),
+        jwt_previous_secret: None,
+        port: 0,
+        registration_mode: RegistrationMode::Open,
+        scim_bearer_token: None,
+        run_migrations: false,
+        bootstrap_admin: None,
+        user_retention_days: 30,
+        webhook_max_attempts: 3,
+        token_format: TokenFormat::Full,
+        device_verification_uri: None,
+        token_endpoint_url: None,
+        oidc_providers: Vec::new(),
+        auth_backends,
+        ldap: Some(LdapConfig {
+            url: url.to_string(),
+            starttls: false,
+            user_dn_template: format!("uid={{username}},{PEOPLE}"),
+            email_attribute: "mail".to_string(),
+            groups_attribute: "memberOf".to_string(),
+            group_roles: vec![(EDITORS.to_lowercase(), "editor".to_string())],
+            auto_provision: true,
+            trust_email: true,
+        }),
+    }
+}
+
+async fn sqlite_repositories() -> Repositories {
+    let pool = auth_service::create_sqlite_pool("sqlite::memory:")
+        .await
+        .expect("Failed to open SQLite database");
+    auth_service::db::run_sqlite_migrations(&pool)
+        .await
+        .expect("Failed to run SQLite migrations");
+    Repositories::sqlite(pool)
+}
+
+macro_rules! test_app {
+    ($repos:expr, $config:expr) => {
+        test::init_service(
+            App::new()
+                .app_data(web::Data::new($repos.clone()))
+                .app_data(web::Data::new($config.clone()))
+                .app_data(web::Data::new(LoginThrottle::default()))
+                .route("/login", web::post().to(login)),
+        )
+        .await
+    };
+}
+
+/// Logs in with `$username` and `$password`, returning the response
+macro_rules! login {
+    ($app:expr, $username:expr, $password:expr) => {{
+        let req = test::TestRequest::post()
+            .uri("/login")
+            .set_json(&LoginRequest {
+                username: $username.to_string(),
+                password: $password.to_string(),
+            })
+            .to_request();
+        test::call_service(&$app, req).await
+    }};
+}
+
+async fn role_names(repos: &Repositories, user_id: uuid::Uuid) -> Vec<String> {
+    let mut names: Vec<String> = repos
+        .assignments
+        .get_user_roles(user_id)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|role| role.name)
+        .collect();
+    names.sort();
+    names
+}
+
+async fn check_ldap_login(repos: Repositories) {
+    let directory = Arc::new(Directory::default());
+    directory.add(
+        "alice",
+        "alice-directory-pw",
+        "alice@example.com",
+        &[EDITORS],
+    );
+    directory.add("bob", "bob-directory-pw", "robert@example.com", &[]);
+    let url = start_directory(directory.clone()).await;
+    let config = test_config(&url, vec![AuthBackendKind::Ldap, AuthBackendKind::Local]);
+    let app = test_app!(repos, config);
+    repos.roles.create("editor", None).await.unwrap();
+
+    // A first login provisions the user, with the roles of their groups
+    let resp = login!(app, "alice", "alice-directory-pw");
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["data"]["username"], "alice");
+    let token = body["data"]["token"].as_str().unwrap();
+    let alice_id = validate_token(token, &config.jwt_secret).unwrap().sub;
+    assert_eq!(role_names(&repos, alice_id).await, ["editor", "user"]);
+    let alice = repos.users.find_by_id(alice_id).await.unwrap().unwrap();
+    assert_eq!(alice.email, "alice@example.com");
+    let identities = repos.identities.list_for_user(alice_id).await.unwrap();
+    assert_eq!(identities.len(), 1);
+    assert_eq!(identities[0].provider, LDAP_IDENTITY_SOURCE);
+    assert_eq!(identities[0].subject, format!("uid=alice,{PEOPLE}"));
+
+    // Any capitalization finds the same user; roles follow the groups
+    directory.set_groups("alice", &[]);
+    let resp = login!(app, "ALICE", "alice-directory-pw");
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["data"]["user_id"], alice_id.to_string());
+    assert_eq!(role_names(&repos, alice_id).await, ["user"]);
+
+    let resp = login!(app, "alice", "wrong-password");
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    // Without a password a bind would be anonymous, so none is attempted
+    let binds = directory.bind_count();
+    let resp = login!(app, "alice", "");
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(directory.bind_count(), binds);
+
+    // The username cannot change the DN's structure
+    let resp = login!(app, "alice,ou=people", "alice-directory-pw");
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let last_bind = directory.binds.lock().unwrap().last().unwrap().clone();
+    assert_eq!(last_bind, format!("uid=alice\\2cou\\3dpeople,{PEOPLE}"));
+
+    // Unless the directory's email addresses are trusted, one in use does
+    // not link the local user, nor is it taken over
+    let robert = repos
+        .users
+        .create(
+            "robert",
+            "robert@example.com",
+            &auth_service::hash_password("robert-local-pw").unwrap(),
+        )
+        .await
+        .unwrap();
+    let mut untrusting = test_config(&url, vec![AuthBackendKind::Ldap, AuthBackendKind::Local]);
+    untrusting.ldap.as_mut().unwrap().trust_email = false;
+    let untrusting_app = test_app!(repos, untrusting);
+    let resp = login!(untrusting_app, "bob", "bob-directory-pw");
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    assert!(repos
+        .identities
+        .list_for_user(robert.id)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // A trusted one does
+    let resp = login!(app, "bob", "bob-directory-pw");
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["data"]["user_id"], robert.id.to_string());
+    assert_eq!(body["data"]["username"], "robert");
+
+    // Users the directory does not know still sign in with local passwords
+    let resp = login!(app, "robert", "robert-local-pw");
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Inactive users are refused even with the right directory password
+    let update = UpdateUser {
+        username: None,
+        email: None,
+        password: None,
+        is_active: Some(false),
+    };
+    repos.users.update(alice_id, &update, None).await.unwrap();
+    let resp = login!(app, "alice", "alice-directory-pw");
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    // With the directory as the only backend, local passwords do not count
+    let config = test_config(&url, vec![AuthBackendKind::Ldap]);
+    let app = test_app!(repos, config);
+    let resp = login!(app, "robert", "robert-local-pw");
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let resp = login!(app, "bob", "bob-directory-pw");
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // With the directory down, local users still sign in; others cannot be
+    // told their password is wrong, as it may not be
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let down_url = format!("ldap://{}", closed.local_addr().unwrap());
+    drop(closed);
+    let config = test_config(
+        &down_url,
+        vec![AuthBackendKind::Ldap, AuthBackendKind::Local],
+    );
+    let app = test_app!(repos, config);
+    let resp = login!(app, "robert", "robert-local-pw");
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = login!(app, "bob", "bob-directory-pw");
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn test_ldap_login_in_memory() {
+    check_ldap_login(Repositories::in_memory()).await;
+}
+
+#[tokio::test]
+async fn test_ldap_login_sqlite() {
+    check_ldap_login(sqlite_repositories().await).await;
+}
@@ -0,0 +1,175 @@
+//! Tests for the SCIM filter parser and its evaluation against resources.
+
+use auth_service::services::scim_filter::{
+    CompareOp, ScimFilter, MAX_FILTER_DEPTH, MAX_FILTER_LENGTH,
+};
+use serde_json::json;
+
+fn compare(path: &str, op: CompareOp, value: serde_json::Value) -> ScimFilter {
+    ScimFilter::Compare {
+        path: path.split('.').map(str::to_string).collect(),
+        op,
+        value,
+    }
+}
+
+fn and(a: ScimFilter, b: ScimFilter) -> ScimFilter {
+    ScimFilter::And(Box::new(a), Box::new(b))
+}
+
+fn or(a: ScimFilter, b: ScimFilter) -> ScimFilter {
+    ScimFilter::Or(Box::new(a), Box::new(b))
+}
+
+#[test]
+fn test_and_binds_tighter_than_or() {
+    let a = compare("a", CompareOp::Eq, json!("1"));
+    let b = compare("b", CompareOp::Eq, json!("2"));
+    let c = compare("c", CompareOp::Eq, json!("3"));
+
+    assert_eq!(
+        ScimFilter::parse(r#"a eq "1" or b eq "2" and c eq "3""#).unwrap(),
+        or(a.clone(), and(b.clone(), c.clone()))
+    );
+    assert_eq!(
+        ScimFilter::parse(r#"a eq "1" and b eq "2" or c eq "3""#).unwrap(),
+        or(and(a.clone(), b.clone()), c.clone())
+    );
+    // Parentheses override it, and chains associate to the left
+    assert_eq!(
+        ScimFilter::parse(r#"(a eq "1" or b eq "2") and c eq "3""#).unwrap(),
+        and(or(a.clone(), b.clone()), c.clone())
+    );
+    assert_eq!(
+        ScimFilter::parse(r#"a eq "1" OR b eq "2" Or c eq "3""#).unwrap(),
+        or(or(a.clone(), b.clone()), c.clone())
+    );
+    assert_eq!(
+        ScimFilter::parse(r#"not (a eq "1") and b eq "2""#).unwrap(),
+        and(ScimFilter::Not(Box::new(a)), b)
+    );
+}
+
+#[test]
+fn test_attribute_paths_and_values() {
+    assert_eq!(
+        ScimFilter::parse(r#"urn:ietf:params:scim:schemas:core:2.0:User:userName eq "x""#).unwrap(),
+        compare("username", CompareOp::Eq, json!("x"))
+    );
+    assert_eq!(
+        ScimFilter::parse("meta.lastModified GT 5").unwrap(),
+        compare("meta.lastmodified", CompareOp::Gt, json!(5.0))
+    );
+    assert_eq!(
+        ScimFilter::parse("active eq TRUE").unwrap(),
+        compare("active", CompareOp::Eq, json!(true))
+    );
+    assert_eq!(
+        ScimFilter::parse("title eq null").unwrap(),
+        compare("title", CompareOp::Eq, json!(null))
+    );
+}
+
+#[test]
+fn test_presence() {
+    let filter = ScimFilter::parse("title pr").unwrap();
+    assert_eq!(filter, ScimFilter::Present(vec!["title".to_string()]));
+
+    assert!(filter.matches(&json!({ "title": "Engineer" })));
+    assert!(!filter.matches(&json!({ "title": "" })));
+    assert!(!filter.matches(&json!({ "title": null })));
+    assert!(!filter.matches(&json!({})));
+
+    let filter = ScimFilter::parse("emails pr and not (groups pr)").unwrap();
+    assert!(filter.matches(&json!({ "emails": [{ "value": "a@example.com" }], "groups": [] })));
+    assert!(!filter.matches(&json!({
+        "emails": [{ "value": "a@example.com" }],
+        "groups": [{ "value": "admin" }],
+    })));
+}
+
+#[test]
+fn test_quoted_strings_with_escapes() {
+    assert_eq!(
+        ScimFilter::parse(r#"userName eq "say \"hi\"""#).unwrap(),
+        compare("username", CompareOp::Eq, json!(r#"say "hi""#))
+    );
+    assert_eq!(
+        ScimFilter::parse(r#"userName eq "back\\slash""#).unwrap(),
+        compare("username", CompareOp::Eq, json!(r"back\slash"))
+    );
+    // Keywords and parentheses inside quotes are plain text
+    let filter = ScimFilter::parse(r#"displayName co "a) or (b""#).unwrap();
+    assert_eq!(
+        filter,
+        compare("displayname", CompareOp::Co, json!("a) or (b"))
+    );
+    assert!(filter.matches(&json!({ "displayName": "Team A) OR (B" })));
+}
+
+#[test]
+fn test_matching_ignores_case_and_flattens_multi_valued_attributes() {
+    let user = json!({
+        "userName": "Alice",
+        "emails": [
+            { "value": "alice@example.com" },
+            { "value": "alice@corp.example" },
+        ],
+        "active": true,
+    });
+    for (filter, expected) in [
+        (r#"USERNAME eq "alice""#, true),
+        (r#"userName ne "alice""#, false),
+        (r#"userName sw "al""#, true),
+        (r#"emails ew "@corp.example""#, true),
+        (r#"emails.value eq "alice@example.com""#, true),
+        (r#"emails co "bob""#, false),
+        ("active eq true", true),
+        (r#"userName gt "Bob""#, false),
+        (r#"userName eq "alice" and active eq false"#, false),
+        (r#"userName eq "bob" or active eq true"#, true),
+    ] {
+        let parsed = ScimFilter::parse(filter).unwrap();
+        assert_eq!(parsed.matches(&user), expected, "{filter}");
+    }
+}
+
+#[test]
+fn test_malformed_filters_are_rejected() {
+    for filter in [
+        "",
+        "userName",
+        "userName eq",
+        r#"userName eq "unterminated"#,
+        r#"userName eq "trailing\"#,
+        r#"userName like "x""#,
+        "userName eq bare",
+        r#"(userName eq "x""#,
+        r#"userName eq "x")"#,
+        r#"userName eq "x" and"#,
+        r#"userName eq "x" userName eq "y""#,
+        r#"not userName eq "x""#,
+        r#"eq "x""#,
+        "()",
+    ] {
+        assert!(ScimFilter::parse(filter).is_err(), "{filter:?} parsed");
+    }
+}
+
+#[test]
+fn test_length_and_depth_are_capped() {
+    let term = r#"userName eq "x""#;
+    let padding = " ".repeat(MAX_FILTER_LENGTH - term.len());
+    assert!(ScimFilter::parse(&format!("{term}{padding}")).is_ok());
+    assert!(ScimFilter::parse(&format!("{term}{padding} ")).is_err());
+
+    let nested = |depth: usize| format!("{}{term}{}", "(".repeat(depth), ")".repeat(depth));
+    assert!(ScimFilter::parse(&nested(MAX_FILTER_DEPTH)).is_ok());
+    let err = ScimFilter::parse(&nested(MAX_FILTER_DEPTH + 1)).unwrap_err();
+    assert!(err.contains("nested"), "{err}");
+
+    // A `not` counts as a level too, and deep input fails fast rather than
+    // recursing once per parenthesis
+    assert!(ScimFilter::parse(&format!("not {}", nested(MAX_FILTER_DEPTH))).is_err());
+    assert!(ScimFilter::parse(&"(".repeat(MAX_FILTER_LENGTH)).is_err());
+}
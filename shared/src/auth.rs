@@ -0,0 +1,355 @@
+//! Bearer token authentication for the resource services: checks the
+//! signature, expiry and audience of access tokens, optionally asks the auth
+//! service whether they are still active, and resolves the permissions of
+//! compact tokens.
+
+use crate::correlation::Correlated;
+use crate::errors::AppError;
+use crate::jwt::{decode_claims, PreviousSecret, RolePermissions};
+use crate::types::ApiResponse;
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    Error, HttpMessage,
+};
+use futures_util::future::LocalBoxFuture;
+use jsonwebtoken::Validation;
+use log::info;
+use serde::Deserialize;
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+/// Longest a role permission mapping is used without asking the auth
+/// service again, for when introspection is off
+const ROLE_PERMISSIONS_TTL: Duration = Duration::from_secs(60);
+
+/// How long before its expiry the resolver's service token is replaced
+const SERVICE_TOKEN_MARGIN: Duration = Duration::from_secs(60);
+
+pub struct JwtAuth {
+    jwt_secret: String,
+    previous_secret: Option<PreviousSecret>,
+    introspection: Option<Introspection>,
+    permissions: Option<PermissionResolver>,
+    audience: Option<String>,
+}
+
+impl JwtAuth {
+    pub fn new(jwt_secret: String) -> Self {
+        Self {
+            jwt_secret,
+            previous_secret: None,
+            introspection: None,
+            permissions: None,
+            audience: None,
+        }
+    }
+
+    /// Also accepts tokens signed with the secret `JWT_SECRET` replaced,
+    /// until its grace window ends
+    pub fn with_previous_secret(mut self, previous: Option<PreviousSecret>) -> Self {
+        self.previous_secret = previous;
+        self
+    }
+
+    /// Accepts audience-restricted tokens issued for `audience`; without it
+    /// every audience-restricted token is refused
+    pub fn with_audience(mut self, audience: &str) -> Self {
+        self.audience = Some(audience.to_string());
+        self
+    }
+
+    /// Also asks the auth service whether each token is still active, so
+    /// revoked sessions and deactivated or deleted users are refused at once
+    /// rather than when their tokens expire
+    pub fn with_introspection(mut self, auth_service_url: &str) -> Self {
+        self.introspection = Some(Introspection {
+            url: format!("{}/auth/introspect", auth_service_url.trim_end_matches('/')),
+            client: reqwest::Client::new(),
+        });
+        self
+    }
+
+    /// Resolves the permissions of compact tokens from their roles; without
+    /// a resolver those tokens carry no permissions at all
+    pub fn with_permission_resolution(mut self, resolver: PermissionResolver) -> Self {
+        self.permissions = Some(resolver);
+        self
+    }
+}
+
+#[derive(Clone)]
+struct Introspection {
+    url: String,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct IntrospectionResponse {
+    active: bool,
+    /// Sent by auth services that version role permissions
+    #[serde(default)]
+    permissions_version: Option<i64>,
+}
+
+impl Introspection {
+    async fn check(&self, token: &str) -> Result<IntrospectionResponse, AppError> {
+        let response = self
+            .client
+            .post(&self.url)
+            .form(&[("token", token)])
+            .correlated()
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| AppError::Internal(format!("Token introspection failed: {e}")))?;
+        let body: IntrospectionResponse = response
+            .json()
+            .await
+            .map_err(|e| AppError::Internal(format!("Invalid introspection response: {e}")))?;
+        Ok(body)
+    }
+}
+
+/// The role permission mapping from the auth service, cached until a token
+/// or introspection shows a newer version exists. The auth service serves it
+/// to service accounts only, so the resolver signs in as one with the client
+/// credentials grant. Clones share the caches.
+#[derive(Clone)]
+pub struct PermissionResolver {
+    url: String,
+    token_url: String,
+    client_id: String,
+    client_secret: String,
+    client: reqwest::Client,
+    cache: Arc<RwLock<Option<(RolePermissions, Instant)>>>,
+    /// The service account's token and when to stop using it
+    service_token: Arc<RwLock<Option<(String, Instant)>>>,
+}
+
+#[derive(Deserialize)]
+struct ServiceTokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+impl PermissionResolver {
+    pub fn new(auth_service_url: &str, client_id: &str, client_secret: &str) -> Self {
+        let auth_service_url = auth_service_url.trim_end_matches('/');
+        Self {
+            url: format!("{auth_service_url}/auth/role-permissions"),
+            token_url: format!("{auth_service_url}/auth/token"),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            client: reqwest::Client::new(),
+            cache: Arc::new(RwLock::new(None)),
+            service_token: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// The permissions granted by `roles` as of at least `min_version`
+    async fn resolve(&self, roles: &[String], min_version: i64) -> Result<Vec<String>, AppError> {
+        let cached = self
+            .cache
+            .read()
+            .unwrap()
+            .as_ref()
+            .filter(|(mapping, fetched_at)| {
+                mapping.version >= min_version && fetched_at.elapsed() < ROLE_PERMISSIONS_TTL
+            })
+            .map(|(mapping, _)| mapping.permissions_for(roles));
+        if let Some(permissions) = cached {
+            return Ok(permissions);
+        }
+
+        let mapping = self.fetch().await?;
+        let permissions = mapping.permissions_for(roles);
+        *self.cache.write().unwrap() = Some((mapping, Instant::now()));
+        Ok(permissions)
+    }
+
+    async fn fetch(&self) -> Result<RolePermissions, AppError> {
+        let token = self.service_token().await?;
+        let response = self
+            .client
+            .get(&self.url)
+            .bearer_auth(token)
+            .correlated()
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| AppError::Internal(format!("Fetching role permissions failed: {e}")))?;
+        let body: ApiResponse<RolePermissions> = response
+            .json()
+            .await
+            .map_err(|e| AppError::Internal(format!("Invalid role permissions response: {e}")))?;
+        Ok(body.data)
+    }
+
+    /// A token for the resolver's service account, kept until shortly
+    /// before it expires
+    async fn service_token(&self) -> Result<String, AppError> {
+        let cached = self
+            .service_token
+            .read()
+            .unwrap()
+            .as_ref()
+            .filter(|(_, renew_at)| Instant::now() < *renew_at)
+            .map(|(token, _)| token.clone());
+        if let Some(token) = cached {
+            return Ok(token);
+        }
+
+        let response = self
+            .client
+            .post(&self.token_url)
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .form(&[("grant_type", "client_credentials")])
+            .correlated()
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| AppError::Internal(format!("Fetching a service token failed: {e}")))?;
+        let body: ServiceTokenResponse = response
+            .json()
+            .await
+            .map_err(|e| AppError::Internal(format!("Invalid service token response: {e}")))?;
+
+        let lifetime = Duration::from_secs(body.expires_in);
+        let renew_at = Instant::now() + lifetime.saturating_sub(SERVICE_TOKEN_MARGIN);
+        *self.service_token.write().unwrap() = Some((body.access_token.clone(), renew_at));
+        Ok(body.access_token)
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for JwtAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = JwtAuthMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(JwtAuthMiddleware {
+            service: Rc::new(service),
+            jwt_secret: self.jwt_secret.clone(),
+            previous_secret: self.previous_secret.clone(),
+            introspection: self.introspection.clone(),
+            permissions: self.permissions.clone(),
+            audience: self.audience.clone(),
+        }))
+    }
+}
+
+pub struct JwtAuthMiddleware<S> {
+    service: Rc<S>,
+    jwt_secret: String,
+    previous_secret: Option<PreviousSecret>,
+    introspection: Option<Introspection>,
+    permissions: Option<PermissionResolver>,
+    audience: Option<String>,
+}
+
+impl<S, B> Service<ServiceRequest> for JwtAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let jwt_secret = self.jwt_secret.clone();
+        let previous_secret = self.previous_secret.clone();
+        let introspection = self.introspection.clone();
+        let permissions = self.permissions.clone();
+        let audience = self.audience.clone();
+
+        Box::pin(async move {
+            // Extract token from Authorization header
+            let auth_header = req
+                .headers()
+                .get("Authorization")
+                .and_then(|h| h.to_str().ok())
+                .ok_or_else(|| {
+                    AppError::Unauthorized("Missing Authorization header".to_string())
+                })?;
+
+            if !auth_header.starts_with("Bearer ") {
+                return Err(AppError::Unauthorized(
+                    "Invalid Authorization header format".to_string(),
+                )
+                .into());
+            }
+
+            let token = auth_header.strip_prefix("Bearer ").unwrap();
+
+            // Validate token
+            let mut validation = Validation::default();
+            validation.validate_aud = false;
+
+            let mut claims =
+                decode_claims(token, &jwt_secret, previous_secret.as_ref(), &validation)
+                    .map_err(|_| AppError::Unauthorized("Invalid or expired token".to_string()))?;
+
+            // Exchanged tokens may only be used at the service they name
+            if let Some(aud) = &claims.aud {
+                if audience.as_ref() != Some(aud) {
+                    return Err(AppError::Unauthorized(
+                        "Token is restricted to another audience".to_string(),
+                    )
+                    .into());
+                }
+            }
+
+            let mut permissions_version = None;
+            if let Some(introspection) = &introspection {
+                let introspected = introspection.check(token).await?;
+                if !introspected.active {
+                    return Err(AppError::Unauthorized("Token has been revoked".to_string()).into());
+                }
+                permissions_version = introspected.permissions_version;
+            }
+
+            // Compact tokens carry only roles; resolve them with a mapping at
+            // least as new as the token and whatever introspection reported
+            if let (Some(pv), Some(permissions)) = (claims.pv, &permissions) {
+                let min_version = permissions_version.map_or(pv, |v| v.max(pv));
+                claims.permissions = permissions.resolve(&claims.roles, min_version).await?;
+            }
+
+            // Impersonated requests are logged with both identities for the audit trail
+            if let Some(actor) = &claims.act {
+                info!(
+                    "{} {} by {} ({}) {} {} ({})",
+                    req.method(),
+                    req.path(),
+                    claims.username,
+                    claims.sub,
+                    actor.relation(),
+                    actor.username,
+                    actor.sub
+                );
+            }
+
+            // Attach claims to request extensions
+            req.extensions_mut().insert(claims);
+
+            let res = svc.call(req).await?;
+            Ok(res)
+        })
+    }
+}
@@ -0,0 +1,146 @@
+//! Request ids and W3C trace context, so the log lines every service writes
+//! for one request, and for the calls it makes upstream, can be tied together.
+
+use actix_web::http::header::HeaderMap;
+use std::future::Future;
+use uuid::Uuid;
+
+/// Header carrying the id of a request, echoed in the response
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// W3C Trace Context header: `00-<trace-id>-<parent-id>-<flags>`
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Longest request id accepted from a caller
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static CURRENT: RequestContext;
+}
+
+/// Identifies the request being handled. Stored in the request extensions
+/// (`web::ReqData<RequestContext>`) and, while the request is handled, in a
+/// task-local read by [`current`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestContext {
+    /// The caller's `X-Request-Id`, or a new UUID
+    pub request_id: String,
+    /// The trace this request is part of: the caller's, or a new one
+    pub trace_id: String,
+    /// This service's span, sent as the parent of the calls it makes
+    pub span_id: String,
+    trace_flags: String,
+}
+
+impl RequestContext {
+    /// Continues the request id and trace the caller sent, starting new ones
+    /// when they are missing or malformed
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+
+        let request_id = header(REQUEST_ID_HEADER)
+            .filter(|id| valid_request_id(id))
+            .map_or_else(|| Uuid::new_v4().to_string(), str::to_string);
+        let (trace_id, trace_flags) = header(TRACEPARENT_HEADER)
+            .and_then(parse_traceparent)
+            .unwrap_or_else(|| (Uuid::new_v4().simple().to_string(), "00".to_string()));
+
+        Self {
+            request_id,
+            trace_id,
+            span_id: new_span_id(),
+            trace_flags,
+        }
+    }
+
+    /// The `traceparent` for calls made while handling this request
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-{}", self.trace_id, self.span_id, self.trace_flags)
+    }
+
+    /// Runs `future` with this context as the [`current`] one
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        CURRENT.scope(self, future).await
+    }
+}
+
+/// The context of the request being handled, if any; background tasks have none
+pub fn current() -> Option<RequestContext> {
+    CURRENT.try_with(RequestContext::clone).ok()
+}
+
+/// Request ids end up in log lines, so only short printable ones are kept
+fn valid_request_id(id: &str) -> bool {
+    (1..=MAX_REQUEST_ID_LENGTH).contains(&id.len()) && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+fn is_lower_hex(value: &str, len: usize) -> bool {
+    value.len() == len
+        && value
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// The trace id and flags of a version 00 `traceparent`. Ids of all zeros
+/// are invalid; the caller's parent id is replaced by this service's span.
+fn parse_traceparent(value: &str) -> Option<(String, String)> {
+    let mut parts = value.trim().split('-');
+    let (version, trace_id, parent_id, flags) =
+        (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    let valid = version == "00"
+        && parts.next().is_none()
+        && is_lower_hex(trace_id, 32)
+        && trace_id.bytes().any(|b| b != b'0')
+        && is_lower_hex(parent_id, 16)
+        && parent_id.bytes().any(|b| b != b'0')
+        && is_lower_hex(flags, 2);
+    valid.then(|| (trace_id.to_string(), flags.to_string()))
+}
+
+fn new_span_id() -> String {
+    let mut id = Uuid::new_v4().simple().to_string();
+    id.truncate(16);
+    id
+}
+
+/// Adds the current request's id and `traceparent` to outbound calls
+pub trait Correlated {
+    fn correlated(self) -> Self;
+}
+
+impl Correlated for reqwest::RequestBuilder {
+    fn correlated(self) -> Self {
+        match current() {
+            Some(context) => self
+                .header(REQUEST_ID_HEADER, context.request_id.as_str())
+                .header(TRACEPARENT_HEADER, context.traceparent()),
+            None => self,
+        }
+    }
+}
+
+/// `env_logger` format naming the request each line was written for:
+/// `env_logger::Builder::from_env(..).format(shared::correlation::format_log)`
+pub fn format_log(
+    buf: &mut env_logger::fmt::Formatter,
+    record: &log::Record,
+) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let level_style = buf.default_level_style(record.level());
+    write!(
+        buf,
+        "[{} {level_style}{:<5}{level_style:#} {}]",
+        buf.timestamp(),
+        record.level(),
+        record.target()
+    )?;
+    if let Some(context) = current() {
+        write!(
+            buf,
+            " request_id={} trace_id={}",
+            context.request_id, context.trace_id
+        )?;
+    }
+    writeln!(buf, " {}", record.args())
+}
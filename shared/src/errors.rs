@@ -1,14 +1,20 @@
-use actix_web::{HttpResponse, ResponseError};
+use actix_web::error::PayloadError;
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
 use serde::{Deserialize, Serialize};
 
-/// Application error types for consistent error handling across services
+/// Content type of error responses (RFC 7807)
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Application error types for consistent error handling across services.
+/// Each renders as an RFC 7807 problem with a stable machine-readable `code`.
 #[derive(Debug, thiserror::Error)]
 pub enum AppError {
     /// Internal server error (500)
     #[error("Internal server error: {0}")]
     Internal(String),
 
-    /// Bad request error (400)
+    /// Bad request error (400): the request is malformed
     #[error("Bad request: {0}")]
     BadRequest(String),
 
@@ -27,28 +33,201 @@ pub enum AppError {
     /// Conflict error (409)
     #[error("Conflict: {0}")]
     Conflict(String),
+
+    /// Precondition failed error (412), e.g. a stale `If-Match`
+    #[error("Precondition failed: {0}")]
+    PreconditionFailed(String),
+
+    /// Payload too large error (413): the body exceeds the endpoint's limit
+    #[error("Payload too large: {0}")]
+    PayloadTooLarge(String),
+
+    /// Unprocessable entity error (422): the request is well-formed but some
+    /// of its values are not acceptable; the field errors say which
+    #[error("Validation failed: {0}")]
+    Validation(String, Vec<FieldError>),
+
+    /// Precondition required error (428), e.g. a missing `If-Match`
+    #[error("Precondition required: {0}")]
+    PreconditionRequired(String),
+
+    /// Too many requests error (429); the client may retry after the given
+    /// number of seconds, sent as `Retry-After`
+    #[error("Too many requests: {0}")]
+    TooManyRequests(String, u64),
+
+    /// Service unavailable error (503), e.g. no upstream could answer
+    #[error("Service unavailable: {0}")]
+    ServiceUnavailable(String),
+
+    /// Gateway timeout error (504): an upstream took too long to answer
+    #[error("Gateway timeout: {0}")]
+    GatewayTimeout(String),
+}
+
+/// A problem with one field of the request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldError {
+    /// The field's name as it appears in the request, e.g. `favourite_cities`
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// The body of every error response: an RFC 7807 problem, extended with a
+/// stable `code`, field errors and the request id
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProblemDetails {
+    /// Always `about:blank`: problems are told apart by `code`
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// The path of the request that failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    pub code: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl AppError {
+    /// The stable, machine-readable name of the kind of error
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Internal(_) => "internal_error",
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::PreconditionFailed(_) => "precondition_failed",
+            AppError::PayloadTooLarge(_) => "payload_too_large",
+            AppError::Validation(..) => "validation_failed",
+            AppError::PreconditionRequired(_) => "precondition_required",
+            AppError::TooManyRequests(..) => "rate_limited",
+            AppError::ServiceUnavailable(_) => "service_unavailable",
+            AppError::GatewayTimeout(_) => "upstream_timeout",
+        }
+    }
+
+    /// The human-readable explanation, without the kind of error
+    pub fn detail(&self) -> &str {
+        match self {
+            AppError::Internal(detail)
+            | AppError::BadRequest(detail)
+            | AppError::Unauthorized(detail)
+            | AppError::Forbidden(detail)
+            | AppError::NotFound(detail)
+            | AppError::Conflict(detail)
+            | AppError::PreconditionFailed(detail)
+            | AppError::PayloadTooLarge(detail)
+            | AppError::Validation(detail, _)
+            | AppError::PreconditionRequired(detail)
+            | AppError::TooManyRequests(detail, _)
+            | AppError::ServiceUnavailable(detail)
+            | AppError::GatewayTimeout(detail) => detail,
+        }
+    }
+
+    pub fn problem(&self) -> ProblemDetails {
+        let status = self.status_code();
+        ProblemDetails {
+            problem_type: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or("Error").to_string(),
+            status: status.as_u16(),
+            detail: self.detail().to_string(),
+            instance: None,
+            code: self.code().to_string(),
+            errors: match self {
+                AppError::Validation(_, errors) => errors.clone(),
+                _ => Vec::new(),
+            },
+            request_id: None,
+        }
+    }
+
+    /// The error response, naming the request that failed
+    pub fn problem_response(
+        &self,
+        instance: Option<&str>,
+        request_id: Option<&str>,
+    ) -> HttpResponse {
+        let mut problem = self.problem();
+        problem.instance = instance.map(str::to_string);
+        problem.request_id = request_id.map(str::to_string);
+
+        let mut response = HttpResponse::build(self.status_code());
+        response.content_type(PROBLEM_JSON);
+        if let AppError::TooManyRequests(_, retry_after) = self {
+            response.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+        }
+        response.json(problem)
+    }
 }
 
 impl ResponseError for AppError {
-    fn error_response(&self) -> HttpResponse {
-        let status = match self {
-            AppError::Internal(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
-            AppError::BadRequest(_) => actix_web::http::StatusCode::BAD_REQUEST,
-            AppError::Unauthorized(_) => actix_web::http::StatusCode::UNAUTHORIZED,
-            AppError::Forbidden(_) => actix_web::http::StatusCode::FORBIDDEN,
-            AppError::NotFound(_) => actix_web::http::StatusCode::NOT_FOUND,
-            AppError::Conflict(_) => actix_web::http::StatusCode::CONFLICT,
-        };
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::Validation(..) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::PreconditionRequired(_) => StatusCode::PRECONDITION_REQUIRED,
+            AppError::TooManyRequests(..) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::GatewayTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
 
-        HttpResponse::build(status).json(ErrorResponse {
-            error: self.to_string(),
-        })
+    fn error_response(&self) -> HttpResponse {
+        self.problem_response(None, None)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ErrorResponse {
-    pub error: String,
+/// Reports bodies, query strings and paths the extractors cannot parse as
+/// problems too; register through `JsonConfig`, `FormConfig`, `QueryConfig`
+/// and `PathConfig`
+pub fn extractor_error(err: impl std::fmt::Display, _req: &HttpRequest) -> actix_web::Error {
+    AppError::BadRequest(err.to_string()).into()
 }
 
 pub type AppResult<T> = Result<T, AppError>;
+
+/// Reports bodies that could not be read, e.g. over the `PayloadConfig`
+/// limit, as problems; [`crate::ErrorContext`] applies it, since
+/// `PayloadConfig` takes no error handler
+pub fn payload_error(err: &PayloadError) -> AppError {
+    match err {
+        PayloadError::Overflow => {
+            AppError::PayloadTooLarge("The request body is too large".to_string())
+        }
+        _ => AppError::BadRequest(err.to_string()),
+    }
+}
+
+/// Answers requests that match no route as problems; register as the
+/// `App`'s `default_service`
+pub async fn no_route(req: HttpRequest) -> AppResult<HttpResponse> {
+    Err(AppError::NotFound(format!(
+        "No route for {} {}",
+        req.method(),
+        req.path()
+    )))
+}
@@ -1,12 +1,170 @@
+use chrono::{DateTime, Utc};
+use jsonwebtoken::errors::{Error as JwtError, ErrorKind};
+use jsonwebtoken::{decode, DecodingKey, Validation};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: Uuid,
     pub username: String,
     pub roles: Vec<String>,
+    /// Empty in compact tokens, whose permissions follow from `roles`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub permissions: Vec<String>,
     pub exp: i64,
     pub iat: i64,
+    /// Id of the login session the token belongs to, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<Uuid>,
+    /// Set on impersonation tokens to identify the admin acting as `sub`,
+    /// and on exchanged tokens the service acting for it (the `act` claim
+    /// from RFC 8693)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+    /// Set on exchanged tokens: the only service the token may be used at
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    /// Set on compact tokens: the permissions version the token was issued
+    /// at. Role permissions older than this must not be used to resolve it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pv: Option<i64>,
+    /// What kind of principal `sub` is; tokens issued before service
+    /// accounts existed are all users'
+    #[serde(default)]
+    pub principal_type: PrincipalType,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrincipalType {
+    /// A person; `sub` is a user id
+    #[default]
+    User,
+    /// A machine identity; `sub` is a service account id and `username` its name
+    Service,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Actor {
+    pub sub: Uuid,
+    pub username: String,
+    #[serde(default)]
+    pub principal_type: PrincipalType,
+    /// Whoever was already acting when this actor took over, on tokens
+    /// exchanged along a chain of calls
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Box<Actor>>,
+}
+
+impl Actor {
+    /// How the actor relates to the subject, for logs: `impersonated by` an
+    /// admin or `delegated to` a service
+    pub fn relation(&self) -> &'static str {
+        match self.principal_type {
+            PrincipalType::User => "impersonated by",
+            PrincipalType::Service => "delegated to",
+        }
+    }
+
+    /// The actor and those it took over from, most recent first
+    pub fn chain(&self) -> impl Iterator<Item = &Actor> {
+        std::iter::successors(Some(self), |actor| actor.act.as_deref())
+    }
+}
+
+impl Claims {
+    /// Whether someone other than `sub` is acting: an impersonating admin,
+    /// or a service holding an exchanged token
+    pub fn is_impersonated(&self) -> bool {
+        self.act.is_some()
+    }
+
+    pub fn is_service(&self) -> bool {
+        self.principal_type == PrincipalType::Service
+    }
+
+    /// Whether the permissions must be resolved from the roles
+    pub fn is_compact(&self) -> bool {
+        self.pv.is_some()
+    }
+}
+
+/// The permission names each role grants, as of a permissions version. The
+/// version increases whenever what any role grants may have changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RolePermissions {
+    pub version: i64,
+    pub roles: BTreeMap<String, Vec<String>>,
+}
+
+impl RolePermissions {
+    /// Permissions granted by any of `roles`, without duplicates
+    pub fn permissions_for(&self, roles: &[String]) -> Vec<String> {
+        let mut permissions: Vec<String> = Vec::new();
+        for permission in roles.iter().filter_map(|r| self.roles.get(r)).flatten() {
+            if !permissions.contains(permission) {
+                permissions.push(permission.clone());
+            }
+        }
+        permissions
+    }
+}
+
+/// The secret tokens were signed with before the last rotation of
+/// `JWT_SECRET`. Tokens it signed are still accepted until `until`, so
+/// rotating the secret does not sign everyone out at once.
+#[derive(Debug, Clone)]
+pub struct PreviousSecret {
+    pub secret: String,
+    pub until: DateTime<Utc>,
+}
+
+impl PreviousSecret {
+    /// From `JWT_PREVIOUS_SECRET` and `JWT_PREVIOUS_SECRET_UNTIL` (RFC 3339);
+    /// None when no previous secret is set
+    pub fn from_env() -> Option<Self> {
+        let secret = env::var("JWT_PREVIOUS_SECRET")
+            .ok()
+            .filter(|v| !v.is_empty())?;
+        let until = env::var("JWT_PREVIOUS_SECRET_UNTIL")
+            .ok()
+            .and_then(|v| DateTime::parse_from_rfc3339(&v).ok())
+            .expect("JWT_PREVIOUS_SECRET_UNTIL must be an RFC 3339 time when JWT_PREVIOUS_SECRET is set")
+            .with_timezone(&Utc);
+        Some(Self { secret, until })
+    }
+
+    /// Whether tokens it signed are still accepted
+    pub fn is_accepted(&self) -> bool {
+        Utc::now() < self.until
+    }
+}
+
+/// Validates an access token signed with `secret`, or with `previous` while
+/// it is still accepted
+pub fn decode_claims(
+    token: &str,
+    secret: &str,
+    previous: Option<&PreviousSecret>,
+    validation: &Validation,
+) -> Result<Claims, JwtError> {
+    match decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(secret.as_ref()),
+        validation,
+    ) {
+        Err(e) if matches!(e.kind(), ErrorKind::InvalidSignature) => match previous {
+            Some(previous) if previous.is_accepted() => decode::<Claims>(
+                token,
+                &DecodingKey::from_secret(previous.secret.as_ref()),
+                validation,
+            )
+            .map(|data| data.claims),
+            _ => Err(e),
+        },
+        result => result.map(|data| data.claims),
+    }
 }
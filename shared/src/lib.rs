@@ -1,9 +1,17 @@
+pub mod auth;
+pub mod correlation;
 pub mod errors;
 pub mod jwt;
 pub mod middleware;
+pub mod permission;
+pub mod preferences;
 pub mod types;
 
-pub use errors::{AppError, AppResult};
-pub use jwt::Claims;
-pub use middleware::LoggingMiddleware;
+pub use auth::{JwtAuth, PermissionResolver};
+pub use correlation::{RequestContext, REQUEST_ID_HEADER, TRACEPARENT_HEADER};
+pub use errors::{AppError, AppResult, FieldError, ProblemDetails};
+pub use jwt::{decode_claims, Actor, Claims, PreviousSecret, PrincipalType, RolePermissions};
+pub use middleware::{Correlation, ErrorContext, LoggingMiddleware};
+pub use permission::PermissionCheck;
+pub use preferences::{Preferences, PreferencesClient, TimeFormat, Units};
 pub use types::*;
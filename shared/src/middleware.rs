@@ -1,6 +1,11 @@
+use crate::correlation::RequestContext;
+use crate::errors::{payload_error, AppError};
 use actix_web::{
+    body::EitherBody,
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    Error,
+    error::{InternalError, PayloadError},
+    http::header::{HeaderName, HeaderValue},
+    Error, HttpMessage,
 };
 use futures_util::future::LocalBoxFuture;
 use log::info;
@@ -10,7 +15,8 @@ use std::{
     time::Instant,
 };
 
-/// Middleware for logging HTTP requests
+/// Middleware for logging HTTP requests. Inside [`Correlation`], each line
+/// names the request through `correlation::format_log`.
 pub struct LoggingMiddleware;
 
 impl<S, B> Transform<S, ServiceRequest> for LoggingMiddleware
@@ -55,9 +61,13 @@ where
         let svc = self.service.clone();
 
         Box::pin(async move {
-            let res = svc.call(req).await?;
+            let res = svc.call(req).await;
             let duration = start.elapsed();
-            let status = res.status();
+            // Errors from middleware have no response yet
+            let status = match &res {
+                Ok(res) => res.status(),
+                Err(e) => e.as_response_error().status_code(),
+            };
 
             info!(
                 "{} {} {} {}ms",
@@ -67,7 +77,156 @@ where
                 duration.as_millis()
             );
 
-            Ok(res)
+            res
         })
     }
 }
+
+/// Completes the problem bodies of [`AppError`] responses with the request's
+/// path as `instance` and, inside [`Correlation`], its request id. Bodies that
+/// could not be read become problems too, through `errors::payload_error`.
+/// Wrap the whole `App` in it so errors from other middleware are covered.
+pub struct ErrorContext;
+
+impl<S, B> Transform<S, ServiceRequest> for ErrorContext
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ErrorContextService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ErrorContextService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct ErrorContextService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for ErrorContextService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let instance = req.path().to_string();
+        let request_id = req
+            .extensions()
+            .get::<RequestContext>()
+            .map(|context| context.request_id.clone());
+        let svc = self.service.clone();
+
+        Box::pin(async move {
+            let problem =
+                |error: &AppError| error.problem_response(Some(&instance), request_id.as_deref());
+
+            match svc.call(req).await {
+                Ok(res) => {
+                    let response =
+                        res.response()
+                            .error()
+                            .and_then(|e| match e.as_error::<PayloadError>() {
+                                Some(payload) => Some(problem(&payload_error(payload))),
+                                None => e.as_error::<AppError>().map(problem),
+                            });
+                    Ok(match response {
+                        Some(response) => res.into_response(response).map_into_right_body(),
+                        None => res.map_into_left_body(),
+                    })
+                }
+                // Errors from middleware have no response yet
+                Err(e) => match e.as_error::<AppError>() {
+                    Some(error) => {
+                        let response = problem(error);
+                        Err(InternalError::from_response(error.to_string(), response).into())
+                    }
+                    None => Err(e),
+                },
+            }
+        })
+    }
+}
+
+/// Gives every request a [`RequestContext`]: the caller's `X-Request-Id` and
+/// `traceparent` when valid, new ones otherwise. The id is echoed in the
+/// `X-Request-Id` response header. Wrap the whole `App` in it, outside
+/// [`LoggingMiddleware`] and [`ErrorContext`].
+pub struct Correlation;
+
+impl<S, B> Transform<S, ServiceRequest> for Correlation
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CorrelationService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CorrelationService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct CorrelationService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for CorrelationService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let context = RequestContext::from_headers(req.headers());
+        req.extensions_mut().insert(context.clone());
+        let request_id = HeaderValue::from_str(&context.request_id).ok();
+        let svc = self.service.clone();
+
+        Box::pin(context.scope(async move {
+            let name = HeaderName::from_static("x-request-id");
+            match svc.call(req).await {
+                Ok(mut res) => {
+                    if let Some(request_id) = request_id {
+                        res.headers_mut().insert(name, request_id);
+                    }
+                    Ok(res)
+                }
+                // Errors from middleware have no response yet
+                Err(e) => {
+                    let mut response = e.error_response();
+                    if let Some(request_id) = request_id {
+                        response.headers_mut().insert(name, request_id);
+                    }
+                    Err(InternalError::from_response(e.to_string(), response).into())
+                }
+            }
+        }))
+    }
+}
@@ -1,9 +1,12 @@
+//! Route guards requiring a permission of the authenticated principal.
+
+use crate::errors::AppError;
+use crate::jwt::Claims;
 use actix_web::{
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
     Error, HttpMessage,
 };
 use futures_util::future::LocalBoxFuture;
-use shared::{AppError, Claims};
 use std::{
     future::{ready, Ready},
     rc::Rc,
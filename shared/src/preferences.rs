@@ -0,0 +1,193 @@
+use crate::correlation::Correlated;
+use crate::jwt::Claims;
+use crate::types::ApiResponse;
+use actix_web::{HttpMessage, HttpRequest};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Longest a request waits on the auth service for the caller's preferences
+/// before going ahead with the defaults
+const FETCH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a user's preferences are reused before asking again, so a burst
+/// of requests costs one call to the auth service while a change still shows
+/// up within seconds
+const PREFERENCES_TTL: Duration = Duration::from_secs(30);
+
+/// A user's defaults, kept by the auth service at `/auth/me/preferences`.
+/// The weather and time services fall back on them when a request leaves
+/// the matching query parameter out.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Preferences {
+    pub units: Option<Units>,
+    /// BCP 47 language tag, such as `en-GB`
+    pub locale: Option<String>,
+    pub time_format: Option<TimeFormat>,
+    /// IANA time zone name, such as `Europe/London`
+    pub home_timezone: Option<String>,
+    /// Most important first; the first one is the default city
+    #[serde(default)]
+    pub favourite_cities: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    /// Degrees Celsius and metres per second
+    #[default]
+    Metric,
+    /// Degrees Fahrenheit and miles per hour
+    Imperial,
+}
+
+impl Units {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Metric => "metric",
+            Self::Imperial => "imperial",
+        }
+    }
+}
+
+impl std::str::FromStr for Units {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "metric" => Ok(Self::Metric),
+            "imperial" => Ok(Self::Imperial),
+            _ => Err(format!("units must be metric or imperial, not '{s}'")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeFormat {
+    #[serde(rename = "12h")]
+    TwelveHour,
+    #[default]
+    #[serde(rename = "24h")]
+    TwentyFourHour,
+}
+
+impl TimeFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::TwelveHour => "12h",
+            Self::TwentyFourHour => "24h",
+        }
+    }
+
+    /// The `chrono` format string for a time of day
+    pub fn pattern(self) -> &'static str {
+        match self {
+            Self::TwelveHour => "%-I:%M %p",
+            Self::TwentyFourHour => "%H:%M",
+        }
+    }
+}
+
+impl std::str::FromStr for TimeFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "12h" => Ok(Self::TwelveHour),
+            "24h" => Ok(Self::TwentyFourHour),
+            _ => Err(format!("time_format must be 12h or 24h, not '{s}'")),
+        }
+    }
+}
+
+/// Reads the caller's preferences from the auth service, to fill in query
+/// parameters their request left out. Clones share the cache.
+#[derive(Clone)]
+pub struct PreferencesClient {
+    url: String,
+    client: reqwest::Client,
+    /// By user id, with when they were fetched
+    cache: Arc<RwLock<HashMap<Uuid, (Preferences, Instant)>>>,
+}
+
+impl PreferencesClient {
+    pub fn new(auth_service_url: &str) -> Self {
+        Self {
+            url: format!(
+                "{}/auth/me/preferences",
+                auth_service_url.trim_end_matches('/')
+            ),
+            client: reqwest::Client::builder()
+                .timeout(FETCH_TIMEOUT)
+                .build()
+                .expect("Failed to build HTTP client"),
+            cache: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// The preferences of the user behind an authenticated request. Service
+    /// accounts have none, and if the auth service cannot be reached the
+    /// request goes ahead with the defaults rather than failing.
+    ///
+    /// Tokens restricted to an audience (exchanged tokens) are refused by
+    /// the auth service, so requests made with them use the defaults too.
+    pub async fn for_request(&self, req: &HttpRequest) -> Preferences {
+        let Some(user_id) = req
+            .extensions()
+            .get::<Claims>()
+            .filter(|claims| !claims.is_service() && claims.aud.is_none())
+            .map(|claims| claims.sub)
+        else {
+            return Preferences::default();
+        };
+        let Some(token) = req
+            .headers()
+            .get("Authorization")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "))
+        else {
+            return Preferences::default();
+        };
+
+        let cached = self
+            .cache
+            .read()
+            .unwrap()
+            .get(&user_id)
+            .filter(|(_, fetched_at)| fetched_at.elapsed() < PREFERENCES_TTL)
+            .map(|(preferences, _)| preferences.clone());
+        if let Some(preferences) = cached {
+            return preferences;
+        }
+
+        match self.fetch(token).await {
+            Ok(preferences) => {
+                let mut cache = self.cache.write().unwrap();
+                cache.retain(|_, (_, fetched_at)| fetched_at.elapsed() < PREFERENCES_TTL);
+                cache.insert(user_id, (preferences.clone(), Instant::now()));
+                preferences
+            }
+            Err(e) => {
+                warn!("Using default preferences: {e}");
+                Preferences::default()
+            }
+        }
+    }
+
+    async fn fetch(&self, token: &str) -> Result<Preferences, reqwest::Error> {
+        let body: ApiResponse<Preferences> = self
+            .client
+            .get(&self.url)
+            .bearer_auth(token)
+            .correlated()
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(body.data)
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use shared::correlation::Correlated;
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
@@ -74,6 +75,7 @@ impl TimezoneCache {
         let client = reqwest::Client::new();
         let response = client
             .get(&url)
+            .correlated()
             .send()
             .await
             .map_err(|e| format!("Failed to fetch timezone data: {e}"))?;
@@ -1,11 +1,22 @@
+use shared::PreviousSecret;
 use std::env;
 
 #[derive(Debug, Clone)]
 pub struct Config {
-    #[allow(dead_code)] // Reserved for future service-to-service communication
     pub auth_service_url: String,
     pub jwt_secret: String,
+    /// The secret `jwt_secret` replaced, accepted until its grace window ends
+    pub jwt_previous_secret: Option<PreviousSecret>,
     pub port: u16,
+    /// Check every token with the auth service so revocations apply at once;
+    /// when off, a token stays valid until it expires
+    pub token_introspection: bool,
+    /// The `aud` this service accepts on audience-restricted tokens: the
+    /// name of its service account
+    pub token_audience: String,
+    /// Client id and secret of this service's account, with which it reads
+    /// the role permissions that compact tokens are resolved with
+    pub service_credentials: Option<(String, String)>,
 }
 
 impl Config {
@@ -14,16 +25,39 @@ impl Config {
             env::var("AUTH_SERVICE_URL").unwrap_or_else(|_| "http://localhost:8000".to_string());
 
         let jwt_secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+        let jwt_previous_secret = PreviousSecret::from_env();
 
         let port = env::var("PORT")
             .unwrap_or_else(|_| "8002".to_string())
             .parse::<u16>()
             .expect("PORT must be a valid number");
 
+        let token_introspection = env::var("TOKEN_INTROSPECTION")
+            .map(|v| !matches!(v.to_ascii_lowercase().as_str(), "0" | "false" | "no"))
+            .unwrap_or(true);
+
+        let token_audience =
+            env::var("TOKEN_AUDIENCE").unwrap_or_else(|_| "time-service".to_string());
+
+        let service_credentials = match (
+            env::var("SERVICE_CLIENT_ID").ok().filter(|v| !v.is_empty()),
+            env::var("SERVICE_CLIENT_SECRET")
+                .ok()
+                .filter(|v| !v.is_empty()),
+        ) {
+            (Some(client_id), Some(client_secret)) => Some((client_id, client_secret)),
+            (None, None) => None,
+            _ => panic!("SERVICE_CLIENT_ID and SERVICE_CLIENT_SECRET must be set together"),
+        };
+
         Self {
             auth_service_url,
             jwt_secret,
+            jwt_previous_secret,
             port,
+            token_introspection,
+            token_audience,
+            service_credentials,
         }
     }
 }
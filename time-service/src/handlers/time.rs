@@ -1,9 +1,10 @@
 use crate::cache::timezone::TimezoneData;
 use crate::cache::TimezoneCache;
-use crate::services::WorldTimeClient;
-use actix_web::{web, HttpResponse, Responder};
-use serde::Serialize;
-use shared::{ApiResponse, AppError, AppResult};
+use crate::services::{PreferencesClient, WorldTimeClient};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use shared::{ApiResponse, AppError, AppResult, TimeFormat};
 
 #[derive(Debug, Serialize)]
 pub struct TimeResponse {
@@ -12,15 +13,115 @@ pub struct TimeResponse {
     pub datetime: String,
     pub utc_offset: String,
     pub unix_time: i64,
+    /// The time of day at `datetime`, as a clock in the requested format
+    /// shows it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_time: Option<String>,
+}
+
+impl TimeResponse {
+    fn new(city: String, data: TimezoneData, time_format: TimeFormat) -> Self {
+        let local_time = DateTime::parse_from_rfc3339(&data.datetime)
+            .ok()
+            .map(|datetime| datetime.format(time_format.pattern()).to_string());
+        Self {
+            city,
+            timezone: data.timezone,
+            datetime: data.datetime,
+            utc_offset: data.utc_offset,
+            unix_time: data.unix_time,
+            local_time,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TimeQuery {
+    pub time_format: Option<String>,
+}
+
+impl TimeQuery {
+    fn time_format(&self) -> AppResult<Option<TimeFormat>> {
+        self.time_format
+            .as_deref()
+            .map(|f| f.parse().map_err(AppError::BadRequest))
+            .transpose()
+    }
+}
+
+/// `?time_format=` if given, else the caller's preferred format, else 24h
+async fn requested_format(
+    query: &TimeQuery,
+    preferences: &PreferencesClient,
+    http_req: &HttpRequest,
+) -> AppResult<TimeFormat> {
+    match query.time_format()? {
+        Some(time_format) => Ok(time_format),
+        None => Ok(preferences
+            .for_request(http_req)
+            .await
+            .time_format
+            .unwrap_or_default()),
+    }
 }
 
 pub async fn get_time_for_city(
     cache: web::Data<TimezoneCache>,
     client: web::Data<WorldTimeClient>,
+    preferences: web::Data<PreferencesClient>,
+    http_req: HttpRequest,
     path: web::Path<String>,
+    query: web::Query<TimeQuery>,
+) -> AppResult<impl Responder> {
+    let time_format = requested_format(&query, &preferences, &http_req).await?;
+    time_for_city(&cache, &client, path.into_inner(), time_format).await
+}
+
+pub async fn get_time_for_timezone(
+    cache: web::Data<TimezoneCache>,
+    client: web::Data<WorldTimeClient>,
+    preferences: web::Data<PreferencesClient>,
+    http_req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<TimeQuery>,
+) -> AppResult<impl Responder> {
+    let time_format = requested_format(&query, &preferences, &http_req).await?;
+    time_for_timezone(&cache, &client, path.into_inner(), time_format).await
+}
+
+/// `GET /time`: the time in the caller's home time zone, or failing that in
+/// their first favourite city
+pub async fn get_home_time(
+    cache: web::Data<TimezoneCache>,
+    client: web::Data<WorldTimeClient>,
+    preferences: web::Data<PreferencesClient>,
+    http_req: HttpRequest,
+    query: web::Query<TimeQuery>,
 ) -> AppResult<impl Responder> {
-    let city = path.into_inner();
+    let requested_format = query.time_format()?;
+    let preferences = preferences.for_request(&http_req).await;
+    let time_format = requested_format
+        .or(preferences.time_format)
+        .unwrap_or_default();
+
+    if let Some(timezone) = preferences.home_timezone {
+        return time_for_timezone(&cache, &client, timezone, time_format).await;
+    }
+    match preferences.favourite_cities.into_iter().next() {
+        Some(city) => time_for_city(&cache, &client, city, time_format).await,
+        None => Err(AppError::BadRequest(
+            "No city or time zone given and no home time zone or favourite cities saved"
+                .to_string(),
+        )),
+    }
+}
 
+async fn time_for_city(
+    cache: &TimezoneCache,
+    client: &WorldTimeClient,
+    city: String,
+    time_format: TimeFormat,
+) -> AppResult<HttpResponse> {
     // Map city to timezone
     let timezone = match city.to_lowercase().as_str() {
         "london" => "Europe/London",
@@ -38,13 +139,7 @@ pub async fn get_time_for_city(
 
     // Check cache first (this is the primary source)
     if let Some(cached_data) = cache.get(timezone).await {
-        let response = TimeResponse {
-            city: city.clone(),
-            timezone: cached_data.timezone,
-            datetime: cached_data.datetime,
-            utc_offset: cached_data.utc_offset,
-            unix_time: cached_data.unix_time,
-        };
+        let response = TimeResponse::new(city, cached_data, time_format);
         return Ok(HttpResponse::Ok().json(ApiResponse::new(response)));
     }
 
@@ -58,65 +153,44 @@ pub async fn get_time_for_city(
     .await
     {
         Ok(Ok(Some(api_data))) => {
-            // Cache the result for future use
-            cache
-                .set(
-                    timezone.to_string(),
-                    TimezoneData {
-                        timezone: api_data.timezone.clone(),
-                        datetime: api_data.datetime.clone(),
-                        utc_offset: api_data.utc_offset.clone(),
-                        unix_time: api_data.unixtime,
-                    },
-                )
-                .await;
-
-            let response = TimeResponse {
-                city: city.clone(),
+            let data = TimezoneData {
                 timezone: api_data.timezone,
                 datetime: api_data.datetime,
                 utc_offset: api_data.utc_offset,
                 unix_time: api_data.unixtime,
             };
-            return Ok(HttpResponse::Ok().json(ApiResponse::new(response)));
+            // Cache the result for future use
+            cache.set(timezone.to_string(), data.clone()).await;
+
+            let response = TimeResponse::new(city, data, time_format);
+            Ok(HttpResponse::Ok().json(ApiResponse::new(response)))
         }
         Ok(Ok(None)) => {
             // City not supported by API
-            return Err(AppError::NotFound(format!("City not supported: {}", city)));
+            Err(AppError::NotFound(format!("City not supported: {}", city)))
         }
         Ok(Err(e)) => {
             // API call failed - log but don't expose internal error
             log::warn!("API call failed for city {}: {}", city, e);
+            Err(unavailable(&city))
         }
         Err(_) => {
             // Timeout - API is too slow
             log::warn!("API call timed out for city: {}", city);
+            Err(timed_out(&city))
         }
     }
-
-    // If both cache and API fail, return error
-    Err(AppError::NotFound(format!(
-        "Time data not found for city: {} (cache miss and API unavailable). Please try again later.",
-        city
-    )))
 }
 
-pub async fn get_time_for_timezone(
-    cache: web::Data<TimezoneCache>,
-    client: web::Data<WorldTimeClient>,
-    path: web::Path<String>,
-) -> AppResult<impl Responder> {
-    let timezone = path.into_inner();
-
+async fn time_for_timezone(
+    cache: &TimezoneCache,
+    client: &WorldTimeClient,
+    timezone: String,
+    time_format: TimeFormat,
+) -> AppResult<HttpResponse> {
     // Check cache first (primary source)
     if let Some(cached) = cache.get(&timezone).await {
-        let response = TimeResponse {
-            city: timezone.clone(),
-            timezone: cached.timezone,
-            datetime: cached.datetime,
-            utc_offset: cached.utc_offset,
-            unix_time: cached.unix_time,
-        };
+        let response = TimeResponse::new(timezone, cached, time_format);
         return Ok(HttpResponse::Ok().json(ApiResponse::new(response)));
     }
 
@@ -129,43 +203,45 @@ pub async fn get_time_for_timezone(
     .await
     {
         Ok(Ok(timezone_data)) => {
-            // Cache the result for future use
-            cache
-                .set(
-                    timezone.clone(),
-                    TimezoneData {
-                        timezone: timezone_data.timezone.clone(),
-                        datetime: timezone_data.datetime.clone(),
-                        utc_offset: timezone_data.utc_offset.clone(),
-                        unix_time: timezone_data.unixtime,
-                    },
-                )
-                .await;
-
-            let response = TimeResponse {
-                city: timezone.clone(),
+            let data = TimezoneData {
                 timezone: timezone_data.timezone,
                 datetime: timezone_data.datetime,
                 utc_offset: timezone_data.utc_offset,
                 unix_time: timezone_data.unixtime,
             };
-            return Ok(HttpResponse::Ok().json(ApiResponse::new(response)));
+            // Cache the result for future use
+            cache.set(timezone.clone(), data.clone()).await;
+
+            let response = TimeResponse::new(timezone, data, time_format);
+            Ok(HttpResponse::Ok().json(ApiResponse::new(response)))
         }
+        Ok(Err(e)) if e.status() == Some(reqwest::StatusCode::NOT_FOUND) => Err(
+            AppError::NotFound(format!("Timezone not supported: {timezone}")),
+        ),
         Ok(Err(e)) => {
             // API call failed - log but don't expose internal error
             log::warn!("API call failed for timezone {}: {}", timezone, e);
+            Err(unavailable(&timezone))
         }
         Err(_) => {
             // Timeout - API is too slow
             log::warn!("API call timed out for timezone: {}", timezone);
+            Err(timed_out(&timezone))
         }
     }
+}
+
+/// Neither the cache nor the API has the time for `place`
+fn unavailable(place: &str) -> AppError {
+    AppError::ServiceUnavailable(format!(
+        "Time data for {place} is not cached and the time API is unavailable. Please try again later."
+    ))
+}
 
-    // If both cache and API fail, return error
-    Err(AppError::NotFound(format!(
-        "Time data not found for timezone: {} (cache miss and API unavailable). Please try again later.",
-        timezone
-    )))
+fn timed_out(place: &str) -> AppError {
+    AppError::GatewayTimeout(format!(
+        "Time data for {place} is not cached and the time API did not answer in time. Please try again later."
+    ))
 }
 
 pub async fn list_timezones(cache: web::Data<TimezoneCache>) -> AppResult<impl Responder> {
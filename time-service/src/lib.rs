@@ -6,4 +6,4 @@ pub mod services;
 
 pub use cache::TimezoneCache;
 pub use config::Config;
-pub use services::WorldTimeClient;
+pub use services::{PreferencesClient, WorldTimeClient};
@@ -7,8 +7,8 @@ mod services;
 use actix_web::{web, App, HttpServer, Responder};
 use cache::TimezoneCache;
 use config::Config;
-use log::info;
-use services::WorldTimeClient;
+use log::{info, warn};
+use services::{PreferencesClient, WorldTimeClient};
 
 async fn health_check() -> impl Responder {
     "OK"
@@ -17,7 +17,9 @@ async fn health_check() -> impl Responder {
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv::dotenv().ok();
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+    env_logger::Builder::from_env(env_logger::Env::new().default_filter_or("info"))
+        .format(shared::correlation::format_log)
+        .init();
 
     let config = Config::from_env();
 
@@ -26,6 +28,8 @@ async fn main() -> std::io::Result<()> {
     // Initialize and populate timezone cache
     let cache = web::Data::new(TimezoneCache::new());
     let client = web::Data::new(WorldTimeClient::new());
+    // Reads callers' preferences for the parameters their requests leave out
+    let preferences = web::Data::new(PreferencesClient::new(&config.auth_service_url));
 
     info!("Populating timezone cache...");
     if let Err(e) = cache.initialize().await {
@@ -49,19 +53,65 @@ async fn main() -> std::io::Result<()> {
         }
     });
 
-    let jwt_secret = config.jwt_secret.clone();
     let port = config.port;
 
+    if !config.token_introspection {
+        warn!("TOKEN_INTROSPECTION is off: revoked tokens are accepted until they expire");
+    }
+
+    // Shared by all workers so they share the cached role permissions
+    let permission_resolver =
+        config
+            .service_credentials
+            .as_ref()
+            .map(|(client_id, client_secret)| {
+                middleware::PermissionResolver::new(
+                    &config.auth_service_url,
+                    client_id,
+                    client_secret,
+                )
+            });
+    if permission_resolver.is_none() {
+        warn!("SERVICE_CLIENT_ID is not set: compact tokens are given no permissions");
+    }
+
     HttpServer::new(move || {
+        let jwt_auth = middleware::JwtAuth::new(config.jwt_secret.clone())
+            .with_previous_secret(config.jwt_previous_secret.clone())
+            .with_audience(&config.token_audience);
+        let jwt_auth = match &permission_resolver {
+            Some(resolver) => jwt_auth.with_permission_resolution(resolver.clone()),
+            None => jwt_auth,
+        };
+        let jwt_auth = if config.token_introspection {
+            jwt_auth.with_introspection(&config.auth_service_url)
+        } else {
+            jwt_auth
+        };
+
         App::new()
+            // Errors, those of middleware and extractors included, are
+            // answered as problem+json naming the failed request
+            .wrap(shared::ErrorContext)
+            .wrap(shared::LoggingMiddleware)
+            // Outermost, so the request id is known to everything inside, logs
+            // and upstream calls included
+            .wrap(shared::Correlation)
+            .app_data(web::JsonConfig::default().error_handler(shared::errors::extractor_error))
+            .app_data(web::QueryConfig::default().error_handler(shared::errors::extractor_error))
+            .app_data(web::PathConfig::default().error_handler(shared::errors::extractor_error))
             .app_data(web::Data::new(config.clone()))
             .app_data(cache.clone())
             .app_data(client.clone())
+            .app_data(preferences.clone())
             .route("/health", web::get().to(health_check))
             .service(
                 web::scope("/time")
-                    .wrap(middleware::JwtAuth::new(jwt_secret.clone()))
+                    // Middleware registered last runs first: JwtAuth must attach
+                    // the claims before PermissionCheck inspects them
                     .wrap(middleware::PermissionCheck::new("time:read".to_string()))
+                    .wrap(jwt_auth)
+                    .route("", web::get().to(handlers::time::get_home_time))
                     .route("/timezones", web::get().to(handlers::time::list_timezones))
                     .route(
                         "/timezone/{timezone}",
@@ -69,6 +119,7 @@ async fn main() -> std::io::Result<()> {
                     )
                     .route("/{city}", web::get().to(handlers::time::get_time_for_city)),
             )
+            .default_service(web::route().to(shared::errors::no_route))
     })
     .bind(("0.0.0.0", port))?
     .run()
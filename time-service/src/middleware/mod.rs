@@ -1,5 +0,0 @@
-pub mod jwt;
-pub mod permission;
-
-pub use jwt::JwtAuth;
-pub use permission::PermissionCheck;
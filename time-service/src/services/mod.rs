@@ -1,3 +1,4 @@
 pub mod worldtime;
 
+pub use shared::PreferencesClient;
 pub use worldtime::WorldTimeClient;
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use shared::correlation::Correlated;
 
 const BASE_URL: &str = "http://worldtimeapi.org/api";
 
@@ -30,7 +31,14 @@ impl WorldTimeClient {
 
     pub async fn get_timezone(&self, timezone: &str) -> Result<TimezoneResponse, reqwest::Error> {
         let url = format!("{BASE_URL}/timezone/{timezone}");
-        let response = self.client.get(&url).send().await?;
+        // Unknown time zones fail with the API's 404 status
+        let response = self
+            .client
+            .get(&url)
+            .correlated()
+            .send()
+            .await?
+            .error_for_status()?;
 
         let data: TimezoneResponse = response.json().await?;
         Ok(data)
@@ -1,10 +1,13 @@
-use actix_web::{http::StatusCode, test, web, App};
+use actix_web::{http::StatusCode, test, web, App, HttpResponse};
 use chrono::Utc;
 use jsonwebtoken::{encode, EncodingKey, Header};
-use shared::Claims;
-use time_service::handlers::time::{get_time_for_city, get_time_for_timezone, list_timezones};
+use shared::{Claims, PrincipalType};
+use time_service::cache::timezone::TimezoneData;
+use time_service::handlers::time::{
+    get_home_time, get_time_for_city, get_time_for_timezone, list_timezones,
+};
 use time_service::middleware::JwtAuth;
-use time_service::{Config, TimezoneCache, WorldTimeClient};
+use time_service::{Config, PreferencesClient, TimezoneCache, WorldTimeClient};
 use uuid::Uuid;
 
 // Helper function to generate a test JWT token
@@ -16,6 +19,11 @@ fn generate_test_token(jwt_secret: &str) -> String {
         permissions: vec!["time:read".to_string()],
         exp: (Utc::now().timestamp() + 3600), // 1 hour from now
         iat: Utc::now().timestamp(),
+        sid: None,
+        act: None,
+        aud: None,
+        pv: None,
+        principal_type: PrincipalType::User,
     };
 
     let header = Header::default();
@@ -34,6 +42,9 @@ async fn test_get_time_for_timezone() {
             .app_data(web::Data::new(config.clone()))
             .app_data(cache)
             .app_data(client)
+            .app_data(web::Data::new(PreferencesClient::new(
+                &config.auth_service_url,
+            )))
             .service(
                 web::scope("/time")
                     .wrap(JwtAuth::new(config.jwt_secret.clone()))
@@ -57,8 +68,9 @@ async fn test_get_time_for_timezone() {
     // Also might return 404 if timezone not found
     assert!(
         resp.status() == StatusCode::OK
-            || resp.status() == StatusCode::INTERNAL_SERVER_ERROR
             || resp.status() == StatusCode::NOT_FOUND
+            || resp.status() == StatusCode::SERVICE_UNAVAILABLE
+            || resp.status() == StatusCode::GATEWAY_TIMEOUT
     );
 
     if resp.status() == StatusCode::OK {
@@ -86,6 +98,9 @@ async fn test_get_time_for_city() {
             .app_data(web::Data::new(config.clone()))
             .app_data(cache)
             .app_data(client)
+            .app_data(web::Data::new(PreferencesClient::new(
+                &config.auth_service_url,
+            )))
             .service(
                 web::scope("/time")
                     .wrap(JwtAuth::new(config.jwt_secret.clone()))
@@ -105,11 +120,12 @@ async fn test_get_time_for_city() {
 
     let resp = test::call_service(&app, req).await;
 
-    // This might return 404 if city is not found, 200 if found, or 500 if API error
+    // This might return 404 if city is not found, 200 if found, or 503/504 if the API fails
     assert!(
         resp.status() == StatusCode::OK
             || resp.status() == StatusCode::NOT_FOUND
-            || resp.status() == StatusCode::INTERNAL_SERVER_ERROR
+            || resp.status() == StatusCode::SERVICE_UNAVAILABLE
+            || resp.status() == StatusCode::GATEWAY_TIMEOUT
     );
 
     if resp.status() == StatusCode::OK {
@@ -136,6 +152,9 @@ async fn test_list_timezones() {
             .app_data(web::Data::new(config.clone()))
             .app_data(cache)
             .app_data(client)
+            .app_data(web::Data::new(PreferencesClient::new(
+                &config.auth_service_url,
+            )))
             .service(
                 web::scope("/time")
                     .wrap(JwtAuth::new(config.jwt_secret.clone()))
@@ -177,6 +196,9 @@ async fn test_get_time_for_timezone_caching() {
             .app_data(web::Data::new(config.clone()))
             .app_data(cache)
             .app_data(client)
+            .app_data(web::Data::new(PreferencesClient::new(
+                &config.auth_service_url,
+            )))
             .service(
                 web::scope("/time")
                     .wrap(JwtAuth::new(config.jwt_secret.clone()))
@@ -215,3 +237,126 @@ async fn test_get_time_for_timezone_caching() {
         assert_eq!(body1, body2);
     }
 }
+
+#[actix_web::test]
+async fn test_preferences_fill_in_time_format_and_place() {
+    let config = Config::from_env();
+    let token = generate_test_token(&config.jwt_secret);
+    let traveller = generate_test_token(&config.jwt_secret);
+    let stranger = generate_test_token(&config.jwt_secret);
+
+    // Stands in for the auth service: `token`'s user has a home time zone,
+    // `traveller`'s only favourite cities, and nobody else has preferences
+    let (home, away) = (token.clone(), traveller.clone());
+    let stub = actix_web::HttpServer::new(move || {
+        let (home, away) = (home.clone(), away.clone());
+        App::new().route(
+            "/auth/me/preferences",
+            web::get().to(move |req: actix_web::HttpRequest| {
+                let bearer = req
+                    .headers()
+                    .get("Authorization")
+                    .and_then(|h| h.to_str().ok())
+                    .and_then(|h| h.strip_prefix("Bearer "))
+                    .unwrap_or_default()
+                    .to_string();
+                let preferences = if bearer == home {
+                    Some(serde_json::json!({
+                        "time_format": "12h",
+                        "home_timezone": "Asia/Tokyo",
+                        "favourite_cities": ["London"],
+                    }))
+                } else if bearer == away {
+                    Some(serde_json::json!({ "favourite_cities": ["London"] }))
+                } else {
+                    None
+                };
+                async move {
+                    match preferences {
+                        Some(data) => HttpResponse::Ok().json(serde_json::json!({ "data": data })),
+                        None => HttpResponse::Unauthorized().finish(),
+                    }
+                }
+            }),
+        )
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let auth_service_url = format!("http://{}", stub.addrs()[0]);
+    actix_web::rt::spawn(stub.run());
+
+    // Cached times, so the world time API is not called
+    let cache = web::Data::new(TimezoneCache::new());
+    for (timezone, datetime, utc_offset) in [
+        ("Asia/Tokyo", "2024-03-01T21:05:00.000000+09:00", "+09:00"),
+        (
+            "Europe/London",
+            "2024-03-01T12:05:00.000000+00:00",
+            "+00:00",
+        ),
+    ] {
+        cache
+            .set(
+                timezone.to_string(),
+                TimezoneData {
+                    timezone: timezone.to_string(),
+                    datetime: datetime.to_string(),
+                    utc_offset: utc_offset.to_string(),
+                    unix_time: 1_709_294_700,
+                },
+            )
+            .await;
+    }
+
+    let app = test::init_service(
+        App::new()
+            .app_data(cache)
+            .app_data(web::Data::new(WorldTimeClient::new()))
+            .app_data(web::Data::new(PreferencesClient::new(&auth_service_url)))
+            .service(
+                web::scope("/time")
+                    .wrap(JwtAuth::new(config.jwt_secret.clone()))
+                    .route("", web::get().to(get_home_time))
+                    .route("/timezone/{timezone}", web::get().to(get_time_for_timezone))
+                    .route("/{city}", web::get().to(get_time_for_city)),
+            ),
+    )
+    .await;
+    let get = |uri: &str, token: &str| {
+        test::TestRequest::get()
+            .uri(uri)
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .to_request()
+    };
+    async fn data(resp: actix_web::dev::ServiceResponse) -> serde_json::Value {
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        body["data"].clone()
+    }
+
+    // The home time zone, in the preferred format
+    let home = data(test::call_service(&app, get("/time", &token)).await).await;
+    assert_eq!(home["timezone"], "Asia/Tokyo");
+    assert_eq!(home["local_time"], "9:05 PM");
+
+    // A query parameter wins over the preference
+    let london =
+        data(test::call_service(&app, get("/time/London?time_format=24h", &token)).await).await;
+    assert_eq!(london["local_time"], "12:05");
+    let london = data(test::call_service(&app, get("/time/London", &token)).await).await;
+    assert_eq!(london["local_time"], "12:05 PM");
+    let resp = test::call_service(&app, get("/time/London?time_format=13h", &token)).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // Without a home time zone the first favourite city stands in
+    let away = data(test::call_service(&app, get("/time", &traveller)).await).await;
+    assert_eq!(away["timezone"], "Europe/London");
+    assert_eq!(away["local_time"], "12:05");
+
+    // Without preferences there is nowhere to default to
+    let resp = test::call_service(&app, get("/time", &stranger)).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let tokyo = data(test::call_service(&app, get("/time/Tokyo", &stranger)).await).await;
+    assert_eq!(tokyo["local_time"], "21:05");
+}
@@ -1,30 +1,65 @@
 use crate::cache::WeatherCache;
-use crate::services::WeatherAggregator;
-use actix_web::{web, HttpResponse, Responder};
-use serde_json::json;
-use shared::{ApiResponse, AppError, AppResult};
+use crate::services::{PreferencesClient, WeatherAggregator};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use serde_json::{json, Value};
+use shared::{ApiResponse, AppError, AppResult, Units};
+use std::collections::HashMap;
 
-pub async fn get_weather(
-    cache: web::Data<WeatherCache>,
-    aggregator: web::Data<WeatherAggregator>,
-    path: web::Path<String>,
-    query: web::Query<std::collections::HashMap<String, String>>,
-) -> AppResult<impl Responder> {
-    let city = path.into_inner();
+const MPH_PER_METRE_PER_SECOND: f64 = 2.236_936;
+
+fn parse_units(query: &HashMap<String, String>) -> AppResult<Option<Units>> {
+    query
+        .get("units")
+        .map(|u| u.parse().map_err(AppError::BadRequest))
+        .transpose()
+}
+
+/// Converts a reading's temperature and wind speed from the metric units
+/// the providers report in
+fn convert_reading(reading: &mut Value, units: Units) {
+    if units == Units::Metric {
+        return;
+    }
+    if let Some(celsius) = reading.get("temperature").and_then(Value::as_f64) {
+        reading["temperature"] = json!(celsius * 9.0 / 5.0 + 32.0);
+    }
+    if let Some(speed) = reading.get("wind_speed").and_then(Value::as_f64) {
+        reading["wind_speed"] = json!(speed * MPH_PER_METRE_PER_SECOND);
+    }
+}
+
+/// The cached response converted to `units`, with the units named
+fn in_units(mut data: Value, units: Units) -> Value {
+    if let Some(aggregated) = data.get_mut("aggregated") {
+        convert_reading(aggregated, units);
+    }
+    if let Some(Value::Array(sources)) = data.get_mut("sources") {
+        for source in sources {
+            convert_reading(source, units);
+        }
+    }
+    data["units"] = json!(units.as_str());
+    data
+}
+
+async fn weather_for(
+    cache: &WeatherCache,
+    aggregator: &WeatherAggregator,
+    city: String,
+    query: &HashMap<String, String>,
+    units: Units,
+) -> AppResult<HttpResponse> {
     let force_refresh = query.get("cache").map(|v| v == "false").unwrap_or(false);
 
-    // Check cache first (unless force refresh)
+    // Check cache first (unless force refresh); it holds metric readings
     if !force_refresh {
         if let Some(cached_data) = cache.get(&city) {
-            return Ok(HttpResponse::Ok().json(ApiResponse::new(cached_data)));
+            return Ok(HttpResponse::Ok().json(ApiResponse::new(in_units(cached_data, units))));
         }
     }
 
     // Fetch fresh data
-    let aggregated = aggregator
-        .aggregate_weather(&city)
-        .await
-        .map_err(|e| AppError::Internal(format!("Failed to aggregate weather: {e}")))?;
+    let aggregated = aggregator.aggregate_weather(&city).await?;
 
     let response_data = json!({
         "city": aggregated.city,
@@ -39,9 +74,53 @@ pub async fn get_weather(
     });
 
     // Cache the result
-    cache.set(city.clone(), response_data.clone());
+    cache.set(city, response_data.clone());
+
+    Ok(HttpResponse::Ok().json(ApiResponse::new(in_units(response_data, units))))
+}
+
+/// `GET /weather/{city}`: in `?units=`, else the caller's preferred units,
+/// else metric
+pub async fn get_weather(
+    cache: web::Data<WeatherCache>,
+    aggregator: web::Data<WeatherAggregator>,
+    preferences: web::Data<PreferencesClient>,
+    http_req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<HashMap<String, String>>,
+) -> AppResult<impl Responder> {
+    let units = match parse_units(&query)? {
+        Some(units) => units,
+        None => preferences
+            .for_request(&http_req)
+            .await
+            .units
+            .unwrap_or_default(),
+    };
+
+    weather_for(&cache, &aggregator, path.into_inner(), &query, units).await
+}
+
+/// `GET /weather`: the weather in the caller's first favourite city
+pub async fn get_favourite_weather(
+    cache: web::Data<WeatherCache>,
+    aggregator: web::Data<WeatherAggregator>,
+    preferences: web::Data<PreferencesClient>,
+    http_req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> AppResult<impl Responder> {
+    let requested_units = parse_units(&query)?;
+    let preferences = preferences.for_request(&http_req).await;
+    let city = preferences
+        .favourite_cities
+        .into_iter()
+        .next()
+        .ok_or_else(|| {
+            AppError::BadRequest("No city given and no favourite cities saved".to_string())
+        })?;
+    let units = requested_units.or(preferences.units).unwrap_or_default();
 
-    Ok(HttpResponse::Ok().json(ApiResponse::new(response_data)))
+    weather_for(&cache, &aggregator, city, &query, units).await
 }
 
 pub async fn get_weather_providers(
@@ -50,10 +129,7 @@ pub async fn get_weather_providers(
 ) -> AppResult<impl Responder> {
     let city = path.into_inner();
 
-    let aggregated = aggregator
-        .aggregate_weather(&city)
-        .await
-        .map_err(|e| AppError::Internal(format!("Failed to aggregate weather: {e}")))?;
+    let aggregated = aggregator.aggregate_weather(&city).await?;
 
     Ok(HttpResponse::Ok().json(ApiResponse::new(aggregated.sources)))
 }
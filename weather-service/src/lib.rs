@@ -7,4 +7,4 @@ pub mod services;
 pub use cache::WeatherCache;
 pub use config::Config;
 pub use handlers::weather;
-pub use services::{RateLimiter, WeatherAggregator};
+pub use services::{PreferencesClient, RateLimiter, WeatherAggregator};
@@ -7,8 +7,9 @@ mod services;
 use actix_web::{web, App, HttpServer, Responder};
 use cache::WeatherCache;
 use config::Config;
-use log::info;
-use services::{RateLimiter, WeatherAggregator};
+use log::{info, warn};
+use services::{PreferencesClient, RateLimiter, WeatherAggregator};
+use std::time::Duration;
 
 async fn health_check() -> impl Responder {
     "OK"
@@ -17,7 +18,9 @@ async fn health_check() -> impl Responder {
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv::dotenv().ok();
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+    env_logger::Builder::from_env(env_logger::Env::new().default_filter_or("info"))
+        .format(shared::correlation::format_log)
+        .init();
 
     let config = Config::from_env();
 
@@ -26,31 +29,82 @@ async fn main() -> std::io::Result<()> {
     // Initialize cache (30 minute TTL)
     let cache = web::Data::new(WeatherCache::new(1800));
 
-    // Initialize rate limiter (1 second minimum delay)
-    let rate_limiter = RateLimiter::new(1);
+    // Initialize rate limiter (1 second minimum delay); requests that would
+    // queue for longer than 10 seconds are refused with 429
+    let rate_limiter = RateLimiter::new(1).with_max_wait(Duration::from_secs(10));
 
     // Initialize aggregator
     let aggregator = web::Data::new(WeatherAggregator::new(rate_limiter));
 
-    let jwt_secret = config.jwt_secret.clone();
+    // Reads callers' preferences for the parameters their requests leave out
+    let preferences = web::Data::new(PreferencesClient::new(&config.auth_service_url));
+
     let port = config.port;
 
+    if !config.token_introspection {
+        warn!("TOKEN_INTROSPECTION is off: revoked tokens are accepted until they expire");
+    }
+
+    // Shared by all workers so they share the cached role permissions
+    let permission_resolver =
+        config
+            .service_credentials
+            .as_ref()
+            .map(|(client_id, client_secret)| {
+                middleware::PermissionResolver::new(
+                    &config.auth_service_url,
+                    client_id,
+                    client_secret,
+                )
+            });
+    if permission_resolver.is_none() {
+        warn!("SERVICE_CLIENT_ID is not set: compact tokens are given no permissions");
+    }
+
     HttpServer::new(move || {
+        let jwt_auth = middleware::JwtAuth::new(config.jwt_secret.clone())
+            .with_previous_secret(config.jwt_previous_secret.clone())
+            .with_audience(&config.token_audience);
+        let jwt_auth = match &permission_resolver {
+            Some(resolver) => jwt_auth.with_permission_resolution(resolver.clone()),
+            None => jwt_auth,
+        };
+        let jwt_auth = if config.token_introspection {
+            jwt_auth.with_introspection(&config.auth_service_url)
+        } else {
+            jwt_auth
+        };
+
         App::new()
+            // Errors, those of middleware and extractors included, are
+            // answered as problem+json naming the failed request
+            .wrap(shared::ErrorContext)
+            .wrap(shared::LoggingMiddleware)
+            // Outermost, so the request id is known to everything inside, logs
+            // and upstream calls included
+            .wrap(shared::Correlation)
+            .app_data(web::JsonConfig::default().error_handler(shared::errors::extractor_error))
+            .app_data(web::QueryConfig::default().error_handler(shared::errors::extractor_error))
+            .app_data(web::PathConfig::default().error_handler(shared::errors::extractor_error))
             .app_data(web::Data::new(config.clone()))
             .app_data(cache.clone())
             .app_data(aggregator.clone())
+            .app_data(preferences.clone())
             .route("/health", web::get().to(health_check))
             .service(
                 web::scope("/weather")
-                    .wrap(middleware::JwtAuth::new(jwt_secret.clone()))
+                    // Middleware registered last runs first: JwtAuth must attach
+                    // the claims before PermissionCheck inspects them
                     .wrap(middleware::PermissionCheck::new("weather:read".to_string()))
+                    .wrap(jwt_auth)
+                    .route("", web::get().to(handlers::weather::get_favourite_weather))
                     .route("/{city}", web::get().to(handlers::weather::get_weather))
                     .route(
                         "/{city}/providers",
                         web::get().to(handlers::weather::get_weather_providers),
                     ),
             )
+            .default_service(web::route().to(shared::errors::no_route))
     })
     .bind(("0.0.0.0", port))?
     .run()
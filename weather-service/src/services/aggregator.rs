@@ -2,6 +2,7 @@ use crate::services::providers::{OpenMeteoProvider, WttrInProvider};
 use crate::services::{RateLimiter, WeatherProvider};
 use serde::Serialize;
 use serde_json::Value;
+use shared::AppError;
 use std::collections::HashMap;
 
 /// Aggregates weather data from multiple providers
@@ -37,14 +38,28 @@ impl WeatherAggregator {
     }
 
     /// Aggregate weather data from all available providers for a given city
-    /// Returns aggregated temperature, condition, humidity, and wind speed
-    pub async fn aggregate_weather(&self, city: &str) -> Result<AggregatedWeather, String> {
-        // Check rate limits for both providers concurrently
-        let (_, _) = tokio::join!(
+    /// Returns aggregated temperature, condition, humidity, and wind speed.
+    /// Fails with a gateway timeout if every provider timed out, service
+    /// unavailable if any failed, not found if none knew the city, and too
+    /// many requests if the providers are booked up.
+    pub async fn aggregate_weather(&self, city: &str) -> Result<AggregatedWeather, AppError> {
+        // Check rate limits for both providers concurrently; refuse rather
+        // than queue for longer than the limiter allows
+        let (wttrin_slot, openmeteo_slot) = tokio::join!(
             self.rate_limiter.wait_if_needed(&WeatherProvider::WttrIn),
             self.rate_limiter
                 .wait_if_needed(&WeatherProvider::OpenMeteo)
         );
+        if let Some(wait) = [wttrin_slot.err(), openmeteo_slot.err()]
+            .into_iter()
+            .flatten()
+            .max()
+        {
+            return Err(AppError::TooManyRequests(
+                "Weather providers are busy; try again shortly".to_string(),
+                wait.as_secs().max(1),
+            ));
+        }
 
         // Fetch from both providers concurrently with timeout handling
         const API_TIMEOUT_SECS: u64 = 10;
@@ -60,6 +75,8 @@ impl WeatherAggregator {
         let mut conditions = Vec::new();
         let mut humidities = Vec::new();
         let mut wind_speeds = Vec::new();
+        let mut failures = 0;
+        let mut timeouts = 0;
 
         // Process wttr.in result with error handling
         match wttrin_result {
@@ -83,9 +100,11 @@ impl WeatherAggregator {
                 log::debug!("wttr.in returned no data for city: {}", city);
             }
             Ok(Err(e)) => {
+                failures += 1;
                 log::warn!("wttr.in API error for city {}: {}", city, e);
             }
             Err(_) => {
+                timeouts += 1;
                 log::warn!(
                     "wttr.in API timeout for city: {} (exceeded {}s)",
                     city,
@@ -113,9 +132,11 @@ impl WeatherAggregator {
                 log::debug!("OpenMeteo returned no data for city: {}", city);
             }
             Ok(Err(e)) => {
+                failures += 1;
                 log::warn!("OpenMeteo API error for city {}: {}", city, e);
             }
             Err(_) => {
+                timeouts += 1;
                 log::warn!(
                     "OpenMeteo API timeout for city: {} (exceeded {}s)",
                     city,
@@ -125,7 +146,18 @@ impl WeatherAggregator {
         }
 
         if sources.is_empty() {
-            return Err("No weather data available from any provider".to_string());
+            // Both providers timing out is slowness upstream, not an outage
+            return Err(if timeouts == 2 {
+                AppError::GatewayTimeout(format!(
+                    "No weather provider answered within {API_TIMEOUT_SECS}s"
+                ))
+            } else if failures + timeouts > 0 {
+                AppError::ServiceUnavailable(
+                    "No weather data available from any provider".to_string(),
+                )
+            } else {
+                AppError::NotFound(format!("No weather data found for city: {city}"))
+            });
         }
 
         // Aggregate data
@@ -4,3 +4,4 @@ pub mod rate_limiter;
 
 pub use aggregator::WeatherAggregator;
 pub use rate_limiter::{RateLimiter, WeatherProvider};
+pub use shared::PreferencesClient;
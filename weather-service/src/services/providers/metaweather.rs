@@ -1,4 +1,5 @@
 use serde::Deserialize;
+use shared::correlation::Correlated;
 use std::collections::HashMap;
 
 const BASE_URL: &str = "https://www.metaweather.com/api";
@@ -52,7 +53,7 @@ impl MetaWeatherProvider {
 
     pub async fn search_location(&self, city: &str) -> Result<Option<i64>, reqwest::Error> {
         let url = format!("{}/location/search/?query={}", BASE_URL, city);
-        let response = self.client.get(&url).send().await?;
+        let response = self.client.get(&url).correlated().send().await?;
         
         if response.status().is_success() {
             let locations: Vec<LocationSearchResponse> = response.json().await?;
@@ -64,7 +65,7 @@ impl MetaWeatherProvider {
 
     pub async fn get_weather(&self, woeid: i64) -> Result<serde_json::Value, reqwest::Error> {
         let url = format!("{}/location/{}/", BASE_URL, woeid);
-        let response = self.client.get(&url).send().await?;
+        let response = self.client.get(&url).correlated().send().await?;
         
         let response = response.error_for_status()?;
         let data: ConsolidatedWeather = response.json().await?;
@@ -74,7 +75,7 @@ impl MetaWeatherProvider {
             // Create a reqwest error by making a dummy request that will fail
             // This is a workaround since reqwest::Error doesn't have a simple constructor
             let dummy_url = "http://invalid-url-for-error-creation";
-            let _ = self.client.get(dummy_url).send().await?;
+            let _ = self.client.get(dummy_url).correlated().send().await?;
             unreachable!();
         }
         
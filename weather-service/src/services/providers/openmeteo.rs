@@ -1,4 +1,5 @@
 use serde::Deserialize;
+use shared::correlation::Correlated;
 use std::collections::HashMap;
 
 const BASE_URL: &str = "https://api.open-meteo.com/v1";
@@ -56,7 +57,7 @@ impl OpenMeteoProvider {
 
     pub async fn geocode_city(&self, city: &str) -> Result<Option<(f64, f64)>, reqwest::Error> {
         let url = format!("https://geocoding-api.open-meteo.com/v1/search?name={city}");
-        let response = self.client.get(&url).send().await?;
+        let response = self.client.get(&url).correlated().send().await?;
 
         if response.status().is_success() {
             let data: GeocodingResponse = response.json().await?;
@@ -78,7 +79,7 @@ impl OpenMeteoProvider {
         let url = format!(
             "{BASE_URL}/forecast?latitude={latitude}&longitude={longitude}&current_weather=true"
         );
-        let response = self.client.get(&url).send().await?;
+        let response = self.client.get(&url).correlated().send().await?;
         let response = response.error_for_status()?;
         let data: WeatherResponse = response.json().await?;
 
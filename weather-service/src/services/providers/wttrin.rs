@@ -1,4 +1,5 @@
 use serde::Deserialize;
+use shared::correlation::Correlated;
 use std::collections::HashMap;
 
 const BASE_URL: &str = "https://wttr.in";
@@ -91,7 +92,7 @@ impl WttrInProvider {
     ) -> Result<Option<serde_json::Value>, reqwest::Error> {
         // wttr.in API format: https://wttr.in/{city}?format=j1
         let url = format!("{BASE_URL}/{city}?format=j1");
-        let response = self.client.get(&url).send().await?;
+        let response = self.client.get(&url).correlated().send().await?;
 
         if !response.status().is_success() {
             return Ok(None);
@@ -18,49 +18,64 @@ impl WeatherProvider {
     }
 }
 
+/// Spaces out calls to each provider by reserving the next free slot, and
+/// refuses a call whose slot is further away than `max_wait`
 pub struct RateLimiter {
-    last_calls: Arc<Mutex<HashMap<String, Instant>>>,
+    next_slots: Arc<Mutex<HashMap<String, Instant>>>,
     min_delay: Duration,
+    max_wait: Duration,
 }
 
+/// Longest a request queues for a provider slot by default
+const DEFAULT_MAX_WAIT_SECONDS: u64 = 10;
+
 impl RateLimiter {
     pub fn new(min_delay_seconds: u64) -> Self {
         Self {
-            last_calls: Arc::new(Mutex::new(HashMap::new())),
+            next_slots: Arc::new(Mutex::new(HashMap::new())),
             min_delay: Duration::from_secs(min_delay_seconds),
+            max_wait: Duration::from_secs(DEFAULT_MAX_WAIT_SECONDS),
         }
     }
 
-    pub async fn wait_if_needed(&self, provider: &WeatherProvider) {
+    pub fn with_max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = max_wait;
+        self
+    }
+
+    /// Waits for the provider's next slot, or returns how long that slot is
+    /// away without taking it when that exceeds the maximum wait
+    pub async fn wait_if_needed(&self, provider: &WeatherProvider) -> Result<(), Duration> {
         let provider_name = provider.as_str();
 
-        // Check if we need to wait
-        {
-            let last_calls = self.last_calls.lock().await;
-            if let Some(last_call) = last_calls.get(provider_name) {
-                let elapsed = last_call.elapsed();
-                if elapsed < self.min_delay {
-                    let wait_time = self.min_delay - elapsed;
-                    drop(last_calls); // Release lock before sleeping
-                    tokio::time::sleep(wait_time).await;
-                }
+        let wait = {
+            let mut next_slots = self.next_slots.lock().await;
+            let now = Instant::now();
+            let slot = next_slots
+                .get(provider_name)
+                .copied()
+                .filter(|slot| *slot > now)
+                .unwrap_or(now);
+            let wait = slot - now;
+            if wait > self.max_wait {
+                return Err(wait);
             }
-        }
+            next_slots.insert(provider_name.to_string(), slot + self.min_delay);
+            wait
+        };
 
-        // Update last call time
-        let mut last_calls = self.last_calls.lock().await;
-        last_calls.insert(provider_name.to_string(), Instant::now());
+        // The slot is reserved, so sleep without holding the lock
+        tokio::time::sleep(wait).await;
+        Ok(())
     }
 
     #[allow(dead_code)] // Public API method for checking rate limit status
     pub async fn can_make_request(&self, provider: &WeatherProvider) -> bool {
-        let provider_name = provider.as_str();
-        let last_calls = self.last_calls.lock().await;
+        let next_slots = self.next_slots.lock().await;
 
-        if let Some(last_call) = last_calls.get(provider_name) {
-            last_call.elapsed() >= self.min_delay
-        } else {
-            true
+        match next_slots.get(provider.as_str()) {
+            Some(slot) => *slot <= Instant::now(),
+            None => true,
         }
     }
 }
@@ -1,11 +1,18 @@
-use actix_web::{http::StatusCode, test, web, App};
+use actix_web::{http::StatusCode, test, web, App, HttpResponse};
 use chrono::Utc;
 use jsonwebtoken::{encode, EncodingKey, Header};
-use shared::Claims;
+use shared::{ApiResponse, Claims, PrincipalType, RolePermissions};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use uuid::Uuid;
-use weather_service::handlers::weather::{get_weather, get_weather_providers};
-use weather_service::middleware::JwtAuth;
-use weather_service::{Config, RateLimiter, WeatherAggregator, WeatherCache};
+use weather_service::handlers::weather::{
+    get_favourite_weather, get_weather, get_weather_providers,
+};
+use weather_service::middleware::{JwtAuth, PermissionCheck, PermissionResolver};
+use weather_service::services::WeatherProvider;
+use weather_service::{Config, PreferencesClient, RateLimiter, WeatherAggregator, WeatherCache};
 
 // Helper function to generate a test JWT token
 fn generate_test_token(jwt_secret: &str) -> String {
@@ -16,6 +23,11 @@ fn generate_test_token(jwt_secret: &str) -> String {
         permissions: vec!["weather:read".to_string()],
         exp: (Utc::now().timestamp() + 3600), // 1 hour from now
         iat: Utc::now().timestamp(),
+        sid: None,
+        act: None,
+        aud: None,
+        pv: None,
+        principal_type: PrincipalType::User,
     };
 
     let header = Header::default();
@@ -35,6 +47,9 @@ async fn test_get_weather() {
             .app_data(web::Data::new(config.clone()))
             .app_data(cache)
             .app_data(aggregator)
+            .app_data(web::Data::new(PreferencesClient::new(
+                &config.auth_service_url,
+            )))
             .service(
                 web::scope("/weather")
                     .wrap(JwtAuth::new(config.jwt_secret.clone()))
@@ -54,8 +69,11 @@ async fn test_get_weather() {
     let resp = test::call_service(&app, req).await;
 
     // Weather API might fail or succeed depending on external API availability
-    // So we check for either success or internal server error
-    assert!(resp.status() == StatusCode::OK || resp.status() == StatusCode::INTERNAL_SERVER_ERROR);
+    // So we check for either success or the upstream being unavailable
+    assert!(matches!(
+        resp.status(),
+        StatusCode::OK | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    ));
 
     if resp.status() == StatusCode::OK {
         let body: serde_json::Value = test::read_body_json(resp).await;
@@ -84,6 +102,9 @@ async fn test_get_weather_with_cache() {
             .app_data(web::Data::new(config.clone()))
             .app_data(cache)
             .app_data(aggregator)
+            .app_data(web::Data::new(PreferencesClient::new(
+                &config.auth_service_url,
+            )))
             .service(
                 web::scope("/weather")
                     .wrap(JwtAuth::new(config.jwt_secret.clone()))
@@ -134,6 +155,9 @@ async fn test_get_weather_force_refresh() {
             .app_data(web::Data::new(config.clone()))
             .app_data(cache)
             .app_data(aggregator)
+            .app_data(web::Data::new(PreferencesClient::new(
+                &config.auth_service_url,
+            )))
             .service(
                 web::scope("/weather")
                     .wrap(JwtAuth::new(config.jwt_secret.clone()))
@@ -154,7 +178,10 @@ async fn test_get_weather_force_refresh() {
     let resp = test::call_service(&app, req).await;
 
     // Should attempt to fetch fresh data
-    assert!(resp.status() == StatusCode::OK || resp.status() == StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(matches!(
+        resp.status(),
+        StatusCode::OK | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    ));
 }
 
 #[tokio::test]
@@ -169,6 +196,9 @@ async fn test_get_weather_providers() {
             .app_data(web::Data::new(config.clone()))
             .app_data(cache)
             .app_data(aggregator)
+            .app_data(web::Data::new(PreferencesClient::new(
+                &config.auth_service_url,
+            )))
             .service(
                 web::scope("/weather")
                     .wrap(JwtAuth::new(config.jwt_secret.clone()))
@@ -188,7 +218,10 @@ async fn test_get_weather_providers() {
     let resp = test::call_service(&app, req).await;
 
     // Weather API might fail or succeed depending on external API availability
-    assert!(resp.status() == StatusCode::OK || resp.status() == StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(matches!(
+        resp.status(),
+        StatusCode::OK | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    ));
 
     if resp.status() == StatusCode::OK {
         let body: serde_json::Value = test::read_body_json(resp).await;
@@ -212,6 +245,9 @@ async fn test_get_weather_different_cities() {
             .app_data(web::Data::new(config.clone()))
             .app_data(cache)
             .app_data(aggregator)
+            .app_data(web::Data::new(PreferencesClient::new(
+                &config.auth_service_url,
+            )))
             .service(
                 web::scope("/weather")
                     .wrap(JwtAuth::new(config.jwt_secret.clone()))
@@ -234,8 +270,629 @@ async fn test_get_weather_different_cities() {
         let resp = test::call_service(&app, req).await;
 
         // All should either succeed or fail together (if API is down)
-        assert!(
-            resp.status() == StatusCode::OK || resp.status() == StatusCode::INTERNAL_SERVER_ERROR
+        assert!(matches!(
+            resp.status(),
+            StatusCode::OK | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+        ));
+    }
+}
+
+#[tokio::test]
+async fn test_rate_limiter_refuses_beyond_max_wait() {
+    let rate_limiter = RateLimiter::new(60).with_max_wait(Duration::from_secs(5));
+
+    assert!(rate_limiter
+        .wait_if_needed(&WeatherProvider::WttrIn)
+        .await
+        .is_ok());
+    // The next wttr.in slot is a minute away, the other provider is free
+    let wait = rate_limiter
+        .wait_if_needed(&WeatherProvider::WttrIn)
+        .await
+        .unwrap_err();
+    assert!(wait > Duration::from_secs(5));
+    assert!(rate_limiter
+        .wait_if_needed(&WeatherProvider::OpenMeteo)
+        .await
+        .is_ok());
+}
+
+#[actix_web::test]
+async fn test_revoked_tokens_are_refused_with_introspection() {
+    let config = Config::from_env();
+    let active_token = generate_test_token(&config.jwt_secret);
+    let revoked_token = generate_test_token(&config.jwt_secret);
+
+    // Stands in for the auth service's introspection endpoint
+    let accepted = active_token.clone();
+    let stub = actix_web::HttpServer::new(move || {
+        let accepted = accepted.clone();
+        App::new().route(
+            "/auth/introspect",
+            web::post().to(move |form: web::Form<HashMap<String, String>>| {
+                let active = form.get("token") == Some(&accepted);
+                async move { HttpResponse::Ok().json(serde_json::json!({ "active": active })) }
+            }),
+        )
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let auth_service_url = format!("http://{}", stub.addrs()[0]);
+    actix_web::rt::spawn(stub.run());
+
+    let app = test::init_service(
+        App::new().service(
+            web::scope("/weather")
+                .wrap(JwtAuth::new(config.jwt_secret.clone()).with_introspection(&auth_service_url))
+                .route("/{city}", web::get().to(HttpResponse::Ok)),
+        ),
+    )
+    .await;
+
+    let request = |token: &str| {
+        test::TestRequest::get()
+            .uri("/weather/London")
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .to_request()
+    };
+    let resp = test::call_service(&app, request(&active_token)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let err = test::try_call_service(&app, request(&revoked_token))
+        .await
+        .unwrap_err();
+    assert_eq!(err.error_response().status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn test_compact_tokens_resolve_permissions_from_roles() {
+    let config = Config::from_env();
+    let claims = Claims {
+        sub: Uuid::new_v4(),
+        username: "compactuser".to_string(),
+        roles: vec!["user".to_string()],
+        permissions: vec![],
+        exp: Utc::now().timestamp() + 3600,
+        iat: Utc::now().timestamp(),
+        sid: None,
+        act: None,
+        aud: None,
+        pv: Some(1),
+        principal_type: PrincipalType::User,
+    };
+    let token = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(config.jwt_secret.as_ref()),
+    )
+    .unwrap();
+
+    // Stands in for the auth service: the user role is granted weather:read
+    // at version 2. The mapping is served to the resolver's service account.
+    let version = Arc::new(AtomicI64::new(1));
+    let fetches = Arc::new(AtomicUsize::new(0));
+    let token_grants = Arc::new(AtomicUsize::new(0));
+    let (stub_version, stub_fetches, stub_grants) =
+        (version.clone(), fetches.clone(), token_grants.clone());
+    let stub = actix_web::HttpServer::new(move || {
+        let (introspected, current, fetches, grants) = (
+            stub_version.clone(),
+            stub_version.clone(),
+            stub_fetches.clone(),
+            stub_grants.clone(),
         );
+        App::new()
+            .route(
+                "/auth/token",
+                web::post().to(move |req: actix_web::HttpRequest| {
+                    grants.fetch_add(1, Ordering::SeqCst);
+                    // weather-service:resolver-secret
+                    let authorized = req.headers().get("Authorization").unwrap()
+                        == "Basic d2VhdGhlci1zZXJ2aWNlOnJlc29sdmVyLXNlY3JldA==";
+                    async move {
+                        if !authorized {
+                            return HttpResponse::Unauthorized().finish();
+                        }
+                        HttpResponse::Ok().json(serde_json::json!({
+                            "access_token": "resolver-token",
+                            "token_type": "Bearer",
+                            "expires_in": 3600,
+                        }))
+                    }
+                }),
+            )
+            .route(
+                "/auth/introspect",
+                web::post().to(move || {
+                    let version = introspected.load(Ordering::SeqCst);
+                    async move {
+                        HttpResponse::Ok().json(serde_json::json!({
+                            "active": true,
+                            "permissions_version": version,
+                        }))
+                    }
+                }),
+            )
+            .route(
+                "/auth/role-permissions",
+                web::get().to(move |req: actix_web::HttpRequest| {
+                    fetches.fetch_add(1, Ordering::SeqCst);
+                    let version = current.load(Ordering::SeqCst);
+                    let granted = if version >= 2 {
+                        vec!["weather:read".to_string()]
+                    } else {
+                        vec![]
+                    };
+                    let authorized =
+                        req.headers().get("Authorization").unwrap() == "Bearer resolver-token";
+                    async move {
+                        if !authorized {
+                            return HttpResponse::Forbidden().finish();
+                        }
+                        HttpResponse::Ok().json(ApiResponse::new(RolePermissions {
+                            version,
+                            roles: BTreeMap::from([("user".to_string(), granted)]),
+                        }))
+                    }
+                }),
+            )
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let auth_service_url = format!("http://{}", stub.addrs()[0]);
+    actix_web::rt::spawn(stub.run());
+
+    let app = test::init_service(
+        App::new().service(
+            web::scope("/weather")
+                .wrap(PermissionCheck::new("weather:read".to_string()))
+                .wrap(
+                    JwtAuth::new(config.jwt_secret.clone())
+                        .with_introspection(&auth_service_url)
+                        .with_permission_resolution(PermissionResolver::new(
+                            &auth_service_url,
+                            "weather-service",
+                            "resolver-secret",
+                        )),
+                )
+                .route("/{city}", web::get().to(HttpResponse::Ok)),
+        ),
+    )
+    .await;
+
+    let request = || {
+        test::TestRequest::get()
+            .uri("/weather/London")
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .to_request()
+    };
+    let err = test::try_call_service(&app, request()).await.unwrap_err();
+    assert_eq!(err.error_response().status(), StatusCode::FORBIDDEN);
+    // The mapping is cached while the version stands
+    let err = test::try_call_service(&app, request()).await.unwrap_err();
+    assert_eq!(err.error_response().status(), StatusCode::FORBIDDEN);
+    assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+    // A newer version seen on introspection invalidates it
+    version.store(2, Ordering::SeqCst);
+    let resp = test::call_service(&app, request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    // The service token is reused until it nears expiry
+    assert_eq!(token_grants.load(Ordering::SeqCst), 1);
+}
+
+#[actix_web::test]
+async fn test_audience_restricted_tokens_only_accepted_by_their_audience() {
+    let config = Config::from_env();
+    let token_for = |aud: Option<&str>| {
+        let claims = Claims {
+            sub: Uuid::new_v4(),
+            username: "delegatinguser".to_string(),
+            roles: vec![],
+            permissions: vec!["weather:read".to_string()],
+            exp: Utc::now().timestamp() + 300,
+            iat: Utc::now().timestamp(),
+            sid: None,
+            act: Some(shared::Actor {
+                sub: Uuid::new_v4(),
+                username: "report-generator".to_string(),
+                principal_type: PrincipalType::Service,
+                act: None,
+            }),
+            aud: aud.map(str::to_string),
+            pv: None,
+            principal_type: PrincipalType::User,
+        };
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(config.jwt_secret.as_ref()),
+        )
+        .unwrap()
+    };
+
+    let app = test::init_service(
+        App::new().service(
+            web::scope("/weather")
+                .wrap(PermissionCheck::new("weather:read".to_string()))
+                .wrap(JwtAuth::new(config.jwt_secret.clone()).with_audience("weather-service"))
+                .route("/{city}", web::get().to(HttpResponse::Ok)),
+        ),
+    )
+    .await;
+    let request = |token: String| {
+        test::TestRequest::get()
+            .uri("/weather/London")
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .to_request()
+    };
+
+    let resp = test::call_service(&app, request(token_for(Some("weather-service")))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, request(token_for(None))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let err = test::try_call_service(&app, request(token_for(Some("time-service"))))
+        .await
+        .unwrap_err();
+    assert_eq!(err.error_response().status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn test_preferences_fill_in_units_and_city() {
+    let config = Config::from_env();
+    let token = generate_test_token(&config.jwt_secret);
+    let stranger = generate_test_token(&config.jwt_secret);
+
+    // Stands in for the auth service: only `token`'s user has saved preferences
+    let known = token.clone();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counted = calls.clone();
+    let stub = actix_web::HttpServer::new(move || {
+        let known = known.clone();
+        let counted = counted.clone();
+        App::new().route(
+            "/auth/me/preferences",
+            web::get().to(move |req: actix_web::HttpRequest| {
+                counted.fetch_add(1, Ordering::SeqCst);
+                let authorized = req
+                    .headers()
+                    .get("Authorization")
+                    .and_then(|h| h.to_str().ok())
+                    == Some(format!("Bearer {known}").as_str());
+                async move {
+                    if !authorized {
+                        return HttpResponse::Unauthorized().finish();
+                    }
+                    HttpResponse::Ok().json(serde_json::json!({
+                        "data": {
+                            "units": "imperial",
+                            "locale": "en-US",
+                            "time_format": "12h",
+                            "home_timezone": "America/Chicago",
+                            "favourite_cities": ["Springfield", "Shelbyville"],
+                        },
+                        "message": null,
+                    }))
+                }
+            }),
+        )
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let auth_service_url = format!("http://{}", stub.addrs()[0]);
+    actix_web::rt::spawn(stub.run());
+
+    // Cached metric readings, so no provider is called
+    let cache = web::Data::new(WeatherCache::new(1800));
+    cache.set(
+        "Springfield".to_string(),
+        serde_json::json!({
+            "city": "Springfield",
+            "timestamp": Utc::now(),
+            "aggregated": {
+                "temperature": 20.0,
+                "condition": "Clear",
+                "humidity": 40,
+                "wind_speed": 10.0,
+            },
+            "sources": [{ "provider": "stub", "temperature": 20.0, "wind_speed": 10.0 }],
+        }),
+    );
+
+    let app = test::init_service(
+        App::new()
+            .app_data(cache)
+            .app_data(web::Data::new(WeatherAggregator::new(RateLimiter::new(1))))
+            .app_data(web::Data::new(PreferencesClient::new(&auth_service_url)))
+            .service(
+                web::scope("/weather")
+                    .wrap(JwtAuth::new(config.jwt_secret.clone()).with_audience("weather-service"))
+                    .route("", web::get().to(get_favourite_weather))
+                    .route("/{city}", web::get().to(get_weather)),
+            ),
+    )
+    .await;
+    let get = |uri: &str, token: &str| {
+        test::TestRequest::get()
+            .uri(uri)
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .to_request()
+    };
+
+    // The first favourite city, in the preferred units
+    let resp = test::call_service(&app, get("/weather", &token)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let data = &body["data"];
+    assert_eq!(data["city"], "Springfield");
+    assert_eq!(data["units"], "imperial");
+    assert_eq!(data["aggregated"]["temperature"], 68.0);
+    let wind = data["aggregated"]["wind_speed"].as_f64().unwrap();
+    assert!((wind - 22.37).abs() < 0.01);
+    assert_eq!(data["sources"][0]["temperature"], 68.0);
+
+    // A query parameter wins over the preference, and the cache stays metric
+    let resp = test::call_service(&app, get("/weather/Springfield?units=metric", &token)).await;
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["data"]["units"], "metric");
+    assert_eq!(body["data"]["aggregated"]["temperature"], 20.0);
+
+    let resp = test::call_service(&app, get("/weather/Springfield?units=kelvin", &token)).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // Without preferences the defaults apply, and there is no default city
+    let resp = test::call_service(&app, get("/weather/Springfield", &stranger)).await;
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["data"]["units"], "metric");
+    let resp = test::call_service(&app, get("/weather", &stranger)).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // `token`'s preferences were fetched once for its three requests; the
+    // stranger's failed fetches are not cached, so each was retried
+    let fetches = || calls.load(Ordering::SeqCst);
+    assert_eq!(fetches(), 3);
+
+    // The auth service refuses exchanged tokens, so they are not sent there
+    // and get the defaults
+    let claims = Claims {
+        sub: Uuid::new_v4(),
+        username: "delegatinguser".to_string(),
+        roles: vec![],
+        permissions: vec!["weather:read".to_string()],
+        exp: Utc::now().timestamp() + 300,
+        iat: Utc::now().timestamp(),
+        sid: None,
+        act: Some(shared::Actor {
+            sub: Uuid::new_v4(),
+            username: "report-generator".to_string(),
+            principal_type: PrincipalType::Service,
+            act: None,
+        }),
+        aud: Some("weather-service".to_string()),
+        pv: None,
+        principal_type: PrincipalType::User,
+    };
+    let exchanged = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(config.jwt_secret.as_ref()),
+    )
+    .unwrap();
+    let resp = test::call_service(&app, get("/weather/Springfield", &exchanged)).await;
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["data"]["units"], "metric");
+    assert_eq!(fetches(), 3);
+}
+
+#[derive(serde::Deserialize)]
+#[allow(dead_code)]
+struct Refresh {
+    cache: Option<bool>,
+}
+
+#[actix_web::test]
+async fn test_errors_are_problem_details() {
+    let config = Config::from_env();
+    let token = generate_test_token(&config.jwt_secret);
+
+    let app = test::init_service(
+        App::new()
+            .wrap(shared::ErrorContext)
+            .app_data(web::QueryConfig::default().error_handler(shared::errors::extractor_error))
+            .service(
+                web::scope("/weather")
+                    .wrap(JwtAuth::new(config.jwt_secret.clone()))
+                    .route(
+                        "/busy",
+                        web::get().to(|| async {
+                            Err::<HttpResponse, _>(shared::AppError::TooManyRequests(
+                                "Slow down".to_string(),
+                                30,
+                            ))
+                        }),
+                    )
+                    .route(
+                        "/{city}",
+                        web::get()
+                            .to(|_: web::Query<Refresh>| async { HttpResponse::Ok().finish() }),
+                    ),
+            ),
+    )
+    .await;
+
+    // Refused by the middleware, before any handler runs
+    let req = test::TestRequest::get().uri("/weather/London").to_request();
+    let err = test::try_call_service(&app, req).await.unwrap_err();
+    let resp = err.error_response();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        shared::errors::PROBLEM_JSON
+    );
+    let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["type"], "about:blank");
+    assert_eq!(body["title"], "Unauthorized");
+    assert_eq!(body["status"], 401);
+    assert_eq!(body["code"], "unauthorized");
+    assert_eq!(body["instance"], "/weather/London");
+
+    // A query string the extractor cannot parse
+    let req = test::TestRequest::get()
+        .uri("/weather/London?cache=maybe")
+        .insert_header(("Authorization", format!("Bearer {token}")))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "bad_request");
+    assert_eq!(body["instance"], "/weather/London");
+
+    // Retry-After tells a rate limited client when to come back
+    let req = test::TestRequest::get()
+        .uri("/weather/busy")
+        .insert_header(("Authorization", format!("Bearer {token}")))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(resp.headers().get("retry-after").unwrap(), "30");
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "rate_limited");
+    assert_eq!(body["detail"], "Slow down");
+}
+
+#[actix_web::test]
+async fn test_request_ids_are_echoed_and_forwarded() {
+    let config = Config::from_env();
+    let token = generate_test_token(&config.jwt_secret);
+
+    // Stands in for the auth service, noting the correlation headers it is sent
+    let seen = Arc::new(std::sync::Mutex::new(Vec::<(String, String)>::new()));
+    let recorded = seen.clone();
+    let stub = actix_web::HttpServer::new(move || {
+        let recorded = recorded.clone();
+        let record = move |req: &actix_web::HttpRequest| {
+            let header = |name| {
+                req.headers()
+                    .get(name)
+                    .and_then(|h| h.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            recorded
+                .lock()
+                .unwrap()
+                .push((header("x-request-id"), header("traceparent")));
+        };
+        let record_introspection = record.clone();
+        App::new()
+            .route(
+                "/auth/introspect",
+                web::post().to(move |req: actix_web::HttpRequest| {
+                    record_introspection(&req);
+                    async { HttpResponse::Ok().json(serde_json::json!({ "active": true })) }
+                }),
+            )
+            .route(
+                "/auth/me/preferences",
+                web::get().to(move |req: actix_web::HttpRequest| {
+                    record(&req);
+                    async {
+                        HttpResponse::Ok().json(serde_json::json!({
+                            "data": { "favourite_cities": ["Springfield"] },
+                            "message": null,
+                        }))
+                    }
+                }),
+            )
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let auth_service_url = format!("http://{}", stub.addrs()[0]);
+    actix_web::rt::spawn(stub.run());
+
+    // A cached reading, so no provider is called
+    let cache = web::Data::new(WeatherCache::new(1800));
+    cache.set(
+        "Springfield".to_string(),
+        serde_json::json!({
+            "city": "Springfield",
+            "timestamp": Utc::now(),
+            "aggregated": { "temperature": 20.0, "condition": "Clear", "humidity": 40, "wind_speed": 10.0 },
+            "sources": [],
+        }),
+    );
+
+    let app = test::init_service(
+        App::new()
+            .wrap(shared::ErrorContext)
+            .wrap(shared::LoggingMiddleware)
+            .wrap(shared::Correlation)
+            .app_data(cache)
+            .app_data(web::Data::new(WeatherAggregator::new(RateLimiter::new(1))))
+            .app_data(web::Data::new(PreferencesClient::new(&auth_service_url)))
+            .service(
+                web::scope("/weather")
+                    .wrap(
+                        JwtAuth::new(config.jwt_secret.clone())
+                            .with_introspection(&auth_service_url),
+                    )
+                    .route("", web::get().to(get_favourite_weather)),
+            ),
+    )
+    .await;
+
+    // The caller's id and trace are kept, and passed on to the auth service
+    // with this service's span as the parent
+    let trace_id = "0af7651916cd43dd8448eb211c80319c";
+    let req = test::TestRequest::get()
+        .uri("/weather")
+        .insert_header(("Authorization", format!("Bearer {token}")))
+        .insert_header(("X-Request-Id", "req-abc"))
+        .insert_header(("traceparent", format!("00-{trace_id}-b7ad6b7169203331-01")))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("x-request-id").unwrap(), "req-abc");
+    let calls = std::mem::take(&mut *seen.lock().unwrap());
+    assert_eq!(calls.len(), 2);
+    for (request_id, traceparent) in &calls {
+        assert_eq!(request_id, "req-abc");
+        let parts: Vec<_> = traceparent.split('-').collect();
+        assert_eq!(parts.len(), 4);
+        assert_eq!(parts[..2], ["00", trace_id]);
+        assert_ne!(parts[2], "b7ad6b7169203331");
+        assert_eq!(parts[3], "01");
     }
+    assert_eq!(calls[0], calls[1]);
+
+    // Without them, or with ones that are not valid, new ones are made up;
+    // errors name the id too
+    let req = test::TestRequest::get()
+        .uri("/weather")
+        .insert_header(("X-Request-Id", "not a valid id"))
+        .insert_header((
+            "traceparent",
+            "00-00000000000000000000000000000000-b7ad6b7169203331-01",
+        ))
+        .to_request();
+    let err = test::try_call_service(&app, req).await.unwrap_err();
+    let resp = err.error_response();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let request_id = resp
+        .headers()
+        .get("x-request-id")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert!(Uuid::parse_str(&request_id).is_ok());
+    let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["request_id"], request_id.as_str());
+    assert!(seen.lock().unwrap().is_empty());
 }